@@ -431,4 +431,9 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             Err(ErrorCode::SIZE)
         }
     }
+
+    fn load_context(&self, state: &mut CortexMStoredState, input: &[u8]) -> Result<(), ErrorCode> {
+        *state = CortexMStoredState::try_from(input)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,94 @@
+//! Component for AppLoader, which installs applications received over a UART.
+//!
+//! The loader gets its own virtual UART device on the UART mux and writes
+//! applications to flash through `F`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let app_loader = components::app_loader::AppLoaderComponent::new(
+//!     uart_mux,
+//!     &base_peripherals.nvmc,
+//!     dynamic_process_loader,
+//! )
+//! .finalize(components::app_loader_component_helper!(nrf52840::nvmc::Nvmc));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::app_loader::{self, AppLoader};
+use capsules::virtual_uart::{MuxUart, UartDevice};
+use kernel::component::Component;
+use kernel::dynamic_process_loader::DynamicProcessLoad;
+use kernel::hil;
+use kernel::hil::flash::{Flash, HasClient};
+use kernel::{static_init, static_init_half};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! app_loader_component_helper {
+    ($F:ty) => {{
+        use capsules::app_loader::AppLoader;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<AppLoader<'static, $F>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<<$F as kernel::hil::flash::Flash>::Page> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct AppLoaderComponent<F: 'static + Flash + HasClient<'static, AppLoader<'static, F>>> {
+    uart_mux: &'static MuxUart<'static>,
+    flash: &'static F,
+    loader: &'static dyn DynamicProcessLoad,
+}
+
+impl<F: 'static + Flash + HasClient<'static, AppLoader<'static, F>>> AppLoaderComponent<F> {
+    pub fn new(
+        uart_mux: &'static MuxUart<'static>,
+        flash: &'static F,
+        loader: &'static dyn DynamicProcessLoad,
+    ) -> AppLoaderComponent<F> {
+        AppLoaderComponent {
+            uart_mux,
+            flash,
+            loader,
+        }
+    }
+}
+
+impl<F: 'static + Flash + HasClient<'static, AppLoader<'static, F>>> Component
+    for AppLoaderComponent<F>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<AppLoader<'static, F>>,
+        &'static mut MaybeUninit<F::Page>,
+    );
+    type Output = &'static AppLoader<'static, F>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let loader_uart = static_init!(UartDevice, UartDevice::new(self.uart_mux, true));
+        loader_uart.setup();
+
+        let page_buffer = static_init_half!(static_buffer.1, F::Page, F::Page::default());
+
+        let app_loader = static_init_half!(
+            static_buffer.0,
+            AppLoader<'static, F>,
+            AppLoader::new(
+                loader_uart,
+                self.flash,
+                self.loader,
+                &mut app_loader::RX_BUF,
+                &mut app_loader::TX_BUF,
+                page_buffer,
+            )
+        );
+        hil::uart::Transmit::set_transmit_client(loader_uart, app_loader);
+        hil::uart::Receive::set_receive_client(loader_uart, app_loader);
+        self.flash.set_client(app_loader);
+        let _ = app_loader.start();
+
+        app_loader
+    }
+}
@@ -0,0 +1,135 @@
+//! Component for CheckpointStorage, which stores checkpoints of processes in
+//! flash.
+//!
+//! The board passes the same `CheckpointRegion` to
+//! `kernel::process::load_processes_and_restore()`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let checkpoint_region = kernel::checkpoint::CheckpointRegion::new(
+//!     core::slice::from_raw_parts(
+//!         (CHECKPOINT_FIRST_PAGE * 4096) as *const u8,
+//!         CHECKPOINT_PAGES * 4096,
+//!     ),
+//!     CHECKPOINT_SLOT_PAGES * 4096,
+//!     kernel::checkpoint::crc32(
+//!         0,
+//!         core::slice::from_raw_parts(
+//!             &_stext as *const u8,
+//!             &_etext as *const u8 as usize - &_stext as *const u8 as usize,
+//!         ),
+//!     ),
+//! );
+//! let checkpoint = components::checkpoint::CheckpointComponent::new(
+//!     board_kernel,
+//!     capsules::checkpoint::DRIVER_NUM,
+//!     &base_peripherals.nvmc,
+//!     checkpoint_region,
+//!     CHECKPOINT_FIRST_PAGE,
+//! )
+//! .finalize(components::checkpoint_component_helper!(nrf52840::nvmc::Nvmc));
+//!
+//! kernel::process::load_processes_and_restore(
+//!     board_kernel,
+//!     chip,
+//!     app_flash,
+//!     app_memory,
+//!     &mut PROCESSES,
+//!     &FAULT_RESPONSE,
+//!     &checkpoint_region,
+//!     &process_management_capability,
+//! )
+//! .unwrap();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::checkpoint::CheckpointStorage;
+use kernel::capabilities;
+use kernel::checkpoint::CheckpointRegion;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::flash::{Flash, HasClient};
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! checkpoint_component_helper {
+    ($F:ty $(,)?) => {{
+        use capsules::checkpoint::CheckpointStorage;
+        use components::checkpoint::Capability;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<CheckpointStorage<'static, $F, Capability>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<<$F as kernel::hil::flash::Flash>::Page> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+pub struct CheckpointComponent<
+    F: 'static + Flash + HasClient<'static, CheckpointStorage<'static, F, Capability>>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    flash: &'static F,
+    region: CheckpointRegion,
+    first_page: usize,
+}
+
+impl<F: 'static + Flash + HasClient<'static, CheckpointStorage<'static, F, Capability>>>
+    CheckpointComponent<F>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        flash: &'static F,
+        region: CheckpointRegion,
+        first_page: usize,
+    ) -> CheckpointComponent<F> {
+        CheckpointComponent {
+            board_kernel,
+            driver_num,
+            flash,
+            region,
+            first_page,
+        }
+    }
+}
+
+impl<F: 'static + Flash + HasClient<'static, CheckpointStorage<'static, F, Capability>>> Component
+    for CheckpointComponent<F>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<CheckpointStorage<'static, F, Capability>>,
+        &'static mut MaybeUninit<F::Page>,
+    );
+    type Output = &'static CheckpointStorage<'static, F, Capability>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let page_buffer = static_init_half!(static_buffer.1, F::Page, F::Page::default());
+
+        let storage = static_init_half!(
+            static_buffer.0,
+            CheckpointStorage<'static, F, Capability>,
+            CheckpointStorage::new(
+                self.flash,
+                self.board_kernel,
+                Capability,
+                self.region,
+                self.first_page,
+                page_buffer,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            )
+        );
+        self.flash.set_client(storage);
+
+        storage
+    }
+}
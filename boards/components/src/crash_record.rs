@@ -0,0 +1,116 @@
+//! Component for CrashRecordStorage, which stores crash records in flash.
+//!
+//! The component connects the storage to the fault policy of the board, so
+//! that process faults are recorded. To record kernel panics, the board keeps
+//! the returned storage in a static that its panic handler passes to
+//! `kernel::debug::panic_crash_record()`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let fault_policy = static_init!(
+//!     kernel::process::CrashRecordFaultPolicy<kernel::process::RestartFaultPolicy>,
+//!     kernel::process::CrashRecordFaultPolicy::new(kernel::process::RestartFaultPolicy {})
+//! );
+//!
+//! let crash_record = components::crash_record::CrashRecordComponent::new(
+//!     board_kernel,
+//!     capsules::crash_record::DRIVER_NUM,
+//!     &base_peripherals.nvmc,
+//!     CRASH_RECORD_FIRST_PAGE,
+//!     fault_policy,
+//! )
+//! .finalize(components::crash_record_component_helper!(nrf52840::nvmc::Nvmc));
+//! CRASH_RECORDER = Some(crash_record);
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::crash_record::{self, CrashRecordStorage};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::flash::{Flash, HasClient};
+use kernel::process::{CrashRecordFaultPolicy, ProcessFaultPolicy};
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! crash_record_component_helper {
+    ($F:ty $(,)?) => {{
+        use capsules::crash_record::CrashRecordStorage;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<CrashRecordStorage<'static, $F>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<<$F as kernel::hil::flash::Flash>::Page> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct CrashRecordComponent<
+    F: 'static + Flash + HasClient<'static, CrashRecordStorage<'static, F>>,
+    P: 'static + ProcessFaultPolicy,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    flash: &'static F,
+    first_page: usize,
+    fault_policy: &'static CrashRecordFaultPolicy<P>,
+}
+
+impl<
+        F: 'static + Flash + HasClient<'static, CrashRecordStorage<'static, F>>,
+        P: 'static + ProcessFaultPolicy,
+    > CrashRecordComponent<F, P>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        flash: &'static F,
+        first_page: usize,
+        fault_policy: &'static CrashRecordFaultPolicy<P>,
+    ) -> CrashRecordComponent<F, P> {
+        CrashRecordComponent {
+            board_kernel,
+            driver_num,
+            flash,
+            first_page,
+            fault_policy,
+        }
+    }
+}
+
+impl<
+        F: 'static + Flash + HasClient<'static, CrashRecordStorage<'static, F>>,
+        P: 'static + ProcessFaultPolicy,
+    > Component for CrashRecordComponent<F, P>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<CrashRecordStorage<'static, F>>,
+        &'static mut MaybeUninit<F::Page>,
+    );
+    type Output = &'static CrashRecordStorage<'static, F>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let page_buffer = static_init_half!(static_buffer.1, F::Page, F::Page::default());
+
+        let storage = static_init_half!(
+            static_buffer.0,
+            CrashRecordStorage<'static, F>,
+            CrashRecordStorage::new(
+                self.flash,
+                self.first_page,
+                &mut crash_record::RECORD_BUF,
+                page_buffer,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            )
+        );
+        self.flash.set_client(storage);
+        self.fault_policy.set_recorder(storage);
+        storage.start();
+
+        storage
+    }
+}
@@ -0,0 +1,128 @@
+//! Component for the FAT filesystem on an SD card.
+//!
+//! This provides a component that mounts the FAT16 or FAT32 volume of an SD
+//! card, and lets applications read and write files in their own directory
+//! of the volume.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sdcard = static_init!(
+//!     capsules::sdcard::SDCard<'static, VirtualMuxAlarm<'static, nrf52833::rtc::Rtc>>,
+//!     capsules::sdcard::SDCard::new(
+//!         sdcard_spi,
+//!         sdcard_virtual_alarm,
+//!         Some(&SD_DETECT_PIN),
+//!         &mut capsules::sdcard::TXBUFFER,
+//!         &mut capsules::sdcard::RXBUFFER,
+//!     )
+//! );
+//! sdcard_spi.set_client(sdcard);
+//! sdcard_virtual_alarm.set_alarm_client(sdcard);
+//! SD_DETECT_PIN.set_client(sdcard);
+//!
+//! let (fat, fat_driver) = components::fat::FatComponent::new(
+//!     board_kernel,
+//!     capsules::fat_driver::DRIVER_NUM,
+//!     sdcard,
+//!     dynamic_deferred_caller,
+//! )
+//! .finalize(components::fat_component_helper!(
+//!     VirtualMuxAlarm<'static, nrf52833::rtc::Rtc>
+//! ));
+//!
+//! // Mount the card once it is initialized.
+//! fat.start();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::fat::filesystem::FatFs;
+use capsules::fat::layout::SECTOR_SIZE;
+use capsules::fat_driver::FatDriver;
+use capsules::sdcard::SDCard;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::hil::time::Alarm;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! fat_component_helper {
+    ($A:ty $(,)?) => {{
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<capsules::fat::filesystem::FatFs<'static, $A>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<capsules::fat_driver::FatDriver<'static, $A>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<[u8; capsules::fat::layout::SECTOR_SIZE]> =
+            MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<[u8; capsules::fat::layout::SECTOR_SIZE]> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4)
+    };};
+}
+
+pub struct FatComponent<A: 'static + Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    sdcard: &'static SDCard<'static, A>,
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl<A: 'static + Alarm<'static>> FatComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        sdcard: &'static SDCard<'static, A>,
+        deferred_caller: &'static DynamicDeferredCall,
+    ) -> FatComponent<A> {
+        FatComponent {
+            board_kernel,
+            driver_num,
+            sdcard,
+            deferred_caller,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>> Component for FatComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<FatFs<'static, A>>,
+        &'static mut MaybeUninit<FatDriver<'static, A>>,
+        &'static mut MaybeUninit<[u8; SECTOR_SIZE]>,
+        &'static mut MaybeUninit<[u8; SECTOR_SIZE]>,
+    );
+    type Output = (&'static FatFs<'static, A>, &'static FatDriver<'static, A>);
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let sector_buffer = static_init_half!(s.2, [u8; SECTOR_SIZE], [0; SECTOR_SIZE]);
+        let fat = static_init_half!(
+            s.0,
+            FatFs<'static, A>,
+            FatFs::new(self.sdcard, self.deferred_caller, sector_buffer)
+        );
+        fat.initialize_callback_handle(
+            self.deferred_caller.register(fat).unwrap(), // Unwrap fail = no deferred call slot available for the FAT filesystem
+        );
+        self.sdcard.set_client(fat);
+
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let data_buffer = static_init_half!(s.3, [u8; SECTOR_SIZE], [0; SECTOR_SIZE]);
+        let fat_driver = static_init_half!(
+            s.1,
+            FatDriver<'static, A>,
+            FatDriver::new(
+                fat,
+                data_buffer,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            )
+        );
+        fat.set_client(fat_driver);
+
+        (fat, fat_driver)
+    }
+}
@@ -0,0 +1,80 @@
+//! Component for GdbStub, which lets GDB debug processes over a UART.
+//!
+//! The stub must also be the `ContextSwitchCallback` of the board for its
+//! `entry-stop` and `entry-step` monitor commands to work.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let gdb_stub = GdbStubComponent::new(board_kernel, uart_mux)
+//!     .finalize(components::gdb_stub_component_helper!(NUM_PROCS));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::gdb_stub::{self, GdbStub};
+use capsules::virtual_uart::{MuxUart, UartDevice};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::{static_init, static_init_half};
+
+#[macro_export]
+macro_rules! gdb_stub_component_helper {
+    ($N: expr) => {{
+        use capsules::gdb_stub::GdbStub;
+        use components::gdb_stub::Capability;
+        use core::mem::MaybeUninit;
+
+        static mut BUFFER: MaybeUninit<GdbStub<'static, Capability, $N>> = MaybeUninit::uninit();
+
+        &mut BUFFER
+    }};
+}
+
+pub struct GdbStubComponent<const NUM_PROCS: usize> {
+    board_kernel: &'static kernel::Kernel,
+    uart_mux: &'static MuxUart<'static>,
+}
+
+impl<const NUM_PROCS: usize> GdbStubComponent<NUM_PROCS> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        uart_mux: &'static MuxUart<'static>,
+    ) -> GdbStubComponent<NUM_PROCS> {
+        GdbStubComponent {
+            board_kernel,
+            uart_mux,
+        }
+    }
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+impl<const NUM_PROCS: usize> Component for GdbStubComponent<NUM_PROCS> {
+    type StaticInput = &'static mut MaybeUninit<GdbStub<'static, Capability, NUM_PROCS>>;
+    type Output = &'static GdbStub<'static, Capability, NUM_PROCS>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let gdb_uart = static_init!(UartDevice, UartDevice::new(self.uart_mux, true));
+        gdb_uart.setup();
+
+        let gdb_stub = static_init_half!(
+            static_buffer,
+            GdbStub<'static, Capability, NUM_PROCS>,
+            GdbStub::new(
+                gdb_uart,
+                &mut gdb_stub::RX_BUF,
+                &mut gdb_stub::PACKET_BUF,
+                &mut gdb_stub::TX_BUF,
+                self.board_kernel,
+                Capability,
+            )
+        );
+        hil::uart::Transmit::set_transmit_client(gdb_uart, gdb_stub);
+        hil::uart::Receive::set_receive_client(gdb_uart, gdb_stub);
+
+        gdb_stub
+    }
+}
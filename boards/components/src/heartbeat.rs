@@ -0,0 +1,111 @@
+//! Component for HeartbeatMonitor, which detects hung processes with
+//! heartbeats and only feeds the watchdog while all of them are healthy.
+//!
+//! The returned monitor is used as the `WatchDog` of the board in its
+//! `KernelResources`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let heartbeat = components::heartbeat::HeartbeatComponent::new(
+//!     board_kernel,
+//!     capsules::heartbeat::DRIVER_NUM,
+//!     mux_alarm,
+//!     &peripherals.wdt,
+//! )
+//! .finalize(components::heartbeat_component_helper!(
+//!     sam4l::ast::Ast,
+//!     sam4l::wdt::Wdt
+//! ));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::heartbeat::HeartbeatMonitor;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::{self, Alarm};
+use kernel::platform::watchdog::WatchDog;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! heartbeat_component_helper {
+    ($A:ty, $W:ty $(,)?) => {{
+        use capsules::heartbeat::HeartbeatMonitor;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use components::heartbeat::Capability;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<
+            HeartbeatMonitor<'static, VirtualMuxAlarm<'static, $A>, $W, Capability>,
+        > = MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+pub struct HeartbeatComponent<A: 'static + time::Alarm<'static>, W: 'static + WatchDog> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    watchdog: &'static W,
+}
+
+impl<A: 'static + time::Alarm<'static>, W: 'static + WatchDog> HeartbeatComponent<A, W> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        watchdog: &'static W,
+    ) -> HeartbeatComponent<A, W> {
+        HeartbeatComponent {
+            board_kernel,
+            driver_num,
+            alarm_mux,
+            watchdog,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>, W: 'static + WatchDog> Component
+    for HeartbeatComponent<A, W>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<
+            HeartbeatMonitor<'static, VirtualMuxAlarm<'static, A>, W, Capability>,
+        >,
+    );
+    type Output = &'static HeartbeatMonitor<'static, VirtualMuxAlarm<'static, A>, W, Capability>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        virtual_alarm.setup();
+
+        let heartbeat = static_init_half!(
+            static_buffer.1,
+            HeartbeatMonitor<'static, VirtualMuxAlarm<'static, A>, W, Capability>,
+            HeartbeatMonitor::new(
+                virtual_alarm,
+                self.watchdog,
+                self.board_kernel,
+                Capability,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            )
+        );
+        virtual_alarm.set_alarm_client(heartbeat);
+
+        heartbeat
+    }
+}
@@ -5,20 +5,26 @@ pub mod adc_microphone;
 pub mod alarm;
 pub mod analog_comparator;
 pub mod app_flash_driver;
+pub mod app_loader;
 pub mod bus;
 pub mod button;
 pub mod cdc;
+pub mod checkpoint;
 pub mod console;
+pub mod crash_record;
 pub mod crc;
 pub mod ctap;
 pub mod debug_queue;
 pub mod debug_writer;
 pub mod digest;
+pub mod fat;
 pub mod flash;
 pub mod ft6x06;
 pub mod fxos8700;
+pub mod gdb_stub;
 pub mod gpio;
 pub mod hd44780;
+pub mod heartbeat;
 pub mod hmac;
 pub mod hts221;
 pub mod humidity;
@@ -38,6 +44,7 @@ pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
 pub mod panic_button;
+pub mod ping_driver;
 pub mod process_console;
 pub mod process_printer;
 pub mod rng;
@@ -50,6 +57,8 @@ pub mod si7021;
 pub mod sound_pressure;
 pub mod spi;
 pub mod st77xx;
+pub mod syscall_trace;
+pub mod tcp_driver;
 pub mod temperature;
 pub mod temperature_rp2040;
 pub mod temperature_stm;
@@ -59,3 +68,7 @@ pub mod tickv;
 pub mod touch;
 pub mod udp_driver;
 pub mod udp_mux;
+pub mod usb_composite;
+pub mod usb_dfu;
+pub mod usb_hid;
+pub mod usb_msc;
@@ -130,6 +130,7 @@ impl<A: 'static + Alarm<'static>> Component for ProcessConsoleComponent<A> {
                 &mut process_console::READ_BUF,
                 &mut process_console::QUEUE_BUF,
                 &mut process_console::COMMAND_BUF,
+                &mut process_console::HISTORY_BUF,
                 self.board_kernel,
                 kernel_addresses,
                 Capability,
@@ -0,0 +1,96 @@
+//! Component for the real-time scheduler.
+//!
+//! This provides one Component, RealtimeComponent, which creates a scheduler
+//! for periodic processes that orders them either by earliest deadline or by
+//! rate.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let scheduler = components::sched::realtime::RealtimeComponent::new(
+//!     mux_alarm,
+//!     &PROCESSES,
+//!     RealtimePolicy::EarliestDeadlineFirst,
+//! )
+//! .finalize(components::realtime_component_helper!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     NUM_PROCS
+//! ));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::component::Component;
+use kernel::hil::time;
+use kernel::process::Process;
+use kernel::scheduler::realtime::{RealtimePolicy, RealtimeProcessNode, RealtimeSched};
+use kernel::static_init_half;
+
+#[macro_export]
+macro_rules! realtime_component_helper {
+    ($A:ty, $N:expr $(,)?) => {{
+        use core::mem::MaybeUninit;
+        use kernel::scheduler::realtime::{RealtimeProcessNode, RealtimeSched};
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<RealtimeSched<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        const UNINIT: MaybeUninit<RealtimeProcessNode<'static>> = MaybeUninit::uninit();
+        static mut BUF3: [MaybeUninit<RealtimeProcessNode<'static>>; $N] = [UNINIT; $N];
+        (&mut BUF1, &mut BUF2, &mut BUF3)
+    };};
+}
+
+pub struct RealtimeComponent<A: 'static + time::Alarm<'static>> {
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    processes: &'static [Option<&'static dyn Process>],
+    policy: RealtimePolicy,
+}
+
+impl<A: 'static + time::Alarm<'static>> RealtimeComponent<A> {
+    pub fn new(
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        processes: &'static [Option<&'static dyn Process>],
+        policy: RealtimePolicy,
+    ) -> RealtimeComponent<A> {
+        RealtimeComponent {
+            alarm_mux,
+            processes,
+            policy,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for RealtimeComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<RealtimeSched<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut [MaybeUninit<RealtimeProcessNode<'static>>],
+    );
+    type Output = &'static mut RealtimeSched<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let (alarm_buf, sched_buf, proc_nodes) = static_buffer;
+        let scheduler_alarm = static_init_half!(
+            alarm_buf,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        scheduler_alarm.setup();
+
+        let scheduler = static_init_half!(
+            sched_buf,
+            RealtimeSched<'static, VirtualMuxAlarm<'static, A>>,
+            RealtimeSched::new(scheduler_alarm, self.policy)
+        );
+        for (i, node) in proc_nodes.iter_mut().enumerate() {
+            let init_node = static_init_half!(
+                node,
+                RealtimeProcessNode<'static>,
+                RealtimeProcessNode::new(&self.processes[i])
+            );
+            scheduler.processes.push_tail(init_node);
+        }
+        scheduler
+    }
+}
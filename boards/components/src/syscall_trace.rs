@@ -0,0 +1,110 @@
+//! Component for SyscallTrace, which records the system calls of processes
+//! and streams them to a host.
+//!
+//! The returned tracer is passed to `Kernel::set_syscall_tracer()`. Each
+//! process gets a buffer of `DEPTH` records.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let syscall_trace = components::syscall_trace::SyscallTraceComponent::new(
+//!     board_kernel,
+//!     trace_uart,
+//!     &base_peripherals.rtc,
+//! )
+//! .finalize(components::syscall_trace_component_helper!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     NUM_PROCS,
+//!     32
+//! ));
+//! board_kernel.set_syscall_tracer(syscall_trace, &main_loop_capability);
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::syscall_trace::{self, ProcessTrace, Record, SyscallTrace};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::time::Time;
+use kernel::hil::uart;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! syscall_trace_component_helper {
+    ($T:ty, $N:expr, $DEPTH:expr $(,)?) => {{
+        use capsules::syscall_trace::{ProcessTrace, Record, SyscallTrace};
+        use components::syscall_trace::Capability;
+        use core::mem::MaybeUninit;
+        static mut RECORDS: [[Record; $DEPTH]; $N] = [[Record::EMPTY; $DEPTH]; $N];
+        static mut TRACES: MaybeUninit<[ProcessTrace; $N]> = MaybeUninit::uninit();
+        static mut BUFFER: MaybeUninit<SyscallTrace<'static, $T, Capability>> =
+            MaybeUninit::uninit();
+        (&mut RECORDS, &mut TRACES, &mut BUFFER)
+    };};
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+pub struct SyscallTraceComponent<T: 'static + Time, const NUM_PROCS: usize, const DEPTH: usize> {
+    board_kernel: &'static kernel::Kernel,
+    uart: &'static dyn uart::Transmit<'static>,
+    time: &'static T,
+}
+
+impl<T: 'static + Time, const NUM_PROCS: usize, const DEPTH: usize>
+    SyscallTraceComponent<T, NUM_PROCS, DEPTH>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        uart: &'static dyn uart::Transmit<'static>,
+        time: &'static T,
+    ) -> SyscallTraceComponent<T, NUM_PROCS, DEPTH> {
+        SyscallTraceComponent {
+            board_kernel,
+            uart,
+            time,
+        }
+    }
+}
+
+impl<T: 'static + Time, const NUM_PROCS: usize, const DEPTH: usize> Component
+    for SyscallTraceComponent<T, NUM_PROCS, DEPTH>
+{
+    type StaticInput = (
+        &'static mut [[Record; DEPTH]; NUM_PROCS],
+        &'static mut MaybeUninit<[ProcessTrace; NUM_PROCS]>,
+        &'static mut MaybeUninit<SyscallTrace<'static, T, Capability>>,
+    );
+    type Output = &'static SyscallTrace<'static, T, Capability>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let mut records = static_buffer.0.iter_mut();
+        let traces = static_init_half!(
+            static_buffer.1,
+            [ProcessTrace; NUM_PROCS],
+            [(); NUM_PROCS].map(|_| ProcessTrace::new(
+                records
+                    .next()
+                    .map_or::<&'static mut [Record], _>(&mut [], |records| records)
+            ))
+        );
+
+        let syscall_trace = static_init_half!(
+            static_buffer.2,
+            SyscallTrace<'static, T, Capability>,
+            SyscallTrace::new(
+                self.uart,
+                self.time,
+                self.board_kernel,
+                Capability,
+                traces,
+                &mut syscall_trace::TX_BUF,
+            )
+        );
+        self.uart.set_transmit_client(syscall_trace);
+
+        syscall_trace
+    }
+}
@@ -0,0 +1,293 @@
+//! Component to initialize the TCP/6LoWPAN stack and the userland TCP driver.
+//!
+//! This provides one Component, TCPDriverComponent. The TCP stack uses its own
+//! MacUser, 6LoWPAN state and IPv6 sender on top of the virtual MAC, so it can
+//! be used alongside the UDP stack set up by `UDPMuxComponent`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let tcp_driver = TCPDriverComponent::new(
+//!        board_kernel,
+//!        capsules::net::tcp::DRIVER_NUM,
+//!        mux_mac,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::tcp_driver_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules;
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::net::tcp::tcp_mux::MuxTcp;
+use capsules::net::tcp::tcp_socket::TCPSocket;
+use capsules::net::tcp::{TCPDriver, TCPHeader};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init, static_init_half};
+
+// The TCP stack requires several buffers:
+//
+//   1. RADIO_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. SIXLOWPAN_RX_BUF: Buffer to hold full IP packets after they are decompressed by 6LoWPAN
+//   3. TCP_SEGMENT: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd.
+//   4. MUX_TX_BUF: Buffer the MuxTcp builds the payload of outgoing segments in.
+//   5. SOCKET_TX_BUF/SOCKET_RX_BUF: The transmit and receive buffers of each socket. The
+//      size of the receive buffer is the largest window advertised to the peer.
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
+
+/// The largest TCP payload sent in a single segment.
+pub const MAX_SEGMENT_LEN: usize = 200;
+static mut TCP_SEGMENT: [u8; MAX_SEGMENT_LEN] = [0; MAX_SEGMENT_LEN];
+static mut MUX_TX_BUF: [u8; MAX_SEGMENT_LEN] = [0; MAX_SEGMENT_LEN];
+
+/// The number of connections that can be open at the same time.
+pub const NUM_SOCKETS: usize = 2;
+pub const SOCKET_BUF_LEN: usize = 256;
+static mut SOCKET_TX_BUF: [[u8; SOCKET_BUF_LEN]; NUM_SOCKETS] = [[0; SOCKET_BUF_LEN]; NUM_SOCKETS];
+static mut SOCKET_RX_BUF: [[u8; SOCKET_BUF_LEN]; NUM_SOCKETS] = [[0; SOCKET_BUF_LEN]; NUM_SOCKETS];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! tcp_driver_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules;
+        use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use capsules::net::tcp::tcp_mux::MuxTcp;
+        use capsules::net::tcp::tcp_socket::TCPSocket;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<sixlowpan_state::RxState<'static>> = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<MuxTcp<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF7: MaybeUninit<
+            [TCPSocket<'static, VirtualMuxAlarm<'static, $A>>; $crate::tcp_driver::NUM_SOCKETS],
+        > = MaybeUninit::uninit();
+        static mut BUF8: MaybeUninit<
+            capsules::net::tcp::TCPDriver<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        (
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6, &mut BUF7,
+            &mut BUF8,
+        )
+    };};
+}
+
+pub struct TCPDriverComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface_list: &'static [IPAddr],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> TCPDriverComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            mux_mac,
+            ctx_pfix_len,
+            ctx_pfix,
+            dst_mac_addr,
+            src_mac_addr,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for TCPDriverComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<sixlowpan_state::RxState<'static>>,
+        &'static mut MaybeUninit<IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<MuxTcp<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[TCPSocket<'static, VirtualMuxAlarm<'static, A>>; NUM_SOCKETS]>,
+        &'static mut MaybeUninit<TCPDriver<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static TCPDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        ipsender_virtual_alarm.setup();
+        let tcp_virtual_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        tcp_virtual_alarm.setup();
+
+        let tcp_mac = static_init_half!(
+            static_buffer.2,
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(tcp_mac);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let sixlowpan = static_init_half!(
+            static_buffer.3,
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+            )
+        );
+
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+        let default_rx_state = static_init_half!(
+            static_buffer.4,
+            sixlowpan_state::RxState<'static>,
+            sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF)
+        );
+        sixlowpan_state.add_rx_state(default_rx_state);
+        tcp_mac.set_receive_client(sixlowpan);
+
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::TCP(TCPHeader::new()),
+            payload: &mut TCP_SEGMENT,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init_half!(
+            static_buffer.5,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RADIO_BUF,
+                sixlowpan_tx,
+                tcp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        ip_send.set_addr(self.interface_list[0]);
+        tcp_mac.set_transmit_client(ip_send);
+
+        let ip_receive = static_init!(
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
+        );
+        sixlowpan_state.set_rx_client(ip_receive);
+
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+        let tcp_mux = static_init_half!(
+            static_buffer.6,
+            MuxTcp<'static, VirtualMuxAlarm<'static, A>>,
+            MuxTcp::new(ip_send, tcp_virtual_alarm, &mut MUX_TX_BUF, net_cap)
+        );
+        ip_send.set_client(tcp_mux);
+        ip_receive.set_client(tcp_mux);
+        tcp_virtual_alarm.set_alarm_client(tcp_mux);
+
+        let [tx_buf0, tx_buf1] = &mut SOCKET_TX_BUF;
+        let [rx_buf0, rx_buf1] = &mut SOCKET_RX_BUF;
+        let sockets = static_init_half!(
+            static_buffer.7,
+            [TCPSocket<'static, VirtualMuxAlarm<'static, A>>; NUM_SOCKETS],
+            [
+                TCPSocket::new(0, tcp_mux, tx_buf0, rx_buf0),
+                TCPSocket::new(1, tcp_mux, tx_buf1, rx_buf1),
+            ]
+        );
+
+        let tcp_driver = static_init_half!(
+            static_buffer.8,
+            TCPDriver<'static, VirtualMuxAlarm<'static, A>>,
+            TCPDriver::new(
+                sockets,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+                self.interface_list,
+            )
+        );
+        for socket in sockets.iter() {
+            tcp_mux.add_socket(socket);
+            socket.set_client(tcp_driver);
+        }
+        tcp_driver
+    }
+}
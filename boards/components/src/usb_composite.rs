@@ -0,0 +1,146 @@
+//! Components for composite USB devices.
+//!
+//! This provides two components: `UsbCompositeComponent`, which creates the
+//! composite device on top of the USB controller, and `UsbFunctionComponent`,
+//! which creates a virtual controller for one class driver of the device.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "XYZ Corp.",      // Manufacturer
+//!     "Security Key",   // Product
+//!     "Serial No. 5",   // Serial number
+//! ];
+//!
+//! let composite = components::usb_composite::UsbCompositeComponent::new(
+//!     &nrf52840_peripherals.usbd,
+//!     capsules::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x503a,
+//!     STRINGS,
+//! )
+//! .finalize(components::usb_composite_component_helper!(nrf52840::usbd::Usbd));
+//!
+//! let ctap_function = components::usb_composite::UsbFunctionComponent::new(composite)
+//!     .finalize(components::usb_function_component_helper!(nrf52840::usbd::Usbd));
+//! let ctap_hid = static_init!(
+//!     capsules::usb::ctap::CtapHid<
+//!         'static,
+//!         capsules::usb::composite::UsbFunction<'static, nrf52840::usbd::Usbd>,
+//!     >,
+//!     capsules::usb::ctap::CtapHid::new(ctap_function, 0x1915, 0x503a, STRINGS)
+//! );
+//! ctap_function.set_client(ctap_hid);
+//!
+//! composite.enable();
+//! composite.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::composite::{CompositeDevice, UsbFunction, DESCRIPTOR_BUFLEN};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_composite_component_helper {
+    ($U:ty $(,)?) => {{
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<capsules::usb::composite::CompositeDevice<'static, $U>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<[u8; capsules::usb::composite::DESCRIPTOR_BUFLEN]> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+#[macro_export]
+macro_rules! usb_function_component_helper {
+    ($U:ty $(,)?) => {{
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<capsules::usb::composite::UsbFunction<'static, $U>> =
+            MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct UsbCompositeComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbCompositeComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+    ) -> UsbCompositeComponent<U> {
+        UsbCompositeComponent {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbCompositeComponent<U> {
+    type StaticInput = (
+        &'static mut MaybeUninit<CompositeDevice<'static, U>>,
+        &'static mut MaybeUninit<[u8; DESCRIPTOR_BUFLEN]>,
+    );
+    type Output = &'static CompositeDevice<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let descriptor_storage =
+            static_init_half!(s.1, [u8; DESCRIPTOR_BUFLEN], [0; DESCRIPTOR_BUFLEN]);
+
+        let composite = static_init_half!(
+            s.0,
+            CompositeDevice<'static, U>,
+            CompositeDevice::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                descriptor_storage,
+            )
+        );
+        self.usb.set_client(composite);
+
+        composite
+    }
+}
+
+pub struct UsbFunctionComponent<U: 'static + hil::usb::UsbController<'static>> {
+    composite: &'static CompositeDevice<'static, U>,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbFunctionComponent<U> {
+    pub fn new(composite: &'static CompositeDevice<'static, U>) -> UsbFunctionComponent<U> {
+        UsbFunctionComponent { composite }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbFunctionComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<UsbFunction<'static, U>>;
+    type Output = &'static UsbFunction<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let function =
+            static_init_half!(s, UsbFunction<'static, U>, UsbFunction::new(self.composite));
+        function.setup();
+
+        function
+    }
+}
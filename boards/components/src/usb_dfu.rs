@@ -0,0 +1,143 @@
+//! Component for USB device firmware upgrade support.
+//!
+//! This provides a component for using the USB DFU driver. This allows the
+//! kernel or applications to be written over USB with `dfu-util`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "XYZ Corp.",    // Manufacturer
+//!     "DFU Device",   // Product
+//!     "Serial No. 5", // Serial number
+//! ];
+//! static DFU_TARGETS: &'static [capsules::usb::dfu::DfuTarget] = &[
+//!     // Alternate setting 0: the kernel
+//!     capsules::usb::dfu::DfuTarget {
+//!         start_address: 0x00000,
+//!         length: 0x40000,
+//!     },
+//!     // Alternate setting 1: the applications
+//!     capsules::usb::dfu::DfuTarget {
+//!         start_address: 0x40000,
+//!         length: 0xA0000,
+//!     },
+//! ];
+//!
+//! let dfu = components::usb_dfu::UsbDfuComponent::new(
+//!     &nrf52840_peripherals.usbd,
+//!     capsules::usb::usbc_client::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x6667,
+//!     0xabcf,
+//!     STRINGS,
+//!     &base_peripherals.nvmc,
+//!     DFU_TARGETS,
+//!     None,
+//! )
+//! .finalize(components::usb_dfu_component_helper!(
+//!     nrf52840::usbd::Usbd,
+//!     nrf52840::nvmc::Nvmc
+//! ));
+//!
+//! dfu.enable();
+//! dfu.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::dfu::{Dfu, DfuTarget};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::flash::{Flash, HasClient};
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_dfu_component_helper {
+    ($U:ty, $F:ty $(,)?) => {{
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<capsules::usb::dfu::Dfu<'static, $U, $F>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<<$F as kernel::hil::flash::Flash>::Page> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct UsbDfuComponent<
+    U: 'static + hil::usb::UsbController<'static>,
+    F: 'static + Flash + HasClient<'static, Dfu<'static, U, F>>,
+> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+    flash: &'static F,
+    targets: &'static [DfuTarget],
+    manifest_function: Option<&'static (dyn Fn() + 'static)>,
+}
+
+impl<
+        U: 'static + hil::usb::UsbController<'static>,
+        F: 'static + Flash + HasClient<'static, Dfu<'static, U, F>>,
+    > UsbDfuComponent<U, F>
+{
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        flash: &'static F,
+        targets: &'static [DfuTarget],
+        manifest_function: Option<&'static (dyn Fn() + 'static)>,
+    ) -> UsbDfuComponent<U, F> {
+        UsbDfuComponent {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+            flash,
+            targets,
+            manifest_function,
+        }
+    }
+}
+
+impl<
+        U: 'static + hil::usb::UsbController<'static>,
+        F: 'static + Flash + HasClient<'static, Dfu<'static, U, F>>,
+    > Component for UsbDfuComponent<U, F>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<Dfu<'static, U, F>>,
+        &'static mut MaybeUninit<F::Page>,
+    );
+    type Output = &'static Dfu<'static, U, F>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let page_buffer = static_init_half!(s.1, F::Page, F::Page::default());
+
+        let dfu = static_init_half!(
+            s.0,
+            Dfu<'static, U, F>,
+            Dfu::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                self.flash,
+                self.targets,
+                page_buffer,
+                self.manifest_function,
+            )
+        );
+        self.usb.set_client(dfu);
+        self.flash.set_client(dfu);
+
+        dfu
+    }
+}
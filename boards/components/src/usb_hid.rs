@@ -0,0 +1,140 @@
+//! Component for USB keyboard and mouse support.
+//!
+//! This provides a component for using the USB HID keyboard and mouse driver.
+//! This allows applications to inject key presses and pointer movement into a
+//! USB host.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "XYZ Corp.",      // Manufacturer
+//!     "Input Injector", // Product
+//!     "Serial No. 5",   // Serial number
+//! ];
+//!
+//! let (hid, hid_driver) = components::usb_hid::UsbHidComponent::new(
+//!     board_kernel,
+//!     capsules::hid::DRIVER_NUM,
+//!     &nrf52840_peripherals.usbd,
+//!     capsules::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x503b,
+//!     STRINGS,
+//! )
+//! .finalize(components::usb_hid_component_helper!(nrf52840::usbd::Usbd));
+//!
+//! hid.enable();
+//! hid.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::hid::HidDriver;
+use capsules::usb::hid::Hid;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::hil::usb_hid::UsbHid;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_hid_component_helper {
+    ($U:ty $(,)?) => {{
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<capsules::usb::hid::Hid<'static, $U>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<
+            capsules::hid::HidDriver<'static, capsules::usb::hid::Hid<'static, $U>>,
+        > = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<[u8; 8]> = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<[u8; 4]> = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<[u8; 8]> = MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5)
+    };};
+}
+
+pub struct UsbHidComponent<U: 'static + hil::usb::UsbController<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbHidComponent<U> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+    ) -> UsbHidComponent<U> {
+        UsbHidComponent {
+            board_kernel,
+            driver_num,
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbHidComponent<U> {
+    type StaticInput = (
+        &'static mut MaybeUninit<Hid<'static, U>>,
+        &'static mut MaybeUninit<HidDriver<'static, Hid<'static, U>>>,
+        &'static mut MaybeUninit<[u8; 8]>,
+        &'static mut MaybeUninit<[u8; 4]>,
+        &'static mut MaybeUninit<[u8; 8]>,
+    );
+    type Output = (
+        &'static Hid<'static, U>,
+        &'static HidDriver<'static, Hid<'static, U>>,
+    );
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let hid = static_init_half!(
+            s.0,
+            Hid<'static, U>,
+            Hid::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+            )
+        );
+        self.usb.set_client(hid);
+
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let keyboard_buffer = static_init_half!(s.2, [u8; 8], [0; 8]);
+        let mouse_buffer = static_init_half!(s.3, [u8; 4], [0; 4]);
+        let hid_driver = static_init_half!(
+            s.1,
+            HidDriver<'static, Hid<'static, U>>,
+            HidDriver::new(
+                hid,
+                keyboard_buffer,
+                mouse_buffer,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            )
+        );
+        hid.set_keyboard_client(hid_driver);
+        hid.set_mouse_client(hid_driver);
+
+        // Receive the keyboard LED state.
+        let led_buffer = static_init_half!(s.4, [u8; 8], [0; 8]);
+        let _ = UsbHid::<[u8; 8]>::receive_buffer(hid, led_buffer);
+
+        (hid, hid_driver)
+    }
+}
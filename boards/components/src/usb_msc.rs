@@ -0,0 +1,126 @@
+//! Component for USB mass storage support.
+//!
+//! This provides a component for using the USB mass storage driver. This
+//! allows a host to read and write a nonvolatile storage as a removable disk.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "XYZ Corp.",    // Manufacturer
+//!     "Data Logger",  // Product
+//!     "Serial No. 5", // Serial number
+//! ];
+//!
+//! // Expose an SD card. The number of blocks is set once the card is
+//! // initialized.
+//! let sdcard_storage = static_init!(
+//!     capsules::sdcard::SDCardStorage<'static, VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>>,
+//!     capsules::sdcard::SDCardStorage::new(sdcard)
+//! );
+//! sdcard.set_client(sdcard_storage);
+//!
+//! let msc = components::usb_msc::UsbMassStorageComponent::new(
+//!     &nrf52840::usbd::USBD,
+//!     capsules::usb::usbc_client::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x6667,
+//!     0xabce,
+//!     STRINGS,
+//!     sdcard_storage,
+//!     0, // Start address
+//!     0, // No blocks until the card is initialized
+//! )
+//! .finalize(components::usb_msc_component_helper!(nrf52840::usbd::Usbd));
+//!
+//! sdcard_storage.set_medium_client(msc);
+//! sdcard_storage.start();
+//! msc.enable();
+//! msc.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::msc::{MassStorage, BLOCK_SIZE};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_msc_component_helper {
+    ($U:ty $(,)?) => {{
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<capsules::usb::msc::MassStorage<'static, $U>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<[u8; capsules::usb::msc::BLOCK_SIZE]> = MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct UsbMassStorageComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+    storage: &'static dyn NonvolatileStorage<'static>,
+    start_address: usize,
+    block_count: u32,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbMassStorageComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage: &'static dyn NonvolatileStorage<'static>,
+        start_address: usize,
+        block_count: u32,
+    ) -> UsbMassStorageComponent<U> {
+        UsbMassStorageComponent {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+            storage,
+            start_address,
+            block_count,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbMassStorageComponent<U> {
+    type StaticInput = (
+        &'static mut MaybeUninit<MassStorage<'static, U>>,
+        &'static mut MaybeUninit<[u8; BLOCK_SIZE]>,
+    );
+    type Output = &'static MassStorage<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let block_buffer = static_init_half!(s.1, [u8; BLOCK_SIZE], [0; BLOCK_SIZE]);
+
+        let msc = static_init_half!(
+            s.0,
+            MassStorage<'static, U>,
+            MassStorage::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                self.storage,
+                self.start_address,
+                self.block_count,
+                block_buffer,
+            )
+        );
+        self.usb.set_client(msc);
+        self.storage.set_client(msc);
+
+        msc
+    }
+}
@@ -300,6 +300,13 @@ unsafe fn get_peripherals(
     static_init!(Sam4lDefaultPeripherals, Sam4lDefaultPeripherals::new(pm))
 }
 
+/// Resets the chip from the `reset` command of the process console.
+fn reset_chip() {
+    unsafe {
+        cortexm4::scb::reset();
+    }
+}
+
 /// Main function.
 ///
 /// This is called after RAM initialization is complete.
@@ -378,6 +385,10 @@ pub unsafe fn main() {
         .finalize(components::process_console_component_helper!(
             sam4l::ast::Ast
         ));
+    pconsole.set_reset_function(
+        reset_chip,
+        &create_capability!(capabilities::ChipResetCapability),
+    );
     let console =
         ConsoleComponent::new(board_kernel, capsules::console::DRIVER_NUM, uart_mux).finalize(());
     DebugWriterComponent::new(uart_mux).finalize(());
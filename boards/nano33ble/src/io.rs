@@ -10,6 +10,7 @@ use kernel::ErrorCode;
 use nrf52840::gpio::Pin;
 
 use crate::CHIP;
+use crate::CRASH_RECORDER;
 use crate::PROCESSES;
 use crate::PROCESS_PRINTER;
 use kernel::hil::uart::Transmit;
@@ -119,7 +120,9 @@ impl IoWrite for Writer {
 
 /// Default panic handler for the Nano 33 Board.
 ///
-/// We just use the standard default provided by the debug module in the kernel.
+/// The panic is stored as a crash record in flash first, as the USB serial
+/// port is often not connected when the board panics. It is then printed by
+/// the standard default provided by the debug module in the kernel.
 #[cfg(not(test))]
 #[no_mangle]
 #[panic_handler]
@@ -127,6 +130,7 @@ pub unsafe extern "C" fn panic_fmt(pi: &PanicInfo) -> ! {
     let led_kernel_pin = &nrf52840::gpio::GPIOPin::new(Pin::P0_13);
     let led = &mut led::LedLow::new(led_kernel_pin);
     let writer = &mut WRITER;
+    debug::panic_crash_record(&CRASH_RECORDER, pi, &PROCESSES, &CHIP);
     debug::panic(
         &mut [led],
         writer,
@@ -84,6 +84,10 @@ pub mod io;
 const FAULT_RESPONSE: kernel::process::StopWithDebugFaultPolicy =
     kernel::process::StopWithDebugFaultPolicy {};
 
+// Crash records are stored in the last page of flash, which is left out of the
+// app flash region by the linker script.
+const CRASH_RECORD_FIRST_PAGE: usize = 0xFF000 / 4096;
+
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 8;
 
@@ -101,6 +105,7 @@ static mut CDC_REF_FOR_PANIC: Option<
     >,
 > = None;
 static mut NRF52_POWER: Option<&'static nrf52840::power::Power> = None;
+static mut CRASH_RECORDER: Option<&'static dyn kernel::crash_record::CrashRecorder> = None;
 
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
@@ -147,6 +152,8 @@ pub struct Platform {
         capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52::rtc::Rtc<'static>>,
     >,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    crash_record:
+        &'static capsules::crash_record::CrashRecordStorage<'static, nrf52840::nvmc::Nvmc>,
     scheduler: &'static RoundRobinSched<'static>,
     systick: cortexm4::systick::SysTick,
 }
@@ -169,6 +176,7 @@ impl SyscallDriverLookup for Platform {
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.ieee802154_radio)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::crash_record::DRIVER_NUM => f(Some(self.crash_record)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -603,6 +611,28 @@ pub unsafe fn main() {
     )
     .finalize(components::udp_driver_component_helper!(nrf52840::rtc::Rtc));
 
+    //--------------------------------------------------------------------------
+    // CRASH RECORDS
+    //--------------------------------------------------------------------------
+
+    // Record process faults before stopping the process, the panic handler
+    // records kernel panics.
+    let fault_policy = static_init!(
+        kernel::process::CrashRecordFaultPolicy<kernel::process::StopWithDebugFaultPolicy>,
+        kernel::process::CrashRecordFaultPolicy::new(FAULT_RESPONSE)
+    );
+    let crash_record = components::crash_record::CrashRecordComponent::new(
+        board_kernel,
+        capsules::crash_record::DRIVER_NUM,
+        &base_peripherals.nvmc,
+        CRASH_RECORD_FIRST_PAGE,
+        fault_policy,
+    )
+    .finalize(components::crash_record_component_helper!(
+        nrf52840::nvmc::Nvmc
+    ));
+    CRASH_RECORDER = Some(crash_record);
+
     //--------------------------------------------------------------------------
     // FINAL SETUP AND BOARD BOOT
     //--------------------------------------------------------------------------
@@ -628,6 +658,7 @@ pub unsafe fn main() {
         rng,
         alarm,
         udp_driver,
+        crash_record,
         ipc: kernel::ipc::IPC::new(
             board_kernel,
             kernel::ipc::DRIVER_NUM,
@@ -695,7 +726,7 @@ pub unsafe fn main() {
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
         &mut PROCESSES,
-        &FAULT_RESPONSE,
+        fault_policy,
         &process_management_capability,
     )
     .unwrap_or_else(|err| {
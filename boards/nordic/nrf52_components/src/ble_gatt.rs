@@ -0,0 +1,119 @@
+//! Component for a BLE GATT server on nRF52 based platforms.
+//!
+//! This creates the link layer, L2CAP, ATT server and attribute table of
+//! `capsules::ble`, and the syscall driver processes use to register their
+//! services. The server needs the radio to itself, so a board uses either
+//! this component or `BLEComponent`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ble_gatt = BleGattComponent::new(
+//!     board_kernel,
+//!     capsules::ble_gatt_driver::DRIVER_NUM,
+//!     &base_peripherals.ble_radio,
+//!     mux_alarm,
+//!     [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6],
+//!     b"Tock sensor",
+//! )
+//! .finalize(());
+//! ```
+
+use capsules;
+use capsules::ble::att::AttServer;
+use capsules::ble::gatt::AttributeTable;
+use capsules::ble::l2cap::L2cap;
+use capsules::ble::link_layer::{LinkLayer, ADDRESS_LEN};
+use capsules::ble_gatt_driver::BleGatt;
+use capsules::virtual_alarm::VirtualMuxAlarm;
+
+use nrf52::rtc::Rtc;
+
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::ble_connection::BleConnectionDriver;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init};
+
+/// Generic Unknown, from the Bluetooth Assigned Numbers.
+const APPEARANCE_UNKNOWN: u16 = 0x0000;
+
+pub struct BleGattComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    radio: &'static nrf52::ble_radio::Radio<'static>,
+    mux_alarm: &'static capsules::virtual_alarm::MuxAlarm<'static, nrf52::rtc::Rtc<'static>>,
+    address: [u8; ADDRESS_LEN],
+    device_name: &'static [u8],
+}
+
+impl BleGattComponent {
+    /// `address` is the static random device address, least significant
+    /// byte first. Its two most significant bits must be set.
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        radio: &'static nrf52::ble_radio::Radio,
+        mux_alarm: &'static capsules::virtual_alarm::MuxAlarm<'static, nrf52::rtc::Rtc>,
+        address: [u8; ADDRESS_LEN],
+        device_name: &'static [u8],
+    ) -> BleGattComponent {
+        BleGattComponent {
+            board_kernel,
+            driver_num,
+            radio,
+            mux_alarm,
+            address,
+            device_name,
+        }
+    }
+}
+
+impl Component for BleGattComponent {
+    type StaticInput = ();
+    type Output = &'static BleGatt<
+        'static,
+        nrf52::ble_radio::Radio<'static>,
+        VirtualMuxAlarm<'static, Rtc<'static>>,
+    >;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, Rtc>,
+            VirtualMuxAlarm::new(self.mux_alarm)
+        );
+        virtual_alarm.setup();
+
+        let link_layer = static_init!(
+            LinkLayer<'static, nrf52::ble_radio::Radio, VirtualMuxAlarm<'static, Rtc>>,
+            LinkLayer::new(self.radio, virtual_alarm, self.address)
+        );
+        self.radio.set_connection_client(link_layer);
+        virtual_alarm.set_alarm_client(link_layer);
+
+        let l2cap = static_init!(L2cap<'static>, L2cap::new(link_layer));
+        link_layer.set_client(l2cap);
+
+        let table = static_init!(
+            AttributeTable,
+            AttributeTable::new(self.device_name, APPEARANCE_UNKNOWN)
+        );
+        let att = static_init!(AttServer<'static>, AttServer::new(l2cap, table));
+        l2cap.set_att_client(att);
+
+        let ble_gatt = static_init!(
+            BleGatt<'static, nrf52::ble_radio::Radio, VirtualMuxAlarm<'static, Rtc>>,
+            BleGatt::new(
+                link_layer,
+                att,
+                table,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap)
+            )
+        );
+        att.set_client(ble_gatt);
+
+        ble_gatt
+    }
+}
@@ -1,9 +1,11 @@
 #![no_std]
 
 pub mod ble;
+pub mod ble_gatt;
 pub mod startup;
 
 pub use self::ble::BLEComponent;
+pub use self::ble_gatt::BleGattComponent;
 pub use self::startup::{
     NrfClockComponent, NrfStartupComponent, UartChannel, UartChannelComponent, UartPins,
 };
@@ -0,0 +1,205 @@
+//! Process credential checker that accepts processes with a valid SHA-256
+//! credential.
+//!
+//! The checker computes the SHA-256 hash of the integrity region of a process
+//! and compares it against the hash stored in a `SHA256` credentials footer.
+//! Processes whose hash matches are accepted, processes whose hash does not
+//! match (i.e. they were modified after the footer was written) are rejected.
+//! Credentials of any other format are passed on.
+//!
+//! Since the application binary lives in flash and the digest HIL operates on
+//! mutable buffers, the binary is copied into `data_buffer` one chunk at a
+//! time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let checker = static_init!(
+//!     capsules::app_checker_sha256::AppCheckerSha256<'static, lowrisc::hmac::Hmac>,
+//!     capsules::app_checker_sha256::AppCheckerSha256::new(
+//!         &peripherals.hmac,
+//!         &mut capsules::app_checker_sha256::DATA_BUFFER,
+//!         &mut capsules::app_checker_sha256::HASH_BUFFER,
+//!     )
+//! );
+//! digest::DigestData::set_data_client(&peripherals.hmac, checker);
+//! digest::DigestVerify::set_verify_client(&peripherals.hmac, checker);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil::digest;
+use kernel::process_checker::{AppCredentialsChecker, CheckResult, Client};
+use kernel::process_checker::{TbfFooterV2Credentials, TbfFooterV2CredentialsType};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// Buffer the application binary is copied into before it is hashed.
+pub static mut DATA_BUFFER: [u8; 64] = [0; 64];
+/// Buffer holding the expected hash during verification.
+pub static mut HASH_BUFFER: [u8; 32] = [0; 32];
+
+pub struct AppCheckerSha256<'a, H: digest::DigestDataVerify<'a, 32> + digest::Sha256> {
+    hasher: &'a H,
+    client: OptionalCell<&'a dyn Client<'a>>,
+    data_buffer: TakeCell<'static, [u8]>,
+    hash_buffer: TakeCell<'static, [u8; 32]>,
+    credentials: OptionalCell<TbfFooterV2Credentials>,
+    binary: OptionalCell<&'a [u8]>,
+    /// How many bytes of `binary` have been passed to the hasher.
+    offset: Cell<usize>,
+}
+
+impl<'a, H: digest::DigestDataVerify<'a, 32> + digest::Sha256> AppCheckerSha256<'a, H> {
+    pub fn new(
+        hasher: &'a H,
+        data_buffer: &'static mut [u8],
+        hash_buffer: &'static mut [u8; 32],
+    ) -> AppCheckerSha256<'a, H> {
+        AppCheckerSha256 {
+            hasher,
+            client: OptionalCell::empty(),
+            data_buffer: TakeCell::new(data_buffer),
+            hash_buffer: TakeCell::new(hash_buffer),
+            credentials: OptionalCell::empty(),
+            binary: OptionalCell::empty(),
+            offset: Cell::new(0),
+        }
+    }
+
+    /// Pass the next chunk of the binary to the hasher.
+    fn add_next_chunk(&self) -> Result<(), ErrorCode> {
+        let binary = self.binary.extract().ok_or(ErrorCode::FAIL)?;
+        let buffer = self.data_buffer.take().ok_or(ErrorCode::BUSY)?;
+
+        let offset = self.offset.get();
+        let len = cmp::min(binary.len() - offset, buffer.len());
+        buffer[..len].copy_from_slice(&binary[offset..offset + len]);
+        self.offset.set(offset + len);
+
+        let mut lease_buf = LeasableBuffer::new(buffer);
+        lease_buf.slice(..len);
+        self.hasher
+            .add_data(lease_buf)
+            .map(|_| ())
+            .map_err(|(e, buf)| {
+                self.data_buffer.replace(buf);
+                e
+            })
+    }
+
+    /// Compare the hash of the binary against the hash in the credentials.
+    fn verify(&self) -> Result<(), ErrorCode> {
+        let hash = self.hash_buffer.take().ok_or(ErrorCode::FAIL)?;
+        self.hasher.verify(hash).map_err(|(e, hash)| {
+            self.hash_buffer.replace(hash);
+            e
+        })
+    }
+
+    fn finish(&self, result: Result<CheckResult, ErrorCode>) {
+        self.hasher.clear_data();
+        let binary = self.binary.take();
+        let credentials = self.credentials.take();
+        if let (Some(binary), Some(credentials)) = (binary, credentials) {
+            self.client
+                .map(|client| client.check_done(result, credentials, binary));
+        }
+    }
+}
+
+impl<'a, H: digest::DigestDataVerify<'a, 32> + digest::Sha256> AppCredentialsChecker<'a>
+    for AppCheckerSha256<'a, H>
+{
+    fn set_client(&self, client: &'a dyn Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn require_credentials(&self) -> bool {
+        true
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        binary: &'a [u8],
+    ) -> Result<(), (ErrorCode, TbfFooterV2Credentials, &'a [u8])> {
+        if credentials.format() != TbfFooterV2CredentialsType::SHA256 {
+            return Err((ErrorCode::NOSUPPORT, credentials, binary));
+        }
+        if self.binary.is_some() {
+            return Err((ErrorCode::BUSY, credentials, binary));
+        }
+        if let Err(e) = self.hasher.set_mode_sha256() {
+            return Err((e, credentials, binary));
+        }
+
+        // The parser guarantees SHA256 credentials hold exactly 32 bytes.
+        let copied = self.hash_buffer.map(|hash| {
+            hash.copy_from_slice(credentials.data());
+        });
+        if copied.is_none() {
+            return Err((ErrorCode::FAIL, credentials, binary));
+        }
+
+        self.credentials.set(credentials);
+        self.binary.set(binary);
+        self.offset.set(0);
+
+        let started = if binary.is_empty() {
+            self.verify()
+        } else {
+            self.add_next_chunk()
+        };
+        started.map_err(|e| {
+            self.hasher.clear_data();
+            self.credentials.clear();
+            self.binary.clear();
+            (e, credentials, binary)
+        })
+    }
+}
+
+impl<'a, H: digest::DigestDataVerify<'a, 32> + digest::Sha256> digest::ClientData<'a, 32>
+    for AppCheckerSha256<'a, H>
+{
+    fn add_data_done(&'a self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.data_buffer.replace(data);
+
+        let next = result.and_then(|()| {
+            let done = self
+                .binary
+                .map_or(true, |binary| self.offset.get() >= binary.len());
+            if done {
+                self.verify()
+            } else {
+                self.add_next_chunk()
+            }
+        });
+        if let Err(e) = next {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, H: digest::DigestDataVerify<'a, 32> + digest::Sha256> digest::ClientVerify<'a, 32>
+    for AppCheckerSha256<'a, H>
+{
+    fn verification_done(
+        &'a self,
+        result: Result<bool, ErrorCode>,
+        compare: &'static mut [u8; 32],
+    ) {
+        self.hash_buffer.replace(compare);
+        self.finish(result.map(|matches| {
+            if matches {
+                CheckResult::Accept
+            } else {
+                CheckResult::Reject
+            }
+        }));
+    }
+}
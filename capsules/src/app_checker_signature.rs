@@ -0,0 +1,272 @@
+//! Process credential checker that accepts processes with a valid signature.
+//!
+//! The checker handles credentials of a single signature format chosen by the
+//! board (for example `Rsa4096Key` or `EcdsaNistP256`). It computes the SHA-256
+//! hash of the integrity region of a process and asks a `SignatureVerify`
+//! implementation whether the signature stored in the credentials footer is a
+//! valid signature over that hash. The public key used for verification is
+//! owned by the verifier, so only apps signed by the holder of the matching
+//! private key are accepted. Credentials of any other format are passed on.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let checker = static_init!(
+//!     capsules::app_checker_signature::AppCheckerSignature<
+//!         'static,
+//!         lowrisc::hmac::Hmac,
+//!         earlgrey::otbn::RsaVerifier,
+//!         512,
+//!     >,
+//!     capsules::app_checker_signature::AppCheckerSignature::new(
+//!         &peripherals.hmac,
+//!         rsa_verifier,
+//!         kernel::process_checker::TbfFooterV2CredentialsType::Rsa4096Key,
+//!         &mut capsules::app_checker_signature::DATA_BUFFER,
+//!         &mut capsules::app_checker_signature::HASH_BUFFER,
+//!         &mut SIGNATURE_BUFFER,
+//!     )
+//! );
+//! digest::DigestData::set_data_client(&peripherals.hmac, checker);
+//! digest::DigestHash::set_hash_client(&peripherals.hmac, checker);
+//! rsa_verifier.set_verify_client(checker);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil::digest;
+use kernel::hil::public_key_crypto::signature;
+use kernel::process_checker::{AppCredentialsChecker, CheckResult, Client};
+use kernel::process_checker::{TbfFooterV2Credentials, TbfFooterV2CredentialsType};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// Buffer the application binary is copied into before it is hashed.
+pub static mut DATA_BUFFER: [u8; 64] = [0; 64];
+/// Buffer holding the computed hash.
+pub static mut HASH_BUFFER: [u8; 32] = [0; 32];
+
+pub struct AppCheckerSignature<
+    'a,
+    H: digest::DigestDataHash<'a, 32> + digest::Sha256,
+    V: signature::SignatureVerify<'a, 32, SL>,
+    const SL: usize,
+> {
+    hasher: &'a H,
+    verifier: &'a V,
+    format: TbfFooterV2CredentialsType,
+    client: OptionalCell<&'a dyn Client<'a>>,
+    data_buffer: TakeCell<'static, [u8]>,
+    hash_buffer: TakeCell<'static, [u8; 32]>,
+    signature_buffer: TakeCell<'static, [u8; SL]>,
+    credentials: OptionalCell<TbfFooterV2Credentials>,
+    binary: OptionalCell<&'a [u8]>,
+    /// How many bytes of `binary` have been passed to the hasher.
+    offset: Cell<usize>,
+}
+
+impl<
+        'a,
+        H: digest::DigestDataHash<'a, 32> + digest::Sha256,
+        V: signature::SignatureVerify<'a, 32, SL>,
+        const SL: usize,
+    > AppCheckerSignature<'a, H, V, SL>
+{
+    pub fn new(
+        hasher: &'a H,
+        verifier: &'a V,
+        format: TbfFooterV2CredentialsType,
+        data_buffer: &'static mut [u8],
+        hash_buffer: &'static mut [u8; 32],
+        signature_buffer: &'static mut [u8; SL],
+    ) -> AppCheckerSignature<'a, H, V, SL> {
+        AppCheckerSignature {
+            hasher,
+            verifier,
+            format,
+            client: OptionalCell::empty(),
+            data_buffer: TakeCell::new(data_buffer),
+            hash_buffer: TakeCell::new(hash_buffer),
+            signature_buffer: TakeCell::new(signature_buffer),
+            credentials: OptionalCell::empty(),
+            binary: OptionalCell::empty(),
+            offset: Cell::new(0),
+        }
+    }
+
+    /// Pass the next chunk of the binary to the hasher.
+    fn add_next_chunk(&self) -> Result<(), ErrorCode> {
+        let binary = self.binary.extract().ok_or(ErrorCode::FAIL)?;
+        let buffer = self.data_buffer.take().ok_or(ErrorCode::BUSY)?;
+
+        let offset = self.offset.get();
+        let len = cmp::min(binary.len() - offset, buffer.len());
+        buffer[..len].copy_from_slice(&binary[offset..offset + len]);
+        self.offset.set(offset + len);
+
+        let mut lease_buf = LeasableBuffer::new(buffer);
+        lease_buf.slice(..len);
+        self.hasher
+            .add_data(lease_buf)
+            .map(|_| ())
+            .map_err(|(e, buf)| {
+                self.data_buffer.replace(buf);
+                e
+            })
+    }
+
+    /// Compute the final hash of the binary.
+    fn run_hash(&self) -> Result<(), ErrorCode> {
+        let hash = self.hash_buffer.take().ok_or(ErrorCode::FAIL)?;
+        self.hasher.run(hash).map_err(|(e, hash)| {
+            self.hash_buffer.replace(hash);
+            e
+        })
+    }
+
+    fn finish(&self, result: Result<CheckResult, ErrorCode>) {
+        self.hasher.clear_data();
+        let binary = self.binary.take();
+        let credentials = self.credentials.take();
+        if let (Some(binary), Some(credentials)) = (binary, credentials) {
+            self.client
+                .map(|client| client.check_done(result, credentials, binary));
+        }
+    }
+}
+
+impl<
+        'a,
+        H: digest::DigestDataHash<'a, 32> + digest::Sha256,
+        V: signature::SignatureVerify<'a, 32, SL>,
+        const SL: usize,
+    > AppCredentialsChecker<'a> for AppCheckerSignature<'a, H, V, SL>
+{
+    fn set_client(&self, client: &'a dyn Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn require_credentials(&self) -> bool {
+        true
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        binary: &'a [u8],
+    ) -> Result<(), (ErrorCode, TbfFooterV2Credentials, &'a [u8])> {
+        if credentials.format() != self.format || credentials.data().len() != SL {
+            return Err((ErrorCode::NOSUPPORT, credentials, binary));
+        }
+        if self.binary.is_some() {
+            return Err((ErrorCode::BUSY, credentials, binary));
+        }
+        if let Err(e) = self.hasher.set_mode_sha256() {
+            return Err((e, credentials, binary));
+        }
+
+        self.credentials.set(credentials);
+        self.binary.set(binary);
+        self.offset.set(0);
+
+        // An empty integrity region cannot happen for a valid TBF object, as
+        // it always includes the TBF header.
+        self.add_next_chunk().map_err(|e| {
+            self.hasher.clear_data();
+            self.credentials.clear();
+            self.binary.clear();
+            (e, credentials, binary)
+        })
+    }
+}
+
+impl<
+        'a,
+        H: digest::DigestDataHash<'a, 32> + digest::Sha256,
+        V: signature::SignatureVerify<'a, 32, SL>,
+        const SL: usize,
+    > digest::ClientData<'a, 32> for AppCheckerSignature<'a, H, V, SL>
+{
+    fn add_data_done(&'a self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.data_buffer.replace(data);
+
+        let next = result.and_then(|()| {
+            let done = self
+                .binary
+                .map_or(true, |binary| self.offset.get() >= binary.len());
+            if done {
+                self.run_hash()
+            } else {
+                self.add_next_chunk()
+            }
+        });
+        if let Err(e) = next {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<
+        'a,
+        H: digest::DigestDataHash<'a, 32> + digest::Sha256,
+        V: signature::SignatureVerify<'a, 32, SL>,
+        const SL: usize,
+    > digest::ClientHash<'a, 32> for AppCheckerSignature<'a, H, V, SL>
+{
+    fn hash_done(&'a self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 32]) {
+        // The hasher is no longer needed once the hash is computed.
+        self.hasher.clear_data();
+
+        if let Err(e) = result {
+            self.hash_buffer.replace(digest);
+            self.finish(Err(e));
+            return;
+        }
+
+        let signature = match self.signature_buffer.take() {
+            Some(signature) => signature,
+            None => {
+                self.hash_buffer.replace(digest);
+                self.finish(Err(ErrorCode::FAIL));
+                return;
+            }
+        };
+        self.credentials.map(|credentials| {
+            signature.copy_from_slice(credentials.data());
+        });
+
+        if let Err((e, hash, signature)) = self.verifier.verify(digest, signature) {
+            self.hash_buffer.replace(hash);
+            self.signature_buffer.replace(signature);
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<
+        'a,
+        H: digest::DigestDataHash<'a, 32> + digest::Sha256,
+        V: signature::SignatureVerify<'a, 32, SL>,
+        const SL: usize,
+    > signature::ClientVerify<32, SL> for AppCheckerSignature<'a, H, V, SL>
+{
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static mut [u8; 32],
+        signature: &'static mut [u8; SL],
+    ) {
+        self.hash_buffer.replace(hash);
+        self.signature_buffer.replace(signature);
+        self.finish(result.map(|valid| {
+            if valid {
+                CheckResult::Accept
+            } else {
+                CheckResult::Reject
+            }
+        }));
+    }
+}
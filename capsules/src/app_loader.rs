@@ -0,0 +1,409 @@
+//! Installs applications received over a UART while the kernel is running.
+//!
+//! The capsule receives a TBF object over any `hil::uart::UartData`, for
+//! example a virtual UART on top of the console UART or a USB CDC-ACM device,
+//! writes it to the app flash region through `hil::flash::Flash` and then has
+//! the kernel's `DynamicProcessLoad` implementation start it as a new process.
+//! This allows updating a single application in the field without reflashing
+//! the board.
+//!
+//! Protocol
+//! --------
+//!
+//! The host sends the first 8 bytes of the TBF object, which contain the TBF
+//! version and the lengths of the header and the whole object. It then sends
+//! the rest of the object in chunks of `CHUNK_SIZE` bytes, where the last
+//! chunk may be shorter. After the first 8 bytes and after every chunk, the
+//! host waits for a single status byte from the capsule before sending more
+//! data. The status byte is `0` on success and the `ErrorCode` of the failure
+//! otherwise. The status after the last chunk tells whether the process was
+//! started. On boards that check credentials, it only tells that the process
+//! was loaded, as it starts once its credentials are approved. After any
+//! failure, the capsule waits for the start of a new TBF
+//! object.
+//!
+//! The header of the object is only written to flash once the whole object
+//! has been written and is erased again if the kernel refuses to load the
+//! process, so an interrupted or rejected transfer is not found at the next
+//! boot.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{hil, static_init};
+//!
+//! let loader_uart = static_init!(UartDevice, UartDevice::new(uart_mux, true));
+//! loader_uart.setup();
+//! let page_buffer = static_init!(
+//!     nrf52840::nvmc::NrfPage,
+//!     nrf52840::nvmc::NrfPage::default()
+//! );
+//! let app_loader = static_init!(
+//!     capsules::app_loader::AppLoader<'static, nrf52840::nvmc::Nvmc>,
+//!     capsules::app_loader::AppLoader::new(
+//!         loader_uart,
+//!         &base_peripherals.nvmc,
+//!         dynamic_process_loader,
+//!         &mut capsules::app_loader::RX_BUF,
+//!         &mut capsules::app_loader::TX_BUF,
+//!         page_buffer,
+//!     )
+//! );
+//! hil::uart::Transmit::set_transmit_client(loader_uart, app_loader);
+//! hil::uart::Receive::set_receive_client(loader_uart, app_loader);
+//! hil::flash::HasClient::set_client(&base_peripherals.nvmc, app_loader);
+//! app_loader.start();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::dynamic_process_loader::{DynamicProcessLoad, FlashRegion};
+use kernel::hil;
+use kernel::hil::uart;
+use kernel::process::ProcessLoadError;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Number of bytes of the TBF object the host sends at once.
+pub const CHUNK_SIZE: usize = 512;
+
+/// Length of the start of a TBF object that contains its lengths.
+const TBF_LENGTHS_SIZE: usize = 8;
+
+pub static mut RX_BUF: [u8; CHUNK_SIZE] = [0; CHUNK_SIZE];
+pub static mut TX_BUF: [u8; 1] = [0; 1];
+
+/// What to do once the current flash write completes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    /// The padding object in front of the new object was written.
+    Padding,
+    /// Zeros were written in place of the header lengths of the new object.
+    Placeholder,
+    /// A chunk of the new object was written.
+    Chunk,
+    /// The header lengths of the new object were written.
+    Header,
+    /// The header lengths were erased again because loading failed.
+    Invalidate(ErrorCode),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Waiting for the start of a TBF object.
+    Idle,
+    /// Waiting for the next chunk of the TBF object.
+    Receiving,
+    /// Writing to flash.
+    Writing(Step),
+}
+
+pub struct AppLoader<'a, F: hil::flash::Flash + 'static> {
+    uart: &'a dyn uart::UartData<'a>,
+    flash: &'a F,
+    loader: &'a dyn DynamicProcessLoad,
+    state: Cell<State>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    page_buffer: TakeCell<'static, F::Page>,
+    page_size: usize,
+    /// Where the TBF object that is being received is written.
+    region: OptionalCell<FlashRegion>,
+    /// The first bytes of the TBF object, which are written last.
+    lengths: Cell<[u8; TBF_LENGTHS_SIZE]>,
+    /// Number of bytes of the TBF object that have been received.
+    received: Cell<usize>,
+    /// Absolute flash address the rest of `rx_buffer` is written to.
+    write_address: Cell<usize>,
+    /// Index in `rx_buffer` of the next byte to write.
+    write_index: Cell<usize>,
+    /// Number of bytes left to write.
+    write_remaining: Cell<usize>,
+}
+
+impl<'a, F: hil::flash::Flash> AppLoader<'a, F> {
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        flash: &'a F,
+        loader: &'a dyn DynamicProcessLoad,
+        rx_buffer: &'static mut [u8; CHUNK_SIZE],
+        tx_buffer: &'static mut [u8],
+        page_buffer: &'static mut F::Page,
+    ) -> AppLoader<'a, F> {
+        let page_size = page_buffer.as_mut().len();
+        AppLoader {
+            uart,
+            flash,
+            loader,
+            state: Cell::new(State::Idle),
+            rx_buffer: TakeCell::new(rx_buffer),
+            tx_buffer: TakeCell::new(tx_buffer),
+            page_buffer: TakeCell::new(page_buffer),
+            page_size,
+            region: OptionalCell::empty(),
+            lengths: Cell::new([0; TBF_LENGTHS_SIZE]),
+            received: Cell::new(0),
+            write_address: Cell::new(0),
+            write_index: Cell::new(0),
+            write_remaining: Cell::new(0),
+        }
+    }
+
+    /// Starts waiting for a TBF object.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        self.state.set(State::Idle);
+        self.receive(TBF_LENGTHS_SIZE)
+    }
+
+    fn receive(&self, len: usize) -> Result<(), ErrorCode> {
+        self.rx_buffer
+            .take()
+            .map_or(Err(ErrorCode::BUSY), |buffer| {
+                self.uart
+                    .receive_buffer(buffer, len)
+                    .map_err(|(err, buffer)| {
+                        self.rx_buffer.replace(buffer);
+                        err
+                    })
+            })
+    }
+
+    /// Sends the status byte for the last request of the host.
+    fn respond(&self, status: Result<(), ErrorCode>) {
+        self.tx_buffer.take().map(|buffer| {
+            buffer[0] = match status {
+                Ok(()) => 0,
+                Err(err) => usize::from(err) as u8,
+            };
+            if let Err((_, buffer)) = self.uart.transmit_buffer(buffer, 1) {
+                self.tx_buffer.replace(buffer);
+            }
+        });
+    }
+
+    /// Ends the current transfer and waits for the next TBF object.
+    fn finish(&self, status: Result<(), ErrorCode>) {
+        self.region.clear();
+        let _ = self.start();
+        self.respond(status);
+    }
+
+    /// Handles the start of a TBF object.
+    fn start_object(&self, lengths: [u8; TBF_LENGTHS_SIZE]) -> Result<(), ErrorCode> {
+        let version = u16::from_le_bytes([lengths[0], lengths[1]]);
+        let header_size = u16::from_le_bytes([lengths[2], lengths[3]]) as usize;
+        let total_size =
+            u32::from_le_bytes([lengths[4], lengths[5], lengths[6], lengths[7]]) as usize;
+        if version != 2 || header_size < 16 || header_size > total_size {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let region = self
+            .loader
+            .allocate_flash(total_size)
+            .map_err(load_error_code)?;
+        self.region.set(region);
+        self.lengths.set(lengths);
+        self.received.set(TBF_LENGTHS_SIZE);
+
+        match region.padding_header() {
+            Some(padding_header) => {
+                self.rx_buffer
+                    .map(|buffer| buffer[..padding_header.len()].copy_from_slice(&padding_header));
+                self.write(Step::Padding, region.padding_start, padding_header.len())
+            }
+            None => self.write_placeholder(),
+        }
+    }
+
+    /// Writes zeros in place of the header lengths, so that the object is not
+    /// found by the kernel until it has been written completely.
+    fn write_placeholder(&self) -> Result<(), ErrorCode> {
+        let start = self.region.map_or(0, |region| region.start);
+        self.rx_buffer
+            .map(|buffer| buffer[..TBF_LENGTHS_SIZE].fill(0));
+        self.write(Step::Placeholder, start, TBF_LENGTHS_SIZE)
+    }
+
+    /// Writes the first `len` bytes of `rx_buffer` to `address`.
+    fn write(&self, step: Step, address: usize, len: usize) -> Result<(), ErrorCode> {
+        self.state.set(State::Writing(step));
+        self.write_address.set(address);
+        self.write_index.set(0);
+        self.write_remaining.set(len);
+        self.read_page()
+    }
+
+    /// Reads the page containing `write_address`, so that the bytes of the
+    /// page outside of the written range are preserved.
+    fn read_page(&self) -> Result<(), ErrorCode> {
+        self.page_buffer
+            .take()
+            .map_or(Err(ErrorCode::NOMEM), |page_buffer| {
+                self.flash
+                    .read_page(self.write_address.get() / self.page_size, page_buffer)
+                    .map_err(|(err, page_buffer)| {
+                        self.page_buffer.replace(page_buffer);
+                        err
+                    })
+            })
+    }
+
+    /// Continues after a write of a whole range completed.
+    fn step_done(&self, step: Step) -> Result<(), ErrorCode> {
+        let region = self.region.extract().ok_or(ErrorCode::FAIL)?;
+        match step {
+            Step::Padding => self.write_placeholder(),
+            Step::Placeholder | Step::Chunk => {
+                let remaining = region.length - self.received.get();
+                if remaining > 0 {
+                    self.state.set(State::Receiving);
+                    self.receive(cmp::min(remaining, CHUNK_SIZE))?;
+                    self.respond(Ok(()));
+                    Ok(())
+                } else {
+                    let lengths = self.lengths.get();
+                    self.rx_buffer
+                        .map(|buffer| buffer[..TBF_LENGTHS_SIZE].copy_from_slice(&lengths));
+                    self.write(Step::Header, region.start, TBF_LENGTHS_SIZE)
+                }
+            }
+            Step::Header => match self.loader.load_process(&region) {
+                Ok(_) => {
+                    self.finish(Ok(()));
+                    Ok(())
+                }
+                Err(err) => {
+                    self.rx_buffer
+                        .map(|buffer| buffer[..TBF_LENGTHS_SIZE].fill(0));
+                    self.write(
+                        Step::Invalidate(load_error_code(err)),
+                        region.start,
+                        TBF_LENGTHS_SIZE,
+                    )
+                }
+            },
+            Step::Invalidate(err) => Err(err),
+        }
+    }
+}
+
+/// Maps the reason the kernel refused a process to the status sent to the
+/// host.
+fn load_error_code(err: ProcessLoadError) -> ErrorCode {
+    match err {
+        ProcessLoadError::NotEnoughFlash
+        | ProcessLoadError::NotEnoughMemory
+        | ProcessLoadError::NoProcessSlot => ErrorCode::NOMEM,
+        ProcessLoadError::TbfHeaderParseFailure(_)
+        | ProcessLoadError::NotEnabled
+        | ProcessLoadError::IncompatibleKernelVersion { .. }
+        | ProcessLoadError::InvalidBinaryEnd
+        | ProcessLoadError::IncorrectFlashAddress { .. } => ErrorCode::INVAL,
+        _ => ErrorCode::FAIL,
+    }
+}
+
+impl<'a, F: hil::flash::Flash> uart::TransmitClient for AppLoader<'a, F> {
+    fn transmitted_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rcode: Result<(), ErrorCode>,
+    ) {
+        self.tx_buffer.replace(buffer);
+    }
+}
+
+impl<'a, F: hil::flash::Flash> uart::ReceiveClient for AppLoader<'a, F> {
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        rcode: Result<(), ErrorCode>,
+        error: uart::Error,
+    ) {
+        if rcode.is_err() || error != uart::Error::None {
+            self.rx_buffer.replace(buffer);
+            self.finish(Err(ErrorCode::FAIL));
+            return;
+        }
+
+        let mut lengths = [0; TBF_LENGTHS_SIZE];
+        if self.state.get() == State::Idle {
+            lengths.copy_from_slice(&buffer[..TBF_LENGTHS_SIZE]);
+        }
+        self.rx_buffer.replace(buffer);
+
+        let result = match self.state.get() {
+            State::Idle => self.start_object(lengths),
+            State::Receiving => {
+                let address = self.region.map_or(0, |region| region.start) + self.received.get();
+                self.write(Step::Chunk, address, rx_len)
+            }
+            State::Writing(_) => Err(ErrorCode::BUSY),
+        };
+        if let Err(err) = result {
+            self.finish(Err(err));
+        }
+    }
+}
+
+impl<'a, F: hil::flash::Flash> hil::flash::Client<F> for AppLoader<'a, F> {
+    fn read_complete(&self, page_buffer: &'static mut F::Page, error: hil::flash::Error) {
+        if error != hil::flash::Error::CommandComplete {
+            self.page_buffer.replace(page_buffer);
+            self.finish(Err(ErrorCode::FAIL));
+            return;
+        }
+
+        let address = self.write_address.get();
+        let page_offset = address % self.page_size;
+        let len = cmp::min(self.write_remaining.get(), self.page_size - page_offset);
+        let index = self.write_index.get();
+        self.rx_buffer.map(|buffer| {
+            page_buffer.as_mut()[page_offset..page_offset + len]
+                .copy_from_slice(&buffer[index..index + len])
+        });
+        self.write_address.set(address + len);
+        self.write_index.set(index + len);
+        self.write_remaining.set(self.write_remaining.get() - len);
+
+        if let Err((err, page_buffer)) =
+            self.flash.write_page(address / self.page_size, page_buffer)
+        {
+            self.page_buffer.replace(page_buffer);
+            self.finish(Err(err));
+        }
+    }
+
+    fn write_complete(&self, page_buffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.page_buffer.replace(page_buffer);
+        if error != hil::flash::Error::CommandComplete {
+            self.finish(Err(ErrorCode::FAIL));
+            return;
+        }
+
+        let result = if self.write_remaining.get() > 0 {
+            self.read_page()
+        } else {
+            match self.state.get() {
+                State::Writing(step) => {
+                    if step == Step::Chunk {
+                        self.received
+                            .set(self.received.get() + self.write_index.get());
+                    }
+                    self.step_done(step)
+                }
+                _ => Err(ErrorCode::FAIL),
+            }
+        };
+        if let Err(err) = result {
+            self.finish(Err(err));
+        }
+    }
+
+    fn erase_complete(&self, _error: hil::flash::Error) {}
+}
@@ -0,0 +1,587 @@
+//! Attribute Protocol server.
+//!
+//! Answers the requests of a GATT client from an `AttributeTable`: MTU
+//! exchange, service and characteristic discovery, reads and writes. Values
+//! written by the client are reported to the `Client`, and the owner of a
+//! characteristic can notify the client of its value with `notify()`.
+//!
+//! The server keeps the default ATT_MTU of 23 bytes, longer values are read
+//! with Read Blob requests. Prepared writes and indications are not
+//! supported.
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F]
+
+use core::cmp;
+
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+use super::gatt::{AttributeTable, Uuid, PRIMARY_SERVICE, SECONDARY_SERVICE};
+use super::l2cap;
+use super::l2cap::L2cap;
+
+pub const ATT_MTU: usize = 23;
+
+/// ATT error codes.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AttError {
+    InvalidHandle = 0x01,
+    ReadNotPermitted = 0x02,
+    WriteNotPermitted = 0x03,
+    InvalidPdu = 0x04,
+    RequestNotSupported = 0x06,
+    InvalidOffset = 0x07,
+    AttributeNotFound = 0x0A,
+    InvalidAttributeValueLength = 0x0D,
+    UnsupportedGroupType = 0x10,
+}
+
+/// ATT opcodes.
+///
+/// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F], section 3.4.8
+mod opcode {
+    pub const ERROR_RSP: u8 = 0x01;
+    pub const EXCHANGE_MTU_REQ: u8 = 0x02;
+    pub const EXCHANGE_MTU_RSP: u8 = 0x03;
+    pub const FIND_INFORMATION_REQ: u8 = 0x04;
+    pub const FIND_INFORMATION_RSP: u8 = 0x05;
+    pub const FIND_BY_TYPE_VALUE_REQ: u8 = 0x06;
+    pub const FIND_BY_TYPE_VALUE_RSP: u8 = 0x07;
+    pub const READ_BY_TYPE_REQ: u8 = 0x08;
+    pub const READ_BY_TYPE_RSP: u8 = 0x09;
+    pub const READ_REQ: u8 = 0x0A;
+    pub const READ_RSP: u8 = 0x0B;
+    pub const READ_BLOB_REQ: u8 = 0x0C;
+    pub const READ_BLOB_RSP: u8 = 0x0D;
+    pub const READ_BY_GROUP_TYPE_REQ: u8 = 0x10;
+    pub const READ_BY_GROUP_TYPE_RSP: u8 = 0x11;
+    pub const WRITE_REQ: u8 = 0x12;
+    pub const WRITE_RSP: u8 = 0x13;
+    pub const HANDLE_VALUE_NTF: u8 = 0x1B;
+    pub const WRITE_CMD: u8 = 0x52;
+    /// Commands are not answered, not even with an error.
+    pub const COMMAND_FLAG: u8 = 0x40;
+}
+
+/// A failed request: the handle the error refers to, and the error.
+type RequestError = (u16, AttError);
+
+pub trait Client {
+    fn connected(&self);
+    fn disconnected(&self, reason: u8);
+
+    /// The client wrote `len` bytes to the attribute with `handle`.
+    fn value_written(&self, handle: u16, len: usize);
+}
+
+fn u16_at(pdu: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([pdu[i], pdu[i + 1]])
+}
+
+pub struct AttServer<'a> {
+    l2cap: &'a L2cap<'a>,
+    table: &'a AttributeTable,
+    client: OptionalCell<&'a dyn Client>,
+}
+
+impl<'a> AttServer<'a> {
+    pub fn new(l2cap: &'a L2cap<'a>, table: &'a AttributeTable) -> AttServer<'a> {
+        AttServer {
+            l2cap,
+            table,
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    /// Sends the value of the characteristic with value `handle` to the
+    /// client, if it enabled notifications. Values longer than ATT_MTU - 3
+    /// are truncated.
+    pub fn notify(&self, handle: u16) -> Result<(), ErrorCode> {
+        if !self.table.notifications_enabled(handle) {
+            return Err(ErrorCode::OFF);
+        }
+        let mut pdu = [0; ATT_MTU];
+        pdu[0] = opcode::HANDLE_VALUE_NTF;
+        pdu[1..3].copy_from_slice(&handle.to_le_bytes());
+        let len = self
+            .table
+            .with_value(handle, |value| {
+                let len = cmp::min(value.len(), ATT_MTU - 3);
+                pdu[3..3 + len].copy_from_slice(&value[..len]);
+                len
+            })
+            .ok_or(ErrorCode::INVAL)?;
+        self.l2cap.send(l2cap::CID_ATT, &pdu[..3 + len])
+    }
+
+    /// Returns the last handle to consider for a request for `start` to
+    /// `end`.
+    fn check_range(&self, start: u16, end: u16) -> Result<u16, RequestError> {
+        if start == 0 || start > end {
+            return Err((start, AttError::InvalidHandle));
+        }
+        if start > self.table.last_handle() {
+            return Err((start, AttError::AttributeNotFound));
+        }
+        Ok(cmp::min(end, self.table.last_handle()))
+    }
+
+    fn exchange_mtu(&self, response: &mut [u8]) -> Result<usize, RequestError> {
+        response[0] = opcode::EXCHANGE_MTU_RSP;
+        response[1..3].copy_from_slice(&(ATT_MTU as u16).to_le_bytes());
+        Ok(3)
+    }
+
+    fn find_information(&self, request: &[u8], response: &mut [u8]) -> Result<usize, RequestError> {
+        let start = u16_at(request, 1);
+        let end = self.check_range(start, u16_at(request, 3))?;
+        response[0] = opcode::FIND_INFORMATION_RSP;
+        let mut len = 2;
+        let mut uuid_len = 0;
+        for handle in start..=end {
+            let typ = match self.table.attribute_type(handle) {
+                Some(typ) => typ,
+                None => break,
+            };
+            // All entries have the same format, 16 or 128-bit UUIDs
+            if uuid_len == 0 {
+                uuid_len = typ.len();
+                response[1] = if uuid_len == 2 { 1 } else { 2 };
+            }
+            if typ.len() != uuid_len || len + 2 + uuid_len > ATT_MTU {
+                break;
+            }
+            response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+            typ.write_to(&mut response[len + 2..]);
+            len += 2 + uuid_len;
+        }
+        if len == 2 {
+            return Err((start, AttError::AttributeNotFound));
+        }
+        Ok(len)
+    }
+
+    fn find_by_type_value(
+        &self,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, RequestError> {
+        let start = u16_at(request, 1);
+        let end = self.check_range(start, u16_at(request, 3))?;
+        let typ = Uuid::Uuid16(u16_at(request, 5));
+        let value = &request[7..];
+        response[0] = opcode::FIND_BY_TYPE_VALUE_RSP;
+        let mut len = 1;
+        for handle in start..=end {
+            if len + 4 > ATT_MTU {
+                break;
+            }
+            if self.table.attribute_type(handle) != Some(typ)
+                || self.table.with_value(handle, |v| v == value) != Some(true)
+            {
+                continue;
+            }
+            let group_end = if self.table.is_service(handle) {
+                self.table.service_end(handle)
+            } else {
+                handle
+            };
+            response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+            response[len + 2..len + 4].copy_from_slice(&group_end.to_le_bytes());
+            len += 4;
+        }
+        if len == 1 {
+            return Err((start, AttError::AttributeNotFound));
+        }
+        Ok(len)
+    }
+
+    fn read_by_type(&self, request: &[u8], response: &mut [u8]) -> Result<usize, RequestError> {
+        let start = u16_at(request, 1);
+        let end = self.check_range(start, u16_at(request, 3))?;
+        let typ = Uuid::from_bytes(&request[5..]).ok_or((0, AttError::InvalidPdu))?;
+        response[0] = opcode::READ_BY_TYPE_RSP;
+        let mut len = 2;
+        let mut entry_len = 0;
+        for handle in start..=end {
+            if self.table.attribute_type(handle) != Some(typ) {
+                continue;
+            }
+            // Each value is truncated to fit a single entry in the response
+            let mut value = [0; ATT_MTU - 4];
+            let value_len = match self.table.read(handle, 0, &mut value) {
+                Ok(value_len) => value_len,
+                Err(error) if entry_len == 0 => return Err((handle, error)),
+                Err(_) => break,
+            };
+            // All entries have the same length
+            if entry_len == 0 {
+                entry_len = 2 + value_len;
+                response[1] = entry_len as u8;
+            }
+            if 2 + value_len != entry_len || len + entry_len > ATT_MTU {
+                break;
+            }
+            response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+            response[len + 2..len + entry_len].copy_from_slice(&value[..value_len]);
+            len += entry_len;
+        }
+        if len == 2 {
+            return Err((start, AttError::AttributeNotFound));
+        }
+        Ok(len)
+    }
+
+    fn read(&self, request: &[u8], response: &mut [u8]) -> Result<usize, RequestError> {
+        let handle = u16_at(request, 1);
+        let offset = if request[0] == opcode::READ_BLOB_REQ {
+            response[0] = opcode::READ_BLOB_RSP;
+            u16_at(request, 3) as usize
+        } else {
+            response[0] = opcode::READ_RSP;
+            0
+        };
+        self.table
+            .read(handle, offset, &mut response[1..ATT_MTU])
+            .map(|len| 1 + len)
+            .map_err(|error| (handle, error))
+    }
+
+    fn read_by_group_type(
+        &self,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, RequestError> {
+        let start = u16_at(request, 1);
+        let end = self.check_range(start, u16_at(request, 3))?;
+        match Uuid::from_bytes(&request[5..]) {
+            Some(Uuid::Uuid16(PRIMARY_SERVICE)) => {}
+            // There are no secondary services
+            Some(Uuid::Uuid16(SECONDARY_SERVICE)) => {
+                return Err((start, AttError::AttributeNotFound))
+            }
+            Some(_) => return Err((start, AttError::UnsupportedGroupType)),
+            None => return Err((0, AttError::InvalidPdu)),
+        }
+        response[0] = opcode::READ_BY_GROUP_TYPE_RSP;
+        let mut len = 2;
+        let mut entry_len = 0;
+        for handle in start..=end {
+            if !self.table.is_service(handle) {
+                continue;
+            }
+            let mut value = [0; 16];
+            let value_len = self.table.read(handle, 0, &mut value).unwrap_or(0);
+            // All entries have the same length
+            if entry_len == 0 {
+                entry_len = 4 + value_len;
+                response[1] = entry_len as u8;
+            }
+            if 4 + value_len != entry_len || len + entry_len > ATT_MTU {
+                break;
+            }
+            let group_end = self.table.service_end(handle);
+            response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+            response[len + 2..len + 4].copy_from_slice(&group_end.to_le_bytes());
+            response[len + 4..len + entry_len].copy_from_slice(&value[..value_len]);
+            len += entry_len;
+        }
+        if len == 2 {
+            return Err((start, AttError::AttributeNotFound));
+        }
+        Ok(len)
+    }
+
+    fn write(&self, request: &[u8], response: &mut [u8]) -> Result<usize, RequestError> {
+        let handle = u16_at(request, 1);
+        let value = &request[3..];
+        self.table
+            .write(handle, value)
+            .map_err(|error| (handle, error))?;
+        self.client
+            .map(|client| client.value_written(handle, value.len()));
+        if request[0] == opcode::WRITE_CMD {
+            Ok(0)
+        } else {
+            response[0] = opcode::WRITE_RSP;
+            Ok(1)
+        }
+    }
+
+    fn handle_request(&self, request: &[u8], response: &mut [u8]) -> Result<usize, RequestError> {
+        // Minimum length of each request, including the opcode
+        let min_len = match request[0] {
+            opcode::EXCHANGE_MTU_REQ => 3,
+            opcode::FIND_INFORMATION_REQ => 5,
+            opcode::FIND_BY_TYPE_VALUE_REQ => 7,
+            opcode::READ_BY_TYPE_REQ | opcode::READ_BY_GROUP_TYPE_REQ => 7,
+            opcode::READ_REQ => 3,
+            opcode::READ_BLOB_REQ => 5,
+            opcode::WRITE_REQ | opcode::WRITE_CMD => 3,
+            _ => return Err((0, AttError::RequestNotSupported)),
+        };
+        if request.len() < min_len {
+            return Err((0, AttError::InvalidPdu));
+        }
+        match request[0] {
+            opcode::EXCHANGE_MTU_REQ => self.exchange_mtu(response),
+            opcode::FIND_INFORMATION_REQ => self.find_information(request, response),
+            opcode::FIND_BY_TYPE_VALUE_REQ => self.find_by_type_value(request, response),
+            opcode::READ_BY_TYPE_REQ => self.read_by_type(request, response),
+            opcode::READ_REQ | opcode::READ_BLOB_REQ => self.read(request, response),
+            opcode::READ_BY_GROUP_TYPE_REQ => self.read_by_group_type(request, response),
+            _ => self.write(request, response),
+        }
+    }
+}
+
+impl<'a> l2cap::Client for AttServer<'a> {
+    fn connected(&self) {
+        self.client.map(|client| client.connected());
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.table.reset_client_configurations();
+        self.client.map(|client| client.disconnected(reason));
+    }
+
+    fn received(&self, request: &[u8]) {
+        let request_opcode = match request.first() {
+            Some(request_opcode) => *request_opcode,
+            None => return,
+        };
+        let mut response = [0; ATT_MTU];
+        let len = match self.handle_request(request, &mut response) {
+            Ok(len) => len,
+            Err(_) if request_opcode & opcode::COMMAND_FLAG != 0 => 0,
+            Err((handle, error)) => {
+                response[0] = opcode::ERROR_RSP;
+                response[1] = request_opcode;
+                response[2..4].copy_from_slice(&handle.to_le_bytes());
+                response[4] = error as u8;
+                5
+            }
+        };
+        if len > 0 {
+            let _ = self.l2cap.send(l2cap::CID_ATT, &response[..len]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::gatt::properties;
+    use super::super::link_layer;
+    use super::*;
+    use kernel::utilities::cells::MapCell;
+    use opcode::{
+        ERROR_RSP, EXCHANGE_MTU_REQ, EXCHANGE_MTU_RSP, FIND_BY_TYPE_VALUE_REQ,
+        FIND_BY_TYPE_VALUE_RSP, FIND_INFORMATION_REQ, READ_BLOB_REQ, READ_BLOB_RSP,
+        READ_BY_GROUP_TYPE_REQ, READ_BY_GROUP_TYPE_RSP, READ_BY_TYPE_REQ, READ_REQ, READ_RSP,
+        WRITE_CMD, WRITE_REQ, WRITE_RSP,
+    };
+
+    /// Keeps the ATT PDU of the last L2CAP PDU sent.
+    struct FakeLink {
+        sent: MapCell<([u8; l2cap::MAX_PDU_LEN], usize)>,
+    }
+
+    impl link_layer::Transmit for FakeLink {
+        fn send(&self, pdu: &[u8]) -> Result<(), ErrorCode> {
+            let mut buf = [0; l2cap::MAX_PDU_LEN];
+            buf[..pdu.len() - 4].copy_from_slice(&pdu[4..]);
+            self.sent.replace((buf, pdu.len() - 4));
+            Ok(())
+        }
+    }
+
+    /// Handle of the device name value in the GAP service.
+    const DEVICE_NAME_HANDLE: u8 = 3;
+    /// Handle of the readable and writable characteristic value.
+    const VALUE_HANDLE: u16 = 9;
+
+    /// Sends `request` to a server with one characteristic and checks the
+    /// response.
+    fn check(request: &[u8], expected: &[u8]) {
+        let link = FakeLink {
+            sent: MapCell::empty(),
+        };
+        let l2cap = L2cap::new(&link);
+        let table = AttributeTable::new(b"Tock", 0x1234);
+        table.add_service(Uuid::Uuid16(0xaaaa), 0).unwrap();
+        assert_eq!(
+            table.add_characteristic(
+                Uuid::Uuid16(0xaaab),
+                properties::READ | properties::WRITE,
+                8,
+                0,
+            ),
+            Ok(VALUE_HANDLE)
+        );
+        let server = AttServer::new(&l2cap, &table);
+
+        l2cap::Client::received(&server, request);
+        match link.sent.take() {
+            Some((buf, len)) => assert_eq!(&buf[..len], expected),
+            None => assert!(expected.is_empty()),
+        }
+    }
+
+    /// Checks that `request` is answered with an error about `handle`.
+    fn check_error(request: &[u8], handle: u8, error: AttError) {
+        check(request, &[ERROR_RSP, request[0], handle, 0, error as u8]);
+    }
+
+    #[test]
+    fn test_read_device_name() {
+        check(
+            &[READ_REQ, DEVICE_NAME_HANDLE, 0],
+            &[READ_RSP, b'T', b'o', b'c', b'k'],
+        );
+        check(
+            &[READ_BLOB_REQ, DEVICE_NAME_HANDLE, 0, 2, 0],
+            &[READ_BLOB_RSP, b'c', b'k'],
+        );
+        check_error(
+            &[READ_BLOB_REQ, DEVICE_NAME_HANDLE, 0, 5, 0],
+            DEVICE_NAME_HANDLE,
+            AttError::InvalidOffset,
+        );
+    }
+
+    #[test]
+    fn test_write_value() {
+        check(&[WRITE_REQ, VALUE_HANDLE as u8, 0, 1, 2], &[WRITE_RSP]);
+        check(&[WRITE_CMD, VALUE_HANDLE as u8, 0, 1, 2], &[]);
+        check_error(
+            &[WRITE_REQ, VALUE_HANDLE as u8, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            VALUE_HANDLE as u8,
+            AttError::InvalidAttributeValueLength,
+        );
+    }
+
+    #[test]
+    fn test_write_device_name() {
+        check_error(
+            &[WRITE_REQ, DEVICE_NAME_HANDLE, 0, b'x'],
+            DEVICE_NAME_HANDLE,
+            AttError::WriteNotPermitted,
+        );
+        // Commands are never answered.
+        check(&[WRITE_CMD, DEVICE_NAME_HANDLE, 0, b'x'], &[]);
+    }
+
+    #[test]
+    fn test_short_requests() {
+        let requests: &[&[u8]] = &[
+            &[EXCHANGE_MTU_REQ, 23],
+            &[FIND_INFORMATION_REQ, 1, 0, 0xff],
+            &[FIND_BY_TYPE_VALUE_REQ, 1, 0, 0xff, 0xff, 0x00],
+            &[READ_BY_TYPE_REQ, 1, 0, 0xff, 0xff, 0x00],
+            &[READ_BY_GROUP_TYPE_REQ, 1, 0, 0xff, 0xff, 0x00],
+            &[READ_REQ, 3],
+            &[READ_BLOB_REQ, 3, 0, 0],
+            &[WRITE_REQ, 9],
+            // A UUID of unsupported length
+            &[READ_BY_TYPE_REQ, 1, 0, 0xff, 0xff, 0x00, 0x28, 0x00],
+        ];
+        for request in requests {
+            check_error(request, 0, AttError::InvalidPdu);
+        }
+        check(&[WRITE_CMD, 9], &[]);
+    }
+
+    #[test]
+    fn test_unsupported_requests() {
+        // Prepare Write Request and Handle Value Confirmation
+        check_error(&[0x16, 9, 0, 0, 0], 0, AttError::RequestNotSupported);
+        check_error(&[0x1e], 0, AttError::RequestNotSupported);
+        // Signed Write Command
+        check(&[0xd2, 9, 0, 1], &[]);
+    }
+
+    #[test]
+    fn test_invalid_handles() {
+        check_error(&[READ_REQ, 0, 0], 0, AttError::InvalidHandle);
+        check_error(&[READ_REQ, 0x40, 0], 0x40, AttError::InvalidHandle);
+        check_error(
+            &[FIND_INFORMATION_REQ, 0, 0, 0xff, 0xff],
+            0,
+            AttError::InvalidHandle,
+        );
+        check_error(
+            &[FIND_INFORMATION_REQ, 5, 0, 4, 0],
+            5,
+            AttError::InvalidHandle,
+        );
+        check_error(
+            &[FIND_INFORMATION_REQ, 0x40, 0, 0xff, 0xff],
+            0x40,
+            AttError::AttributeNotFound,
+        );
+    }
+
+    #[test]
+    fn test_discovery() {
+        check(&[EXCHANGE_MTU_REQ, 0xf7, 0x00], &[EXCHANGE_MTU_RSP, 23, 0]);
+        // The GAP, GATT and custom services
+        check(
+            &[READ_BY_GROUP_TYPE_REQ, 1, 0, 0xff, 0xff, 0x00, 0x28],
+            &[
+                READ_BY_GROUP_TYPE_RSP,
+                6,
+                1,
+                0,
+                5,
+                0,
+                0x00,
+                0x18,
+                6,
+                0,
+                6,
+                0,
+                0x01,
+                0x18,
+                7,
+                0,
+                9,
+                0,
+                0xaa,
+                0xaa,
+            ],
+        );
+        check(
+            &[
+                FIND_BY_TYPE_VALUE_REQ,
+                1,
+                0,
+                0xff,
+                0xff,
+                0x00,
+                0x28,
+                0xaa,
+                0xaa,
+            ],
+            &[FIND_BY_TYPE_VALUE_RSP, 7, 0, 9, 0],
+        );
+        check_error(
+            &[READ_BY_GROUP_TYPE_REQ, 1, 0, 0xff, 0xff, 0x03, 0x28],
+            1,
+            AttError::UnsupportedGroupType,
+        );
+        check_error(
+            &[READ_BY_GROUP_TYPE_REQ, 1, 0, 0xff, 0xff, 0x01, 0x28],
+            1,
+            AttError::AttributeNotFound,
+        );
+        check_error(
+            &[READ_BY_TYPE_REQ, 1, 0, 0xff, 0xff, 0x34, 0x12],
+            1,
+            AttError::AttributeNotFound,
+        );
+    }
+}
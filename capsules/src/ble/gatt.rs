@@ -0,0 +1,396 @@
+//! GATT server attribute table.
+//!
+//! The table holds the attributes of the services a GATT server exposes, in
+//! handle order: a service declaration, followed by a declaration and a value
+//! attribute for each of its characteristics. Characteristics that support
+//! notifications also get a Client Characteristic Configuration descriptor,
+//! directly after their value.
+//!
+//! The table always starts with the Generic Access service, holding the device
+//! name and appearance, and the Generic Attribute service. Further services
+//! are added at runtime. Attributes cannot be removed, so the services should
+//! be set up before a client connects.
+//!
+//! Values are stored in the table, so they can be read at any time without
+//! involving their owner.
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::utilities::cells::MapCell;
+use kernel::ErrorCode;
+
+use super::att::AttError;
+
+/// Maximum number of attributes, including the 6 of the GAP and GATT
+/// services.
+pub const MAX_ATTRIBUTES: usize = 48;
+/// Size of the storage for attribute values.
+pub const VALUE_STORAGE_LEN: usize = 512;
+/// Maximum length of the device name.
+pub const MAX_DEVICE_NAME_LEN: usize = 20;
+
+// Bluetooth Assigned Numbers, GATT declarations and descriptors
+pub const PRIMARY_SERVICE: u16 = 0x2800;
+pub const SECONDARY_SERVICE: u16 = 0x2801;
+const CHARACTERISTIC: u16 = 0x2803;
+const CLIENT_CHARACTERISTIC_CONFIGURATION: u16 = 0x2902;
+
+// Bluetooth Assigned Numbers, services and characteristics
+const GAP_SERVICE: u16 = 0x1800;
+const GATT_SERVICE: u16 = 0x1801;
+const DEVICE_NAME: u16 = 0x2A00;
+const APPEARANCE: u16 = 0x2A01;
+
+/// Characteristic properties.
+///
+/// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part G], section 3.3.1.1
+pub mod properties {
+    pub const READ: u8 = 0x02;
+    pub const WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+    pub const WRITE: u8 = 0x08;
+    pub const NOTIFY: u8 = 0x10;
+}
+
+/// Attribute permissions.
+const READABLE: u8 = 0x01;
+const WRITABLE: u8 = 0x02;
+
+/// Owner of the attributes of the GAP and GATT services.
+const OWNER_NONE: usize = usize::MAX;
+
+/// The Bluetooth Base UUID, 00000000-0000-1000-8000-00805F9B34FB, in little
+/// endian byte order. 16-bit UUIDs replace bytes 12 and 13.
+const BASE_UUID: [u8; 16] = [
+    0xFB, 0x34, 0x9B, 0x5F, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Uuid {
+    Uuid16(u16),
+    Uuid128([u8; 16]),
+}
+
+impl Uuid {
+    /// Parses a 2 or 16 byte UUID in little endian byte order, as it is
+    /// transmitted. 128-bit UUIDs based on the Bluetooth Base UUID are
+    /// returned as 16-bit UUIDs so that both forms compare equal.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Uuid> {
+        match bytes.len() {
+            2 => Some(Uuid::Uuid16(u16::from_le_bytes([bytes[0], bytes[1]]))),
+            16 => {
+                let mut uuid = [0; 16];
+                uuid.copy_from_slice(bytes);
+                let mut base = uuid;
+                base[12] = 0;
+                base[13] = 0;
+                if base == BASE_UUID {
+                    Some(Uuid::Uuid16(u16::from_le_bytes([uuid[12], uuid[13]])))
+                } else {
+                    Some(Uuid::Uuid128(uuid))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Length of the UUID on air.
+    pub fn len(&self) -> usize {
+        match self {
+            Uuid::Uuid16(_) => 2,
+            Uuid::Uuid128(_) => 16,
+        }
+    }
+
+    /// Writes the UUID in little endian byte order to the start of `buf`,
+    /// which must be at least `len()` bytes long.
+    pub fn write_to(&self, buf: &mut [u8]) {
+        match self {
+            Uuid::Uuid16(uuid) => buf[..2].copy_from_slice(&uuid.to_le_bytes()),
+            Uuid::Uuid128(uuid) => buf[..16].copy_from_slice(uuid),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Attribute {
+    typ: Uuid,
+    permissions: u8,
+    /// Location of the value in the value storage.
+    offset: u16,
+    len: u16,
+    capacity: u16,
+    /// Identifies who added the attribute, for the user of the table.
+    owner: usize,
+}
+
+impl Attribute {
+    const EMPTY: Attribute = Attribute {
+        typ: Uuid::Uuid16(0),
+        permissions: 0,
+        offset: 0,
+        len: 0,
+        capacity: 0,
+        owner: OWNER_NONE,
+    };
+}
+
+pub struct AttributeTable {
+    attributes: MapCell<[Attribute; MAX_ATTRIBUTES]>,
+    count: Cell<usize>,
+    values: MapCell<[u8; VALUE_STORAGE_LEN]>,
+    values_used: Cell<usize>,
+}
+
+impl AttributeTable {
+    /// Creates a table with the GAP service, exposing `device_name` and
+    /// `appearance`, and the GATT service.
+    pub fn new(device_name: &[u8], appearance: u16) -> AttributeTable {
+        let table = AttributeTable {
+            attributes: MapCell::new([Attribute::EMPTY; MAX_ATTRIBUTES]),
+            count: Cell::new(0),
+            values: MapCell::new([0; VALUE_STORAGE_LEN]),
+            values_used: Cell::new(0),
+        };
+        let device_name = &device_name[..cmp::min(device_name.len(), MAX_DEVICE_NAME_LEN)];
+
+        let _ = table.add_service(Uuid::Uuid16(GAP_SERVICE), OWNER_NONE);
+        let _ = table
+            .add_characteristic(
+                Uuid::Uuid16(DEVICE_NAME),
+                properties::READ,
+                device_name.len() as u16,
+                OWNER_NONE,
+            )
+            .and_then(|handle| table.set_value(handle, device_name));
+        let _ = table
+            .add_characteristic(Uuid::Uuid16(APPEARANCE), properties::READ, 2, OWNER_NONE)
+            .and_then(|handle| table.set_value(handle, &appearance.to_le_bytes()));
+        let _ = table.add_service(Uuid::Uuid16(GATT_SERVICE), OWNER_NONE);
+        table
+    }
+
+    fn push(
+        &self,
+        typ: Uuid,
+        permissions: u8,
+        value: &[u8],
+        capacity: usize,
+        owner: usize,
+    ) -> Result<u16, ErrorCode> {
+        let index = self.count.get();
+        let offset = self.values_used.get();
+        if index >= MAX_ATTRIBUTES || offset + capacity > VALUE_STORAGE_LEN {
+            return Err(ErrorCode::NOMEM);
+        }
+        self.values.map(|values| {
+            values[offset..offset + value.len()].copy_from_slice(value);
+        });
+        self.attributes.map(|attributes| {
+            attributes[index] = Attribute {
+                typ,
+                permissions,
+                offset: offset as u16,
+                len: value.len() as u16,
+                capacity: capacity as u16,
+                owner,
+            };
+        });
+        self.count.set(index + 1);
+        self.values_used.set(offset + capacity);
+        Ok(index as u16 + 1)
+    }
+
+    fn attribute(&self, handle: u16) -> Option<Attribute> {
+        let index = (handle as usize).checked_sub(1)?;
+        if index >= self.count.get() {
+            return None;
+        }
+        self.attributes.map(|attributes| attributes[index])
+    }
+
+    /// Adds a primary service, returning the handle of its declaration.
+    pub fn add_service(&self, uuid: Uuid, owner: usize) -> Result<u16, ErrorCode> {
+        let mut value = [0; 16];
+        uuid.write_to(&mut value);
+        self.push(
+            Uuid::Uuid16(PRIMARY_SERVICE),
+            READABLE,
+            &value[..uuid.len()],
+            uuid.len(),
+            owner,
+        )
+    }
+
+    /// Adds a characteristic to the last added service, returning the handle
+    /// of its value. `properties` is a combination of the `properties`
+    /// constants and `max_len` the maximum length of its value.
+    pub fn add_characteristic(
+        &self,
+        uuid: Uuid,
+        properties: u8,
+        max_len: u16,
+        owner: usize,
+    ) -> Result<u16, ErrorCode> {
+        if self.count.get() == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        let notify = properties & properties::NOTIFY != 0;
+        let attributes = if notify { 3 } else { 2 };
+        let declaration_len = 3 + uuid.len();
+        let cccd_len = if notify { 2 } else { 0 };
+        if self.count.get() + attributes > MAX_ATTRIBUTES
+            || self.values_used.get() + declaration_len + max_len as usize + cccd_len
+                > VALUE_STORAGE_LEN
+        {
+            return Err(ErrorCode::NOMEM);
+        }
+
+        // Characteristic declaration: properties, value handle and UUID
+        let value_handle = self.count.get() as u16 + 2;
+        let mut declaration = [0; 19];
+        declaration[0] = properties;
+        declaration[1..3].copy_from_slice(&value_handle.to_le_bytes());
+        uuid.write_to(&mut declaration[3..]);
+        self.push(
+            Uuid::Uuid16(CHARACTERISTIC),
+            READABLE,
+            &declaration[..declaration_len],
+            declaration_len,
+            owner,
+        )?;
+
+        let mut permissions = 0;
+        if properties & properties::READ != 0 {
+            permissions |= READABLE;
+        }
+        if properties & (properties::WRITE | properties::WRITE_WITHOUT_RESPONSE) != 0 {
+            permissions |= WRITABLE;
+        }
+        self.push(uuid, permissions, &[], max_len as usize, owner)?;
+
+        if notify {
+            self.push(
+                Uuid::Uuid16(CLIENT_CHARACTERISTIC_CONFIGURATION),
+                READABLE | WRITABLE,
+                &[0, 0],
+                2,
+                owner,
+            )?;
+        }
+        Ok(value_handle)
+    }
+
+    /// The handle of the last attribute.
+    pub fn last_handle(&self) -> u16 {
+        self.count.get() as u16
+    }
+
+    /// The type of the attribute with `handle`.
+    pub fn attribute_type(&self, handle: u16) -> Option<Uuid> {
+        self.attribute(handle).map(|attribute| attribute.typ)
+    }
+
+    /// Whoever added the attribute with `handle`.
+    pub fn owner(&self, handle: u16) -> Option<usize> {
+        self.attribute(handle)
+            .map(|attribute| attribute.owner)
+            .filter(|owner| *owner != OWNER_NONE)
+    }
+
+    /// Whether `handle` is a primary service declaration.
+    pub fn is_service(&self, handle: u16) -> bool {
+        self.attribute_type(handle) == Some(Uuid::Uuid16(PRIMARY_SERVICE))
+    }
+
+    /// The last handle of the service declared at `handle`.
+    pub fn service_end(&self, handle: u16) -> u16 {
+        let mut end = handle;
+        while end < self.last_handle() && !self.is_service(end + 1) {
+            end += 1;
+        }
+        end
+    }
+
+    /// Calls `f` with the value of the attribute with `handle`, ignoring its
+    /// permissions.
+    pub fn with_value<F, R>(&self, handle: u16, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let attribute = self.attribute(handle)?;
+        let start = attribute.offset as usize;
+        self.values
+            .map(|values| f(&values[start..start + attribute.len as usize]))
+    }
+
+    /// Sets the value of the attribute with `handle`, ignoring its
+    /// permissions.
+    pub fn set_value(&self, handle: u16, value: &[u8]) -> Result<(), ErrorCode> {
+        let attribute = self.attribute(handle).ok_or(ErrorCode::INVAL)?;
+        if value.len() > attribute.capacity as usize {
+            return Err(ErrorCode::SIZE);
+        }
+        let start = attribute.offset as usize;
+        self.values.map(|values| {
+            values[start..start + value.len()].copy_from_slice(value);
+        });
+        self.attributes.map(|attributes| {
+            attributes[handle as usize - 1].len = value.len() as u16;
+        });
+        Ok(())
+    }
+
+    /// Reads the value of the attribute with `handle` for a client, starting
+    /// at `offset`, into `buf`. Returns the number of bytes read.
+    pub fn read(&self, handle: u16, offset: usize, buf: &mut [u8]) -> Result<usize, AttError> {
+        let attribute = self.attribute(handle).ok_or(AttError::InvalidHandle)?;
+        if attribute.permissions & READABLE == 0 {
+            return Err(AttError::ReadNotPermitted);
+        }
+        if offset > attribute.len as usize {
+            return Err(AttError::InvalidOffset);
+        }
+        let start = attribute.offset as usize + offset;
+        let len = cmp::min(attribute.len as usize - offset, buf.len());
+        self.values
+            .map(|values| buf[..len].copy_from_slice(&values[start..start + len]));
+        Ok(len)
+    }
+
+    /// Writes the value of the attribute with `handle` for a client.
+    pub fn write(&self, handle: u16, value: &[u8]) -> Result<(), AttError> {
+        let attribute = self.attribute(handle).ok_or(AttError::InvalidHandle)?;
+        if attribute.permissions & WRITABLE == 0 {
+            return Err(AttError::WriteNotPermitted);
+        }
+        self.set_value(handle, value)
+            .map_err(|_| AttError::InvalidAttributeValueLength)
+    }
+
+    /// Whether the client enabled notifications of the characteristic value
+    /// with `handle`.
+    pub fn notifications_enabled(&self, handle: u16) -> bool {
+        if self.attribute_type(handle + 1)
+            != Some(Uuid::Uuid16(CLIENT_CHARACTERISTIC_CONFIGURATION))
+        {
+            return false;
+        }
+        self.with_value(handle + 1, |value| {
+            value.first().map_or(false, |v| v & 0x01 != 0)
+        })
+        .unwrap_or(false)
+    }
+
+    /// Disables all notifications, as the configuration of a client is lost
+    /// when it disconnects.
+    pub fn reset_client_configurations(&self) {
+        for handle in 1..=self.last_handle() {
+            if self.attribute_type(handle)
+                == Some(Uuid::Uuid16(CLIENT_CHARACTERISTIC_CONFIGURATION))
+            {
+                let _ = self.set_value(handle, &[0, 0]);
+            }
+        }
+    }
+}
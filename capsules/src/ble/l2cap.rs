@@ -0,0 +1,123 @@
+//! Logical Link Control and Adaptation Protocol for a BLE peripheral.
+//!
+//! Only the fixed LE channels are used. PDUs on the attribute protocol
+//! channel are passed to a client, typically the ATT server. Commands on the
+//! LE signaling channel are rejected, and pairing requests of the security
+//! manager are answered with "pairing not supported".
+//!
+//! PDUs are not fragmented: each L2CAP PDU must fit in a single link layer
+//! PDU. This holds for the default ATT_MTU of 23 bytes.
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part A]
+
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+use super::link_layer;
+
+/// Channel identifiers.
+pub const CID_ATT: u16 = 0x0004;
+const CID_SIGNALING: u16 = 0x0005;
+const CID_SMP: u16 = 0x0006;
+
+const HEADER_LEN: usize = 4;
+/// Largest PDU, the payload of a link layer data PDU.
+pub const MAX_PDU_LEN: usize = link_layer::MAX_PAYLOAD_LEN;
+/// Largest service data unit.
+pub const MAX_SDU_LEN: usize = MAX_PDU_LEN - HEADER_LEN;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part A], section 4.1
+const COMMAND_REJECT: u8 = 0x01;
+const COMMAND_NOT_UNDERSTOOD: u16 = 0x0000;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part H], section 3.5
+const PAIRING_REQUEST: u8 = 0x01;
+const PAIRING_FAILED: u8 = 0x05;
+const PAIRING_NOT_SUPPORTED: u8 = 0x05;
+
+pub trait Client {
+    fn connected(&self);
+    fn disconnected(&self, reason: u8);
+
+    /// A service data unit was received on the channel.
+    fn received(&self, sdu: &[u8]);
+}
+
+pub struct L2cap<'a> {
+    link: &'a dyn link_layer::Transmit,
+    att_client: OptionalCell<&'a dyn Client>,
+}
+
+impl<'a> L2cap<'a> {
+    pub fn new(link: &'a dyn link_layer::Transmit) -> L2cap<'a> {
+        L2cap {
+            link,
+            att_client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_att_client(&self, client: &'a dyn Client) {
+        self.att_client.set(client);
+    }
+
+    /// Queues `sdu` for transmission on channel `cid`.
+    pub fn send(&self, cid: u16, sdu: &[u8]) -> Result<(), ErrorCode> {
+        if sdu.len() > MAX_SDU_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut pdu = [0; MAX_PDU_LEN];
+        pdu[0..2].copy_from_slice(&(sdu.len() as u16).to_le_bytes());
+        pdu[2..4].copy_from_slice(&cid.to_le_bytes());
+        pdu[HEADER_LEN..HEADER_LEN + sdu.len()].copy_from_slice(sdu);
+        self.link.send(&pdu[..HEADER_LEN + sdu.len()])
+    }
+
+    fn signaling(&self, command: &[u8]) {
+        // Code, identifier and length. Never answer a reject with a reject.
+        if command.len() < 4 || command[0] == COMMAND_REJECT {
+            return;
+        }
+        let reason = COMMAND_NOT_UNDERSTOOD.to_le_bytes();
+        let _ = self.send(
+            CID_SIGNALING,
+            &[COMMAND_REJECT, command[1], 2, 0, reason[0], reason[1]],
+        );
+    }
+
+    fn security_manager(&self, command: &[u8]) {
+        if command.first() == Some(&PAIRING_REQUEST) {
+            let _ = self.send(CID_SMP, &[PAIRING_FAILED, PAIRING_NOT_SUPPORTED]);
+        }
+    }
+}
+
+impl<'a> link_layer::Client for L2cap<'a> {
+    fn connected(&self) {
+        self.att_client.map(|client| client.connected());
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.att_client.map(|client| client.disconnected(reason));
+    }
+
+    fn received(&self, pdu: &[u8]) {
+        if pdu.len() < HEADER_LEN {
+            return;
+        }
+        let len = u16::from_le_bytes([pdu[0], pdu[1]]) as usize;
+        let cid = u16::from_le_bytes([pdu[2], pdu[3]]);
+        if HEADER_LEN + len > pdu.len() {
+            // Fragmented PDUs are not supported
+            return;
+        }
+        let sdu = &pdu[HEADER_LEN..HEADER_LEN + len];
+        match cid {
+            CID_ATT => {
+                self.att_client.map(|client| client.received(sdu));
+            }
+            CID_SIGNALING => self.signaling(sdu),
+            CID_SMP => self.security_manager(sdu),
+            _ => {}
+        }
+    }
+}
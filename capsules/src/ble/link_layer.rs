@@ -0,0 +1,974 @@
+//! Bluetooth Low Energy link layer in the peripheral (slave) role.
+//!
+//! The link layer advertises with connectable ADV_IND PDUs on the three
+//! advertising channels. When a central answers with a CONNECT_IND for our
+//! address, it enters the connection state: it wakes up for every connection
+//! event, hops to the next data channel with channel selection algorithm #1,
+//! and answers the packet of the central with the next queued PDU or an empty
+//! one. Sequence numbers (SN and NESN) acknowledge PDUs in both directions, a
+//! queued PDU is retransmitted until the central acknowledges it.
+//!
+//! LL control procedures initiated by the central are answered here:
+//! connection parameter and channel map updates, version and feature
+//! exchange, ping and termination. Data PDUs carry L2CAP PDUs, which are
+//! passed to the client.
+//!
+//! Limitations:
+//!
+//! - A single connection, and no advertising while connected.
+//! - One packet exchange per connection event, the MD bit is not used.
+//! - Slave latency is not used, every connection event is attended.
+//! - No encryption and no data length extension, data PDUs carry at most 27
+//!   bytes. L2CAP PDUs must fit in a single data PDU.
+//! - SCAN_REQ PDUs are not answered.
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B]
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::ble_connection;
+use kernel::hil::ble_connection::BleConnectionDriver;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::ErrorCode;
+
+pub trait Client {
+    /// A central connected.
+    fn connected(&self);
+
+    /// The connection was closed, `reason` is an HCI error code.
+    fn disconnected(&self, reason: u8);
+
+    /// An L2CAP PDU was received.
+    fn received(&self, pdu: &[u8]);
+}
+
+pub trait Transmit {
+    /// Queues an L2CAP PDU for transmission to the central.
+    fn send(&self, pdu: &[u8]) -> Result<(), ErrorCode>;
+}
+
+/// Maximum payload of a data channel PDU.
+pub const MAX_PAYLOAD_LEN: usize = 27;
+/// Maximum advertising data length.
+pub const MAX_ADV_DATA_LEN: usize = 31;
+pub const ADDRESS_LEN: usize = 6;
+
+const PDU_LEN: usize = 2 + MAX_PAYLOAD_LEN;
+const ADV_PDU_LEN: usize = 2 + ADDRESS_LEN + MAX_ADV_DATA_LEN;
+const TX_QUEUE_LEN: usize = 4;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3
+const ADV_IND: u8 = 0b0000;
+const CONNECT_IND: u8 = 0b0101;
+const PDU_TYPE_MASK: u8 = 0x0f;
+const TXADD: u8 = 1 << 6;
+const CONNECT_IND_LEN: usize = 2 + 34;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.4
+const LLID_MASK: u8 = 0b11;
+const LLID_CONTINUATION: u8 = 0b01;
+const LLID_START: u8 = 0b10;
+const LLID_CONTROL: u8 = 0b11;
+const NESN: u8 = 1 << 2;
+const SN: u8 = 1 << 3;
+
+/// LL control PDU opcodes.
+///
+/// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.4.2
+mod control {
+    pub const CONNECTION_UPDATE_IND: u8 = 0x00;
+    pub const CHANNEL_MAP_IND: u8 = 0x01;
+    pub const TERMINATE_IND: u8 = 0x02;
+    pub const UNKNOWN_RSP: u8 = 0x07;
+    pub const FEATURE_REQ: u8 = 0x08;
+    pub const FEATURE_RSP: u8 = 0x09;
+    pub const VERSION_IND: u8 = 0x0C;
+    pub const PING_REQ: u8 = 0x12;
+    pub const PING_RSP: u8 = 0x13;
+}
+
+/// Disconnection reasons, HCI error codes.
+pub const CONNECTION_TIMEOUT: u8 = 0x08;
+pub const REMOTE_USER_TERMINATED: u8 = 0x13;
+pub const LOCAL_HOST_TERMINATED: u8 = 0x16;
+pub const INVALID_LL_PARAMETERS: u8 = 0x1E;
+
+/// Reported version: Bluetooth 4.0, by an unassigned company.
+const VERSION: u8 = 0x06;
+const COMPANY_ID: u16 = 0xFFFF;
+
+/// Connection intervals, window sizes and offsets are in 1.25 ms units.
+const UNIT_US: u32 = 1250;
+/// How long to listen for a request after each advertisement.
+const ADV_LISTEN_US: u32 = 1000;
+/// Listen this much earlier and longer than the expected anchor point, on top
+/// of the widening for the sleep clock accuracy, to cover the alarm
+/// resolution and interrupt latency.
+const WINDOW_MARGIN_US: u32 = 500;
+/// Give up on the response of a connection event after this long.
+const RESPONSE_TIMEOUT_US: u32 = 2000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum State {
+    Standby,
+    /// Waiting for the next advertising event.
+    AdvertisingIdle,
+    /// Listening for a request after advertising on the channel.
+    Advertising(RadioChannel),
+    /// Waiting for the next connection event.
+    ConnectedIdle,
+    /// In a connection event.
+    ConnectionEvent,
+}
+
+/// What the last transmitted data PDU was, until the central acknowledges it.
+#[derive(Copy, Clone, PartialEq)]
+enum Unacknowledged {
+    Nothing,
+    Empty,
+    /// The PDU at the head of the transmit queue.
+    Queued,
+}
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.2
+fn valid_parameters(interval: u16, latency: u16, timeout: u16) -> bool {
+    (6..=3200).contains(&interval)
+        && latency <= 499
+        && (10..=3200).contains(&timeout)
+        // The timeout, in units of 10 ms, must be longer than two intervals
+        // of the latency plus one, in units of 1.25 ms.
+        && timeout as u32 * 8 > (1 + latency as u32) * interval as u32 * 2
+}
+
+#[derive(Copy, Clone)]
+struct ConnectionUpdate {
+    window_size: u8,
+    window_offset: u16,
+    interval: u16,
+    timeout: u16,
+    instant: u16,
+}
+
+#[derive(Copy, Clone)]
+struct Connection {
+    interval_us: u32,
+    supervision_timeout_us: u32,
+    channel_map: [u8; 5],
+    hop_increment: u8,
+    last_unmapped_channel: u8,
+    event_counter: u16,
+    /// Offset of the expected anchor point of the next event from the last
+    /// anchor point.
+    next_anchor_us: u32,
+    /// Size of the transmit window after the expected anchor point.
+    window_us: u32,
+    sn: bool,
+    nesn: bool,
+    unacknowledged: Unacknowledged,
+    update: Option<ConnectionUpdate>,
+    channel_map_update: Option<([u8; 5], u16)>,
+    /// Disconnect with this reason after the current event.
+    terminate: Option<u8>,
+    /// Disconnect once all queued PDUs are acknowledged.
+    terminating: bool,
+    version_sent: bool,
+}
+
+impl Connection {
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.8.2
+    fn next_channel(&mut self) -> RadioChannel {
+        let unmapped = (self.last_unmapped_channel + self.hop_increment) % 37;
+        self.last_unmapped_channel = unmapped;
+        let used = |channel: u8| self.channel_map[channel as usize / 8] & (1 << (channel % 8)) != 0;
+        let channel = if used(unmapped) {
+            unmapped
+        } else {
+            let used_count = (0..37).filter(|c| used(*c)).count() as u8;
+            let remapping_index = unmapped % cmp::max(used_count, 1);
+            (0..37)
+                .filter(|c| used(*c))
+                .nth(remapping_index as usize)
+                .unwrap_or(0)
+        };
+        RadioChannel::data_channel(channel).unwrap_or(RadioChannel::DataChannel0)
+    }
+
+    /// Widening of the receive window, for a sleep clock accuracy of 500 ppm
+    /// on both sides.
+    fn widening_us(&self) -> u32 {
+        self.next_anchor_us / 1000 + WINDOW_MARGIN_US
+    }
+}
+
+pub struct LinkLayer<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> {
+    radio: &'a R,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn Client>,
+    state: Cell<State>,
+    address: [u8; ADDRESS_LEN],
+
+    adv_pdu: MapCell<[u8; ADV_PDU_LEN]>,
+    adv_interval_ms: Cell<u32>,
+    /// State of the pseudo random advertising delay.
+    random_nonce: Cell<u32>,
+
+    connection: MapCell<Connection>,
+    /// Anchor point of the last connection event.
+    anchor: Cell<A::Ticks>,
+    /// When the last packet of the central was received.
+    last_received: Cell<A::Ticks>,
+
+    tx_queue: MapCell<[[u8; PDU_LEN]; TX_QUEUE_LEN]>,
+    tx_head: Cell<usize>,
+    tx_count: Cell<usize>,
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> LinkLayer<'a, R, A> {
+    /// `address` is the static random device address, in the order it is
+    /// transmitted (least significant byte first).
+    pub fn new(radio: &'a R, alarm: &'a A, address: [u8; ADDRESS_LEN]) -> LinkLayer<'a, R, A> {
+        let mut adv_pdu = [0; ADV_PDU_LEN];
+        adv_pdu[0] = ADV_IND | TXADD;
+        adv_pdu[1] = ADDRESS_LEN as u8;
+        adv_pdu[2..2 + ADDRESS_LEN].copy_from_slice(&address);
+        LinkLayer {
+            radio,
+            alarm,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Standby),
+            address,
+            adv_pdu: MapCell::new(adv_pdu),
+            adv_interval_ms: Cell::new(100),
+            random_nonce: Cell::new(0xdeadbeef),
+            connection: MapCell::empty(),
+            anchor: Cell::new(A::Ticks::from(0)),
+            last_received: Cell::new(A::Ticks::from(0)),
+            tx_queue: MapCell::new([[0; PDU_LEN]; TX_QUEUE_LEN]),
+            tx_head: Cell::new(0),
+            tx_count: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    /// Sets the advertising data, at most `MAX_ADV_DATA_LEN` bytes of AD
+    /// structures.
+    pub fn set_advertising_data(&self, data: &[u8]) -> Result<(), ErrorCode> {
+        if data.len() > MAX_ADV_DATA_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        self.adv_pdu.map(|pdu| {
+            pdu[1] = (ADDRESS_LEN + data.len()) as u8;
+            pdu[2 + ADDRESS_LEN..2 + ADDRESS_LEN + data.len()].copy_from_slice(data);
+        });
+        Ok(())
+    }
+
+    /// Starts advertising every `interval_ms` milliseconds, until a central
+    /// connects or `stop_advertising()` is called.
+    pub fn start_advertising(&self, interval_ms: u32) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Standby {
+            return Err(ErrorCode::BUSY);
+        }
+        // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.4.2.2
+        self.adv_interval_ms.set(cmp::max(20, interval_ms));
+        self.random_nonce.set(self.alarm.now().into_u32() | 1);
+        self.state.set(State::AdvertisingIdle);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(1));
+        Ok(())
+    }
+
+    pub fn stop_advertising(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::AdvertisingIdle | State::Advertising(_) => {
+                self.radio.stop();
+                let _ = self.alarm.disarm();
+                self.state.set(State::Standby);
+                Ok(())
+            }
+            _ => Err(ErrorCode::OFF),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Terminates the connection once all queued PDUs are sent.
+    pub fn disconnect(&self) -> Result<(), ErrorCode> {
+        if !self.is_connected() {
+            return Err(ErrorCode::OFF);
+        }
+        self.queue(
+            LLID_CONTROL,
+            &[control::TERMINATE_IND, REMOTE_USER_TERMINATED],
+        )?;
+        self.connection
+            .map(|connection| connection.terminating = true);
+        Ok(())
+    }
+
+    fn queue(&self, llid: u8, payload: &[u8]) -> Result<(), ErrorCode> {
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        let count = self.tx_count.get();
+        if count == TX_QUEUE_LEN {
+            return Err(ErrorCode::BUSY);
+        }
+        let index = (self.tx_head.get() + count) % TX_QUEUE_LEN;
+        self.tx_queue.map(|queue| {
+            queue[index][0] = llid;
+            queue[index][1] = payload.len() as u8;
+            queue[index][2..2 + payload.len()].copy_from_slice(payload);
+        });
+        self.tx_count.set(count + 1);
+        Ok(())
+    }
+
+    fn pop(&self) {
+        if self.tx_count.get() > 0 {
+            self.tx_head.set((self.tx_head.get() + 1) % TX_QUEUE_LEN);
+            self.tx_count.set(self.tx_count.get() - 1);
+        }
+    }
+
+    // Returns a new pseudo-random number, for the advertising delay.
+    fn random_nonce(&self) -> u32 {
+        let mut next_nonce = ::core::num::Wrapping(self.random_nonce.get());
+        next_nonce ^= next_nonce << 13;
+        next_nonce ^= next_nonce >> 17;
+        next_nonce ^= next_nonce << 5;
+        self.random_nonce.set(next_nonce.0);
+        next_nonce.0
+    }
+
+    fn advertise(&self, channel: RadioChannel) {
+        self.state.set(State::Advertising(channel));
+        self.adv_pdu.map(|pdu| {
+            let len = 2 + pdu[1] as usize;
+            self.radio.advertise_and_listen(&pdu[..len], channel);
+        });
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(ADV_LISTEN_US));
+    }
+
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3.3.1
+    fn connect(&self, pdu: &[u8]) {
+        let now = self.alarm.now();
+        let u16_at = |i: usize| u16::from_le_bytes([pdu[i], pdu[i + 1]]);
+        let access_address = u32::from_le_bytes([pdu[14], pdu[15], pdu[16], pdu[17]]);
+        let crc_init = pdu[18] as u32 | (pdu[19] as u32) << 8 | (pdu[20] as u32) << 16;
+        let window_size = pdu[21];
+        let window_offset = u16_at(22);
+        let interval = u16_at(24);
+        let latency = u16_at(26);
+        let timeout = u16_at(28);
+        let mut channel_map = [0; 5];
+        channel_map.copy_from_slice(&pdu[30..35]);
+        // Only channels 0 to 36 are data channels
+        channel_map[4] &= 0x1f;
+        let hop_increment = pdu[35] & 0x1f;
+
+        let used_channels: u32 = channel_map.iter().map(|b| b.count_ones()).sum();
+        if !valid_parameters(interval, latency, timeout)
+            || !(5..=16).contains(&hop_increment)
+            || used_channels < 2
+        {
+            return;
+        }
+
+        self.radio.stop();
+        self.radio.set_access_address(access_address, crc_init);
+        self.tx_head.set(0);
+        self.tx_count.set(0);
+        self.connection.put(Connection {
+            interval_us: interval as u32 * UNIT_US,
+            supervision_timeout_us: timeout as u32 * 10_000,
+            channel_map,
+            hop_increment,
+            last_unmapped_channel: 0,
+            event_counter: 0,
+            // The transmit window starts 1.25 ms plus the window offset after
+            // the end of the CONNECT_IND.
+            next_anchor_us: UNIT_US + window_offset as u32 * UNIT_US,
+            window_us: window_size as u32 * UNIT_US,
+            sn: false,
+            nesn: false,
+            unacknowledged: Unacknowledged::Nothing,
+            update: None,
+            channel_map_update: None,
+            terminate: None,
+            terminating: false,
+            version_sent: false,
+        });
+        self.anchor.set(now);
+        self.last_received.set(now);
+        self.schedule_connection_event();
+        self.client.map(|client| client.connected());
+    }
+
+    fn disconnect_now(&self, reason: u8) {
+        self.radio.stop();
+        let _ = self.alarm.disarm();
+        self.connection.take();
+        self.tx_count.set(0);
+        self.state.set(State::Standby);
+        self.client.map(|client| client.disconnected(reason));
+    }
+
+    fn schedule_connection_event(&self) {
+        self.state.set(State::ConnectedIdle);
+        let start = self.connection.map_or(0, |connection| {
+            connection
+                .next_anchor_us
+                .saturating_sub(connection.widening_us())
+        });
+        self.alarm
+            .set_alarm(self.anchor.get(), self.alarm.ticks_from_us(start));
+    }
+
+    fn start_connection_event(&self) {
+        let (channel, window_end) = match self.connection.map(|connection| {
+            if let Some((channel_map, instant)) = connection.channel_map_update {
+                if instant == connection.event_counter {
+                    connection.channel_map = channel_map;
+                    connection.channel_map_update = None;
+                }
+            }
+            (
+                connection.next_channel(),
+                connection.next_anchor_us + connection.window_us + connection.widening_us(),
+            )
+        }) {
+            Some(event) => event,
+            None => return,
+        };
+        self.state.set(State::ConnectionEvent);
+        self.radio.listen_and_respond(channel);
+        self.alarm
+            .set_alarm(self.anchor.get(), self.alarm.ticks_from_us(window_end));
+    }
+
+    fn end_connection_event(&self) {
+        let now = self.alarm.now();
+        let since_received = now.wrapping_sub(self.last_received.get());
+        let disconnect = self.connection.map(|connection| {
+            connection.event_counter = connection.event_counter.wrapping_add(1);
+            connection.next_anchor_us += connection.interval_us;
+
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 5.1.1
+            if let Some(update) = connection.update {
+                if update.instant == connection.event_counter {
+                    connection.next_anchor_us += update.window_offset as u32 * UNIT_US;
+                    connection.window_us = update.window_size as u32 * UNIT_US;
+                    connection.interval_us = update.interval as u32 * UNIT_US;
+                    connection.supervision_timeout_us = update.timeout as u32 * 10_000;
+                    connection.update = None;
+                }
+            }
+
+            if since_received > self.alarm.ticks_from_us(connection.supervision_timeout_us) {
+                Some(CONNECTION_TIMEOUT)
+            } else if connection.terminate.is_some() {
+                connection.terminate
+            } else if connection.terminating
+                && self.tx_count.get() == 0
+                && connection.unacknowledged != Unacknowledged::Queued
+            {
+                Some(LOCAL_HOST_TERMINATED)
+            } else {
+                None
+            }
+        });
+        match disconnect {
+            Some(Some(reason)) => self.disconnect_now(reason),
+            Some(None) => self.schedule_connection_event(),
+            None => self.state.set(State::Standby),
+        }
+    }
+
+    /// Handles a newly received data PDU, after its response was written.
+    fn receive(&self, pdu: &[u8]) {
+        let len = cmp::min(pdu[1] as usize, pdu.len() - 2);
+        let payload = &pdu[2..2 + len];
+        match pdu[0] & LLID_MASK {
+            LLID_CONTROL => self.control(payload),
+            LLID_START => {
+                self.client.map(|client| client.received(payload));
+            }
+            // Empty PDUs, and continuation fragments which are not supported
+            _ => (),
+        }
+    }
+
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 5.1
+    fn control(&self, pdu: &[u8]) {
+        let opcode = match pdu.first() {
+            Some(opcode) => *opcode,
+            None => return,
+        };
+        let u16_at = |i: usize| u16::from_le_bytes([pdu[i], pdu[i + 1]]);
+        match opcode {
+            control::CONNECTION_UPDATE_IND if pdu.len() >= 12 => {
+                let update = ConnectionUpdate {
+                    window_size: pdu[1],
+                    window_offset: u16_at(2),
+                    interval: u16_at(4),
+                    timeout: u16_at(8),
+                    instant: u16_at(10),
+                };
+                if valid_parameters(update.interval, u16_at(6), update.timeout) {
+                    self.connection
+                        .map(|connection| connection.update = Some(update));
+                } else {
+                    // Tell the peer why, or drop the connection at the end of
+                    // this event if the queue is full.
+                    let queued = self.queue(
+                        LLID_CONTROL,
+                        &[control::TERMINATE_IND, INVALID_LL_PARAMETERS],
+                    );
+                    self.connection.map(|connection| match queued {
+                        Ok(()) => connection.terminating = true,
+                        Err(_) => connection.terminate = Some(INVALID_LL_PARAMETERS),
+                    });
+                }
+            }
+            control::CHANNEL_MAP_IND if pdu.len() >= 8 => {
+                let mut channel_map = [0; 5];
+                channel_map.copy_from_slice(&pdu[1..6]);
+                channel_map[4] &= 0x1f;
+                let instant = u16_at(6);
+                self.connection
+                    .map(|connection| connection.channel_map_update = Some((channel_map, instant)));
+            }
+            control::TERMINATE_IND if pdu.len() >= 2 => {
+                self.connection
+                    .map(|connection| connection.terminate = Some(pdu[1]));
+            }
+            control::FEATURE_REQ => {
+                // No optional features are supported
+                let _ = self.queue(
+                    LLID_CONTROL,
+                    &[control::FEATURE_RSP, 0, 0, 0, 0, 0, 0, 0, 0],
+                );
+            }
+            control::VERSION_IND => {
+                // The version is only sent once per connection
+                if self.connection.map_or(false, |connection| {
+                    !core::mem::replace(&mut connection.version_sent, true)
+                }) {
+                    let company = COMPANY_ID.to_le_bytes();
+                    let _ = self.queue(
+                        LLID_CONTROL,
+                        &[control::VERSION_IND, VERSION, company[0], company[1], 0, 0],
+                    );
+                }
+            }
+            control::PING_REQ => {
+                let _ = self.queue(LLID_CONTROL, &[control::PING_RSP]);
+            }
+            control::UNKNOWN_RSP | control::FEATURE_RSP | control::PING_RSP => {}
+            _ => {
+                let _ = self.queue(LLID_CONTROL, &[control::UNKNOWN_RSP, opcode]);
+            }
+        }
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> Transmit for LinkLayer<'a, R, A> {
+    fn send(&self, pdu: &[u8]) -> Result<(), ErrorCode> {
+        if !self.is_connected() {
+            return Err(ErrorCode::OFF);
+        }
+        self.queue(LLID_START, pdu)
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> AlarmClient for LinkLayer<'a, R, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Standby => {}
+            State::AdvertisingIdle => self.advertise(RadioChannel::AdvertisingChannel37),
+            State::Advertising(channel) => {
+                self.radio.stop();
+                match channel {
+                    RadioChannel::AdvertisingChannel37 => {
+                        self.advertise(RadioChannel::AdvertisingChannel38)
+                    }
+                    RadioChannel::AdvertisingChannel38 => {
+                        self.advertise(RadioChannel::AdvertisingChannel39)
+                    }
+                    _ => {
+                        // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.4.2.2
+                        // advDelay is a pseudo-random value between 0 and 10 ms
+                        let delay_ms = self.random_nonce() % 10;
+                        self.state.set(State::AdvertisingIdle);
+                        self.alarm.set_alarm(
+                            self.alarm.now(),
+                            self.alarm
+                                .ticks_from_ms(self.adv_interval_ms.get() + delay_ms),
+                        );
+                    }
+                }
+            }
+            State::ConnectedIdle => self.start_connection_event(),
+            State::ConnectionEvent => {
+                // Nothing was received in the receive window, or the response
+                // was not sent
+                self.radio.stop();
+                self.end_connection_event();
+            }
+        }
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> ble_connection::ConnectionClient
+    for LinkLayer<'a, R, A>
+{
+    fn advertising_request(&self, pdu: &[u8], result: Result<(), ErrorCode>) {
+        if let State::Advertising(_) = self.state.get() {
+            if result.is_ok()
+                && pdu.len() >= CONNECT_IND_LEN
+                && pdu[0] & PDU_TYPE_MASK == CONNECT_IND
+                && pdu[8..8 + ADDRESS_LEN] == self.address
+            {
+                self.connect(pdu);
+            }
+        }
+    }
+
+    fn data_received(
+        &self,
+        pdu: &[u8],
+        result: Result<(), ErrorCode>,
+        response: &mut [u8],
+    ) -> usize {
+        if self.state.get() != State::ConnectionEvent || pdu.len() < 2 {
+            return 0;
+        }
+        let now = self.alarm.now();
+        let valid = result.is_ok();
+
+        let written = self.connection.map(|connection| {
+            let mut new_data = false;
+            if valid {
+                // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.9
+                if (pdu[0] & SN != 0) == connection.nesn {
+                    connection.nesn = !connection.nesn;
+                    new_data = true;
+                }
+                if (pdu[0] & NESN != 0) != connection.sn {
+                    connection.sn = !connection.sn;
+                    if connection.unacknowledged == Unacknowledged::Queued {
+                        self.pop();
+                    }
+                    connection.unacknowledged = Unacknowledged::Nothing;
+                }
+            }
+
+            // Retransmit an unacknowledged PDU, otherwise send the next one
+            if connection.unacknowledged == Unacknowledged::Nothing {
+                connection.unacknowledged = if self.tx_count.get() > 0 {
+                    Unacknowledged::Queued
+                } else {
+                    Unacknowledged::Empty
+                };
+            }
+            let len = if connection.unacknowledged == Unacknowledged::Queued {
+                let head = self.tx_head.get();
+                self.tx_queue.map_or(0, |queue| {
+                    let len = 2 + queue[head][1] as usize;
+                    response[..len].copy_from_slice(&queue[head][..len]);
+                    len
+                })
+            } else {
+                response[0] = LLID_CONTINUATION;
+                response[1] = 0;
+                2
+            };
+            if connection.sn {
+                response[0] |= SN;
+            }
+            if connection.nesn {
+                response[0] |= NESN;
+            }
+
+            // The central transmits at the anchor point, the packet took
+            // (preamble, access address, header, payload and CRC) 8 µs per
+            // byte.
+            let duration_us = (10 + pdu.len() as u32) * 8;
+            self.anchor
+                .set(now.wrapping_sub(self.alarm.ticks_from_us(duration_us)));
+            connection.next_anchor_us = 0;
+            connection.window_us = 0;
+            (len, new_data)
+        });
+
+        self.last_received.set(now);
+        // Guard against the response never being reported
+        self.alarm
+            .set_alarm(now, self.alarm.ticks_from_us(RESPONSE_TIMEOUT_US));
+
+        match written {
+            Some((len, new_data)) => {
+                if new_data {
+                    self.receive(pdu);
+                }
+                len
+            }
+            None => 0,
+        }
+    }
+
+    fn response_sent(&self) {
+        if self.state.get() == State::ConnectionEvent {
+            self.end_connection_event();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::hil::ble_connection::ConnectionClient;
+    use kernel::hil::time::{Freq1MHz, Ticks32, Time};
+
+    struct FakeRadio {
+        access_address: Cell<Option<(u32, u32)>>,
+        listening: Cell<Option<RadioChannel>>,
+    }
+
+    impl<'a> BleConnectionDriver<'a> for FakeRadio {
+        fn set_connection_client(&self, _client: &'a dyn ble_connection::ConnectionClient) {}
+
+        fn advertise_and_listen(&self, _pdu: &[u8], _channel: RadioChannel) {}
+
+        fn set_access_address(&self, access_address: u32, crc_init: u32) {
+            self.access_address.set(Some((access_address, crc_init)));
+        }
+
+        fn listen_and_respond(&self, channel: RadioChannel) {
+            self.listening.set(Some(channel));
+        }
+
+        fn stop(&self) {}
+    }
+
+    struct FakeAlarm {
+        now: Cell<Ticks32>,
+        alarm: Cell<Ticks32>,
+    }
+
+    impl Time for FakeAlarm {
+        type Ticks = Ticks32;
+        type Frequency = Freq1MHz;
+
+        fn now(&self) -> Ticks32 {
+            self.now.get()
+        }
+    }
+
+    impl<'a> Alarm<'a> for FakeAlarm {
+        fn set_alarm_client(&self, _client: &'a dyn AlarmClient) {}
+
+        fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+            self.alarm.set(reference.wrapping_add(dt));
+        }
+
+        fn get_alarm(&self) -> Ticks32 {
+            self.alarm.get()
+        }
+
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn is_armed(&self) -> bool {
+            true
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            1u32.into()
+        }
+    }
+
+    const ADDRESS: [u8; ADDRESS_LEN] = [0x11, 0x22, 0x33, 0x44, 0x55, 0xc6];
+    const ACCESS_ADDRESS: u32 = 0x71764129;
+    const CRC_INIT: u32 = 0x5a3c0f;
+
+    /// A CONNECT_IND for `ADDRESS` using all data channels, with a hop
+    /// increment of 7.
+    fn connect_ind() -> [u8; CONNECT_IND_LEN] {
+        let mut pdu = [0; CONNECT_IND_LEN];
+        pdu[0] = CONNECT_IND | TXADD;
+        pdu[1] = 34;
+        pdu[2..8].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        pdu[8..14].copy_from_slice(&ADDRESS);
+        pdu[14..18].copy_from_slice(&ACCESS_ADDRESS.to_le_bytes());
+        pdu[18..21].copy_from_slice(&CRC_INIT.to_le_bytes()[..3]);
+        // Window size 2, window offset 1, interval 24, latency 0, timeout 72
+        pdu[21] = 2;
+        pdu[22..24].copy_from_slice(&1u16.to_le_bytes());
+        pdu[24..26].copy_from_slice(&24u16.to_le_bytes());
+        pdu[28..30].copy_from_slice(&72u16.to_le_bytes());
+        pdu[30..35].copy_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x1f]);
+        pdu[35] = 7;
+        pdu
+    }
+
+    fn fake_radio() -> FakeRadio {
+        FakeRadio {
+            access_address: Cell::new(None),
+            listening: Cell::new(None),
+        }
+    }
+
+    fn fake_alarm() -> FakeAlarm {
+        FakeAlarm {
+            now: Cell::new(0u32.into()),
+            alarm: Cell::new(0u32.into()),
+        }
+    }
+
+    /// Starts advertising and delivers `pdu` on the first channel.
+    fn deliver<'a>(
+        link: &LinkLayer<'a, FakeRadio, FakeAlarm>,
+        alarm: &FakeAlarm,
+        pdu: &[u8],
+        result: Result<(), ErrorCode>,
+    ) {
+        link.start_advertising(100).unwrap();
+        alarm.now.set(alarm.alarm.get());
+        link.alarm();
+        link.advertising_request(pdu, result);
+    }
+
+    #[test]
+    fn test_connect() {
+        let radio = fake_radio();
+        let alarm = fake_alarm();
+        let link = LinkLayer::new(&radio, &alarm, ADDRESS);
+        deliver(&link, &alarm, &connect_ind(), Ok(()));
+        assert!(link.is_connected());
+        assert_eq!(radio.access_address.get(), Some((ACCESS_ADDRESS, CRC_INIT)));
+
+        // The first connection event is on the channel of the hop increment.
+        alarm.now.set(alarm.alarm.get());
+        link.alarm();
+        assert_eq!(link.state.get(), State::ConnectionEvent);
+        assert_eq!(
+            radio
+                .listening
+                .get()
+                .map(|channel| channel.get_channel_index()),
+            Some(7)
+        );
+    }
+
+    /// Delivers a CONNECT_IND changed by `modify`, and returns the data
+    /// channel of the first connection event if the link layer connected.
+    fn first_channel(len: usize, modify: fn(&mut [u8; CONNECT_IND_LEN])) -> Option<u32> {
+        let radio = fake_radio();
+        let alarm = fake_alarm();
+        let link = LinkLayer::new(&radio, &alarm, ADDRESS);
+        let mut pdu = [0; CONNECT_IND_LEN + 1];
+        let mut connect_ind = connect_ind();
+        modify(&mut connect_ind);
+        pdu[..CONNECT_IND_LEN].copy_from_slice(&connect_ind);
+        deliver(&link, &alarm, &pdu[..len], Ok(()));
+        if !link.is_connected() {
+            return None;
+        }
+        alarm.now.set(alarm.alarm.get());
+        link.alarm();
+        radio
+            .listening
+            .get()
+            .map(|channel| channel.get_channel_index())
+    }
+
+    #[test]
+    fn test_connect_ind_validation() {
+        assert_eq!(first_channel(CONNECT_IND_LEN + 1, |_| {}), Some(7));
+        assert_eq!(first_channel(CONNECT_IND_LEN - 1, |_| {}), None);
+        assert_eq!(first_channel(CONNECT_IND_LEN, |p| p[0] = ADV_IND), None);
+        // Addressed to another device
+        assert_eq!(first_channel(CONNECT_IND_LEN, |p| p[13] ^= 1), None);
+        // The sleep clock accuracy bits are not part of the hop increment.
+        assert_eq!(first_channel(CONNECT_IND_LEN, |p| p[35] = 0xe5), Some(5));
+        assert_eq!(first_channel(CONNECT_IND_LEN, |p| p[35] = 4), None);
+        assert_eq!(first_channel(CONNECT_IND_LEN, |p| p[35] = 16), Some(16));
+        assert_eq!(first_channel(CONNECT_IND_LEN, |p| p[35] = 17), None);
+        assert_eq!(first_channel(CONNECT_IND_LEN, |p| p[24] = 5), None);
+        assert_eq!(first_channel(CONNECT_IND_LEN, |p| p[24] = 6), Some(7));
+        // The supervision timeout is shorter than two intervals.
+        assert_eq!(first_channel(CONNECT_IND_LEN, |p| p[28] = 5), None);
+    }
+
+    #[test]
+    fn test_connect_ind_channel_map() {
+        // Channel 7 is unused, 7 % 2 picks the second used channel.
+        assert_eq!(
+            first_channel(CONNECT_IND_LEN, |p| p[30..35]
+                .copy_from_slice(&[0x03, 0, 0, 0, 0])),
+            Some(1)
+        );
+        assert_eq!(
+            first_channel(CONNECT_IND_LEN, |p| p[30..35]
+                .copy_from_slice(&[0, 0, 0, 0, 0x01])),
+            None
+        );
+        // Channels above 36 are not data channels.
+        assert_eq!(
+            first_channel(CONNECT_IND_LEN, |p| p[30..35]
+                .copy_from_slice(&[0, 0, 0, 0, 0xe1])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_connect_crc_error() {
+        let radio = fake_radio();
+        let alarm = fake_alarm();
+        let link = LinkLayer::new(&radio, &alarm, ADDRESS);
+        deliver(&link, &alarm, &connect_ind(), Err(ErrorCode::FAIL));
+        assert!(!link.is_connected());
+        assert_eq!(radio.access_address.get(), None);
+    }
+
+    #[test]
+    fn test_invalid_connection_update() {
+        let radio = fake_radio();
+        let alarm = fake_alarm();
+        let link = LinkLayer::new(&radio, &alarm, ADDRESS);
+        deliver(&link, &alarm, &connect_ind(), Ok(()));
+
+        // Interval 0 at instant 10
+        link.control(&[
+            control::CONNECTION_UPDATE_IND,
+            2,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            72,
+            0,
+            10,
+            0,
+        ]);
+        assert_eq!(
+            link.connection
+                .map(|connection| (connection.update.is_none(), connection.terminating)),
+            Some((true, true))
+        );
+    }
+}
@@ -0,0 +1,18 @@
+//! Bluetooth Low Energy peripheral with a GATT server.
+//!
+//! ```text
+//!   GATT attribute table (gatt)
+//!              |
+//!   Attribute Protocol server (att)
+//!              |
+//!            L2CAP (l2cap)
+//!              |
+//!   Link layer, connection state (link_layer)
+//!              |
+//!   hil::ble_connection::BleConnectionDriver
+//! ```
+
+pub mod att;
+pub mod gatt;
+pub mod l2cap;
+pub mod link_layer;
@@ -0,0 +1,325 @@
+//! Bluetooth Low Energy GATT Server Driver
+//!
+//! A system call driver that lets processes expose services over a BLE
+//! connection, so that the board can be read and controlled by any GATT
+//! client, such as the generic BLE apps for phones. Processes register
+//! services and characteristics in the attribute table of the GATT server and
+//! keep their values up to date. The server answers the reads of the client
+//! from the table, and reports writes to the process that registered the
+//! characteristic.
+//!
+//! The services should be registered before advertising starts: the table
+//! cannot change while a client is connected.
+//!
+//! The driver uses the link layer, L2CAP and ATT server in `capsules::ble`.
+//! It needs the radio to itself, so it cannot be used together with
+//! `ble_advertising_driver`.
+//!
+//! ### Allow system calls
+//!
+//! * ReadOnly 0: The UUID of a service or characteristic to register, 2 or 16
+//!               bytes in little endian byte order.
+//! * ReadOnly 1: A characteristic value to set.
+//! * ReadOnly 2: The advertising data, a sequence of AD structures of at most
+//!               31 bytes.
+//! * ReadWrite 0: Buffer a characteristic value is read into.
+//!
+//! ### Subscribe system call
+//!
+//! * 0: A client wrote a characteristic of the process. The upcall receives
+//!      the handle and the length of the new value.
+//! * 1: A client connected (first argument `1`) or disconnected (first
+//!      argument `0`, second argument the reason).
+//!
+//! ### Command system call
+//!
+//! * 0: Driver check.
+//! * 1: Register a primary service with the UUID in ReadOnly 0. Returns the
+//!      handle of the service.
+//! * 2: Add a characteristic to the last registered service, with the UUID in
+//!      ReadOnly 0. Argument 1 holds the properties (read 0x02, write without
+//!      response 0x04, write 0x08, notify 0x10), argument 2 the maximum value
+//!      length. Returns the handle of the value.
+//! * 3: Set the value of the characteristic with handle argument 1 to the
+//!      contents of ReadOnly 1.
+//! * 4: Notify the client of the value of the characteristic with handle
+//!      argument 1. Fails with OFF if the client did not enable notifications.
+//! * 5: Read the value of the characteristic with handle argument 1 into
+//!      ReadWrite 0. Returns the length of the value.
+//! * 6: Start advertising every argument 1 milliseconds, with the advertising
+//!      data in ReadOnly 2. Advertising stops when a client connects.
+//! * 7: Stop advertising.
+//! * 8: Disconnect the client.
+//!
+//! Processes can only set, notify and read the characteristics they
+//! registered.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ble_gatt = nrf52_components::BleGattComponent::new(
+//!     board_kernel,
+//!     capsules::ble_gatt_driver::DRIVER_NUM,
+//!     &base_peripherals.ble_radio,
+//!     mux_alarm,
+//!     [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6],
+//!     b"Tock sensor",
+//! )
+//! .finalize(());
+//! ```
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::ble_connection::BleConnectionDriver;
+use kernel::hil::time::Alarm;
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+use crate::ble::att;
+use crate::ble::att::AttServer;
+use crate::ble::gatt::{AttributeTable, Uuid};
+use crate::ble::link_layer::{LinkLayer, MAX_ADV_DATA_LEN};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::BleGatt as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const UUID: usize = 0;
+    pub const VALUE: usize = 1;
+    pub const ADV_DATA: usize = 2;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 3;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const VALUE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Ids for subscribe upcalls
+mod upcall {
+    pub const WRITTEN: usize = 0;
+    pub const CONNECTION: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Maximum length of a characteristic value.
+pub const MAX_VALUE_LEN: usize = 128;
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct BleGatt<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> {
+    link: &'a LinkLayer<'a, R, A>,
+    att: &'a AttServer<'a>,
+    table: &'a AttributeTable,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> BleGatt<'a, R, A> {
+    pub fn new(
+        link: &'a LinkLayer<'a, R, A>,
+        att: &'a AttServer<'a>,
+        table: &'a AttributeTable,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> BleGatt<'a, R, A> {
+        BleGatt {
+            link,
+            att,
+            table,
+            apps: grant,
+        }
+    }
+
+    /// Copies the read-only allow buffer `index` of the process to `buf`,
+    /// returning its length.
+    fn copy_allowed(
+        &self,
+        processid: ProcessId,
+        index: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(index)
+                    .and_then(|allowed| {
+                        allowed.enter(|data| {
+                            if data.len() > buf.len() {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            data.copy_to_slice(&mut buf[..data.len()]);
+                            Ok(data.len())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn allowed_uuid(&self, processid: ProcessId) -> Result<Uuid, ErrorCode> {
+        let mut uuid = [0; 16];
+        let len = self.copy_allowed(processid, ro_allow::UUID, &mut uuid)?;
+        Uuid::from_bytes(&uuid[..len]).ok_or(ErrorCode::INVAL)
+    }
+
+    fn check_owner(&self, handle: usize, processid: ProcessId) -> Result<u16, ErrorCode> {
+        let handle = handle as u16;
+        if self.table.owner(handle) == Some(processid.id()) {
+            Ok(handle)
+        } else {
+            Err(ErrorCode::INVAL)
+        }
+    }
+
+    fn set_value(&self, handle: usize, processid: ProcessId) -> Result<(), ErrorCode> {
+        let handle = self.check_owner(handle, processid)?;
+        let mut value = [0; MAX_VALUE_LEN];
+        let len = self.copy_allowed(processid, ro_allow::VALUE, &mut value)?;
+        self.table.set_value(handle, &value[..len])
+    }
+
+    fn read_value(&self, handle: usize, processid: ProcessId) -> Result<u32, ErrorCode> {
+        let handle = self.check_owner(handle, processid)?;
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::VALUE)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|buffer| {
+                            self.table
+                                .with_value(handle, |value| {
+                                    buffer
+                                        .get_to(..value.len())
+                                        .ok_or(ErrorCode::SIZE)?
+                                        .copy_from_slice_or_err(value)?;
+                                    Ok(value.len() as u32)
+                                })
+                                .unwrap_or(Err(ErrorCode::INVAL))
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn start_advertising(&self, interval_ms: usize, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.link.is_connected() {
+            return Err(ErrorCode::BUSY);
+        }
+        let mut adv_data = [0; MAX_ADV_DATA_LEN];
+        let len = self.copy_allowed(processid, ro_allow::ADV_DATA, &mut adv_data)?;
+        self.link.set_advertising_data(&adv_data[..len])?;
+        self.link.start_advertising(interval_ms as u32)
+    }
+
+    fn connection_upcall(&self, connected: bool, reason: u8) {
+        self.apps.each(|_, _, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::CONNECTION, (connected as usize, reason as usize, 0))
+                .ok();
+        });
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> att::Client for BleGatt<'a, R, A> {
+    fn connected(&self) {
+        self.connection_upcall(true, 0);
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.connection_upcall(false, reason);
+    }
+
+    fn value_written(&self, handle: u16, len: usize) {
+        if let Some(owner) = self.table.owner(handle) {
+            self.apps.each(|processid, _, kernel_data| {
+                if processid.id() == owner {
+                    kernel_data
+                        .schedule_upcall(upcall::WRITTEN, (handle as usize, len, 0))
+                        .ok();
+                }
+            });
+        }
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> SyscallDriver for BleGatt<'a, R, A> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            // Register a primary service
+            1 => match self
+                .allowed_uuid(processid)
+                .and_then(|uuid| self.table.add_service(uuid, processid.id()))
+            {
+                Ok(handle) => CommandReturn::success_u32(handle as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            // Add a characteristic
+            2 => {
+                if data2 > MAX_VALUE_LEN {
+                    return CommandReturn::failure(ErrorCode::SIZE);
+                }
+                match self.allowed_uuid(processid).and_then(|uuid| {
+                    self.table
+                        .add_characteristic(uuid, data1 as u8, data2 as u16, processid.id())
+                }) {
+                    Ok(handle) => CommandReturn::success_u32(handle as u32),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
+            // Set a characteristic value
+            3 => self.set_value(data1, processid).into(),
+
+            // Notify the client
+            4 => self
+                .check_owner(data1, processid)
+                .and_then(|handle| self.att.notify(handle))
+                .into(),
+
+            // Read a characteristic value
+            5 => match self.read_value(data1, processid) {
+                Ok(len) => CommandReturn::success_u32(len),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            6 => self.start_advertising(data1, processid).into(),
+
+            7 => self.link.stop_advertising().into(),
+
+            8 => self.link.disconnect().into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,476 @@
+//! Stores checkpoints of processes in flash, so that they resume where they
+//! were after a reboot.
+//!
+//! A process asks for a checkpoint through the syscall interface, or the
+//! kernel requests one with `CheckpointStorage::checkpoint()`. The process is
+//! stopped while `Process::read_checkpoint()` streams its checkpoint into the
+//! slot of the process in a `kernel::checkpoint::CheckpointRegion`, one flash
+//! page at a time. The first page, which holds the header, is erased first
+//! and written last, so a checkpoint that was interrupted, for example by a
+//! brown-out, is never restored.
+//!
+//! On the next boot the board loads its processes with
+//! `kernel::process::load_processes_and_restore()`, which resumes every
+//! process that has a valid checkpoint. A restored process has no grants, so
+//! it has to subscribe and allow again, and upcalls that were pending are
+//! lost. Before a process can be checkpointed it therefore sets a resume
+//! function, which the kernel calls in the restored process as its first
+//! upcall.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let checkpoint_region = kernel::checkpoint::CheckpointRegion::new(
+//!     core::slice::from_raw_parts(
+//!         (CHECKPOINT_FIRST_PAGE * 4096) as *const u8,
+//!         CHECKPOINT_PAGES * 4096,
+//!     ),
+//!     CHECKPOINT_SLOT_PAGES * 4096,
+//!     kernel::checkpoint::crc32(
+//!         0,
+//!         core::slice::from_raw_parts(
+//!             &_stext as *const u8,
+//!             &_etext as *const u8 as usize - &_stext as *const u8 as usize,
+//!         ),
+//!     ),
+//! );
+//! let checkpoint = static_init!(
+//!     capsules::checkpoint::CheckpointStorage<'static, nrf52840::nvmc::Nvmc, Capability>,
+//!     capsules::checkpoint::CheckpointStorage::new(
+//!         &base_peripherals.nvmc,
+//!         board_kernel,
+//!         Capability,
+//!         checkpoint_region,
+//!         CHECKPOINT_FIRST_PAGE,
+//!         page_buffer,
+//!         board_kernel.create_grant(capsules::checkpoint::DRIVER_NUM, &grant_cap)
+//!     )
+//! );
+//! base_peripherals.nvmc.set_client(checkpoint);
+//!
+//! kernel::process::load_processes_and_restore(
+//!     board_kernel,
+//!     chip,
+//!     app_flash,
+//!     app_memory,
+//!     &mut PROCESSES,
+//!     &FAULT_RESPONSE,
+//!     &checkpoint_region,
+//!     &process_management_capability,
+//! )
+//! .unwrap();
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Stability: 0 - Draft
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Stores a checkpoint of the calling process. The upcall is scheduled
+//!   when it has been stored. Fails with `RESERVE` if no resume function is
+//!   set.
+//! - `2`: Discards the checkpoint of the calling process, so that it starts
+//!   from the beginning after the next reboot. The upcall is scheduled when
+//!   done.
+//! - `3`: Sets the resume function of the calling process to the address in
+//!   the first argument. When the process is resumed from a checkpoint, the
+//!   function is called like an upcall, with the second argument of this
+//!   command as its last argument and zero for the others.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Upcall when command `1` or `2` completes, with the status as first
+//!   argument.
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::checkpoint::{self, CheckpointRegion};
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil;
+use kernel::process::State as ProcessState;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Checkpoint as usize;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    /// Erasing the first page of the slot before writing a checkpoint.
+    Erasing,
+    /// Writing the page with the given index in the slot.
+    Writing(usize),
+    /// Erasing the first page of the slot to discard a checkpoint.
+    Discarding,
+}
+
+pub struct CheckpointStorage<'a, F: hil::flash::Flash + 'static, C: ProcessManagementCapability> {
+    flash: &'a F,
+    kernel: &'static Kernel,
+    capability: C,
+    region: CheckpointRegion,
+    /// Flash page the region starts at.
+    first_page: usize,
+    /// Length of a flash page.
+    page_size: usize,
+    page_buffer: TakeCell<'static, F::Page>,
+    state: Cell<State>,
+    /// The process whose checkpoint is being written or discarded.
+    process: OptionalCell<ProcessId>,
+    /// Whether the process asked for the operation and expects an upcall.
+    requested_by_process: Cell<bool>,
+    /// Whether the process was stopped for the checkpoint and must be resumed.
+    stopped: Cell<bool>,
+    /// Length of the checkpoint being written.
+    length: Cell<usize>,
+    /// Resume function and its argument for the checkpoint being written.
+    resume: Cell<(usize, usize)>,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+}
+
+#[derive(Default)]
+pub struct App {
+    /// Address and argument of the function called when the process is
+    /// resumed from a checkpoint.
+    resume: Option<(usize, usize)>,
+}
+
+impl<'a, F: hil::flash::Flash + 'static, C: ProcessManagementCapability>
+    CheckpointStorage<'a, F, C>
+{
+    /// Creates storage for checkpoints in `region`, which starts at the page
+    /// `first_page` of `flash`.
+    pub fn new(
+        flash: &'a F,
+        kernel: &'static Kernel,
+        capability: C,
+        region: CheckpointRegion,
+        first_page: usize,
+        page_buffer: &'static mut F::Page,
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> CheckpointStorage<'a, F, C> {
+        CheckpointStorage {
+            flash,
+            kernel,
+            capability,
+            region,
+            first_page,
+            page_size: page_buffer.as_mut().len(),
+            page_buffer: TakeCell::new(page_buffer),
+            state: Cell::new(State::Idle),
+            process: OptionalCell::empty(),
+            requested_by_process: Cell::new(false),
+            stopped: Cell::new(false),
+            length: Cell::new(0),
+            resume: Cell::new((0, 0)),
+            apps: grant,
+        }
+    }
+
+    /// Stores a checkpoint of the process. The process is stopped until the
+    /// checkpoint is stored. Fails with `RESERVE` if the process has not set
+    /// a resume function.
+    pub fn checkpoint(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.start_checkpoint(processid, false)
+    }
+
+    /// The flash page the slot of the process starts at.
+    fn slot_page(&self, processid: ProcessId) -> Result<usize, ErrorCode> {
+        let page_size = self.page_size;
+        if page_size < checkpoint::HEADER_LENGTH {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        self.region
+            .slot_offset(processid)
+            .map(|offset| self.first_page + offset / page_size)
+            .ok_or(ErrorCode::NOMEM)
+    }
+
+    fn start_checkpoint(
+        &self,
+        processid: ProcessId,
+        requested_by_process: bool,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let slot_page = self.slot_page(processid)?;
+        let resume = self
+            .apps
+            .enter(processid, |app, _| app.resume)
+            .map_err(ErrorCode::from)?
+            .ok_or(ErrorCode::RESERVE)?;
+
+        // Stop the process so that it does not change while its checkpoint
+        // is read.
+        let stopped = self
+            .kernel
+            .process_map_or_external(
+                None,
+                processid,
+                |process| match process.get_state() {
+                    ProcessState::Running | ProcessState::Yielded => {
+                        process.stop();
+                        Some(true)
+                    }
+                    ProcessState::StoppedRunning | ProcessState::StoppedYielded => Some(false),
+                    _ => None,
+                },
+                &self.capability,
+            )
+            .ok_or(ErrorCode::INVAL)?;
+        self.process.set(processid);
+        self.stopped.set(stopped);
+        self.resume.set(resume);
+
+        let length = self.kernel.process_map_or_external(
+            Err(ErrorCode::FAIL),
+            processid,
+            |process| process.read_checkpoint(0, &mut []),
+            &self.capability,
+        );
+        let result = length.and_then(|length| {
+            if length > self.region.slot_length() {
+                Err(ErrorCode::SIZE)
+            } else {
+                self.flash.erase_page(slot_page).map(|()| length)
+            }
+        });
+        match result {
+            Ok(length) => {
+                self.length.set(length);
+                self.requested_by_process.set(requested_by_process);
+                self.state.set(State::Erasing);
+                Ok(())
+            }
+            Err(err) => {
+                self.resume();
+                Err(err)
+            }
+        }
+    }
+
+    fn start_discard(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.flash.erase_page(self.slot_page(processid)?)?;
+        self.process.set(processid);
+        self.stopped.set(false);
+        self.requested_by_process.set(true);
+        self.state.set(State::Discarding);
+        Ok(())
+    }
+
+    /// The page written after the page `index`. The first page is written
+    /// last, and `None` is returned after it.
+    fn next_page(&self, index: usize) -> Option<usize> {
+        let page_size = self.page_size;
+        let pages = (self.length.get() + page_size - 1) / page_size;
+        if index == 0 {
+            None
+        } else if index + 1 < pages {
+            Some(index + 1)
+        } else {
+            Some(0)
+        }
+    }
+
+    fn write_page(&self, index: usize) {
+        let result = self.process.map_or(Err(ErrorCode::FAIL), |processid| {
+            let page_number = self.slot_page(*processid)? + index;
+            self.page_buffer
+                .take()
+                .map_or(Err(ErrorCode::BUSY), |page_buffer| {
+                    match self.fill_page(*processid, index, page_buffer.as_mut()) {
+                        Ok(()) => self.flash.write_page(page_number, page_buffer).or_else(
+                            |(err, page_buffer)| {
+                                self.page_buffer.replace(page_buffer);
+                                Err(err)
+                            },
+                        ),
+                        Err(err) => {
+                            self.page_buffer.replace(page_buffer);
+                            Err(err)
+                        }
+                    }
+                })
+        });
+        match result {
+            Ok(()) => self.state.set(State::Writing(index)),
+            Err(err) => self.finish(Err(err)),
+        }
+    }
+
+    /// Reads the page `index` of the checkpoint into `page`, and seals the
+    /// checkpoint if it is the first page. The CRC of the other pages, which
+    /// are written before it, is computed from the data in flash.
+    fn fill_page(
+        &self,
+        processid: ProcessId,
+        index: usize,
+        page: &mut [u8],
+    ) -> Result<(), ErrorCode> {
+        for byte in page.iter_mut() {
+            *byte = 0xFF;
+        }
+        let offset = index * page.len();
+        let length = self.kernel.process_map_or_external(
+            Err(ErrorCode::FAIL),
+            processid,
+            |process| process.read_checkpoint(offset, page),
+            &self.capability,
+        )?;
+        // The process must not have changed since the checkpoint started.
+        if length != self.length.get() {
+            return Err(ErrorCode::FAIL);
+        }
+
+        if index == 0 {
+            let end = cmp::min(page.len(), length);
+            let written = self
+                .region
+                .slot_contents(processid)
+                .and_then(|slot| slot.get(end..length))
+                .ok_or(ErrorCode::FAIL)?;
+            let crc = checkpoint::crc32(
+                checkpoint::crc32(0, &page[checkpoint::HEADER_LENGTH..end]),
+                written,
+            );
+            let (resume_function, resume_argument) = self.resume.get();
+            checkpoint::seal(
+                page,
+                crc,
+                self.region.kernel_id(),
+                resume_function,
+                resume_argument,
+            );
+        }
+        Ok(())
+    }
+
+    fn resume(&self) {
+        if self.stopped.get() {
+            self.process.map(|processid| {
+                self.kernel.process_map_or_external(
+                    (),
+                    *processid,
+                    |process| process.resume(),
+                    &self.capability,
+                );
+            });
+        }
+        self.stopped.set(false);
+    }
+
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        self.state.set(State::Idle);
+        self.resume();
+        self.process.take().map(|processid| {
+            if self.requested_by_process.get() {
+                let _ = self.apps.enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .schedule_upcall(0, (kernel::errorcode::into_statuscode(result), 0, 0))
+                        .ok();
+                });
+            }
+        });
+    }
+}
+
+impl<'a, F: hil::flash::Flash + 'static, C: ProcessManagementCapability> hil::flash::Client<F>
+    for CheckpointStorage<'a, F, C>
+{
+    fn read_complete(&self, page_buffer: &'static mut F::Page, _error: hil::flash::Error) {
+        self.page_buffer.replace(page_buffer);
+    }
+
+    fn write_complete(&self, page_buffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.page_buffer.replace(page_buffer);
+        if let State::Writing(index) = self.state.get() {
+            if error != hil::flash::Error::CommandComplete {
+                self.finish(Err(ErrorCode::FAIL));
+            } else if let Some(next) = self.next_page(index) {
+                self.write_page(next);
+            } else {
+                self.finish(Ok(()));
+            }
+        }
+    }
+
+    fn erase_complete(&self, error: hil::flash::Error) {
+        let result = if error == hil::flash::Error::CommandComplete {
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        };
+        match self.state.get() {
+            State::Erasing => match result {
+                // The first page holds the header and is written last.
+                Ok(()) => {
+                    if self.length.get() > self.page_size {
+                        self.write_page(1);
+                    } else {
+                        self.write_page(0);
+                    }
+                }
+                Err(err) => self.finish(Err(err)),
+            },
+            State::Discarding => self.finish(result),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, F: hil::flash::Flash + 'static, C: ProcessManagementCapability> SyscallDriver
+    for CheckpointStorage<'a, F, C>
+{
+    /// Store or discard the checkpoint of the calling process.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Stores a checkpoint of the calling process.
+    /// - `2`: Discards the checkpoint of the calling process.
+    /// - `3`: Sets the resume function of the calling process.
+    fn command(
+        &self,
+        command_num: usize,
+        function: usize,
+        argument: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.start_checkpoint(processid, true).into(),
+
+            2 => self.start_discard(processid).into(),
+
+            3 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.resume = Some((function, argument));
+                })
+                .map_err(ErrorCode::from)
+                .into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
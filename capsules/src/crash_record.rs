@@ -0,0 +1,416 @@
+//! Stores crash records in flash and provides them to userspace after a
+//! reboot.
+//!
+//! This capsule is a `CrashRecorder` for the kernel. When a process faults or
+//! the kernel panics, it serializes the state of the crash with
+//! `kernel::crash_record::CrashRecordWriter` and writes the record to a region
+//! of flash reserved for it. On boot it reads the region back, so the last
+//! crash record can be retrieved through the syscall interface, for example by
+//! an application that uploads it, and decoded on a host with
+//! `tools/decode_crash_record.py`.
+//!
+//! Process faults are recorded by `kernel::process::CrashRecordFaultPolicy`,
+//! kernel panics by `kernel::debug::panic_crash_record()` in the panic handler
+//! of the board. A new record replaces the previous one, except that the
+//! record of a kernel panic is kept until an application erases it: it is
+//! only replaced by the record of another panic, never by that of a process
+//! fault.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let crash_record = static_init!(
+//!     capsules::crash_record::CrashRecordStorage<'static, nrf52840::nvmc::Nvmc>,
+//!     capsules::crash_record::CrashRecordStorage::new(
+//!         &base_peripherals.nvmc,
+//!         CRASH_RECORD_FIRST_PAGE,
+//!         &mut capsules::crash_record::RECORD_BUF,
+//!         page_buffer,
+//!         board_kernel.create_grant(capsules::crash_record::DRIVER_NUM, &grant_cap)
+//!     )
+//! );
+//! base_peripherals.nvmc.set_client(crash_record);
+//! fault_policy.set_recorder(crash_record);
+//! CRASH_RECORDER = Some(crash_record);
+//! crash_record.start();
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Stability: 0 - Draft
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Returns the length of the stored crash record, or zero if there is
+//!   none.
+//! - `2`: Copies the stored crash record into the read-write allow buffer and
+//!   returns how many bytes were copied.
+//! - `3`: Erases the stored crash record. The upcall is scheduled when done.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Upcall when the record has been erased.
+//!
+//! ### Allow ReadWrite
+//!
+//! - `0`: Buffer the crash record is copied into.
+
+use core::cell::Cell;
+use core::cmp;
+use core::panic::PanicInfo;
+
+use kernel::crash_record::{self, CrashKind, CrashRecordWriter, CrashRecorder};
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil;
+use kernel::process::Process;
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::CrashRecord as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const BUFFER: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Buffer that holds the crash record. Its length determines how much flash
+/// the records use.
+pub static mut RECORD_BUF: [u8; 1024] = [0; 1024];
+
+/// How many times the flash is serviced while waiting for it during a panic
+/// before giving up.
+const PANIC_SERVICE_LIMIT: usize = 1_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    /// Reading the page with the given index in the region at boot.
+    Reading(usize),
+    /// Writing the page with the given index in the region.
+    Writing(usize),
+    Erasing,
+}
+
+pub struct CrashRecordStorage<'a, F: hil::flash::Flash + 'static> {
+    flash: &'a F,
+    /// First page of the flash region that holds the record.
+    first_page: usize,
+    record: TakeCell<'static, [u8]>,
+    /// Length of the record in `record`, or zero if there is none.
+    record_length: Cell<usize>,
+    page_buffer: TakeCell<'static, F::Page>,
+    state: Cell<State>,
+    apps: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    erasing_app: OptionalCell<ProcessId>,
+}
+
+impl<'a, F: hil::flash::Flash + 'static> CrashRecordStorage<'a, F> {
+    /// Creates storage for crash records of up to `record.len()` bytes in the
+    /// pages of `flash` starting at `first_page`.
+    pub fn new(
+        flash: &'a F,
+        first_page: usize,
+        record: &'static mut [u8],
+        page_buffer: &'static mut F::Page,
+        grant: Grant<(), UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    ) -> CrashRecordStorage<'a, F> {
+        CrashRecordStorage {
+            flash,
+            first_page,
+            record: TakeCell::new(record),
+            record_length: Cell::new(0),
+            page_buffer: TakeCell::new(page_buffer),
+            state: Cell::new(State::Idle),
+            apps: grant,
+            erasing_app: OptionalCell::empty(),
+        }
+    }
+
+    /// Reads the crash record stored before the last reboot, if any.
+    pub fn start(&self) {
+        if self.state.get() == State::Idle {
+            self.read_page(0);
+        }
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_buffer
+            .map_or(0, |page_buffer| page_buffer.as_mut().len())
+    }
+
+    /// How many pages are needed to store `length` bytes of the record.
+    fn pages_for(&self, length: usize) -> usize {
+        let page_size = self.page_size();
+        if page_size == 0 {
+            0
+        } else {
+            (length + page_size - 1) / page_size
+        }
+    }
+
+    fn read_page(&self, index: usize) {
+        let page_count = self.pages_for(self.record.map_or(0, |record| record.len()));
+        if index >= page_count {
+            self.record_length.set(
+                self.record
+                    .map_or(None, |record| crash_record::validate(record))
+                    .unwrap_or(0),
+            );
+            self.state.set(State::Idle);
+            return;
+        }
+
+        self.page_buffer.take().map(|page_buffer| {
+            match self.flash.read_page(self.first_page + index, page_buffer) {
+                Ok(()) => self.state.set(State::Reading(index)),
+                Err((_, page_buffer)) => {
+                    self.page_buffer.replace(page_buffer);
+                    self.state.set(State::Idle);
+                }
+            }
+        });
+    }
+
+    fn write_page(&self, index: usize) {
+        let length = self.record_length.get();
+        if index >= self.pages_for(length) {
+            self.state.set(State::Idle);
+            return;
+        }
+
+        let page_size = self.page_size();
+        let started = self.page_buffer.take().map_or(false, |page_buffer| {
+            let page = page_buffer.as_mut();
+            let start = index * page_size;
+            let end = cmp::min(start + page_size, length);
+            self.record.map(|record| {
+                page[..end - start].copy_from_slice(&record[start..end]);
+            });
+            for byte in &mut page[end - start..] {
+                *byte = 0xFF;
+            }
+            match self.flash.write_page(self.first_page + index, page_buffer) {
+                Ok(()) => true,
+                Err((_, page_buffer)) => {
+                    self.page_buffer.replace(page_buffer);
+                    false
+                }
+            }
+        });
+        self.state.set(if started {
+            State::Writing(index)
+        } else {
+            State::Idle
+        });
+    }
+
+    /// Serializes a record with `fill` and starts writing it to flash. Crashes
+    /// while the storage is busy are not recorded, and neither are process
+    /// faults while the record of a kernel panic has not been erased.
+    fn record(&self, kind: CrashKind, fill: &dyn Fn(&mut CrashRecordWriter)) {
+        if self.state.get() != State::Idle {
+            return;
+        }
+        if kind == CrashKind::ProcessFault && self.stored_kind() == Some(CrashKind::KernelPanic) {
+            return;
+        }
+        let length = self.record.map_or(0, |record| {
+            CrashRecordWriter::new(record, kind).map_or(0, |mut writer| {
+                fill(&mut writer);
+                writer.finish()
+            })
+        });
+        if length > 0 {
+            self.record_length.set(length);
+            self.write_page(0);
+        }
+    }
+
+    /// The cause of the crash of the stored record, if there is one.
+    fn stored_kind(&self) -> Option<CrashKind> {
+        let length = self.record_length.get();
+        if length == 0 {
+            return None;
+        }
+        self.record
+            .map_or(None, |record| crash_record::kind(&record[..length]))
+    }
+
+    /// Services the flash until it is idle. Returns `false` if it does not
+    /// become idle.
+    fn wait_until_idle(&self, service: &dyn Fn()) -> bool {
+        for _ in 0..PANIC_SERVICE_LIMIT {
+            if self.state.get() == State::Idle {
+                return true;
+            }
+            service();
+        }
+        self.state.get() == State::Idle
+    }
+}
+
+impl<'a, F: hil::flash::Flash + 'static> CrashRecorder for CrashRecordStorage<'a, F> {
+    fn record_process_fault(&self, process: &dyn Process) {
+        self.record(CrashKind::ProcessFault, &|writer| {
+            writer.add_process(process);
+        });
+    }
+
+    fn record_panic(
+        &self,
+        panic_info: &PanicInfo,
+        processes: &[Option<&'static dyn Process>],
+        service: &dyn Fn(),
+    ) {
+        // Let a record of a process fault that is still being written
+        // complete first.
+        if !self.wait_until_idle(service) {
+            return;
+        }
+        self.record(CrashKind::KernelPanic, &|writer| {
+            writer.add_panic_message(format_args!("{}", panic_info));
+            for process in processes.iter().flatten() {
+                writer.add_process(*process);
+            }
+        });
+        self.wait_until_idle(service);
+    }
+}
+
+impl<'a, F: hil::flash::Flash + 'static> hil::flash::Client<F> for CrashRecordStorage<'a, F> {
+    fn read_complete(&self, page_buffer: &'static mut F::Page, error: hil::flash::Error) {
+        if let State::Reading(index) = self.state.get() {
+            if error == hil::flash::Error::CommandComplete {
+                let page = page_buffer.as_mut();
+                let start = index * page.len();
+                self.record.map(|record| {
+                    let end = cmp::min(start + page.len(), record.len());
+                    record[start..end].copy_from_slice(&page[..end - start]);
+                });
+                self.page_buffer.replace(page_buffer);
+                self.read_page(index + 1);
+            } else {
+                self.page_buffer.replace(page_buffer);
+                self.state.set(State::Idle);
+            }
+        } else {
+            self.page_buffer.replace(page_buffer);
+        }
+    }
+
+    fn write_complete(&self, page_buffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.page_buffer.replace(page_buffer);
+        if let State::Writing(index) = self.state.get() {
+            if error == hil::flash::Error::CommandComplete {
+                self.write_page(index + 1);
+            } else {
+                self.state.set(State::Idle);
+            }
+        }
+    }
+
+    fn erase_complete(&self, error: hil::flash::Error) {
+        if self.state.get() == State::Erasing {
+            self.state.set(State::Idle);
+            let result = if error == hil::flash::Error::CommandComplete {
+                self.record_length.set(0);
+                Ok(())
+            } else {
+                Err(ErrorCode::FAIL)
+            };
+            self.erasing_app.take().map(|processid| {
+                let _ = self.apps.enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .schedule_upcall(0, (kernel::errorcode::into_statuscode(result), 0, 0))
+                        .ok();
+                });
+            });
+        }
+    }
+}
+
+impl<'a, F: hil::flash::Flash + 'static> SyscallDriver for CrashRecordStorage<'a, F> {
+    /// Access the stored crash record.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Returns the length of the stored crash record, or zero if there
+    ///   is none.
+    /// - `2`: Copies the stored crash record into the allowed buffer and
+    ///   returns how many bytes were copied.
+    /// - `3`: Erases the stored crash record.
+    fn command(
+        &self,
+        command_num: usize,
+        _: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if let State::Reading(_) = self.state.get() {
+            // The record is not known until it has been read.
+            if command_num != 0 {
+                return CommandReturn::failure(ErrorCode::BUSY);
+            }
+        }
+
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => CommandReturn::success_u32(self.record_length.get() as u32),
+
+            2 => {
+                let length = self.record_length.get();
+                let result = self.apps.enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::BUFFER)
+                        .and_then(|buffer| {
+                            buffer.mut_enter(|dest| {
+                                let count = cmp::min(dest.len(), length);
+                                self.record.map_or(0, |record| {
+                                    dest[..count].copy_from_slice(&record[..count]);
+                                    count
+                                })
+                            })
+                        })
+                        .unwrap_or(0)
+                });
+                match result {
+                    Ok(count) => CommandReturn::success_u32(count as u32),
+                    Err(err) => CommandReturn::failure(err.into()),
+                }
+            }
+
+            3 => {
+                if self.state.get() != State::Idle {
+                    return CommandReturn::failure(ErrorCode::BUSY);
+                }
+                match self.flash.erase_page(self.first_page) {
+                    Ok(()) => {
+                        self.state.set(State::Erasing);
+                        self.erasing_app.set(processid);
+                        CommandReturn::success()
+                    }
+                    Err(err) => CommandReturn::failure(err),
+                }
+            }
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
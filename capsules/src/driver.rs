@@ -21,6 +21,9 @@ pub enum NUM {
 
     // Kernel
     Ipc                   = 0x10000,
+    Heartbeat             = 0x10001,
+    Mailbox               = 0x10002,
+    SyscallFilter         = 0x10003,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -28,11 +31,15 @@ pub enum NUM {
     I2cMaster             = 0x20003,
     UsbUser               = 0x20005,
     I2cMasterSlave        = 0x20006,
+    UsbHid                = 0x20007,
 
     // Radio
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Tcp                   = 0x30003,
+    Ping                  = 0x30004,
+    BleGatt               = 0x30005,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -46,6 +53,10 @@ pub enum NUM {
     AppFlash              = 0x50000,
     NvmStorage            = 0x50001,
     SdCard                = 0x50002,
+    Kv                    = 0x50003,
+    CrashRecord           = 0x50004,
+    Fat                   = 0x50005,
+    Checkpoint            = 0x50006,
 
     // Sensors
     Temperature           = 0x60000,
@@ -0,0 +1,1657 @@
+//! Asynchronous FAT16 and FAT32 filesystem on an SD card.
+//!
+//! `FatFs` mounts the volume of an SD card through the block reads and writes
+//! of `capsules::sdcard::SDCard`, and lets the kernel open, create, read,
+//! write and append to files, and create directories, anywhere on the
+//! volume. Addressing the card in blocks rather than in bytes, as
+//! `hil::nonvolatile_storage` does, keeps cards larger than 4 GiB usable.
+//!
+//! The volume is mounted once the card is initialized, and unmounted when the
+//! card is removed. Cards with a partition table and cards formatted without
+//! one are both supported; on a partitioned card the first FAT partition is
+//! used.
+//!
+//! Only one operation runs at a time; starting another one fails with
+//! `BUSY`. A single sector is cached. The directory entry of a file is updated
+//! at the end of every write, so that the data survives the card being
+//! removed before the file is closed.
+//!
+//! Limitations:
+//!
+//! - Names are short 8.3 names, matched without regard to case. Files with
+//!   long names can be opened with their short alias, such as `LONGFI~1.TXT`.
+//! - Files and directories cannot be deleted, renamed or truncated.
+//! - All timestamps are 1980-01-01, as there is no real time clock.
+//! - The free cluster count of FAT32 volumes is marked unknown when mounting,
+//!   rather than being kept up to date.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let fat = static_init!(
+//!     capsules::fat::filesystem::FatFs<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::fat::filesystem::FatFs::new(
+//!         sdcard,
+//!         dynamic_deferred_caller,
+//!         static_init!([u8; 512], [0; 512]),
+//!     )
+//! );
+//! fat.initialize_callback_handle(dynamic_deferred_caller.register(fat).unwrap());
+//! sdcard.set_client(fat);
+//! fat.set_client(client);
+//! fat.start();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::time::Alarm;
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use super::layout::{
+    self, attributes, DirEntry, Geometry, DIR_ENTRY_SIZE, ENTRIES_PER_SECTOR, ENTRY_END,
+    ENTRY_FREE, NAME_LEN, SECTOR_SIZE,
+};
+use crate::sdcard::{SDCard, SDCardClient};
+
+/// Maximum number of files open at the same time.
+pub const MAX_OPEN_FILES: usize = 4;
+/// Maximum length of a path.
+pub const MAX_PATH_LEN: usize = 64;
+
+/// Flags for `FatFs::open`.
+pub mod open_flags {
+    /// Create the file if it does not exist.
+    pub const CREATE: u32 = 0x1;
+    /// Start at the end of the file, so that writes append to it.
+    pub const APPEND: u32 = 0x2;
+}
+
+/// Identifies an open file.
+pub type FileHandle = usize;
+
+pub trait Client {
+    /// The volume was mounted, or could not be mounted.
+    fn mounted(&self, result: Result<(), ErrorCode>);
+
+    /// The card was removed. All files were closed.
+    fn unmounted(&self);
+
+    /// An `open` completed.
+    fn opened(&self, result: Result<FileHandle, ErrorCode>);
+
+    /// A `create_dir` completed.
+    fn directory_created(&self, result: Result<(), ErrorCode>);
+
+    /// A `read` completed, with the number of bytes read. Fewer bytes than
+    /// requested are read at the end of the file.
+    fn read_done(&self, buffer: &'static mut [u8], result: Result<usize, ErrorCode>);
+
+    /// A `write` completed, with the number of bytes written. Fewer bytes
+    /// than requested are written if the volume is full.
+    fn write_done(&self, buffer: &'static mut [u8], result: Result<usize, ErrorCode>);
+}
+
+/// Location of a directory entry.
+#[derive(Clone, Copy, PartialEq)]
+struct EntryRef {
+    sector: u32,
+    offset: usize,
+}
+
+#[derive(Clone, Copy)]
+struct File {
+    entry: EntryRef,
+    /// First cluster of the file, 0 if no cluster is allocated yet.
+    first_cluster: u32,
+    size: u32,
+    position: u32,
+    /// The cluster at index `cluster_index` of the chain of the file, or 0
+    /// if the chain has not been followed yet.
+    cluster: u32,
+    cluster_index: u32,
+}
+
+/// A position in a directory.
+#[derive(Clone, Copy)]
+struct DirCursor {
+    /// First cluster of the directory, 0 for the FAT16 root directory.
+    start: u32,
+    /// The cluster holding the entry, 0 for the FAT16 root directory.
+    cluster: u32,
+    /// Index of the entry in `cluster`.
+    index: u32,
+}
+
+/// A search for a name in a directory.
+#[derive(Clone, Copy)]
+struct Lookup {
+    cursor: DirCursor,
+    name: [u8; NAME_LEN],
+    /// The first free entry found, where the name can be created.
+    free: Option<EntryRef>,
+}
+
+/// A directory entry seen during a lookup.
+enum Scanned {
+    /// The entry ends the directory.
+    End,
+    Free,
+    /// The entry has another name, or is not a file or directory.
+    Other,
+    Match {
+        directory: bool,
+        cluster: u32,
+        size: u32,
+    },
+}
+
+/// What the path of an operation leads to.
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    File { flags: u32 },
+    Directory,
+}
+
+#[derive(Clone, Copy)]
+enum CreateStep {
+    /// Append a cluster to the directory, which is full.
+    ExtendDirectory,
+    /// Zero the sectors of the cluster appended to the directory.
+    ClearExtension { cluster: u32, remaining: u32 },
+    /// Allocate the cluster of a new directory.
+    AllocateDirectory,
+    /// Zero the sectors of the cluster of a new directory.
+    ClearDirectory { cluster: u32, remaining: u32 },
+    /// Write the `.` and `..` entries of a new directory.
+    WriteDots { cluster: u32 },
+    /// Write the directory entry, with the first cluster of a directory.
+    WriteEntry { cluster: u32 },
+}
+
+/// The result of an operation, reported once the cache is written back.
+#[derive(Clone, Copy)]
+enum Outcome {
+    Mounted,
+    Opened(FileHandle),
+    DirectoryCreated,
+    Read(usize),
+    Written(usize),
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Idle,
+    Mount {
+        boot_sector: u32,
+    },
+    InvalidateFsInfo,
+    /// Look up the next component of the path in `directory`.
+    Descend {
+        target: Target,
+        directory: u32,
+    },
+    Lookup {
+        target: Target,
+        lookup: Lookup,
+    },
+    Create {
+        target: Target,
+        lookup: Lookup,
+        step: CreateStep,
+    },
+    Read {
+        file: FileHandle,
+        len: usize,
+        done: usize,
+    },
+    Write {
+        file: FileHandle,
+        len: usize,
+        done: usize,
+    },
+    Done(Outcome),
+}
+
+/// Progress of the allocation of a cluster.
+#[derive(Clone, Copy, PartialEq)]
+enum Allocation {
+    Idle,
+    Scanning { next: u32, remaining: u32 },
+    Found(u32),
+    Marked(u32),
+}
+
+/// Transfer of the sector buffer to or from the card.
+#[derive(Clone, Copy, PartialEq)]
+enum Io {
+    Idle,
+    Read(u32),
+    /// Writing a sector. Sectors of the first FAT are written to every copy
+    /// of the FAT, `copy` counts them.
+    Write {
+        sector: u32,
+        copy: u32,
+    },
+}
+
+/// Why a step of an operation could not complete.
+enum Pending {
+    /// A sector is being transferred; the step runs again once it is done.
+    Io,
+    /// The operation failed.
+    Failed(ErrorCode),
+}
+
+impl From<ErrorCode> for Pending {
+    fn from(error: ErrorCode) -> Pending {
+        Pending::Failed(error)
+    }
+}
+
+type Step<T> = Result<T, Pending>;
+
+/// Operations run as a sequence of steps that each access a single sector.
+/// If the sector is not cached the step starts reading it and returns
+/// `Pending::Io`, and runs again once the sector arrived. Progress is saved
+/// in `state` after every sector access, so that a step never needs two
+/// sectors at once with only one cached.
+pub struct FatFs<'a, A: Alarm<'a>> {
+    sdcard: &'a SDCard<'a, A>,
+    client: OptionalCell<&'a dyn Client>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+    geometry: OptionalCell<Geometry>,
+    state: Cell<State>,
+    files: [Cell<Option<File>>; MAX_OPEN_FILES],
+    /// Path of the running `open` or `create_dir`.
+    path: MapCell<[u8; MAX_PATH_LEN]>,
+    path_len: Cell<usize>,
+    /// End of the path component being looked up.
+    path_position: Cell<usize>,
+    /// Buffer of the running `read` or `write`.
+    data: TakeCell<'static, [u8]>,
+    allocation: Cell<Allocation>,
+    /// Where the search for a free cluster starts.
+    next_free: Cell<u32>,
+    /// The sector cache.
+    buffer: TakeCell<'static, [u8]>,
+    cached: Cell<Option<u32>>,
+    dirty: Cell<bool>,
+    io: Cell<Io>,
+}
+
+impl<'a, A: Alarm<'a>> FatFs<'a, A> {
+    pub fn new(
+        sdcard: &'a SDCard<'a, A>,
+        deferred_caller: &'a DynamicDeferredCall,
+        buffer: &'static mut [u8; SECTOR_SIZE],
+    ) -> FatFs<'a, A> {
+        FatFs {
+            sdcard,
+            client: OptionalCell::empty(),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+            geometry: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            files: Default::default(),
+            path: MapCell::new([0; MAX_PATH_LEN]),
+            path_len: Cell::new(0),
+            path_position: Cell::new(0),
+            data: TakeCell::empty(),
+            allocation: Cell::new(Allocation::Idle),
+            next_free: Cell::new(2),
+            buffer: TakeCell::new(buffer),
+            cached: Cell::new(None),
+            dirty: Cell::new(false),
+            io: Cell::new(Io::Idle),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Initialize the card if one is installed, and watch for the card being
+    /// inserted or removed.
+    pub fn start(&self) {
+        self.sdcard.detect_changes();
+        if self.sdcard.is_installed() {
+            let _ = self.sdcard.initialize();
+        }
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.geometry.is_some()
+    }
+
+    /// Opens the file at `path`, such as `/logs/today.txt`, for reading and
+    /// writing. `flags` are a combination of `open_flags`. Parent directories
+    /// must exist.
+    pub fn open(&self, path: &[u8], flags: u32) -> Result<(), ErrorCode> {
+        self.check_idle()?;
+        if self.files.iter().all(|file| file.get().is_some()) {
+            return Err(ErrorCode::NOMEM);
+        }
+        self.start_path(path, Target::File { flags })
+    }
+
+    /// Creates the directory at `path`, and any missing parent directories.
+    /// Succeeds if the directory already exists.
+    pub fn create_dir(&self, path: &[u8]) -> Result<(), ErrorCode> {
+        self.check_idle()?;
+        self.start_path(path, Target::Directory)
+    }
+
+    /// Reads up to `len` bytes from the current position of `file` into
+    /// `buffer`.
+    pub fn read(
+        &self,
+        file: FileHandle,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(error) = self.check_idle().and_then(|()| self.file(file)) {
+            return Err((error, buffer));
+        }
+        let len = cmp::min(len, buffer.len());
+        self.data.replace(buffer);
+        self.state.set(State::Read { file, len, done: 0 });
+        self.schedule();
+        Ok(())
+    }
+
+    /// Writes `len` bytes of `buffer` at the current position of `file`,
+    /// extending the file as needed.
+    pub fn write(
+        &self,
+        file: FileHandle,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(error) = self.check_idle().and_then(|()| self.file(file)) {
+            return Err((error, buffer));
+        }
+        let len = cmp::min(len, buffer.len());
+        self.data.replace(buffer);
+        self.state.set(State::Write { file, len, done: 0 });
+        self.schedule();
+        Ok(())
+    }
+
+    /// Closes `file`. Nothing needs to be written, as every write updates the
+    /// directory entry of the file.
+    pub fn close(&self, file: FileHandle) -> Result<(), ErrorCode> {
+        self.file(file)?;
+        match self.state.get() {
+            State::Read { file: running, .. } | State::Write { file: running, .. }
+                if running == file =>
+            {
+                Err(ErrorCode::BUSY)
+            }
+            _ => {
+                self.files[file].set(None);
+                Ok(())
+            }
+        }
+    }
+
+    /// The size of `file` in bytes.
+    pub fn size(&self, file: FileHandle) -> Result<u32, ErrorCode> {
+        self.file(file).map(|file| file.size)
+    }
+
+    fn check_idle(&self) -> Result<(), ErrorCode> {
+        if self.geometry.is_none() {
+            Err(ErrorCode::OFF)
+        } else if !matches!(self.state.get(), State::Idle) {
+            Err(ErrorCode::BUSY)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn file(&self, file: FileHandle) -> Result<File, ErrorCode> {
+        self.files
+            .get(file)
+            .and_then(|file| file.get())
+            .ok_or(ErrorCode::INVAL)
+    }
+
+    fn start_path(&self, path: &[u8], target: Target) -> Result<(), ErrorCode> {
+        if path.len() > MAX_PATH_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        self.path
+            .map(|buffer| buffer[..path.len()].copy_from_slice(path));
+        self.path_len.set(path.len());
+        self.path_position.set(0);
+        let root = self.geometry.map_or(0, |geometry| geometry.root_cluster);
+        self.state.set(State::Descend {
+            target,
+            directory: root,
+        });
+        self.schedule();
+        Ok(())
+    }
+
+    /// Runs the operation that was just started once the caller returned, so
+    /// that the client is never called back from within the call.
+    fn schedule(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    /// Mounts the card that was just initialized.
+    fn mount(&self) {
+        self.close_all();
+        self.state.set(State::Mount { boot_sector: 0 });
+        self.run();
+    }
+
+    fn close_all(&self) {
+        self.geometry.clear();
+        for file in self.files.iter() {
+            file.set(None);
+        }
+        self.cached.set(None);
+        self.dirty.set(false);
+        self.allocation.set(Allocation::Idle);
+    }
+
+    fn run(&self) {
+        loop {
+            match self.step() {
+                Ok(true) => {}
+                Ok(false) | Err(Pending::Io) => return,
+                Err(Pending::Failed(error)) => {
+                    self.fail(error);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Makes progress on the running operation. Returns whether there is
+    /// more to do, or `false` once the operation completed.
+    fn step(&self) -> Step<bool> {
+        let state = self.state.get();
+        let geometry = match state {
+            State::Idle => return Ok(false),
+            State::Mount { boot_sector } => return self.mount_step(boot_sector),
+            _ => self.geometry.extract().ok_or(ErrorCode::OFF)?,
+        };
+        match state {
+            State::Idle | State::Mount { .. } => Ok(false),
+            State::InvalidateFsInfo => {
+                if let Some(fs_info) = geometry.fs_info {
+                    if self.sector(fs_info, layout::invalidate_free_count)? {
+                        self.dirty.set(true);
+                    }
+                }
+                self.state.set(State::Done(Outcome::Mounted));
+                Ok(true)
+            }
+            State::Descend { target, directory } => self.descend(target, directory),
+            State::Lookup { target, lookup } => self.lookup_step(&geometry, target, lookup),
+            State::Create {
+                target,
+                lookup,
+                step,
+            } => self.create_step(&geometry, target, lookup, step),
+            State::Read { file, len, done } => self.read_step(&geometry, file, len, done),
+            State::Write { file, len, done } => self.write_step(&geometry, file, len, done),
+            State::Done(outcome) => {
+                self.flush()?;
+                self.state.set(State::Idle);
+                self.report(outcome);
+                Ok(false)
+            }
+        }
+    }
+
+    fn mount_step(&self, boot_sector: u32) -> Step<bool> {
+        let (geometry, partition) = self.sector(boot_sector, |sector| {
+            (
+                Geometry::from_boot_sector(sector, boot_sector),
+                layout::partition_start(sector),
+            )
+        })?;
+        match (geometry, partition) {
+            (Some(geometry), _) => {
+                self.geometry.set(geometry);
+                self.next_free.set(2);
+                self.state.set(State::InvalidateFsInfo);
+            }
+            (None, Some(start)) if boot_sector == 0 && start != 0 => {
+                self.state.set(State::Mount { boot_sector: start });
+            }
+            _ => return Err(Pending::Failed(ErrorCode::NOSUPPORT)),
+        }
+        Ok(true)
+    }
+
+    /// Starts looking up the next component of the path in `directory`.
+    fn descend(&self, target: Target, directory: u32) -> Step<bool> {
+        match self.next_component() {
+            None => match target {
+                Target::Directory => self.state.set(State::Done(Outcome::DirectoryCreated)),
+                Target::File { .. } => return Err(Pending::Failed(ErrorCode::INVAL)),
+            },
+            Some(name) => {
+                let cursor = DirCursor {
+                    start: directory,
+                    cluster: directory,
+                    index: 0,
+                };
+                self.state.set(State::Lookup {
+                    target,
+                    lookup: Lookup {
+                        cursor,
+                        name: name?,
+                        free: None,
+                    },
+                });
+            }
+        }
+        Ok(true)
+    }
+
+    /// Parses the next component of the path into a short name, or returns
+    /// `None` at the end of the path.
+    fn next_component(&self) -> Option<Result<[u8; NAME_LEN], ErrorCode>> {
+        self.path
+            .map(|path| {
+                let path = &path[..self.path_len.get()];
+                let position = self.path_position.get();
+                let start = position + path[position..].iter().take_while(|&&c| c == b'/').count();
+                if start == path.len() {
+                    return None;
+                }
+                let end = path[start..]
+                    .iter()
+                    .position(|&c| c == b'/')
+                    .map_or(path.len(), |len| start + len);
+                self.path_position.set(end);
+                Some(layout::short_name(&path[start..end]).ok_or(ErrorCode::INVAL))
+            })
+            .flatten()
+    }
+
+    fn is_last_component(&self) -> bool {
+        self.path.map_or(true, |path| {
+            path[self.path_position.get()..self.path_len.get()]
+                .iter()
+                .all(|&c| c == b'/')
+        })
+    }
+
+    /// Number of entries in the cluster of `cursor`.
+    fn entries_in(&self, geometry: &Geometry, cursor: &DirCursor) -> u32 {
+        if cursor.cluster == 0 {
+            geometry.root_entries
+        } else {
+            geometry.sectors_per_cluster * ENTRIES_PER_SECTOR
+        }
+    }
+
+    fn entry_location(&self, geometry: &Geometry, cursor: &DirCursor) -> EntryRef {
+        let first = if cursor.cluster == 0 {
+            geometry.root_start
+        } else {
+            geometry.cluster_sector(cursor.cluster)
+        };
+        EntryRef {
+            sector: first + cursor.index / ENTRIES_PER_SECTOR,
+            offset: (cursor.index % ENTRIES_PER_SECTOR) as usize * DIR_ENTRY_SIZE,
+        }
+    }
+
+    fn lookup_step(&self, geometry: &Geometry, target: Target, mut lookup: Lookup) -> Step<bool> {
+        if lookup.cursor.index == self.entries_in(geometry, &lookup.cursor) {
+            // Continue in the next cluster of the directory.
+            let next = if lookup.cursor.cluster == 0 {
+                0
+            } else {
+                self.fat_entry(geometry, lookup.cursor.cluster)?
+            };
+            if geometry.is_end_of_chain(next) {
+                return self.not_found(target, lookup);
+            }
+            lookup.cursor.cluster = next;
+            lookup.cursor.index = 0;
+            self.state.set(State::Lookup { target, lookup });
+            return Ok(true);
+        }
+
+        let location = self.entry_location(geometry, &lookup.cursor);
+        let name = lookup.name;
+        let entry = self.sector(location.sector, |sector| {
+            let entry = DirEntry(&sector[location.offset..location.offset + DIR_ENTRY_SIZE]);
+            match entry.0[0] {
+                ENTRY_END => Scanned::End,
+                ENTRY_FREE => Scanned::Free,
+                _ if entry.is_named() && entry.name() == name => Scanned::Match {
+                    directory: entry.is_directory(),
+                    cluster: entry.first_cluster(),
+                    size: entry.size(),
+                },
+                _ => Scanned::Other,
+            }
+        })?;
+        match entry {
+            Scanned::Match {
+                directory: true,
+                cluster,
+                ..
+            } => {
+                if self.is_last_component() {
+                    return match target {
+                        Target::Directory => {
+                            self.state.set(State::Done(Outcome::DirectoryCreated));
+                            Ok(true)
+                        }
+                        Target::File { .. } => Err(Pending::Failed(ErrorCode::INVAL)),
+                    };
+                }
+                self.state.set(State::Descend {
+                    target,
+                    directory: cluster,
+                });
+                Ok(true)
+            }
+            Scanned::Match { cluster, size, .. } => match target {
+                Target::File { flags } if self.is_last_component() => {
+                    self.open_file(flags, location, cluster, size)
+                }
+                _ => Err(Pending::Failed(ErrorCode::INVAL)),
+            },
+            Scanned::End => {
+                // All entries from here on are free.
+                lookup.free = lookup.free.or(Some(location));
+                self.not_found(target, lookup)
+            }
+            Scanned::Free | Scanned::Other => {
+                if let Scanned::Free = entry {
+                    lookup.free = lookup.free.or(Some(location));
+                }
+                lookup.cursor.index += 1;
+                self.state.set(State::Lookup { target, lookup });
+                Ok(true)
+            }
+        }
+    }
+
+    /// The name is not in the directory, so create it if allowed.
+    fn not_found(&self, target: Target, lookup: Lookup) -> Step<bool> {
+        let step = match target {
+            Target::File { flags } if flags & open_flags::CREATE != 0 => {
+                if !self.is_last_component() {
+                    return Err(Pending::Failed(ErrorCode::INVAL));
+                }
+                CreateStep::WriteEntry { cluster: 0 }
+            }
+            Target::File { .. } => return Err(Pending::Failed(ErrorCode::INVAL)),
+            Target::Directory => CreateStep::AllocateDirectory,
+        };
+        let step = if lookup.free.is_some() {
+            step
+        } else if lookup.cursor.cluster == 0 {
+            // The FAT16 root directory cannot grow.
+            return Err(Pending::Failed(ErrorCode::NOMEM));
+        } else {
+            CreateStep::ExtendDirectory
+        };
+        self.state.set(State::Create {
+            target,
+            lookup,
+            step,
+        });
+        Ok(true)
+    }
+
+    fn create_step(
+        &self,
+        geometry: &Geometry,
+        target: Target,
+        mut lookup: Lookup,
+        step: CreateStep,
+    ) -> Step<bool> {
+        let step = match step {
+            CreateStep::ExtendDirectory => {
+                let cluster = self.allocate(geometry, lookup.cursor.cluster)?;
+                CreateStep::ClearExtension {
+                    cluster,
+                    remaining: geometry.sectors_per_cluster,
+                }
+            }
+            CreateStep::ClearExtension { cluster, remaining } if remaining > 0 => {
+                // Clear the first sector last, so that it is cached for the
+                // new entry.
+                self.clear(geometry.cluster_sector(cluster) + remaining - 1)?;
+                CreateStep::ClearExtension {
+                    cluster,
+                    remaining: remaining - 1,
+                }
+            }
+            CreateStep::ClearExtension { cluster, .. } => {
+                lookup.free = Some(EntryRef {
+                    sector: geometry.cluster_sector(cluster),
+                    offset: 0,
+                });
+                match target {
+                    Target::File { .. } => CreateStep::WriteEntry { cluster: 0 },
+                    Target::Directory => CreateStep::AllocateDirectory,
+                }
+            }
+            CreateStep::AllocateDirectory => {
+                let cluster = self.allocate(geometry, 0)?;
+                CreateStep::ClearDirectory {
+                    cluster,
+                    remaining: geometry.sectors_per_cluster,
+                }
+            }
+            CreateStep::ClearDirectory { cluster, remaining } if remaining > 0 => {
+                self.clear(geometry.cluster_sector(cluster) + remaining - 1)?;
+                CreateStep::ClearDirectory {
+                    cluster,
+                    remaining: remaining - 1,
+                }
+            }
+            CreateStep::ClearDirectory { cluster, .. } => CreateStep::WriteDots { cluster },
+            CreateStep::WriteDots { cluster } => {
+                // `..` refers to the root directory as cluster 0.
+                let parent = if lookup.cursor.start == geometry.root_cluster {
+                    0
+                } else {
+                    lookup.cursor.start
+                };
+                self.modify_sector(geometry.cluster_sector(cluster), |sector| {
+                    layout::write_dot_entries(sector, cluster, parent)
+                })?;
+                CreateStep::WriteEntry { cluster }
+            }
+            CreateStep::WriteEntry { cluster } => {
+                let location = lookup.free.ok_or(ErrorCode::FAIL)?;
+                let attributes = match target {
+                    Target::File { .. } => attributes::ARCHIVE,
+                    Target::Directory => attributes::DIRECTORY,
+                };
+                self.modify_sector(location.sector, |sector| {
+                    layout::write_entry(
+                        &mut sector[location.offset..],
+                        &lookup.name,
+                        attributes,
+                        cluster,
+                    )
+                })?;
+                return match target {
+                    Target::File { flags } => self.open_file(flags, location, 0, 0),
+                    Target::Directory => {
+                        self.state.set(State::Descend {
+                            target,
+                            directory: cluster,
+                        });
+                        Ok(true)
+                    }
+                };
+            }
+        };
+        self.state.set(State::Create {
+            target,
+            lookup,
+            step,
+        });
+        Ok(true)
+    }
+
+    fn open_file(&self, flags: u32, entry: EntryRef, first_cluster: u32, size: u32) -> Step<bool> {
+        if self
+            .files
+            .iter()
+            .any(|file| file.get().map_or(false, |file| file.entry == entry))
+        {
+            return Err(Pending::Failed(ErrorCode::ALREADY));
+        }
+        let slot = self
+            .files
+            .iter()
+            .position(|file| file.get().is_none())
+            .ok_or(ErrorCode::NOMEM)?;
+        self.files[slot].set(Some(File {
+            entry,
+            first_cluster,
+            size,
+            position: if flags & open_flags::APPEND != 0 {
+                size
+            } else {
+                0
+            },
+            cluster: 0,
+            cluster_index: 0,
+        }));
+        self.state.set(State::Done(Outcome::Opened(slot)));
+        Ok(true)
+    }
+
+    /// Returns the cluster holding the byte at the position of `file`,
+    /// following the chain one cluster at a time. Past the end of the chain
+    /// clusters are allocated if `extend` is set, otherwise 0 is returned.
+    fn file_cluster(&self, geometry: &Geometry, handle: FileHandle, extend: bool) -> Step<u32> {
+        loop {
+            let mut file = self.file(handle)?;
+            let index = file.position / geometry.cluster_bytes();
+            if file.cluster != 0 && file.cluster_index == index {
+                return Ok(file.cluster);
+            }
+            if file.cluster == 0 || file.cluster_index > index {
+                if file.first_cluster == 0 {
+                    if !extend {
+                        return Ok(0);
+                    }
+                    file.first_cluster = self.allocate(geometry, 0)?;
+                }
+                file.cluster = file.first_cluster;
+                file.cluster_index = 0;
+            } else {
+                // Only look at the FAT entry if no allocation is under way,
+                // as the allocation needs its own FAT sectors.
+                let next = if self.allocation.get() != Allocation::Idle {
+                    self.allocate(geometry, file.cluster)?
+                } else {
+                    match self.fat_entry(geometry, file.cluster)? {
+                        next if !geometry.is_end_of_chain(next) => next,
+                        _ if extend => self.allocate(geometry, file.cluster)?,
+                        _ => return Ok(0),
+                    }
+                };
+                file.cluster = next;
+                file.cluster_index += 1;
+            }
+            self.files[handle].set(Some(file));
+        }
+    }
+
+    /// The position of `file` as a sector and an offset in that sector.
+    fn file_sector(&self, geometry: &Geometry, cluster: u32, file: &File) -> (u32, usize) {
+        let offset = file.position % geometry.cluster_bytes();
+        (
+            geometry.cluster_sector(cluster) + offset / SECTOR_SIZE as u32,
+            offset as usize % SECTOR_SIZE,
+        )
+    }
+
+    fn read_step(
+        &self,
+        geometry: &Geometry,
+        handle: FileHandle,
+        len: usize,
+        done: usize,
+    ) -> Step<bool> {
+        let file = self.file(handle)?;
+        let cluster = if done < len && file.position < file.size {
+            self.file_cluster(geometry, handle, false)?
+        } else {
+            0
+        };
+        if cluster == 0 {
+            self.state.set(State::Done(Outcome::Read(done)));
+            return Ok(true);
+        }
+
+        let mut file = self.file(handle)?;
+        let (sector, start) = self.file_sector(geometry, cluster, &file);
+        let count = cmp::min(
+            cmp::min(SECTOR_SIZE - start, len - done),
+            (file.size - file.position) as usize,
+        );
+        self.sector(sector, |sector| {
+            self.data
+                .map(|data| data[done..done + count].copy_from_slice(&sector[start..start + count]))
+        })?;
+        file.position += count as u32;
+        self.files[handle].set(Some(file));
+        self.state.set(State::Read {
+            file: handle,
+            len,
+            done: done + count,
+        });
+        Ok(true)
+    }
+
+    fn write_step(
+        &self,
+        geometry: &Geometry,
+        handle: FileHandle,
+        len: usize,
+        done: usize,
+    ) -> Step<bool> {
+        if done == len {
+            // Record the size and first cluster in the directory entry.
+            let file = self.file(handle)?;
+            self.modify_sector(file.entry.sector, |sector| {
+                layout::set_entry_data(
+                    &mut sector[file.entry.offset..],
+                    file.first_cluster,
+                    file.size,
+                )
+            })?;
+            self.state.set(State::Done(Outcome::Written(done)));
+            return Ok(true);
+        }
+
+        let cluster = match self.file_cluster(geometry, handle, true) {
+            Err(Pending::Failed(ErrorCode::NOMEM)) if done > 0 => {
+                // The volume is full; keep what was written.
+                self.state.set(State::Write {
+                    file: handle,
+                    len: done,
+                    done,
+                });
+                return Ok(true);
+            }
+            result => result?,
+        };
+        let mut file = self.file(handle)?;
+        let (sector, start) = self.file_sector(geometry, cluster, &file);
+        let count = cmp::min(SECTOR_SIZE - start, len - done);
+        if start == 0 && (count == SECTOR_SIZE || file.size <= file.position + count as u32) {
+            // No data of the file in the sector survives, so skip reading it.
+            self.claim(sector)?;
+        }
+        self.modify_sector(sector, |sector| {
+            self.data
+                .map(|data| sector[start..start + count].copy_from_slice(&data[done..done + count]))
+        })?;
+        file.position += count as u32;
+        file.size = cmp::max(file.size, file.position);
+        self.files[handle].set(Some(file));
+        self.state.set(State::Write {
+            file: handle,
+            len,
+            done: done + count,
+        });
+        Ok(true)
+    }
+
+    /// Allocates a free cluster and appends it to the chain ending with
+    /// `last`, or starts a new chain if `last` is 0. The caller must save the
+    /// returned cluster before accessing another sector.
+    fn allocate(&self, geometry: &Geometry, last: u32) -> Step<u32> {
+        loop {
+            match self.allocation.get() {
+                Allocation::Idle => self.allocation.set(Allocation::Scanning {
+                    next: self.next_free.get(),
+                    remaining: geometry.cluster_count,
+                }),
+                Allocation::Scanning { remaining: 0, .. } => {
+                    self.allocation.set(Allocation::Idle);
+                    return Err(Pending::Failed(ErrorCode::NOMEM));
+                }
+                Allocation::Scanning { next, remaining } => {
+                    let next = if geometry.is_valid_cluster(next) {
+                        next
+                    } else {
+                        2
+                    };
+                    let free = self.fat_entry(geometry, next)? == 0;
+                    self.allocation.set(if free {
+                        Allocation::Found(next)
+                    } else {
+                        Allocation::Scanning {
+                            next: next + 1,
+                            remaining: remaining - 1,
+                        }
+                    });
+                }
+                Allocation::Found(cluster) => {
+                    self.set_fat_entry(geometry, cluster, geometry.end_of_chain())?;
+                    self.allocation.set(Allocation::Marked(cluster));
+                }
+                Allocation::Marked(cluster) => {
+                    if last != 0 {
+                        self.set_fat_entry(geometry, last, cluster)?;
+                    }
+                    self.allocation.set(Allocation::Idle);
+                    self.next_free.set(cluster + 1);
+                    return Ok(cluster);
+                }
+            }
+        }
+    }
+
+    fn fat_entry(&self, geometry: &Geometry, cluster: u32) -> Step<u32> {
+        let (sector, offset) = geometry.fat_entry_location(cluster);
+        self.sector(sector, |sector| geometry.read_fat_entry(&sector[offset..]))
+    }
+
+    fn set_fat_entry(&self, geometry: &Geometry, cluster: u32, value: u32) -> Step<()> {
+        let (sector, offset) = geometry.fat_entry_location(cluster);
+        self.modify_sector(sector, |sector| {
+            geometry.write_fat_entry(&mut sector[offset..], value)
+        })
+    }
+
+    /// Runs `f` on the contents of `sector`, reading it first if it is not
+    /// cached.
+    fn sector<R>(&self, sector: u32, f: impl FnOnce(&mut [u8]) -> R) -> Step<R> {
+        if self.cached.get() != Some(sector) {
+            self.flush()?;
+            self.cached.set(None);
+            return Err(self.start_io(Io::Read(sector)));
+        }
+        self.buffer
+            .map(|buffer| f(buffer))
+            .ok_or(Pending::Failed(ErrorCode::FAIL))
+    }
+
+    /// Like `sector`, and marks the sector to be written back.
+    fn modify_sector<R>(&self, sector: u32, f: impl FnOnce(&mut [u8]) -> R) -> Step<R> {
+        let result = self.sector(sector, f)?;
+        self.dirty.set(true);
+        Ok(result)
+    }
+
+    /// Caches `sector` without reading it, for a sector whose contents will
+    /// be overwritten.
+    fn claim(&self, sector: u32) -> Step<()> {
+        if self.cached.get() != Some(sector) {
+            self.flush()?;
+            self.cached.set(Some(sector));
+        }
+        Ok(())
+    }
+
+    /// Fills `sector` with zeros.
+    fn clear(&self, sector: u32) -> Step<()> {
+        self.claim(sector)?;
+        self.modify_sector(sector, |sector| sector.fill(0))
+    }
+
+    /// Writes the cached sector back if it was modified.
+    fn flush(&self) -> Step<()> {
+        match self.cached.get() {
+            Some(sector) if self.dirty.get() => Err(self.start_io(Io::Write { sector, copy: 0 })),
+            _ => Ok(()),
+        }
+    }
+
+    fn start_io(&self, io: Io) -> Pending {
+        // The card drops the buffer of a transfer it refuses, so only start
+        // transfers it accepts.
+        if !self.sdcard.is_installed() || !self.sdcard.is_initialized() {
+            return Pending::Failed(ErrorCode::OFF);
+        }
+        let result = self
+            .buffer
+            .take()
+            .map_or(Err(ErrorCode::BUSY), |buffer| match io {
+                Io::Read(sector) => self.sdcard.read_blocks(buffer, sector, 1),
+                Io::Write { sector, copy } => {
+                    let fat_sectors = self.geometry.map_or(0, |geometry| geometry.fat_sectors);
+                    self.sdcard
+                        .write_blocks(buffer, sector + copy * fat_sectors, 1)
+                }
+                Io::Idle => {
+                    self.buffer.replace(buffer);
+                    Ok(())
+                }
+            });
+        match result {
+            Ok(()) => {
+                self.io.set(io);
+                Pending::Io
+            }
+            Err(error) => Pending::Failed(error),
+        }
+    }
+
+    /// A transfer failed, so the cached sector can no longer be trusted.
+    fn io_failed(&self) {
+        self.cached.set(None);
+        self.dirty.set(false);
+        self.fail(ErrorCode::FAIL);
+    }
+
+    fn report(&self, outcome: Outcome) {
+        match outcome {
+            Outcome::Mounted => self.client.map(|client| client.mounted(Ok(()))),
+            Outcome::Opened(file) => self.client.map(|client| client.opened(Ok(file))),
+            Outcome::DirectoryCreated => self.client.map(|client| client.directory_created(Ok(()))),
+            Outcome::Read(len) => self.data.take().map(|buffer| {
+                self.client
+                    .map(move |client| client.read_done(buffer, Ok(len)));
+            }),
+            Outcome::Written(len) => self.data.take().map(|buffer| {
+                self.client
+                    .map(move |client| client.write_done(buffer, Ok(len)));
+            }),
+        };
+    }
+
+    /// Ends the running operation with `error`.
+    fn fail(&self, error: ErrorCode) {
+        let state = self.state.replace(State::Idle);
+        self.allocation.set(Allocation::Idle);
+        let outcome = match state {
+            State::Idle => return,
+            State::Mount { .. } | State::InvalidateFsInfo => Outcome::Mounted,
+            State::Descend { target, .. }
+            | State::Lookup { target, .. }
+            | State::Create { target, .. } => match target {
+                Target::File { .. } => Outcome::Opened(0),
+                Target::Directory => Outcome::DirectoryCreated,
+            },
+            State::Read { .. } => Outcome::Read(0),
+            State::Write { .. } => Outcome::Written(0),
+            State::Done(outcome) => outcome,
+        };
+        match outcome {
+            Outcome::Mounted => {
+                self.geometry.clear();
+                self.client.map(|client| client.mounted(Err(error)));
+            }
+            Outcome::Opened(file) => {
+                if let State::Done(_) = state {
+                    self.files[file].set(None);
+                }
+                self.client.map(|client| client.opened(Err(error)));
+            }
+            Outcome::DirectoryCreated => {
+                self.client
+                    .map(|client| client.directory_created(Err(error)));
+            }
+            Outcome::Read(_) => {
+                self.data.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.read_done(buffer, Err(error)));
+                });
+            }
+            Outcome::Written(_) => {
+                self.data.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.write_done(buffer, Err(error)));
+                });
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> DynamicDeferredCallClient for FatFs<'a, A> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.run();
+    }
+}
+
+impl<'a, A: Alarm<'a>> SDCardClient for FatFs<'a, A> {
+    fn card_detection_changed(&self, installed: bool) {
+        if installed {
+            // The volume is mounted once the card is initialized.
+            let _ = self.sdcard.initialize();
+        } else {
+            // A running transfer fails on its own, which ends the operation.
+            if self.io.get() == Io::Idle {
+                self.fail(ErrorCode::NODEVICE);
+            }
+            self.close_all();
+            self.client.map(|client| client.unmounted());
+        }
+    }
+
+    fn init_done(&self, _block_size: u32, _total_size: u64) {
+        self.mount();
+    }
+
+    fn read_done(&self, data: &'static mut [u8], len: usize) {
+        self.buffer.replace(data);
+        match self.io.replace(Io::Idle) {
+            Io::Read(sector) if len >= SECTOR_SIZE => {
+                self.cached.set(Some(sector));
+                self.dirty.set(false);
+                self.run();
+            }
+            _ => self.io_failed(),
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8]) {
+        self.buffer.replace(buffer);
+        if let Io::Write { sector, copy } = self.io.replace(Io::Idle) {
+            let copies = self.geometry.map_or(1, |geometry| {
+                if geometry.is_fat_sector(sector) {
+                    geometry.fat_count
+                } else {
+                    1
+                }
+            });
+            if copy + 1 < copies {
+                if let Pending::Failed(_) = self.start_io(Io::Write {
+                    sector,
+                    copy: copy + 1,
+                }) {
+                    self.io_failed();
+                }
+                return;
+            }
+        }
+        self.dirty.set(false);
+        self.run();
+    }
+
+    fn error(&self, _error: u32) {
+        self.sdcard
+            .take_failed_buffer()
+            .map(|buffer| self.buffer.replace(buffer));
+        if self.io.replace(Io::Idle) != Io::Idle {
+            self.io_failed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use kernel::dynamic_deferred_call::DynamicDeferredCallClientState;
+    use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+    use kernel::hil::time::{AlarmClient, Freq1MHz, Ticks32, Time};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    const DATA_TOKEN: u8 = 0xFE;
+
+    /// FAT16 volume without a partition table: 1 sector clusters, 1 reserved
+    /// sector, two FATs of 17 sectors and a root directory of 16 entries.
+    const FAT_START: usize = 1;
+    const FAT_SECTORS: usize = 17;
+    const ROOT_START: usize = FAT_START + 2 * FAT_SECTORS;
+    const DATA_START: usize = ROOT_START + 1;
+    const CLUSTERS: usize = 4200;
+
+    type Transfer = (&'static mut [u8], &'static mut [u8], usize);
+
+    /// An SD card on the bus, holding the sectors of `disk`. Transfers
+    /// complete when `complete` is called.
+    struct FakeCard {
+        client: OptionalCell<&'static dyn SpiMasterClient>,
+        transfer: Cell<Option<Transfer>>,
+        disk: RefCell<Vec<u8>>,
+        /// The sector of a running single block read or write.
+        block: Cell<Option<u32>>,
+        /// Whether a data block was just written, so the data response is
+        /// due.
+        written: Cell<bool>,
+    }
+
+    impl FakeCard {
+        fn complete(&self) -> bool {
+            let (write, read, len) = match self.transfer.take() {
+                Some(transfer) => transfer,
+                None => return false,
+            };
+            read[..len].fill(0xFF);
+            if len == SECTOR_SIZE + 3 && write[0] == DATA_TOKEN {
+                let sector = self.block.take().unwrap() as usize;
+                self.disk.borrow_mut()[sector * SECTOR_SIZE..][..SECTOR_SIZE]
+                    .copy_from_slice(&write[1..SECTOR_SIZE + 1]);
+                self.written.set(true);
+            } else if len == SECTOR_SIZE + 2 {
+                let sector = self.block.take().unwrap() as usize;
+                read[..SECTOR_SIZE]
+                    .copy_from_slice(&self.disk.borrow()[sector * SECTOR_SIZE..][..SECTOR_SIZE]);
+            } else if len == 1 {
+                read[0] = if self.block.get().is_some() {
+                    DATA_TOKEN
+                } else if self.written.take() {
+                    0x05
+                } else {
+                    0xFF
+                };
+            } else {
+                // A command; the response follows the 8 command bytes.
+                let arg = u32::from_be_bytes([write[3], write[4], write[5], write[6]]);
+                let response: &[u8] = match write[2] & 0x3F {
+                    0 | 55 => &[0x01],
+                    8 => &[0x01, 0, 0, 0x01, 0xAA],
+                    // Block addressed, with a version 2 CSD.
+                    58 => &[0x00, 0x40, 0, 0, 0],
+                    9 => &[0x00, DATA_TOKEN, 0x40],
+                    17 | 24 => {
+                        self.block.set(Some(arg));
+                        &[0x00]
+                    }
+                    _ => &[0x00],
+                };
+                read[8..8 + response.len()].copy_from_slice(response);
+            }
+            self.client
+                .map(move |client| client.read_write_done(write, Some(read), len, Ok(())));
+            true
+        }
+
+        fn sector(&self, sector: usize) -> Vec<u8> {
+            self.disk.borrow()[sector * SECTOR_SIZE..][..SECTOR_SIZE].to_vec()
+        }
+
+        fn fat_entry(&self, copy: usize, cluster: usize) -> u16 {
+            let offset = (FAT_START + copy * FAT_SECTORS) * SECTOR_SIZE + cluster * 2;
+            let disk = self.disk.borrow();
+            u16::from_le_bytes([disk[offset], disk[offset + 1]])
+        }
+
+        fn set_fat_entry(&self, cluster: usize, value: u16) {
+            for copy in 0..2 {
+                let offset = (FAT_START + copy * FAT_SECTORS) * SECTOR_SIZE + cluster * 2;
+                self.disk.borrow_mut()[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    impl SpiMasterDevice for FakeCard {
+        fn set_client(&self, client: &'static dyn SpiMasterClient) {
+            self.client.set(client);
+        }
+
+        fn configure(
+            &self,
+            _cpol: ClockPolarity,
+            _cpal: ClockPhase,
+            _rate: u32,
+        ) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn read_write_bytes(
+            &self,
+            write_buffer: &'static mut [u8],
+            read_buffer: Option<&'static mut [u8]>,
+            len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8], Option<&'static mut [u8]>)> {
+            match read_buffer {
+                Some(read_buffer) => {
+                    self.transfer.set(Some((write_buffer, read_buffer, len)));
+                    Ok(())
+                }
+                None => Err((ErrorCode::INVAL, write_buffer, None)),
+            }
+        }
+
+        fn set_rate(&self, _rate: u32) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_rate(&self) -> u32 {
+            400_000
+        }
+
+        fn set_polarity(&self, _polarity: ClockPolarity) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_polarity(&self) -> ClockPolarity {
+            ClockPolarity::IdleLow
+        }
+
+        fn set_phase(&self, _phase: ClockPhase) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_phase(&self) -> ClockPhase {
+            ClockPhase::SampleLeading
+        }
+    }
+
+    struct FakeAlarm;
+
+    impl Time for FakeAlarm {
+        type Ticks = Ticks32;
+        type Frequency = Freq1MHz;
+
+        fn now(&self) -> Ticks32 {
+            0u32.into()
+        }
+    }
+
+    impl<'a> Alarm<'a> for FakeAlarm {
+        fn set_alarm_client(&self, _client: &'a dyn AlarmClient) {}
+
+        fn set_alarm(&self, _reference: Ticks32, _dt: Ticks32) {}
+
+        fn get_alarm(&self) -> Ticks32 {
+            0u32.into()
+        }
+
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn is_armed(&self) -> bool {
+            false
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            1u32.into()
+        }
+    }
+
+    /// Records the results of operations and keeps the returned buffers.
+    struct Results {
+        mounted: Cell<Option<Result<(), ErrorCode>>>,
+        opened: Cell<Option<Result<FileHandle, ErrorCode>>>,
+        directory_created: Cell<Option<Result<(), ErrorCode>>>,
+        done: Cell<Option<Result<usize, ErrorCode>>>,
+        buffer: TakeCell<'static, [u8]>,
+    }
+
+    impl Client for Results {
+        fn mounted(&self, result: Result<(), ErrorCode>) {
+            self.mounted.set(Some(result));
+        }
+
+        fn unmounted(&self) {}
+
+        fn opened(&self, result: Result<FileHandle, ErrorCode>) {
+            self.opened.set(Some(result));
+        }
+
+        fn directory_created(&self, result: Result<(), ErrorCode>) {
+            self.directory_created.set(Some(result));
+        }
+
+        fn read_done(&self, buffer: &'static mut [u8], result: Result<usize, ErrorCode>) {
+            self.buffer.replace(buffer);
+            self.done.set(Some(result));
+        }
+
+        fn write_done(&self, buffer: &'static mut [u8], result: Result<usize, ErrorCode>) {
+            self.buffer.replace(buffer);
+            self.done.set(Some(result));
+        }
+    }
+
+    struct Setup {
+        card: &'static FakeCard,
+        results: &'static Results,
+        fat: &'static FatFs<'static, FakeAlarm>,
+    }
+
+    impl Setup {
+        /// Runs the operation that was started until it completed.
+        fn run(&self) {
+            self.fat.run();
+            while self.card.complete() {}
+        }
+
+        fn open(&self, path: &[u8], flags: u32) -> FileHandle {
+            assert_eq!(self.fat.open(path, flags), Ok(()));
+            self.run();
+            self.results.opened.take().unwrap().unwrap()
+        }
+
+        fn write(&self, file: FileHandle, data: &[u8]) -> Result<usize, ErrorCode> {
+            let buffer = Box::leak(data.to_vec().into_boxed_slice());
+            assert!(self.fat.write(file, buffer, data.len()).is_ok());
+            self.run();
+            self.results.done.take().unwrap()
+        }
+
+        fn read(&self, file: FileHandle, len: usize) -> Vec<u8> {
+            let buffer = Box::leak(std::vec![0; len].into_boxed_slice());
+            assert!(self.fat.read(file, buffer, len).is_ok());
+            self.run();
+            let read = self.results.done.take().unwrap().unwrap();
+            self.results.buffer.take().unwrap()[..read].to_vec()
+        }
+    }
+
+    /// A freshly formatted volume, after `modify` ran on the card.
+    fn mount(modify: impl FnOnce(&FakeCard)) -> Setup {
+        let mut disk = std::vec![0; (DATA_START + CLUSTERS) * SECTOR_SIZE];
+        let boot_sector = &mut disk[..SECTOR_SIZE];
+        boot_sector[0] = 0xEB;
+        boot_sector[11..13].copy_from_slice(&512u16.to_le_bytes());
+        boot_sector[13] = 1;
+        boot_sector[14..16].copy_from_slice(&(FAT_START as u16).to_le_bytes());
+        boot_sector[16] = 2;
+        boot_sector[17..19].copy_from_slice(&16u16.to_le_bytes());
+        boot_sector[22..24].copy_from_slice(&(FAT_SECTORS as u16).to_le_bytes());
+        boot_sector[32..36].copy_from_slice(&((DATA_START + CLUSTERS) as u32).to_le_bytes());
+        boot_sector[510] = 0x55;
+        boot_sector[511] = 0xAA;
+        let card = Box::leak(Box::new(FakeCard {
+            client: OptionalCell::empty(),
+            transfer: Cell::new(None),
+            disk: RefCell::new(disk),
+            block: Cell::new(None),
+            written: Cell::new(false),
+        }));
+        card.set_fat_entry(0, 0xFFF8);
+        card.set_fat_entry(1, 0xFFFF);
+        modify(card);
+
+        let sdcard = Box::leak(Box::new(SDCard::new(
+            card,
+            Box::leak(Box::new(FakeAlarm)),
+            None,
+            Box::leak(Box::new([0; 515])),
+            Box::leak(Box::new([0; 515])),
+        )));
+        card.set_client(sdcard);
+        let deferred_caller = Box::leak(Box::new(DynamicDeferredCall::new(Box::leak(Box::new([
+            DynamicDeferredCallClientState::default(),
+        ])))));
+        let fat = Box::leak(Box::new(FatFs::new(
+            sdcard,
+            deferred_caller,
+            Box::leak(Box::new([0; SECTOR_SIZE])),
+        )));
+        sdcard.set_client(fat);
+        let results = Box::leak(Box::new(Results {
+            mounted: Cell::new(None),
+            opened: Cell::new(None),
+            directory_created: Cell::new(None),
+            done: Cell::new(None),
+            buffer: TakeCell::empty(),
+        }));
+        fat.set_client(results);
+
+        let setup = Setup { card, results, fat };
+        fat.start();
+        setup.run();
+        assert_eq!(results.mounted.get(), Some(Ok(())));
+        setup
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_write_read_back() {
+        let setup = mount(|_| {});
+        let file = setup.open(b"/log.txt", open_flags::CREATE);
+        let written = data(1300);
+        assert_eq!(setup.write(file, &written), Ok(1300));
+        assert_eq!(setup.fat.close(file), Ok(()));
+
+        // The directory entry and the data are on the card.
+        let root = setup.card.sector(ROOT_START);
+        let entry = DirEntry(&root[..DIR_ENTRY_SIZE]);
+        assert_eq!(entry.name(), b"LOG     TXT");
+        assert_eq!((entry.first_cluster(), entry.size()), (2, 1300));
+        assert_eq!(setup.card.sector(DATA_START)[..], written[..SECTOR_SIZE]);
+
+        // Opening without `CREATE` finds it again, matching without regard
+        // to case, and reads stop at the end of the file.
+        assert_eq!(setup.fat.open(b"/missing.txt", 0), Ok(()));
+        setup.run();
+        assert_eq!(setup.results.opened.take(), Some(Err(ErrorCode::INVAL)));
+        let file = setup.open(b"/LOG.TXT", 0);
+        assert_eq!(setup.fat.size(file), Ok(1300));
+        assert_eq!(setup.read(file, 2000), written);
+        assert_eq!(setup.read(file, 10), []);
+    }
+
+    #[test]
+    fn test_chain_allocation() {
+        // Cluster 3 belongs to another file.
+        let setup = mount(|card| card.set_fat_entry(3, 0xFFFF));
+        let file = setup.open(b"/data.bin", open_flags::CREATE);
+        assert_eq!(setup.write(file, &data(1024)), Ok(1024));
+        assert_eq!(setup.fat.close(file), Ok(()));
+
+        // Appending continues after the last cluster of the chain.
+        let file = setup.open(b"/data.bin", open_flags::APPEND);
+        assert_eq!(setup.write(file, &data(100)), Ok(100));
+        for copy in 0..2 {
+            let chain: Vec<u16> = (2..6).map(|c| setup.card.fat_entry(copy, c)).collect();
+            assert_eq!(chain, [4, 0xFFFF, 5, 0xFFFF], "FAT {}", copy);
+        }
+        assert_eq!(setup.card.sector(DATA_START + 3)[..100], data(100)[..]);
+
+        // Reading follows the chain past the used cluster.
+        assert_eq!(setup.fat.close(file), Ok(()));
+        let file = setup.open(b"/data.bin", 0);
+        let mut expected = data(1024);
+        expected.extend(data(100));
+        assert_eq!(setup.read(file, 2000), expected);
+    }
+
+    #[test]
+    fn test_create_in_directory() {
+        let setup = mount(|_| {});
+        assert_eq!(setup.fat.create_dir(b"/logs"), Ok(()));
+        setup.run();
+        assert_eq!(setup.results.directory_created.get(), Some(Ok(())));
+
+        // The directory got a cluster of its own, starting with `.` and `..`.
+        let root = setup.card.sector(ROOT_START);
+        let entry = DirEntry(&root[..DIR_ENTRY_SIZE]);
+        assert!(entry.is_directory());
+        assert_eq!(entry.first_cluster(), 2);
+        assert_eq!(setup.card.fat_entry(0, 2), 0xFFFF);
+        let directory = setup.card.sector(DATA_START);
+        assert_eq!(
+            DirEntry(&directory[..DIR_ENTRY_SIZE]).name(),
+            b".          "
+        );
+
+        let file = setup.open(b"/logs/today.txt", open_flags::CREATE);
+        assert_eq!(setup.write(file, b"hello"), Ok(5));
+        let directory = setup.card.sector(DATA_START);
+        let entry = DirEntry(&directory[2 * DIR_ENTRY_SIZE..3 * DIR_ENTRY_SIZE]);
+        assert_eq!(entry.name(), b"TODAY   TXT");
+        assert_eq!((entry.first_cluster(), entry.size()), (3, 5));
+        assert_eq!(setup.card.sector(DATA_START + 1)[..5], b"hello"[..]);
+    }
+}
@@ -0,0 +1,446 @@
+//! On-disk structures of FAT16 and FAT32 volumes.
+//!
+//! Only the parts needed to find, read, create and extend files and
+//! directories are decoded. FAT12 volumes are not supported, as they only
+//! occur on media too small to matter.
+//!
+//! Microsoft Extensible Firmware Initiative FAT32 File System Specification,
+//! version 1.03
+
+use core::convert::TryInto;
+
+/// Size of a sector. Volumes with other sector sizes are not supported.
+pub const SECTOR_SIZE: usize = 512;
+/// Size of a directory entry.
+pub const DIR_ENTRY_SIZE: usize = 32;
+/// Number of directory entries in a sector.
+pub const ENTRIES_PER_SECTOR: u32 = (SECTOR_SIZE / DIR_ENTRY_SIZE) as u32;
+/// Length of a short name, 8 characters of name and 3 of extension.
+pub const NAME_LEN: usize = 11;
+
+/// Attributes of a directory entry.
+pub mod attributes {
+    pub const VOLUME_ID: u8 = 0x08;
+    pub const DIRECTORY: u8 = 0x10;
+    pub const ARCHIVE: u8 = 0x20;
+    /// Entries with all of these attributes hold a part of a long name.
+    pub const LONG_NAME: u8 = 0x0F;
+}
+
+/// First byte of the name of a deleted entry.
+pub const ENTRY_FREE: u8 = 0xE5;
+/// First byte of the name of the entry that ends the directory.
+pub const ENTRY_END: u8 = 0x00;
+
+/// Date stamped on new entries, 1980-01-01, as there is no real time clock.
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FatType {
+    Fat16,
+    Fat32,
+}
+
+/// Location and size of the regions of a volume. All sectors are absolute
+/// sector numbers on the card.
+#[derive(Clone, Copy, Debug)]
+pub struct Geometry {
+    pub fat_type: FatType,
+    pub sectors_per_cluster: u32,
+    /// First sector of the first FAT.
+    pub fat_start: u32,
+    /// Sectors in each FAT.
+    pub fat_sectors: u32,
+    /// Number of copies of the FAT.
+    pub fat_count: u32,
+    /// First sector of the fixed root directory of FAT16 volumes.
+    pub root_start: u32,
+    /// Entries in the fixed root directory of FAT16 volumes.
+    pub root_entries: u32,
+    /// First sector of cluster 2.
+    pub data_start: u32,
+    /// Number of data clusters, numbered from 2.
+    pub cluster_count: u32,
+    /// First cluster of the root directory of FAT32 volumes, 0 for FAT16.
+    pub root_cluster: u32,
+    /// The FSInfo sector of FAT32 volumes.
+    pub fs_info: Option<u32>,
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap_or([0; 2]))
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap_or([0; 4]))
+}
+
+fn has_signature(sector: &[u8]) -> bool {
+    sector.len() >= SECTOR_SIZE && sector[510] == 0x55 && sector[511] == 0xAA
+}
+
+impl Geometry {
+    /// Decodes the boot sector `sector`, found at sector number `start`.
+    /// Returns `None` if it is not the boot sector of a FAT16 or FAT32
+    /// volume with 512 byte sectors.
+    pub fn from_boot_sector(sector: &[u8], start: u32) -> Option<Geometry> {
+        if !has_signature(sector) || (sector[0] != 0xEB && sector[0] != 0xE9) {
+            return None;
+        }
+        let bytes_per_sector = u16_at(sector, 11) as usize;
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved_sectors = u16_at(sector, 14) as u32;
+        let fat_count = sector[16] as u32;
+        let root_entries = u16_at(sector, 17) as u32;
+        let total_sectors = match u16_at(sector, 19) {
+            0 => u32_at(sector, 32),
+            total => total as u32,
+        };
+        let fat_sectors = match u16_at(sector, 22) {
+            0 => u32_at(sector, 36),
+            sectors => sectors as u32,
+        };
+        if bytes_per_sector != SECTOR_SIZE
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+            || fat_sectors == 0
+        {
+            return None;
+        }
+
+        let root_sectors = (root_entries + ENTRIES_PER_SECTOR - 1) / ENTRIES_PER_SECTOR;
+        let data_offset = reserved_sectors
+            .checked_add(fat_count.checked_mul(fat_sectors)?)?
+            .checked_add(root_sectors)?;
+        let cluster_count = total_sectors.checked_sub(data_offset)? / sectors_per_cluster;
+        let fat_type = if cluster_count < 4085 {
+            return None;
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+        let (root_cluster, fs_info) = match fat_type {
+            FatType::Fat16 => (0, None),
+            FatType::Fat32 => {
+                let fs_info = u16_at(sector, 48) as u32;
+                (
+                    u32_at(sector, 44),
+                    if fs_info == 0 || fs_info == 0xFFFF {
+                        None
+                    } else {
+                        Some(start + fs_info)
+                    },
+                )
+            }
+        };
+
+        let geometry = Geometry {
+            fat_type,
+            sectors_per_cluster,
+            fat_start: start + reserved_sectors,
+            fat_sectors,
+            fat_count,
+            root_start: start + reserved_sectors + fat_count * fat_sectors,
+            root_entries,
+            data_start: start + data_offset,
+            cluster_count,
+            root_cluster,
+            fs_info,
+        };
+        // The FAT must have an entry for every cluster.
+        let fat_entries =
+            fat_sectors as u64 * SECTOR_SIZE as u64 / geometry.fat_entry_size() as u64;
+        if fat_entries < cluster_count as u64 + 2
+            || (fat_type == FatType::Fat32 && !geometry.is_valid_cluster(root_cluster))
+        {
+            return None;
+        }
+        Some(geometry)
+    }
+
+    fn fat_entry_size(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => 2,
+            FatType::Fat32 => 4,
+        }
+    }
+
+    pub fn cluster_bytes(&self) -> u32 {
+        self.sectors_per_cluster * SECTOR_SIZE as u32
+    }
+
+    /// Whether `cluster` is a data cluster of the volume.
+    pub fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
+    }
+
+    /// The first sector of `cluster`.
+    pub fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    /// The sector of the first FAT holding the entry of `cluster`, and the
+    /// offset of the entry in that sector.
+    pub fn fat_entry_location(&self, cluster: u32) -> (u32, usize) {
+        let offset = cluster * self.fat_entry_size();
+        (
+            self.fat_start + offset / SECTOR_SIZE as u32,
+            offset as usize % SECTOR_SIZE,
+        )
+    }
+
+    /// Whether `sector` is in the first FAT, whose sectors must be copied to
+    /// the other FATs when they are written.
+    pub fn is_fat_sector(&self, sector: u32) -> bool {
+        sector >= self.fat_start && sector - self.fat_start < self.fat_sectors
+    }
+
+    /// Decodes the FAT entry at the start of `entry`.
+    pub fn read_fat_entry(&self, entry: &[u8]) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => u16_at(entry, 0) as u32,
+            FatType::Fat32 => u32_at(entry, 0) & 0x0FFF_FFFF,
+        }
+    }
+
+    /// Encodes `value` in the FAT entry at the start of `entry`.
+    pub fn write_fat_entry(&self, entry: &mut [u8], value: u32) {
+        match self.fat_type {
+            FatType::Fat16 => entry[0..2].copy_from_slice(&(value as u16).to_le_bytes()),
+            FatType::Fat32 => {
+                // The upper four bits are reserved and must be preserved.
+                let value = (u32_at(entry, 0) & 0xF000_0000) | (value & 0x0FFF_FFFF);
+                entry[0..4].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    /// The FAT entry value that ends a cluster chain.
+    pub fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    /// Whether the FAT entry `value` does not point to a next cluster.
+    /// Bad cluster marks and corrupt entries end the chain as well.
+    pub fn is_end_of_chain(&self, value: u32) -> bool {
+        !self.is_valid_cluster(value)
+    }
+}
+
+/// Returns the first sector of the first FAT16 or FAT32 partition in the
+/// master boot record `sector`.
+pub fn partition_start(sector: &[u8]) -> Option<u32> {
+    if !has_signature(sector) {
+        return None;
+    }
+    (0..4).find_map(|partition| {
+        let entry = &sector[446 + partition * 16..462 + partition * 16];
+        match entry[4] {
+            // FAT16, FAT16 with LBA, FAT32 and FAT32 with LBA
+            0x04 | 0x06 | 0x0E | 0x0B | 0x0C => Some(u32_at(entry, 8)),
+            _ => None,
+        }
+    })
+}
+
+/// Marks the free cluster count of the FAT32 FSInfo sector `sector` as
+/// unknown, as it is not kept up to date. Returns `false` if `sector` is not
+/// an FSInfo sector or the count is already unknown.
+pub fn invalidate_free_count(sector: &mut [u8]) -> bool {
+    if u32_at(sector, 0) != 0x4161_5252
+        || u32_at(sector, 484) != 0x6141_7272
+        || u32_at(sector, 488) == 0xFFFF_FFFF
+    {
+        return false;
+    }
+    sector[488..492].copy_from_slice(&[0xFF; 4]);
+    true
+}
+
+/// Converts a path component such as `log.txt` to the short name stored in
+/// directory entries, `LOG     TXT`. Returns `None` if the component is not
+/// a valid short name. Long names are not supported.
+pub fn short_name(component: &[u8]) -> Option<[u8; NAME_LEN]> {
+    let (base, extension) = match component.iter().position(|&c| c == b'.') {
+        Some(dot) => (&component[..dot], &component[dot + 1..]),
+        None => (component, &[][..]),
+    };
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+    let mut name = [b' '; NAME_LEN];
+    for (i, &c) in base.iter().enumerate() {
+        name[i] = short_name_char(c)?;
+    }
+    for (i, &c) in extension.iter().enumerate() {
+        name[8 + i] = short_name_char(c)?;
+    }
+    Some(name)
+}
+
+fn short_name_char(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' | b'0'..=b'9' => Some(c),
+        b'a'..=b'z' => Some(c.to_ascii_uppercase()),
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'(' | b')' | b'-' | b'@' | b'^' | b'_'
+        | b'`' | b'{' | b'}' | b'~' => Some(c),
+        _ => None,
+    }
+}
+
+/// Accessors for a 32 byte directory entry.
+pub struct DirEntry<'b>(pub &'b [u8]);
+
+impl DirEntry<'_> {
+    pub fn name(&self) -> &[u8] {
+        &self.0[0..NAME_LEN]
+    }
+
+    pub fn attributes(&self) -> u8 {
+        self.0[11]
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes() & attributes::DIRECTORY != 0
+    }
+
+    /// Whether the entry names a file or directory, rather than being free,
+    /// a part of a long name or the volume label.
+    pub fn is_named(&self) -> bool {
+        self.0[0] != ENTRY_FREE
+            && self.0[0] != ENTRY_END
+            && self.attributes() & attributes::LONG_NAME != attributes::LONG_NAME
+            && self.attributes() & attributes::VOLUME_ID == 0
+    }
+
+    pub fn first_cluster(&self) -> u32 {
+        (u16_at(self.0, 20) as u32) << 16 | u16_at(self.0, 26) as u32
+    }
+
+    pub fn size(&self) -> u32 {
+        u32_at(self.0, 28)
+    }
+}
+
+/// Fills `entry` with a new entry named `name`.
+pub fn write_entry(entry: &mut [u8], name: &[u8; NAME_LEN], attributes: u8, first_cluster: u32) {
+    entry[..DIR_ENTRY_SIZE].fill(0);
+    entry[0..NAME_LEN].copy_from_slice(name);
+    entry[11] = attributes;
+    // Creation, access and modification dates
+    entry[16..18].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    entry[18..20].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    entry[24..26].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    set_entry_data(entry, first_cluster, 0);
+}
+
+/// Updates the first cluster and size of the file of `entry`.
+pub fn set_entry_data(entry: &mut [u8], first_cluster: u32, size: u32) {
+    entry[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+/// Fills the first sector of a new directory with the `.` and `..` entries.
+/// `parent` is 0 if the parent is the root directory.
+pub fn write_dot_entries(sector: &mut [u8], cluster: u32, parent: u32) {
+    let mut name = [b' '; NAME_LEN];
+    name[0] = b'.';
+    write_entry(&mut sector[0..], &name, attributes::DIRECTORY, cluster);
+    name[1] = b'.';
+    write_entry(
+        &mut sector[DIR_ENTRY_SIZE..],
+        &name,
+        attributes::DIRECTORY,
+        parent,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Boot sector of a 32 MiB FAT16 volume: 4 sector clusters, 4 reserved
+    /// sectors, two FATs of 64 sectors and 512 root entries.
+    fn fat16_boot_sector() -> [u8; SECTOR_SIZE] {
+        let mut sector = [0; SECTOR_SIZE];
+        sector[0] = 0xEB;
+        sector[11..13].copy_from_slice(&512u16.to_le_bytes());
+        sector[13] = 4;
+        sector[14..16].copy_from_slice(&4u16.to_le_bytes());
+        sector[16] = 2;
+        sector[17..19].copy_from_slice(&512u16.to_le_bytes());
+        sector[22..24].copy_from_slice(&64u16.to_le_bytes());
+        sector[32..36].copy_from_slice(&65536u32.to_le_bytes());
+        sector[510] = 0x55;
+        sector[511] = 0xAA;
+        sector
+    }
+
+    #[test]
+    fn test_fat16_geometry() {
+        let geometry = Geometry::from_boot_sector(&fat16_boot_sector(), 100).unwrap();
+        assert_eq!(geometry.fat_type, FatType::Fat16);
+        assert_eq!(geometry.fat_start, 104);
+        assert_eq!(geometry.root_start, 232);
+        assert_eq!(geometry.data_start, 264);
+        assert_eq!(geometry.cluster_count, 16343);
+        assert_eq!(geometry.cluster_sector(2), 264);
+    }
+
+    #[test]
+    fn test_fat32_geometry() {
+        let mut sector = [0; SECTOR_SIZE];
+        sector[0] = 0xE9;
+        sector[11..13].copy_from_slice(&512u16.to_le_bytes());
+        sector[13] = 8;
+        sector[14..16].copy_from_slice(&32u16.to_le_bytes());
+        sector[16] = 2;
+        sector[32..36].copy_from_slice(&0x20_0000u32.to_le_bytes());
+        sector[36..40].copy_from_slice(&2048u32.to_le_bytes());
+        sector[44..48].copy_from_slice(&2u32.to_le_bytes());
+        sector[48..50].copy_from_slice(&1u16.to_le_bytes());
+        sector[510] = 0x55;
+        sector[511] = 0xAA;
+        let geometry = Geometry::from_boot_sector(&sector, 0).unwrap();
+        assert_eq!(geometry.fat_type, FatType::Fat32);
+        assert_eq!(geometry.data_start, 4128);
+        assert_eq!(geometry.cluster_count, 261628);
+        assert_eq!(geometry.root_cluster, 2);
+        assert_eq!(geometry.fs_info, Some(1));
+    }
+
+    #[test]
+    fn test_invalid_boot_sector() {
+        let mut sector = fat16_boot_sector();
+        sector[511] = 0;
+        assert!(Geometry::from_boot_sector(&sector, 0).is_none());
+
+        // Too small for its FATs
+        let mut sector = fat16_boot_sector();
+        sector[32..36].copy_from_slice(&100u32.to_le_bytes());
+        assert!(Geometry::from_boot_sector(&sector, 0).is_none());
+    }
+
+    #[test]
+    fn test_short_name() {
+        assert_eq!(short_name(b"log.txt"), Some(*b"LOG     TXT"));
+        assert_eq!(short_name(b"README"), Some(*b"README     "));
+        assert_eq!(short_name(b"123456789"), None);
+        assert_eq!(short_name(b"log.text"), None);
+        assert_eq!(short_name(b"a b"), None);
+    }
+
+    #[test]
+    fn test_short_name_dots() {
+        // Paths can't leave a directory through `.` or `..`.
+        assert_eq!(short_name(b"."), None);
+        assert_eq!(short_name(b".."), None);
+        assert_eq!(short_name(b".txt"), None);
+    }
+}
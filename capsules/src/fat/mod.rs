@@ -0,0 +1,23 @@
+//! FAT filesystem for SD cards.
+//!
+//! `filesystem::FatFs` implements FAT16 and FAT32 on top of the block
+//! interface of `capsules::sdcard`, so that data written by the board, such
+//! as logs, can be read by plugging the card into a PC. The userspace
+//! interface is `capsules::fat_driver`.
+//!
+//! ```text
+//! +------------------------------+
+//! |     fat_driver::FatDriver    |
+//! +------------------------------+
+//!         filesystem::Client
+//! +------------------------------+
+//! |     filesystem::FatFs        |
+//! +------------------------------+
+//!      sdcard::SDCardClient
+//! +------------------------------+
+//! |        sdcard::SDCard        |
+//! +------------------------------+
+//! ```
+
+pub mod filesystem;
+pub mod layout;
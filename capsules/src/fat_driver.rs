@@ -0,0 +1,443 @@
+//! Userspace access to files on a FAT formatted SD card.
+//!
+//! This driver lets applications create, read, write and append to files on
+//! the volume mounted by `capsules::fat::filesystem::FatFs`, for example to
+//! log data that is later read on a PC.
+//!
+//! Every application is confined to its own directory, `/APPS/<ID>`, where
+//! `<ID>` is the storage write ID from the `PersistentAcl` TLV of its TBF
+//! header as eight hexadecimal digits. The ID stays the same across reboots
+//! and updates of the application, so it finds its files again. Paths passed
+//! by the application, such as `logs/today.txt`, are relative to that
+//! directory and cannot leave it, as `.` and `..` are not valid names. The
+//! directory is created the first time the application opens a file or
+//! creates a directory. Applications without a write ID cannot use this
+//! driver.
+//!
+//! Only one operation runs at a time. Operations from other applications are
+//! queued and started once the current one completes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let fat_driver = static_init!(
+//!     capsules::fat_driver::FatDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::fat_driver::FatDriver::new(
+//!         fat,
+//!         static_init!([u8; 512], [0; 512]),
+//!         board_kernel.create_grant(capsules::fat_driver::DRIVER_NUM, &grant_cap),
+//!     )
+//! );
+//! fat.set_client(fat_driver);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::Alarm;
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use crate::fat::filesystem::{self, FatFs, FileHandle, MAX_OPEN_FILES, MAX_PATH_LEN};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Fat as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const PATH: usize = 0;
+    pub const WRITE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const READ: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Directory holding the directories of the applications.
+const APPS_DIRECTORY: &[u8] = b"/APPS/";
+/// Length of the path of the directory of an application.
+const ROOT_LEN: usize = APPS_DIRECTORY.len() + 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum UserSpaceOp {
+    Open { flags: u32 },
+    Read { file: FileHandle },
+    Write { file: FileHandle },
+    CreateDir,
+}
+
+#[derive(Default)]
+pub struct App {
+    /// The operation the app asked for that has not completed yet.
+    op: Option<UserSpaceOp>,
+    /// Whether the directory of the app is known to exist.
+    root_created: bool,
+}
+
+pub struct FatDriver<'a, A: Alarm<'a>> {
+    fs: &'a FatFs<'a, A>,
+    apps: Grant<
+        App,
+        UpcallCount<1>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The app whose operation is running.
+    processid: OptionalCell<ProcessId>,
+    /// Whether the running operation creates the directory of the app,
+    /// before the operation the app asked for.
+    creating_root: Cell<bool>,
+    /// The app each open file belongs to.
+    owners: [OptionalCell<ProcessId>; MAX_OPEN_FILES],
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, A: Alarm<'a>> FatDriver<'a, A> {
+    pub fn new(
+        fs: &'a FatFs<'a, A>,
+        buffer: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<1>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> FatDriver<'a, A> {
+        FatDriver {
+            fs,
+            apps: grant,
+            processid: OptionalCell::empty(),
+            creating_root: Cell::new(false),
+            owners: [
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+            ],
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    fn check_owner(&self, file: usize, processid: ProcessId) -> Result<FileHandle, ErrorCode> {
+        match self.owners.get(file) {
+            Some(owner) if owner.contains(&processid) => Ok(file),
+            _ => Err(ErrorCode::INVAL),
+        }
+    }
+
+    /// Closes the files of apps that no longer exist.
+    fn close_orphaned_files(&self) {
+        for (file, owner) in self.owners.iter().enumerate() {
+            if let Some(processid) = owner.extract() {
+                if self.apps.enter(processid, |_, _| {}).is_err() {
+                    let _ = self.fs.close(file);
+                    owner.clear();
+                }
+            }
+        }
+    }
+
+    /// Start the pending operation of `processid`.
+    fn run(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let write_id = processid
+            .get_storage_permissions()
+            .and_then(|permissions| permissions.get_write_id())
+            .ok_or(ErrorCode::NOSUPPORT)?;
+        if let Ok(Some(UserSpaceOp::Open { .. })) = self.apps.enter(processid, |app, _| app.op) {
+            self.close_orphaned_files();
+        }
+
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                let op = app.op.ok_or(ErrorCode::FAIL)?;
+
+                let mut path = [0; MAX_PATH_LEN];
+                path[..APPS_DIRECTORY.len()].copy_from_slice(APPS_DIRECTORY);
+                for (i, c) in path[APPS_DIRECTORY.len()..ROOT_LEN].iter_mut().enumerate() {
+                    *c = b"0123456789ABCDEF"[(write_id >> (28 - 4 * i)) as usize & 0xF];
+                }
+
+                match op {
+                    UserSpaceOp::Open { .. } | UserSpaceOp::CreateDir if !app.root_created => {
+                        self.creating_root.set(true);
+                        self.fs.create_dir(&path[..ROOT_LEN]).map_err(|e| {
+                            self.creating_root.set(false);
+                            e
+                        })
+                    }
+                    UserSpaceOp::Open { .. } | UserSpaceOp::CreateDir => {
+                        // Append the path of the app to its directory.
+                        path[ROOT_LEN] = b'/';
+                        let path_len = kernel_data
+                            .get_readonly_processbuffer(ro_allow::PATH)
+                            .and_then(|app_path| {
+                                app_path.enter(|app_path| {
+                                    let len = ROOT_LEN + 1 + app_path.len();
+                                    if len > path.len() {
+                                        Err(ErrorCode::SIZE)
+                                    } else {
+                                        app_path.copy_to_slice(&mut path[ROOT_LEN + 1..len]);
+                                        Ok(len)
+                                    }
+                                })
+                            })
+                            .unwrap_or(Err(ErrorCode::RESERVE))?;
+                        match op {
+                            UserSpaceOp::Open { flags } => self.fs.open(&path[..path_len], flags),
+                            _ => self.fs.create_dir(&path[..path_len]),
+                        }
+                    }
+                    UserSpaceOp::Read { file } => {
+                        let file = self.check_owner(file, processid)?;
+                        let len = kernel_data
+                            .get_readwrite_processbuffer(rw_allow::READ)
+                            .map_or(0, |buffer| buffer.len());
+                        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+                        self.fs.read(file, buffer, len).map_err(|(e, buffer)| {
+                            self.buffer.replace(buffer);
+                            e
+                        })
+                    }
+                    UserSpaceOp::Write { file } => {
+                        let file = self.check_owner(file, processid)?;
+                        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+                        // Write as much as fits in the buffer, the app is
+                        // told how much was written.
+                        let len = kernel_data
+                            .get_readonly_processbuffer(ro_allow::WRITE)
+                            .and_then(|data| {
+                                data.enter(|data| {
+                                    let len = cmp::min(data.len(), buffer.len());
+                                    data[..len].copy_to_slice(&mut buffer[..len]);
+                                    len
+                                })
+                            })
+                            .unwrap_or(0);
+                        self.fs.write(file, buffer, len).map_err(|(e, buffer)| {
+                            self.buffer.replace(buffer);
+                            e
+                        })
+                    }
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Start the next queued operation, if no operation is running.
+    fn check_queue(&self) {
+        for appiter in self.apps.iter() {
+            if self.processid.is_some() {
+                return;
+            }
+
+            let processid = appiter.processid();
+            let pending = appiter.enter(|app, _| app.op.is_some());
+            if pending {
+                self.processid.set(processid);
+                if let Err(e) = self.run(processid) {
+                    self.operation_done(Err(e), 0);
+                }
+            }
+        }
+    }
+
+    /// Tell the app whose operation was running that it completed.
+    fn operation_done(&self, result: Result<(), ErrorCode>, value: usize) {
+        self.processid.take().map(|processid| {
+            let _ = self.apps.enter(processid, |app, kernel_data| {
+                app.op = None;
+                kernel_data
+                    .schedule_upcall(0, (into_statuscode(result), value, 0))
+                    .ok();
+            });
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>> filesystem::Client for FatDriver<'a, A> {
+    fn mounted(&self, _result: Result<(), ErrorCode>) {}
+
+    fn unmounted(&self) {
+        for owner in self.owners.iter() {
+            owner.clear();
+        }
+        // The next card may not have the directories.
+        self.apps.each(|_, app, _| app.root_created = false);
+    }
+
+    fn opened(&self, result: Result<FileHandle, ErrorCode>) {
+        match result {
+            Ok(file) => {
+                self.processid
+                    .map(|processid| self.owners[file].set(*processid));
+                self.operation_done(Ok(()), file);
+            }
+            Err(e) => self.operation_done(Err(e), 0),
+        }
+        self.check_queue();
+    }
+
+    fn directory_created(&self, result: Result<(), ErrorCode>) {
+        if self.creating_root.replace(false) && result.is_ok() {
+            // Now run the operation the app asked for.
+            let started = self.processid.map_or(Err(ErrorCode::FAIL), |processid| {
+                let _ = self
+                    .apps
+                    .enter(*processid, |app, _| app.root_created = true);
+                self.run(*processid)
+            });
+            if let Err(e) = started {
+                self.operation_done(Err(e), 0);
+                self.check_queue();
+            }
+            return;
+        }
+        self.operation_done(result, 0);
+        self.check_queue();
+    }
+
+    fn read_done(&self, buffer: &'static mut [u8], result: Result<usize, ErrorCode>) {
+        let mut length = 0;
+        let result = result.and_then(|len| {
+            length = len;
+            self.processid.map_or(Err(ErrorCode::FAIL), |processid| {
+                self.apps
+                    .enter(*processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readwrite_processbuffer(rw_allow::READ)
+                            .and_then(|dest| {
+                                dest.mut_enter(|dest| {
+                                    let len = cmp::min(len, dest.len());
+                                    dest[..len].copy_from_slice(&buffer[..len]);
+                                })
+                            })
+                            .map_err(ErrorCode::from)
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+            })
+        });
+        self.buffer.replace(buffer);
+        self.operation_done(result, length);
+        self.check_queue();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], result: Result<usize, ErrorCode>) {
+        self.buffer.replace(buffer);
+        match result {
+            Ok(len) => self.operation_done(Ok(()), len),
+            Err(e) => self.operation_done(Err(e), 0),
+        }
+        self.check_queue();
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for FatDriver<'a, A> {
+    /// Access files in the directory of the app.
+    ///
+    /// Paths are passed in read-only allow buffer `0`, and data to write in
+    /// read-only allow buffer `1`. Data is read into read-write allow buffer
+    /// `0`.
+    ///
+    /// Commands `1`, `2`, `3` and `5` complete with an upcall on subscribe
+    /// number `0` with the status of the operation and the file handle
+    /// (open) or the number of bytes transferred (read and write).
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Open the file at the path. `data1` holds the flags: `1` to
+    ///        create the file if it does not exist, `2` to append to it.
+    /// - `2`: Read from file `data1`, up to the length of the buffer.
+    /// - `3`: Write the buffer to file `data1`.
+    /// - `4`: Close file `data1`.
+    /// - `5`: Create the directory at the path, and any missing parents.
+    /// - `6`: Get the size of file `data1`.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let op = match command_num {
+            0 => return CommandReturn::success(),
+            1 => UserSpaceOp::Open {
+                flags: data1 as u32,
+            },
+            2 => UserSpaceOp::Read { file: data1 },
+            3 => UserSpaceOp::Write { file: data1 },
+            4 => {
+                return self
+                    .check_owner(data1, processid)
+                    .and_then(|file| self.fs.close(file))
+                    .map(|()| self.owners[data1].clear())
+                    .into();
+            }
+            5 => UserSpaceOp::CreateDir,
+            6 => {
+                return match self
+                    .check_owner(data1, processid)
+                    .and_then(|file| self.fs.size(file))
+                {
+                    Ok(size) => CommandReturn::success_u32(size),
+                    Err(e) => CommandReturn::failure(e),
+                };
+            }
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        if processid
+            .get_storage_permissions()
+            .and_then(|permissions| permissions.get_write_id())
+            .is_none()
+        {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+
+        let queued = self.apps.enter(processid, |app, _| {
+            if app.op.is_some() {
+                Err(ErrorCode::BUSY)
+            } else {
+                app.op = Some(op);
+                Ok(())
+            }
+        });
+        match queued {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return CommandReturn::failure(e),
+            Err(err) => return CommandReturn::failure(err.into()),
+        }
+
+        if self.processid.is_some() {
+            // The operation will be started once the running one completes.
+            return CommandReturn::success();
+        }
+
+        self.processid.set(processid);
+        match self.run(processid) {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => {
+                self.processid.clear();
+                let _ = self.apps.enter(processid, |app, _| {
+                    app.op = None;
+                });
+                CommandReturn::failure(e)
+            }
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
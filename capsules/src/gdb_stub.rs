@@ -0,0 +1,1089 @@
+//! Debugging of processes with GDB over a UART.
+//!
+//! This capsule implements a stub for the GDB remote serial protocol, so that
+//! `gdb` can attach to the processes on a board:
+//!
+//! ```text
+//! (gdb) target remote /dev/ttyACM0
+//! (gdb) info threads
+//! ```
+//!
+//! Every process is a GDB thread, with the identifier of the process plus one
+//! as its thread id. When GDB attaches or interrupts the board, all processes
+//! are stopped, and they are resumed when GDB continues. The kernel and its
+//! capsules keep running the whole time.
+//!
+//! Registers are read and written through the stored state of the process,
+//! and memory accesses are bounded to the flash of the process and the memory
+//! below its app break. Register access supports Cortex-M and RV32I
+//! processes.
+//!
+//! Processes run from flash, so breakpoints cannot be inserted into their
+//! code, and the stub cannot single step them. GDB gets an error when it
+//! tries to set a breakpoint or to step. Instead, the stub is the
+//! `ContextSwitchCallback` of the board and checks the process every time it
+//! enters the kernel with a system call or an interrupt. Monitor commands let
+//! the processes stop there:
+//!
+//! ```text
+//! (gdb) monitor entry-stop 0x40031a2
+//! (gdb) monitor entry-step
+//! (gdb) monitor entry-stop-clear
+//! ```
+//!
+//! `entry-stop` stops the processes when one of them enters the kernel at
+//! the given address, which is reliable for the instruction after a system
+//! call. `entry-step` makes the next `continue` stop when the selected thread
+//! enters the kernel again. `entry-stop-clear` removes all addresses.
+//!
+//! Setup
+//! -----
+//!
+//! ```rust
+//! let gdb_stub = components::gdb_stub::GdbStubComponent::new(board_kernel, uart_mux)
+//!     .finalize(components::gdb_stub_component_helper!(NUM_PROCS));
+//!
+//! // In the `KernelResources` of the board:
+//! type ContextSwitchCallback = capsules::gdb_stub::GdbStub<
+//!     'static,
+//!     components::gdb_stub::Capability,
+//!     NUM_PROCS,
+//! >;
+//! fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
+//!     self.gdb_stub
+//! }
+//! ```
+
+use core::cell::Cell;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::uart;
+use kernel::platform::ContextSwitchCallback;
+use kernel::process::{Process, State};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Longest packet GDB may send.
+pub const PACKET_LEN: usize = 512;
+
+pub static mut RX_BUF: [u8; 1] = [0; 1];
+pub static mut PACKET_BUF: [u8; PACKET_LEN] = [0; PACKET_LEN];
+pub static mut TX_BUF: [u8; PACKET_LEN + 8] = [0; PACKET_LEN + 8];
+
+/// Number of addresses processes can stop at when they enter the kernel.
+const MAX_ENTRY_STOPS: usize = 8;
+
+/// Longest monitor command.
+const MAX_MONITOR_COMMAND: usize = 64;
+
+/// Most registers of a supported architecture.
+const MAX_REGISTERS: usize = 33;
+
+/// Longest stored state of a supported architecture.
+const STORED_STATE_LEN: usize = 160;
+
+/// GDB signal numbers for stop replies.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const CORTEX_M_TARGET: &str = concat!(
+    "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">",
+    "<target><architecture>arm</architecture>",
+    "<feature name=\"org.gnu.gdb.arm.m-profile\">",
+    "<reg name=\"r0\" bitsize=\"32\"/><reg name=\"r1\" bitsize=\"32\"/>",
+    "<reg name=\"r2\" bitsize=\"32\"/><reg name=\"r3\" bitsize=\"32\"/>",
+    "<reg name=\"r4\" bitsize=\"32\"/><reg name=\"r5\" bitsize=\"32\"/>",
+    "<reg name=\"r6\" bitsize=\"32\"/><reg name=\"r7\" bitsize=\"32\"/>",
+    "<reg name=\"r8\" bitsize=\"32\"/><reg name=\"r9\" bitsize=\"32\"/>",
+    "<reg name=\"r10\" bitsize=\"32\"/><reg name=\"r11\" bitsize=\"32\"/>",
+    "<reg name=\"r12\" bitsize=\"32\"/>",
+    "<reg name=\"sp\" bitsize=\"32\" type=\"data_ptr\"/>",
+    "<reg name=\"lr\" bitsize=\"32\"/>",
+    "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\"/>",
+    "<reg name=\"xpsr\" bitsize=\"32\"/>",
+    "</feature></target>",
+);
+
+const RV32I_TARGET: &str = concat!(
+    "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">",
+    "<target><architecture>riscv:rv32</architecture>",
+    "<feature name=\"org.gnu.gdb.riscv.cpu\">",
+    "<reg name=\"zero\" bitsize=\"32\"/><reg name=\"ra\" bitsize=\"32\"/>",
+    "<reg name=\"sp\" bitsize=\"32\" type=\"data_ptr\"/>",
+    "<reg name=\"gp\" bitsize=\"32\"/><reg name=\"tp\" bitsize=\"32\"/>",
+    "<reg name=\"t0\" bitsize=\"32\"/><reg name=\"t1\" bitsize=\"32\"/>",
+    "<reg name=\"t2\" bitsize=\"32\"/><reg name=\"fp\" bitsize=\"32\"/>",
+    "<reg name=\"s1\" bitsize=\"32\"/><reg name=\"a0\" bitsize=\"32\"/>",
+    "<reg name=\"a1\" bitsize=\"32\"/><reg name=\"a2\" bitsize=\"32\"/>",
+    "<reg name=\"a3\" bitsize=\"32\"/><reg name=\"a4\" bitsize=\"32\"/>",
+    "<reg name=\"a5\" bitsize=\"32\"/><reg name=\"a6\" bitsize=\"32\"/>",
+    "<reg name=\"a7\" bitsize=\"32\"/><reg name=\"s2\" bitsize=\"32\"/>",
+    "<reg name=\"s3\" bitsize=\"32\"/><reg name=\"s4\" bitsize=\"32\"/>",
+    "<reg name=\"s5\" bitsize=\"32\"/><reg name=\"s6\" bitsize=\"32\"/>",
+    "<reg name=\"s7\" bitsize=\"32\"/><reg name=\"s8\" bitsize=\"32\"/>",
+    "<reg name=\"s9\" bitsize=\"32\"/><reg name=\"s10\" bitsize=\"32\"/>",
+    "<reg name=\"s11\" bitsize=\"32\"/><reg name=\"t3\" bitsize=\"32\"/>",
+    "<reg name=\"t4\" bitsize=\"32\"/><reg name=\"t5\" bitsize=\"32\"/>",
+    "<reg name=\"t6\" bitsize=\"32\"/>",
+    "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\"/>",
+    "</feature></target>",
+);
+
+/// Architectures whose stored state the stub understands, identified by the
+/// tag in the stored state.
+#[derive(Clone, Copy, PartialEq)]
+enum Arch {
+    /// Stored state words: version, size, tag, yield pc, psr, psp, r4-r11.
+    /// r0-r3, r12, lr, pc and xpsr are in the exception frame at psp.
+    CortexM,
+    /// Stored state words: version, size, tag, pc, mcause, mtval, x1-x31.
+    Rv32i,
+}
+
+impl Arch {
+    fn from_stored_state(state: &[u8]) -> Option<Arch> {
+        match state.get(8..12)? {
+            [b'c', b't', b'x', b'm'] => Some(Arch::CortexM),
+            [b'r', b'v', b'5', b'i'] => Some(Arch::Rv32i),
+            _ => None,
+        }
+    }
+
+    fn register_count(&self) -> usize {
+        match self {
+            Arch::CortexM => 17,
+            Arch::Rv32i => 33,
+        }
+    }
+
+    fn target_description(&self) -> &'static str {
+        match self {
+            Arch::CortexM => CORTEX_M_TARGET,
+            Arch::Rv32i => RV32I_TARGET,
+        }
+    }
+}
+
+const CORTEX_M_YIELD_PC: usize = 3;
+const CORTEX_M_PSR: usize = 4;
+const CORTEX_M_PSP: usize = 5;
+const CORTEX_M_R4: usize = 6;
+const RV32I_PC: usize = 3;
+const RV32I_X1: usize = 6;
+
+/// Index in the Cortex-M exception frame of r0-r3, r12, lr, pc and xpsr.
+fn cortex_m_frame_index(register: usize) -> Option<usize> {
+    match register {
+        0..=3 => Some(register),
+        12 => Some(4),
+        14 => Some(5),
+        15 => Some(6),
+        16 => Some(7),
+        _ => None,
+    }
+}
+
+fn state_word(state: &[u8], index: usize) -> Option<u32> {
+    let bytes = state.get(index * 4..index * 4 + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn set_state_word(state: &mut [u8], index: usize, value: u32) {
+    if let Some(bytes) = state.get_mut(index * 4..index * 4 + 4) {
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// The registers of a process in the order of the GDB target description.
+struct Registers {
+    arch: Arch,
+    values: [u32; MAX_REGISTERS],
+}
+
+impl Registers {
+    fn values(&self) -> &[u32] {
+        &self.values[..self.arch.register_count()]
+    }
+}
+
+fn read_stored_state(process: &dyn Process) -> Option<([u8; STORED_STATE_LEN], usize)> {
+    let mut state = [0; STORED_STATE_LEN];
+    let len = process.get_stored_state(&mut state).ok()?;
+    Some((state, len))
+}
+
+/// The Cortex-M exception frame of a process and the value of its stack
+/// pointer before the frame was pushed.
+fn read_cortex_m_frame(process: &dyn Process, psp: u32) -> Option<([u32; 8], u32)> {
+    let mut bytes = [0; 32];
+    process.debug_read_memory(psp as usize, &mut bytes).ok()?;
+    let mut frame = [0; 8];
+    for (word, bytes) in frame.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    // Bit 9 of the stacked xPSR is set if the frame was aligned.
+    let sp = psp + 32 + if frame[7] & (1 << 9) != 0 { 4 } else { 0 };
+    Some((frame, sp))
+}
+
+fn read_registers(process: &dyn Process) -> Option<Registers> {
+    let (state, len) = read_stored_state(process)?;
+    let state = &state[..len];
+    let arch = Arch::from_stored_state(state)?;
+    let mut values = [0; MAX_REGISTERS];
+    match arch {
+        Arch::CortexM => {
+            let (frame, sp) = read_cortex_m_frame(process, state_word(state, CORTEX_M_PSP)?)?;
+            for (register, value) in values[..17].iter_mut().enumerate() {
+                *value = match (register, cortex_m_frame_index(register)) {
+                    (_, Some(index)) => frame[index],
+                    (4..=11, None) => state_word(state, CORTEX_M_R4 + register - 4)?,
+                    _ => sp,
+                };
+            }
+        }
+        Arch::Rv32i => {
+            for register in 1..32 {
+                values[register] = state_word(state, RV32I_X1 + register - 1)?;
+            }
+            values[32] = state_word(state, RV32I_PC)?;
+        }
+    }
+    Some(Registers { arch, values })
+}
+
+/// Writes all registers of a stopped process. The stack pointer of Cortex-M
+/// processes cannot be changed.
+fn write_registers(process: &dyn Process, registers: &Registers) -> Result<(), ErrorCode> {
+    let (mut state, len) = read_stored_state(process).ok_or(ErrorCode::FAIL)?;
+    let state = &mut state[..len];
+    let values = registers.values();
+    match registers.arch {
+        Arch::CortexM => {
+            let psp = state_word(state, CORTEX_M_PSP).ok_or(ErrorCode::FAIL)?;
+            let (_, sp) = read_cortex_m_frame(process, psp).ok_or(ErrorCode::FAIL)?;
+            if values[13] != sp {
+                return Err(ErrorCode::NOSUPPORT);
+            }
+            let mut frame = [0; 32];
+            for (register, value) in values.iter().enumerate() {
+                if let Some(index) = cortex_m_frame_index(register) {
+                    frame[index * 4..index * 4 + 4].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            for register in 4..12 {
+                set_state_word(state, CORTEX_M_R4 + register - 4, values[register]);
+            }
+            set_state_word(state, CORTEX_M_YIELD_PC, values[15]);
+            set_state_word(state, CORTEX_M_PSR, values[16]);
+            process.set_stored_state(state)?;
+            process.debug_write_memory(psp as usize, &frame)
+        }
+        Arch::Rv32i => {
+            for register in 1..32 {
+                set_state_word(state, RV32I_X1 + register - 1, values[register]);
+            }
+            set_state_word(state, RV32I_PC, values[32]);
+            process.set_stored_state(state)
+        }
+    }
+}
+
+fn program_counter(process: &dyn Process) -> Option<u32> {
+    let registers = read_registers(process)?;
+    Some(match registers.arch {
+        Arch::CortexM => registers.values[15],
+        Arch::Rv32i => registers.values[32],
+    })
+}
+
+fn state_name(state: State) -> &'static str {
+    match state {
+        State::Running => "Running",
+        State::Yielded => "Yielded",
+        State::StoppedRunning | State::StoppedYielded => "Stopped",
+        State::Faulted => "Faulted",
+        State::Terminated => "Terminated",
+        State::Unstarted => "Unstarted",
+        State::CredentialsUnchecked | State::CredentialsFailed => "Not approved",
+    }
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex(bytes: &[u8]) -> Option<usize> {
+    if bytes.is_empty() {
+        return None;
+    }
+    bytes.iter().try_fold(0usize, |value, byte| {
+        value
+            .checked_mul(16)?
+            .checked_add(hex_digit(*byte)? as usize)
+    })
+}
+
+fn parse_hex_byte(bytes: &[u8]) -> Option<u8> {
+    Some(hex_digit(*bytes.get(0)?)? << 4 | hex_digit(*bytes.get(1)?)?)
+}
+
+/// Splits `bytes` at the first `separator`.
+fn split(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let index = bytes.iter().position(|byte| *byte == separator)?;
+    Some((&bytes[..index], &bytes[index + 1..]))
+}
+
+/// Parses the `addr,length` arguments of memory packets.
+fn parse_range(bytes: &[u8]) -> Option<(usize, usize)> {
+    let (address, length) = split(bytes, b',')?;
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+/// A packet being written into the transmit buffer.
+struct Reply {
+    buffer: &'static mut [u8],
+    len: usize,
+    /// Index of the first byte of the packet data.
+    start: usize,
+}
+
+impl Reply {
+    fn new(buffer: &'static mut [u8], ack: bool) -> Reply {
+        let mut reply = Reply {
+            buffer,
+            len: 0,
+            start: 0,
+        };
+        if ack {
+            reply.push(b'+');
+        }
+        reply.push(b'$');
+        reply.start = reply.len;
+        reply
+    }
+
+    /// Bytes left for packet data.
+    fn remaining(&self) -> usize {
+        // Leave room for the checksum.
+        self.buffer.len() - 3 - self.len
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.remaining() > 0 {
+            self.buffer[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, string: &str) {
+        string.bytes().for_each(|byte| self.push(byte));
+    }
+
+    fn push_hex_byte(&mut self, byte: u8) {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        self.push(DIGITS[(byte >> 4) as usize]);
+        self.push(DIGITS[(byte & 0xf) as usize]);
+    }
+
+    /// Pushes a number in hex without leading zeros.
+    fn push_hex(&mut self, value: usize) {
+        let digits = (usize::BITS - value.leading_zeros() + 3) / 4;
+        for digit in (0..digits.max(1)).rev() {
+            let nibble = (value >> (digit * 4)) & 0xf;
+            self.push(b"0123456789abcdef"[nibble]);
+        }
+    }
+
+    /// Pushes a register value in target byte order.
+    fn push_register(&mut self, value: u32) {
+        value
+            .to_le_bytes()
+            .iter()
+            .for_each(|byte| self.push_hex_byte(*byte));
+    }
+
+    fn push_error(&mut self) {
+        self.push_str("E01");
+    }
+
+    fn push_result(&mut self, result: Result<(), ErrorCode>) {
+        match result {
+            Ok(()) => self.push_str("OK"),
+            Err(_) => self.push_error(),
+        }
+    }
+
+    /// Appends the checksum, and returns the buffer and the length to send.
+    fn finish(mut self) -> (&'static mut [u8], usize) {
+        let checksum = self.buffer[self.start..self.len]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        self.buffer[self.len] = b'#';
+        self.len += 1;
+        // Room for the checksum was left by `remaining()`.
+        let len = self.len;
+        self.len = len + 2;
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        self.buffer[len] = DIGITS[(checksum >> 4) as usize];
+        self.buffer[len + 1] = DIGITS[(checksum & 0xf) as usize];
+        (self.buffer, self.len)
+    }
+
+    /// Returns the buffer to only send the acknowledgement.
+    fn ack_only(self) -> (&'static mut [u8], usize) {
+        (self.buffer, 1)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RxState {
+    /// Waiting for the start of a packet.
+    Idle,
+    /// Receiving packet data.
+    Data,
+    /// Waiting for the first and second digit of the checksum.
+    Checksum,
+    ChecksumLow(u8),
+}
+
+pub struct GdbStub<'a, C: ProcessManagementCapability, const NUM_PROCS: usize> {
+    uart: &'a dyn uart::UartData<'a>,
+    kernel: &'static Kernel,
+    capability: C,
+
+    rx_buffer: TakeCell<'static, [u8]>,
+    packet: TakeCell<'static, [u8]>,
+    packet_len: Cell<usize>,
+    packet_overflow: Cell<bool>,
+    rx_state: Cell<RxState>,
+    checksum: Cell<u8>,
+    /// A complete packet waits for the transmit buffer.
+    packet_pending: Cell<bool>,
+
+    tx_buffer: TakeCell<'static, [u8]>,
+    /// A stop reply waits for the transmit buffer.
+    stop_pending: OptionalCell<(ProcessId, u8)>,
+
+    /// The thread registers and memory are accessed in.
+    thread: OptionalCell<ProcessId>,
+    /// GDB waits for the processes to stop after continuing.
+    running: Cell<bool>,
+    /// The thread that stops the next time it enters the kernel.
+    entry_step: OptionalCell<ProcessId>,
+    /// Addresses at which processes stop when they enter the kernel.
+    entry_stops: [OptionalCell<usize>; MAX_ENTRY_STOPS],
+    /// Processes stopped by the stub, which it resumes when GDB continues.
+    stopped: [OptionalCell<ProcessId>; NUM_PROCS],
+}
+
+impl<'a, C: ProcessManagementCapability, const NUM_PROCS: usize> GdbStub<'a, C, NUM_PROCS> {
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        rx_buffer: &'static mut [u8],
+        packet_buffer: &'static mut [u8],
+        tx_buffer: &'static mut [u8],
+        kernel: &'static Kernel,
+        capability: C,
+    ) -> GdbStub<'a, C, NUM_PROCS> {
+        GdbStub {
+            uart,
+            kernel,
+            capability,
+            rx_buffer: TakeCell::new(rx_buffer),
+            packet: TakeCell::new(packet_buffer),
+            packet_len: Cell::new(0),
+            packet_overflow: Cell::new(false),
+            rx_state: Cell::new(RxState::Idle),
+            checksum: Cell::new(0),
+            packet_pending: Cell::new(false),
+            tx_buffer: TakeCell::new(tx_buffer),
+            stop_pending: OptionalCell::empty(),
+            thread: OptionalCell::empty(),
+            running: Cell::new(false),
+            entry_step: OptionalCell::empty(),
+            entry_stops: Default::default(),
+            stopped: [(); NUM_PROCS].map(|_| OptionalCell::empty()),
+        }
+    }
+
+    /// Starts listening for GDB.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        self.receive()
+    }
+
+    fn receive(&self) -> Result<(), ErrorCode> {
+        self.rx_buffer
+            .take()
+            .map_or(Err(ErrorCode::BUSY), |buffer| {
+                self.uart
+                    .receive_buffer(buffer, 1)
+                    .map_err(|(err, buffer)| {
+                        self.rx_buffer.replace(buffer);
+                        err
+                    })
+            })
+    }
+
+    fn transmit(&self, buffer: &'static mut [u8], len: usize) {
+        if let Err((_, buffer)) = self.uart.transmit_buffer(buffer, len) {
+            self.tx_buffer.replace(buffer);
+        }
+    }
+
+    fn receive_byte(&self, byte: u8) {
+        match self.rx_state.get() {
+            RxState::Idle => match byte {
+                b'$' => {
+                    self.packet_len.set(0);
+                    self.packet_overflow.set(false);
+                    self.checksum.set(0);
+                    self.rx_state.set(RxState::Data);
+                }
+                // Ctrl-C from GDB.
+                0x03 => self.interrupt(),
+                // Acknowledgements of our packets.
+                _ => {}
+            },
+            RxState::Data => {
+                if byte == b'#' {
+                    self.rx_state.set(RxState::Checksum);
+                } else {
+                    self.checksum.set(self.checksum.get().wrapping_add(byte));
+                    let len = self.packet_len.get();
+                    self.packet.map(|packet| {
+                        if len < packet.len() {
+                            packet[len] = byte;
+                            self.packet_len.set(len + 1);
+                        } else {
+                            self.packet_overflow.set(true);
+                        }
+                    });
+                }
+            }
+            RxState::Checksum => {
+                self.rx_state
+                    .set(hex_digit(byte).map_or(RxState::Idle, RxState::ChecksumLow));
+            }
+            RxState::ChecksumLow(high) => {
+                self.rx_state.set(RxState::Idle);
+                let valid = hex_digit(byte).map_or(false, |low| {
+                    high << 4 | low == self.checksum.get() && !self.packet_overflow.get()
+                });
+                if valid {
+                    self.packet_pending.set(true);
+                    self.handle_pending_packet();
+                } else if let Some(buffer) = self.tx_buffer.take() {
+                    // Ask GDB to send the packet again.
+                    buffer[0] = b'-';
+                    self.transmit(buffer, 1);
+                }
+            }
+        }
+    }
+
+    /// Handles the received packet once the transmit buffer is free.
+    fn handle_pending_packet(&self) {
+        if !self.packet_pending.get() {
+            return;
+        }
+        if let Some(buffer) = self.tx_buffer.take() {
+            self.packet_pending.set(false);
+            let mut reply = Reply::new(buffer, true);
+            let respond = self.packet.map_or(false, |packet| {
+                self.handle_packet(&packet[..self.packet_len.get()], &mut reply)
+            });
+            let (buffer, len) = if respond {
+                reply.finish()
+            } else {
+                reply.ack_only()
+            };
+            self.transmit(buffer, len);
+        }
+    }
+
+    /// Sends the stop reply once the transmit buffer is free.
+    fn send_stop(&self, processid: ProcessId, signal: u8) {
+        match self.tx_buffer.take() {
+            Some(buffer) => {
+                let mut reply = Reply::new(buffer, false);
+                self.push_stop_reply(&mut reply, Some(processid), signal);
+                let (buffer, len) = reply.finish();
+                self.transmit(buffer, len);
+            }
+            None => self.stop_pending.set((processid, signal)),
+        }
+    }
+
+    fn push_stop_reply(&self, reply: &mut Reply, thread: Option<ProcessId>, signal: u8) {
+        reply.push(b'T');
+        reply.push_hex_byte(signal);
+        if let Some(processid) = thread {
+            reply.push_str("thread:");
+            reply.push_hex(processid.id() + 1);
+            reply.push(b';');
+        }
+    }
+
+    fn find_thread(&self, thread_id: usize) -> Option<ProcessId> {
+        let mut found = None;
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.processid().id() + 1 == thread_id {
+                    found = Some(process.processid());
+                }
+            });
+        found
+    }
+
+    /// The selected thread, or the first process if the selected one no
+    /// longer exists.
+    fn current_thread(&self) -> Option<ProcessId> {
+        let selected = self
+            .thread
+            .extract()
+            .filter(|processid| self.find_thread(processid.id() + 1).is_some());
+        selected.or_else(|| {
+            let mut first = None;
+            self.kernel
+                .process_each_capability(&self.capability, |process| {
+                    if first.is_none() {
+                        first = Some(process.processid());
+                    }
+                });
+            first
+        })
+    }
+
+    fn with_current<R>(&self, default: R, closure: impl FnOnce(&dyn Process) -> R) -> R {
+        match self.current_thread() {
+            Some(processid) => {
+                self.kernel
+                    .process_map_or_external(default, processid, closure, &self.capability)
+            }
+            None => default,
+        }
+    }
+
+    fn stop_all(&self) {
+        self.kernel
+            .process_each_capability(&self.capability, |process| match process.get_state() {
+                State::Running | State::Yielded => {
+                    process.stop();
+                    if let Some(slot) = self.stopped.iter().find(|slot| slot.is_none()) {
+                        slot.set(process.processid());
+                    }
+                }
+                _ => {}
+            });
+    }
+
+    fn resume(&self, processid: ProcessId) {
+        self.kernel.process_map_or_external(
+            (),
+            processid,
+            |process| process.resume(),
+            &self.capability,
+        );
+    }
+
+    fn resume_all(&self) {
+        for slot in self.stopped.iter() {
+            slot.take().map(|processid| self.resume(processid));
+        }
+    }
+
+    fn interrupt(&self) {
+        if self.running.get() {
+            self.running.set(false);
+            self.entry_step.clear();
+            self.stop_all();
+            if let Some(processid) = self.current_thread() {
+                self.thread.set(processid);
+                self.send_stop(processid, SIGINT);
+            }
+        }
+    }
+
+    fn detach(&self) {
+        self.running.set(false);
+        self.entry_step.clear();
+        self.entry_stops.iter().for_each(|stop| stop.clear());
+        self.resume_all();
+    }
+
+    fn is_entry_stop(&self, address: usize) -> bool {
+        self.entry_stops.iter().any(|stop| stop.contains(&address))
+    }
+
+    /// Handles a packet and writes the reply. Returns false if the packet
+    /// has no immediate reply.
+    fn handle_packet(&self, packet: &[u8], reply: &mut Reply) -> bool {
+        let (command, arguments) = match packet.split_first() {
+            Some((command, arguments)) => (*command, arguments),
+            None => return true,
+        };
+        match command {
+            b'?' => {
+                // GDB attached, or asks why the processes stopped.
+                self.running.set(false);
+                self.stop_all();
+                let thread = self.current_thread();
+                thread.map(|processid| self.thread.set(processid));
+                self.push_stop_reply(reply, thread, SIGTRAP);
+            }
+            b'q' => self.handle_query(arguments, reply),
+            b'H' => {
+                let thread = arguments.get(1..).unwrap_or(&[]);
+                match parse_hex(thread) {
+                    // Any thread.
+                    _ if thread == b"-1" => reply.push_str("OK"),
+                    Some(0) => reply.push_str("OK"),
+                    Some(thread_id) => match self.find_thread(thread_id) {
+                        Some(processid) => {
+                            self.thread.set(processid);
+                            reply.push_str("OK");
+                        }
+                        None => reply.push_error(),
+                    },
+                    None => reply.push_error(),
+                }
+            }
+            b'T' => {
+                let alive = parse_hex(arguments).and_then(|tid| self.find_thread(tid));
+                reply.push_result(alive.map(|_| ()).ok_or(ErrorCode::INVAL));
+            }
+            b'g' => match self.with_current(None, read_registers) {
+                Some(registers) => registers
+                    .values()
+                    .iter()
+                    .for_each(|value| reply.push_register(*value)),
+                None => reply.push_error(),
+            },
+            b'G' => {
+                let result = self.with_current(Err(ErrorCode::INVAL), |process| {
+                    let mut registers = read_registers(process).ok_or(ErrorCode::FAIL)?;
+                    let count = registers.arch.register_count();
+                    if arguments.len() != count * 8 {
+                        return Err(ErrorCode::SIZE);
+                    }
+                    for (value, hex) in registers.values[..count]
+                        .iter_mut()
+                        .zip(arguments.chunks_exact(8))
+                    {
+                        *value = parse_register(hex).ok_or(ErrorCode::INVAL)?;
+                    }
+                    write_registers(process, &registers)
+                });
+                reply.push_result(result);
+            }
+            b'p' => {
+                let value = parse_hex(arguments).and_then(|register| {
+                    self.with_current(None, read_registers)
+                        .and_then(|registers| registers.values().get(register).copied())
+                });
+                match value {
+                    Some(value) => reply.push_register(value),
+                    None => reply.push_error(),
+                }
+            }
+            b'P' => {
+                let result = split(arguments, b'=')
+                    .and_then(|(register, value)| {
+                        Some((parse_hex(register)?, parse_register(value)?))
+                    })
+                    .ok_or(ErrorCode::INVAL)
+                    .and_then(|(register, value)| {
+                        self.with_current(Err(ErrorCode::INVAL), |process| {
+                            let mut registers = read_registers(process).ok_or(ErrorCode::FAIL)?;
+                            if register >= registers.arch.register_count() {
+                                return Err(ErrorCode::INVAL);
+                            }
+                            registers.values[register] = value;
+                            write_registers(process, &registers)
+                        })
+                    });
+                reply.push_result(result);
+            }
+            b'm' => match parse_range(arguments) {
+                Some((address, length)) => self.read_memory(address, length, reply),
+                None => reply.push_error(),
+            },
+            b'M' => {
+                let result = split(arguments, b':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, data)))
+                    .ok_or(ErrorCode::INVAL)
+                    .and_then(|((address, length), data)| self.write_memory(address, length, data));
+                reply.push_result(result);
+            }
+            // Breakpoints cannot be inserted into flash and processes cannot
+            // be single stepped, see the `entry-stop` and `entry-step`
+            // monitor commands instead.
+            b'Z' | b'z' if arguments.starts_with(b"0,") || arguments.starts_with(b"1,") => {
+                reply.push_error();
+            }
+            b's' => reply.push_error(),
+            b'c' => {
+                self.running.set(true);
+                self.resume_all();
+                return false;
+            }
+            b'D' => {
+                self.detach();
+                reply.push_str("OK");
+            }
+            b'k' => {
+                self.detach();
+                return false;
+            }
+            // Unsupported packets get an empty reply.
+            _ => {}
+        }
+        true
+    }
+
+    fn handle_query(&self, query: &[u8], reply: &mut Reply) {
+        if query.starts_with(b"Supported") {
+            reply.push_str("PacketSize=");
+            reply.push_hex(PACKET_LEN);
+            reply.push_str(";qXfer:features:read+");
+        } else if query == b"Attached" {
+            reply.push_str("1");
+        } else if query == b"C" {
+            if let Some(processid) = self.current_thread() {
+                reply.push_str("QC");
+                reply.push_hex(processid.id() + 1);
+            }
+        } else if query == b"fThreadInfo" {
+            reply.push(b'm');
+            let mut first = true;
+            self.kernel
+                .process_each_capability(&self.capability, |process| {
+                    if !first {
+                        reply.push(b',');
+                    }
+                    first = false;
+                    reply.push_hex(process.processid().id() + 1);
+                });
+            if first {
+                // No processes.
+                reply.len = reply.start;
+                reply.push(b'l');
+            }
+        } else if query == b"sThreadInfo" {
+            reply.push(b'l');
+        } else if let Some(thread) = query.strip_prefix(b"ThreadExtraInfo,") {
+            let found = parse_hex(thread).and_then(|tid| self.find_thread(tid));
+            match found {
+                Some(processid) => self.kernel.process_map_or_external(
+                    (),
+                    processid,
+                    |process| {
+                        let name = process.get_process_name();
+                        let state = state_name(process.get_state());
+                        name.bytes()
+                            .chain(b" (".iter().copied())
+                            .chain(state.bytes())
+                            .chain(b")".iter().copied())
+                            .for_each(|byte| reply.push_hex_byte(byte));
+                    },
+                    &self.capability,
+                ),
+                None => reply.push_error(),
+            }
+        } else if let Some(command) = query.strip_prefix(b"Rcmd,") {
+            reply.push_result(self.monitor_command(command));
+        } else if let Some(range) = query.strip_prefix(b"Xfer:features:read:target.xml:") {
+            let target = self.with_current(None, |process| {
+                read_stored_state(process)
+                    .and_then(|(state, len)| Arch::from_stored_state(&state[..len]))
+            });
+            match (target, parse_range(range)) {
+                (Some(arch), Some((offset, length))) => {
+                    let description = arch.target_description().as_bytes();
+                    let chunk = description.get(offset..).unwrap_or(&[]);
+                    let length = length.min(reply.remaining() - 1);
+                    if chunk.len() > length {
+                        reply.push(b'm');
+                        chunk[..length].iter().for_each(|byte| reply.push(*byte));
+                    } else {
+                        reply.push(b'l');
+                        chunk.iter().for_each(|byte| reply.push(*byte));
+                    }
+                }
+                _ => reply.push_error(),
+            }
+        }
+    }
+
+    /// Runs a hex encoded `monitor` command.
+    fn monitor_command(&self, hex: &[u8]) -> Result<(), ErrorCode> {
+        let mut command = [0; MAX_MONITOR_COMMAND];
+        let len = hex.len() / 2;
+        if hex.len() % 2 != 0 || len > command.len() {
+            return Err(ErrorCode::SIZE);
+        }
+        for (byte, hex) in command[..len].iter_mut().zip(hex.chunks_exact(2)) {
+            *byte = parse_hex_byte(hex).ok_or(ErrorCode::INVAL)?;
+        }
+        let command = &command[..len];
+        let (name, argument) = split(command, b' ').unwrap_or((command, &[]));
+        match name {
+            b"entry-stop" => {
+                let address = argument.strip_prefix(b"0x").unwrap_or(argument);
+                let address = parse_hex(address).ok_or(ErrorCode::INVAL)?;
+                if self.is_entry_stop(address) {
+                    return Ok(());
+                }
+                self.entry_stops
+                    .iter()
+                    .find(|stop| stop.is_none())
+                    .map(|stop| stop.set(address))
+                    .ok_or(ErrorCode::NOMEM)
+            }
+            b"entry-stop-clear" => {
+                self.entry_stops.iter().for_each(|stop| stop.clear());
+                Ok(())
+            }
+            b"entry-step" => {
+                let processid = self.current_thread().ok_or(ErrorCode::INVAL)?;
+                self.thread.set(processid);
+                self.entry_step.set(processid);
+                Ok(())
+            }
+            _ => Err(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn read_memory(&self, address: usize, length: usize, reply: &mut Reply) {
+        let length = length.min(reply.remaining() / 2);
+        let mut chunk = [0; 32];
+        let result = self.with_current(Err(ErrorCode::INVAL), |process| {
+            // Check the whole range first, so that errors have no data.
+            process.debug_read_memory(address, &mut [])?;
+            let end = address.checked_add(length).ok_or(ErrorCode::INVAL)?;
+            if length > 0 {
+                process.debug_read_memory(end - 1, &mut chunk[..1])?;
+            }
+            let mut offset = 0;
+            while offset < length {
+                let len = (length - offset).min(chunk.len());
+                process.debug_read_memory(address + offset, &mut chunk[..len])?;
+                chunk[..len]
+                    .iter()
+                    .for_each(|byte| reply.push_hex_byte(*byte));
+                offset += len;
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            reply.len = reply.start;
+            reply.push_error();
+        }
+    }
+
+    fn write_memory(&self, address: usize, length: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        if length.checked_mul(2) != Some(data.len()) {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut chunk = [0; 32];
+        self.with_current(Err(ErrorCode::INVAL), |process| {
+            for (index, hex) in data.chunks(chunk.len() * 2).enumerate() {
+                let len = hex.len() / 2;
+                for (byte, hex) in chunk[..len].iter_mut().zip(hex.chunks_exact(2)) {
+                    *byte = parse_hex_byte(hex).ok_or(ErrorCode::INVAL)?;
+                }
+                let chunk_address = index
+                    .checked_mul(chunk.len())
+                    .and_then(|offset| address.checked_add(offset))
+                    .ok_or(ErrorCode::INVAL)?;
+                process.debug_write_memory(chunk_address, &chunk[..len])?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Parses a register value in target byte order.
+fn parse_register(hex: &[u8]) -> Option<u32> {
+    if hex.len() != 8 {
+        return None;
+    }
+    let mut bytes = [0; 4];
+    for (byte, hex) in bytes.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = parse_hex_byte(hex)?;
+    }
+    Some(u32::from_le_bytes(bytes))
+}
+
+/// Stops processes when they enter the kernel at an entry stop, or after an
+/// entry step.
+impl<'a, C: ProcessManagementCapability, const NUM_PROCS: usize> ContextSwitchCallback
+    for GdbStub<'a, C, NUM_PROCS>
+{
+    fn context_switch_hook(&self, _process: &dyn Process) {}
+
+    fn context_switch_return_hook(&self, process: &dyn Process) {
+        if !self.running.get() {
+            return;
+        }
+        let processid = process.processid();
+        let trapped = self.entry_step.contains(&processid)
+            || (self.entry_stops.iter().any(|stop| stop.is_some())
+                && program_counter(process).map_or(false, |pc| self.is_entry_stop(pc as usize)));
+        if trapped {
+            self.running.set(false);
+            self.entry_step.clear();
+            self.stop_all();
+            self.thread.set(processid);
+            self.send_stop(processid, SIGTRAP);
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability, const NUM_PROCS: usize> uart::TransmitClient
+    for GdbStub<'a, C, NUM_PROCS>
+{
+    fn transmitted_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rcode: Result<(), ErrorCode>,
+    ) {
+        self.tx_buffer.replace(buffer);
+        if let Some((processid, signal)) = self.stop_pending.take() {
+            self.send_stop(processid, signal);
+        } else if self.packet_pending.get() {
+            self.handle_pending_packet();
+            let _ = self.receive();
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability, const NUM_PROCS: usize> uart::ReceiveClient
+    for GdbStub<'a, C, NUM_PROCS>
+{
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        _rcode: Result<(), ErrorCode>,
+        error: uart::Error,
+    ) {
+        let byte = buffer[0];
+        self.rx_buffer.replace(buffer);
+        if error == uart::Error::None && rx_len == 1 {
+            self.receive_byte(byte);
+        }
+        // A packet waiting for the transmit buffer stays in the packet
+        // buffer, so stop receiving until it is handled.
+        if !self.packet_pending.get() {
+            let _ = self.receive();
+        }
+    }
+}
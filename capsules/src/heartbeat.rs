@@ -0,0 +1,335 @@
+//! Monitors the health of processes with heartbeats, and only feeds the
+//! system watchdog while all of them are healthy.
+//!
+//! The kernel loop tickles the watchdog on every iteration, so a process that
+//! hangs but keeps yielding is never detected. With this capsule, a process
+//! registers a heartbeat interval and must ping the capsule within every
+//! interval. When a process misses its deadline, the capsule reports it as a
+//! fault with `Process::set_fault_state()`, so the `ProcessFaultPolicy` of the
+//! board decides whether the process is restarted, stopped or the board
+//! panics. Restarting or stopping a process clears its heartbeat.
+//!
+//! `HeartbeatMonitor` wraps the watchdog of the chip and is used as the
+//! `WatchDog` of the board. It only tickles the chip's watchdog while no
+//! heartbeat deadline has passed. If a missed deadline is not resolved by the
+//! fault policy, the watchdog resets the system.
+//!
+//! The deadline of a stopped process, for example one stopped from the
+//! process console, is paused until the process resumes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let heartbeat_alarm = static_init!(
+//!     capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+//!     capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! heartbeat_alarm.setup();
+//! let heartbeat = static_init!(
+//!     capsules::heartbeat::HeartbeatMonitor<
+//!         'static,
+//!         capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+//!         nrf52840::wdt::Wdt,
+//!         Capability,
+//!     >,
+//!     capsules::heartbeat::HeartbeatMonitor::new(
+//!         heartbeat_alarm,
+//!         &nrf52840_peripherals.wdt,
+//!         board_kernel,
+//!         Capability,
+//!         board_kernel.create_grant(capsules::heartbeat::DRIVER_NUM, &grant_cap)
+//!     )
+//! );
+//! heartbeat_alarm.set_alarm_client(heartbeat);
+//!
+//! // In the `KernelResources` of the board:
+//! type WatchDog = capsules::heartbeat::HeartbeatMonitor<...>;
+//! fn watchdog(&self) -> &Self::WatchDog {
+//!     self.heartbeat
+//! }
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Stability: 0 - Draft
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Registers a heartbeat with the interval in milliseconds in the
+//!   first argument, or changes the interval. This counts as a ping.
+//! - `2`: Pings the heartbeat, which starts a new interval.
+//! - `3`: Unregisters the heartbeat.
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::debug;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks};
+use kernel::platform::watchdog::WatchDog;
+use kernel::process::State;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Heartbeat as usize;
+
+/// Longest heartbeat interval in ticks, so that the elapsed time since the
+/// last ping can be computed in 32 bits.
+const MAX_INTERVAL_TICKS: u32 = u32::MAX / 2;
+
+#[derive(Default)]
+pub struct App {
+    /// Heartbeat interval in ticks, or zero if no heartbeat is registered.
+    interval: u32,
+    /// When the process last pinged, in the lower 32 bits of the alarm ticks.
+    last_ping: u32,
+    /// Whether the process missed its deadline.
+    missed: bool,
+}
+
+impl App {
+    /// Ticks until the deadline, or zero if it passed.
+    fn remaining(&self, now: u32) -> u32 {
+        self.interval
+            .saturating_sub(now.wrapping_sub(self.last_ping))
+    }
+}
+
+/// The earliest deadline of all heartbeats.
+#[derive(Clone, Copy)]
+enum Deadline {
+    /// Expires `dt` ticks after `reference`.
+    At { reference: u32, dt: u32 },
+    /// A deadline was missed.
+    Missed,
+}
+
+pub struct HeartbeatMonitor<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> {
+    alarm: &'a A,
+    watchdog: &'a W,
+    kernel: &'static Kernel,
+    capability: C,
+    apps: Grant<App, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
+    /// The earliest deadline, or empty if no heartbeat is registered.
+    deadline: OptionalCell<Deadline>,
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> HeartbeatMonitor<'a, A, W, C> {
+    pub fn new(
+        alarm: &'a A,
+        watchdog: &'a W,
+        kernel: &'static Kernel,
+        capability: C,
+        grant: Grant<App, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> HeartbeatMonitor<'a, A, W, C> {
+        HeartbeatMonitor {
+            alarm,
+            watchdog,
+            kernel,
+            capability,
+            apps: grant,
+            deadline: OptionalCell::empty(),
+        }
+    }
+
+    /// Whether no heartbeat deadline has passed.
+    pub fn healthy(&self) -> bool {
+        self.deadline.map_or(true, |deadline| match deadline {
+            Deadline::At { reference, dt } => self.now().wrapping_sub(*reference) < *dt,
+            Deadline::Missed => false,
+        })
+    }
+
+    fn now(&self) -> u32 {
+        self.alarm.now().into_u32()
+    }
+
+    fn is_stopped(&self, processid: ProcessId) -> bool {
+        self.kernel.process_map_or_external(
+            false,
+            processid,
+            |process| match process.get_state() {
+                State::StoppedRunning | State::StoppedYielded => true,
+                _ => false,
+            },
+            &self.capability,
+        )
+    }
+
+    /// Recomputes the earliest deadline and sets the alarm for it.
+    fn update_deadline(&self) {
+        let now = self.now();
+        let mut earliest: Option<u32> = None;
+        let mut missed = false;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if app.missed {
+                    missed = true;
+                } else if app.interval > 0 {
+                    let remaining = app.remaining(now);
+                    earliest = Some(earliest.map_or(remaining, |dt| dt.min(remaining)));
+                }
+            });
+        }
+
+        match earliest {
+            Some(dt) => {
+                self.alarm
+                    .set_alarm(A::Ticks::from(now), A::Ticks::from(dt));
+            }
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+        if missed {
+            self.deadline.set(Deadline::Missed);
+        } else {
+            match earliest {
+                Some(dt) => self.deadline.set(Deadline::At { reference: now, dt }),
+                None => self.deadline.clear(),
+            }
+        }
+    }
+
+    /// Finds a process that missed its deadline and was not reported yet,
+    /// and marks it as missed. Deadlines of stopped processes are paused.
+    fn take_missed(&self, now: u32) -> Option<ProcessId> {
+        self.apps.iter().find_map(|app| {
+            let processid = app.processid();
+            let stopped = self.is_stopped(processid);
+            app.enter(|app, _| {
+                if app.interval == 0 || app.missed {
+                    None
+                } else if stopped {
+                    app.last_ping = now;
+                    None
+                } else if app.remaining(now) == 0 {
+                    app.missed = true;
+                    Some(processid)
+                } else {
+                    None
+                }
+            })
+        })
+    }
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> time::AlarmClient
+    for HeartbeatMonitor<'a, A, W, C>
+{
+    fn alarm(&self) {
+        let now = self.now();
+        // The process is faulted outside of its grant, since restarting it
+        // frees the grant.
+        while let Some(processid) = self.take_missed(now) {
+            self.kernel.process_map_or_external(
+                (),
+                processid,
+                |process| {
+                    debug!(
+                        "Process {} missed its heartbeat deadline",
+                        process.get_process_name()
+                    );
+                    process.set_fault_state();
+                },
+                &self.capability,
+            );
+        }
+        self.update_deadline();
+    }
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> WatchDog
+    for HeartbeatMonitor<'a, A, W, C>
+{
+    fn setup(&self) {
+        self.watchdog.setup();
+    }
+
+    /// Only tickles the watchdog while all heartbeats are healthy.
+    fn tickle(&self) {
+        if self.healthy() {
+            self.watchdog.tickle();
+        }
+    }
+
+    fn suspend(&self) {
+        self.watchdog.suspend();
+    }
+
+    fn resume(&self) {
+        self.watchdog.resume();
+    }
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> SyscallDriver
+    for HeartbeatMonitor<'a, A, W, C>
+{
+    /// Register and ping heartbeats.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register a heartbeat with the interval `data` in milliseconds.
+    /// - `2`: Ping the heartbeat.
+    /// - `3`: Unregister the heartbeat.
+    fn command(
+        &self,
+        command_num: usize,
+        data: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let now = self.now();
+        let result = self.apps.enter(processid, |app, _| match command_num {
+            0 => Ok(()),
+
+            1 => {
+                let interval = self.alarm.ticks_from_ms(data as u32).into_u32();
+                if data == 0 || data > u32::MAX as usize || interval > MAX_INTERVAL_TICKS {
+                    Err(ErrorCode::INVAL)
+                } else {
+                    app.interval = interval;
+                    app.last_ping = now;
+                    Ok(())
+                }
+            }
+
+            2 => {
+                if app.interval == 0 {
+                    Err(ErrorCode::RESERVE)
+                } else {
+                    app.last_ping = now;
+                    Ok(())
+                }
+            }
+
+            3 => {
+                app.interval = 0;
+                Ok(())
+            }
+
+            _ => Err(ErrorCode::NOSUPPORT),
+        });
+        match result {
+            Ok(Ok(())) => {
+                if command_num != 0 {
+                    self.update_deadline();
+                }
+                CommandReturn::success()
+            }
+            Ok(Err(err)) => CommandReturn::failure(err),
+            Err(err) => CommandReturn::failure(err.into()),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
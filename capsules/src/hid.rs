@@ -0,0 +1,269 @@
+//! Provides userspace with a USB keyboard and mouse.
+//!
+//! Processes send boot protocol keyboard and mouse reports, which are passed
+//! to a device implementing `hil::usb_hid::UsbHid` for both report formats,
+//! such as `capsules::usb::hid::Hid`. This lets a board inject input into a
+//! host, for example for test automation.
+//!
+//! A report describes the complete state of the keyboard or mouse. To type a
+//! key, a process sends a report with the key pressed, followed by a report
+//! with no keys pressed.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let (hid, hid_driver) = components::usb_hid::UsbHidComponent::new(
+//!     board_kernel,
+//!     capsules::hid::DRIVER_NUM,
+//!     &nrf52840_peripherals.usbd,
+//!     capsules::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x503b,
+//!     STRINGS,
+//! )
+//! .finalize(components::usb_hid_component_helper!(nrf52840::usbd::Usbd));
+//!
+//! hid.enable();
+//! hid.attach();
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Stability: 0 - Draft
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Send a keyboard report. Argument 1 holds the modifier byte in bits
+//!   0-7 and the first three key codes in bits 8-31, argument 2 holds the
+//!   last three key codes in bits 0-23.
+//! - `2`: Send a mouse report. Argument 1 holds the buttons, argument 2 the X,
+//!   Y and wheel movement as signed bytes in bits 0-7, 8-15 and 16-23.
+//! - `3`: Returns the state of the keyboard LEDs.
+//!
+//! Only one report of each kind can be pending at a time, until it is sent
+//! the command returns BUSY.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Upcall when a report has been sent. The first argument is `0` for a
+//!   keyboard report and `1` for a mouse report, the second the status.
+//! - `1`: Upcall when the host changed the keyboard LEDs. The first argument
+//!   is the new LED state.
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::usb_hid;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::UsbHid as usize;
+
+/// Ids for subscribe upcalls
+mod upcall {
+    pub const REPORT_SENT: usize = 0;
+    pub const LEDS: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+const KEYBOARD: usize = 0;
+const MOUSE: usize = 1;
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct HidDriver<'a, H: usb_hid::UsbHid<'a, [u8; 8]> + usb_hid::UsbHid<'a, [u8; 4]>> {
+    hid: &'a H,
+    apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+
+    keyboard_buffer: TakeCell<'static, [u8; 8]>,
+    mouse_buffer: TakeCell<'static, [u8; 4]>,
+    /// The processes that sent the pending reports.
+    keyboard_app: OptionalCell<ProcessId>,
+    mouse_app: OptionalCell<ProcessId>,
+    /// The keyboard LED state last set by the host.
+    leds: Cell<u8>,
+}
+
+impl<'a, H: usb_hid::UsbHid<'a, [u8; 8]> + usb_hid::UsbHid<'a, [u8; 4]>> HidDriver<'a, H> {
+    pub fn new(
+        hid: &'a H,
+        keyboard_buffer: &'static mut [u8; 8],
+        mouse_buffer: &'static mut [u8; 4],
+        grant: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> HidDriver<'a, H> {
+        HidDriver {
+            hid,
+            apps: grant,
+            keyboard_buffer: TakeCell::new(keyboard_buffer),
+            mouse_buffer: TakeCell::new(mouse_buffer),
+            keyboard_app: OptionalCell::empty(),
+            mouse_app: OptionalCell::empty(),
+            leds: Cell::new(0),
+        }
+    }
+
+    fn send_keyboard_report(
+        &self,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        self.keyboard_buffer
+            .take()
+            .map_or(CommandReturn::failure(ErrorCode::BUSY), |buf| {
+                buf[0] = data1 as u8; // Modifiers
+                buf[1] = 0; // Reserved
+                for i in 0..3 {
+                    buf[2 + i] = (data1 >> (8 * (i + 1))) as u8;
+                    buf[5 + i] = (data2 >> (8 * i)) as u8;
+                }
+                match usb_hid::UsbHid::<[u8; 8]>::send_buffer(self.hid, buf) {
+                    Ok(_) => {
+                        self.keyboard_app.set(processid);
+                        CommandReturn::success()
+                    }
+                    Err((err, buf)) => {
+                        self.keyboard_buffer.replace(buf);
+                        CommandReturn::failure(err)
+                    }
+                }
+            })
+    }
+
+    fn send_mouse_report(&self, data1: usize, data2: usize, processid: ProcessId) -> CommandReturn {
+        self.mouse_buffer
+            .take()
+            .map_or(CommandReturn::failure(ErrorCode::BUSY), |buf| {
+                buf[0] = data1 as u8; // Buttons
+                buf[1] = data2 as u8; // X
+                buf[2] = (data2 >> 8) as u8; // Y
+                buf[3] = (data2 >> 16) as u8; // Wheel
+                match usb_hid::UsbHid::<[u8; 4]>::send_buffer(self.hid, buf) {
+                    Ok(_) => {
+                        self.mouse_app.set(processid);
+                        CommandReturn::success()
+                    }
+                    Err((err, buf)) => {
+                        self.mouse_buffer.replace(buf);
+                        CommandReturn::failure(err)
+                    }
+                }
+            })
+    }
+
+    fn report_sent(
+        &self,
+        processid: Option<ProcessId>,
+        kind: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        processid.map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall::REPORT_SENT, (kind, into_statuscode(result), 0))
+                    .ok();
+            });
+        });
+    }
+}
+
+impl<'a, H: usb_hid::UsbHid<'a, [u8; 8]> + usb_hid::UsbHid<'a, [u8; 4]>>
+    usb_hid::Client<'a, [u8; 8]> for HidDriver<'a, H>
+{
+    /// A keyboard output report, holding the LED state, was received.
+    fn packet_received(
+        &'a self,
+        _result: Result<(), ErrorCode>,
+        buffer: &'static mut [u8; 8],
+        _endpoint: usize,
+    ) {
+        let leds = buffer[0];
+        self.leds.set(leds);
+        self.apps.each(|_, _, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::LEDS, (leds as usize, 0, 0))
+                .ok();
+        });
+
+        // Wait for the next output report.
+        let _ = usb_hid::UsbHid::<[u8; 8]>::receive_buffer(self.hid, buffer);
+    }
+
+    fn packet_transmitted(
+        &'a self,
+        result: Result<(), ErrorCode>,
+        buffer: &'static mut [u8; 8],
+        _endpoint: usize,
+    ) {
+        self.keyboard_buffer.replace(buffer);
+        self.report_sent(self.keyboard_app.take(), KEYBOARD, result);
+    }
+
+    fn can_receive(&'a self) -> bool {
+        true
+    }
+}
+
+impl<'a, H: usb_hid::UsbHid<'a, [u8; 8]> + usb_hid::UsbHid<'a, [u8; 4]>>
+    usb_hid::Client<'a, [u8; 4]> for HidDriver<'a, H>
+{
+    /// The mouse has no output reports.
+    fn packet_received(
+        &'a self,
+        _result: Result<(), ErrorCode>,
+        _buffer: &'static mut [u8; 4],
+        _endpoint: usize,
+    ) {
+    }
+
+    fn packet_transmitted(
+        &'a self,
+        result: Result<(), ErrorCode>,
+        buffer: &'static mut [u8; 4],
+        _endpoint: usize,
+    ) {
+        self.mouse_buffer.replace(buffer);
+        self.report_sent(self.mouse_app.take(), MOUSE, result);
+    }
+
+    fn can_receive(&'a self) -> bool {
+        false
+    }
+}
+
+impl<'a, H: usb_hid::UsbHid<'a, [u8; 8]> + usb_hid::UsbHid<'a, [u8; 4]>> SyscallDriver
+    for HidDriver<'a, H>
+{
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.send_keyboard_report(data1, data2, processid),
+
+            2 => self.send_mouse_report(data1, data2, processid),
+
+            3 => CommandReturn::success_u32(self.leds.get() as u32),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
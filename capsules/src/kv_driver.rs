@@ -0,0 +1,382 @@
+//! Userspace access to the key-value store.
+//!
+//! This driver lets applications get, set and delete values in a
+//! `hil::kv_store` implementation (such as `capsules::kv_store::KVStore`).
+//! Every operation is performed with the storage permissions of the calling
+//! application, which come from the `PersistentAcl` TLV in its TBF header.
+//! Applications without that TLV cannot use this driver.
+//!
+//! Only one operation runs at a time. Operations from other applications are
+//! queued and started once the current one completes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let kv_driver = static_init!(
+//!     capsules::kv_driver::KVStoreDriver<'static, KVStore<'static, ...>>,
+//!     capsules::kv_driver::KVStoreDriver::new(
+//!         kv_store,
+//!         static_init!([u8; 64], [0; 64]),
+//!         static_init!([u8; 512], [0; 512]),
+//!         board_kernel.create_grant(capsules::kv_driver::DRIVER_NUM, &grant_cap),
+//!     )
+//! );
+//! kv_store.set_client(kv_driver);
+//! ```
+
+use core::cmp;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::kv_store::{KVStore, StoreClient};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Kv as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const KEY: usize = 0;
+    pub const VALUE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const VALUE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum UserSpaceOp {
+    Get,
+    Set,
+    Delete,
+}
+
+#[derive(Default)]
+pub struct App {
+    /// The operation the app asked for that has not completed yet.
+    op: Option<UserSpaceOp>,
+}
+
+pub struct KVStoreDriver<'a, V: KVStore<'a>> {
+    kv: &'a V,
+    apps: Grant<
+        App,
+        UpcallCount<1>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The app whose operation is running.
+    processid: OptionalCell<ProcessId>,
+    key_buffer: TakeCell<'static, [u8]>,
+    value_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, V: KVStore<'a>> KVStoreDriver<'a, V> {
+    pub fn new(
+        kv: &'a V,
+        key_buffer: &'static mut [u8],
+        value_buffer: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<1>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> KVStoreDriver<'a, V> {
+        KVStoreDriver {
+            kv,
+            apps: grant,
+            processid: OptionalCell::empty(),
+            key_buffer: TakeCell::new(key_buffer),
+            value_buffer: TakeCell::new(value_buffer),
+        }
+    }
+
+    /// Start the pending operation of `processid`.
+    fn run(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let permissions = processid
+            .get_storage_permissions()
+            .ok_or(ErrorCode::NOSUPPORT)?;
+
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                let op = app.op.ok_or(ErrorCode::FAIL)?;
+
+                // Copy the key into the kernel.
+                let key_buffer = self.key_buffer.take().ok_or(ErrorCode::BUSY)?;
+                let key_len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::KEY)
+                    .and_then(|key| {
+                        key.enter(|key| {
+                            if key.len() == 0 {
+                                Err(ErrorCode::INVAL)
+                            } else if key.len() > key_buffer.len() {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                key.copy_to_slice(&mut key_buffer[..key.len()]);
+                                Ok(key.len())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE));
+                let key_len = match key_len {
+                    Ok(len) => len,
+                    Err(e) => {
+                        self.key_buffer.replace(key_buffer);
+                        return Err(e);
+                    }
+                };
+                let mut key = LeasableBuffer::new(key_buffer);
+                key.slice(..key_len);
+
+                match op {
+                    UserSpaceOp::Get => {
+                        let value_buffer = match self.value_buffer.take() {
+                            Some(value_buffer) => value_buffer,
+                            None => {
+                                self.key_buffer.replace(key.take());
+                                return Err(ErrorCode::BUSY);
+                            }
+                        };
+                        self.kv
+                            .get(key, value_buffer, permissions)
+                            .map_err(|(key, value, e)| {
+                                self.key_buffer.replace(key);
+                                self.value_buffer.replace(value);
+                                e
+                            })
+                    }
+                    UserSpaceOp::Set => {
+                        let value_buffer = match self.value_buffer.take() {
+                            Some(value_buffer) => value_buffer,
+                            None => {
+                                self.key_buffer.replace(key.take());
+                                return Err(ErrorCode::BUSY);
+                            }
+                        };
+
+                        // Copy the value into the kernel, leaving room for the
+                        // header in front of it.
+                        let header_size = self.kv.header_size();
+                        let value_len = kernel_data
+                            .get_readonly_processbuffer(ro_allow::VALUE)
+                            .and_then(|value| {
+                                value.enter(|value| {
+                                    if header_size + value.len() > value_buffer.len() {
+                                        Err(ErrorCode::SIZE)
+                                    } else {
+                                        value.copy_to_slice(
+                                            &mut value_buffer
+                                                [header_size..header_size + value.len()],
+                                        );
+                                        Ok(value.len())
+                                    }
+                                })
+                            })
+                            .unwrap_or(Err(ErrorCode::RESERVE));
+                        let value_len = match value_len {
+                            Ok(len) => len,
+                            Err(e) => {
+                                self.key_buffer.replace(key.take());
+                                self.value_buffer.replace(value_buffer);
+                                return Err(e);
+                            }
+                        };
+
+                        self.kv
+                            .set(key, value_buffer, value_len, permissions)
+                            .map_err(|(key, value, e)| {
+                                self.key_buffer.replace(key);
+                                self.value_buffer.replace(value);
+                                e
+                            })
+                    }
+                    UserSpaceOp::Delete => self.kv.delete(key, permissions).map_err(|(key, e)| {
+                        self.key_buffer.replace(key);
+                        e
+                    }),
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Start the next queued operation, if no operation is running.
+    fn check_queue(&self) {
+        for appiter in self.apps.iter() {
+            if self.processid.is_some() {
+                return;
+            }
+
+            let processid = appiter.processid();
+            let pending = appiter.enter(|app, _| app.op.is_some());
+            if pending {
+                self.processid.set(processid);
+                if let Err(e) = self.run(processid) {
+                    self.processid.clear();
+                    let _ = self.apps.enter(processid, |app, kernel_data| {
+                        app.op = None;
+                        kernel_data
+                            .schedule_upcall(0, (into_statuscode(Err(e)), 0, 0))
+                            .ok();
+                    });
+                }
+            }
+        }
+    }
+
+    /// Tell the app whose operation was running that it completed.
+    fn operation_done(&self, result: Result<(), ErrorCode>, length: usize) {
+        self.processid.take().map(|processid| {
+            let _ = self.apps.enter(processid, |app, kernel_data| {
+                app.op = None;
+                kernel_data
+                    .schedule_upcall(0, (into_statuscode(result), length, 0))
+                    .ok();
+            });
+        });
+    }
+}
+
+impl<'a, V: KVStore<'a>> StoreClient for KVStoreDriver<'a, V> {
+    fn get_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    ) {
+        self.key_buffer.replace(key);
+
+        let mut length = 0;
+        let ret = result.and_then(|value_len| {
+            length = value_len;
+            self.processid.map_or(Err(ErrorCode::FAIL), |processid| {
+                self.apps
+                    .enter(*processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readwrite_processbuffer(rw_allow::VALUE)
+                            .and_then(|dest| {
+                                dest.mut_enter(|dest| {
+                                    let copy_len = cmp::min(value_len, dest.len());
+                                    dest[..copy_len].copy_from_slice(&value[..copy_len]);
+                                    if copy_len < value_len {
+                                        // The length of the value is still
+                                        // reported so the app can retry with
+                                        // a larger buffer.
+                                        Err(ErrorCode::SIZE)
+                                    } else {
+                                        Ok(())
+                                    }
+                                })
+                            })
+                            .unwrap_or(Err(ErrorCode::RESERVE))
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+            })
+        });
+
+        self.value_buffer.replace(value);
+        self.operation_done(ret, length);
+        self.check_queue();
+    }
+
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    ) {
+        self.key_buffer.replace(key);
+        self.value_buffer.replace(value);
+        self.operation_done(result, 0);
+        self.check_queue();
+    }
+
+    fn delete_complete(&self, result: Result<(), ErrorCode>, key: &'static mut [u8]) {
+        self.key_buffer.replace(key);
+        self.operation_done(result, 0);
+        self.check_queue();
+    }
+}
+
+impl<'a, V: KVStore<'a>> SyscallDriver for KVStoreDriver<'a, V> {
+    /// Access the key-value store.
+    ///
+    /// The key is passed in read-only allow buffer `0`. The value to store is
+    /// passed in read-only allow buffer `1`, and values that are read are
+    /// copied into read-write allow buffer `0`.
+    ///
+    /// Operations complete with an upcall on subscribe number `0` with the
+    /// status of the operation and, for `get`, the length of the value.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the value for the key.
+    /// - `2`: Set the value for the key.
+    /// - `3`: Delete the key.
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let op = match command_num {
+            0 => return CommandReturn::success(),
+            1 => UserSpaceOp::Get,
+            2 => UserSpaceOp::Set,
+            3 => UserSpaceOp::Delete,
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        if processid.get_storage_permissions().is_none() {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+
+        let queued = self.apps.enter(processid, |app, _| {
+            if app.op.is_some() {
+                Err(ErrorCode::BUSY)
+            } else {
+                app.op = Some(op);
+                Ok(())
+            }
+        });
+        match queued {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return CommandReturn::failure(e),
+            Err(err) => return CommandReturn::failure(err.into()),
+        }
+
+        if self.processid.is_some() {
+            // The operation will be started once the running one completes.
+            return CommandReturn::success();
+        }
+
+        self.processid.set(processid);
+        match self.run(processid) {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => {
+                self.processid.clear();
+                let _ = self.apps.enter(processid, |app, _| {
+                    app.op = None;
+                });
+                CommandReturn::failure(e)
+            }
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
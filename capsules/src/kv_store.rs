@@ -0,0 +1,485 @@
+//! Tock Key-Value store capsule with permissions.
+//!
+//! This capsule provides the `hil::kv_store` interface on top of a
+//! `hil::kv_system` implementation (such as TicKV). Keys are hashed with the
+//! KV system, and values are stored together with a header that records the
+//! `write_id` of the writer so that permissions can be checked when the value
+//! is later read, replaced or deleted.
+//!
+//! +-----------------------+
+//! |                       |
+//! |  Capsule using K-V    |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::kv_store
+//!
+//! +-----------------------+
+//! |                       |
+//! |  K-V in Tock (this)   |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::kv_system
+//!
+//! +-----------------------+
+//! |                       |
+//! |  TicKV                |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::flash
+//!
+//! Before an existing value is replaced or deleted its header is read into
+//! `existing_value`, so that buffer must be large enough to hold the largest
+//! value (including the header) stored in the KV system.
+//!
+//! Only the active portion of the caller's key is hashed. It is copied into
+//! `key_buffer` prefixed with its length and padded with zeros, so
+//! `key_buffer` must be at least one byte longer than the longest key.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let kv_store = static_init!(
+//!     capsules::kv_store::KVStore<'static, TicKVStore<...>, TicKVKeyType>,
+//!     capsules::kv_store::KVStore::new(
+//!         tickv,
+//!         static_init!(TicKVKeyType, [0; 8]),
+//!         static_init!([u8; 65], [0; 65]),
+//!         static_init!([u8; 256], [0; 256]),
+//!     )
+//! );
+//! tickv.set_client(kv_store);
+//! ```
+
+use core::cell::Cell;
+use core::convert::TryInto;
+
+use kernel::hil::kv_store::{self, StoreClient};
+use kernel::hil::kv_system::{self, KVSystem, KeyType};
+use kernel::storage_permissions::StoragePermissions;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// The version of the header format.
+const HEADER_VERSION: u8 = 0;
+/// The length of the header in bytes.
+pub const HEADER_LENGTH: usize = 9;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Get,
+    Set,
+    Delete,
+}
+
+/// The header stored in front of every value.
+#[derive(Clone, Copy, Debug)]
+struct KeyHeader {
+    version: u8,
+    length: u32,
+    write_id: u32,
+}
+
+impl KeyHeader {
+    /// Parse a header from the start of `buf`.
+    fn new_from_buf(buf: &[u8]) -> Option<KeyHeader> {
+        let header = KeyHeader {
+            version: *buf.get(0)?,
+            length: u32::from_le_bytes(buf.get(1..5)?.try_into().ok()?),
+            write_id: u32::from_le_bytes(buf.get(5..9)?.try_into().ok()?),
+        };
+        if header.version == HEADER_VERSION {
+            Some(header)
+        } else {
+            None
+        }
+    }
+
+    /// Write the header to the start of `buf`.
+    fn copy_to_buf(&self, buf: &mut [u8]) {
+        buf[0] = self.version;
+        buf[1..5].copy_from_slice(&self.length.to_le_bytes());
+        buf[5..9].copy_from_slice(&self.write_id.to_le_bytes());
+    }
+}
+
+pub struct KVStore<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType> {
+    kv: &'a K,
+    operation: Cell<Operation>,
+    permissions: OptionalCell<StoragePermissions>,
+
+    hashed_key: TakeCell<'static, T>,
+    key_buffer: TakeCell<'static, [u8]>,
+    unhashed_key: TakeCell<'static, [u8]>,
+    value: TakeCell<'static, [u8]>,
+    value_length: Cell<usize>,
+    existing_value: TakeCell<'static, [u8]>,
+
+    client: OptionalCell<&'a dyn StoreClient>,
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVStore<'a, K, T> {
+    pub fn new(
+        kv: &'a K,
+        hashed_key: &'static mut T,
+        key_buffer: &'static mut [u8],
+        existing_value: &'static mut [u8],
+    ) -> KVStore<'a, K, T> {
+        Self {
+            kv,
+            operation: Cell::new(Operation::None),
+            permissions: OptionalCell::empty(),
+            hashed_key: TakeCell::new(hashed_key),
+            key_buffer: TakeCell::new(key_buffer),
+            unhashed_key: TakeCell::empty(),
+            value: TakeCell::empty(),
+            value_length: Cell::new(0),
+            existing_value: TakeCell::new(existing_value),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Copy the active portion of `key` into `key_buffer` and start hashing
+    /// it. On success the caller's key buffer is held in `unhashed_key` until
+    /// the operation completes.
+    fn generate_key(
+        &self,
+        key: LeasableBuffer<'static, u8>,
+    ) -> Result<(), (&'static mut [u8], ErrorCode)> {
+        let (key_buffer, hashed_key) = match (self.key_buffer.take(), self.hashed_key.take()) {
+            (Some(key_buffer), Some(hashed_key)) => (key_buffer, hashed_key),
+            (key_buffer, hashed_key) => {
+                key_buffer.map(|buf| self.key_buffer.replace(buf));
+                hashed_key.map(|buf| self.hashed_key.replace(buf));
+                return Err((key.take(), ErrorCode::BUSY));
+            }
+        };
+
+        let length = key.len();
+        if length > u8::MAX as usize || length >= key_buffer.len() {
+            self.key_buffer.replace(key_buffer);
+            self.hashed_key.replace(hashed_key);
+            return Err((key.take(), ErrorCode::SIZE));
+        }
+        key_buffer[0] = length as u8;
+        for (i, b) in key_buffer[1..].iter_mut().enumerate() {
+            *b = if i < length { key[i] } else { 0 };
+        }
+
+        match self.kv.generate_key(key_buffer, hashed_key) {
+            Ok(()) => {
+                self.unhashed_key.replace(key.take());
+                Ok(())
+            }
+            Err((key_buffer, hashed_key, e)) => {
+                self.key_buffer.replace(key_buffer);
+                self.hashed_key.replace(hashed_key);
+                Err((key.take(), e.err().unwrap_or(ErrorCode::FAIL)))
+            }
+        }
+    }
+
+    /// Whether the caller may modify the value whose header is at the start
+    /// of `buf`.
+    fn check_modify(&self, buf: &[u8]) -> bool {
+        KeyHeader::new_from_buf(buf).map_or(false, |header| {
+            self.permissions.map_or(false, |perms| {
+                perms.check_modify_permission(header.write_id)
+            })
+        })
+    }
+
+    /// Write the value (whose header is already in place) for the hashed key.
+    fn append(&self, key: &'static mut T) {
+        let value = self.value.take().unwrap();
+        let length = self.value_length.get();
+        if let Err((key, value, _)) = self.kv.append_key_mut(key, value, length) {
+            self.hashed_key.replace(key);
+            self.set_done(Err(ErrorCode::FAIL), value);
+        }
+    }
+
+    /// Remove the value for the hashed key.
+    fn invalidate(&self, key: &'static mut T) {
+        if let Err((key, _)) = self.kv.invalidate_key(key) {
+            self.hashed_key.replace(key);
+            self.finish(Err(ErrorCode::FAIL));
+        }
+    }
+
+    /// Read the value that is currently stored for the hashed key into
+    /// `existing_value` to check the permissions of its writer.
+    fn get_existing(&self, key: &'static mut T) {
+        let buf = self.existing_value.take().unwrap();
+        if let Err((key, buf, _)) = self.kv.get_value(key, buf) {
+            self.hashed_key.replace(key);
+            self.existing_value.replace(buf);
+            self.existing_not_found();
+        }
+    }
+
+    /// Continue after the existing value could not be read, which means it
+    /// most likely does not exist.
+    fn existing_not_found(&self) {
+        match self.operation.get() {
+            // There is nothing to replace, so the new value can be added.
+            Operation::Set => self.append(self.hashed_key.take().unwrap()),
+            _ => self.finish(Err(ErrorCode::FAIL)),
+        }
+    }
+
+    fn set_done(&self, result: Result<(), ErrorCode>, value: &'static mut [u8]) {
+        self.operation.set(Operation::None);
+        self.permissions.clear();
+        if let Some(key) = self.unhashed_key.take() {
+            self.client
+                .map(move |cb| cb.set_complete(result, key, value));
+        }
+    }
+
+    /// Report the failure or completion of the current operation.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        match self.operation.get() {
+            Operation::Get => {
+                let value = self.value.take().unwrap();
+                self.operation.set(Operation::None);
+                self.permissions.clear();
+                if let Some(key) = self.unhashed_key.take() {
+                    self.client
+                        .map(move |cb| cb.get_complete(result.map(|()| 0), key, value));
+                }
+            }
+            Operation::Set => {
+                let value = self.value.take().unwrap();
+                self.set_done(result, value);
+            }
+            Operation::Delete => {
+                self.operation.set(Operation::None);
+                self.permissions.clear();
+                if let Some(key) = self.unhashed_key.take() {
+                    self.client.map(move |cb| cb.delete_complete(result, key));
+                }
+            }
+            Operation::None => {}
+        }
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: KeyType> kv_store::KVStore<'a> for KVStore<'a, K, T> {
+    fn set_client(&self, client: &'a dyn StoreClient) {
+        self.client.set(client);
+    }
+
+    fn header_size(&self) -> usize {
+        HEADER_LENGTH
+    }
+
+    fn get(
+        &self,
+        key: LeasableBuffer<'static, u8>,
+        value: &'static mut [u8],
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], &'static mut [u8], ErrorCode)> {
+        if self.operation.get() != Operation::None {
+            return Err((key.take(), value, ErrorCode::BUSY));
+        }
+
+        match self.generate_key(key) {
+            Ok(()) => {
+                self.operation.set(Operation::Get);
+                self.permissions.set(permissions);
+                self.value.replace(value);
+                Ok(())
+            }
+            Err((key, e)) => Err((key, value, e)),
+        }
+    }
+
+    fn set(
+        &self,
+        key: LeasableBuffer<'static, u8>,
+        value: &'static mut [u8],
+        length: usize,
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], &'static mut [u8], ErrorCode)> {
+        if self.operation.get() != Operation::None {
+            return Err((key.take(), value, ErrorCode::BUSY));
+        }
+        let write_id = match permissions.get_write_id() {
+            Some(write_id) => write_id,
+            None => return Err((key.take(), value, ErrorCode::NOSUPPORT)),
+        };
+        if value.len() < HEADER_LENGTH + length {
+            return Err((key.take(), value, ErrorCode::SIZE));
+        }
+
+        KeyHeader {
+            version: HEADER_VERSION,
+            length: length as u32,
+            write_id,
+        }
+        .copy_to_buf(value);
+
+        match self.generate_key(key) {
+            Ok(()) => {
+                self.operation.set(Operation::Set);
+                self.permissions.set(permissions);
+                self.value.replace(value);
+                self.value_length.set(HEADER_LENGTH + length);
+                Ok(())
+            }
+            Err((key, e)) => Err((key, value, e)),
+        }
+    }
+
+    fn delete(
+        &self,
+        key: LeasableBuffer<'static, u8>,
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], ErrorCode)> {
+        if self.operation.get() != Operation::None {
+            return Err((key.take(), ErrorCode::BUSY));
+        }
+
+        match self.generate_key(key) {
+            Ok(()) => {
+                self.operation.set(Operation::Delete);
+                self.permissions.set(permissions);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: KeyType> kv_system::Client<T> for KVStore<'a, K, T> {
+    fn generate_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        unhashed_key: &'static mut [u8],
+        key_buf: &'static mut T,
+    ) {
+        self.key_buffer.replace(unhashed_key);
+
+        if result.is_err() {
+            self.hashed_key.replace(key_buf);
+            self.finish(Err(ErrorCode::FAIL));
+            return;
+        }
+
+        match self.operation.get() {
+            Operation::Get => {
+                let value = self.value.take().unwrap();
+                if let Err((key, value, _)) = self.kv.get_value(key_buf, value) {
+                    self.hashed_key.replace(key);
+                    self.value.replace(value);
+                    self.finish(Err(ErrorCode::FAIL));
+                }
+            }
+            Operation::Set | Operation::Delete => self.get_existing(key_buf),
+            Operation::None => {
+                self.hashed_key.replace(key_buf);
+            }
+        }
+    }
+
+    fn append_key_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        _value: &'static [u8],
+    ) {
+        // Values are always appended with `append_key_mut()`.
+        self.hashed_key.replace(key);
+    }
+
+    fn append_key_mut_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        self.hashed_key.replace(key);
+        self.set_done(result.map_err(|_| ErrorCode::FAIL), value);
+    }
+
+    fn get_value_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        ret_buf: &'static mut [u8],
+    ) {
+        match self.operation.get() {
+            Operation::Get => {
+                self.hashed_key.replace(key);
+                self.operation.set(Operation::None);
+
+                let ret = result
+                    .map_err(|_| ErrorCode::FAIL)
+                    .and_then(|()| KeyHeader::new_from_buf(ret_buf).ok_or(ErrorCode::FAIL))
+                    .and_then(|header| {
+                        let readable = self
+                            .permissions
+                            .map_or(false, |perms| perms.check_read_permission(header.write_id));
+                        let length = header.length as usize;
+                        if !readable {
+                            Err(ErrorCode::NOSUPPORT)
+                        } else if HEADER_LENGTH + length > ret_buf.len() {
+                            Err(ErrorCode::SIZE)
+                        } else {
+                            // Move the value to the start of the buffer.
+                            ret_buf.copy_within(HEADER_LENGTH..HEADER_LENGTH + length, 0);
+                            Ok(length)
+                        }
+                    });
+
+                if ret.is_err() {
+                    // Don't hand out data the caller may not be allowed to see.
+                    ret_buf.iter_mut().for_each(|b| *b = 0);
+                }
+
+                self.permissions.clear();
+                if let Some(unhashed_key) = self.unhashed_key.take() {
+                    self.client
+                        .map(move |cb| cb.get_complete(ret, unhashed_key, ret_buf));
+                }
+            }
+            Operation::Set | Operation::Delete => {
+                let exists = result.is_ok();
+                let allowed = exists && self.check_modify(ret_buf);
+                self.existing_value.replace(ret_buf);
+
+                if !exists {
+                    self.hashed_key.replace(key);
+                    self.existing_not_found();
+                } else if allowed {
+                    self.invalidate(key);
+                } else {
+                    self.hashed_key.replace(key);
+                    self.finish(Err(ErrorCode::NOSUPPORT));
+                }
+            }
+            Operation::None => {
+                self.hashed_key.replace(key);
+            }
+        }
+    }
+
+    fn invalidate_key_complete(&self, result: Result<(), ErrorCode>, key: &'static mut T) {
+        match (self.operation.get(), result) {
+            // The old value is gone, now store the new one.
+            (Operation::Set, Ok(())) => self.append(key),
+            _ => {
+                self.hashed_key.replace(key);
+                self.finish(result.map_err(|_| ErrorCode::FAIL));
+            }
+        }
+    }
+
+    fn garbage_collect_complete(&self, _result: Result<(), ErrorCode>) {}
+}
@@ -14,23 +14,35 @@ pub mod ambient_light;
 pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod apds9960;
+pub mod app_checker_sha256;
+pub mod app_checker_signature;
 pub mod app_flash_driver;
+pub mod app_loader;
+pub mod ble;
 pub mod ble_advertising_driver;
+pub mod ble_gatt_driver;
 pub mod bus;
 pub mod button;
 pub mod buzzer_driver;
+pub mod checkpoint;
 pub mod console;
+pub mod crash_record;
 pub mod crc;
 pub mod ctap;
 pub mod dac;
 pub mod debug_process_restart;
 pub mod driver;
+pub mod fat;
+pub mod fat_driver;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
+pub mod gdb_stub;
 pub mod gpio;
 pub mod gpio_async;
 pub mod hd44780;
+pub mod heartbeat;
+pub mod hid;
 pub mod hmac;
 pub mod hts221;
 pub mod humidity;
@@ -38,6 +50,8 @@ pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod kv_driver;
+pub mod kv_store;
 pub mod l3gd20;
 pub mod led;
 pub mod led_matrix;
@@ -49,6 +63,7 @@ pub mod lsm303dlhc;
 pub mod lsm303xx;
 pub mod lsm6dsoxtr;
 pub mod ltc294x;
+pub mod mailbox;
 pub mod max17205;
 pub mod mcp230xx;
 pub mod mlx90614;
@@ -78,6 +93,8 @@ pub mod spi_controller;
 pub mod spi_peripheral;
 pub mod st77xx;
 pub mod symmetric_encryption;
+pub mod syscall_filter;
+pub mod syscall_trace;
 pub mod temperature;
 pub mod temperature_rp2040;
 pub mod temperature_stm;
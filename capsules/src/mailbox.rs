@@ -0,0 +1,471 @@
+//! Message-passing IPC between processes with named services.
+//!
+//! A service registers a name, and clients look up the service by that name
+//! and send it messages. Unlike `kernel::ipc`, the processes share no memory:
+//! the kernel copies every message from an allow buffer of the sender into
+//! the mailbox of the receiver, which lives in the grant of the receiver, and
+//! the receiver copies it out of its mailbox into one of its own buffers.
+//!
+//! Every mailbox holds up to `DEPTH` messages of up to `MAX_LEN` bytes. When
+//! the mailbox of the receiver is full, sending fails with `NOMEM` and the
+//! sender has to retry later, so a slow receiver pushes back on its senders
+//! instead of losing messages.
+//!
+//! A client that expects an answer sends a request. The service receives a
+//! reply token with the request, which allows it to send exactly one reply to
+//! that client. Tokens cannot be used after the reply was sent, so a service
+//! can only answer clients that asked it something.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mailbox = static_init!(
+//!     capsules::mailbox::Mailbox<4, 64>,
+//!     capsules::mailbox::Mailbox::new(
+//!         board_kernel.create_grant(capsules::mailbox::DRIVER_NUM, &grant_cap)
+//!     )
+//! );
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Stability: 0 - Draft
+//!
+//! ### Allow
+//!
+//! - Read-only `0`: The message to send.
+//! - Read-only `1`: The service name to register or discover.
+//! - Read-write `0`: The buffer that messages are received into.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Called when a message arrives in the mailbox, with its length, the
+//!   id of the sender and its kind (`0` message, `1` request, `2` reply).
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Register the name in read-only allow `1` as a service.
+//! - `2`: Look up the service with the name in read-only allow `1`, returns
+//!   its id.
+//! - `3`: Send the message to the service with the id in the first argument.
+//!   If the second argument is not zero, the message is a request.
+//! - `4`: Reply with the message to the request with the reply token in the
+//!   first argument.
+//! - `5`: Receive the oldest message into read-write allow `0`, returns its
+//!   length with its kind in the upper 16 bits, the id of the sender and a
+//!   reply token for requests.
+//! - `6`: Unregister the service.
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Mailbox as usize;
+
+/// Longest service name in bytes.
+pub const NAME_LEN: usize = 16;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const MESSAGE: usize = 0;
+    pub const NAME: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const RECEIVE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Ids for upcalls
+mod upcall {
+    pub const RECEIVED: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Message = 0,
+    Request = 1,
+    Reply = 2,
+}
+
+#[derive(Clone, Copy)]
+struct Message<const MAX_LEN: usize> {
+    kind: Kind,
+    /// Id of the sending process.
+    sender: u32,
+    /// Reply token for requests, otherwise zero.
+    reply: u32,
+    len: usize,
+    data: [u8; MAX_LEN],
+}
+
+impl<const MAX_LEN: usize> Default for Message<MAX_LEN> {
+    fn default() -> Self {
+        Message {
+            kind: Kind::Message,
+            sender: 0,
+            reply: 0,
+            len: 0,
+            data: [0; MAX_LEN],
+        }
+    }
+}
+
+/// The right to send one reply to a client.
+#[derive(Clone, Copy)]
+struct ReplyCapability {
+    client: ProcessId,
+    generation: u8,
+}
+
+pub struct App<const DEPTH: usize, const MAX_LEN: usize> {
+    /// Name of the service, empty if the process is not a service.
+    name: [u8; NAME_LEN],
+    name_len: usize,
+    /// Messages in the mailbox, in a ring starting at `head`.
+    messages: [Message<MAX_LEN>; DEPTH],
+    head: usize,
+    count: usize,
+    /// Clients the process may reply to, indexed by the reply token.
+    replies: [Option<ReplyCapability>; DEPTH],
+    /// Makes tokens of reused reply slots differ from earlier ones.
+    generation: u8,
+}
+
+impl<const DEPTH: usize, const MAX_LEN: usize> Default for App<DEPTH, MAX_LEN> {
+    fn default() -> Self {
+        App {
+            name: [0; NAME_LEN],
+            name_len: 0,
+            messages: [Message::default(); DEPTH],
+            head: 0,
+            count: 0,
+            replies: [None; DEPTH],
+            generation: 0,
+        }
+    }
+}
+
+impl<const DEPTH: usize, const MAX_LEN: usize> App<DEPTH, MAX_LEN> {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    /// Stores a reply capability for `client` and returns its token.
+    fn add_reply(&mut self, client: ProcessId) -> Option<u32> {
+        let slot = self.replies.iter().position(|reply| reply.is_none())?;
+        self.generation = self.generation.wrapping_add(1);
+        self.replies[slot] = Some(ReplyCapability {
+            client,
+            generation: self.generation,
+        });
+        Some(reply_token(slot, self.generation))
+    }
+
+    /// Returns the slot and client of a reply token.
+    fn find_reply(&self, token: usize) -> Option<(usize, ProcessId)> {
+        let slot = (token & 0xffff).checked_sub(1)?;
+        let reply = self.replies.get(slot).copied().flatten()?;
+        (token == reply_token(slot, reply.generation) as usize).then(|| (slot, reply.client))
+    }
+}
+
+/// Reply tokens are never zero, so that zero means no reply capability.
+fn reply_token(slot: usize, generation: u8) -> u32 {
+    ((generation as u32) << 16) | (slot as u32 + 1)
+}
+
+/// A message copied out of the allow buffer of its sender.
+struct Outgoing<const MAX_LEN: usize> {
+    sender: ProcessId,
+    len: usize,
+    data: [u8; MAX_LEN],
+}
+
+/// Mailboxes of `DEPTH` messages of up to `MAX_LEN` bytes. `MAX_LEN` must be
+/// below 65536, since the length is returned in 16 bits.
+pub struct Mailbox<const DEPTH: usize, const MAX_LEN: usize> {
+    apps: Grant<
+        App<DEPTH, MAX_LEN>,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+}
+
+impl<const DEPTH: usize, const MAX_LEN: usize> Mailbox<DEPTH, MAX_LEN> {
+    pub fn new(
+        grant: Grant<
+            App<DEPTH, MAX_LEN>,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Mailbox<DEPTH, MAX_LEN> {
+        Mailbox { apps: grant }
+    }
+
+    /// Copies the name in the name allow buffer of `processid`.
+    fn read_name(&self, processid: ProcessId) -> Result<([u8; NAME_LEN], usize), ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::NAME)
+                    .and_then(|name| {
+                        name.enter(|name| {
+                            if name.len() == 0 || name.len() > NAME_LEN {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            let mut buffer = [0; NAME_LEN];
+                            name.copy_to_slice(&mut buffer[..name.len()]);
+                            Ok((buffer, name.len()))
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Returns the service registered as `name`, other than `except`.
+    fn find_service(&self, name: &[u8], except: Option<ProcessId>) -> Option<ProcessId> {
+        self.apps.iter().find_map(|app| {
+            let processid = app.processid();
+            if Some(processid) == except {
+                return None;
+            }
+            app.enter(|app, _| (app.name_len > 0 && app.name() == name).then(|| processid))
+        })
+    }
+
+    /// Copies the message in the message allow buffer of `processid`.
+    fn read_message(&self, processid: ProcessId) -> Result<Outgoing<MAX_LEN>, ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::MESSAGE)
+                    .and_then(|message| {
+                        message.enter(|message| {
+                            if message.len() > MAX_LEN {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            let mut data = [0; MAX_LEN];
+                            message.copy_to_slice(&mut data[..message.len()]);
+                            Ok(Outgoing {
+                                sender: processid,
+                                len: message.len(),
+                                data,
+                            })
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Copies `message` into the mailbox of `receiver`. Requests also give
+    /// the receiver a reply capability for the sender.
+    fn deliver(
+        &self,
+        receiver: ProcessId,
+        message: &Outgoing<MAX_LEN>,
+        kind: Kind,
+    ) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(receiver, |app, kernel_data| {
+                if kind != Kind::Reply && app.name_len == 0 {
+                    return Err(ErrorCode::INVAL);
+                }
+                if app.count == DEPTH {
+                    return Err(ErrorCode::NOMEM);
+                }
+                let reply = if kind == Kind::Request {
+                    app.add_reply(message.sender).ok_or(ErrorCode::NOMEM)?
+                } else {
+                    0
+                };
+
+                let index = (app.head + app.count) % DEPTH;
+                app.messages[index] = Message {
+                    kind,
+                    sender: message.sender.id() as u32,
+                    reply,
+                    len: message.len,
+                    data: message.data,
+                };
+                app.count += 1;
+
+                kernel_data
+                    .schedule_upcall(
+                        upcall::RECEIVED,
+                        (message.len, message.sender.id(), kind as usize),
+                    )
+                    .ok();
+                Ok(())
+            })
+            .unwrap_or(Err(ErrorCode::INVAL))
+    }
+
+    fn register(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let (name, name_len) = self.read_name(processid)?;
+        if self
+            .find_service(&name[..name_len], Some(processid))
+            .is_some()
+        {
+            return Err(ErrorCode::BUSY);
+        }
+        self.apps
+            .enter(processid, |app, _| {
+                app.name = name;
+                app.name_len = name_len;
+            })
+            .map_err(ErrorCode::from)
+    }
+
+    fn discover(&self, processid: ProcessId) -> Result<u32, ErrorCode> {
+        let (name, name_len) = self.read_name(processid)?;
+        self.find_service(&name[..name_len], None)
+            .map(|service| service.id() as u32)
+            .ok_or(ErrorCode::NODEVICE)
+    }
+
+    fn send(&self, processid: ProcessId, service: usize, request: bool) -> Result<(), ErrorCode> {
+        let message = self.read_message(processid)?;
+        let receiver = self
+            .apps
+            .iter()
+            .map(|app| app.processid())
+            .find(|receiver| receiver.id() == service)
+            .ok_or(ErrorCode::INVAL)?;
+        let kind = if request {
+            Kind::Request
+        } else {
+            Kind::Message
+        };
+        self.deliver(receiver, &message, kind)
+    }
+
+    fn reply(&self, processid: ProcessId, token: usize) -> Result<(), ErrorCode> {
+        let message = self.read_message(processid)?;
+        let (slot, client) = self
+            .apps
+            .enter(processid, |app, _| app.find_reply(token))?
+            .ok_or(ErrorCode::INVAL)?;
+
+        let result = self.deliver(client, &message, Kind::Reply);
+        // The capability stays valid if the mailbox of the client is full, so
+        // that the service can retry.
+        if result != Err(ErrorCode::NOMEM) {
+            self.apps.enter(processid, |app, _| {
+                app.replies[slot] = None;
+            })?;
+        }
+        result
+    }
+
+    fn receive(&self, processid: ProcessId) -> Result<(u32, u32, u32), ErrorCode> {
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                if app.count == 0 {
+                    return Err(ErrorCode::FAIL);
+                }
+                let message = &app.messages[app.head];
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::RECEIVE)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|buffer| {
+                            if buffer.len() < message.len {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            buffer[..message.len].copy_from_slice(&message.data[..message.len]);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+
+                let received = (
+                    ((message.kind as u32) << 16) | message.len as u32,
+                    message.sender,
+                    message.reply,
+                );
+                app.head = (app.head + 1) % DEPTH;
+                app.count -= 1;
+                Ok(received)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<const DEPTH: usize, const MAX_LEN: usize> SyscallDriver for Mailbox<DEPTH, MAX_LEN> {
+    /// Send and receive messages.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register as the service named in read-only allow `1`. Returns
+    ///   BUSY if another process uses the name.
+    /// - `2`: Look up the service named in read-only allow `1`. Returns its id,
+    ///   or NODEVICE if there is no such service.
+    /// - `3`: Send the message in read-only allow `0` to the service with id
+    ///   `data1`, as a request if `data2` is not zero. Returns NOMEM if the
+    ///   mailbox of the service is full.
+    /// - `4`: Send the message in read-only allow `0` as the reply to the
+    ///   request with reply token `data1`. Returns NOMEM if the mailbox of the
+    ///   client is full.
+    /// - `5`: Receive the oldest message into read-write allow `0`. Returns
+    ///   its length with its kind in the upper 16 bits, the id of its sender
+    ///   and its reply token, or FAIL if the mailbox is empty.
+    /// - `6`: Unregister the service.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.register(processid).into(),
+
+            2 => match self.discover(processid) {
+                Ok(service) => CommandReturn::success_u32(service),
+                Err(err) => CommandReturn::failure(err),
+            },
+
+            3 => self.send(processid, data1, data2 != 0).into(),
+
+            4 => self.reply(processid, data1).into(),
+
+            5 => match self.receive(processid) {
+                Ok((len, sender, reply)) => CommandReturn::success_u32_u32_u32(len, sender, reply),
+                Err(err) => CommandReturn::failure(err),
+            },
+
+            6 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.name_len = 0;
+                })
+                .map_err(ErrorCode::from)
+                .into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
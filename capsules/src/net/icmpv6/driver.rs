@@ -0,0 +1,243 @@
+//! Ping userspace interface.
+//!
+//! Implements a userspace interface for sending ICMPv6 Echo Requests and
+//! waiting for the matching Echo Replies, which allows processes to check
+//! whether other devices are reachable. Each process can have one Echo
+//! Request outstanding at a time. The identifier of the requests is derived
+//! from the process id, so that replies can be matched to the process that
+//! sent the request.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ping_driver = static_init!(
+//!     capsules::net::icmpv6::driver::PingDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::net::icmpv6::driver::PingDriver::new(
+//!         icmp_responder,
+//!         ping_alarm,
+//!         board_kernel.create_grant(capsules::net::icmpv6::driver::DRIVER_NUM, &grant_cap),
+//!     )
+//! );
+//! icmp_responder.set_echo_client(ping_driver);
+//! ping_alarm.set_alarm_client(ping_driver);
+//! ```
+
+use crate::net::icmpv6::icmpv6_responder::{ICMP6EchoClient, ICMP6Responder};
+use crate::net::ipv6::ip_utils::IPAddr;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Ping as usize;
+
+/// Time to wait for an Echo Reply, in milliseconds.
+pub const TIMEOUT_MS: u32 = 3000;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const ADDR: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Ids for subscribe upcalls
+mod upcall {
+    pub const REPLY: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+#[derive(Copy, Clone)]
+struct Ping {
+    seqno: u16,
+    /// Lower 32 bits of the time the request was sent at.
+    sent: u32,
+}
+
+#[derive(Default)]
+pub struct App {
+    ping: Option<Ping>,
+}
+
+pub struct PingDriver<'a, A: time::Alarm<'a>> {
+    responder: &'a ICMP6Responder<'a>,
+    alarm: &'a A,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+}
+
+impl<'a, A: time::Alarm<'a>> PingDriver<'a, A> {
+    pub fn new(
+        responder: &'a ICMP6Responder<'a>,
+        alarm: &'a A,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+    ) -> PingDriver<'a, A> {
+        PingDriver {
+            responder,
+            alarm,
+            apps: grant,
+        }
+    }
+
+    /// Returns the identifier of the Echo Requests sent for `processid`.
+    fn echo_id(processid: ProcessId) -> u16 {
+        processid.id() as u16
+    }
+
+    /// Returns the time since `ping` was sent.
+    fn elapsed(&self, ping: &Ping) -> A::Ticks {
+        A::Ticks::from(self.alarm.now().into_u32()).wrapping_sub(A::Ticks::from(ping.sent))
+    }
+
+    /// Sends an Echo Request with `len` bytes of payload to the address in
+    /// the address buffer of `processid`.
+    fn ping(&self, processid: ProcessId, seqno: u16, len: usize) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                if app.ping.is_some() {
+                    return Err(ErrorCode::BUSY);
+                }
+                let dst = kernel_data
+                    .get_readonly_processbuffer(ro_allow::ADDR)
+                    .and_then(|addr| {
+                        addr.enter(|addr| {
+                            if addr.len() != 16 {
+                                return None;
+                            }
+                            let mut dst = IPAddr::new();
+                            addr.copy_to_slice(&mut dst.0);
+                            Some(dst)
+                        })
+                    })
+                    .ok()
+                    .flatten()
+                    .ok_or(ErrorCode::INVAL)?;
+                self.responder
+                    .send_echo_request(dst, Self::echo_id(processid), seqno, len)?;
+                app.ping = Some(Ping {
+                    seqno,
+                    sent: self.alarm.now().into_u32(),
+                });
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+        self.reset_timer();
+        Ok(())
+    }
+
+    /// Arms the alarm for the outstanding request that times out first, or
+    /// disarms it if there is none.
+    fn reset_timer(&self) {
+        let timeout = self.alarm.ticks_from_ms(TIMEOUT_MS);
+        let mut next: Option<A::Ticks> = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if let Some(ping) = app.ping {
+                    let elapsed = self.elapsed(&ping);
+                    let remaining = if elapsed >= timeout {
+                        A::Ticks::from(0)
+                    } else {
+                        timeout.wrapping_sub(elapsed)
+                    };
+                    next = Some(next.map_or(remaining, |next| next.min(remaining)));
+                }
+            });
+        }
+        match next {
+            Some(remaining) => self.alarm.set_alarm(self.alarm.now(), remaining),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6EchoClient for PingDriver<'a, A> {
+    fn echo_reply(&self, _src_addr: IPAddr, id: u16, seqno: u16, _payload: &[u8]) {
+        self.apps.each(|processid, app, kernel_data| {
+            if Self::echo_id(processid) != id {
+                return;
+            }
+            if let Some(ping) = app.ping.filter(|ping| ping.seqno == seqno) {
+                app.ping = None;
+                let rtt = self.alarm.ticks_to_ms(self.elapsed(&ping));
+                kernel_data
+                    .schedule_upcall(upcall::REPLY, (0, seqno as usize, rtt as usize))
+                    .ok();
+            }
+        });
+        self.reset_timer();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for PingDriver<'a, A> {
+    fn alarm(&self) {
+        let timeout = self.alarm.ticks_from_ms(TIMEOUT_MS);
+        self.apps.each(|_, app, kernel_data| {
+            if let Some(ping) = app.ping.filter(|ping| self.elapsed(ping) >= timeout) {
+                app.ping = None;
+                kernel_data
+                    .schedule_upcall(
+                        upcall::REPLY,
+                        (
+                            into_statuscode(Err(ErrorCode::NOACK)),
+                            ping.seqno as usize,
+                            0,
+                        ),
+                    )
+                    .ok();
+            }
+        });
+        self.reset_timer();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> SyscallDriver for PingDriver<'a, A> {
+    /// Ping control
+    ///
+    /// The address buffer (read-only allow `0`) holds the 16 byte IPv6
+    /// address to send Echo Requests to.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send an Echo Request to the address in the address buffer.
+    ///        `arg1` is the sequence number and `arg2` the number of bytes
+    ///        of payload. Upcall `0` reports the reply, or that no reply
+    ///        arrived within `TIMEOUT_MS`. Returns BUSY if the process is
+    ///        already waiting for a reply or another message is being sent,
+    ///        INVAL if the address buffer is invalid, and SIZE if the
+    ///        payload is too long.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.ping(processid, arg1 as u16, arg2).into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
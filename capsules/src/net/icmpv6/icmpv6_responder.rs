@@ -0,0 +1,557 @@
+//! This file contains the [ICMP6Responder](struct.ICMP6Responder.html),
+//! which handles ICMPv6 messages received by the IPv6 layer.
+//!
+//! The responder answers Echo Requests sent to one of the addresses of the
+//! device, or to the link-local all-nodes address, with Echo Replies. It
+//! also answers Neighbor Solicitations for these addresses with Neighbor
+//! Advertisements that carry the extended MAC address of the device, as
+//! described in RFC 4861 and RFC 4944. Echo Replies are passed to the
+//! `ICMP6EchoClient`, which can send its own Echo Requests with
+//! `send_echo_request`.
+//!
+//! The addresses of the device are the addresses in the interface list and
+//! the link-local address derived from the extended MAC address.
+//!
+//! Only one message is sent at a time. Echo Replies and Neighbor
+//! Advertisements that would have to be sent while another message is
+//! being sent are dropped, and the sender of the request has to retry.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let icmp_responder = static_init!(
+//!     capsules::net::icmpv6::icmpv6_responder::ICMP6Responder<'static>,
+//!     capsules::net::icmpv6::icmpv6_responder::ICMP6Responder::new(
+//!         ip_send,
+//!         icmp_mac,
+//!         local_ip_ifaces,
+//!         &mut ICMP_TX_BUF,
+//!         net_cap,
+//!     )
+//! );
+//! ip_send.set_client(icmp_responder);
+//! ip_receive.set_client(icmp_responder);
+//! ```
+
+use crate::ieee802154::device::MacDevice;
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, TransportHeader, ICMP_HDR_LEN};
+use crate::net::network_capabilities::NetworkCapability;
+
+use core::cell::Cell;
+
+use kernel::debug;
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// Link-local all-nodes multicast address, ff02::1.
+const LINK_LOCAL_ALL_NODES: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+
+/// Prefix of solicited-node multicast addresses, ff02::1:ff00:0/104.
+const SOLICITED_NODE_PREFIX: [u8; 13] = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff];
+
+/// Neighbor Discovery messages must be sent with this hop limit, and
+/// received messages with a different hop limit were forwarded by a router.
+const ND_HOP_LIMIT: u8 = 255;
+
+/// Neighbor Advertisement flags.
+const NA_FLAG_SOLICITED: u32 = 0x4000_0000;
+const NA_FLAG_OVERRIDE: u32 = 0x2000_0000;
+
+/// Type of the Target Link-Layer Address option.
+const OPTION_TARGET_LL_ADDR: u8 = 2;
+
+/// Length of the Target Link-Layer Address option for an extended
+/// 802.15.4 address, in units of 8 bytes (RFC 4944, Section 8).
+const OPTION_LL_ADDR_LEN: u8 = 2;
+
+/// Length of a Neighbor Advertisement after the ICMPv6 header: the target
+/// address and the Target Link-Layer Address option.
+const NA_LEN: usize = 16 + 8 * OPTION_LL_ADDR_LEN as usize;
+
+/// A trait for clients of the `ICMP6Responder` that send Echo Requests.
+pub trait ICMP6EchoClient {
+    /// Called when an Echo Reply is received.
+    ///
+    /// `src_addr` - The address the reply was sent from
+    /// `id` - The identifier of the request
+    /// `seqno` - The sequence number of the request
+    /// `payload` - The data echoed by the remote device
+    fn echo_reply(&self, src_addr: IPAddr, id: u16, seqno: u16, payload: &[u8]);
+}
+
+pub struct ICMP6Responder<'a> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    mac: &'a dyn MacDevice<'a>,
+    /// List of IP Addresses of the interfaces on the device
+    interface_list: &'static [IPAddr],
+    /// Payload of the message being sent. Its length limits the size of
+    /// the echo messages that are sent.
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    sending: Cell<bool>,
+    echo_client: OptionalCell<&'a dyn ICMP6EchoClient>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a> ICMP6Responder<'a> {
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        mac: &'a dyn MacDevice<'a>,
+        interface_list: &'static [IPAddr],
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> ICMP6Responder<'a> {
+        ICMP6Responder {
+            ip_sender,
+            mac,
+            interface_list,
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            sending: Cell::new(false),
+            echo_client: OptionalCell::empty(),
+            net_cap,
+        }
+    }
+
+    pub fn set_echo_client(&self, client: &'a dyn ICMP6EchoClient) {
+        self.echo_client.set(client);
+    }
+
+    /// Returns the largest payload that can be sent in a message.
+    pub fn max_payload_len(&self) -> usize {
+        self.tx_buffer.map_or(0, |buf| buf.len())
+    }
+
+    /// Sends an Echo Request with `len` bytes of payload to `dst`. Replies
+    /// are passed to the `ICMP6EchoClient`. Returns BUSY if another message
+    /// is being sent, and SIZE if `len` exceeds `max_payload_len`.
+    pub fn send_echo_request(
+        &self,
+        dst: IPAddr,
+        id: u16,
+        seqno: u16,
+        len: usize,
+    ) -> Result<(), ErrorCode> {
+        if len > self.max_payload_len() {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut header = ICMP6Header::new(ICMP6Type::Type128);
+        header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
+        self.send(self.source_for(dst), dst, header, |buf| {
+            for (i, byte) in buf[..len].iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+            len
+        })
+    }
+
+    /// Returns the link-local address derived from the extended MAC
+    /// address.
+    fn link_local_addr(&self) -> IPAddr {
+        IPAddr::generate_from_mac(MacAddress::Long(self.mac.get_address_long()))
+    }
+
+    /// Returns true if `addr` is a unicast address of this device.
+    fn is_local_addr(&self, addr: &IPAddr) -> bool {
+        self.interface_list.contains(addr) || *addr == self.link_local_addr()
+    }
+
+    /// Returns true if `addr` is the solicited-node multicast address of
+    /// one of the addresses of this device.
+    fn is_solicited_node_addr(&self, addr: &IPAddr) -> bool {
+        addr.0[..13] == SOLICITED_NODE_PREFIX
+            && self
+                .interface_list
+                .iter()
+                .chain(core::iter::once(&self.link_local_addr()))
+                .any(|local| local.0[13..] == addr.0[13..])
+    }
+
+    /// Returns the address to send messages to `dst` from.
+    fn source_for(&self, dst: IPAddr) -> IPAddr {
+        if dst.is_unicast_link_local() || dst.is_multicast() || self.interface_list.is_empty() {
+            self.link_local_addr()
+        } else {
+            self.interface_list[0]
+        }
+    }
+
+    /// Sends a message with `header`. `fill` writes the payload into the
+    /// buffer it is passed and returns its length.
+    fn send<F: FnOnce(&mut [u8]) -> usize>(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        header: ICMP6Header,
+        fill: F,
+    ) -> Result<(), ErrorCode> {
+        if self.sending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let mut buf = self.tx_buffer.take().ok_or(ErrorCode::BUSY)?;
+        buf.reset();
+        let len = fill(&mut buf[..]);
+        buf.slice(..len);
+
+        self.sending.set(true);
+        self.ip_sender.set_addr(src);
+        let result = self
+            .ip_sender
+            .send_to(dst, TransportHeader::ICMP(header), &buf, self.net_cap);
+        self.tx_buffer.replace(buf);
+        if result.is_err() {
+            self.sending.set(false);
+        }
+        result
+    }
+
+    fn receive_echo_request(&self, ip_header: &IP6Header, id: u16, seqno: u16, data: &[u8]) {
+        let dst = ip_header.get_dst_addr();
+        let src = ip_header.get_src_addr();
+        if src.is_multicast() || src.is_unspecified() || data.len() > self.max_payload_len() {
+            return;
+        }
+        let reply_src = if self.is_local_addr(&dst) {
+            dst
+        } else {
+            self.source_for(src)
+        };
+        let mut header = ICMP6Header::new(ICMP6Type::Type129);
+        header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+        let result = self.send(reply_src, src, header, |buf| {
+            buf[..data.len()].copy_from_slice(data);
+            data.len()
+        });
+        if result.is_err() {
+            debug!("[ICMP6] dropped echo reply: {:?}", result);
+        }
+    }
+
+    fn receive_neighbor_solicitation(&self, ip_header: &IP6Header, code: u8, data: &[u8]) {
+        if ip_header.get_hop_limit() != ND_HOP_LIMIT
+            || code != 0
+            || data.len() < 16
+            || self.max_payload_len() < NA_LEN
+        {
+            return;
+        }
+        let mut target = IPAddr::new();
+        target.0.copy_from_slice(&data[..16]);
+        if target.is_multicast() || !self.is_local_addr(&target) {
+            return;
+        }
+
+        // Solicitations from the unspecified address are sent during
+        // duplicate address detection, and are answered to all nodes.
+        let src = ip_header.get_src_addr();
+        let (dst, flags) = if src.is_unspecified() {
+            (LINK_LOCAL_ALL_NODES, NA_FLAG_OVERRIDE)
+        } else {
+            (src, NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE)
+        };
+        let mut header = ICMP6Header::new(ICMP6Type::Type136);
+        header.set_options(ICMP6HeaderOptions::Type136 { flags });
+        let ext_addr = self.mac.get_address_long();
+        let result = self.send(target, dst, header, |buf| {
+            buf[..16].copy_from_slice(&target.0);
+            buf[16] = OPTION_TARGET_LL_ADDR;
+            buf[17] = OPTION_LL_ADDR_LEN;
+            buf[18..26].copy_from_slice(&ext_addr);
+            // Pad the option to a multiple of 8 bytes.
+            buf[26..NA_LEN].iter_mut().for_each(|byte| *byte = 0);
+            NA_LEN
+        });
+        if result.is_err() {
+            debug!("[ICMP6] dropped neighbor advertisement: {:?}", result);
+        }
+    }
+}
+
+impl<'a> IP6SendClient for ICMP6Responder<'a> {
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        if result != Ok(()) {
+            debug!("[ICMP6] send failed: {:?}", result);
+        }
+        self.sending.set(false);
+    }
+}
+
+impl<'a> IP6RecvClient for ICMP6Responder<'a> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::ICMP {
+            return;
+        }
+        let dst = ip_header.get_dst_addr();
+        if !self.is_local_addr(&dst)
+            && dst != LINK_LOCAL_ALL_NODES
+            && !self.is_solicited_node_addr(&dst)
+        {
+            return;
+        }
+        let header = match ICMP6Header::decode(payload).done() {
+            Some((_, header)) => header,
+            None => return,
+        };
+        let data = &payload[ICMP_HDR_LEN..];
+        match header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                self.receive_echo_request(&ip_header, id, seqno, data)
+            }
+            ICMP6HeaderOptions::Type129 { id, seqno } => {
+                if self.is_local_addr(&dst) {
+                    self.echo_client
+                        .map(|client| client.echo_reply(ip_header.get_src_addr(), id, seqno, data));
+                }
+            }
+            ICMP6HeaderOptions::Type135 { .. } => {
+                self.receive_neighbor_solicitation(&ip_header, header.get_code(), data)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::ieee802154::device::{RxClient, TxClient};
+    use crate::ieee802154::framer::Frame;
+    use crate::net::ieee802154::{KeyId, PanID, SecurityLevel};
+    use crate::net::ipv6::{IP6Packet, IPPayload};
+    use std::boxed::Box;
+
+    const EXT_ADDR: [u8; 8] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+    /// The link-local address derived from `EXT_ADDR`.
+    const LINK_LOCAL: IPAddr = IPAddr([
+        0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+    ]);
+    const GLOBAL: IPAddr = IPAddr([0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    const PEER: IPAddr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+    const PEER_GLOBAL: IPAddr = IPAddr([0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+    const OTHER: IPAddr = IPAddr([0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3]);
+    /// Solicited-node multicast address of `LINK_LOCAL`, ff02::1:ff55:6677.
+    const SOLICITED: IPAddr = IPAddr([
+        0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, 0x55, 0x66, 0x77,
+    ]);
+    const UNSPECIFIED: IPAddr = IPAddr([0; 16]);
+    static INTERFACES: [IPAddr; 1] = [GLOBAL];
+
+    struct FakeMac;
+
+    impl<'a> MacDevice<'a> for FakeMac {
+        fn set_transmit_client(&self, _client: &'a dyn TxClient) {}
+        fn set_receive_client(&self, _client: &'a dyn RxClient) {}
+        fn get_address(&self) -> u16 {
+            0
+        }
+        fn get_address_long(&self) -> [u8; 8] {
+            EXT_ADDR
+        }
+        fn get_pan(&self) -> u16 {
+            0
+        }
+        fn set_address(&self, _addr: u16) {}
+        fn set_address_long(&self, _addr: [u8; 8]) {}
+        fn set_pan(&self, _id: u16) {}
+        fn config_commit(&self) {}
+        fn is_on(&self) -> bool {
+            true
+        }
+        fn prepare_data_frame(
+            &self,
+            buf: &'static mut [u8],
+            _dst_pan: PanID,
+            _dst_addr: MacAddress,
+            _src_pan: PanID,
+            _src_addr: MacAddress,
+            _security_needed: Option<(SecurityLevel, KeyId)>,
+        ) -> Result<Frame, &'static mut [u8]> {
+            Err(buf)
+        }
+        fn transmit(&self, frame: Frame) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            Err((ErrorCode::OFF, frame.into_buf()))
+        }
+    }
+
+    /// Encodes the last packet sent the way `IP6SendStruct` does.
+    struct FakeIp {
+        src_addr: Cell<IPAddr>,
+        sent: MapCell<([u8; 128], usize)>,
+    }
+
+    impl<'a> IP6Sender<'a> for FakeIp {
+        fn set_client(&self, _client: &'a dyn IP6SendClient) {}
+        fn set_addr(&self, src_addr: IPAddr) {
+            self.src_addr.set(src_addr);
+        }
+        fn set_gateway(&self, _gateway: MacAddress) {}
+        fn set_header(&mut self, _ip6_header: IP6Header) {}
+        fn send_to(
+            &self,
+            dst: IPAddr,
+            transport_header: TransportHeader,
+            payload: &LeasableBuffer<'static, u8>,
+            _net_cap: &'static NetworkCapability,
+        ) -> Result<(), ErrorCode> {
+            let mut payload_buf = [0; 64];
+            let mut packet = IP6Packet::new(IPPayload::new(transport_header, &mut payload_buf));
+            packet.header.src_addr = self.src_addr.get();
+            packet.header.dst_addr = dst;
+            packet.set_payload(transport_header, payload);
+            packet.set_transport_checksum();
+            let mut buf = [0; 128];
+            let (len, _) = packet.encode(&mut buf).done().unwrap();
+            self.sent.replace((buf, len));
+            Ok(())
+        }
+    }
+
+    type Packet = ([u8; 128], usize);
+
+    /// Delivers an ICMPv6 message and returns the reply, if any.
+    fn deliver(
+        src: IPAddr,
+        dst: IPAddr,
+        hop_limit: u8,
+        header: ICMP6Header,
+        data: &[u8],
+    ) -> Option<Packet> {
+        let ip = FakeIp {
+            src_addr: Cell::new(IPAddr::new()),
+            sent: MapCell::empty(),
+        };
+        let responder = ICMP6Responder::new(
+            &ip,
+            &FakeMac,
+            &INTERFACES,
+            Box::leak(Box::new([0; 32])),
+            Box::leak(Box::new(NetworkCapability::new_unrestricted())),
+        );
+
+        let mut ip_header = IP6Header::new();
+        ip_header.src_addr = src;
+        ip_header.dst_addr = dst;
+        ip_header.set_next_header(ip6_nh::ICMP);
+        ip_header.set_hop_limit(hop_limit);
+        let mut payload = [0; 64];
+        header.encode(&mut payload, 0).done().unwrap();
+        payload[ICMP_HDR_LEN..ICMP_HDR_LEN + data.len()].copy_from_slice(data);
+        responder.receive(ip_header, &payload[..ICMP_HDR_LEN + data.len()]);
+        ip.sent.take()
+    }
+
+    fn echo(src: IPAddr, dst: IPAddr) -> Option<Packet> {
+        let mut header = ICMP6Header::new(ICMP6Type::Type128);
+        header.set_options(ICMP6HeaderOptions::Type128 {
+            id: 0x1234,
+            seqno: 7,
+        });
+        deliver(src, dst, 64, header, &[0xde, 0xad])
+    }
+
+    fn solicit(
+        src: IPAddr,
+        dst: IPAddr,
+        hop_limit: u8,
+        code: u8,
+        target: IPAddr,
+    ) -> Option<Packet> {
+        let mut header = ICMP6Header::new(ICMP6Type::Type135);
+        header.set_code(code);
+        deliver(src, dst, hop_limit, header, &target.0)
+    }
+
+    /// Checks the addresses of a sent packet, and its checksum by summing the
+    /// pseudo-header and the ICMPv6 message, and returns the message.
+    fn check_packet(packet: &Packet, src: IPAddr, dst: IPAddr) -> &[u8] {
+        let packet = &packet.0[..packet.1];
+        assert_eq!(packet[8..24], src.0, "source address");
+        assert_eq!(packet[24..40], dst.0, "destination address");
+        let message = &packet[40..];
+        let mut sum: u32 = (message.len() + ip6_nh::ICMP as usize) as u32;
+        for bytes in [&packet[8..40], message].iter() {
+            for word in bytes.chunks(2) {
+                sum += (word[0] as u32) << 8 | *word.get(1).unwrap_or(&0) as u32;
+            }
+        }
+        while sum > 0xffff {
+            sum = (sum >> 16) + (sum & 0xffff);
+        }
+        assert_eq!(sum, 0xffff, "checksum");
+        message
+    }
+
+    #[test]
+    fn test_echo_reply() {
+        let reply = echo(PEER, LINK_LOCAL).unwrap();
+        let message = check_packet(&reply, LINK_LOCAL, PEER);
+        assert_eq!(message[..2], [129, 0]);
+        assert_eq!(message[4..], [0x12, 0x34, 0, 7, 0xde, 0xad]);
+    }
+
+    #[test]
+    fn test_echo_reply_source() {
+        // The reply comes from the address the request was sent to, or the
+        // address of the same scope as the sender for multicast requests.
+        for &(src, dst, reply_src) in [
+            (PEER_GLOBAL, GLOBAL, GLOBAL),
+            (PEER_GLOBAL, LINK_LOCAL, LINK_LOCAL),
+            (PEER, LINK_LOCAL_ALL_NODES, LINK_LOCAL),
+            (PEER_GLOBAL, LINK_LOCAL_ALL_NODES, GLOBAL),
+        ]
+        .iter()
+        {
+            check_packet(&echo(src, dst).unwrap(), reply_src, src);
+        }
+    }
+
+    #[test]
+    fn test_echo_request_ignored() {
+        assert!(echo(PEER_GLOBAL, OTHER).is_none());
+        // Never reply to multicast or unspecified sources.
+        assert!(echo(LINK_LOCAL_ALL_NODES, LINK_LOCAL).is_none());
+        assert!(echo(UNSPECIFIED, LINK_LOCAL).is_none());
+    }
+
+    #[test]
+    fn test_neighbor_advertisement() {
+        let flags = (NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE).to_be_bytes();
+        let reply = solicit(PEER, SOLICITED, 255, 0, LINK_LOCAL).unwrap();
+        let message = check_packet(&reply, LINK_LOCAL, PEER);
+        assert_eq!(message.len(), ICMP_HDR_LEN + NA_LEN);
+        assert_eq!(message[..2], [136, 0]);
+        assert_eq!(message[4..8], flags);
+        assert_eq!(message[8..24], LINK_LOCAL.0);
+        assert_eq!(message[24..26], [OPTION_TARGET_LL_ADDR, OPTION_LL_ADDR_LEN]);
+        assert_eq!(message[26..34], EXT_ADDR);
+
+        let reply = solicit(PEER_GLOBAL, GLOBAL, 255, 0, GLOBAL).unwrap();
+        assert_eq!(check_packet(&reply, GLOBAL, PEER_GLOBAL)[4..8], flags);
+    }
+
+    #[test]
+    fn test_duplicate_address_detection() {
+        // Solicitations from the unspecified address are answered to all
+        // nodes, without the solicited flag.
+        let reply = solicit(UNSPECIFIED, SOLICITED, 255, 0, LINK_LOCAL).unwrap();
+        let message = check_packet(&reply, LINK_LOCAL, LINK_LOCAL_ALL_NODES);
+        assert_eq!(message[4..8], NA_FLAG_OVERRIDE.to_be_bytes());
+    }
+
+    #[test]
+    fn test_neighbor_solicitation_ignored() {
+        // Only solicitations with a hop limit of 255 come from the link.
+        assert!(solicit(PEER, SOLICITED, 254, 0, LINK_LOCAL).is_none());
+        assert!(solicit(PEER, SOLICITED, 255, 1, LINK_LOCAL).is_none());
+        assert!(solicit(PEER_GLOBAL, GLOBAL, 255, 0, OTHER).is_none());
+        assert!(solicit(PEER_GLOBAL, GLOBAL, 255, 0, LINK_LOCAL_ALL_NODES).is_none());
+    }
+}
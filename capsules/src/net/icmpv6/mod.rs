@@ -1,3 +1,5 @@
+pub mod driver;
+pub mod icmpv6_responder;
 pub mod icmpv6_send;
 
 // Reexport the exports of the [`icmpv6`] module, to avoid redundant
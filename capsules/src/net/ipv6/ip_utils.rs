@@ -5,6 +5,7 @@
 use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::IP6Header;
+use crate::net::tcp::TCPHeader;
 use crate::net::udp::UDPHeader;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -197,7 +198,10 @@ pub fn compute_icmp_checksum(
 
     // add options
     match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
+        ICMP6HeaderOptions::Type1 { unused }
+        | ICMP6HeaderOptions::Type3 { unused }
+        | ICMP6HeaderOptions::Type135 { reserved: unused }
+        | ICMP6HeaderOptions::Type136 { flags: unused } => {
             sum += unused >> 16; // upper 16 bits
             sum += unused & 0xffff; // lower 16 bits
         }
@@ -215,7 +219,7 @@ pub fn compute_icmp_checksum(
     while sum > 0xffff {
         let sum_upper = sum >> 16;
         let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = sum_upper + sum_lower;
     }
 
     sum = !sum;
@@ -224,6 +228,53 @@ pub fn compute_icmp_checksum(
     sum as u16
 }
 
+/// Computes the TCP checksum of a segment. `payload` holds everything after
+/// the fixed 20 byte header, including any options, and `tcp_header.get_len()`
+/// must be the length of the whole segment. When computed over a received
+/// segment whose checksum is correct, the result is 0.
+pub fn compute_tcp_checksum(ip6_header: &IP6Header, tcp_header: &TCPHeader, payload: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+
+    // add ipv6 pseudo-header
+    for i in (0..16).step_by(2) {
+        sum += ((ip6_header.src_addr.0[i] as u32) << 8) + ip6_header.src_addr.0[i + 1] as u32;
+        sum += ((ip6_header.dst_addr.0[i] as u32) << 8) + ip6_header.dst_addr.0[i + 1] as u32;
+    }
+    sum += tcp_header.get_len() as u32;
+    sum += ip6_nh::TCP as u32;
+
+    // add tcp header
+    sum += tcp_header.get_src_port() as u32;
+    sum += tcp_header.get_dst_port() as u32;
+    sum += tcp_header.get_seq_num() >> 16;
+    sum += tcp_header.get_seq_num() & 0xffff;
+    sum += tcp_header.get_ack_num() >> 16;
+    sum += tcp_header.get_ack_num() & 0xffff;
+    sum += tcp_header.offset_and_control as u32;
+    sum += tcp_header.get_window() as u32;
+    sum += tcp_header.get_cksum() as u32;
+    sum += tcp_header.get_urg_ptr() as u32;
+
+    // add tcp payload, padding an odd final byte with zero
+    let payload_len = tcp_header.get_len() as usize - 20;
+    for i in (0..payload_len).step_by(2) {
+        let msb = (payload[i] as u32) << 8;
+        let lsb = if i + 1 < payload_len {
+            payload[i + 1] as u32
+        } else {
+            0
+        };
+        sum += msb + lsb;
+    }
+
+    // carry overflow
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+
+    !sum as u16
+}
+
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
     let mut sum: u32 = 0;
 
@@ -241,7 +292,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -253,7 +304,12 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        // An odd trailing byte is padded with zero.
+        let lsb = if i + 1 < len as usize {
+            buf[i + 1] as u32
+        } else {
+            0
+        };
         sum += msb + lsb;
         i += 2;
     }
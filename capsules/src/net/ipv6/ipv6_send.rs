@@ -31,6 +31,9 @@ use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::utilities::leasable_buffer::LeasableBuffer;
 use kernel::ErrorCode;
 
+/// Short MAC address that is received by all devices on the link.
+const BROADCAST_MAC_ADDR: u16 = 0xffff;
+
 /// This trait must be implemented by upper layers in order to receive
 /// the `send_done` callback when a transmission has completed. The upper
 /// layer must then call `IP6Sender.set_client` in order to receive this
@@ -103,7 +106,6 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
     client: OptionalCell<&'a dyn IP6SendClient>,
     ip_vis: &'static IpVisibilityCapability,
@@ -137,12 +139,16 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
             return Err(ErrorCode::FAIL);
         }
-        let _ = self.sixlowpan.init(
-            self.src_mac_addr,
-            self.dst_mac_addr,
-            self.radio.get_pan(),
-            None,
-        );
+        // Multicast packets are broadcast on the link, everything else is
+        // sent to the gateway.
+        let dst_mac_addr = if dst.is_multicast() {
+            MacAddress::Short(BROADCAST_MAC_ADDR)
+        } else {
+            self.gateway.get()
+        };
+        let _ = self
+            .sixlowpan
+            .init(self.src_mac_addr, dst_mac_addr, self.radio.get_pan(), None);
         self.init_packet(dst, transport_header, payload);
         let ret = self.send_next_fragment();
         ret
@@ -168,7 +174,6 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
             client: OptionalCell::empty(),
             ip_vis: ip_vis,
@@ -166,3 +166,16 @@ impl NetworkCapability {
         self.local_ports.is_port_valid(local_port)
     }
 }
+
+#[cfg(test)]
+impl NetworkCapability {
+    /// Creates a capability for any address and port. Unit tests can't
+    /// create a `NetworkCapabilityCreationCapability` in this crate.
+    pub(crate) fn new_unrestricted() -> NetworkCapability {
+        NetworkCapability {
+            remote_addrs: AddrRange::Any,
+            remote_ports: PortRange::Any,
+            local_ports: PortRange::Any,
+        }
+    }
+}
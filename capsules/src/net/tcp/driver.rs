@@ -0,0 +1,393 @@
+//! TCP userspace interface.
+//!
+//! Implements a userspace interface for TCP connections. Each process can
+//! have one connection at a time, which it either opens to a remote endpoint
+//! or waits for by listening on a local port. The connections use a fixed set
+//! of `TCPSocket`s, which are handed out to processes as they open
+//! connections and returned once the connection is closed.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tcp_driver = static_init!(
+//!     capsules::net::tcp::TCPDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::net::tcp::TCPDriver::new(
+//!         tcp_sockets,
+//!         board_kernel.create_grant(capsules::net::tcp::DRIVER_NUM, &grant_cap),
+//!         local_ip_ifaces,
+//!     )
+//! );
+//! for socket in tcp_sockets.iter() {
+//!     socket.set_client(tcp_driver);
+//! }
+//! ```
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::tcp::tcp_socket::{TCPClient, TCPSocket, TcpState};
+
+use core::cmp;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::time;
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Tcp as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const WRITE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const READ: usize = 0;
+    pub const CFG: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for subscribe upcalls
+mod upcall {
+    pub const CONNECTED: usize = 0;
+    pub const RECEIVED: usize = 1;
+    pub const SENT: usize = 2;
+    pub const CLOSED: usize = 3;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 4;
+}
+
+/// Length of an endpoint in the config buffer: a 16 byte IPv6 address
+/// followed by a port in host byte order.
+const ENDPOINT_LEN: usize = 18;
+
+#[derive(Copy, Clone)]
+struct TCPEndpoint {
+    addr: IPAddr,
+    port: u16,
+}
+
+impl TCPEndpoint {
+    fn decode(buf: &[u8; ENDPOINT_LEN]) -> TCPEndpoint {
+        let mut addr = IPAddr::new();
+        addr.0.copy_from_slice(&buf[..16]);
+        TCPEndpoint {
+            addr,
+            port: u16::from_ne_bytes([buf[16], buf[17]]),
+        }
+    }
+
+    fn encode(&self, buf: &mut [u8; ENDPOINT_LEN]) {
+        buf[..16].copy_from_slice(&self.addr.0);
+        buf[16..].copy_from_slice(&self.port.to_ne_bytes());
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    /// Index of the socket used by this process.
+    socket: Option<usize>,
+}
+
+pub struct TCPDriver<'a, A: time::Alarm<'a>> {
+    sockets: &'a [TCPSocket<'a, A>],
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// List of IP Addresses of the interfaces on the device
+    interface_list: &'static [IPAddr],
+}
+
+impl<'a, A: time::Alarm<'a>> TCPDriver<'a, A> {
+    /// `sockets` - The sockets available to processes. The id of each socket
+    /// must be its index in this slice.
+    pub fn new(
+        sockets: &'a [TCPSocket<'a, A>],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        interface_list: &'static [IPAddr],
+    ) -> TCPDriver<'a, A> {
+        TCPDriver {
+            sockets,
+            apps: grant,
+            interface_list,
+        }
+    }
+
+    /// Reads the local and remote endpoints from the config buffer.
+    fn read_endpoints(
+        &self,
+        kernel_data: &GrantKernelData,
+    ) -> Result<(TCPEndpoint, TCPEndpoint), ErrorCode> {
+        kernel_data
+            .get_readwrite_processbuffer(rw_allow::CFG)
+            .and_then(|cfg| {
+                cfg.enter(|cfg| {
+                    if cfg.len() != 2 * ENDPOINT_LEN {
+                        return Err(ErrorCode::INVAL);
+                    }
+                    let mut local = [0; ENDPOINT_LEN];
+                    let mut remote = [0; ENDPOINT_LEN];
+                    cfg[..ENDPOINT_LEN].copy_to_slice(&mut local);
+                    cfg[ENDPOINT_LEN..].copy_to_slice(&mut remote);
+                    Ok((TCPEndpoint::decode(&local), TCPEndpoint::decode(&remote)))
+                })
+            })
+            .unwrap_or(Err(ErrorCode::INVAL))
+    }
+
+    /// Returns the index of a socket that no process is using. Sockets
+    /// still connected for processes that no longer exist are aborted.
+    fn allocate_socket(&self) -> Option<usize> {
+        let id = (0..self.sockets.len()).find(|&id| {
+            !self
+                .apps
+                .iter()
+                .any(|app| app.enter(|app, _| app.socket == Some(id)))
+        })?;
+        if self.sockets[id].get_state() != TcpState::Closed {
+            self.sockets[id].abort();
+        }
+        Some(id)
+    }
+
+    /// Opens a connection for `processid`, actively if `active` is true and
+    /// by listening otherwise.
+    fn open(&self, processid: ProcessId, active: bool) -> Result<(), ErrorCode> {
+        let (local, remote) = self
+            .apps
+            .enter(processid, |app, kernel_data| {
+                if app.socket.is_some() {
+                    return Err(ErrorCode::BUSY);
+                }
+                self.read_endpoints(kernel_data)
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        // The local address must be one of our interfaces, or unspecified.
+        if !local.addr.is_unspecified() && !self.interface_list.contains(&local.addr) {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let id = self.allocate_socket().ok_or(ErrorCode::NOMEM)?;
+        let socket = &self.sockets[id];
+        if active {
+            socket.connect(local.port, remote.addr, remote.port)?;
+        } else {
+            socket.listen(local.port)?;
+        }
+        self.apps
+            .enter(processid, |app, _| {
+                app.socket = Some(id);
+            })
+            .map_err(|err| {
+                socket.abort();
+                err.into()
+            })
+    }
+
+    /// Calls `fun` with the socket of `processid`.
+    fn with_socket<F>(&self, processid: ProcessId, fun: F) -> Result<u32, ErrorCode>
+    where
+        F: FnOnce(&TCPSocket<'a, A>, &GrantKernelData) -> Result<u32, ErrorCode>,
+    {
+        self.apps
+            .enter(processid, |app, kernel_data| match app.socket {
+                Some(id) => fun(&self.sockets[id], kernel_data),
+                None => Err(ErrorCode::RESERVE),
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Calls `fun` for the process using socket `socket_id`.
+    fn with_owner<F>(&self, socket_id: usize, fun: F)
+    where
+        F: FnOnce(&mut App, &GrantKernelData),
+    {
+        let mut fun = Some(fun);
+        self.apps.each(|_, app, kernel_data| {
+            if app.socket == Some(socket_id) {
+                fun.take().map(|fun| fun(app, kernel_data));
+            }
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> TCPClient for TCPDriver<'a, A> {
+    fn connected(&self, socket_id: usize, result: Result<(), ErrorCode>) {
+        let (remote_addr, remote_port) = self.sockets[socket_id].get_remote();
+        self.with_owner(socket_id, |app, kernel_data| {
+            if result.is_ok() {
+                // Tell the process who it is connected to.
+                let mut remote = [0; ENDPOINT_LEN];
+                TCPEndpoint {
+                    addr: remote_addr,
+                    port: remote_port,
+                }
+                .encode(&mut remote);
+                let _ = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::CFG)
+                    .and_then(|cfg| {
+                        cfg.mut_enter(|cfg| {
+                            if cfg.len() == 2 * ENDPOINT_LEN {
+                                cfg[ENDPOINT_LEN..].copy_from_slice(&remote);
+                            }
+                        })
+                    });
+            } else {
+                app.socket = None;
+            }
+            kernel_data
+                .schedule_upcall(upcall::CONNECTED, (into_statuscode(result), 0, 0))
+                .ok();
+        });
+    }
+
+    fn data_available(&self, socket_id: usize, len: usize) {
+        self.with_owner(socket_id, |_, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::RECEIVED, (len, 0, 0))
+                .ok();
+        });
+    }
+
+    fn send_done(&self, socket_id: usize, acked: usize) {
+        self.with_owner(socket_id, |_, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::SENT, (acked, 0, 0))
+                .ok();
+        });
+    }
+
+    fn remote_closed(&self, socket_id: usize) {
+        let len = self.sockets[socket_id].bytes_available();
+        self.with_owner(socket_id, |_, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::RECEIVED, (len, 1, 0))
+                .ok();
+        });
+    }
+
+    fn closed(&self, socket_id: usize, result: Result<(), ErrorCode>) {
+        self.with_owner(socket_id, |app, kernel_data| {
+            app.socket = None;
+            kernel_data
+                .schedule_upcall(upcall::CLOSED, (into_statuscode(result), 0, 0))
+                .ok();
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> SyscallDriver for TCPDriver<'a, A> {
+    /// TCP control
+    ///
+    /// The config buffer (read-write allow `1`) holds two endpoints of 18
+    /// bytes each: the local endpoint followed by the remote endpoint. An
+    /// endpoint is a 16 byte IPv6 address followed by a port in host byte
+    /// order. Received data is copied to read-write allow `0`, and data to
+    /// send is taken from read-only allow `0`.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Connect from the local endpoint in the config buffer to the
+    ///        remote endpoint. A local port of 0 picks an ephemeral port.
+    ///        Upcall `0` reports whether the connection was established.
+    ///        Returns BUSY if the process already has a connection or the
+    ///        local port is in use, INVAL if the config buffer is invalid,
+    ///        and NOMEM if no socket is free.
+    /// - `2`: Listen on the local endpoint in the config buffer. Upcall `0`
+    ///        reports when a peer connected, and the peer's endpoint is
+    ///        written to the remote half of the config buffer. Returns the
+    ///        same errors as `1`.
+    /// - `3`: Send the contents of the write buffer. Returns the number of
+    ///        bytes queued, which may be less than the buffer length. Upcall
+    ///        `2` reports how many bytes the peer acknowledged. Returns BUSY
+    ///        if no data could be queued, and INVAL if the connection is not
+    ///        established.
+    /// - `4`: Copy received data into the read buffer. Returns the number of
+    ///        bytes copied. Upcall `1` reports how many bytes are available,
+    ///        and has a second argument of 1 once the peer closed the
+    ///        connection.
+    /// - `5`: Close the connection once all data has been sent. Upcall `3`
+    ///        reports when the connection is closed.
+    /// - `6`: Abort the connection immediately. No upcall is scheduled.
+    ///
+    /// Commands `3` to `6` return RESERVE if the process has no connection.
+    fn command(
+        &self,
+        command_num: usize,
+        _arg1: usize,
+        _arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.open(processid, true).into(),
+            2 => self.open(processid, false).into(),
+            3 => self
+                .with_socket(processid, |socket, kernel_data| {
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::WRITE)
+                        .and_then(|write| {
+                            write.enter(|payload| {
+                                socket.send_with(payload.len(), |buf| {
+                                    payload[..buf.len()].copy_to_slice(buf)
+                                })
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::INVAL))
+                        .map(|queued| queued as u32)
+                })
+                .map_or_else(CommandReturn::failure, CommandReturn::success_u32),
+            4 => self
+                .with_socket(processid, |socket, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::READ)
+                        .and_then(|read| {
+                            read.mut_enter(|rbuf| {
+                                socket.receive(|data| {
+                                    let len = cmp::min(data.len(), rbuf.len());
+                                    rbuf[..len].copy_from_slice(&data[..len]);
+                                    len
+                                })
+                            })
+                        })
+                        .map(|copied| copied as u32)
+                        .map_err(ErrorCode::from)
+                })
+                .map_or_else(CommandReturn::failure, CommandReturn::success_u32),
+            5 => self
+                .with_socket(processid, |socket, _| socket.close().map(|()| 0))
+                .map_or_else(CommandReturn::failure, |_| CommandReturn::success()),
+            6 => self
+                .apps
+                .enter(processid, |app, _| match app.socket.take() {
+                    Some(id) => {
+                        self.sockets[id].abort();
+                        CommandReturn::success()
+                    }
+                    None => CommandReturn::failure(ErrorCode::RESERVE),
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,238 @@
+//! This file contains the [MuxTcp](struct.MuxTcp.html), which connects all
+//! [TCPSocket](../tcp_socket/struct.TCPSocket.html)s to the IPv6 layer.
+//!
+//! Received TCP segments are dispatched to the socket whose connection they
+//! belong to, or else to a socket listening on the destination port. Segments
+//! for which there is no socket are answered with a RST.
+//!
+//! Sockets do not send segments themselves. Whenever the IPv6 sender is idle,
+//! the mux asks each socket in turn whether it has a segment to send, and
+//! transmits the first one it gets. Because sockets are asked in order, a
+//! socket that always has data to send can delay sockets later in the list.
+//!
+//! The mux also owns a periodic timer that ticks every `TIMER_TICK_MS` while
+//! any socket has a retransmission or TIME-WAIT timer running.
+
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::tcp::tcp_socket::{TCPSocket, TcpState};
+use crate::net::tcp::{tcp_flags, TCPHeader};
+
+use core::cell::Cell;
+
+use kernel::collections::list::List;
+use kernel::debug;
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// Period of the timer that drives retransmissions, in milliseconds.
+pub const TIMER_TICK_MS: u32 = 250;
+
+/// First port of the range ephemeral ports are picked from.
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+pub struct MuxTcp<'a, A: time::Alarm<'a>> {
+    socket_list: List<'a, TCPSocket<'a, A>>,
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    /// Payload of the segment being sent. Its length limits the size of
+    /// the segments sent.
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    sending: Cell<bool>,
+    timer_armed: Cell<bool>,
+    next_port: Cell<u16>,
+    /// RST to send in response to a segment that matched no socket.
+    pending_rst: OptionalCell<(IPAddr, TCPHeader)>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> MuxTcp<'a, A> {
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> MuxTcp<'a, A> {
+        MuxTcp {
+            socket_list: List::new(),
+            ip_sender,
+            alarm,
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            sending: Cell::new(false),
+            timer_armed: Cell::new(false),
+            next_port: Cell::new(EPHEMERAL_PORT_START),
+            pending_rst: OptionalCell::empty(),
+            net_cap,
+        }
+    }
+
+    pub fn add_socket(&self, socket: &'a TCPSocket<'a, A>) {
+        self.socket_list.push_tail(socket);
+    }
+
+    /// Returns true if any open socket uses `port` as its local port.
+    pub fn port_in_use(&self, port: u16) -> bool {
+        self.socket_list
+            .iter()
+            .any(|socket| socket.get_state() != TcpState::Closed && socket.get_local_port() == port)
+    }
+
+    /// Returns a free port from the ephemeral port range, if there is one.
+    pub(crate) fn ephemeral_port(&self) -> Option<u16> {
+        let range = u16::MAX - EPHEMERAL_PORT_START + 1;
+        for _ in 0..range {
+            let port = self.next_port.get();
+            self.next_port.set(if port == u16::MAX {
+                EPHEMERAL_PORT_START
+            } else {
+                port + 1
+            });
+            if !self.port_in_use(port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    /// Generates an initial sequence number from the current time. This
+    /// avoids reusing sequence numbers of earlier connections, but is not
+    /// unpredictable.
+    pub(crate) fn generate_iss(&self) -> u32 {
+        self.alarm.now().into_u32().wrapping_mul(2654435761)
+    }
+
+    /// Starts the timer, if it is not running already.
+    pub(crate) fn start_timer(&self) {
+        if !self.timer_armed.get() {
+            self.timer_armed.set(true);
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TIMER_TICK_MS));
+        }
+    }
+
+    /// Sends the next pending segment, if the IPv6 sender is idle.
+    pub(crate) fn send_pending(&self) {
+        // `send_done` may be called before `send_to` returns, in which case
+        // the next segment is sent by the next iteration.
+        while !self.sending.get() {
+            let mut buf = match self.tx_buffer.take() {
+                Some(buf) => buf,
+                None => return,
+            };
+            buf.reset();
+
+            let segment = match self.pending_rst.take() {
+                Some((dst, header)) => Some((dst, header, 0)),
+                None => self
+                    .socket_list
+                    .iter()
+                    .find_map(|socket| socket.next_segment(&mut buf[..])),
+            };
+            let (dst, header, len) = match segment {
+                Some(segment) => segment,
+                None => {
+                    self.tx_buffer.replace(buf);
+                    return;
+                }
+            };
+
+            buf.slice(..len);
+            self.sending.set(true);
+            let result =
+                self.ip_sender
+                    .send_to(dst, TransportHeader::TCP(header), &buf, self.net_cap);
+            self.tx_buffer.replace(buf);
+            if result != Ok(()) {
+                // Lost segments are retransmitted when the timer of the
+                // socket expires.
+                debug!("[TCP] IP send_to failed: {:?}", result);
+                self.sending.set(false);
+            }
+        }
+    }
+
+    /// Answers a segment that matched no socket with a RST.
+    fn reset_unknown(&self, src_addr: IPAddr, header: &TCPHeader, data_len: usize) {
+        if header.has_flags(tcp_flags::RST) {
+            return;
+        }
+        let mut rst = TCPHeader::new();
+        rst.set_src_port(header.get_dst_port());
+        rst.set_dst_port(header.get_src_port());
+        if header.has_flags(tcp_flags::ACK) {
+            rst.set_seq_num(header.get_ack_num());
+            rst.set_flags(tcp_flags::RST);
+        } else {
+            let mut seg_len = data_len as u32;
+            if header.has_flags(tcp_flags::SYN) {
+                seg_len += 1;
+            }
+            if header.has_flags(tcp_flags::FIN) {
+                seg_len += 1;
+            }
+            rst.set_ack_num(header.get_seq_num().wrapping_add(seg_len));
+            rst.set_flags(tcp_flags::RST | tcp_flags::ACK);
+        }
+        self.pending_rst.set((src_addr, rst));
+        self.send_pending();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6SendClient for MuxTcp<'a, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        if result != Ok(()) {
+            debug!("[TCP] send failed: {:?}", result);
+        }
+        self.sending.set(false);
+        self.send_pending();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for MuxTcp<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::TCP {
+            return;
+        }
+        let (offset, tcp_header) = match TCPHeader::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let data = &payload[offset..];
+        let src_addr = ip_header.get_src_addr();
+        let src_port = tcp_header.get_src_port();
+        let dst_port = tcp_header.get_dst_port();
+
+        let socket = self
+            .socket_list
+            .iter()
+            .find(|socket| socket.is_connection(src_addr, src_port, dst_port))
+            .or_else(|| {
+                self.socket_list
+                    .iter()
+                    .find(|socket| socket.is_listening(dst_port))
+            });
+        match socket {
+            Some(socket) => socket.receive_segment(src_addr, &tcp_header, data),
+            None => self.reset_unknown(src_addr, &tcp_header, data.len()),
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for MuxTcp<'a, A> {
+    fn alarm(&self) {
+        self.timer_armed.set(false);
+        let mut running = false;
+        for socket in self.socket_list.iter() {
+            running |= socket.tick();
+        }
+        if running {
+            self.start_timer();
+        }
+        self.send_pending();
+    }
+}
@@ -0,0 +1,855 @@
+//! This file contains the TCP state machine. Each [TCPSocket](struct.TCPSocket.html)
+//! holds a single connection, which is either opened actively with
+//! `connect()` or passively with `listen()`. Sockets send and receive segments
+//! through the [MuxTcp](../tcp_mux/struct.MuxTcp.html) they are registered
+//! with, which also drives their retransmission timers.
+//!
+//! Every socket owns a transmit and a receive buffer, which bound how much
+//! data can be in flight in each direction. The free space of the receive
+//! buffer is advertised as the receive window, and data stays in the transmit
+//! buffer until it is acknowledged, so that it can be retransmitted.
+//!
+//! This is a minimal implementation of RFC 793. Known limitations:
+//!
+//! - A listening socket turns into the connection for the first SYN it
+//!   receives. There is no accept queue, so a socket has to be put back into
+//!   listening state once the connection is closed.
+//! - Segments that arrive out of order are dropped rather than queued; the
+//!   peer retransmits them.
+//! - No TCP options are sent or interpreted, so the peer's MSS is never
+//!   learned. Segments are limited by the buffer of the `MuxTcp` instead.
+//! - There is no congestion control or RTT estimation: the retransmission
+//!   timeout starts at a fixed value and doubles with every retransmission.
+//! - Initial sequence numbers come from the alarm and are predictable.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::tcp::tcp_mux::MuxTcp;
+use crate::net::tcp::{tcp_flags, TCPHeader};
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::collections::list::{ListLink, ListNode};
+use kernel::hil::time;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Retransmission timeout used for the first retransmission of a segment, in
+/// ticks of the `MuxTcp` timer. It is doubled after every retransmission.
+const INITIAL_RTO: usize = 4;
+/// Upper bound for the retransmission timeout, in ticks of the `MuxTcp` timer.
+const MAX_RTO: usize = 64;
+/// Number of retransmissions after which the connection is aborted.
+const MAX_RETRIES: usize = 6;
+/// Time spent in TIME-WAIT, in ticks of the `MuxTcp` timer.
+const TIME_WAIT_TICKS: usize = 8;
+
+/// Returns true if sequence number `a` comes before `b`.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// The states of a TCP connection, as defined in RFC 793.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// Implemented by users of a `TCPSocket` to learn about events on the
+/// connection. Every callback passes the id of the socket, so that one client
+/// can serve several sockets.
+pub trait TCPClient {
+    /// The connection opened with `connect()` or `listen()` was established,
+    /// or could not be established (`Err(FAIL)`).
+    fn connected(&self, socket_id: usize, result: Result<(), ErrorCode>);
+
+    /// Data was received. `len` is the total number of bytes that can be read
+    /// with `receive()`.
+    fn data_available(&self, socket_id: usize, len: usize);
+
+    /// The peer acknowledged `acked` bytes, freeing that much space in the
+    /// transmit buffer.
+    fn send_done(&self, socket_id: usize, acked: usize);
+
+    /// The peer closed its side of the connection, so no more data will be
+    /// received. Data can still be sent until `close()` is called.
+    fn remote_closed(&self, socket_id: usize);
+
+    /// The connection is fully closed. `result` is `Ok(())` after an orderly
+    /// close and `Err(FAIL)` if the connection was reset or timed out.
+    fn closed(&self, socket_id: usize, result: Result<(), ErrorCode>);
+}
+
+pub struct TCPSocket<'a, A: time::Alarm<'a>> {
+    id: usize,
+    mux: &'a MuxTcp<'a, A>,
+    client: OptionalCell<&'a dyn TCPClient>,
+    next: ListLink<'a, TCPSocket<'a, A>>,
+
+    state: Cell<TcpState>,
+    /// Whether the connection was opened with `listen()`.
+    passive: Cell<bool>,
+    local_port: Cell<u16>,
+    remote_addr: Cell<IPAddr>,
+    remote_port: Cell<u16>,
+
+    /// Initial send sequence number.
+    iss: Cell<u32>,
+    /// Oldest unacknowledged sequence number. Once the connection is
+    /// established, this is the sequence number of `tx_buffer[0]`.
+    snd_una: Cell<u32>,
+    /// Next sequence number to send. The FIN is not included, see `fin_sent`.
+    /// Rewound to `snd_una` when the retransmission timer expires.
+    snd_nxt: Cell<u32>,
+    /// One past the highest sequence number sent so far, including the FIN.
+    /// Unlike `snd_nxt` it is never rewound, so it bounds the acknowledgment
+    /// numbers that are accepted.
+    snd_max: Cell<u32>,
+    /// Window advertised by the peer.
+    snd_wnd: Cell<u16>,
+    /// Next sequence number expected from the peer.
+    rcv_nxt: Cell<u32>,
+
+    tx_buffer: TakeCell<'static, [u8]>,
+    /// Bytes in `tx_buffer`, both sent and unsent.
+    tx_len: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    /// Bytes in `rx_buffer` that have not been read yet.
+    rx_len: Cell<usize>,
+
+    /// `close()` was called, so a FIN is sent after the buffered data.
+    fin_queued: Cell<bool>,
+    /// The FIN was sent and is occupying sequence number `snd_nxt`.
+    fin_sent: Cell<bool>,
+    ack_pending: Cell<bool>,
+    rst_pending: Cell<bool>,
+    /// Send a byte even though the peer's window is zero.
+    probe: Cell<bool>,
+
+    /// Ticks until the retransmission (or TIME-WAIT) timer expires, 0 if it
+    /// is not running.
+    timer: Cell<usize>,
+    rto: Cell<usize>,
+    retries: Cell<usize>,
+}
+
+impl<'a, A: time::Alarm<'a>> ListNode<'a, TCPSocket<'a, A>> for TCPSocket<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, TCPSocket<'a, A>> {
+        &self.next
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> TCPSocket<'a, A> {
+    /// Creates a closed socket. The socket must be registered with
+    /// `MuxTcp::add_socket()` before it is used.
+    ///
+    /// `id` - Passed to every `TCPClient` callback
+    /// `tx_buffer` - Holds data until it is acknowledged by the peer
+    /// `rx_buffer` - Holds received data until it is read; its size is the
+    /// maximum receive window
+    pub fn new(
+        id: usize,
+        mux: &'a MuxTcp<'a, A>,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+    ) -> TCPSocket<'a, A> {
+        TCPSocket {
+            id,
+            mux,
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+            state: Cell::new(TcpState::Closed),
+            passive: Cell::new(false),
+            local_port: Cell::new(0),
+            remote_addr: Cell::new(IPAddr::new()),
+            remote_port: Cell::new(0),
+            iss: Cell::new(0),
+            snd_una: Cell::new(0),
+            snd_nxt: Cell::new(0),
+            snd_max: Cell::new(0),
+            snd_wnd: Cell::new(0),
+            rcv_nxt: Cell::new(0),
+            tx_buffer: TakeCell::new(tx_buffer),
+            tx_len: Cell::new(0),
+            rx_buffer: TakeCell::new(rx_buffer),
+            rx_len: Cell::new(0),
+            fin_queued: Cell::new(false),
+            fin_sent: Cell::new(false),
+            ack_pending: Cell::new(false),
+            rst_pending: Cell::new(false),
+            probe: Cell::new(false),
+            timer: Cell::new(0),
+            rto: Cell::new(INITIAL_RTO),
+            retries: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn TCPClient) {
+        self.client.set(client);
+    }
+
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+
+    pub fn get_state(&self) -> TcpState {
+        self.state.get()
+    }
+
+    pub fn get_local_port(&self) -> u16 {
+        self.local_port.get()
+    }
+
+    /// Returns the address and port of the peer.
+    pub fn get_remote(&self) -> (IPAddr, u16) {
+        (self.remote_addr.get(), self.remote_port.get())
+    }
+
+    /// Returns the number of received bytes that can be read with `receive()`.
+    pub fn bytes_available(&self) -> usize {
+        self.rx_len.get()
+    }
+
+    /// Waits for a connection on `local_port`. The `connected` callback is
+    /// called once a peer has connected.
+    ///
+    /// Returns BUSY if the socket is not closed or the port is in use, and
+    /// INVAL if the port is 0.
+    pub fn listen(&self, local_port: u16) -> Result<(), ErrorCode> {
+        if self.state.get() != TcpState::Closed {
+            return Err(ErrorCode::BUSY);
+        }
+        if local_port == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.mux.port_in_use(local_port) {
+            return Err(ErrorCode::BUSY);
+        }
+        self.reset(TcpState::Listen);
+        self.passive.set(true);
+        self.local_port.set(local_port);
+        Ok(())
+    }
+
+    /// Opens a connection to `remote_port` on `remote_addr`. If `local_port`
+    /// is 0, a free ephemeral port is used. The `connected` callback is called
+    /// once the connection is established or has failed.
+    ///
+    /// Returns BUSY if the socket is not closed or the port is in use, and
+    /// INVAL if `remote_port` is 0.
+    pub fn connect(
+        &self,
+        local_port: u16,
+        remote_addr: IPAddr,
+        remote_port: u16,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != TcpState::Closed {
+            return Err(ErrorCode::BUSY);
+        }
+        if remote_port == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        let local_port = if local_port == 0 {
+            self.mux.ephemeral_port().ok_or(ErrorCode::BUSY)?
+        } else if self.mux.port_in_use(local_port) {
+            return Err(ErrorCode::BUSY);
+        } else {
+            local_port
+        };
+
+        self.reset(TcpState::SynSent);
+        self.passive.set(false);
+        self.local_port.set(local_port);
+        self.remote_addr.set(remote_addr);
+        self.remote_port.set(remote_port);
+        let iss = self.mux.generate_iss();
+        self.iss.set(iss);
+        self.snd_una.set(iss);
+        self.snd_nxt.set(iss);
+        self.snd_max.set(iss);
+        self.snd_wnd.set(0);
+        self.mux.send_pending();
+        Ok(())
+    }
+
+    /// Queues `data` for transmission. Returns the number of bytes that fit
+    /// into the transmit buffer.
+    pub fn send(&self, data: &[u8]) -> Result<usize, ErrorCode> {
+        self.send_with(data.len(), |buf| buf.copy_from_slice(&data[..buf.len()]))
+    }
+
+    /// Queues up to `len` bytes for transmission. `fill` is called with the
+    /// part of the transmit buffer the data should be copied to, which may be
+    /// shorter than `len`. Returns the number of bytes queued.
+    ///
+    /// Returns INVAL if the connection is not established or was closed with
+    /// `close()`, and BUSY if the transmit buffer is full.
+    pub fn send_with<F: FnOnce(&mut [u8])>(&self, len: usize, fill: F) -> Result<usize, ErrorCode> {
+        match self.state.get() {
+            TcpState::Established | TcpState::CloseWait => {}
+            _ => return Err(ErrorCode::INVAL),
+        }
+        if self.fin_queued.get() {
+            return Err(ErrorCode::INVAL);
+        }
+        let queued = self.tx_buffer.map_or(Err(ErrorCode::NOMEM), |buf| {
+            let start = self.tx_len.get();
+            let count = cmp::min(len, buf.len() - start);
+            if count == 0 {
+                return Err(ErrorCode::BUSY);
+            }
+            fill(&mut buf[start..start + count]);
+            self.tx_len.set(start + count);
+            Ok(count)
+        })?;
+        self.mux.send_pending();
+        Ok(queued)
+    }
+
+    /// Reads received data. `read` is called with all buffered data and
+    /// returns how many bytes it consumed. Returns the number of bytes
+    /// consumed.
+    pub fn receive<F: FnOnce(&[u8]) -> usize>(&self, read: F) -> usize {
+        let consumed = self.rx_buffer.map_or(0, |buf| {
+            let len = self.rx_len.get();
+            let consumed = cmp::min(read(&buf[..len]), len);
+            buf.copy_within(consumed..len, 0);
+            self.rx_len.set(len - consumed);
+            consumed
+        });
+        if consumed > 0 && self.is_synchronized() {
+            // Let the peer know the window opened.
+            self.ack_pending.set(true);
+            self.mux.send_pending();
+        }
+        consumed
+    }
+
+    /// Closes the connection once all queued data has been sent. The `closed`
+    /// callback is called once the connection is fully closed. Sockets that
+    /// are listening or still connecting are closed immediately, without a
+    /// callback.
+    ///
+    /// Returns ALREADY if the socket is closed or already closing.
+    pub fn close(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            TcpState::Listen | TcpState::SynSent => {
+                self.reset(TcpState::Closed);
+                Ok(())
+            }
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait
+                if !self.fin_queued.get() =>
+            {
+                self.fin_queued.set(true);
+                self.mux.send_pending();
+                Ok(())
+            }
+            _ => Err(ErrorCode::ALREADY),
+        }
+    }
+
+    /// Closes the socket immediately, discarding any buffered data. If a
+    /// connection is open, the peer is sent a RST. No callback is called.
+    pub fn abort(&self) {
+        let synchronized = self.is_synchronized() || self.state.get() == TcpState::SynReceived;
+        self.reset(TcpState::Closed);
+        if synchronized {
+            self.rst_pending.set(true);
+            self.mux.send_pending();
+        }
+    }
+
+    /// Returns true if this socket is listening on `port`.
+    pub(crate) fn is_listening(&self, port: u16) -> bool {
+        self.state.get() == TcpState::Listen && self.local_port.get() == port
+    }
+
+    /// Returns true if segments from `src_addr`:`src_port` to `dst_port`
+    /// belong to the connection of this socket.
+    pub(crate) fn is_connection(&self, src_addr: IPAddr, src_port: u16, dst_port: u16) -> bool {
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen => false,
+            _ => {
+                self.local_port.get() == dst_port
+                    && self.remote_port.get() == src_port
+                    && self.remote_addr.get() == src_addr
+            }
+        }
+    }
+
+    /// Returns true in the states in which both sides know each other's
+    /// sequence numbers.
+    fn is_synchronized(&self) -> bool {
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen | TcpState::SynSent | TcpState::SynReceived => {
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn reset(&self, state: TcpState) {
+        self.state.set(state);
+        self.tx_len.set(0);
+        self.rx_len.set(0);
+        self.fin_queued.set(false);
+        self.fin_sent.set(false);
+        self.ack_pending.set(false);
+        self.probe.set(false);
+        self.timer.set(0);
+        self.rto.set(INITIAL_RTO);
+        self.retries.set(0);
+    }
+
+    fn start_timer(&self, ticks: usize) {
+        self.timer.set(ticks);
+        self.mux.start_timer();
+    }
+
+    /// The receive window to advertise: the free space in the receive buffer.
+    fn window(&self) -> u16 {
+        self.rx_buffer.map_or(0, |buf| {
+            cmp::min(buf.len() - self.rx_len.get(), u16::MAX as usize) as u16
+        })
+    }
+
+    fn header(&self, flags: u8, seq_num: u32) -> TCPHeader {
+        let mut header = TCPHeader::new();
+        header.set_src_port(self.local_port.get());
+        header.set_dst_port(self.remote_port.get());
+        header.set_seq_num(seq_num);
+        if flags & tcp_flags::ACK != 0 {
+            header.set_ack_num(self.rcv_nxt.get());
+        }
+        header.set_flags(flags);
+        header.set_window(self.window());
+        header
+    }
+
+    /// Called by the `MuxTcp` when it can send a segment. If this socket has
+    /// something to send, the payload is written to `buf` and the destination,
+    /// header and payload length are returned.
+    pub(crate) fn next_segment(&self, buf: &mut [u8]) -> Option<(IPAddr, TCPHeader, usize)> {
+        let remote_addr = self.remote_addr.get();
+        if self.rst_pending.take() {
+            let seq = self.snd_nxt.get();
+            return Some((remote_addr, self.header(tcp_flags::RST, seq), 0));
+        }
+
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen => None,
+            TcpState::SynSent | TcpState::SynReceived if self.snd_nxt.get() == self.iss.get() => {
+                // (Re)transmit our SYN.
+                let iss = self.iss.get();
+                let flags = if self.state.get() == TcpState::SynSent {
+                    tcp_flags::SYN
+                } else {
+                    tcp_flags::SYN | tcp_flags::ACK
+                };
+                self.snd_nxt.set(iss.wrapping_add(1));
+                self.snd_max.set(iss.wrapping_add(1));
+                self.ack_pending.set(false);
+                if self.timer.get() == 0 {
+                    self.start_timer(self.rto.get());
+                }
+                Some((remote_addr, self.header(flags, iss), 0))
+            }
+            TcpState::SynSent => None,
+            TcpState::SynReceived => {
+                if self.ack_pending.take() {
+                    let seq = self.snd_nxt.get();
+                    Some((remote_addr, self.header(tcp_flags::ACK, seq), 0))
+                } else {
+                    None
+                }
+            }
+            _ => self.next_data_segment(buf),
+        }
+    }
+
+    fn next_data_segment(&self, buf: &mut [u8]) -> Option<(IPAddr, TCPHeader, usize)> {
+        let snd_una = self.snd_una.get();
+        let snd_nxt = self.snd_nxt.get();
+        let fin_was_sent = self.fin_sent.get();
+        let tx_len = self.tx_len.get();
+        let in_flight = snd_nxt.wrapping_sub(snd_una) as usize;
+        let unsent = tx_len - in_flight;
+
+        let mut window = self.snd_wnd.get() as usize;
+        if window == 0 && unsent > 0 && self.probe.take() {
+            window = 1;
+        }
+        let count = cmp::min(
+            cmp::min(unsent, window.saturating_sub(in_flight)),
+            buf.len(),
+        );
+
+        let mut flags = tcp_flags::ACK;
+        if count > 0 {
+            self.tx_buffer.map(|tx_buffer| {
+                buf[..count].copy_from_slice(&tx_buffer[in_flight..in_flight + count]);
+            });
+            flags |= tcp_flags::PSH;
+            self.snd_nxt.set(snd_nxt.wrapping_add(count as u32));
+            self.advance_snd_max(self.snd_nxt.get());
+        }
+
+        let all_sent = in_flight + count == tx_len;
+        if self.fin_queued.get() && !fin_was_sent && all_sent {
+            flags |= tcp_flags::FIN;
+            self.fin_sent.set(true);
+            self.advance_snd_max(self.snd_nxt.get().wrapping_add(1));
+            match self.state.get() {
+                TcpState::Established => self.state.set(TcpState::FinWait1),
+                TcpState::CloseWait => self.state.set(TcpState::LastAck),
+                _ => {}
+            }
+        }
+
+        if flags == tcp_flags::ACK && !self.ack_pending.get() {
+            if unsent > 0 && in_flight == 0 && self.timer.get() == 0 {
+                // The peer's window is closed. Probe it once the timer
+                // expires.
+                self.start_timer(self.rto.get());
+            }
+            return None;
+        }
+        self.ack_pending.set(false);
+        if flags != tcp_flags::ACK && self.timer.get() == 0 {
+            self.start_timer(self.rto.get());
+        }
+
+        // Once the FIN is sent it occupies the sequence number after the data.
+        let seq = if fin_was_sent {
+            snd_nxt.wrapping_add(1)
+        } else {
+            snd_nxt
+        };
+        Some((self.remote_addr.get(), self.header(flags, seq), count))
+    }
+
+    /// Raises `snd_max` to `seq` if something beyond it was sent.
+    fn advance_snd_max(&self, seq: u32) {
+        if seq_lt(self.snd_max.get(), seq) {
+            self.snd_max.set(seq);
+        }
+    }
+
+    /// Called by the `MuxTcp` every timer tick. Returns true if the timer of
+    /// this socket is still running.
+    pub(crate) fn tick(&self) -> bool {
+        match self.timer.get() {
+            0 => return false,
+            1 => self.timer.set(0),
+            ticks => {
+                self.timer.set(ticks - 1);
+                return true;
+            }
+        }
+
+        if self.state.get() == TcpState::TimeWait {
+            self.reset(TcpState::Closed);
+            self.client.map(|client| client.closed(self.id, Ok(())));
+            return false;
+        }
+
+        // Probing a closed window is not a retransmission, as the peer
+        // may keep its window closed for a long time.
+        let probing = self.is_synchronized() && self.snd_wnd.get() == 0 && !self.fin_sent.get();
+        if !probing {
+            self.retries.set(self.retries.get() + 1);
+            if self.retries.get() > MAX_RETRIES {
+                self.fail();
+                return false;
+            }
+        }
+        self.rto.set(cmp::min(self.rto.get() * 2, MAX_RTO));
+
+        // Go back and retransmit everything that is unacknowledged. The
+        // timer is restarted once the segment is sent.
+        if self.is_synchronized() {
+            self.snd_nxt.set(self.snd_una.get());
+            self.fin_sent.set(false);
+            self.probe.set(probing);
+        } else {
+            self.snd_nxt.set(self.iss.get());
+        }
+        false
+    }
+
+    /// The connection was reset or timed out.
+    fn fail(&self) {
+        match self.state.get() {
+            TcpState::SynReceived if self.passive.get() => {
+                // Wait for the next connection attempt.
+                self.reset(TcpState::Listen);
+            }
+            TcpState::SynSent | TcpState::SynReceived => {
+                self.reset(TcpState::Closed);
+                self.client
+                    .map(|client| client.connected(self.id, Err(ErrorCode::FAIL)));
+            }
+            TcpState::Closed | TcpState::Listen => {}
+            TcpState::TimeWait => {
+                self.reset(TcpState::Closed);
+                self.client.map(|client| client.closed(self.id, Ok(())));
+            }
+            _ => {
+                self.reset(TcpState::Closed);
+                self.client
+                    .map(|client| client.closed(self.id, Err(ErrorCode::FAIL)));
+            }
+        }
+    }
+
+    /// Called by the `MuxTcp` for every segment that belongs to this socket.
+    pub(crate) fn receive_segment(&self, src_addr: IPAddr, header: &TCPHeader, data: &[u8]) {
+        match self.state.get() {
+            TcpState::Closed => {}
+            TcpState::Listen => {
+                if header.has_flags(tcp_flags::SYN)
+                    && !header.has_flags(tcp_flags::RST)
+                    && !header.has_flags(tcp_flags::ACK)
+                {
+                    self.remote_addr.set(src_addr);
+                    self.remote_port.set(header.get_src_port());
+                    self.rcv_nxt.set(header.get_seq_num().wrapping_add(1));
+                    self.snd_wnd.set(header.get_window());
+                    let iss = self.mux.generate_iss();
+                    self.iss.set(iss);
+                    self.snd_una.set(iss);
+                    self.snd_nxt.set(iss);
+                    self.snd_max.set(iss);
+                    self.state.set(TcpState::SynReceived);
+                }
+            }
+            TcpState::SynSent => self.receive_syn_sent(header),
+            _ => self.receive_synchronized(header, data),
+        }
+        self.mux.send_pending();
+    }
+
+    fn receive_syn_sent(&self, header: &TCPHeader) {
+        let ack_ok = header.get_ack_num() == self.iss.get().wrapping_add(1);
+        if header.has_flags(tcp_flags::ACK) && !ack_ok {
+            return;
+        }
+        if header.has_flags(tcp_flags::RST) {
+            if header.has_flags(tcp_flags::ACK) {
+                // Connection refused.
+                self.fail();
+            }
+            return;
+        }
+        if !header.has_flags(tcp_flags::SYN) {
+            return;
+        }
+
+        self.rcv_nxt.set(header.get_seq_num().wrapping_add(1));
+        self.snd_wnd.set(header.get_window());
+        if header.has_flags(tcp_flags::ACK) {
+            self.snd_una.set(self.iss.get().wrapping_add(1));
+            self.established();
+            self.ack_pending.set(true);
+            self.client.map(|client| client.connected(self.id, Ok(())));
+        } else {
+            // Simultaneous open: answer with a SYN-ACK.
+            self.state.set(TcpState::SynReceived);
+            self.snd_nxt.set(self.iss.get());
+        }
+    }
+
+    fn established(&self) {
+        self.state.set(TcpState::Established);
+        self.snd_nxt.set(self.snd_una.get());
+        self.snd_max.set(self.snd_una.get());
+        self.timer.set(0);
+        self.retries.set(0);
+        self.rto.set(INITIAL_RTO);
+    }
+
+    fn receive_synchronized(&self, header: &TCPHeader, data: &[u8]) {
+        let flags = header.get_flags();
+        let seq = header.get_seq_num();
+        let rcv_nxt = self.rcv_nxt.get();
+        let mut data = data;
+        let mut fin = flags & tcp_flags::FIN != 0;
+
+        if seq != rcv_nxt {
+            if flags & tcp_flags::RST != 0 {
+                return;
+            }
+            if seq_lt(rcv_nxt, seq) {
+                // Out of order. Remind the peer what we expect.
+                self.ack_pending.set(true);
+                return;
+            }
+            if self.state.get() == TcpState::SynReceived
+                && flags & tcp_flags::SYN != 0
+                && seq.wrapping_add(1) == rcv_nxt
+            {
+                // Our SYN-ACK was lost and the peer retransmitted its SYN.
+                self.snd_nxt.set(self.iss.get());
+                return;
+            }
+            // Skip the part of the segment that was already received.
+            let skip = rcv_nxt.wrapping_sub(seq) as usize;
+            if skip > data.len() || (skip == data.len() && !fin) {
+                fin = false;
+                self.ack_pending.set(true);
+            }
+            data = &data[cmp::min(skip, data.len())..];
+        }
+
+        if flags & tcp_flags::RST != 0 {
+            self.fail();
+            return;
+        }
+        if flags & tcp_flags::SYN != 0 {
+            self.ack_pending.set(true);
+            return;
+        }
+        if flags & tcp_flags::ACK == 0 {
+            return;
+        }
+
+        if self.state.get() == TcpState::SynReceived {
+            if header.get_ack_num() != self.iss.get().wrapping_add(1) {
+                return;
+            }
+            self.snd_una.set(self.iss.get().wrapping_add(1));
+            self.snd_wnd.set(header.get_window());
+            self.established();
+            self.client.map(|client| client.connected(self.id, Ok(())));
+        } else if !self.receive_ack(header) {
+            return;
+        }
+
+        // The state may have changed to closed above.
+        match self.state.get() {
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => {}
+            _ => return,
+        }
+
+        if !data.is_empty() {
+            let copied = self.rx_buffer.map_or(0, |buf| {
+                let start = self.rx_len.get();
+                let count = cmp::min(data.len(), buf.len() - start);
+                buf[start..start + count].copy_from_slice(&data[..count]);
+                self.rx_len.set(start + count);
+                count
+            });
+            self.rcv_nxt
+                .set(self.rcv_nxt.get().wrapping_add(copied as u32));
+            self.ack_pending.set(true);
+            if copied < data.len() {
+                // The FIN comes after the data that did not fit.
+                fin = false;
+            }
+            if copied > 0 {
+                self.client
+                    .map(|client| client.data_available(self.id, self.rx_len.get()));
+            }
+        }
+
+        if fin {
+            self.rcv_nxt.set(self.rcv_nxt.get().wrapping_add(1));
+            self.ack_pending.set(true);
+            match self.state.get() {
+                TcpState::Established => {
+                    self.state.set(TcpState::CloseWait);
+                    self.client.map(|client| client.remote_closed(self.id));
+                }
+                TcpState::FinWait1 => self.state.set(TcpState::Closing),
+                TcpState::FinWait2 => {
+                    self.state.set(TcpState::TimeWait);
+                    self.start_timer(TIME_WAIT_TICKS);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Handles the acknowledgment number and window of a segment. Returns
+    /// false if the rest of the segment should be ignored.
+    ///
+    /// Acknowledgments are checked against `snd_max` rather than `snd_nxt`,
+    /// as `snd_nxt` is rewound when the retransmission timer expires and an
+    /// acknowledgment for data sent before that may still arrive.
+    fn receive_ack(&self, header: &TCPHeader) -> bool {
+        let ack = header.get_ack_num();
+        let snd_una = self.snd_una.get();
+        let snd_max = self.snd_max.get();
+
+        if seq_lt(snd_max, ack) {
+            // Acknowledges something that was not sent.
+            self.ack_pending.set(true);
+            return false;
+        }
+        if ack == snd_una {
+            self.snd_wnd.set(header.get_window());
+        }
+        if !seq_lt(snd_una, ack) {
+            return true;
+        }
+
+        // The FIN follows the last byte in the transmit buffer and is only
+        // sent once all data is.
+        let tx_len = self.tx_len.get();
+        let acked_seq = ack.wrapping_sub(snd_una) as usize;
+        let fin_acked = self.fin_queued.get() && acked_seq == tx_len + 1;
+        let acked = cmp::min(acked_seq, tx_len);
+        self.tx_buffer.map(|buf| buf.copy_within(acked..tx_len, 0));
+        self.tx_len.set(tx_len - acked);
+        self.snd_una.set(snd_una.wrapping_add(acked as u32));
+        self.snd_wnd.set(header.get_window());
+        self.retries.set(0);
+        self.rto.set(INITIAL_RTO);
+
+        // Don't retransmit what a rewound `snd_nxt` still points at but the
+        // peer has already received.
+        if seq_lt(self.snd_nxt.get(), self.snd_una.get()) {
+            self.snd_nxt.set(self.snd_una.get());
+        }
+        if fin_acked {
+            self.snd_nxt.set(self.snd_una.get());
+            self.fin_sent.set(true);
+        }
+
+        if ack != snd_max {
+            self.start_timer(self.rto.get());
+        } else {
+            self.timer.set(0);
+        }
+
+        if acked > 0 {
+            self.client.map(|client| client.send_done(self.id, acked));
+        }
+        if fin_acked {
+            match self.state.get() {
+                TcpState::FinWait1 => self.state.set(TcpState::FinWait2),
+                TcpState::Closing => {
+                    self.state.set(TcpState::TimeWait);
+                    self.start_timer(TIME_WAIT_TICKS);
+                }
+                TcpState::LastAck => {
+                    self.reset(TcpState::Closed);
+                    self.client.map(|client| client.closed(self.id, Ok(())));
+                    return false;
+                }
+                _ => {}
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,1153 @@
+//! Implements Mesh Link Establishment (MLE) for attaching a Sleepy End
+//! Device (SED) to a Thread network, as described in Chapter 4 of the
+//! Thread 1.1.1 Specification.
+//!
+//! MLE for network attaching comprises a four-step handshake that works
+//! as follows:
+//!
+//! 1. A child device multicasts a Parent Request MLE command.
+//! 2. Each potential parent device on the network unicasts a Parent
+//!    Response MLE command.
+//! 3. The child device selects a parent based on a hierarchy of
+//!    connectivity metrics and unicasts a Child ID Request MLE
+//!    command.
+//! 4. The selected parent unicasts a Child ID Response MLE command.
+//!
+//! The Parent Request is first sent to routers only. If no router answers
+//! within 750 ms, it is sent a second time to routers and router-eligible
+//! end devices. Among the parents that answer, the one with the best link
+//! quality is picked, using the parent priority and the number of neighbors
+//! with the highest link quality to break ties (Section 4.7.2).
+//!
+//! MLE messages are exchanged over UDP port 19788, and are secured with
+//! AES-CCM using the MLE key. The MLE key, and the key used to secure
+//! 802.15.4 frames, are both derived from the network master key and the
+//! key sequence with HMAC-SHA256 (Section 7.1.4). A device adopts the key
+//! sequence of its parent when it attaches. Once attached, the `Mle`
+//! capsule acts as the `KeyProcedure` and `DeviceProcedure` of the 802.15.4
+//! framer, so that frames exchanged with the parent can be secured with the
+//! MAC key.
+//!
+//! Known limitations:
+//!
+//! - The MLE source address is the link-local address derived from the
+//!   extended MAC address. The IPv6 sender must use this address, and the
+//!   extended MAC address, as its source addresses.
+//! - Challenges are generated from the time and the extended address, and
+//!   are not unpredictable. Replayed messages are still rejected based on
+//!   the MLE frame counter.
+//! - Only one MLE message is processed at a time. Messages that arrive while
+//!   a previous message is being decrypted are dropped.
+//! - Once attached, no further MLE messages are processed. Key rotation,
+//!   Child Update Requests and reattaching after the parent is lost are
+//!   not implemented, and data polls are left to the MAC layer.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mle = static_init!(
+//!     capsules::net::thread::mle::Mle<
+//!         'static,
+//!         VirtualMuxAlarm<'static, Rtc>,
+//!         VirtualMuxHmac<'static, Hmac, 32>,
+//!     >,
+//!     capsules::net::thread::mle::Mle::new(
+//!         mle_udp_send,
+//!         mle_udp_recv,
+//!         udp_port_table,
+//!         ip_send,
+//!         mac_user,
+//!         mle_aes_ccm,
+//!         mle_hmac,
+//!         mle_alarm,
+//!         &mut MLE_CRYPT_BUF,
+//!         LeasableBuffer::new(&mut MLE_DGRAM),
+//!         &mut MLE_KDF_BUF,
+//!         &mut MLE_DIGEST_BUF,
+//!         net_cap,
+//!     )
+//! );
+//! mle_udp_send.set_client(mle);
+//! mle_udp_recv.set_client(mle);
+//! mle_aes_ccm.set_client(mle);
+//! mle_hmac.set_client(mle);
+//! mle_alarm.set_alarm_client(mle);
+//! framer.set_key_procedure(mle);
+//! framer.set_device_procedure(mle);
+//!
+//! mle.set_master_key(MASTER_KEY);
+//! mle.start();
+//! ```
+
+use crate::ieee802154::device::MacDevice;
+use crate::ieee802154::framer::{DeviceProcedure, KeyProcedure};
+use crate::net::ieee802154::{KeyId, KeyIdMode, MacAddress, SecurityLevel};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::IP6Sender;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::thread::tlv::{LinkMode, MulticastResponder, Tlv, TlvType, TL_WIDTH};
+use crate::net::udp::udp_port_table::UdpPortManager;
+use crate::net::udp::udp_recv::{UDPReceiver, UDPRecvClient};
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use core::cell::Cell;
+
+use kernel::hil::digest;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM, CCM_NONCE_LENGTH};
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// UDP port MLE messages are sent from and to.
+pub const MLE_PORT: u16 = 19788;
+
+/// Thread version advertised in the Version TLV.
+const THREAD_VERSION: u16 = 2;
+
+/// Length of the MLE key, the MAC key and the master key.
+pub const KEY_LEN: usize = 16;
+
+/// Length of the key derivation input, the key sequence followed by
+/// "Thread". `new` requires a buffer of at least this length.
+pub const KDF_INPUT_LEN: usize = 10;
+
+/// Offset of the MLE message in the crypt buffer. It is preceded by the
+/// authenticated data: the source and destination IPv6 addresses and the
+/// auxiliary security header.
+pub const A_DATA_LEN: usize = 16 + 16 + AUX_HDR_LEN;
+
+/// Security suite value of MLE messages secured with 802.15.4 security.
+const SECURITY_SUITE_154: u8 = 0;
+
+/// Security control field: key identifier mode 2, ENC-MIC-32.
+const SECURITY_CONTROL: u8 = KeyIdMode::Source4Index as u8 | SecurityLevel::EncMic32 as u8;
+
+/// Length of the auxiliary security header: the security control field,
+/// the frame counter, the key source and the key index.
+const AUX_HDR_LEN: usize = 10;
+
+const MIC_LEN: usize = 4;
+
+const PARENT_REQUEST_ROUTER_TIMEOUT_MS: u32 = 750;
+const PARENT_REQUEST_REED_TIMEOUT_MS: u32 = 1250;
+const CHILD_ID_RESPONSE_TIMEOUT_MS: u32 = 1250;
+const MAX_CHILD_ID_REQUESTS: u8 = 3;
+
+/// Default child timeout sent in the Timeout TLV, in seconds.
+const DEFAULT_CHILD_TIMEOUT_S: u32 = 240;
+
+/// Link-local all-routers multicast address, ff02::2.
+const LINK_LOCAL_ALL_ROUTERS: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+/// MLE command types (Section 4.4).
+mod command {
+    pub const PARENT_REQUEST: u8 = 9;
+    pub const PARENT_RESPONSE: u8 = 10;
+    pub const CHILD_ID_REQUEST: u8 = 11;
+    pub const CHILD_ID_RESPONSE: u8 = 12;
+}
+
+/// Attach state of the device.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AttachState {
+    /// Not attached, and not trying to attach.
+    Detached,
+    /// Parent Requests are sent, and Parent Responses collected.
+    ParentRequest,
+    /// A Child ID Request was sent to the selected parent.
+    ChildIdRequest,
+    /// Attached to a parent as a child.
+    Attached,
+}
+
+pub trait MleClient {
+    /// Called when an attach started with `start` completes. On success,
+    /// `result` contains the RLOC16 assigned by the parent, which is also
+    /// set as the short MAC address. Returns NOACK if no parent answered.
+    fn attach_done(&self, result: Result<u16, ErrorCode>);
+}
+
+/// Keys derived from the master key for one key sequence.
+#[derive(Copy, Clone)]
+struct KeySet {
+    sequence: u32,
+    mle: [u8; KEY_LEN],
+    mac: [u8; KEY_LEN],
+}
+
+/// A parent that answered a Parent Request.
+#[derive(Copy, Clone)]
+struct Parent {
+    ext_addr: [u8; 8],
+    rloc16: u16,
+    /// Challenge to return in the Child ID Request.
+    challenge: [u8; 8],
+    link_quality: u8,
+    priority: i8,
+    link_quality_3: u8,
+    /// Last MLE frame counter received from the parent.
+    frame_counter: u32,
+    keys: KeySet,
+}
+
+impl Parent {
+    /// Returns true if `self` is a better parent than `other`.
+    fn is_better_than(&self, other: &Parent) -> bool {
+        (self.link_quality, self.priority, self.link_quality_3)
+            > (other.link_quality, other.priority, other.link_quality_3)
+    }
+}
+
+/// The secured MLE message in the crypt buffer.
+#[derive(Copy, Clone)]
+struct RxInfo {
+    ext_addr: [u8; 8],
+    frame_counter: u32,
+    /// Length of the message, including the command type.
+    len: usize,
+}
+
+/// The operation the crypt buffer and the crypto engines are used for.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Operation {
+    Idle,
+    /// Deriving the keys for the configured key sequence, before sending.
+    TxKeys,
+    /// Deriving the keys for the key sequence of a received message.
+    RxKeys,
+    Encrypt,
+    Decrypt,
+}
+
+pub struct Mle<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    udp_receiver: &'a UDPReceiver<'a>,
+    port_table: &'static UdpPortManager,
+    ip_sender: &'a dyn IP6Sender<'a>,
+    mac: &'a dyn MacDevice<'a>,
+    aes_ccm: &'a dyn AES128CCM<'a>,
+    hmac: &'a H,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn MleClient>,
+    net_cap: &'static NetworkCapability,
+
+    state: Cell<AttachState>,
+    operation: Cell<Operation>,
+    /// A message for the current state has to be sent once the crypt
+    /// buffer is free.
+    tx_pending: Cell<bool>,
+    parent_requests: Cell<u8>,
+    child_id_requests: Cell<u8>,
+    parent: OptionalCell<Parent>,
+    challenge: Cell<[u8; 8]>,
+    rand_state: Cell<u32>,
+    child_timeout: Cell<u32>,
+    frame_counter: Cell<u32>,
+
+    master_key: OptionalCell<[u8; KEY_LEN]>,
+    key_sequence: Cell<u32>,
+    keys: OptionalCell<KeySet>,
+    /// Key sequence the HMAC engine is deriving keys for.
+    derive_sequence: Cell<u32>,
+
+    crypt_buf: TakeCell<'static, [u8]>,
+    tx_dst: Cell<IPAddr>,
+    tx_len: Cell<usize>,
+    rx_info: OptionalCell<RxInfo>,
+    rx_keys: OptionalCell<KeySet>,
+    udp_dgram: MapCell<LeasableBuffer<'static, u8>>,
+    kdf_buf: TakeCell<'static, [u8]>,
+    digest_buf: TakeCell<'static, [u8; 32]>,
+}
+
+impl<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256> Mle<'a, A, H> {
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        udp_receiver: &'a UDPReceiver<'a>,
+        port_table: &'static UdpPortManager,
+        ip_sender: &'a dyn IP6Sender<'a>,
+        mac: &'a dyn MacDevice<'a>,
+        aes_ccm: &'a dyn AES128CCM<'a>,
+        hmac: &'a H,
+        alarm: &'a A,
+        crypt_buf: &'static mut [u8],
+        udp_dgram: LeasableBuffer<'static, u8>,
+        kdf_buf: &'static mut [u8],
+        digest_buf: &'static mut [u8; 32],
+        net_cap: &'static NetworkCapability,
+    ) -> Mle<'a, A, H> {
+        Mle {
+            udp_sender,
+            udp_receiver,
+            port_table,
+            ip_sender,
+            mac,
+            aes_ccm,
+            hmac,
+            alarm,
+            client: OptionalCell::empty(),
+            net_cap,
+            state: Cell::new(AttachState::Detached),
+            operation: Cell::new(Operation::Idle),
+            tx_pending: Cell::new(false),
+            parent_requests: Cell::new(0),
+            child_id_requests: Cell::new(0),
+            parent: OptionalCell::empty(),
+            challenge: Cell::new([0; 8]),
+            rand_state: Cell::new(0),
+            child_timeout: Cell::new(DEFAULT_CHILD_TIMEOUT_S),
+            frame_counter: Cell::new(0),
+            master_key: OptionalCell::empty(),
+            key_sequence: Cell::new(0),
+            keys: OptionalCell::empty(),
+            derive_sequence: Cell::new(0),
+            crypt_buf: TakeCell::new(crypt_buf),
+            tx_dst: Cell::new(IPAddr::new()),
+            tx_len: Cell::new(0),
+            rx_info: OptionalCell::empty(),
+            rx_keys: OptionalCell::empty(),
+            udp_dgram: MapCell::new(udp_dgram),
+            kdf_buf: TakeCell::new(kdf_buf),
+            digest_buf: TakeCell::new(digest_buf),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn MleClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the master key of the network to join.
+    pub fn set_master_key(&self, master_key: [u8; KEY_LEN]) {
+        self.master_key.set(master_key);
+        self.keys.clear();
+    }
+
+    /// Sets the key sequence to start attaching with. Parents using a
+    /// newer key sequence are accepted, and their key sequence adopted.
+    pub fn set_key_sequence(&self, key_sequence: u32) {
+        self.key_sequence.set(key_sequence);
+        self.keys.clear();
+    }
+
+    /// Sets the time after which the parent may remove the child if it
+    /// does not hear from it, in seconds.
+    pub fn set_child_timeout(&self, timeout: u32) {
+        self.child_timeout.set(timeout);
+    }
+
+    pub fn get_state(&self) -> AttachState {
+        self.state.get()
+    }
+
+    pub fn get_key_sequence(&self) -> u32 {
+        self.key_sequence.get()
+    }
+
+    /// Returns the RLOC16 of the device, if it is attached.
+    pub fn get_rloc16(&self) -> Option<u16> {
+        if self.state.get() == AttachState::Attached {
+            Some(self.mac.get_address())
+        } else {
+            None
+        }
+    }
+
+    /// Starts attaching to a parent. `attach_done` is called once the
+    /// attach completes. Returns BUSY if the device is attaching or
+    /// attached, and INVAL if no master key was set.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != AttachState::Detached {
+            return Err(ErrorCode::BUSY);
+        }
+        let master_key = self.master_key.extract().ok_or(ErrorCode::INVAL)?;
+        if !self.udp_receiver.is_bound() {
+            self.bind()?;
+        }
+
+        let ext_addr = self.mac.get_address_long();
+        let seed = ext_addr
+            .chunks(4)
+            .fold(self.alarm.now().into_u32(), |acc, chunk| {
+                acc ^ u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])
+            });
+        self.rand_state.set(seed | 1);
+
+        self.parent.clear();
+        self.parent_requests.set(0);
+        self.child_id_requests.set(0);
+        self.state.set(AttachState::ParentRequest);
+        if self.keys.is_some() {
+            self.send_parent_request();
+            Ok(())
+        } else {
+            let result = self.derive_keys(&master_key, self.key_sequence.get(), Operation::TxKeys);
+            if result.is_ok() {
+                self.send_parent_request();
+            } else {
+                self.state.set(AttachState::Detached);
+            }
+            result
+        }
+    }
+
+    /// Binds the UDP sender and receiver to the MLE port.
+    fn bind(&self) -> Result<(), ErrorCode> {
+        let socket = self.port_table.create_socket().map_err(|err| match err {
+            Err(ecode) => ecode,
+            Ok(()) => ErrorCode::FAIL,
+        })?;
+        // If binding fails, the socket is dropped, which releases it.
+        let (send_binding, recv_binding) = self
+            .port_table
+            .bind(socket, MLE_PORT, self.net_cap)
+            .map_err(|_| ErrorCode::BUSY)?;
+        self.udp_sender.set_binding(send_binding);
+        self.udp_receiver.set_binding(recv_binding);
+        Ok(())
+    }
+
+    /// Starts deriving the keys for `sequence`. `hash_done` continues with
+    /// `operation` once the keys are available.
+    fn derive_keys(
+        &self,
+        master_key: &[u8; KEY_LEN],
+        sequence: u32,
+        operation: Operation,
+    ) -> Result<(), ErrorCode> {
+        let kdf_buf = self.kdf_buf.take().ok_or(ErrorCode::BUSY)?;
+        kdf_buf[..4].copy_from_slice(&sequence.to_be_bytes());
+        kdf_buf[4..KDF_INPUT_LEN].copy_from_slice(b"Thread");
+        if let Err(ecode) = self.hmac.set_mode_hmacsha256(master_key) {
+            self.kdf_buf.replace(kdf_buf);
+            return Err(ecode);
+        }
+        let mut lease = LeasableBuffer::new(kdf_buf);
+        lease.slice(..KDF_INPUT_LEN);
+        match self.hmac.add_data(lease) {
+            Ok(_) => {
+                self.derive_sequence.set(sequence);
+                self.operation.set(operation);
+                Ok(())
+            }
+            Err((ecode, kdf_buf)) => {
+                self.kdf_buf.replace(kdf_buf);
+                Err(ecode)
+            }
+        }
+    }
+
+    fn next_random(&self) -> u32 {
+        // xorshift32
+        let mut x = self.rand_state.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rand_state.set(x);
+        x
+    }
+
+    fn arm_timer(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    fn send_parent_request(&self) {
+        let first = self.parent_requests.get() == 0;
+        self.parent_requests.set(self.parent_requests.get() + 1);
+        let mut challenge = [0; 8];
+        challenge[..4].copy_from_slice(&self.next_random().to_ne_bytes());
+        challenge[4..].copy_from_slice(&self.next_random().to_ne_bytes());
+        self.challenge.set(challenge);
+        self.send_message();
+        self.arm_timer(if first {
+            PARENT_REQUEST_ROUTER_TIMEOUT_MS
+        } else {
+            PARENT_REQUEST_REED_TIMEOUT_MS
+        });
+    }
+
+    fn send_child_id_request(&self) {
+        self.child_id_requests.set(self.child_id_requests.get() + 1);
+        self.send_message();
+        self.arm_timer(CHILD_ID_RESPONSE_TIMEOUT_MS);
+    }
+
+    /// Encrypts and sends the message for the current state, or marks it
+    /// pending if the crypt buffer is in use.
+    fn send_message(&self) {
+        if self.operation.get() != Operation::Idle {
+            self.tx_pending.set(true);
+            return;
+        }
+        self.tx_pending.set(false);
+        let buf = match self.crypt_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        match self.encode_message(buf) {
+            Ok((dst, keys, len, buf)) => {
+                self.tx_dst.set(dst);
+                self.tx_len.set(len);
+                // A message that could not be encrypted is sent again when
+                // the timer fires.
+                let _ = self.encrypt(buf, &keys, len);
+            }
+            Err(buf) => {
+                self.crypt_buf.replace(buf);
+            }
+        }
+    }
+
+    /// Writes the authenticated data and the plaintext message for the
+    /// current state into `buf`. Returns the destination, the keys to
+    /// secure the message with and the length of the message.
+    fn encode_message(
+        &self,
+        buf: &'static mut [u8],
+    ) -> Result<(IPAddr, KeySet, usize, &'static mut [u8]), &'static mut [u8]> {
+        let (dst, keys) = match self.state.get() {
+            AttachState::ParentRequest => match self.keys.extract() {
+                Some(keys) => (LINK_LOCAL_ALL_ROUTERS, keys),
+                None => return Err(buf),
+            },
+            AttachState::ChildIdRequest => match self.parent.extract() {
+                Some(parent) => (
+                    IPAddr::generate_from_mac(MacAddress::Long(parent.ext_addr)),
+                    parent.keys,
+                ),
+                None => return Err(buf),
+            },
+            _ => return Err(buf),
+        };
+        if buf.len() < A_DATA_LEN + MIC_LEN + 1 {
+            return Err(buf);
+        }
+
+        let src = IPAddr::generate_from_mac(MacAddress::Long(self.mac.get_address_long()));
+        buf[..16].copy_from_slice(&src.0);
+        buf[16..32].copy_from_slice(&dst.0);
+        encode_aux_header(&mut buf[32..A_DATA_LEN], self.frame_counter.get(), &keys);
+
+        let end = buf.len() - MIC_LEN;
+        let mut offset = A_DATA_LEN;
+        let mode = LinkMode::SecureDataRequests as u8;
+        let encoded = match self.state.get() {
+            AttachState::ParentRequest => {
+                let scan_mask = if self.parent_requests.get() > 1 {
+                    MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8
+                } else {
+                    MulticastResponder::Router as u8
+                };
+                buf[offset] = command::PARENT_REQUEST;
+                offset += 1;
+                [
+                    Tlv::Mode(mode),
+                    Tlv::Challenge(self.challenge.get()),
+                    Tlv::ScanMask(scan_mask),
+                    Tlv::Version(THREAD_VERSION),
+                ]
+                .iter()
+                .try_fold(offset, |offset, tlv| encode_tlv(buf, offset, end, tlv))
+            }
+            _ => {
+                let challenge = self.parent.map_or([0; 8], |parent| parent.challenge);
+                let requested = [TlvType::Address16 as u8, TlvType::NetworkData as u8];
+                buf[offset] = command::CHILD_ID_REQUEST;
+                offset += 1;
+                [
+                    Tlv::Response(challenge),
+                    // The 802.15.4 framer does not keep frame counters, and
+                    // always uses 0.
+                    Tlv::LinkLayerFrameCounter(0),
+                    Tlv::MleFrameCounter(self.frame_counter.get()),
+                    Tlv::Mode(mode),
+                    Tlv::Timeout(self.child_timeout.get()),
+                    Tlv::Version(THREAD_VERSION),
+                    Tlv::TlvRequest(&requested),
+                ]
+                .iter()
+                .try_fold(offset, |offset, tlv| encode_tlv(buf, offset, end, tlv))
+            }
+        };
+        match encoded {
+            Some(offset) => Ok((dst, keys, offset - A_DATA_LEN, buf)),
+            None => Err(buf),
+        }
+    }
+
+    fn encrypt(&self, buf: &'static mut [u8], keys: &KeySet, len: usize) -> Result<(), ErrorCode> {
+        let frame_counter = self.frame_counter.get();
+        self.frame_counter.set(frame_counter.wrapping_add(1));
+        let nonce = ccm_nonce(&self.mac.get_address_long(), frame_counter);
+        self.crypt(buf, keys, &nonce, len, true, Operation::Encrypt)
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        keys: &KeySet,
+        nonce: &[u8; CCM_NONCE_LENGTH],
+        len: usize,
+        encrypting: bool,
+        operation: Operation,
+    ) -> Result<(), ErrorCode> {
+        let result = self
+            .aes_ccm
+            .set_key(&keys.mle)
+            .and_then(|()| self.aes_ccm.set_nonce(nonce));
+        if let Err(ecode) = result {
+            self.crypt_buf.replace(buf);
+            return Err(ecode);
+        }
+        match self
+            .aes_ccm
+            .crypt(buf, 0, A_DATA_LEN, len, MIC_LEN, true, encrypting)
+        {
+            Ok(()) => {
+                self.operation.set(operation);
+                Ok(())
+            }
+            Err((ecode, buf)) => {
+                self.crypt_buf.replace(buf);
+                Err(ecode)
+            }
+        }
+    }
+
+    /// Starts decrypting the received message in the crypt buffer.
+    fn decrypt(&self, keys: &KeySet) {
+        self.rx_keys.set(*keys);
+        let result = match (self.crypt_buf.take(), self.rx_info.extract()) {
+            (Some(buf), Some(info)) => {
+                let nonce = ccm_nonce(&info.ext_addr, info.frame_counter);
+                self.crypt(buf, keys, &nonce, info.len, false, Operation::Decrypt)
+            }
+            (buf, _) => {
+                buf.map(|buf| self.crypt_buf.replace(buf));
+                Err(ErrorCode::FAIL)
+            }
+        };
+        if result.is_err() {
+            self.operation_done();
+        }
+    }
+
+    /// Continues the operation that required the keys for the key sequence
+    /// being derived. `keys` is None if the derivation failed.
+    fn keys_done(&self, keys: Option<KeySet>) {
+        match (self.operation.get(), keys) {
+            (Operation::TxKeys, Some(keys)) => {
+                self.keys.set(keys);
+                self.operation_done();
+            }
+            (Operation::TxKeys, None) => {
+                self.attach_failed(ErrorCode::FAIL);
+                self.operation_done();
+            }
+            (Operation::RxKeys, Some(keys)) => self.decrypt(&keys),
+            _ => self.operation_done(),
+        }
+    }
+
+    /// Frees the crypt buffer for the next operation, and sends the message
+    /// waiting for it.
+    fn operation_done(&self) {
+        self.operation.set(Operation::Idle);
+        self.rx_info.clear();
+        self.rx_keys.clear();
+        if self.tx_pending.get() {
+            self.send_message();
+        }
+    }
+
+    fn attach_failed(&self, ecode: ErrorCode) {
+        let _ = self.alarm.disarm();
+        self.state.set(AttachState::Detached);
+        self.tx_pending.set(false);
+        self.parent.clear();
+        self.client.map(|client| client.attach_done(Err(ecode)));
+    }
+
+    /// Processes a decrypted message. `msg` starts with the command type.
+    fn receive_message(&self, msg: &[u8], info: &RxInfo, keys: &KeySet) {
+        match (self.state.get(), msg[0]) {
+            (AttachState::ParentRequest, command::PARENT_RESPONSE) => {
+                self.receive_parent_response(&msg[1..], info, keys)
+            }
+            (AttachState::ChildIdRequest, command::CHILD_ID_RESPONSE) => {
+                self.receive_child_id_response(&msg[1..], info)
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_parent_response(&self, tlvs: &[u8], info: &RxInfo, keys: &KeySet) {
+        let mut response = None;
+        let mut challenge = None;
+        let mut rloc16 = None;
+        let mut link_margin = None;
+        let mut connectivity = None;
+        for_each_tlv(tlvs, |tlv| match tlv {
+            Tlv::Response(value) => response = Some(value),
+            Tlv::Challenge(value) => challenge = Some(value),
+            Tlv::SourceAddress(value) => rloc16 = Some(value),
+            Tlv::LinkMargin(value) => link_margin = Some(value),
+            Tlv::Connectivity {
+                parent_priority,
+                link_quality_3,
+                ..
+            } => connectivity = Some((parent_priority, link_quality_3)),
+            _ => {}
+        });
+        if response != Some(self.challenge.get()) {
+            return;
+        }
+        let candidate = match (challenge, rloc16, link_margin, connectivity) {
+            (Some(challenge), Some(rloc16), Some(link_margin), Some((priority, lq3))) => Parent {
+                ext_addr: info.ext_addr,
+                rloc16,
+                challenge,
+                link_quality: link_quality(link_margin),
+                // The priority is a signed two bit value in the top bits.
+                priority: (priority as i8) >> 6,
+                link_quality_3: lq3,
+                frame_counter: info.frame_counter,
+                keys: *keys,
+            },
+            _ => return,
+        };
+        let better = self
+            .parent
+            .map_or(true, |parent| candidate.is_better_than(parent));
+        if better {
+            self.parent.set(candidate);
+        }
+    }
+
+    fn receive_child_id_response(&self, tlvs: &[u8], info: &RxInfo) {
+        let parent = match self.parent.extract() {
+            Some(parent) => parent,
+            None => return,
+        };
+        if info.ext_addr != parent.ext_addr || info.frame_counter <= parent.frame_counter {
+            return;
+        }
+        let mut rloc16 = None;
+        let mut source = None;
+        for_each_tlv(tlvs, |tlv| match tlv {
+            Tlv::Address16(value) => rloc16 = Some(value),
+            Tlv::SourceAddress(value) => source = Some(value),
+            _ => {}
+        });
+        let rloc16 = match rloc16 {
+            Some(rloc16) if source.map_or(true, |source| source == parent.rloc16) => rloc16,
+            _ => return,
+        };
+
+        let _ = self.alarm.disarm();
+        self.parent.set(Parent {
+            frame_counter: info.frame_counter,
+            ..parent
+        });
+        self.keys.set(parent.keys);
+        self.key_sequence.set(parent.keys.sequence);
+        self.mac.set_address(rloc16);
+        self.mac.config_commit();
+        self.ip_sender
+            .set_gateway(MacAddress::Long(parent.ext_addr));
+        self.state.set(AttachState::Attached);
+        self.client.map(|client| client.attach_done(Ok(rloc16)));
+    }
+}
+
+/// Returns the index of the key for `sequence` (Section 7.1.4).
+fn key_index(sequence: u32) -> u8 {
+    (sequence & 0x7f) as u8 + 1
+}
+
+/// Maps the link margin in dB to a link quality (Section 4.5.15).
+fn link_quality(link_margin: u8) -> u8 {
+    match link_margin {
+        0..=2 => 0,
+        3..=10 => 1,
+        11..=20 => 2,
+        _ => 3,
+    }
+}
+
+fn encode_aux_header(buf: &mut [u8], frame_counter: u32, keys: &KeySet) {
+    buf[0] = SECURITY_CONTROL;
+    // The frame counter is little endian, as in the 802.15.4 header.
+    buf[1..5].copy_from_slice(&frame_counter.to_le_bytes());
+    buf[5..9].copy_from_slice(&keys.sequence.to_be_bytes());
+    buf[9] = key_index(keys.sequence);
+}
+
+fn ccm_nonce(ext_addr: &[u8; 8], frame_counter: u32) -> [u8; CCM_NONCE_LENGTH] {
+    let mut nonce = [0; CCM_NONCE_LENGTH];
+    nonce[..8].copy_from_slice(ext_addr);
+    nonce[8..12].copy_from_slice(&frame_counter.to_be_bytes());
+    nonce[12] = SecurityLevel::EncMic32 as u8;
+    nonce
+}
+
+/// Encodes `tlv` at `offset`, without writing past `end`. Returns the
+/// offset after the TLV.
+fn encode_tlv(buf: &mut [u8], offset: usize, end: usize, tlv: &Tlv) -> Option<usize> {
+    tlv.encode(&mut buf[offset..end])
+        .done()
+        .map(|(len, ())| offset + len)
+}
+
+/// Calls `f` with each TLV in `buf` that is implemented by the TLV codec.
+/// Other TLVs are skipped.
+fn for_each_tlv<'b, F: FnMut(Tlv<'b>)>(buf: &'b [u8], mut f: F) {
+    let mut offset = 0;
+    while offset + TL_WIDTH <= buf.len() {
+        let end = offset + TL_WIDTH + buf[offset + 1] as usize;
+        if end > buf.len() {
+            return;
+        }
+        if let Some((_, tlv)) = Tlv::decode(&buf[offset..end]).done() {
+            f(tlv);
+        }
+        offset = end;
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256> time::AlarmClient
+    for Mle<'a, A, H>
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            AttachState::ParentRequest => {
+                if self.parent.is_some() {
+                    self.state.set(AttachState::ChildIdRequest);
+                    self.send_child_id_request();
+                } else if self.parent_requests.get() < 2 {
+                    self.send_parent_request();
+                } else {
+                    self.attach_failed(ErrorCode::NOACK);
+                }
+            }
+            AttachState::ChildIdRequest => {
+                if self.child_id_requests.get() < MAX_CHILD_ID_REQUESTS {
+                    self.send_child_id_request();
+                } else {
+                    self.attach_failed(ErrorCode::NOACK);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256> UDPRecvClient
+    for Mle<'a, A, H>
+{
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        _src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        match self.state.get() {
+            AttachState::ParentRequest | AttachState::ChildIdRequest => {}
+            _ => return,
+        }
+        if self.operation.get() != Operation::Idle
+            || !src_addr.is_unicast_link_local()
+            || payload.len() < 1 + AUX_HDR_LEN + 1 + MIC_LEN
+            || payload[0] != SECURITY_SUITE_154
+            || payload[1] != SECURITY_CONTROL
+        {
+            return;
+        }
+        let frame_counter = u32::from_le_bytes([payload[2], payload[3], payload[4], payload[5]]);
+        let key_sequence = u32::from_be_bytes([payload[6], payload[7], payload[8], payload[9]]);
+        if payload[10] != key_index(key_sequence) {
+            return;
+        }
+        let mut ext_addr = [0; 8];
+        ext_addr.copy_from_slice(&src_addr.0[8..]);
+        ext_addr[0] ^= 0x02;
+
+        // Lay the message out as expected by `crypt`: the authenticated
+        // data, then the message and the MIC.
+        let buf = match self.crypt_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let total_len = 32 + payload.len() - 1;
+        if total_len > buf.len() {
+            self.crypt_buf.replace(buf);
+            return;
+        }
+        buf[..16].copy_from_slice(&src_addr.0);
+        buf[16..32].copy_from_slice(&dst_addr.0);
+        buf[32..total_len].copy_from_slice(&payload[1..]);
+        self.crypt_buf.replace(buf);
+        self.rx_info.set(RxInfo {
+            ext_addr,
+            frame_counter,
+            len: total_len - A_DATA_LEN - MIC_LEN,
+        });
+
+        match self.keys.extract() {
+            Some(keys) if keys.sequence == key_sequence => self.decrypt(&keys),
+            // Parents may use a newer key sequence than the configured one.
+            Some(keys) if key_sequence > keys.sequence => {
+                let result = self
+                    .master_key
+                    .extract()
+                    .map_or(Err(ErrorCode::FAIL), |key| {
+                        self.derive_keys(&key, key_sequence, Operation::RxKeys)
+                    });
+                if result.is_err() {
+                    self.rx_info.clear();
+                }
+            }
+            _ => self.rx_info.clear(),
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256> UDPSendClient
+    for Mle<'a, A, H>
+{
+    fn send_done(&self, _result: Result<(), ErrorCode>, mut dgram: LeasableBuffer<'static, u8>) {
+        dgram.reset();
+        self.udp_dgram.replace(dgram);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256> CCMClient
+    for Mle<'a, A, H>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        match self.operation.get() {
+            Operation::Encrypt if res.is_ok() => {
+                // The UDP payload is the security suite, followed by the
+                // auxiliary header, the encrypted message and the MIC.
+                let secured = &buf[32..A_DATA_LEN + self.tx_len.get() + MIC_LEN];
+                if let Some(mut dgram) = self.udp_dgram.take() {
+                    if dgram.len() > secured.len() {
+                        dgram[0] = SECURITY_SUITE_154;
+                        dgram[1..=secured.len()].copy_from_slice(secured);
+                        dgram.slice(..=secured.len());
+                        let dst = self.tx_dst.get();
+                        if let Err(mut dgram) =
+                            self.udp_sender.send_to(dst, MLE_PORT, dgram, self.net_cap)
+                        {
+                            dgram.reset();
+                            self.udp_dgram.replace(dgram);
+                        }
+                    } else {
+                        self.udp_dgram.replace(dgram);
+                    }
+                }
+            }
+            Operation::Decrypt if res.is_ok() && tag_is_valid => {
+                let msg = (self.rx_info.extract(), self.rx_keys.extract());
+                if let (Some(info), Some(keys)) = msg {
+                    self.receive_message(&buf[A_DATA_LEN..A_DATA_LEN + info.len], &info, &keys);
+                }
+            }
+            _ => {}
+        }
+        self.crypt_buf.replace(buf);
+        self.operation_done();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256>
+    digest::ClientData<'a, 32> for Mle<'a, A, H>
+{
+    fn add_data_done(&'a self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.kdf_buf.replace(data);
+        let result = result.and_then(|()| match self.digest_buf.take() {
+            Some(digest) => self.hmac.run(digest).map_err(|(ecode, digest)| {
+                self.digest_buf.replace(digest);
+                ecode
+            }),
+            None => Err(ErrorCode::BUSY),
+        });
+        if result.is_err() {
+            self.hmac.clear_data();
+            self.keys_done(None);
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256>
+    digest::ClientHash<'a, 32> for Mle<'a, A, H>
+{
+    fn hash_done(&'a self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 32]) {
+        let keys = result.ok().map(|()| {
+            let mut keys = KeySet {
+                sequence: self.derive_sequence.get(),
+                mle: [0; KEY_LEN],
+                mac: [0; KEY_LEN],
+            };
+            keys.mle.copy_from_slice(&digest[..KEY_LEN]);
+            keys.mac.copy_from_slice(&digest[KEY_LEN..]);
+            keys
+        });
+        self.digest_buf.replace(digest);
+        self.hmac.clear_data();
+        self.keys_done(keys);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256>
+    digest::ClientVerify<'a, 32> for Mle<'a, A, H>
+{
+    fn verification_done(
+        &'a self,
+        _result: Result<bool, ErrorCode>,
+        compare: &'static mut [u8; 32],
+    ) {
+        self.digest_buf.replace(compare);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256> KeyProcedure
+    for Mle<'a, A, H>
+{
+    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
+        if level == SecurityLevel::None || self.state.get() != AttachState::Attached {
+            return None;
+        }
+        self.keys.extract().and_then(|keys| {
+            if key_id == KeyId::Index(key_index(keys.sequence)) {
+                Some(keys.mac)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, H: digest::Digest<'a, 32> + digest::HMACSha256> DeviceProcedure
+    for Mle<'a, A, H>
+{
+    fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]> {
+        if self.state.get() != AttachState::Attached {
+            return None;
+        }
+        self.parent.extract().and_then(|parent| match addr {
+            MacAddress::Short(short_addr) if short_addr == parent.rloc16 => Some(parent.ext_addr),
+            MacAddress::Long(long_addr) if long_addr == parent.ext_addr => Some(parent.ext_addr),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(sequence: u32) -> KeySet {
+        KeySet {
+            sequence,
+            mle: [0; KEY_LEN],
+            mac: [0; KEY_LEN],
+        }
+    }
+
+    #[test]
+    fn test_key_index_wraps() {
+        assert_eq!(key_index(0), 1);
+        assert_eq!(key_index(127), 128);
+        assert_eq!(key_index(128), 1);
+        assert_eq!(key_index(u32::MAX), 128);
+    }
+
+    #[test]
+    fn test_aux_header_key_sequence() {
+        let mut buf = [0; AUX_HDR_LEN];
+        encode_aux_header(&mut buf, 0x0102_0304, &keys(0x8000_0080));
+        assert_eq!(buf, [0x15, 4, 3, 2, 1, 0x80, 0, 0, 0x80, 1]);
+    }
+
+    #[test]
+    fn test_challenge_echoed_unchanged() {
+        let challenge = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut msg = [0; 32];
+        let mut offset = encode_tlv(&mut msg, 0, 32, &Tlv::Challenge(challenge)).unwrap();
+        // An unsupported TLV (Route64) is skipped.
+        msg[offset..offset + 4].copy_from_slice(&[9, 2, 0xaa, 0xbb]);
+        offset += 4;
+
+        let mut received = None;
+        for_each_tlv(&msg[..offset], |tlv| {
+            if let Tlv::Challenge(value) = tlv {
+                received = Some(value);
+            }
+        });
+        assert_eq!(received, Some(challenge));
+
+        let mut echo = [0; 10];
+        encode_tlv(&mut echo, 0, 10, &Tlv::Response(challenge)).unwrap();
+        assert_eq!(echo[2..], msg[2..10]);
+    }
+
+    /// Values of the TLVs found in `msg`, in order.
+    fn parse(msg: &[u8]) -> ([u32; 8], usize) {
+        let mut values = [0; 8];
+        let mut count = 0;
+        for_each_tlv(msg, |tlv| {
+            values[count] = match tlv {
+                Tlv::SourceAddress(rloc16) => rloc16 as u32,
+                Tlv::Timeout(timeout) => timeout,
+                Tlv::MleFrameCounter(counter) => counter,
+                Tlv::LinkMargin(margin) => margin as u32,
+                Tlv::Version(version) => version as u32,
+                _ => panic!("unexpected TLV"),
+            };
+            count += 1;
+        });
+        (values, count)
+    }
+
+    #[test]
+    fn test_tlv_round_trip() {
+        let mut msg = [0; 32];
+        let len = [
+            Tlv::SourceAddress(0x0400),
+            Tlv::Timeout(240),
+            Tlv::MleFrameCounter(0x0102_0304),
+            Tlv::LinkMargin(12),
+            Tlv::Version(THREAD_VERSION),
+        ]
+        .iter()
+        .try_fold(0, |offset, tlv| encode_tlv(&mut msg, offset, 32, tlv))
+        .unwrap();
+        let (values, count) = parse(&msg[..len]);
+        assert_eq!(
+            values[..count],
+            [0x0400, 240, 0x0102_0304, 12, THREAD_VERSION as u32]
+        );
+    }
+
+    #[test]
+    fn test_malformed_tlvs() {
+        // Source address 0x0400, an unsupported Route64 TLV, a source address
+        // that is too short, and a link margin whose length runs past the end.
+        let msg = [0, 2, 0x04, 0x00, 9, 2, 0xaa, 0xbb, 0, 1, 0x04, 16, 4, 20];
+        let (values, count) = parse(&msg);
+        assert_eq!(values[..count], [0x0400]);
+        // A trailing byte without a length is ignored.
+        assert_eq!(parse(&msg[..5]).1, 1);
+
+        // A TLV that does not fit is not encoded.
+        let mut buf = [0; 8];
+        assert_eq!(encode_tlv(&mut buf, 0, 5, &Tlv::Timeout(240)), None);
+        assert_eq!(encode_tlv(&mut buf, 4, 8, &Tlv::SourceAddress(1)), Some(8));
+    }
+}
@@ -1 +1,2 @@
+pub mod mle;
 pub mod tlv;
@@ -5,16 +5,30 @@
 //! --------
 //!
 //! This module provides a simple text-based console to inspect and control
-//! which processes are running. The console has five commands:
+//! which processes are running. The console has these commands:
 //!  - 'help' prints the available commands and arguments
 //!  - 'status' prints the current system status
 //!  - 'list' lists the current processes with their IDs and running state
+//!  - 'top' lists the CPU time and peak memory use of each process
 //!  - 'stop n' stops the process with name n
 //!  - 'start n' starts the stopped process with name n
 //!  - 'fault n' forces the process with name n into a fault state
+//!  - 'terminate n' terminates the process with name n
+//!  - 'boot n' restarts the terminated process with name n
 //!  - 'panic' causes the kernel to run the panic handler
 //!  - 'process n' prints the memory map of process with name n
+//!  - 'memmap n' prints the grant, heap and stack regions of process with
+//!    name n
 //!  - 'kernel' prints the kernel memory map
+//!  - 'reset' resets the chip, if the board set a reset function
+//!
+//! ### Line Editing
+//!
+//! The command line can be edited with the left and right arrow keys, Home,
+//! End (or Ctrl-A and Ctrl-E), Backspace and Delete. The up and down arrow
+//! keys step through the last `HISTORY_LEN` commands. Tab completes the
+//! command name, or the process name in an argument, and lists the choices if
+//! there is more than one.
 //!
 //! ### `list` Command Fields:
 //!
@@ -23,6 +37,8 @@
 //! - `Name`: The process name.
 //! - `Quanta`: How many times this process has exceeded its alloted time
 //!   quanta.
+//! - `Misses`: How many deadlines of its periodic jobs this process has
+//!   missed. This is only counted by real-time schedulers.
 //! - `Syscalls`: The number of system calls the process has made to the kernel.
 //! - `Dropped Upcalls`: How many upcalls were dropped for this process
 //!   because the queue was full.
@@ -32,6 +48,18 @@
 //! - `Grants`: The number of grants that have been initialized for the process
 //!   out of the total number of grants defined by the kernel.
 //!
+//! ### `top` Command Fields:
+//!
+//! - `PID`: The identifier for the process.
+//! - `Name`: The process name.
+//! - `Time(ms)`: How long the process has run on the CPU since it started.
+//!   Only time measured by the scheduler timer is counted.
+//! - `CPU`: The share of the CPU time used by all processes that this process
+//!   used.
+//! - `Heap Max`: The most bytes of heap the process has used.
+//! - `Grant Max`: The most bytes of grant region the kernel has used on behalf
+//!   of the process.
+//!
 //! Setup
 //! -----
 //!
@@ -52,6 +80,7 @@
 //!                  &mut console::WRITE_BUF,
 //!                  &mut console::READ_BUF,
 //!                  &mut console::COMMAND_BUF,
+//!                  &mut console::HISTORY_BUF,
 //!                  kernel,
 //!                  Capability));
 //! hil::uart::UART::set_client(&usart::USART0, pconsole);
@@ -85,9 +114,18 @@
 //! Initialization complete. Entering main loop
 //! Hello World!
 //! list
-//! PID    Name    Quanta  Syscalls  Dropped Upcalls  Restarts    State  Grants
-//! 00     blink        0       113                0         0  Yielded    1/12
-//! 01     c_hello      0         8                0         0  Yielded    3/12
+//! PID    Name    Quanta  Misses  Syscalls  Dropped Upcalls  Restarts    State  Grants
+//! 00     blink        0       0       113                0         0  Yielded    1/12
+//! 01     c_hello      0       0         8                0         0  Yielded    3/12
+//! ```
+//!
+//! To see which processes use the most CPU time and memory, use `top`:
+//!
+//! ```text
+//! top
+//!  PID    Name                  Time(ms)   CPU  Heap Max  Grant Max
+//!   00     blink                      12   25%      1024        280
+//!   01     c_hello                    36   75%      2048        432
 //! ```
 //!
 //! To get a general view of the system, use the status command:
@@ -97,6 +135,7 @@
 //! Total processes: 2
 //! Active processes: 2
 //! Timeslice expirations: 0
+//! Deadline misses: 0
 //! ```
 //!
 //! and you can control processes with the `start` and `stop` commands:
@@ -105,22 +144,54 @@
 //! stop blink
 //! Process blink stopped
 //! ```
+//!
+//! To see where the grant region, heap and stack of a process are, use
+//! `memmap`:
+//!
+//! ```text
+//! memmap blink
+//! Process blink memory map:
+//!   Region     Start       End           Size
+//!   Grant      0x20006BC0  0x20007000   1088
+//!   Unused     0x20006600  0x20006BC0   1472
+//!   Heap       0x20006200  0x20006600   1024
+//!   Data       0x20006000  0x20006200    512
+//!   Stack      0x20005F80  0x20006000    128
+//!   Unused     0x20005C00  0x20005F80    896
+//!   Flash      0x00040000  0x00042000   8192
+//! ```
+//!
+//! The stack grows down from its start, and only the lowest address the stack
+//! pointer was seen at is known, so the stack row shows the part of the stack
+//! that has been used.
+//!
+//! The `reset` command is only available if the board lets the console reset
+//! the chip:
+//!
+//! ```rust
+//! # use kernel::{capabilities, create_capability};
+//! fn reset() {
+//!     unsafe { cortexm4::scb::reset() };
+//! }
+//!
+//! pconsole.set_reset_function(reset, &create_capability!(capabilities::ChipResetCapability));
+//! ```
 
 use core::cell::Cell;
 use core::cmp;
 use core::fmt;
 use core::fmt::write;
 use core::str;
-use kernel::capabilities::ProcessManagementCapability;
+use kernel::capabilities::{ChipResetCapability, ProcessManagementCapability};
 use kernel::hil::time::ConvertTicks;
-use kernel::utilities::cells::TakeCell;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::ProcessId;
 
 use kernel::debug;
 use kernel::hil::time::{Alarm, AlarmClient};
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
-use kernel::process::{ProcessPrinter, ProcessPrinterContext};
+use kernel::process::{Process, ProcessPrinter, ProcessPrinterContext, State};
 use kernel::utilities::binary_write::BinaryWrite;
 use kernel::ErrorCode;
 use kernel::Kernel;
@@ -136,6 +207,33 @@ pub static mut READ_BUF: [u8; 4] = [0; 4];
 /// Commands can be up to 32 bytes long: since commands themselves are 4-5
 /// characters, limiting arguments to 25 bytes or so seems fine for now.
 pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
+/// Number of previous commands kept for the command history.
+pub const HISTORY_LEN: usize = 8;
+/// Holds the previous commands, each in a slot of the size of the command
+/// buffer.
+pub static mut HISTORY_BUF: [u8; HISTORY_LEN * 32] = [0; HISTORY_LEN * 32];
+
+const PROMPT: &[u8] = b"tock$ ";
+
+const HELP: &[u8] =
+    b"help status list top stop start fault terminate boot process memmap kernel reset\r\n";
+
+/// Commands offered by tab completion.
+const COMMANDS: [&str; 13] = [
+    "help",
+    "status",
+    "list",
+    "top",
+    "stop",
+    "start",
+    "fault",
+    "terminate",
+    "boot",
+    "process",
+    "memmap",
+    "kernel",
+    "reset",
+];
 
 /// States used for state machine to allow printing large strings asynchronously
 /// across multiple calls. This reduces the size of the buffer needed to print
@@ -157,6 +255,11 @@ enum WriterState {
         index: isize,
         total: isize,
     },
+    Top {
+        index: isize,
+        total: isize,
+        execution_time_us: u64,
+    },
 }
 
 impl Default for WriterState {
@@ -165,6 +268,17 @@ impl Default for WriterState {
     }
 }
 
+/// States for parsing the escape sequences that terminals send for the arrow
+/// and editing keys.
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum EscapeState {
+    None,
+    /// Received `ESC`.
+    Escape,
+    /// Received `ESC [` or `ESC O`, and the numeric parameter so far.
+    Sequence(u8),
+}
+
 /// Data structure to hold addresses about how the kernel is stored in memory on
 /// the chip.
 ///
@@ -196,6 +310,20 @@ pub struct ProcessConsole<'a, A: Alarm<'a>, C: ProcessManagementCapability> {
     command_buffer: TakeCell<'static, [u8]>,
     command_index: Cell<usize>,
 
+    /// Position of the cursor in the command being edited.
+    cursor: Cell<usize>,
+    escape_state: Cell<EscapeState>,
+
+    /// Previous commands, in a ring of slots of the size of the command buffer.
+    history_buffer: TakeCell<'static, [u8]>,
+    /// Number of commands in the history.
+    history_len: Cell<usize>,
+    /// Slot the next command is stored in.
+    history_next: Cell<usize>,
+    /// How many commands back the command being edited was taken from the
+    /// history, or 0 if it is a new command.
+    history_position: Cell<usize>,
+
     /// Keep the previously read byte to consider \r\n sequences
     /// as a single \n.
     previous_byte: Cell<u8>,
@@ -208,6 +336,13 @@ pub struct ProcessConsole<'a, A: Alarm<'a>, C: ProcessManagementCapability> {
     /// received after finishing echoing the last newline character.
     execute: Cell<bool>,
 
+    /// Internal flag that the chip should be reset once the console has
+    /// finished printing.
+    reset_pending: Cell<bool>,
+
+    /// Function that resets the chip, if the board allows the console to.
+    reset_function: OptionalCell<fn()>,
+
     /// Reference to the kernel object so we can access process state.
     kernel: &'static Kernel,
 
@@ -254,6 +389,32 @@ impl BinaryWrite for ConsoleWriter {
     }
 }
 
+/// Write a row of the `memmap` table. Regions with an unknown address are
+/// printed with `?`.
+fn write_region(
+    console_writer: &mut ConsoleWriter,
+    name: &str,
+    start: Option<usize>,
+    end: Option<usize>,
+) {
+    let _ = match (start, end) {
+        (Some(start), Some(end)) => write(
+            console_writer,
+            format_args!(
+                "  {:<9}  {:#010X}  {:#010X}  {:6}\r\n",
+                name,
+                start,
+                end,
+                end.saturating_sub(start)
+            ),
+        ),
+        _ => write(
+            console_writer,
+            format_args!("  {:<9}  {:>10}  {:>10}  {:>6}\r\n", name, "?", "?", "?"),
+        ),
+    };
+}
+
 impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C> {
     pub fn new(
         uart: &'a dyn uart::UartData<'a>,
@@ -263,6 +424,7 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
         rx_buffer: &'static mut [u8],
         queue_buffer: &'static mut [u8],
         cmd_buffer: &'static mut [u8],
+        history_buffer: &'static mut [u8],
         kernel: &'static Kernel,
         kernel_addresses: KernelAddresses,
         capability: C,
@@ -280,11 +442,19 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
             rx_buffer: TakeCell::new(rx_buffer),
             command_buffer: TakeCell::new(cmd_buffer),
             command_index: Cell::new(0),
+            cursor: Cell::new(0),
+            escape_state: Cell::new(EscapeState::None),
+            history_buffer: TakeCell::new(history_buffer),
+            history_len: Cell::new(0),
+            history_next: Cell::new(0),
+            history_position: Cell::new(0),
 
             previous_byte: Cell::new(0),
 
             running: Cell::new(false),
             execute: Cell::new(false),
+            reset_pending: Cell::new(false),
+            reset_function: OptionalCell::empty(),
             kernel: kernel,
             kernel_addresses: kernel_addresses,
             capability: capability,
@@ -301,6 +471,11 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
         Ok(())
     }
 
+    /// Let the `reset` command reset the chip by calling `reset_function`.
+    pub fn set_reset_function(&self, reset_function: fn(), _capability: &dyn ChipResetCapability) {
+        self.reset_function.set(reset_function);
+    }
+
     /// Print base information about the kernel version installed and the help
     /// message.
     pub fn display_welcome(&self) {
@@ -327,9 +502,8 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
         let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
 
         let _ = self.write_bytes(b"Welcome to the process console.\r\n");
-        let _ = self.write_bytes(
-            b"Valid commands are: help status list stop start fault process kernel\r\n",
-        );
+        let _ = self.write_bytes(b"Valid commands are: ");
+        let _ = self.write_bytes(HELP);
         self.prompt();
     }
 
@@ -362,6 +536,21 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                     }
                 }
             }
+            WriterState::Top {
+                index,
+                total,
+                execution_time_us,
+            } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::Top {
+                        index: index + 1,
+                        total,
+                        execution_time_us,
+                    }
+                }
+            }
             WriterState::Empty => WriterState::Empty,
         }
     }
@@ -512,10 +701,11 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                             let _ = write(
                                 &mut console_writer,
                                 format_args!(
-                                    "  {:?}\t{:<20}{:6}{:10}{:17}{:10}  {:?}{:5}/{}\r\n",
+                                    "  {:?}\t{:<20}{:6}{:8}{:10}{:17}{:10}  {:?}{:5}/{}\r\n",
                                     process_id,
                                     pname,
                                     process.debug_timeslice_expiration_count(),
+                                    process.debug_deadline_miss_count(),
                                     process.debug_syscall_count(),
                                     process.debug_dropped_upcall_count(),
                                     process.get_restart_count(),
@@ -529,6 +719,47 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                         }
                     });
             }
+            WriterState::Top {
+                index,
+                total: _,
+                execution_time_us,
+            } => {
+                let mut local_index = -1;
+                self.kernel
+                    .process_each_capability(&self.capability, |process| {
+                        local_index += 1;
+                        if local_index == index {
+                            let info: KernelInfo = KernelInfo::new(self.kernel);
+
+                            let pname = process.get_process_name();
+                            let process_id = process.processid();
+                            let app_time_us =
+                                info.app_execution_time_us(process_id, &self.capability);
+                            let cpu_percent = if execution_time_us > 0 {
+                                cmp::min(app_time_us * 100 / execution_time_us, 100)
+                            } else {
+                                0
+                            };
+                            let (heap_max, grant_max) =
+                                info.app_memory_high_water_marks(process_id, &self.capability);
+                            let mut console_writer = ConsoleWriter::new();
+                            let _ = write(
+                                &mut console_writer,
+                                format_args!(
+                                    "  {:?}\t{:<20}{:10}{:5}%{:10}{:11}\r\n",
+                                    process_id,
+                                    pname,
+                                    app_time_us / 1000,
+                                    cpu_percent,
+                                    heap_max,
+                                    grant_max
+                                ),
+                            );
+
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                        }
+                    });
+            }
             WriterState::Empty => {
                 self.prompt();
             }
@@ -552,6 +783,7 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
             // ends before the beginning of the buffer, and ends after
             // it starts.
             if terminator > 0 {
+                self.history_push(command, terminator);
                 let cmd_str = str::from_utf8(&command[0..terminator]);
 
                 match cmd_str {
@@ -561,9 +793,7 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                         if clean_str.starts_with("help") {
                             let _ = self.write_bytes(b"Welcome to the process console.\r\n");
                             let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self.write_bytes(
-                                b"help status list stop start fault process kernel\r\n",
-                            );
+                            let _ = self.write_bytes(HELP);
                         } else if clean_str.starts_with("start") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
@@ -621,6 +851,66 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                                                 ),
                                             );
 
+                                            let _ = self.write_bytes(
+                                                &(console_writer.buf)[..console_writer.size],
+                                            );
+                                        }
+                                    });
+                            });
+                        } else if clean_str.starts_with("terminate") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            argument.map(|name| {
+                                self.kernel
+                                    .process_each_capability(&self.capability, |proc| {
+                                        let proc_name = proc.get_process_name();
+                                        if proc_name == name {
+                                            proc.terminate(None);
+                                            let mut console_writer = ConsoleWriter::new();
+                                            let _ = write(
+                                                &mut console_writer,
+                                                format_args!(
+                                                    "Process {} terminated\r\n",
+                                                    proc_name
+                                                ),
+                                            );
+
+                                            let _ = self.write_bytes(
+                                                &(console_writer.buf)[..console_writer.size],
+                                            );
+                                        }
+                                    });
+                            });
+                        } else if clean_str.starts_with("boot") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            argument.map(|name| {
+                                self.kernel
+                                    .process_each_capability(&self.capability, |proc| {
+                                        let proc_name = proc.get_process_name();
+                                        if proc_name == name {
+                                            let mut console_writer = ConsoleWriter::new();
+                                            if proc.get_state() == State::Terminated {
+                                                // Keep the completion code of
+                                                // the last run of the process.
+                                                proc.try_restart(
+                                                    proc.get_completion_code().flatten(),
+                                                );
+                                                let _ = write(
+                                                    &mut console_writer,
+                                                    format_args!(
+                                                        "Process {} booted\r\n",
+                                                        proc_name
+                                                    ),
+                                                );
+                                            } else {
+                                                let _ = write(
+                                                    &mut console_writer,
+                                                    format_args!(
+                                                        "Process {} is not terminated\r\n",
+                                                        proc_name
+                                                    ),
+                                                );
+                                            }
+
                                             let _ = self.write_bytes(
                                                 &(console_writer.buf)[..console_writer.size],
                                             );
@@ -628,7 +918,8 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                                     });
                             });
                         } else if clean_str.starts_with("list") {
-                            let _ = self.write_bytes(b" PID    Name                Quanta  ");
+                            let _ =
+                                self.write_bytes(b" PID    Name                Quanta  Misses  ");
                             let _ = self.write_bytes(b"Syscalls  Dropped Upcalls  ");
                             let _ = self.write_bytes(b"Restarts    State  Grants\r\n");
 
@@ -645,6 +936,27 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                                     total: count,
                                 });
                             }
+                        } else if clean_str.starts_with("top") {
+                            let _ = self.write_bytes(b" PID    Name                  Time(ms)");
+                            let _ = self.write_bytes(b"   CPU  Heap Max  Grant Max\r\n");
+
+                            // Count the number of current processes.
+                            let mut count = 0;
+                            self.kernel.process_each_capability(&self.capability, |_| {
+                                count += 1;
+                            });
+
+                            if count > 0 {
+                                // Take the total now so that every row is
+                                // compared against the same total, even if
+                                // processes run while the table is printed.
+                                let info: KernelInfo = KernelInfo::new(self.kernel);
+                                self.write_state(WriterState::Top {
+                                    index: -1,
+                                    total: count,
+                                    execution_time_us: info.execution_time_us(&self.capability),
+                                });
+                            }
                         } else if clean_str.starts_with("status") {
                             let info: KernelInfo = KernelInfo::new(self.kernel);
                             let mut console_writer = ConsoleWriter::new();
@@ -674,6 +986,15 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                                 ),
                             );
                             let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                            console_writer.clear();
+                            let _ = write(
+                                &mut console_writer,
+                                format_args!(
+                                    "Deadline misses: {}\r\n",
+                                    info.deadline_misses(&self.capability)
+                                ),
+                            );
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
                         } else if clean_str.starts_with("process") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
@@ -712,6 +1033,27 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                                         }
                                     });
                             });
+                        } else if clean_str.starts_with("memmap") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            argument.map(|name| {
+                                let mut found = false;
+                                self.kernel
+                                    .process_each_capability(&self.capability, |proc| {
+                                        if !found && proc.get_process_name() == name {
+                                            self.print_memory_map(proc);
+                                            found = true;
+                                        }
+                                    });
+                            });
+                        } else if clean_str.starts_with("reset") {
+                            if self.reset_function.is_some() {
+                                let _ = self.write_bytes(b"Resetting the chip\r\n");
+                                // Reset once the message has been printed.
+                                self.reset_pending.set(true);
+                            } else {
+                                let _ =
+                                    self.write_bytes(b"Reset is not supported on this board\r\n");
+                            }
                         } else if clean_str.starts_with("kernel") {
                             let mut console_writer = ConsoleWriter::new();
                             let _ = write(
@@ -731,9 +1073,7 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                             self.writer_state.replace(WriterState::KernelStart);
                         } else {
                             let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self.write_bytes(
-                                b"help status list stop start fault process kernel\r\n",
-                            );
+                            let _ = self.write_bytes(HELP);
                         }
                     }
                     Err(_e) => {
@@ -751,13 +1091,334 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
             command[0] = 0;
         });
         self.command_index.set(0);
-        if self.writer_state.get() == WriterState::Empty {
+        self.cursor.set(0);
+        self.history_position.set(0);
+        if self.writer_state.get() == WriterState::Empty && !self.reset_pending.get() {
             self.prompt();
         }
     }
 
+    /// Print the memory regions of a process in RAM, from the highest address
+    /// down, and its region in flash.
+    fn print_memory_map(&self, process: &dyn Process) {
+        let addresses = process.get_addresses();
+        let mut console_writer = ConsoleWriter::new();
+        let _ = write(
+            &mut console_writer,
+            format_args!(
+                "Process {} memory map:\r\n  Region     Start       End           Size\r\n",
+                process.get_process_name()
+            ),
+        );
+        write_region(
+            &mut console_writer,
+            "Grant",
+            Some(addresses.sram_grant_start),
+            Some(addresses.sram_end),
+        );
+        write_region(
+            &mut console_writer,
+            "Unused",
+            Some(addresses.sram_app_brk),
+            Some(addresses.sram_grant_start),
+        );
+        write_region(
+            &mut console_writer,
+            "Heap",
+            addresses.sram_heap_start,
+            Some(addresses.sram_app_brk),
+        );
+        write_region(
+            &mut console_writer,
+            "Data",
+            addresses.sram_stack_top,
+            addresses.sram_heap_start,
+        );
+        write_region(
+            &mut console_writer,
+            "Stack",
+            addresses.sram_stack_bottom,
+            addresses.sram_stack_top,
+        );
+        write_region(
+            &mut console_writer,
+            "Unused",
+            Some(addresses.sram_start),
+            addresses.sram_stack_bottom,
+        );
+        write_region(
+            &mut console_writer,
+            "Flash",
+            Some(addresses.flash_start),
+            Some(addresses.flash_end),
+        );
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+    }
+
+    /// Handle a received byte that does not end the command.
+    fn handle_key(&self, command: &mut [u8], byte: u8) {
+        let len = self.command_index.get();
+        let cursor = self.cursor.get();
+
+        match self.escape_state.get() {
+            EscapeState::Escape => {
+                if byte == b'[' || byte == b'O' {
+                    self.escape_state.set(EscapeState::Sequence(0));
+                } else {
+                    self.escape_state.set(EscapeState::None);
+                }
+                return;
+            }
+            EscapeState::Sequence(parameter) => {
+                if byte.is_ascii_digit() {
+                    let parameter = parameter.saturating_mul(10).saturating_add(byte - b'0');
+                    self.escape_state.set(EscapeState::Sequence(parameter));
+                } else if (0x40..=0x7e).contains(&byte) {
+                    // This is the final byte of the sequence.
+                    self.escape_state.set(EscapeState::None);
+                    match (byte, parameter) {
+                        (b'A', _) => {
+                            if self.history_position.get() < self.history_len.get() {
+                                self.show_history(command, self.history_position.get() + 1);
+                            }
+                        }
+                        (b'B', _) => {
+                            if self.history_position.get() > 0 {
+                                self.show_history(command, self.history_position.get() - 1);
+                            }
+                        }
+                        (b'C', _) => {
+                            if cursor < len {
+                                self.cursor.set(cursor + 1);
+                                let _ = self.write_bytes(b"\x1b[C");
+                            }
+                        }
+                        (b'D', _) => {
+                            if cursor > 0 {
+                                self.cursor.set(cursor - 1);
+                                let _ = self.write_bytes(b"\x1b[D");
+                            }
+                        }
+                        (b'H', _) | (b'~', 1) | (b'~', 7) => self.move_cursor(command, 0),
+                        (b'F', _) | (b'~', 4) | (b'~', 8) => self.move_cursor(command, len),
+                        (b'~', 3) => self.delete(command, cursor),
+                        _ => {}
+                    }
+                }
+                return;
+            }
+            EscapeState::None => {}
+        }
+
+        match byte {
+            0x1b => self.escape_state.set(EscapeState::Escape),
+            // Ctrl-A and Ctrl-E.
+            0x01 => self.move_cursor(command, 0),
+            0x05 => self.move_cursor(command, len),
+            b'\t' => self.complete(command),
+            0x08 | 0x7f => {
+                if cursor > 0 {
+                    self.delete(command, cursor - 1);
+                }
+            }
+            // Sometimes reads return bytes > 127 without an error, which
+            // would fail utf-8 decoding, so only printable ASCII is stored.
+            0x20..=0x7e => self.insert(command, byte),
+            _ => {}
+        }
+    }
+
+    /// Insert a byte at the cursor, if the command buffer has room for it.
+    fn insert(&self, command: &mut [u8], byte: u8) {
+        let len = self.command_index.get();
+        let cursor = self.cursor.get();
+        if len + 1 >= command.len() {
+            return;
+        }
+
+        // Move the rest of the command, including the terminating 0.
+        command.copy_within(cursor..len + 1, cursor + 1);
+        command[cursor] = byte;
+        self.command_index.set(len + 1);
+        self.cursor.set(cursor + 1);
+        if cursor == len {
+            let _ = self.write_byte(byte);
+        } else {
+            self.redraw_line(command);
+        }
+    }
+
+    /// Remove the byte at `index` and move the cursor there.
+    fn delete(&self, command: &mut [u8], index: usize) {
+        let len = self.command_index.get();
+        if index >= len {
+            return;
+        }
+
+        command.copy_within(index + 1..len + 1, index);
+        self.command_index.set(len - 1);
+        let at_end = self.cursor.get() == len && index + 1 == len;
+        self.cursor.set(index);
+        if at_end {
+            // Note echo is '\b \b' to erase
+            let _ = self.write_bytes(b"\x08 \x08");
+        } else {
+            self.redraw_line(command);
+        }
+    }
+
+    fn move_cursor(&self, command: &[u8], position: usize) {
+        if self.cursor.get() != position {
+            self.cursor.set(position);
+            self.redraw_line(command);
+        }
+    }
+
+    /// Print the prompt and the command again, and move the terminal cursor
+    /// to the cursor in the command.
+    fn redraw_line(&self, command: &[u8]) {
+        let len = self.command_index.get();
+        let mut console_writer = ConsoleWriter::new();
+        let _ = console_writer.write_buffer(b"\r");
+        let _ = console_writer.write_buffer(PROMPT);
+        let _ = console_writer.write_buffer(&command[..len]);
+        // Erase the rest of the line.
+        let _ = console_writer.write_buffer(b"\x1b[K");
+        let back = len - self.cursor.get();
+        if back > 0 {
+            let _ = write(&mut console_writer, format_args!("\x1b[{}D", back));
+        }
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+    }
+
+    /// Store a command in the history, unless it is blank or the same as the
+    /// last command in the history. Each slot of the history has the size of
+    /// the command buffer.
+    fn history_push(&self, command: &[u8], len: usize) {
+        let line = &command[..len];
+        if line.iter().all(|byte| *byte == b' ') {
+            return;
+        }
+
+        self.history_buffer.map(|history| {
+            let slot_len = command.len();
+            let slots = history.len() / slot_len;
+            if slots == 0 {
+                return;
+            }
+
+            if self.history_len.get() > 0 {
+                let last = (self.history_next.get() + slots - 1) % slots * slot_len;
+                if &history[last..last + len] == line && history[last + len] == 0 {
+                    return;
+                }
+            }
+
+            let start = self.history_next.get() * slot_len;
+            history[start..start + len].copy_from_slice(line);
+            history[start + len] = 0;
+            self.history_next.set((self.history_next.get() + 1) % slots);
+            self.history_len
+                .set(cmp::min(self.history_len.get() + 1, slots));
+        });
+    }
+
+    /// Replace the command with the one `position` commands back in the
+    /// history, or with an empty command if `position` is 0.
+    fn show_history(&self, command: &mut [u8], position: usize) {
+        self.history_position.set(position);
+        let len = if position == 0 {
+            0
+        } else {
+            self.history_buffer.map_or(0, |history| {
+                let slot_len = command.len();
+                let slots = history.len() / slot_len;
+                let start = (self.history_next.get() + slots - position) % slots * slot_len;
+                let slot = &history[start..start + slot_len];
+                let len = slot.iter().position(|byte| *byte == 0).unwrap_or(0);
+                command[..len].copy_from_slice(&slot[..len]);
+                len
+            })
+        };
+        command[len] = 0;
+        self.command_index.set(len);
+        self.cursor.set(len);
+        self.redraw_line(command);
+    }
+
+    /// Call `f` with each name that can complete a command, if `commands` is
+    /// true, or an argument otherwise.
+    fn for_each_name<F: FnMut(&'static str)>(&self, commands: bool, mut f: F) {
+        if commands {
+            COMMANDS.iter().for_each(|name| f(name));
+        } else {
+            self.kernel
+                .process_each_capability(&self.capability, |process| f(process.get_process_name()));
+        }
+    }
+
+    /// Complete the word before the cursor, which is a command name if it is
+    /// the first word and a process name otherwise. If the word can be
+    /// completed in more than one way, extend it as far as all completions
+    /// agree, or list the completions if it cannot be extended.
+    fn complete(&self, command: &mut [u8]) {
+        let len = self.command_index.get();
+        if self.cursor.get() != len {
+            return;
+        }
+        let word_start = command[..len]
+            .iter()
+            .rposition(|byte| *byte == b' ')
+            .map_or(0, |index| index + 1);
+        let commands = command[..word_start].iter().all(|byte| *byte == b' ');
+
+        let prefix = &command[word_start..len];
+        let mut matches = 0;
+        let mut completion: &'static [u8] = &[];
+        self.for_each_name(commands, |name| {
+            let name = name.as_bytes();
+            if name.starts_with(prefix) {
+                let common = if matches == 0 {
+                    name.len()
+                } else {
+                    completion
+                        .iter()
+                        .zip(name)
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                };
+                completion = &name[..common];
+                matches += 1;
+            }
+        });
+
+        let prefix_len = len - word_start;
+        if matches == 1 || (matches > 1 && completion.len() > prefix_len) {
+            for byte in completion[prefix_len..].iter() {
+                if !byte.is_ascii() {
+                    return;
+                }
+                self.insert(command, *byte);
+            }
+            if matches == 1 {
+                self.insert(command, b' ');
+            }
+        } else if matches > 1 {
+            let prefix = &command[word_start..len];
+            let _ = self.write_bytes(b"\r\n");
+            self.for_each_name(commands, |name| {
+                if name.as_bytes().starts_with(prefix) {
+                    let _ = self.write_bytes(name.as_bytes());
+                    let _ = self.write_bytes(b"  ");
+                }
+            });
+            let _ = self.write_bytes(b"\r\n");
+            self.redraw_line(command);
+        }
+    }
+
     fn prompt(&self) {
-        let _ = self.write_bytes(b"tock$ ");
+        let _ = self.write_bytes(PROMPT);
     }
 
     /// Start or iterate the state machine for an asynchronous write operation
@@ -887,6 +1548,15 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> uart::TransmitClient
                 return;
             }
 
+            if self.reset_pending.get() {
+                self.reset_pending.set(false);
+                self.reset_function.map(|reset| reset());
+                // The chip should not get here, but if it does the console
+                // keeps working.
+                self.prompt();
+                return;
+            }
+
             // Check if we just received and echoed a newline character, and
             // therefore need to process the received message.
             if self.execute.get() {
@@ -914,7 +1584,6 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> uart::ReceiveClient
                     self.command_buffer.map(|command| {
                         let previous_byte = self.previous_byte.get();
                         self.previous_byte.set(read_buf[0]);
-                        let index = self.command_index.get() as usize;
                         if read_buf[0] == ('\n' as u8) || read_buf[0] == ('\r' as u8) {
                             if (previous_byte == ('\n' as u8) || previous_byte == ('\r' as u8))
                                 && previous_byte != read_buf[0]
@@ -924,25 +1593,11 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> uart::ReceiveClient
                                 self.previous_byte.set(0);
                             } else {
                                 self.execute.set(true);
+                                self.escape_state.set(EscapeState::None);
                                 let _ = self.write_bytes(&['\r' as u8, '\n' as u8]);
                             }
-                        } else if read_buf[0] == ('\x08' as u8) || read_buf[0] == ('\x7F' as u8) {
-                            if index > 0 {
-                                // Backspace, echo and remove last byte
-                                // Note echo is '\b \b' to erase
-                                let _ = self.write_bytes(&['\x08' as u8, ' ' as u8, '\x08' as u8]);
-                                command[index - 1] = '\0' as u8;
-                                self.command_index.set(index - 1);
-                            }
-                        } else if index < (command.len() - 1) && read_buf[0] < 128 {
-                            // For some reason, sometimes reads return > 127 but no error,
-                            // which causes utf-8 decoding failure, so check byte is < 128. -pal
-
-                            // Echo the byte and store it
-                            let _ = self.write_byte(read_buf[0]);
-                            command[index] = read_buf[0];
-                            self.command_index.set(index + 1);
-                            command[index + 1] = 0;
+                        } else {
+                            self.handle_key(command, read_buf[0]);
                         }
                     });
                 }
@@ -1,6 +1,8 @@
 //! Provides driver for accessing an SD Card and a userspace Driver.
 //!
 //! This allows initialization and block reads or writes on top of SPI.
+//! `SDCardStorage` provides the card to other capsules as
+//! `hil::nonvolatile_storage`, for example to expose it over USB mass storage.
 //!
 //! Usage
 //! -----
@@ -67,12 +69,15 @@ use core::cmp;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use kernel::hil;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
 use kernel::hil::time::ConvertTicks;
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::{ErrorCode, ProcessId};
 
+use crate::usb::msc::MediumClient;
+
 /// Syscall driver number.
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::SdCard as usize;
@@ -849,28 +854,28 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
             }
 
             SpiState::ReadBlockComplete => {
-                // replace buffers
+                // copy data to user buffer
+                // Limit to minimum length between buffer, read_buffer, and
+                // 512 (block size)
+                let read_len = self.client_buffer.map_or(0, |buffer| {
+                    for (client_byte, &read_byte) in
+                        buffer.iter_mut().zip(read_buffer.iter()).take(512)
+                    {
+                        *client_byte = read_byte;
+                    }
+                    cmp::min(read_buffer.len(), cmp::min(buffer.len(), 512))
+                });
+
+                // replace buffers before the callback, so that the client can
+                // start the next transaction from it
                 self.txbuffer.replace(write_buffer);
                 self.rxbuffer.replace(read_buffer);
 
                 // read finished, perform callback
                 self.state.set(SpiState::Idle);
-                self.rxbuffer.map(|read_buffer| {
-                    self.client_buffer.take().map(move |buffer| {
-                        // copy data to user buffer
-                        // Limit to minimum length between buffer, read_buffer,
-                        // and 512 (block size)
-                        for (client_byte, &read_byte) in
-                            buffer.iter_mut().zip(read_buffer.iter()).take(512)
-                        {
-                            *client_byte = read_byte;
-                        }
-
-                        // callback
-                        let read_len = cmp::min(read_buffer.len(), cmp::min(buffer.len(), 512));
-                        self.client.map(move |client| {
-                            client.read_done(buffer, read_len);
-                        });
+                self.client_buffer.take().map(move |buffer| {
+                    self.client.map(move |client| {
+                        client.read_done(buffer, read_len);
                     });
                 });
             }
@@ -1243,6 +1248,12 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
         self.is_initialized.get()
     }
 
+    /// Returns the buffer of a read or write that failed, as the `error`
+    /// callback does not pass it back to the client.
+    pub fn take_failed_buffer(&self) -> Option<&'static mut [u8]> {
+        self.client_buffer.take()
+    }
+
     /// watches SD card detect pin for changes, sends callback on change
     pub fn detect_changes(&self) {
         self.detect_pin.get().map(|pin| {
@@ -1442,6 +1453,158 @@ impl<'a, A: hil::time::Alarm<'a>> hil::gpio::Client for SDCard<'a, A> {
     }
 }
 
+/// Operations of `SDCardStorage` waiting for the SD card.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StorageOperation {
+    Idle,
+    Read,
+    Write,
+}
+
+/// Provides the SD card as `NonvolatileStorage`, so that capsules which
+/// access storage by address, such as USB mass storage, can use it.
+///
+/// Addresses and lengths must be multiples of the 512 byte block size, and
+/// writes are limited to a single block. The adapter initializes the card when
+/// it is inserted and tells its `MediumClient` the number of blocks of the
+/// card, or zero blocks if the card was removed.
+pub struct SDCardStorage<'a, A: hil::time::Alarm<'a>> {
+    sdcard: &'a SDCard<'a, A>,
+    client: OptionalCell<&'static dyn NonvolatileStorageClient<'static>>,
+    medium_client: OptionalCell<&'static dyn MediumClient>,
+    operation: Cell<StorageOperation>,
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SDCardStorage<'a, A> {
+    pub fn new(sdcard: &'a SDCard<'a, A>) -> SDCardStorage<'a, A> {
+        SDCardStorage {
+            sdcard,
+            client: OptionalCell::empty(),
+            medium_client: OptionalCell::empty(),
+            operation: Cell::new(StorageOperation::Idle),
+        }
+    }
+
+    pub fn set_medium_client(&self, client: &'static dyn MediumClient) {
+        self.medium_client.set(client);
+    }
+
+    /// Initialize the card if one is installed, and watch for the card being
+    /// inserted or removed.
+    pub fn start(&self) {
+        self.sdcard.detect_changes();
+        if self.sdcard.is_installed() {
+            let _ = self.sdcard.initialize();
+        }
+    }
+
+    fn check_blocks(&self, buffer: &[u8], address: usize, length: usize) -> Result<(), ErrorCode> {
+        if address % 512 != 0 || length % 512 != 0 || length == 0 || buffer.len() < length {
+            Err(ErrorCode::INVAL)
+        } else if self.operation.get() != StorageOperation::Idle {
+            Err(ErrorCode::BUSY)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> NonvolatileStorage<'static> for SDCardStorage<'a, A> {
+    fn set_client(&self, client: &'static dyn NonvolatileStorageClient<'static>) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.check_blocks(buffer, address, length)?;
+        self.operation.set(StorageOperation::Read);
+        self.sdcard
+            .read_blocks(buffer, (address / 512) as u32, (length / 512) as u32)
+            .map_err(|error| {
+                self.operation.set(StorageOperation::Idle);
+                error
+            })
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.check_blocks(buffer, address, length)?;
+        if length != 512 {
+            // multi-block SD card writes are unimplemented
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        self.operation.set(StorageOperation::Write);
+        self.sdcard
+            .write_blocks(buffer, (address / 512) as u32, 1)
+            .map_err(|error| {
+                self.operation.set(StorageOperation::Idle);
+                error
+            })
+    }
+}
+
+/// Handle callbacks from SDCard
+impl<'a, A: hil::time::Alarm<'a>> SDCardClient for SDCardStorage<'a, A> {
+    fn card_detection_changed(&self, installed: bool) {
+        if installed {
+            // The card is reported once it is initialized.
+            let _ = self.sdcard.initialize();
+        } else {
+            self.medium_client.map(|client| client.medium_changed(0));
+        }
+    }
+
+    fn init_done(&self, _block_size: u32, total_size: u64) {
+        let block_count = cmp::min(total_size / 512, u32::MAX as u64) as u32;
+        self.medium_client
+            .map(|client| client.medium_changed(block_count));
+    }
+
+    fn read_done(&self, data: &'static mut [u8], len: usize) {
+        self.operation.set(StorageOperation::Idle);
+        self.client.map(move |client| client.read_done(data, len));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8]) {
+        self.operation.set(StorageOperation::Idle);
+        self.client
+            .map(move |client| client.write_done(buffer, 512));
+    }
+
+    fn error(&self, _error: u32) {
+        // The SD card keeps the buffer of a failed operation, so return it
+        // to the client without any data.
+        match self.operation.get() {
+            StorageOperation::Read => {
+                self.operation.set(StorageOperation::Idle);
+                self.sdcard.client_buffer.take().map(|buffer| {
+                    self.client.map(move |client| client.read_done(buffer, 0));
+                });
+            }
+            StorageOperation::Write => {
+                self.operation.set(StorageOperation::Idle);
+                self.sdcard.client_buffer.take().map(|buffer| {
+                    self.client.map(move |client| client.write_done(buffer, 0));
+                });
+            }
+            StorageOperation::Idle => {
+                // Initialization failed, so the card can not be used.
+                if !self.sdcard.is_initialized() {
+                    self.medium_client.map(|client| client.medium_changed(0));
+                }
+            }
+        }
+    }
+}
+
 /// Application driver for SD Card capsule, layers on top of SD Card capsule
 /// This is used if the SDCard is going to be attached directly to userspace
 /// syscalls. SDCardDriver can be ignored if another capsule is going to build
@@ -0,0 +1,683 @@
+//! Allow-list system call filter with a policy set by the board, and an audit
+//! log of denied system calls.
+//!
+//! `TbfHeaderFilterDefaultAllow` enforces the permissions an application
+//! declares in its own TBF header, so the author of the application decides
+//! what it may access. With this filter, the board decides instead. The
+//! policy is a list of rules, each of which allows the processes with a
+//! `Credential` to use a driver, and a mask of the commands of that driver
+//! they may call.
+//!
+//! Processes are identified by the credential that the kernel's
+//! `ProcessCheckerMachine` accepted for them, see `Process::get_credentials()`,
+//! and not by anything the application declares about itself. The board must
+//! therefore load its processes with `load_and_check_processes()` and a
+//! checker such as `app_checker_sha256` or `app_checker_signature`.
+//!
+//! - Subscribe and allow calls to a driver are allowed if a rule for the
+//!   process and the driver exists.
+//! - Commands are allowed if the bit of the command number is set in the
+//!   mask of the rule. A mask of `ALL_COMMANDS` allows every command,
+//!   including those above 31.
+//! - Processes with an accepted credential but without any rule are allowed
+//!   or denied everything, as chosen by the board with `Unlisted`.
+//! - Processes without an accepted credential, because their credentials
+//!   were not checked or the checker does not require one, are denied
+//!   everything. No rule applies to them.
+//! - Yield, memop and exit cannot be filtered.
+//!
+//! A credential of a hash format matches only the application binary with
+//! that hash. A credential of a signature format matches the binary with that
+//! signature, or, without data, every application signed with the key of the
+//! checker. Rules compare the first `CREDENTIAL_LEN` bytes of the credential.
+//!
+//! The rules are added by the board at boot with `add_rule()`, and can be
+//! changed at runtime by one privileged process, also identified by its
+//! credential, through the syscall interface below. No other process can use
+//! this driver, whatever the rules say. Changes take effect immediately, but
+//! are not persistent.
+//!
+//! Denied system calls fail with `NODEVICE`, and are appended to a log, such
+//! as `capsules::log::Log` on a flash volume, as `ENTRY_LEN` byte entries:
+//!
+//! - `0`: System call class, as in `SyscallClass`.
+//! - `1`: Length of the process name.
+//! - `2..4`: How many identical denials this entry stands for.
+//! - `4..8`: Identifier of the process.
+//! - `8..12`: Driver number.
+//! - `12..16`: Command, subscribe or allow number.
+//! - `16..18`: Number of entries lost before this one because the queue of
+//!   entries was full.
+//! - `18..20`: Reserved.
+//! - `20..52`: Process name, truncated to `NAME_LEN` bytes.
+//!
+//! All values are little endian. The log is synced after the queued entries
+//! are written. Identical denials are merged into one entry while earlier
+//! entries are being written, so a process that keeps retrying a denied
+//! system call does not wear out the flash.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! use capsules::syscall_filter::Credential;
+//! use kernel::process_checker::TbfFooterV2CredentialsType;
+//!
+//! let syscall_filter = static_init!(
+//!     capsules::syscall_filter::AllowListFilter<'static, Log<'static, Flash>, Capability, 16>,
+//!     capsules::syscall_filter::AllowListFilter::new(
+//!         audit_log,
+//!         &mut capsules::syscall_filter::LOG_BUF,
+//!         board_kernel,
+//!         Capability,
+//!         Credential::new(TbfFooterV2CredentialsType::SHA256, &POLICY_MANAGER_SHA256)?,
+//!         capsules::syscall_filter::Unlisted::Deny,
+//!         board_kernel.create_grant(capsules::syscall_filter::DRIVER_NUM, &grant_cap),
+//!     )
+//! );
+//! audit_log.set_append_client(syscall_filter);
+//! // The application with this hash may use the LEDs.
+//! syscall_filter.add_rule(
+//!     Credential::new(TbfFooterV2CredentialsType::SHA256, &BLINK_SHA256)?,
+//!     capsules::led::DRIVER_NUM,
+//!     ALL_COMMANDS,
+//! )?;
+//! // Every application signed with the key of the checker may read sensors.
+//! syscall_filter.add_rule(
+//!     Credential::new(TbfFooterV2CredentialsType::EcdsaNistP256, &[])?,
+//!     capsules::temperature::DRIVER_NUM,
+//!     0b11,
+//! )?;
+//!
+//! // In the `KernelResources` of the board:
+//! type SyscallFilter = capsules::syscall_filter::AllowListFilter<...>;
+//! fn syscall_filter(&self) -> &Self::SyscallFilter {
+//!     self.syscall_filter
+//! }
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Stability: 0 - Draft
+//!
+//! ### Allow
+//!
+//! - Read-only `0`: The credential a rule is for: the number of its format
+//!   as in `TbfFooterV2CredentialsType`, followed by up to `CREDENTIAL_LEN`
+//!   bytes of its data.
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Add a rule for the credential in read-only allow `0`, with the
+//!   driver number in the first argument and the command mask in the second,
+//!   or replace the mask of an existing rule.
+//! - `2`: Remove the rule for the credential and the driver number in the
+//!   first argument.
+//! - `3`: Remove all rules for the credential.
+//! - `4`: Returns the number of denied system calls since boot.
+
+use core::cell::Cell;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::log::{LogWrite, LogWriteClient};
+use kernel::platform::SyscallFilter;
+use kernel::process::Process;
+use kernel::process_checker::{TbfFooterV2Credentials, TbfFooterV2CredentialsType};
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, Syscall, SyscallClass, SyscallDriver};
+use kernel::utilities::cells::TakeCell;
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::SyscallFilter as usize;
+
+/// Number of bytes of the data of a credential that rules compare.
+pub const CREDENTIAL_LEN: usize = 32;
+
+/// Longest process name stored in the audit log.
+pub const NAME_LEN: usize = 32;
+
+/// Command mask that allows every command of a driver.
+pub const ALL_COMMANDS: u32 = u32::MAX;
+
+/// Length of an entry in the audit log.
+pub const ENTRY_LEN: usize = 20 + NAME_LEN;
+
+/// Number of denials queued while the log is busy.
+const QUEUE_LEN: usize = 8;
+
+pub static mut LOG_BUF: [u8; ENTRY_LEN] = [0; ENTRY_LEN];
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const CREDENTIAL: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// What the filter does with processes that have an accepted credential but
+/// no rule.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Unlisted {
+    Allow,
+    Deny,
+}
+
+/// Identifies the processes whose accepted credential has a format and
+/// starts with some data.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Credential {
+    format: TbfFooterV2CredentialsType,
+    data: [u8; CREDENTIAL_LEN],
+    data_len: usize,
+}
+
+impl Credential {
+    /// Creates a credential of `format` that matches credentials whose data
+    /// starts with `data`, of which the first `CREDENTIAL_LEN` bytes are
+    /// used.
+    ///
+    /// Any application can carry a valid hash of itself, so hash formats need
+    /// a full hash, or its first `CREDENTIAL_LEN` bytes, to identify one
+    /// binary. Signature formats without data match every signed
+    /// application. Returns `ErrorCode::INVAL` for `Reserved` credentials and
+    /// for hashes that are too short.
+    pub fn new(format: TbfFooterV2CredentialsType, data: &[u8]) -> Result<Credential, ErrorCode> {
+        let data = &data[..data.len().min(CREDENTIAL_LEN)];
+        let min_len = match format {
+            TbfFooterV2CredentialsType::Reserved => return Err(ErrorCode::INVAL),
+            TbfFooterV2CredentialsType::SHA256
+            | TbfFooterV2CredentialsType::SHA384
+            | TbfFooterV2CredentialsType::SHA512 => CREDENTIAL_LEN,
+            TbfFooterV2CredentialsType::Rsa3072Key
+            | TbfFooterV2CredentialsType::Rsa4096Key
+            | TbfFooterV2CredentialsType::EcdsaNistP256 => 0,
+        };
+        if data.len() < min_len {
+            return Err(ErrorCode::INVAL);
+        }
+        let mut credential = Credential {
+            format,
+            data: [0; CREDENTIAL_LEN],
+            data_len: data.len(),
+        };
+        credential.data[..data.len()].copy_from_slice(data);
+        Ok(credential)
+    }
+
+    /// Parses a credential in the format of read-only allow `0`.
+    fn parse(buffer: &[u8]) -> Result<Credential, ErrorCode> {
+        let format = match buffer.first() {
+            Some(1) => TbfFooterV2CredentialsType::Rsa3072Key,
+            Some(2) => TbfFooterV2CredentialsType::Rsa4096Key,
+            Some(3) => TbfFooterV2CredentialsType::SHA256,
+            Some(4) => TbfFooterV2CredentialsType::SHA384,
+            Some(5) => TbfFooterV2CredentialsType::SHA512,
+            Some(6) => TbfFooterV2CredentialsType::EcdsaNistP256,
+            _ => return Err(ErrorCode::INVAL),
+        };
+        if buffer.len() > 1 + CREDENTIAL_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        Credential::new(format, &buffer[1..])
+    }
+
+    /// Whether the accepted credential of a process matches.
+    fn matches(&self, credentials: &TbfFooterV2Credentials) -> bool {
+        credentials.format() == self.format
+            && credentials.data().starts_with(&self.data[..self.data_len])
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Rule {
+    credential: Credential,
+    driver: usize,
+    commands: u32,
+}
+
+/// A denied system call waiting to be logged.
+#[derive(Clone, Copy)]
+struct Denial {
+    name: &'static str,
+    processid: ProcessId,
+    class: SyscallClass,
+    driver: usize,
+    subdriver: usize,
+    repeats: u16,
+}
+
+impl Denial {
+    fn same_call(&self, other: &Denial) -> bool {
+        self.processid == other.processid
+            && self.class as u8 == other.class as u8
+            && self.driver == other.driver
+            && self.subdriver == other.subdriver
+    }
+
+    fn encode(&self, lost: u16, buffer: &mut [u8]) {
+        let name = &self.name.as_bytes()[..self.name.len().min(NAME_LEN)];
+        buffer[0] = self.class as u8;
+        buffer[1] = name.len() as u8;
+        buffer[2..4].copy_from_slice(&self.repeats.to_le_bytes());
+        buffer[4..8].copy_from_slice(&(self.processid.id() as u32).to_le_bytes());
+        buffer[8..12].copy_from_slice(&(self.driver as u32).to_le_bytes());
+        buffer[12..16].copy_from_slice(&(self.subdriver as u32).to_le_bytes());
+        buffer[16..18].copy_from_slice(&lost.to_le_bytes());
+        buffer[18..20].copy_from_slice(&[0; 2]);
+        buffer[20..ENTRY_LEN].fill(0);
+        buffer[20..20 + name.len()].copy_from_slice(name);
+    }
+}
+
+pub struct AllowListFilter<
+    'a,
+    L: LogWrite<'a>,
+    C: ProcessManagementCapability,
+    const MAX_RULES: usize,
+> {
+    rules: [Cell<Option<Rule>>; MAX_RULES],
+    unlisted: Unlisted,
+    /// Credential of the process that may change the rules.
+    privileged: Credential,
+    kernel: &'static Kernel,
+    capability: C,
+    apps: Grant<(), UpcallCount<0>, AllowRoCount<{ ro_allow::COUNT }>, AllowRwCount<0>>,
+
+    log: &'a L,
+    log_buffer: TakeCell<'static, [u8]>,
+    /// Denials waiting for the log, oldest first.
+    queue: [Cell<Option<Denial>>; QUEUE_LEN],
+    /// Denials that did not fit in the queue.
+    lost: Cell<u16>,
+    /// The log has entries that are not synced yet.
+    unsynced: Cell<bool>,
+    /// A sync is in progress, so the log is busy until `sync_done`.
+    syncing: Cell<bool>,
+    denials: Cell<u32>,
+}
+
+impl<'a, L: LogWrite<'a>, C: ProcessManagementCapability, const MAX_RULES: usize>
+    AllowListFilter<'a, L, C, MAX_RULES>
+{
+    pub fn new(
+        log: &'a L,
+        log_buffer: &'static mut [u8],
+        kernel: &'static Kernel,
+        capability: C,
+        privileged: Credential,
+        unlisted: Unlisted,
+        grant: Grant<(), UpcallCount<0>, AllowRoCount<{ ro_allow::COUNT }>, AllowRwCount<0>>,
+    ) -> AllowListFilter<'a, L, C, MAX_RULES> {
+        AllowListFilter {
+            rules: [(); MAX_RULES].map(|_| Cell::new(None)),
+            unlisted,
+            privileged,
+            kernel,
+            capability,
+            apps: grant,
+            log,
+            log_buffer: TakeCell::new(log_buffer),
+            queue: Default::default(),
+            lost: Cell::new(0),
+            unsynced: Cell::new(false),
+            syncing: Cell::new(false),
+            denials: Cell::new(0),
+        }
+    }
+
+    /// Allows the processes with `credential` to use `driver`, and the
+    /// commands of it whose bits are set in `commands`. Replaces the mask if
+    /// the rule exists.
+    pub fn add_rule(
+        &self,
+        credential: Credential,
+        driver: usize,
+        commands: u32,
+    ) -> Result<(), ErrorCode> {
+        let rule = Rule {
+            credential,
+            driver,
+            commands,
+        };
+        let slot = self
+            .rules
+            .iter()
+            .find(|slot| {
+                slot.get()
+                    .map_or(false, |r| r.credential == credential && r.driver == driver)
+            })
+            .or_else(|| self.rules.iter().find(|slot| slot.get().is_none()))
+            .ok_or(ErrorCode::NOMEM)?;
+        slot.set(Some(rule));
+        Ok(())
+    }
+
+    /// Removes the rule for `credential` and `driver`.
+    pub fn remove_rule(&self, credential: Credential, driver: usize) -> Result<(), ErrorCode> {
+        self.remove_rules(credential, Some(driver))
+    }
+
+    /// Removes the rules for `credential` and `driver`, or all rules for
+    /// `credential`.
+    fn remove_rules(&self, credential: Credential, driver: Option<usize>) -> Result<(), ErrorCode> {
+        let mut removed = false;
+        for slot in self.rules.iter() {
+            if slot.get().map_or(false, |rule| {
+                rule.credential == credential && driver.map_or(true, |driver| rule.driver == driver)
+            }) {
+                slot.set(None);
+                removed = true;
+            }
+        }
+        if removed || driver.is_none() {
+            Ok(())
+        } else {
+            Err(ErrorCode::INVAL)
+        }
+    }
+
+    /// Checks the policy for the process with the accepted credential
+    /// `credentials` using `driver`, and command `command` for commands.
+    fn check(
+        &self,
+        credentials: Option<TbfFooterV2Credentials>,
+        driver: usize,
+        command: Option<usize>,
+    ) -> Result<(), ErrorCode> {
+        // Processes the kernel did not approve with a credential have no
+        // identity a rule could be for.
+        let credentials = credentials.ok_or(ErrorCode::NODEVICE)?;
+
+        if driver == DRIVER_NUM {
+            return if self.privileged.matches(&credentials) {
+                Ok(())
+            } else {
+                Err(ErrorCode::NODEVICE)
+            };
+        }
+
+        let mut listed = false;
+        for rule in self.rules.iter().filter_map(|slot| slot.get()) {
+            if !rule.credential.matches(&credentials) {
+                continue;
+            }
+            listed = true;
+            if rule.driver == driver {
+                let allowed = match command {
+                    None => true,
+                    Some(_) if rule.commands == ALL_COMMANDS => true,
+                    Some(command) => command < 32 && rule.commands & (1 << command) != 0,
+                };
+                if allowed {
+                    return Ok(());
+                }
+            }
+        }
+
+        if !listed && self.unlisted == Unlisted::Allow {
+            Ok(())
+        } else {
+            Err(ErrorCode::NODEVICE)
+        }
+    }
+
+    /// Queues a denial for the log, merging it with the newest queued one if
+    /// it is the same system call.
+    fn record_denial(&self, denial: Denial) {
+        self.denials.set(self.denials.get().wrapping_add(1));
+
+        let newest = self.queue.iter().rev().find(|slot| slot.get().is_some());
+        if let Some(slot) = newest {
+            if let Some(mut queued) = slot.get() {
+                if queued.same_call(&denial) && queued.repeats < u16::MAX {
+                    queued.repeats += 1;
+                    slot.set(Some(queued));
+                    return;
+                }
+            }
+        }
+        match self.queue.iter().find(|slot| slot.get().is_none()) {
+            Some(slot) => slot.set(Some(denial)),
+            None => self.lost.set(self.lost.get().saturating_add(1)),
+        }
+        self.write_next();
+    }
+
+    /// Writes the oldest queued denial to the log, or syncs the log once the
+    /// queue is empty.
+    fn write_next(&self) {
+        let oldest = match self.queue[0].get() {
+            Some(denial) => denial,
+            None => {
+                if self.unsynced.get() && self.log_buffer.is_some() && !self.syncing.get() {
+                    let started = self.log.sync().is_ok();
+                    self.unsynced.set(!started);
+                    self.syncing.set(started);
+                }
+                return;
+            }
+        };
+        if self.syncing.get() {
+            // The log refuses appends until `sync_done`.
+            return;
+        }
+        if let Some(buffer) = self.log_buffer.take() {
+            oldest.encode(self.lost.get(), buffer);
+            match self.log.append(buffer, ENTRY_LEN) {
+                Ok(()) => {
+                    // Shift the queue so that new denials are merged with the
+                    // newest entry that is not being written.
+                    for index in 1..QUEUE_LEN {
+                        self.queue[index - 1].set(self.queue[index].get());
+                    }
+                    self.queue[QUEUE_LEN - 1].set(None);
+                    self.lost.set(0);
+                }
+                Err((_, buffer)) => {
+                    // The denial stays queued and is retried on the next
+                    // denial or log callback.
+                    self.log_buffer.replace(buffer);
+                }
+            }
+        }
+    }
+
+    /// Whether the process is the privileged one.
+    fn is_privileged(&self, processid: ProcessId) -> bool {
+        self.kernel.process_map_or_external(
+            false,
+            processid,
+            |process| {
+                process
+                    .get_credentials()
+                    .map_or(false, |credentials| self.privileged.matches(&credentials))
+            },
+            &self.capability,
+        )
+    }
+
+    /// Parses the credential in the allow buffer of `processid`.
+    fn read_credential(&self, processid: ProcessId) -> Result<Credential, ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::CREDENTIAL)
+                    .and_then(|credential| {
+                        credential.enter(|credential| {
+                            let mut buffer = [0; 1 + CREDENTIAL_LEN];
+                            let len = credential.len();
+                            if len > buffer.len() {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            credential.copy_to_slice(&mut buffer[..len]);
+                            Credential::parse(&buffer[..len])
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<'a, L: LogWrite<'a>, C: ProcessManagementCapability, const MAX_RULES: usize> SyscallFilter
+    for AllowListFilter<'a, L, C, MAX_RULES>
+{
+    fn filter_syscall(&self, process: &dyn Process, syscall: &Syscall) -> Result<(), ErrorCode> {
+        let (class, driver, subdriver, command) = match *syscall {
+            Syscall::Subscribe {
+                driver_number,
+                subdriver_number,
+                ..
+            } => (
+                SyscallClass::Subscribe,
+                driver_number,
+                subdriver_number,
+                None,
+            ),
+            Syscall::Command {
+                driver_number,
+                subdriver_number,
+                ..
+            } => (
+                SyscallClass::Command,
+                driver_number,
+                subdriver_number,
+                Some(subdriver_number),
+            ),
+            Syscall::ReadWriteAllow {
+                driver_number,
+                subdriver_number,
+                ..
+            } => (
+                SyscallClass::ReadWriteAllow,
+                driver_number,
+                subdriver_number,
+                None,
+            ),
+            Syscall::UserspaceReadableAllow {
+                driver_number,
+                subdriver_number,
+                ..
+            } => (
+                SyscallClass::UserspaceReadableAllow,
+                driver_number,
+                subdriver_number,
+                None,
+            ),
+            Syscall::ReadOnlyAllow {
+                driver_number,
+                subdriver_number,
+                ..
+            } => (
+                SyscallClass::ReadOnlyAllow,
+                driver_number,
+                subdriver_number,
+                None,
+            ),
+            // Non-filterable system calls
+            Syscall::Yield { .. } | Syscall::Memop { .. } | Syscall::Exit { .. } => return Ok(()),
+        };
+
+        self.check(process.get_credentials(), driver, command)
+            .map_err(|err| {
+                self.record_denial(Denial {
+                    name: process.get_process_name(),
+                    processid: process.processid(),
+                    class,
+                    driver,
+                    subdriver,
+                    repeats: 1,
+                });
+                err
+            })
+    }
+}
+
+impl<'a, L: LogWrite<'a>, C: ProcessManagementCapability, const MAX_RULES: usize> LogWriteClient
+    for AllowListFilter<'a, L, C, MAX_RULES>
+{
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        _length: usize,
+        _records_lost: bool,
+        error: Result<(), ErrorCode>,
+    ) {
+        self.log_buffer.replace(buffer);
+        match error {
+            Ok(()) => self.unsynced.set(true),
+            Err(_) => self.lost.set(self.lost.get().saturating_add(1)),
+        }
+        self.write_next();
+    }
+
+    fn sync_done(&self, error: Result<(), ErrorCode>) {
+        self.syncing.set(false);
+        if error.is_err() {
+            self.unsynced.set(true);
+        }
+        // Denials queued while syncing.
+        if self.queue[0].get().is_some() {
+            self.write_next();
+        }
+    }
+
+    fn erase_done(&self, _error: Result<(), ErrorCode>) {}
+}
+
+impl<'a, L: LogWrite<'a>, C: ProcessManagementCapability, const MAX_RULES: usize> SyscallDriver
+    for AllowListFilter<'a, L, C, MAX_RULES>
+{
+    /// Change the policy. Only the privileged process can use this driver.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Add or replace the rule for the allowed credential and the
+    ///   driver `data1` with the command mask `data2`.
+    /// - `2`: Remove the rule for the allowed credential and the driver
+    ///   `data1`.
+    /// - `3`: Remove all rules for the allowed credential.
+    /// - `4`: Get the number of denied system calls since boot.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if !self.is_privileged(processid) {
+            return CommandReturn::failure(ErrorCode::NODEVICE);
+        }
+
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 | 2 | 3 => {
+                let credential = match self.read_credential(processid) {
+                    Ok(credential) => credential,
+                    Err(err) => return CommandReturn::failure(err),
+                };
+                let result = match command_num {
+                    1 => self.add_rule(credential, data1, data2 as u32),
+                    2 => self.remove_rules(credential, Some(data1)),
+                    _ => self.remove_rules(credential, None),
+                };
+                CommandReturn::from(result)
+            }
+
+            4 => CommandReturn::success_u32(self.denials.get()),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
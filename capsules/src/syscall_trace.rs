@@ -0,0 +1,399 @@
+//! Records the system calls of processes and streams them to a host.
+//!
+//! This capsule is a `SyscallTracer` for the kernel. It records every system
+//! call of every process together with its return value and a timestamp into
+//! a ring buffer for that process, and streams the records in a compact binary
+//! format over a `uart::Transmit` device, such as a UART or `segger_rtt`. The
+//! stream is decoded on the host with `tools/decode_syscall_trace.py`.
+//!
+//! If a process makes system calls faster than they can be sent, the oldest
+//! records of the process are overwritten, and the next record that is sent
+//! says how many records were lost.
+//!
+//! The stream is binary, so the device should not be shared with the console.
+//!
+//! Stream Format
+//! -------------
+//!
+//! All values are little endian. The stream starts with a 12 byte header:
+//!
+//! - `0..4`: Magic `TKST`.
+//! - `4`: Format version, currently 1.
+//! - `5`: Length of a record.
+//! - `6`: Width of the timestamps in bits.
+//! - `7`: Reserved.
+//! - `8..12`: Frequency of the timestamps in Hz.
+//!
+//! The header is followed by records of `RECORD_LEN` bytes:
+//!
+//! - `0`: Sync byte `0xa5`.
+//! - `1`: System call class, as in `SyscallClass`.
+//! - `2..4`: Identifier of the process.
+//! - `4..8`: Timestamp in ticks of the timer, which wraps around at its
+//!   width.
+//! - `8..24`: The four arguments of the system call.
+//! - `24`: Flags, bit 0 is set if the record has a return value.
+//! - `25`: Reserved.
+//! - `26..28`: Number of records of this process lost before this record.
+//! - `28..44`: The return value, encoded in four registers as specified in
+//!   TRD104.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let trace_uart = static_init!(UartDevice, UartDevice::new(uart_mux, false));
+//! trace_uart.setup();
+//! let syscall_trace = components::syscall_trace::SyscallTraceComponent::new(
+//!     board_kernel,
+//!     trace_uart,
+//!     &base_peripherals.rtc,
+//! )
+//! .finalize(components::syscall_trace_component_helper!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     NUM_PROCS,
+//!     32
+//! ));
+//! board_kernel.set_syscall_tracer(syscall_trace, &main_loop_capability);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::collections::queue::Queue;
+use kernel::collections::ring_buffer::RingBuffer;
+use kernel::hil::time::{Frequency, Ticks, Time};
+use kernel::hil::uart;
+use kernel::platform::SyscallTracer;
+use kernel::process::Process;
+use kernel::syscall::{Syscall, SyscallClass, SyscallReturn};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Length of the stream header.
+pub const HEADER_LEN: usize = 12;
+/// Length of an encoded record.
+pub const RECORD_LEN: usize = 44;
+
+const MAGIC: &[u8; 4] = b"TKST";
+const VERSION: u8 = 1;
+const SYNC: u8 = 0xa5;
+const FLAG_RETURNED: u8 = 0x01;
+
+pub static mut TX_BUF: [u8; 4 * RECORD_LEN] = [0; 4 * RECORD_LEN];
+
+/// A system call of a process.
+#[derive(Clone, Copy)]
+pub struct Record {
+    class: u8,
+    timestamp: u32,
+    arguments: [u32; 4],
+    /// The return value encoded in registers, if the system call returned
+    /// one.
+    result: Option<[u32; 4]>,
+}
+
+impl Record {
+    /// A record to initialize trace buffers with.
+    pub const EMPTY: Record = Record {
+        class: 0,
+        timestamp: 0,
+        arguments: [0; 4],
+        result: None,
+    };
+
+    fn new(timestamp: u32, syscall: &Syscall, result: Option<&SyscallReturn>) -> Record {
+        let (class, arguments) = match *syscall {
+            Syscall::Yield { which, address } => {
+                (SyscallClass::Yield, [which, address as usize, 0, 0])
+            }
+            Syscall::Subscribe {
+                driver_number,
+                subdriver_number,
+                upcall_ptr,
+                appdata,
+            } => (
+                SyscallClass::Subscribe,
+                [
+                    driver_number,
+                    subdriver_number,
+                    upcall_ptr as usize,
+                    appdata,
+                ],
+            ),
+            Syscall::Command {
+                driver_number,
+                subdriver_number,
+                arg0,
+                arg1,
+            } => (
+                SyscallClass::Command,
+                [driver_number, subdriver_number, arg0, arg1],
+            ),
+            Syscall::ReadWriteAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => (
+                SyscallClass::ReadWriteAllow,
+                [
+                    driver_number,
+                    subdriver_number,
+                    allow_address as usize,
+                    allow_size,
+                ],
+            ),
+            Syscall::UserspaceReadableAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => (
+                SyscallClass::UserspaceReadableAllow,
+                [
+                    driver_number,
+                    subdriver_number,
+                    allow_address as usize,
+                    allow_size,
+                ],
+            ),
+            Syscall::ReadOnlyAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => (
+                SyscallClass::ReadOnlyAllow,
+                [
+                    driver_number,
+                    subdriver_number,
+                    allow_address as usize,
+                    allow_size,
+                ],
+            ),
+            Syscall::Memop { operand, arg0 } => (SyscallClass::Memop, [operand, arg0, 0, 0]),
+            Syscall::Exit {
+                which,
+                completion_code,
+            } => (SyscallClass::Exit, [which, completion_code, 0, 0]),
+        };
+
+        Record {
+            class: class as u8,
+            timestamp,
+            arguments: arguments.map(|argument| argument as u32),
+            result: result.map(|result| {
+                let mut registers = [0; 4];
+                let [a0, a1, a2, a3] = &mut registers;
+                result.encode_syscall_return(a0, a1, a2, a3);
+                registers
+            }),
+        }
+    }
+
+    fn encode(&self, processid: ProcessId, dropped: u16, buffer: &mut [u8]) {
+        buffer[0] = SYNC;
+        buffer[1] = self.class;
+        buffer[2..4].copy_from_slice(&(processid.id() as u16).to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        for (bytes, argument) in buffer[8..24].chunks_exact_mut(4).zip(self.arguments) {
+            bytes.copy_from_slice(&argument.to_le_bytes());
+        }
+        buffer[24] = if self.result.is_some() {
+            FLAG_RETURNED
+        } else {
+            0
+        };
+        buffer[25] = 0;
+        buffer[26..28].copy_from_slice(&dropped.to_le_bytes());
+        let result = self.result.unwrap_or_default();
+        for (bytes, register) in buffer[28..44].chunks_exact_mut(4).zip(result) {
+            bytes.copy_from_slice(&register.to_le_bytes());
+        }
+    }
+}
+
+/// The trace buffer of one process.
+pub struct ProcessTrace {
+    owner: OptionalCell<ProcessId>,
+    records: MapCell<RingBuffer<'static, Record>>,
+    /// Number of records overwritten since the last record was sent.
+    dropped: Cell<u16>,
+    /// Number of records, sent or dropped, in the buffer being handed to the
+    /// UART, counted as dropped if the UART refuses the buffer.
+    pending: Cell<u16>,
+}
+
+impl ProcessTrace {
+    pub fn new(records: &'static mut [Record]) -> ProcessTrace {
+        ProcessTrace {
+            owner: OptionalCell::empty(),
+            records: MapCell::new(RingBuffer::new(records)),
+            dropped: Cell::new(0),
+            pending: Cell::new(0),
+        }
+    }
+}
+
+pub struct SyscallTrace<'a, T: Time, C: ProcessManagementCapability> {
+    uart: &'a dyn uart::Transmit<'a>,
+    time: &'a T,
+    kernel: &'static Kernel,
+    capability: C,
+    traces: &'a [ProcessTrace],
+    tx_buffer: TakeCell<'static, [u8]>,
+    header_sent: Cell<bool>,
+    /// The trace the next record is sent from, so that the records of all
+    /// processes are interleaved.
+    next: Cell<usize>,
+}
+
+impl<'a, T: Time, C: ProcessManagementCapability> SyscallTrace<'a, T, C> {
+    pub fn new(
+        uart: &'a dyn uart::Transmit<'a>,
+        time: &'a T,
+        kernel: &'static Kernel,
+        capability: C,
+        traces: &'a [ProcessTrace],
+        tx_buffer: &'static mut [u8],
+    ) -> SyscallTrace<'a, T, C> {
+        SyscallTrace {
+            uart,
+            time,
+            kernel,
+            capability,
+            traces,
+            tx_buffer: TakeCell::new(tx_buffer),
+            header_sent: Cell::new(false),
+            next: Cell::new(0),
+        }
+    }
+
+    fn is_alive(&self, processid: ProcessId) -> bool {
+        self.kernel
+            .process_map_or_external(false, processid, |_| true, &self.capability)
+    }
+
+    /// The trace of a process. A process without a trace takes a free one or
+    /// the one of a process that no longer exists.
+    fn trace_for(&self, processid: ProcessId) -> Option<&ProcessTrace> {
+        let trace = self
+            .traces
+            .iter()
+            .find(|trace| trace.owner.contains(&processid))
+            .or_else(|| self.traces.iter().find(|trace| trace.owner.is_none()));
+        let trace = match trace {
+            Some(trace) => trace,
+            None => self
+                .traces
+                .iter()
+                .find(|trace| !trace.owner.map_or(false, |owner| self.is_alive(*owner)))?,
+        };
+        if !trace.owner.contains(&processid) {
+            trace.owner.set(processid);
+            trace.records.map(|records| records.empty());
+            trace.dropped.set(0);
+        }
+        Some(trace)
+    }
+
+    /// Takes the next record to send, from the processes in turn.
+    fn next_record(&self) -> Option<(ProcessId, u16, Record)> {
+        let count = self.traces.len();
+        (0..count).find_map(|offset| {
+            let index = (self.next.get() + offset) % count;
+            let trace = &self.traces[index];
+            let owner = trace.owner.extract()?;
+            let record = trace.records.map(|records| records.dequeue()).flatten()?;
+            self.next.set(index + 1);
+            let dropped = trace.dropped.replace(0);
+            trace.pending.set(
+                trace
+                    .pending
+                    .get()
+                    .saturating_add(dropped)
+                    .saturating_add(1),
+            );
+            Some((owner, dropped, record))
+        })
+    }
+
+    /// Sends as many records as fit in the buffer, if it is not in use.
+    fn send(&self) {
+        self.tx_buffer.take().map(|buffer| {
+            let mut len = 0;
+            if !self.header_sent.get() {
+                buffer[0..4].copy_from_slice(MAGIC);
+                buffer[4] = VERSION;
+                buffer[5] = RECORD_LEN as u8;
+                buffer[6] = (u32::BITS - T::Ticks::max_value().into_u32().leading_zeros()) as u8;
+                buffer[7] = 0;
+                buffer[8..12].copy_from_slice(&T::Frequency::frequency().to_le_bytes());
+                len = HEADER_LEN;
+            }
+            while len + RECORD_LEN <= buffer.len() {
+                match self.next_record() {
+                    Some((processid, dropped, record)) => {
+                        record.encode(processid, dropped, &mut buffer[len..len + RECORD_LEN]);
+                        len += RECORD_LEN;
+                    }
+                    None => break,
+                }
+            }
+
+            if len == 0 {
+                self.tx_buffer.replace(buffer);
+                return;
+            }
+            let sent = match self.uart.transmit_buffer(buffer, len) {
+                Ok(()) => true,
+                Err((_, buffer)) => {
+                    self.tx_buffer.replace(buffer);
+                    false
+                }
+            };
+            if sent {
+                self.header_sent.set(true);
+            }
+            for trace in self.traces {
+                let pending = trace.pending.replace(0);
+                if !sent {
+                    trace
+                        .dropped
+                        .set(trace.dropped.get().saturating_add(pending));
+                }
+            }
+        });
+    }
+}
+
+impl<'a, T: Time, C: ProcessManagementCapability> SyscallTracer for SyscallTrace<'a, T, C> {
+    fn syscall_handled(
+        &self,
+        process: &dyn Process,
+        syscall: &Syscall,
+        result: Option<&SyscallReturn>,
+    ) {
+        let record = Record::new(self.time.now().into_u32(), syscall, result);
+        if let Some(trace) = self.trace_for(process.processid()) {
+            let overwritten = trace.records.map(|records| records.push(record).is_some());
+            if overwritten == Some(true) {
+                trace.dropped.set(trace.dropped.get().saturating_add(1));
+            }
+        }
+        self.send();
+    }
+}
+
+impl<'a, T: Time, C: ProcessManagementCapability> uart::TransmitClient for SyscallTrace<'a, T, C> {
+    fn transmitted_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rcode: Result<(), ErrorCode>,
+    ) {
+        self.tx_buffer.replace(buffer);
+        self.send();
+    }
+}
@@ -45,6 +45,7 @@ enum Operation {
     Init,
     GetKey,
     AppendKey,
+    AppendKeyMut,
     InvalidateKey,
     GarbageCollect,
 }
@@ -124,6 +125,8 @@ pub struct TicKVStore<'a, F: Flash + 'static, H: Hasher<'a, 8>> {
     next_operation: Cell<Operation>,
 
     value_buffer: Cell<Option<&'static [u8]>>,
+    mut_value_buffer: TakeCell<'static, [u8]>,
+    value_length: Cell<usize>,
     key_buffer: TakeCell<'static, [u8; 8]>,
     ret_buffer: TakeCell<'static, [u8]>,
     unhashed_key_buf: TakeCell<'static, [u8]>,
@@ -153,6 +156,8 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> TicKVStore<'a, F, H> {
             operation: Cell::new(Operation::None),
             next_operation: Cell::new(Operation::None),
             value_buffer: Cell::new(None),
+            mut_value_buffer: TakeCell::empty(),
+            value_length: Cell::new(0),
             key_buffer: TakeCell::empty(),
             ret_buffer: TakeCell::empty(),
             unhashed_key_buf: TakeCell::empty(),
@@ -183,6 +188,20 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> TicKVStore<'a, F, H> {
                     _ => {}
                 }
             }
+            Operation::AppendKeyMut => {
+                match self.append_key_mut(
+                    self.key_buffer.take().unwrap(),
+                    self.mut_value_buffer.take().unwrap(),
+                    self.value_length.get(),
+                ) {
+                    Err((key, value, error)) => {
+                        self.client.map(move |cb| {
+                            cb.append_key_mut_complete(error, key, value);
+                        });
+                    }
+                    _ => {}
+                }
+            }
             Operation::GetKey => {
                 match self.get_value(
                     self.key_buffer.take().unwrap(),
@@ -217,6 +236,24 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> TicKVStore<'a, F, H> {
         }
         self.next_operation.set(Operation::None);
     }
+
+    /// Return the key and value buffers of the completed `operation`, which is
+    /// either `AppendKey` or `AppendKeyMut`, to the client.
+    fn append_key_done(&self, operation: Operation, result: Result<(), ErrorCode>) {
+        self.operation.set(Operation::None);
+        let key = self.key_buffer.take().unwrap();
+        self.client.map(move |cb| {
+            if operation == Operation::AppendKeyMut {
+                cb.append_key_mut_complete(
+                    result,
+                    key,
+                    self.tickv.get_stored_mut_value_buffer().unwrap(),
+                );
+            } else {
+                cb.append_key_complete(result, key, self.tickv.get_stored_value_buffer().unwrap());
+            }
+        });
+    }
 }
 
 impl<'a, F: Flash, H: Hasher<'a, 8>> hasher::Client<'a, 8> for TicKVStore<'a, F, H> {
@@ -269,7 +306,9 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> flash::Client<F> for TicKVStore<'a, F, H> {
                         );
                     });
                 }
-                Err(tickv::error_codes::ErrorCode::EraseNotReady(_)) | Ok(_) => {}
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::EraseNotReady(_))
+                | Ok(_) => {}
                 _ => {
                     self.operation.set(Operation::None);
                     self.client.map(|cb| {
@@ -281,19 +320,41 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> flash::Client<F> for TicKVStore<'a, F, H> {
                     });
                 }
             },
-            Operation::AppendKey => match ret {
+            operation @ (Operation::AppendKey | Operation::AppendKeyMut) => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
                     self.operation.set(Operation::None);
                 }
-                _ => {}
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::WriteNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::EraseNotReady(_))
+                | Ok(_) => {}
+                _ => {
+                    // The key could not be added, for example because it
+                    // already exists.
+                    self.append_key_done(operation, Err(ErrorCode::FAIL));
+                }
             },
             Operation::InvalidateKey => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
                     self.operation.set(Operation::None);
                 }
-                _ => {}
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::WriteNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::EraseNotReady(_))
+                | Ok(_) => {}
+                _ => {
+                    // The key could not be invalidated, for example because
+                    // it does not exist.
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.invalidate_key_complete(
+                            Err(ErrorCode::FAIL),
+                            self.key_buffer.take().unwrap(),
+                        );
+                    });
+                }
             },
             Operation::GarbageCollect => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
@@ -320,15 +381,8 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> flash::Client<F> for TicKVStore<'a, F, H> {
             Operation::Init => {
                 self.complete_init();
             }
-            Operation::AppendKey => {
-                self.operation.set(Operation::None);
-                self.client.map(|cb| {
-                    cb.append_key_complete(
-                        Ok(()),
-                        self.key_buffer.take().unwrap(),
-                        self.tickv.get_stored_value_buffer().unwrap(),
-                    );
-                });
+            operation @ (Operation::AppendKey | Operation::AppendKeyMut) => {
+                self.append_key_done(operation, Ok(()));
             }
             Operation::InvalidateKey => {
                 self.operation.set(Operation::None);
@@ -418,7 +472,10 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> KVSystem<'a> for TicKVStore<'a, F, H> {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, value, Err(ErrorCode::FAIL))),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((key, value, Err(ErrorCode::FAIL)))
+                        }
                     },
                 }
             }
@@ -437,6 +494,60 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> KVSystem<'a> for TicKVStore<'a, F, H> {
         }
     }
 
+    fn append_key_mut(
+        &self,
+        key: &'static mut Self::K,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            &'static mut [u8],
+            Result<(), ErrorCode>,
+        ),
+    > {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::AppendKeyMut);
+
+                match self
+                    .tickv
+                    .append_key_mut(u64::from_le_bytes(*key), value, length)
+                {
+                    Ok(_ret) => {
+                        self.key_buffer.replace(key);
+                        Ok(())
+                    }
+                    Err((buf, e)) => match e {
+                        tickv::error_codes::ErrorCode::ReadNotReady(_)
+                        | tickv::error_codes::ErrorCode::WriteNotReady(_) => {
+                            self.key_buffer.replace(key);
+                            Ok(())
+                        }
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((key, buf.unwrap(), Err(ErrorCode::FAIL)))
+                        }
+                    },
+                }
+            }
+            Operation::Init => {
+                // The init process is still occuring.
+                // We can save this request and start it after init
+                self.next_operation.set(Operation::AppendKeyMut);
+                self.key_buffer.replace(key);
+                self.mut_value_buffer.replace(value);
+                self.value_length.set(length);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err((key, value, Err(ErrorCode::BUSY)))
+            }
+        }
+    }
+
     fn get_value(
         &self,
         key: &'static mut Self::K,
@@ -464,7 +575,10 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> KVSystem<'a> for TicKVStore<'a, F, H> {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, buf.unwrap(), Err(ErrorCode::FAIL))),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((key, buf.unwrap(), Err(ErrorCode::FAIL)))
+                        }
                     },
                 }
             }
@@ -502,7 +616,10 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> KVSystem<'a> for TicKVStore<'a, F, H> {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, Err(ErrorCode::FAIL))),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((key, Err(ErrorCode::FAIL)))
+                        }
                     },
                 }
             }
@@ -277,8 +277,9 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> CdcAcm<'a, U, A> {
                 },
                 interfaces,
                 endpoints,
-                None, // No HID descriptor
+                &[], // No HID descriptor
                 Some(cdc_descriptors),
+                None, // No DFU descriptor
             );
 
         Self {
@@ -286,8 +287,8 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> CdcAcm<'a, U, A> {
                 controller,
                 device_descriptor_buffer,
                 other_descriptor_buffer,
-                None, // No HID descriptor
-                None, // No report descriptor
+                &[], // No HID descriptor
+                &[], // No report descriptor
                 LANGUAGES,
                 strings,
             ),
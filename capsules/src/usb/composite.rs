@@ -0,0 +1,761 @@
+//! Composite USB device
+//!
+//! This capsule combines several USB class drivers into a single device with
+//! one configuration, so that a board can, for example, expose a CDC-ACM
+//! console and a CTAP HID authenticator at the same time.
+//!
+//! Each class driver is given a `UsbFunction`, a virtual USB controller, in
+//! place of the real hardware. The `CompositeDevice` is the client of the real
+//! controller and routes its callbacks to the functions:
+//!
+//! ```
+//!      CdcAcm        CtapHid
+//!        |              |
+//!   UsbFunction    UsbFunction
+//!         \            /
+//!        CompositeDevice
+//!               |
+//!         UsbController
+//! ```
+//!
+//! When the device is enabled, the composite device reads the configuration
+//! descriptor of every function, renumbers its interfaces and endpoints so that
+//! they do not overlap, and precedes it with an Interface Association
+//! Descriptor. The host is served the combined configuration. Standard device
+//! requests are answered by the composite device itself. Interface and
+//! endpoint requests are routed to the function owning the addressed interface
+//! or endpoint, with the index translated to the function's own numbering.
+//! Class and vendor requests addressed to the device go to the first function.
+//!
+//! Functions keep using their own interface and endpoint numbers: endpoint `n`
+//! of a function is endpoint `n + offset` on the bus, where the offset is the
+//! number of endpoints used by the functions before it. The controller must
+//! provide enough endpoints for all functions.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let composite = components::usb_composite::UsbCompositeComponent::new(
+//!     &nrf52840_peripherals.usbd,
+//!     capsules::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x503a,
+//!     STRINGS,
+//! )
+//! .finalize(components::usb_composite_component_helper!(
+//!     nrf52840::usbd::Usbd
+//! ));
+//!
+//! let cdc_function = components::usb_composite::UsbFunctionComponent::new(composite)
+//!     .finalize(components::usb_function_component_helper!(nrf52840::usbd::Usbd));
+//! let cdc = components::cdc::CdcAcmComponent::new(cdc_function, ...)
+//!     .finalize(components::usb_cdc_acm_component_helper!(
+//!         capsules::usb::composite::UsbFunction<'static, nrf52840::usbd::Usbd>,
+//!         nrf52::rtc::Rtc
+//!     ));
+//!
+//! // ... more functions ...
+//!
+//! composite.enable();
+//! composite.attach();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::CdcInterfaceDescriptorSubType;
+use super::descriptors::ConfigurationDescriptor;
+use super::descriptors::Descriptor;
+use super::descriptors::DescriptorType;
+use super::descriptors::InterfaceAssociationDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::SetupData;
+use super::descriptors::StandardRequest;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::cells::VolatileCell;
+
+/// Size of the buffer holding the combined configuration descriptor.
+pub const DESCRIPTOR_BUFLEN: usize = 256;
+
+/// Number of endpoints, including the control endpoint, a single function may
+/// use.
+const MAX_FUNCTION_ENDPOINTS: usize = 8;
+
+/// Length of a SETUP packet.
+const SETUP_LEN: usize = 8;
+
+const CONFIGURATION_DESCRIPTOR_LEN: usize = 9;
+const IAD_LEN: usize = 8;
+
+const INTERFACE_DESCRIPTOR: u8 = DescriptorType::Interface as u8;
+const ENDPOINT_DESCRIPTOR: u8 = DescriptorType::Endpoint as u8;
+const CDC_INTERFACE_DESCRIPTOR: u8 = DescriptorType::CdcInterface as u8;
+const CDC_CALL_MANAGEMENT: u8 = CdcInterfaceDescriptorSubType::CallManagement as u8;
+const CDC_UNION: u8 = CdcInterfaceDescriptorSubType::Union as u8;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Endpoint configuration requested by a function. It is applied to the
+/// controller once the function's endpoints have been allocated.
+#[derive(Default)]
+struct EndpointConfig<'a> {
+    in_buffer: Cell<Option<&'a [VolatileCell<u8>]>>,
+    out_buffer: Cell<Option<&'a [VolatileCell<u8>]>>,
+    in_type: Cell<Option<TransferType>>,
+    out_type: Cell<Option<TransferType>>,
+}
+
+/// A virtual USB controller handed to one class driver of a composite device.
+pub struct UsbFunction<'a, U: hil::usb::UsbController<'a>> {
+    device: &'a CompositeDevice<'a, U>,
+    client: OptionalCell<&'a dyn hil::usb::Client<'a>>,
+    ctrl_buffer: OptionalCell<&'a [VolatileCell<u8>]>,
+    endpoints: [EndpointConfig<'a>; MAX_FUNCTION_ENDPOINTS],
+    first_interface: Cell<u8>,
+    interface_count: Cell<u8>,
+    endpoint_offset: Cell<usize>,
+    endpoint_count: Cell<usize>,
+    next: ListLink<'a, UsbFunction<'a, U>>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> ListNode<'a, UsbFunction<'a, U>> for UsbFunction<'a, U> {
+    fn next(&self) -> &'a ListLink<UsbFunction<'a, U>> {
+        &self.next
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> UsbFunction<'a, U> {
+    pub fn new(device: &'a CompositeDevice<'a, U>) -> Self {
+        UsbFunction {
+            device,
+            client: OptionalCell::empty(),
+            ctrl_buffer: OptionalCell::empty(),
+            endpoints: Default::default(),
+            first_interface: Cell::new(0),
+            interface_count: Cell::new(0),
+            endpoint_offset: Cell::new(0),
+            endpoint_count: Cell::new(0),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Add this function to the composite device. Functions are placed in the
+    /// configuration in the order they are set up.
+    pub fn setup(&'a self) {
+        self.device.functions.push_tail(self);
+    }
+
+    fn device_endpoint(&self, endpoint: usize) -> usize {
+        if endpoint == 0 {
+            0
+        } else {
+            endpoint + self.endpoint_offset.get()
+        }
+    }
+
+    fn owns_interface(&self, interface: u8) -> bool {
+        let first = self.first_interface.get();
+        interface >= first && interface - first < self.interface_count.get()
+    }
+
+    fn owns_endpoint(&self, endpoint: usize) -> bool {
+        let offset = self.endpoint_offset.get();
+        endpoint > offset && endpoint - offset <= self.endpoint_count.get()
+    }
+
+    /// The highest endpoint number the function has set up.
+    fn highest_endpoint(&self) -> usize {
+        self.endpoints
+            .iter()
+            .rposition(|config| {
+                config.in_buffer.get().is_some()
+                    || config.out_buffer.get().is_some()
+                    || config.in_type.get().is_some()
+                    || config.out_type.get().is_some()
+            })
+            .unwrap_or(0)
+    }
+
+    /// Record the interfaces and endpoints allocated to this function and
+    /// configure its endpoints on the controller.
+    fn configure(
+        &self,
+        first_interface: u8,
+        interface_count: u8,
+        endpoint_offset: usize,
+        endpoint_count: usize,
+    ) {
+        self.first_interface.set(first_interface);
+        self.interface_count.set(interface_count);
+        self.endpoint_offset.set(endpoint_offset);
+        self.endpoint_count.set(endpoint_count);
+
+        let controller = self.device.controller();
+        for (endpoint, config) in self.endpoints.iter().enumerate().skip(1) {
+            let device_endpoint = self.device_endpoint(endpoint);
+            if let Some(buf) = config.in_buffer.get() {
+                controller.endpoint_set_in_buffer(device_endpoint, buf);
+            }
+            if let Some(buf) = config.out_buffer.get() {
+                controller.endpoint_set_out_buffer(device_endpoint, buf);
+            }
+            match (config.in_type.get(), config.out_type.get()) {
+                (Some(transfer_type), Some(_)) => {
+                    controller.endpoint_in_out_enable(transfer_type, device_endpoint)
+                }
+                (Some(transfer_type), None) => {
+                    controller.endpoint_in_enable(transfer_type, device_endpoint)
+                }
+                (None, Some(transfer_type)) => {
+                    controller.endpoint_out_enable(transfer_type, device_endpoint)
+                }
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::UsbController<'a> for UsbFunction<'a, U> {
+    fn set_client(&self, client: &'a dyn hil::usb::Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn endpoint_set_ctrl_buffer(&self, buf: &'a [VolatileCell<u8>]) {
+        self.ctrl_buffer.set(buf);
+    }
+
+    fn endpoint_set_in_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        if let Some(config) = self.endpoints.get(endpoint) {
+            config.in_buffer.set(Some(buf));
+        }
+    }
+
+    fn endpoint_set_out_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        if let Some(config) = self.endpoints.get(endpoint) {
+            config.out_buffer.set(Some(buf));
+        }
+    }
+
+    // The device state is owned by the composite device, so the following
+    // requests from a function are ignored.
+
+    fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {}
+
+    fn attach(&self) {}
+
+    fn detach(&self) {}
+
+    fn set_address(&self, _addr: u16) {}
+
+    fn enable_address(&self) {}
+
+    fn endpoint_in_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        if endpoint != 0 {
+            if let Some(config) = self.endpoints.get(endpoint) {
+                config.in_type.set(Some(transfer_type));
+            }
+        }
+    }
+
+    fn endpoint_out_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        if endpoint != 0 {
+            if let Some(config) = self.endpoints.get(endpoint) {
+                config.out_type.set(Some(transfer_type));
+            }
+        }
+    }
+
+    fn endpoint_in_out_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        self.endpoint_in_enable(transfer_type, endpoint);
+        self.endpoint_out_enable(transfer_type, endpoint);
+    }
+
+    fn endpoint_resume_in(&self, endpoint: usize) {
+        self.device
+            .controller()
+            .endpoint_resume_in(self.device_endpoint(endpoint));
+    }
+
+    fn endpoint_resume_out(&self, endpoint: usize) {
+        self.device
+            .controller()
+            .endpoint_resume_out(self.device_endpoint(endpoint));
+    }
+}
+
+/// State of a control transfer handled by the composite device itself.
+#[derive(Copy, Clone)]
+enum CtrlState {
+    Idle,
+    /// Sending the combined configuration descriptor, with the given extent
+    /// remaining to send.
+    ConfigurationIn(usize, usize),
+}
+
+/// A USB device made up of several functions.
+pub struct CompositeDevice<'a, U: hil::usb::UsbController<'a>> {
+    /// Helper USB client library for the requests handled by the device.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    functions: List<'a, UsbFunction<'a, U>>,
+
+    /// The combined configuration descriptor.
+    descriptor_storage: &'a [Cell<u8>],
+    descriptor_len: Cell<usize>,
+
+    /// The function handling the current control transfer, if any.
+    ctrl_owner: OptionalCell<&'a UsbFunction<'a, U>>,
+    ctrl_state: Cell<CtrlState>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> CompositeDevice<'a, U> {
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        descriptor_storage: &'a mut [u8],
+    ) -> Self {
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id,
+                    product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    class: 0xef,    // Miscellaneous
+                    subclass: 0x02, // Common class
+                    protocol: 0x01, // Interface Association Descriptor
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                &mut [],
+                &[],
+                &[],  // No HID descriptor
+                None, // No CDC descriptor array
+                None, // No DFU descriptor
+            );
+
+        CompositeDevice {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                &[], // No HID descriptor
+                &[], // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            functions: List::new(),
+            descriptor_storage: Cell::from_mut(descriptor_storage).as_slice_of_cells(),
+            descriptor_len: Cell::new(0),
+            ctrl_owner: OptionalCell::empty(),
+            ctrl_state: Cell::new(CtrlState::Idle),
+        }
+    }
+
+    #[inline]
+    fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    /// Read the configuration descriptor of `function` by issuing it a
+    /// GET_DESCRIPTOR request, and store everything after the configuration
+    /// descriptor itself at `offset` in the descriptor storage. Returns the
+    /// number of bytes stored.
+    fn load_function_descriptors(
+        &self,
+        function: &'a UsbFunction<'a, U>,
+        client: &'a dyn hil::usb::Client<'a>,
+        offset: usize,
+    ) -> usize {
+        let buf = match function.ctrl_buffer.extract() {
+            Some(buf) => buf,
+            None => return 0,
+        };
+        let setup = [
+            0x80, // Device to host, standard, device
+            0x06, // GET_DESCRIPTOR
+            0x00,
+            DescriptorType::Configuration as u8,
+            0x00,
+            0x00,
+            0xff,
+            0xff,
+        ];
+        for (b, v) in buf.iter().zip(setup.iter()) {
+            b.set(*v);
+        }
+
+        let storage = self.descriptor_storage;
+        let mut received = 0;
+        if let hil::usb::CtrlSetupResult::Ok = client.ctrl_setup(0) {
+            while let hil::usb::CtrlInResult::Packet(packet_bytes, last) = client.ctrl_in(0) {
+                for b in buf.iter().take(packet_bytes) {
+                    if received >= CONFIGURATION_DESCRIPTOR_LEN {
+                        let index = offset + received - CONFIGURATION_DESCRIPTOR_LEN;
+                        if index < storage.len() {
+                            storage[index].set(b.get());
+                        }
+                    }
+                    received += 1;
+                }
+                if last || packet_bytes == 0 {
+                    break;
+                }
+            }
+            client.ctrl_status_complete(0);
+        }
+
+        cmp::min(
+            received.saturating_sub(CONFIGURATION_DESCRIPTOR_LEN),
+            storage.len().saturating_sub(offset),
+        )
+    }
+
+    /// Renumber the interfaces and endpoints in the descriptors stored in
+    /// `start..end`. Returns the number of interfaces, the highest endpoint
+    /// number and the class of the first interface as declared by the
+    /// function.
+    fn relocate_descriptors(
+        &self,
+        start: usize,
+        end: usize,
+        first_interface: u8,
+        endpoint_offset: usize,
+    ) -> (u8, usize, (u8, u8, u8)) {
+        let mut interface_count = 0;
+        let mut endpoint_count = 0;
+        let mut class = None;
+
+        let mut i = start;
+        while i + 2 <= end {
+            let length = self.descriptor_storage[i].get() as usize;
+            if length < 2 || i + length > end {
+                break;
+            }
+            let d = &self.descriptor_storage[i..i + length];
+            match d[1].get() {
+                INTERFACE_DESCRIPTOR if length >= 9 => {
+                    if d[3].get() == 0 {
+                        interface_count += 1;
+                    }
+                    if class.is_none() {
+                        class = Some((d[5].get(), d[6].get(), d[7].get()));
+                    }
+                    d[2].set(d[2].get() + first_interface);
+                }
+                ENDPOINT_DESCRIPTOR if length >= 7 => {
+                    let address = d[2].get();
+                    let endpoint = (address & 0x0f) as usize;
+                    endpoint_count = cmp::max(endpoint_count, endpoint);
+                    d[2].set((address & 0x80) | (endpoint + endpoint_offset) as u8);
+                }
+                // CDC functional descriptors refer to other interfaces of the
+                // function.
+                CDC_INTERFACE_DESCRIPTOR if length >= 5 => match d[2].get() {
+                    CDC_CALL_MANAGEMENT => d[4].set(d[4].get() + first_interface),
+                    CDC_UNION => {
+                        for b in &d[3..] {
+                            b.set(b.get() + first_interface);
+                        }
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+            i += length;
+        }
+
+        (interface_count, endpoint_count, class.unwrap_or((0, 0, 0)))
+    }
+
+    /// Enable every function and build the combined configuration
+    /// descriptor.
+    fn enable_functions(&self) {
+        let storage = self.descriptor_storage;
+        let mut len = CONFIGURATION_DESCRIPTOR_LEN;
+        let mut next_interface = 0;
+        let mut endpoint_offset = 0;
+
+        for function in self.functions.iter() {
+            let client = match function.client.extract() {
+                Some(client) => client,
+                None => continue,
+            };
+            client.enable();
+
+            let start = len + IAD_LEN;
+            let function_len = if start < storage.len() {
+                self.load_function_descriptors(function, client, start)
+            } else {
+                0
+            };
+            let (interface_count, highest_endpoint, (class, subclass, protocol)) = self
+                .relocate_descriptors(start, start + function_len, next_interface, endpoint_offset);
+            let endpoint_count = cmp::max(highest_endpoint, function.highest_endpoint());
+            function.configure(
+                next_interface,
+                interface_count,
+                endpoint_offset,
+                endpoint_count,
+            );
+
+            if interface_count > 0 {
+                InterfaceAssociationDescriptor {
+                    first_interface: next_interface,
+                    interface_count,
+                    function_class: class,
+                    function_subclass: subclass,
+                    function_protocol: protocol,
+                    string_index: 0,
+                }
+                .write_to(&storage[len..]);
+                len = start + function_len;
+            }
+            next_interface += interface_count;
+            endpoint_offset += endpoint_count;
+        }
+
+        ConfigurationDescriptor {
+            num_interfaces: next_interface,
+            related_descriptor_length: len - CONFIGURATION_DESCRIPTOR_LEN,
+            ..ConfigurationDescriptor::default()
+        }
+        .write_to(storage);
+        self.descriptor_len.set(len);
+    }
+
+    /// Pass the current SETUP packet to `function`, replacing the low byte of
+    /// its index with `index`.
+    fn forward_setup(
+        &'a self,
+        function: &'a UsbFunction<'a, U>,
+        endpoint: usize,
+        index: u8,
+    ) -> hil::usb::CtrlSetupResult {
+        match (function.client.extract(), function.ctrl_buffer.extract()) {
+            (Some(client), Some(buf)) => {
+                copy_packet(&self.client_ctrl.ctrl_buffer.buf, buf, SETUP_LEN);
+                buf[4].set(index);
+                self.ctrl_owner.set(function);
+                client.ctrl_setup(endpoint)
+            }
+            _ => hil::usb::CtrlSetupResult::ErrGeneric,
+        }
+    }
+
+    fn function_for_interface(&self, interface: u8) -> Option<&'a UsbFunction<'a, U>> {
+        self.functions.iter().find(|f| f.owns_interface(interface))
+    }
+
+    fn function_for_endpoint(&self, endpoint: usize) -> Option<&'a UsbFunction<'a, U>> {
+        self.functions.iter().find(|f| f.owns_endpoint(endpoint))
+    }
+}
+
+/// Copy `len` bytes between two packet buffers.
+fn copy_packet(from: &[VolatileCell<u8>], to: &[VolatileCell<u8>], len: usize) {
+    for (t, f) in to.iter().zip(from.iter()).take(len) {
+        t.set(f.get());
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for CompositeDevice<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        self.enable_functions();
+    }
+
+    fn attach(&'a self) {
+        for function in self.functions.iter() {
+            function.client.map(|client| client.attach());
+        }
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        for function in self.functions.iter() {
+            function.client.map(|client| client.bus_reset());
+        }
+    }
+
+    /// Handle a Control Setup transaction.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        self.ctrl_owner.clear();
+        self.ctrl_state.set(CtrlState::Idle);
+
+        let setup_data = match SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+            Some(setup_data) => setup_data,
+            None => return hil::usb::CtrlSetupResult::ErrNoParse,
+        };
+        let index = setup_data.index as u8;
+
+        match setup_data.request_type.recipient() {
+            Recipient::Device => match setup_data.get_standard_request() {
+                Some(StandardRequest::GetDescriptor {
+                    descriptor_type: DescriptorType::Configuration,
+                    descriptor_index: 0,
+                    requested_length,
+                    ..
+                }) => {
+                    let end = cmp::min(self.descriptor_len.get(), requested_length as usize);
+                    self.ctrl_state.set(CtrlState::ConfigurationIn(0, end));
+                    hil::usb::CtrlSetupResult::Ok
+                }
+                Some(_) => self.client_ctrl.ctrl_setup(endpoint),
+                None => self
+                    .functions
+                    .head()
+                    .map_or(hil::usb::CtrlSetupResult::ErrGeneric, |function| {
+                        self.forward_setup(function, endpoint, index)
+                    }),
+            },
+            Recipient::Interface => self.function_for_interface(index).map_or(
+                hil::usb::CtrlSetupResult::ErrGeneric,
+                |function| {
+                    self.forward_setup(function, endpoint, index - function.first_interface.get())
+                },
+            ),
+            Recipient::Endpoint => self.function_for_endpoint((index & 0x0f) as usize).map_or(
+                hil::usb::CtrlSetupResult::ErrGeneric,
+                |function| {
+                    let local_endpoint = (index & 0x0f) - function.endpoint_offset.get() as u8;
+                    self.forward_setup(function, endpoint, (index & 0x80) | local_endpoint)
+                },
+            ),
+            _ => self.client_ctrl.ctrl_setup(endpoint),
+        }
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        if let Some(function) = self.ctrl_owner.extract() {
+            let result = function
+                .client
+                .map_or(hil::usb::CtrlInResult::Error, |client| {
+                    client.ctrl_in(endpoint)
+                });
+            if let hil::usb::CtrlInResult::Packet(packet_bytes, _) = result {
+                function.ctrl_buffer.map(|buf| {
+                    copy_packet(buf, &self.client_ctrl.ctrl_buffer.buf, packet_bytes);
+                });
+            }
+            return result;
+        }
+
+        match self.ctrl_state.get() {
+            CtrlState::ConfigurationIn(start, end) => {
+                let buf = &self.client_ctrl.ctrl_buffer.buf;
+                let packet_bytes = cmp::min(buf.len(), end.saturating_sub(start));
+                let packet = &self.descriptor_storage[start..start + packet_bytes];
+                for (b, d) in buf.iter().zip(packet.iter()) {
+                    b.set(d.get());
+                }
+
+                let start = start + packet_bytes;
+                self.ctrl_state.set(CtrlState::ConfigurationIn(start, end));
+                hil::usb::CtrlInResult::Packet(packet_bytes, start >= end)
+            }
+            CtrlState::Idle => self.client_ctrl.ctrl_in(endpoint),
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        match self.ctrl_owner.extract() {
+            Some(function) => {
+                function.ctrl_buffer.map(|buf| {
+                    copy_packet(
+                        &self.client_ctrl.ctrl_buffer.buf,
+                        buf,
+                        packet_bytes as usize,
+                    );
+                });
+                function
+                    .client
+                    .map_or(hil::usb::CtrlOutResult::Halted, |client| {
+                        client.ctrl_out(endpoint, packet_bytes)
+                    })
+            }
+            None => self.client_ctrl.ctrl_out(endpoint, packet_bytes),
+        }
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        match self.ctrl_owner.extract() {
+            Some(function) => {
+                function.client.map(|client| client.ctrl_status(endpoint));
+            }
+            None => self.client_ctrl.ctrl_status(endpoint),
+        }
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        match self.ctrl_owner.take() {
+            Some(function) => {
+                function
+                    .client
+                    .map(|client| client.ctrl_status_complete(endpoint));
+            }
+            None => {
+                self.ctrl_state.set(CtrlState::Idle);
+                self.client_ctrl.ctrl_status_complete(endpoint)
+            }
+        }
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        self.function_for_endpoint(endpoint)
+            .and_then(|function| {
+                let local_endpoint = endpoint - function.endpoint_offset.get();
+                function
+                    .client
+                    .map(|client| client.packet_in(transfer_type, local_endpoint))
+            })
+            .unwrap_or(hil::usb::InResult::Error)
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        self.function_for_endpoint(endpoint)
+            .and_then(|function| {
+                let local_endpoint = endpoint - function.endpoint_offset.get();
+                function
+                    .client
+                    .map(|client| client.packet_out(transfer_type, local_endpoint, packet_bytes))
+            })
+            .unwrap_or(hil::usb::OutResult::Error)
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        if let Some(function) = self.function_for_endpoint(endpoint) {
+            let local_endpoint = endpoint - function.endpoint_offset.get();
+            function
+                .client
+                .map(|client| client.packet_transmitted(local_endpoint));
+        }
+    }
+}
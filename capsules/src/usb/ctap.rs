@@ -77,6 +77,9 @@ static HID_DESCRIPTOR: HIDDescriptor<'static> = HIDDescriptor {
     sub_descriptors: SUB_HID_DESCRIPTOR,
 };
 
+static HID_DESCRIPTORS: &'static [&'static HIDDescriptor<'static>] = &[&HID_DESCRIPTOR];
+static REPORT_DESCRIPTORS: &'static [&'static ReportDescriptor<'static>] = &[&REPORT];
+
 /// Implementation of the CTAP HID (Human Interface Device)
 pub struct CtapHid<'a, U: 'a> {
     /// Helper USB client library for handling many USB operations.
@@ -154,7 +157,8 @@ impl<'a, U: hil::usb::UsbController<'a>> CtapHid<'a, U> {
                 },
                 interfaces,
                 endpoints,
-                Some(&HID_DESCRIPTOR),
+                HID_DESCRIPTORS,
+                None,
                 None,
             );
 
@@ -163,8 +167,8 @@ impl<'a, U: hil::usb::UsbController<'a>> CtapHid<'a, U> {
                 controller,
                 device_descriptor_buffer,
                 other_descriptor_buffer,
-                Some(&HID_DESCRIPTOR),
-                Some(&REPORT),
+                HID_DESCRIPTORS,
+                REPORT_DESCRIPTORS,
                 LANGUAGES,
                 strings,
             ),
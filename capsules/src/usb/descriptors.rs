@@ -157,6 +157,7 @@ pub enum DescriptorType {
     DeviceQualifier,
     OtherSpeedConfiguration,
     InterfacePower,
+    InterfaceAssociation = 0x0b,
     HID = 0x21,
     Report = 0x22,
     CdcInterface = 0x24,
@@ -172,6 +173,7 @@ fn get_descriptor_type(byte: u8) -> Option<DescriptorType> {
         6 => Some(DescriptorType::DeviceQualifier),
         7 => Some(DescriptorType::OtherSpeedConfiguration),
         8 => Some(DescriptorType::InterfacePower),
+        0x0b => Some(DescriptorType::InterfaceAssociation),
         0x21 => Some(DescriptorType::HID),
         0x22 => Some(DescriptorType::Report),
         0x24 => Some(DescriptorType::CdcInterface),
@@ -415,14 +417,16 @@ impl DescriptorBuffer {
 /// example, if the interface descriptor list contains `[ID1, ID2, ID3]`,
 /// and the endpoint descriptors list is `[[ED1, ED2], [ED3, ED4, ED5],
 /// [ED6]]`, then the third interface descriptor (`ID3`) has one
-/// corresponding endpoint descriptor (`ED6`).
+/// corresponding endpoint descriptor (`ED6`). HID descriptors are matched to
+/// interface descriptors the same way.
 pub fn create_descriptor_buffers(
     device_descriptor: DeviceDescriptor,
     mut configuration_descriptor: ConfigurationDescriptor,
     interface_descriptor: &mut [InterfaceDescriptor],
     endpoint_descriptors: &[&[EndpointDescriptor]],
-    hid_descriptor: Option<&HIDDescriptor>,
+    hid_descriptors: &[&HIDDescriptor],
     cdc_descriptor: Option<&[CdcInterfaceDescriptor]>,
+    dfu_descriptor: Option<&DfuFunctionalDescriptor>,
 ) -> (DeviceBuffer, DescriptorBuffer) {
     // Create device descriptor buffer and fill.
     // Cell doesn't implement Copy, so here we are.
@@ -493,8 +497,12 @@ pub fn create_descriptor_buffers(
     // descriptors.
 
     // Configuration Descriptor. We assume there is only one configuration
-    // descriptor, since this is very common for most USB devices.
-    configuration_descriptor.num_interfaces = interface_descriptor.len() as u8;
+    // descriptor, since this is very common for most USB devices. Alternate
+    // settings of an interface do not count as separate interfaces.
+    configuration_descriptor.num_interfaces = interface_descriptor
+        .iter()
+        .filter(|d| d.alternate_setting == 0)
+        .count() as u8;
 
     // Calculate the length of all dependent descriptors.
     // TODO should we be erroring here if len > 128? Otherwise we'll probably
@@ -505,8 +513,9 @@ pub fn create_descriptor_buffers(
                 .iter()
                 .map(|descs| descs.iter().map(|d| d.size()).sum::<usize>())
                 .sum::<usize>()
-            + hid_descriptor.map_or(0, |d| d.size())
-            + cdc_descriptor.map_or(0, |ds| ds.iter().map(|d| d.size()).sum::<usize>());
+            + hid_descriptors.iter().map(|d| d.size()).sum::<usize>()
+            + cdc_descriptor.map_or(0, |ds| ds.iter().map(|d| d.size()).sum::<usize>())
+            + dfu_descriptor.map_or(0, |d| d.size());
 
     // Set the number of endpoints for each interface descriptor.
     for (i, d) in interface_descriptor.iter_mut().enumerate() {
@@ -522,13 +531,9 @@ pub fn create_descriptor_buffers(
         // Add the interface descriptor.
         len += d.write_to(&other_buf.buf[len..]);
 
-        // If there is a HID descriptor, we include
-        // it with the first interface descriptor.
-        if i == 0 {
-            // HID descriptor, if any.
-            if let Some(dh) = hid_descriptor {
-                len += dh.write_to(&other_buf.buf[len..]);
-            }
+        // HID descriptor for this interface, if any.
+        if let Some(dh) = hid_descriptors.get(i) {
+            len += dh.write_to(&other_buf.buf[len..]);
         }
 
         // If there is a CDC descriptor array, we include
@@ -547,6 +552,12 @@ pub fn create_descriptor_buffers(
             len += de.write_to(&other_buf.buf[len..]);
         }
     }
+
+    // The DFU functional descriptor follows all of the alternate settings of
+    // the DFU interface.
+    if let Some(ddfu) = dfu_descriptor {
+        len += ddfu.write_to(&other_buf.buf[len..]);
+    }
     other_buf.len = min(len, other_buf.buf.len());
 
     // return the two buffers
@@ -655,6 +666,35 @@ impl Descriptor for InterfaceDescriptor {
     }
 }
 
+/// Groups the consecutive interfaces that make up one function of a
+/// composite device, so the host binds them to a single class driver.
+pub struct InterfaceAssociationDescriptor {
+    pub first_interface: u8,
+    pub interface_count: u8,
+    pub function_class: u8,
+    pub function_subclass: u8,
+    pub function_protocol: u8,
+    pub string_index: u8,
+}
+
+impl Descriptor for InterfaceAssociationDescriptor {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(8); // Size of descriptor
+        buf[1].set(DescriptorType::InterfaceAssociation as u8);
+        buf[2].set(self.first_interface);
+        buf[3].set(self.interface_count);
+        buf[4].set(self.function_class);
+        buf[5].set(self.function_subclass);
+        buf[6].set(self.function_protocol);
+        buf[7].set(self.string_index);
+        8
+    }
+}
+
 pub struct EndpointAddress(u8);
 
 impl EndpointAddress {
@@ -885,6 +925,74 @@ impl CdcAcmSetLineCodingData {
     }
 }
 
+//
+// For DFU
+//
+
+/// Type of the DFU functional descriptor, which is the same as that of the HID
+/// descriptor.
+const DFU_FUNCTIONAL_DESCRIPTOR_TYPE: u8 = 0x21;
+
+/// Attributes of a DFU interface.
+#[derive(Clone, Copy)]
+pub struct DfuAttributes(u8);
+
+impl DfuAttributes {
+    pub fn new(
+        can_download: bool,
+        can_upload: bool,
+        manifestation_tolerant: bool,
+        will_detach: bool,
+    ) -> Self {
+        DfuAttributes(
+            if can_download { 1 << 0 } else { 0 }
+                | if can_upload { 1 << 1 } else { 0 }
+                | if manifestation_tolerant { 1 << 2 } else { 0 }
+                | if will_detach { 1 << 3 } else { 0 },
+        )
+    }
+}
+
+/// The DFU functional descriptor, which describes the capabilities of a
+/// device firmware upgrade interface.
+pub struct DfuFunctionalDescriptor {
+    pub attributes: DfuAttributes,
+    /// Time in ms the device waits for a USB reset after a DFU_DETACH
+    /// request.
+    pub detach_timeout: u16,
+    /// Maximum number of bytes the device accepts per DFU_DNLOAD or returns
+    /// per DFU_UPLOAD request.
+    pub transfer_size: u16,
+    pub dfu_version: u16,
+}
+
+impl Default for DfuFunctionalDescriptor {
+    fn default() -> Self {
+        DfuFunctionalDescriptor {
+            attributes: DfuAttributes::new(true, true, true, false),
+            detach_timeout: 1000,
+            transfer_size: 64,
+            dfu_version: 0x0110,
+        }
+    }
+}
+
+impl Descriptor for DfuFunctionalDescriptor {
+    fn size(&self) -> usize {
+        9
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(9); // Size of descriptor
+        buf[1].set(DFU_FUNCTIONAL_DESCRIPTOR_TYPE);
+        buf[2].set(self.attributes.0);
+        put_u16(&buf[3..5], self.detach_timeout);
+        put_u16(&buf[5..7], self.transfer_size);
+        put_u16(&buf[7..9], self.dfu_version);
+        9
+    }
+}
+
 pub struct LanguagesDescriptor<'a> {
     pub langs: &'a [u16],
 }
@@ -0,0 +1,588 @@
+//! Device Firmware Upgrade Class Device for USB
+//!
+//! This capsule implements the DFU 1.1 protocol, so that regions of flash,
+//! for example the kernel or the applications, can be written and read back
+//! over USB with standard tools like `dfu-util`, without a debugger or an
+//! external bootloader.
+//!
+//! The device enumerates in DFU mode. Every `DfuTarget` is an alternate
+//! setting of the DFU interface, which the host selects before a transfer:
+//!
+//! ```text
+//! dfu-util --alt 1 --download apps.bin
+//! dfu-util --alt 1 --upload backup.bin
+//! ```
+//!
+//! A download writes the image from the start of the target, a page at a
+//! time. The rest of the last page is filled with `0xFF`. An upload returns
+//! the whole target. All transfers happen on the control endpoint with at most
+//! one packet per request, so downloads and uploads proceed in blocks of the
+//! maximum control packet size.
+//!
+//! After a download is complete the optional `manifest_function` is called,
+//! which can for example reset the chip to boot the new image.
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::DfuFunctionalDescriptor;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::RequestType;
+use super::descriptors::StandardRequest;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Maximum number of targets, and therefore alternate settings.
+pub const MAX_TARGETS: usize = 4;
+
+/// Time in ms the host waits before it asks again for the status while a
+/// page is written.
+const POLL_TIMEOUT_MS: u32 = 20;
+
+/// DFU class requests.
+const DFU_DNLOAD: u8 = 1;
+const DFU_UPLOAD: u8 = 2;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_CLRSTATUS: u8 = 4;
+const DFU_GETSTATE: u8 = 5;
+const DFU_ABORT: u8 = 6;
+
+/// Region of flash the host can download to and upload from.
+#[derive(Clone, Copy, Debug)]
+pub struct DfuTarget {
+    /// Address of the start of the region, which must be page aligned.
+    pub start_address: usize,
+    /// Length of the region in bytes.
+    pub length: usize,
+}
+
+/// States of the DFU interface, as reported to the host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DfuState {
+    Idle = 2,
+    DnloadSync = 3,
+    DnBusy = 4,
+    DnloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    UploadIdle = 9,
+    Error = 10,
+}
+
+/// Status codes reported to the host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DfuStatus {
+    Ok = 0x00,
+    ErrWrite = 0x03,
+    ErrAddress = 0x08,
+    ErrUnknown = 0x0e,
+    ErrStalledPacket = 0x0f,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CtrlState {
+    Idle,
+    Download { length: usize },
+    Upload { length: usize, requested: usize },
+    GetStatus { state: DfuState, poll_timeout: u32 },
+    GetState,
+}
+
+fn dfu_interface(alternate_setting: u8) -> InterfaceDescriptor {
+    InterfaceDescriptor {
+        interface_number: 0,
+        alternate_setting,
+        interface_class: 0xfe,    // Application specific
+        interface_subclass: 0x01, // Device firmware upgrade
+        interface_protocol: 0x02, // DFU mode
+        ..InterfaceDescriptor::default()
+    }
+}
+
+pub struct Dfu<'a, U: 'a, F: hil::flash::Flash + 'static> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+    ctrl_state: Cell<CtrlState>,
+
+    flash: &'a F,
+    targets: &'static [DfuTarget],
+    /// Target selected by the host with the alternate setting.
+    target: Cell<usize>,
+    /// Maximum length of a block of a download or upload.
+    transfer_size: usize,
+
+    state: Cell<DfuState>,
+    status: Cell<DfuStatus>,
+    /// Offset in the target of the next byte to download or upload.
+    offset: Cell<usize>,
+
+    page_buffer: TakeCell<'static, F::Page>,
+    page_size: usize,
+    /// Page read into `page_buffer`, for uploads.
+    loaded_page: OptionalCell<usize>,
+    /// Page being read or written.
+    pending_page: OptionalCell<usize>,
+
+    /// Optional function called after a download is complete.
+    manifest_function: Option<&'a (dyn Fn() + 'a)>,
+    /// Whether to call `manifest_function` once the host acknowledged the
+    /// current control transfer.
+    manifest_pending: Cell<bool>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, F: hil::flash::Flash + 'static> Dfu<'a, U, F> {
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        flash: &'a F,
+        targets: &'static [DfuTarget],
+        page_buffer: &'static mut F::Page,
+        manifest_function: Option<&'a (dyn Fn() + 'a)>,
+    ) -> Self {
+        let targets = &targets[..cmp::min(targets.len(), MAX_TARGETS)];
+        let page_size = page_buffer.as_mut().len();
+        // Blocks must not cross pages, so the transfer size is rounded down
+        // to a divisor of the page size.
+        let mut transfer_size = cmp::min(max_ctrl_packet_size as usize, page_size);
+        while page_size % transfer_size != 0 {
+            transfer_size -= 1;
+        }
+
+        let interfaces: &mut [InterfaceDescriptor] = &mut [
+            dfu_interface(0),
+            dfu_interface(1),
+            dfu_interface(2),
+            dfu_interface(3),
+        ];
+
+        // DFU only uses the default control endpoint.
+        let endpoints: &[&[EndpointDescriptor]] = &[&[], &[], &[], &[]];
+
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id,
+                    product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                &mut interfaces[..targets.len()],
+                &endpoints[..targets.len()],
+                &[],  // No HID descriptor
+                None, // No CDC descriptor array
+                Some(&DfuFunctionalDescriptor {
+                    transfer_size: transfer_size as u16,
+                    ..DfuFunctionalDescriptor::default()
+                }),
+            );
+
+        Dfu {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                &[], // No HID descriptor
+                &[], // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            ctrl_state: Cell::new(CtrlState::Idle),
+            flash,
+            targets,
+            target: Cell::new(0),
+            transfer_size,
+            state: Cell::new(DfuState::Idle),
+            status: Cell::new(DfuStatus::Ok),
+            offset: Cell::new(0),
+            page_buffer: TakeCell::new(page_buffer),
+            page_size,
+            loaded_page: OptionalCell::empty(),
+            pending_page: OptionalCell::empty(),
+            manifest_function,
+            manifest_pending: Cell::new(false),
+        }
+    }
+
+    fn target(&self) -> DfuTarget {
+        self.targets[self.target.get()]
+    }
+
+    /// Returns the flash page that holds `offset` of the current target.
+    fn page_number(&self, offset: usize) -> usize {
+        (self.target().start_address + offset) / self.page_size
+    }
+
+    fn error(&self, status: DfuStatus) {
+        self.state.set(DfuState::Error);
+        self.status.set(status);
+    }
+
+    /// Go back to waiting for a download or upload of the current target.
+    fn enter_idle(&self) {
+        self.state.set(DfuState::Idle);
+        self.status.set(DfuStatus::Ok);
+        self.offset.set(0);
+        // Uploads must be answered right away, so read the first page ahead
+        // of time.
+        self.read_page(0);
+    }
+
+    /// Start reading the page that holds `offset` of the current target.
+    fn read_page(&self, offset: usize) {
+        let page_number = self.page_number(offset);
+        if self.loaded_page.contains(&page_number) || self.pending_page.is_some() {
+            return;
+        }
+        self.page_buffer.take().map(|page_buffer| {
+            self.loaded_page.clear();
+            self.pending_page.set(page_number);
+            if let Err((_, page_buffer)) = self.flash.read_page(page_number, page_buffer) {
+                self.pending_page.clear();
+                self.page_buffer.replace(page_buffer);
+            }
+        });
+    }
+
+    /// Start writing the page that holds `offset` of the current target.
+    fn write_page(&self, offset: usize) {
+        let page_number = self.page_number(offset);
+        match self.page_buffer.take() {
+            Some(page_buffer) => {
+                self.loaded_page.clear();
+                self.pending_page.set(page_number);
+                if let Err((_, page_buffer)) = self.flash.write_page(page_number, page_buffer) {
+                    self.pending_page.clear();
+                    self.page_buffer.replace(page_buffer);
+                    self.error(DfuStatus::ErrWrite);
+                }
+            }
+            None => self.error(DfuStatus::ErrWrite),
+        }
+    }
+
+    /// Stall the current request, which moves the interface to the error
+    /// state with `status`.
+    fn stall(&self, status: DfuStatus) -> hil::usb::CtrlSetupResult {
+        self.error(status);
+        hil::usb::CtrlSetupResult::ErrGeneric
+    }
+
+    /// Handle a DFU class request with `length` bytes of data.
+    fn dfu_request(&self, request: u8, length: usize) -> hil::usb::CtrlSetupResult {
+        let state = self.state.get();
+        match request {
+            DFU_DNLOAD => match state {
+                DfuState::Idle | DfuState::DnloadIdle if length > 0 => {
+                    if state == DfuState::Idle {
+                        self.offset.set(0);
+                    }
+                    if length > self.transfer_size
+                        || self.offset.get() % self.page_size + length > self.page_size
+                    {
+                        // Blocks are copied into a single page buffer, so a
+                        // block that would cross a page is refused.
+                        self.stall(DfuStatus::ErrStalledPacket)
+                    } else if self.offset.get() + length > self.target().length {
+                        self.stall(DfuStatus::ErrAddress)
+                    } else {
+                        self.ctrl_state.set(CtrlState::Download { length });
+                        hil::usb::CtrlSetupResult::Ok
+                    }
+                }
+                DfuState::DnloadIdle => {
+                    // The download is complete, so write the last page.
+                    let offset = self.offset.get();
+                    if offset % self.page_size != 0 {
+                        self.page_buffer.map(|page_buffer| {
+                            for byte in page_buffer.as_mut()[offset % self.page_size..].iter_mut() {
+                                *byte = 0xff;
+                            }
+                        });
+                        self.write_page(offset);
+                    }
+                    if self.state.get() != DfuState::Error {
+                        self.state.set(DfuState::ManifestSync);
+                    }
+                    hil::usb::CtrlSetupResult::Ok
+                }
+                _ => self.stall(DfuStatus::ErrStalledPacket),
+            },
+            DFU_UPLOAD => match state {
+                DfuState::Idle | DfuState::UploadIdle => {
+                    if state == DfuState::Idle {
+                        self.offset.set(0);
+                    }
+                    let offset = self.offset.get();
+                    let page_end = (offset / self.page_size + 1) * self.page_size;
+                    let end = cmp::min(self.target().length, page_end);
+                    let block = cmp::min(cmp::min(length, self.transfer_size), end - offset);
+                    if block > 0 && !self.loaded_page.contains(&self.page_number(offset)) {
+                        // The page was not read in time.
+                        self.stall(DfuStatus::ErrUnknown)
+                    } else {
+                        self.state.set(DfuState::UploadIdle);
+                        self.ctrl_state.set(CtrlState::Upload {
+                            length: block,
+                            requested: length,
+                        });
+                        hil::usb::CtrlSetupResult::Ok
+                    }
+                }
+                _ => self.stall(DfuStatus::ErrStalledPacket),
+            },
+            DFU_GETSTATUS => {
+                let (state, poll_timeout) = match state {
+                    DfuState::DnloadSync if self.pending_page.is_some() => {
+                        (DfuState::DnBusy, POLL_TIMEOUT_MS)
+                    }
+                    DfuState::DnloadSync => {
+                        self.state.set(DfuState::DnloadIdle);
+                        (DfuState::DnloadIdle, 0)
+                    }
+                    DfuState::ManifestSync if self.pending_page.is_some() => {
+                        (DfuState::Manifest, POLL_TIMEOUT_MS)
+                    }
+                    DfuState::ManifestSync => {
+                        // We are manifestation tolerant, so the host can
+                        // start another transfer right away.
+                        self.enter_idle();
+                        self.manifest_pending.set(true);
+                        (DfuState::Idle, 0)
+                    }
+                    state => (state, 0),
+                };
+                self.ctrl_state.set(CtrlState::GetStatus {
+                    state,
+                    poll_timeout,
+                });
+                hil::usb::CtrlSetupResult::Ok
+            }
+            DFU_CLRSTATUS => match state {
+                DfuState::Error => {
+                    self.enter_idle();
+                    hil::usb::CtrlSetupResult::Ok
+                }
+                _ => self.stall(DfuStatus::ErrStalledPacket),
+            },
+            DFU_GETSTATE => {
+                self.ctrl_state.set(CtrlState::GetState);
+                hil::usb::CtrlSetupResult::Ok
+            }
+            DFU_ABORT => match state {
+                DfuState::Idle
+                | DfuState::DnloadSync
+                | DfuState::DnloadIdle
+                | DfuState::ManifestSync
+                | DfuState::UploadIdle => {
+                    self.enter_idle();
+                    hil::usb::CtrlSetupResult::Ok
+                }
+                _ => self.stall(DfuStatus::ErrStalledPacket),
+            },
+            // This includes DFU_DETACH, as the device is always in DFU mode
+            // and there is nothing to detach from.
+            _ => self.stall(DfuStatus::ErrStalledPacket),
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, F: hil::flash::Flash + 'static> hil::flash::Client<F>
+    for Dfu<'a, U, F>
+{
+    fn read_complete(&self, page_buffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.page_buffer.replace(page_buffer);
+        self.pending_page.take().map(|page_number| {
+            if error == hil::flash::Error::CommandComplete {
+                self.loaded_page.set(page_number);
+            }
+        });
+    }
+
+    fn write_complete(&self, page_buffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.page_buffer.replace(page_buffer);
+        self.pending_page.clear();
+        if error != hil::flash::Error::CommandComplete {
+            self.error(DfuStatus::ErrWrite);
+        }
+    }
+
+    fn erase_complete(&self, _error: hil::flash::Error) {}
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, F: hil::flash::Flash + 'static> hil::usb::Client<'a>
+    for Dfu<'a, U, F>
+{
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+        self.enter_idle();
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {}
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// Besides the DFU class requests, we handle the selection of the target
+    /// through the alternate setting of the interface.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        let setup_data = match descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+            Some(setup_data) => setup_data,
+            None => return hil::usb::CtrlSetupResult::ErrNoParse,
+        };
+
+        match setup_data.request_type.request_type() {
+            RequestType::Class => {
+                match self.dfu_request(setup_data.request_code, setup_data.length as usize) {
+                    // Let the control endpoint handle the data stage.
+                    hil::usb::CtrlSetupResult::Ok => self.client_ctrl.ctrl_setup(endpoint),
+                    result => result,
+                }
+            }
+            RequestType::Standard
+                if matches!(
+                    setup_data.get_standard_request(),
+                    Some(StandardRequest::SetInterface)
+                ) && matches!(setup_data.request_type.recipient(), Recipient::Interface) =>
+            {
+                let alternate_setting = setup_data.value as usize;
+                if alternate_setting < self.targets.len() && self.pending_page.is_none() {
+                    self.target.set(alternate_setting);
+                    self.enter_idle();
+                    hil::usb::CtrlSetupResult::Ok
+                } else {
+                    hil::usb::CtrlSetupResult::ErrGeneric
+                }
+            }
+            _ => self.client_ctrl.ctrl_setup(endpoint),
+        }
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        let buf = &self.client_ctrl.ctrl_buffer.buf;
+        match self.ctrl_state.get() {
+            CtrlState::GetStatus {
+                state,
+                poll_timeout,
+            } => {
+                buf[0].set(self.status.get() as u8);
+                for (i, byte) in poll_timeout.to_le_bytes()[..3].iter().enumerate() {
+                    buf[1 + i].set(*byte);
+                }
+                buf[4].set(state as u8);
+                buf[5].set(0); // No status description string
+                hil::usb::CtrlInResult::Packet(6, true)
+            }
+            CtrlState::GetState => {
+                buf[0].set(self.state.get() as u8);
+                hil::usb::CtrlInResult::Packet(1, true)
+            }
+            CtrlState::Upload { length, requested } => {
+                let offset = self.offset.get();
+                let start = offset % self.page_size;
+                self.page_buffer.map(|page_buffer| {
+                    let page = page_buffer.as_mut();
+                    for i in 0..length {
+                        buf[i].set(page[start + i]);
+                    }
+                });
+                self.offset.set(offset + length);
+
+                if length < requested {
+                    // A short block ends the upload.
+                    self.enter_idle();
+                } else if (offset + length) % self.page_size == 0 {
+                    self.read_page(offset + length);
+                }
+                hil::usb::CtrlInResult::Packet(length, true)
+            }
+            CtrlState::Download { .. } | CtrlState::Idle => self.client_ctrl.ctrl_in(endpoint),
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        if let CtrlState::Download { length } = self.ctrl_state.get() {
+            let length = cmp::min(length, packet_bytes as usize);
+            let offset = self.offset.get();
+            let start = offset % self.page_size;
+            let buf = &self.client_ctrl.ctrl_buffer.buf;
+            let copied = self.page_buffer.map_or(false, |page_buffer| {
+                let page = page_buffer.as_mut();
+                for i in 0..length {
+                    page[start + i] = buf[i].get();
+                }
+                true
+            });
+
+            if copied {
+                self.loaded_page.clear();
+                self.offset.set(offset + length);
+                self.state.set(DfuState::DnloadSync);
+                if (offset + length) % self.page_size == 0 {
+                    self.write_page(offset);
+                }
+            } else {
+                // The page buffer is still in use by the flash.
+                self.error(DfuStatus::ErrUnknown);
+            }
+        }
+
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.ctrl_state.set(CtrlState::Idle);
+        self.client_ctrl.ctrl_status_complete(endpoint);
+
+        if self.manifest_pending.take() {
+            self.manifest_function.map(|f| {
+                f();
+            });
+        }
+    }
+
+    /// DFU does not use any endpoints other than the control endpoint.
+    fn packet_in(&'a self, _transfer_type: TransferType, _endpoint: usize) -> hil::usb::InResult {
+        hil::usb::InResult::Delay
+    }
+
+    fn packet_out(
+        &'a self,
+        _transfer_type: TransferType,
+        _endpoint: usize,
+        _packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        hil::usb::OutResult::Ok
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {}
+}
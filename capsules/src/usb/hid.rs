@@ -0,0 +1,601 @@
+//! Keyboard and Mouse Human Interface Device for USB
+//!
+//! This capsule makes the board appear to a USB host as a keyboard and a
+//! mouse, so that it can inject input, for example to automate tests. Both
+//! interfaces use the boot protocol report formats, which every host
+//! understands without parsing the report descriptors:
+//!
+//! - Keyboard input reports are 8 bytes: modifier keys, a reserved byte and up
+//!   to six pressed key codes. The single byte output report holds the state
+//!   of the keyboard LEDs.
+//! - Mouse input reports are 4 bytes: buttons, X and Y movement and wheel
+//!   movement, the last three as signed values.
+//!
+//! Keyboard reports are sent through `hil::usb_hid::UsbHid<[u8; 8]>` and mouse
+//! reports through `hil::usb_hid::UsbHid<[u8; 4]>`, each with their own
+//! client. A report is sent once, the host keeps the last report it received
+//! until the next one. LED output reports are passed to the keyboard client
+//! through `receive_buffer()`.
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::Buffer8;
+use super::descriptors::DescriptorType;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::HIDCountryCode;
+use super::descriptors::HIDDescriptor;
+use super::descriptors::HIDSubordinateDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::ReportDescriptor;
+use super::descriptors::RequestType;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::hil::usb_hid;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::cells::TakeCell;
+use kernel::utilities::cells::VolatileCell;
+use kernel::ErrorCode;
+
+/// Interrupt IN endpoint of the keyboard interface.
+pub const KEYBOARD_ENDPOINT: usize = 1;
+/// Interrupt IN endpoint of the mouse interface.
+pub const MOUSE_ENDPOINT: usize = 2;
+
+const KEYBOARD_INTERFACE: u16 = 0;
+const MOUSE_INTERFACE: u16 = 1;
+
+const KEYBOARD_REPORT_LENGTH: usize = 8;
+const MOUSE_REPORT_LENGTH: usize = 4;
+
+const N_ENDPOINTS: usize = 2;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// HID class specific control requests.
+const REQUEST_GET_REPORT: u8 = 0x01;
+const REQUEST_GET_IDLE: u8 = 0x02;
+const REQUEST_GET_PROTOCOL: u8 = 0x03;
+const REQUEST_SET_REPORT: u8 = 0x09;
+const REQUEST_SET_IDLE: u8 = 0x0a;
+const REQUEST_SET_PROTOCOL: u8 = 0x0b;
+
+/// The boot protocol keyboard report descriptor, from appendix B.1 of the HID
+/// specification.
+static KEYBOARD_REPORT_DESCRIPTOR: &'static [u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xA1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0xE0, //   Usage Minimum (224)
+    0x29, 0xE7, //   Usage Maximum (231)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute): modifier byte
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x01, //   Input (Constant): reserved byte
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (1)
+    0x29, 0x05, //   Usage Maximum (5)
+    0x91, 0x02, //   Output (Data, Variable, Absolute): LED report
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x01, //   Output (Constant): LED report padding
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array): key codes
+    0xC0, // End Collection
+];
+
+/// The boot protocol mouse report descriptor from appendix B.2 of the HID
+/// specification, extended with a wheel.
+static MOUSE_REPORT_DESCRIPTOR: &'static [u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xA1, 0x01, // Collection (Application)
+    0x09, 0x01, //   Usage (Pointer)
+    0xA1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Buttons)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x03, //     Usage Maximum (3)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x95, 0x03, //     Report Count (3)
+    0x75, 0x01, //     Report Size (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute): buttons
+    0x95, 0x01, //     Report Count (1)
+    0x75, 0x05, //     Report Size (5)
+    0x81, 0x01, //     Input (Constant): padding
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x09, 0x38, //     Usage (Wheel)
+    0x15, 0x81, //     Logical Minimum (-127)
+    0x25, 0x7F, //     Logical Maximum (127)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x03, //     Report Count (3)
+    0x81, 0x06, //     Input (Data, Variable, Relative): X, Y and wheel
+    0xC0, //   End Collection
+    0xC0, // End Collection
+];
+
+static KEYBOARD_REPORT: ReportDescriptor<'static> = ReportDescriptor {
+    desc: KEYBOARD_REPORT_DESCRIPTOR,
+};
+
+static MOUSE_REPORT: ReportDescriptor<'static> = ReportDescriptor {
+    desc: MOUSE_REPORT_DESCRIPTOR,
+};
+
+static KEYBOARD_SUB_HID_DESCRIPTOR: &'static [HIDSubordinateDescriptor] =
+    &[HIDSubordinateDescriptor {
+        typ: DescriptorType::Report,
+        len: KEYBOARD_REPORT_DESCRIPTOR.len() as u16,
+    }];
+
+static MOUSE_SUB_HID_DESCRIPTOR: &'static [HIDSubordinateDescriptor] =
+    &[HIDSubordinateDescriptor {
+        typ: DescriptorType::Report,
+        len: MOUSE_REPORT_DESCRIPTOR.len() as u16,
+    }];
+
+static KEYBOARD_HID_DESCRIPTOR: HIDDescriptor<'static> = HIDDescriptor {
+    hid_class: 0x0111,
+    country_code: HIDCountryCode::NotSupported,
+    sub_descriptors: KEYBOARD_SUB_HID_DESCRIPTOR,
+};
+
+static MOUSE_HID_DESCRIPTOR: HIDDescriptor<'static> = HIDDescriptor {
+    hid_class: 0x0111,
+    country_code: HIDCountryCode::NotSupported,
+    sub_descriptors: MOUSE_SUB_HID_DESCRIPTOR,
+};
+
+/// HID and report descriptors, indexed by interface number.
+static HID_DESCRIPTORS: &'static [&'static HIDDescriptor<'static>] =
+    &[&KEYBOARD_HID_DESCRIPTOR, &MOUSE_HID_DESCRIPTOR];
+static REPORT_DESCRIPTORS: &'static [&'static ReportDescriptor<'static>] =
+    &[&KEYBOARD_REPORT, &MOUSE_REPORT];
+
+/// State of a class specific control transfer.
+#[derive(Copy, Clone)]
+enum CtrlState {
+    Idle,
+    /// Reply with the given value.
+    Reply([u8; KEYBOARD_REPORT_LENGTH], usize),
+    /// Receiving a keyboard output report.
+    SetReport,
+}
+
+/// Sending reports on one interrupt IN endpoint.
+struct ReportSender<'a, T: 'static + usb_hid::UsbHidType> {
+    client: OptionalCell<&'a dyn usb_hid::Client<'a, T>>,
+    /// The report to send, or the report being sent.
+    send_buffer: TakeCell<'static, T>,
+    /// Whether the report in `send_buffer` has been handed to the controller.
+    sending: Cell<bool>,
+    /// The last report sent, returned for GET_REPORT requests.
+    last_report: Cell<[u8; KEYBOARD_REPORT_LENGTH]>,
+}
+
+impl<'a, T: 'static + usb_hid::UsbHidType + AsRef<[u8]>> ReportSender<'a, T> {
+    fn new() -> Self {
+        ReportSender {
+            client: OptionalCell::empty(),
+            send_buffer: TakeCell::empty(),
+            sending: Cell::new(false),
+            last_report: Cell::new([0; KEYBOARD_REPORT_LENGTH]),
+        }
+    }
+
+    fn send(&self, send: &'static mut T) -> Result<usize, (ErrorCode, &'static mut T)> {
+        if self.send_buffer.is_some() {
+            return Err((ErrorCode::BUSY, send));
+        }
+        let len = send.as_ref().len();
+        self.send_buffer.replace(send);
+        Ok(len)
+    }
+
+    fn cancel(&self) -> Result<&'static mut T, ErrorCode> {
+        if self.sending.get() {
+            Err(ErrorCode::BUSY)
+        } else {
+            self.send_buffer.take().ok_or(ErrorCode::INVAL)
+        }
+    }
+
+    /// Copy the pending report, if any, into `packet`.
+    fn packet_in(&self, packet: &[VolatileCell<u8>]) -> hil::usb::InResult {
+        if self.sending.get() {
+            return hil::usb::InResult::Delay;
+        }
+        self.send_buffer.map_or(hil::usb::InResult::Delay, |buf| {
+            let report = buf.as_ref();
+            let mut last_report = [0; KEYBOARD_REPORT_LENGTH];
+            for (i, b) in report.iter().enumerate() {
+                packet[i].set(*b);
+                last_report[i] = *b;
+            }
+            self.last_report.set(last_report);
+            self.sending.set(true);
+            hil::usb::InResult::Packet(report.len())
+        })
+    }
+
+    fn packet_transmitted(&self, endpoint: usize) {
+        if self.sending.replace(false) {
+            self.send_buffer.take().map(|buf| {
+                self.client.map(move |client| {
+                    client.packet_transmitted(Ok(()), buf, endpoint);
+                });
+            });
+        }
+    }
+}
+
+pub struct Hid<'a, U: 'a> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// Buffers for each endpoint.
+    buffers: [Buffer8; N_ENDPOINTS],
+
+    keyboard: ReportSender<'a, [u8; KEYBOARD_REPORT_LENGTH]>,
+    mouse: ReportSender<'a, [u8; MOUSE_REPORT_LENGTH]>,
+
+    /// Buffer for the next keyboard output report.
+    recv_buffer: TakeCell<'static, [u8; KEYBOARD_REPORT_LENGTH]>,
+    /// The state of the keyboard LEDs.
+    leds: Cell<u8>,
+
+    ctrl_state: Cell<CtrlState>,
+    /// Duration for which the host wants reports repeated, in units of 4 ms.
+    /// We only send reports when they change, which the host accepts.
+    idle_rate: Cell<u8>,
+    /// Boot (0) or report (1) protocol. Both use the same report format.
+    protocol: Cell<u8>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> Hid<'a, U> {
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+    ) -> Self {
+        let interfaces: &mut [InterfaceDescriptor] = &mut [
+            InterfaceDescriptor {
+                interface_number: KEYBOARD_INTERFACE as u8,
+                interface_class: 0x03,    // HID
+                interface_subclass: 0x01, // Boot interface
+                interface_protocol: 0x01, // Keyboard
+                ..InterfaceDescriptor::default()
+            },
+            InterfaceDescriptor {
+                interface_number: MOUSE_INTERFACE as u8,
+                interface_class: 0x03,    // HID
+                interface_subclass: 0x01, // Boot interface
+                interface_protocol: 0x02, // Mouse
+                ..InterfaceDescriptor::default()
+            },
+        ];
+
+        let endpoints: &[&[EndpointDescriptor]] = &[
+            &[EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    KEYBOARD_ENDPOINT,
+                    TransferDirection::DeviceToHost,
+                ),
+                transfer_type: TransferType::Interrupt,
+                max_packet_size: KEYBOARD_REPORT_LENGTH as u16,
+                interval: 10,
+            }],
+            &[EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    MOUSE_ENDPOINT,
+                    TransferDirection::DeviceToHost,
+                ),
+                transfer_type: TransferType::Interrupt,
+                max_packet_size: MOUSE_REPORT_LENGTH as u16,
+                interval: 10,
+            }],
+        ];
+
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id,
+                    product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                interfaces,
+                endpoints,
+                HID_DESCRIPTORS,
+                None, // No CDC descriptor array
+                None, // No DFU descriptor
+            );
+
+        Hid {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                HID_DESCRIPTORS,
+                REPORT_DESCRIPTORS,
+                LANGUAGES,
+                strings,
+            ),
+            buffers: [Buffer8::default(), Buffer8::default()],
+            keyboard: ReportSender::new(),
+            mouse: ReportSender::new(),
+            recv_buffer: TakeCell::empty(),
+            leds: Cell::new(0),
+            ctrl_state: Cell::new(CtrlState::Idle),
+            idle_rate: Cell::new(0),
+            protocol: Cell::new(1),
+        }
+    }
+
+    #[inline]
+    fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    #[inline]
+    fn buffer(&self, endpoint: usize) -> &[VolatileCell<u8>] {
+        &self.buffers[endpoint - 1].buf
+    }
+
+    pub fn set_keyboard_client(
+        &'a self,
+        client: &'a dyn usb_hid::Client<'a, [u8; KEYBOARD_REPORT_LENGTH]>,
+    ) {
+        self.keyboard.client.set(client);
+    }
+
+    pub fn set_mouse_client(
+        &'a self,
+        client: &'a dyn usb_hid::Client<'a, [u8; MOUSE_REPORT_LENGTH]>,
+    ) {
+        self.mouse.client.set(client);
+    }
+
+    /// The state of the keyboard LEDs as last set by the host. Bit 0 is Num
+    /// Lock, bit 1 Caps Lock and bit 2 Scroll Lock.
+    pub fn leds(&self) -> u8 {
+        self.leds.get()
+    }
+
+    fn keyboard_output_report(&self, leds: u8) {
+        self.leds.set(leds);
+        if self
+            .keyboard
+            .client
+            .map_or(false, |client| client.can_receive())
+        {
+            self.recv_buffer.take().map(|buf| {
+                buf[0] = leds;
+                self.keyboard.client.map(move |client| {
+                    client.packet_received(Ok(()), buf, 0);
+                });
+            });
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> usb_hid::UsbHid<'a, [u8; KEYBOARD_REPORT_LENGTH]>
+    for Hid<'a, U>
+{
+    fn send_buffer(
+        &'a self,
+        send: &'static mut [u8; KEYBOARD_REPORT_LENGTH],
+    ) -> Result<usize, (ErrorCode, &'static mut [u8; KEYBOARD_REPORT_LENGTH])> {
+        let len = self.keyboard.send(send)?;
+        self.controller().endpoint_resume_in(KEYBOARD_ENDPOINT);
+        Ok(len)
+    }
+
+    fn send_cancel(&'a self) -> Result<&'static mut [u8; KEYBOARD_REPORT_LENGTH], ErrorCode> {
+        self.keyboard.cancel()
+    }
+
+    fn receive_buffer(
+        &'a self,
+        recv: &'static mut [u8; KEYBOARD_REPORT_LENGTH],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; KEYBOARD_REPORT_LENGTH])> {
+        if self.recv_buffer.is_some() {
+            Err((ErrorCode::BUSY, recv))
+        } else {
+            self.recv_buffer.replace(recv);
+            Ok(())
+        }
+    }
+
+    fn receive_cancel(&'a self) -> Result<&'static mut [u8; KEYBOARD_REPORT_LENGTH], ErrorCode> {
+        self.recv_buffer.take().ok_or(ErrorCode::INVAL)
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> usb_hid::UsbHid<'a, [u8; MOUSE_REPORT_LENGTH]>
+    for Hid<'a, U>
+{
+    fn send_buffer(
+        &'a self,
+        send: &'static mut [u8; MOUSE_REPORT_LENGTH],
+    ) -> Result<usize, (ErrorCode, &'static mut [u8; MOUSE_REPORT_LENGTH])> {
+        let len = self.mouse.send(send)?;
+        self.controller().endpoint_resume_in(MOUSE_ENDPOINT);
+        Ok(len)
+    }
+
+    fn send_cancel(&'a self) -> Result<&'static mut [u8; MOUSE_REPORT_LENGTH], ErrorCode> {
+        self.mouse.cancel()
+    }
+
+    /// The mouse has no output reports.
+    fn receive_buffer(
+        &'a self,
+        recv: &'static mut [u8; MOUSE_REPORT_LENGTH],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; MOUSE_REPORT_LENGTH])> {
+        Err((ErrorCode::NOSUPPORT, recv))
+    }
+
+    fn receive_cancel(&'a self) -> Result<&'static mut [u8; MOUSE_REPORT_LENGTH], ErrorCode> {
+        Err(ErrorCode::INVAL)
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for Hid<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Setup buffers for the interrupt IN endpoints.
+        for endpoint in [KEYBOARD_ENDPOINT, MOUSE_ENDPOINT] {
+            self.controller()
+                .endpoint_set_in_buffer(endpoint, self.buffer(endpoint));
+            self.controller()
+                .endpoint_in_enable(TransferType::Interrupt, endpoint);
+        }
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.protocol.set(1);
+        self.idle_rate.set(0);
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// Class specific requests that return data are answered here, everything
+    /// else is handled by the control endpoint.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        self.ctrl_state.set(CtrlState::Idle);
+        descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf).map(|setup_data| {
+            if let RequestType::Class = setup_data.request_type.request_type() {
+                let mut reply = [0; KEYBOARD_REPORT_LENGTH];
+                match setup_data.request_code {
+                    REQUEST_GET_REPORT => {
+                        let (last_report, len) = match setup_data.index {
+                            KEYBOARD_INTERFACE => {
+                                (self.keyboard.last_report.get(), KEYBOARD_REPORT_LENGTH)
+                            }
+                            _ => (self.mouse.last_report.get(), MOUSE_REPORT_LENGTH),
+                        };
+                        let len = cmp::min(len, setup_data.length as usize);
+                        self.ctrl_state.set(CtrlState::Reply(last_report, len));
+                    }
+                    REQUEST_GET_IDLE => {
+                        reply[0] = self.idle_rate.get();
+                        self.ctrl_state.set(CtrlState::Reply(reply, 1));
+                    }
+                    REQUEST_GET_PROTOCOL => {
+                        reply[0] = self.protocol.get();
+                        self.ctrl_state.set(CtrlState::Reply(reply, 1));
+                    }
+                    REQUEST_SET_REPORT if setup_data.index == KEYBOARD_INTERFACE => {
+                        self.ctrl_state.set(CtrlState::SetReport);
+                    }
+                    REQUEST_SET_IDLE => self.idle_rate.set((setup_data.value >> 8) as u8),
+                    REQUEST_SET_PROTOCOL => self.protocol.set(setup_data.value as u8),
+                    _ => {}
+                }
+            }
+        });
+
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        match self.ctrl_state.get() {
+            CtrlState::Reply(reply, len) => {
+                let buf = &self.client_ctrl.ctrl_buffer.buf;
+                for i in 0..len {
+                    buf[i].set(reply[i]);
+                }
+                hil::usb::CtrlInResult::Packet(len, true)
+            }
+            _ => self.client_ctrl.ctrl_in(endpoint),
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        if let CtrlState::SetReport = self.ctrl_state.get() {
+            if packet_bytes > 0 {
+                self.keyboard_output_report(self.client_ctrl.ctrl_buffer.buf[0].get());
+            }
+        }
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.ctrl_state.set(CtrlState::Idle);
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction.
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        if !matches!(transfer_type, TransferType::Interrupt) {
+            return hil::usb::InResult::Delay;
+        }
+        match endpoint {
+            KEYBOARD_ENDPOINT => self.keyboard.packet_in(self.buffer(endpoint)),
+            MOUSE_ENDPOINT => self.mouse.packet_in(self.buffer(endpoint)),
+            _ => hil::usb::InResult::Error,
+        }
+    }
+
+    /// The HID interfaces have no OUT endpoints.
+    fn packet_out(
+        &'a self,
+        _transfer_type: TransferType,
+        _endpoint: usize,
+        _packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        hil::usb::OutResult::Error
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        match endpoint {
+            KEYBOARD_ENDPOINT => self.keyboard.packet_transmitted(endpoint),
+            MOUSE_ENDPOINT => self.mouse.packet_transmitted(endpoint),
+            _ => {}
+        }
+    }
+}
@@ -1,6 +1,10 @@
 pub mod cdc;
+pub mod composite;
 pub mod ctap;
 pub mod descriptors;
+pub mod dfu;
+pub mod hid;
+pub mod msc;
 pub mod usb_user;
 pub mod usbc_client;
 pub mod usbc_client_ctrl;
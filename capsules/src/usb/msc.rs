@@ -0,0 +1,843 @@
+//! Mass Storage Class Device for USB
+//!
+//! This capsule exposes a block device to a USB host, so that storage written
+//! by the board, for example logs on an SD card, can be read by plugging the
+//! board into a computer. It implements the Bulk-Only Transport (BOT) with the
+//! SCSI transparent command set, which operating systems support without
+//! additional drivers.
+//!
+//! The medium is accessed through `hil::nonvolatile_storage`, one block of
+//! `BLOCK_SIZE` bytes at a time, starting at a fixed address. Flash can be
+//! exposed with `NonvolatileToPages`, and SD cards with
+//! `capsules::sdcard::SDCardStorage`.
+//!
+//! Supported SCSI commands are TEST UNIT READY, REQUEST SENSE, INQUIRY,
+//! MODE SENSE(6), MODE SENSE(10), READ FORMAT CAPACITIES, READ CAPACITY(10),
+//! READ(10) and WRITE(10). Commands to lock the medium, stop the unit, verify
+//! blocks or synchronize the cache succeed without doing anything. Only a
+//! single logical unit is supported.
+//!
+//! If the size of the medium changes, for example because an SD card was
+//! inserted, the storage should notify the capsule through `MediumClient`. A
+//! medium with zero blocks is reported to the host as not present.
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::RequestType;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::usb::TransferType;
+use kernel::utilities::cells::TakeCell;
+use kernel::utilities::cells::VolatileCell;
+use kernel::ErrorCode;
+
+/// Identifying number for the endpoint when transferring data from us to the
+/// host.
+const ENDPOINT_IN_NUM: usize = 1;
+/// Identifying number for the endpoint when transferring data from the host to
+/// us.
+const ENDPOINT_OUT_NUM: usize = 2;
+
+const N_ENDPOINTS: usize = 2;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Size of the blocks of the medium in bytes. The buffer passed to
+/// `MassStorage::new()` must be at least this long.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Class specific control requests.
+const REQUEST_GET_MAX_LUN: u8 = 0xfe;
+const REQUEST_MASS_STORAGE_RESET: u8 = 0xff;
+
+/// Command Block Wrapper, sent by the host to start a command.
+const CBW_SIGNATURE: u32 = 0x43425355; // "USBC"
+const CBW_LENGTH: usize = 31;
+/// Command Status Wrapper, sent to the host when a command completes.
+const CSW_SIGNATURE: u32 = 0x53425355; // "USBS"
+const CSW_LENGTH: usize = 13;
+
+/// SCSI operation codes.
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const MODE_SENSE_6: u8 = 0x1a;
+const START_STOP_UNIT: u8 = 0x1b;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const READ_FORMAT_CAPACITIES: u8 = 0x23;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+const VERIFY_10: u8 = 0x2f;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const MODE_SENSE_10: u8 = 0x5a;
+
+/// SCSI sense keys.
+const SENSE_NO_SENSE: u8 = 0x00;
+const SENSE_NOT_READY: u8 = 0x02;
+const SENSE_MEDIUM_ERROR: u8 = 0x03;
+const SENSE_ILLEGAL_REQUEST: u8 = 0x05;
+const SENSE_UNIT_ATTENTION: u8 = 0x06;
+
+/// SCSI additional sense codes.
+const ASC_NONE: u8 = 0x00;
+const ASC_WRITE_ERROR: u8 = 0x0c;
+const ASC_READ_ERROR: u8 = 0x11;
+const ASC_INVALID_COMMAND: u8 = 0x20;
+const ASC_LBA_OUT_OF_RANGE: u8 = 0x21;
+const ASC_INVALID_FIELD_IN_CDB: u8 = 0x24;
+const ASC_MEDIUM_CHANGED: u8 = 0x28;
+const ASC_MEDIUM_NOT_PRESENT: u8 = 0x3a;
+
+/// Length of the longest response to a command other than READ(10), which is
+/// the standard INQUIRY data.
+const RESPONSE_LENGTH: usize = 36;
+
+/// Receives notifications when the medium behind a storage changes, for
+/// example when an SD card is inserted or removed.
+pub trait MediumClient {
+    /// The medium now holds `block_count` blocks of `BLOCK_SIZE` bytes, or
+    /// is not present if `block_count` is zero.
+    fn medium_changed(&self, block_count: u32);
+}
+
+/// Status of a command reported to the host in the Command Status Wrapper.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CommandStatus {
+    Passed = 0,
+    Failed = 1,
+    PhaseError = 2,
+}
+
+/// States of the Bulk-Only Transport.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Default state. User must call `enable()`.
+    Disabled,
+    /// Waiting for a Command Block Wrapper from the host.
+    Command,
+    /// Sending the response to a command to the host.
+    Response { offset: usize, length: usize },
+    /// Waiting for the storage to read the next block.
+    Reading,
+    /// Sending the block that was read to the host.
+    ReadData { offset: usize },
+    /// Receiving the next block to write from the host.
+    WriteData { offset: usize },
+    /// Waiting for the storage to write a block.
+    Writing,
+    /// Sending zeros for the rest of the data the host expects.
+    Pad,
+    /// Dropping the rest of the data the host sends.
+    Discard,
+    /// Waiting to send the Command Status Wrapper.
+    Status,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CtrlState {
+    Idle,
+    GetMaxLun,
+}
+
+pub struct MassStorage<'a, U: 'a> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// 64 byte buffers for each endpoint.
+    buffers: [Buffer64; N_ENDPOINTS],
+
+    state: Cell<State>,
+    ctrl_state: Cell<CtrlState>,
+
+    /// Strings used for the INQUIRY response.
+    strings: &'static [&'static str; 3],
+
+    /// The medium.
+    storage: &'a dyn NonvolatileStorage<'a>,
+    /// Address of the first block in the storage.
+    start_address: usize,
+    /// Number of blocks in the medium, zero if there is no medium.
+    block_count: Cell<u32>,
+    /// Whether the host has to be told that the medium changed.
+    unit_attention: Cell<bool>,
+    /// Holds the block that is read or written.
+    block_buffer: TakeCell<'a, [u8]>,
+
+    /// Tag of the current command, returned in its status.
+    tag: Cell<u32>,
+    /// Number of bytes the host expects to transfer for the current command.
+    host_length: Cell<u32>,
+    /// Whether the host expects data from us for the current command.
+    host_in: Cell<bool>,
+    /// Number of bytes transferred so far for the current command, including
+    /// padding.
+    transferred: Cell<u32>,
+    /// Number of bytes of actual data transferred for the current command.
+    data_length: Cell<u32>,
+    status: Cell<CommandStatus>,
+    /// Response to a command other than READ(10).
+    response: Cell<[u8; RESPONSE_LENGTH]>,
+    /// Next block to read or write.
+    lba: Cell<u32>,
+    /// Number of blocks left to read or write.
+    blocks: Cell<u32>,
+
+    /// Sense key and additional sense code reported by REQUEST SENSE.
+    sense_key: Cell<u8>,
+    sense_code: Cell<u8>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> MassStorage<'a, U> {
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage: &'a dyn NonvolatileStorage<'a>,
+        start_address: usize,
+        block_count: u32,
+        block_buffer: &'a mut [u8],
+    ) -> Self {
+        let interfaces: &mut [InterfaceDescriptor] = &mut [InterfaceDescriptor {
+            interface_number: 0,
+            interface_class: 0x08,    // Mass storage
+            interface_subclass: 0x06, // SCSI transparent command set
+            interface_protocol: 0x50, // Bulk-Only Transport
+            ..InterfaceDescriptor::default()
+        }];
+
+        let endpoints: &[&[EndpointDescriptor]] = &[&[
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_IN_NUM,
+                    TransferDirection::DeviceToHost,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: 64,
+                interval: 0,
+            },
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_OUT_NUM,
+                    TransferDirection::HostToDevice,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: 64,
+                interval: 0,
+            },
+        ]];
+
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id,
+                    product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                interfaces,
+                endpoints,
+                &[],  // No HID descriptor
+                None, // No CDC descriptor array
+                None, // No DFU descriptor
+            );
+
+        MassStorage {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                &[], // No HID descriptor
+                &[], // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            buffers: [Buffer64::default(), Buffer64::default()],
+            state: Cell::new(State::Disabled),
+            ctrl_state: Cell::new(CtrlState::Idle),
+            strings,
+            storage,
+            start_address,
+            block_count: Cell::new(block_count),
+            unit_attention: Cell::new(false),
+            block_buffer: TakeCell::new(block_buffer),
+            tag: Cell::new(0),
+            host_length: Cell::new(0),
+            host_in: Cell::new(false),
+            transferred: Cell::new(0),
+            data_length: Cell::new(0),
+            status: Cell::new(CommandStatus::Passed),
+            response: Cell::new([0; RESPONSE_LENGTH]),
+            lba: Cell::new(0),
+            blocks: Cell::new(0),
+            sense_key: Cell::new(SENSE_NO_SENSE),
+            sense_code: Cell::new(ASC_NONE),
+        }
+    }
+
+    #[inline]
+    fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    #[inline]
+    fn buffer(&self, i: usize) -> &[VolatileCell<u8>; 64] {
+        &self.buffers[i - 1].buf
+    }
+
+    /// Set the number of blocks of the medium. Setting zero blocks reports
+    /// the medium as not present.
+    pub fn set_block_count(&self, block_count: u32) {
+        if block_count != self.block_count.get() {
+            self.block_count.set(block_count);
+            // Tell the host to read the new capacity.
+            self.unit_attention.set(block_count > 0);
+        }
+    }
+
+    fn set_sense(&self, sense_key: u8, sense_code: u8) {
+        self.sense_key.set(sense_key);
+        self.sense_code.set(sense_code);
+    }
+
+    /// Returns whether commands can access the medium, or sets the sense data
+    /// to tell the host why not.
+    fn medium_ready(&self) -> bool {
+        if self.block_count.get() == 0 {
+            self.set_sense(SENSE_NOT_READY, ASC_MEDIUM_NOT_PRESENT);
+            false
+        } else if self.unit_attention.get() {
+            self.unit_attention.set(false);
+            self.set_sense(SENSE_UNIT_ATTENTION, ASC_MEDIUM_CHANGED);
+            false
+        } else {
+            true
+        }
+    }
+
+    fn address(&self) -> usize {
+        self.start_address + self.lba.get() as usize * BLOCK_SIZE
+    }
+
+    /// Go back to waiting for a command, abandoning the current one.
+    fn reset(&self) {
+        if self.state.get() != State::Disabled {
+            self.state.set(State::Command);
+        }
+    }
+
+    /// Handle a Command Block Wrapper received from the host.
+    fn command_block(&self, packet_bytes: usize) {
+        let packet = self.buffer(ENDPOINT_OUT_NUM);
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                packet[offset].get(),
+                packet[offset + 1].get(),
+                packet[offset + 2].get(),
+                packet[offset + 3].get(),
+            ])
+        };
+        if packet_bytes != CBW_LENGTH || word(0) != CBW_SIGNATURE {
+            // Not a valid command, so there is no tag to report a status
+            // for. Ignore it.
+            return;
+        }
+
+        self.tag.set(word(4));
+        self.host_length.set(word(8));
+        self.host_in.set(packet[12].get() & 0x80 != 0);
+        self.transferred.set(0);
+        self.data_length.set(0);
+        self.status.set(CommandStatus::Passed);
+
+        let mut cb = [0; 16];
+        for (i, byte) in cb.iter_mut().enumerate() {
+            *byte = packet[15 + i].get();
+        }
+        if packet[13].get() & 0x0f != 0 {
+            // Only LUN 0 exists.
+            self.fail(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD_IN_CDB);
+        } else {
+            self.scsi_command(&cb);
+        }
+    }
+
+    /// Execute a SCSI command.
+    fn scsi_command(&self, cb: &[u8; 16]) {
+        let be16 = |offset: usize| u16::from_be_bytes([cb[offset], cb[offset + 1]]) as usize;
+        let be32 = |offset: usize| {
+            u32::from_be_bytes([cb[offset], cb[offset + 1], cb[offset + 2], cb[offset + 3]])
+        };
+
+        match cb[0] {
+            TEST_UNIT_READY => {
+                if self.medium_ready() {
+                    self.skip_data(CommandStatus::Passed);
+                } else {
+                    self.skip_data(CommandStatus::Failed);
+                }
+            }
+            REQUEST_SENSE => {
+                let mut sense = [0; 18];
+                sense[0] = 0x70; // Current error, fixed format
+                sense[2] = self.sense_key.get();
+                sense[7] = 10; // Additional sense length
+                sense[12] = self.sense_code.get();
+                self.set_sense(SENSE_NO_SENSE, ASC_NONE);
+                self.respond(&sense, cb[4] as usize);
+            }
+            INQUIRY => {
+                if cb[1] & 0x01 != 0 {
+                    // Vital product data pages are not supported.
+                    self.fail(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD_IN_CDB);
+                    return;
+                }
+                let mut inquiry = [b' '; RESPONSE_LENGTH];
+                inquiry[0] = 0x00; // Direct access block device
+                inquiry[1] = 0x80; // Removable medium
+                inquiry[2] = 0x04; // SPC-2
+                inquiry[3] = 0x02; // Response data format
+                inquiry[4] = (RESPONSE_LENGTH - 5) as u8; // Additional length
+                inquiry[5] = 0x00;
+                inquiry[6] = 0x00;
+                inquiry[7] = 0x00;
+                // Vendor, product and revision are space padded ASCII.
+                for (byte, c) in inquiry[8..16].iter_mut().zip(self.strings[0].bytes()) {
+                    *byte = c;
+                }
+                for (byte, c) in inquiry[16..32].iter_mut().zip(self.strings[1].bytes()) {
+                    *byte = c;
+                }
+                inquiry[32..36].copy_from_slice(b"1.0 ");
+                self.respond(&inquiry, be16(3));
+            }
+            MODE_SENSE_6 => {
+                // Only the header, without block descriptors or pages.
+                self.respond(&[3, 0, 0, 0], cb[4] as usize);
+            }
+            MODE_SENSE_10 => {
+                self.respond(&[0, 6, 0, 0, 0, 0, 0, 0], be16(7));
+            }
+            READ_FORMAT_CAPACITIES => {
+                let mut capacities = [0; 12];
+                capacities[3] = 8; // Capacity list length
+                capacities[4..8].copy_from_slice(&self.block_count.get().to_be_bytes());
+                // Formatted medium, or no medium present.
+                capacities[8] = if self.block_count.get() > 0 { 2 } else { 3 };
+                capacities[9..12].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes()[1..]);
+                self.respond(&capacities, be16(7));
+            }
+            READ_CAPACITY_10 => {
+                if !self.medium_ready() {
+                    self.skip_data(CommandStatus::Failed);
+                    return;
+                }
+                let mut capacity = [0; 8];
+                capacity[0..4].copy_from_slice(&(self.block_count.get() - 1).to_be_bytes());
+                capacity[4..8].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                self.respond(&capacity, capacity.len());
+            }
+            READ_10 | WRITE_10 => {
+                let read = cb[0] == READ_10;
+                if !self.medium_ready() {
+                    self.skip_data(CommandStatus::Failed);
+                    return;
+                }
+                let lba = be32(2);
+                let blocks = be16(7) as u32;
+                if lba as u64 + blocks as u64 > self.block_count.get() as u64 {
+                    self.fail(SENSE_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE);
+                    return;
+                }
+                if blocks == 0 {
+                    self.skip_data(CommandStatus::Passed);
+                    return;
+                }
+                let length = blocks as u64 * BLOCK_SIZE as u64;
+                if self.host_in.get() != read || (self.host_length.get() as u64) < length {
+                    // The host does not expect the data to be transferred in
+                    // the way the command requires.
+                    self.skip_data(CommandStatus::PhaseError);
+                    return;
+                }
+                self.lba.set(lba);
+                self.blocks.set(blocks);
+                if read {
+                    self.read_block();
+                } else {
+                    self.state.set(State::WriteData { offset: 0 });
+                }
+            }
+            PREVENT_ALLOW_MEDIUM_REMOVAL | START_STOP_UNIT | VERIFY_10 | SYNCHRONIZE_CACHE_10 => {
+                // Writes go directly to the storage, so there is nothing to
+                // do for these commands.
+                self.skip_data(CommandStatus::Passed);
+            }
+            _ => {
+                self.fail(SENSE_ILLEGAL_REQUEST, ASC_INVALID_COMMAND);
+            }
+        }
+    }
+
+    /// Send `data` to the host, at most `allocation_length` bytes of it.
+    fn respond(&self, data: &[u8], allocation_length: usize) {
+        let length = cmp::min(data.len(), allocation_length);
+        if length == 0 {
+            self.skip_data(CommandStatus::Passed);
+        } else if !self.host_in.get() {
+            self.skip_data(CommandStatus::PhaseError);
+        } else {
+            let mut response = [0; RESPONSE_LENGTH];
+            response[..length].copy_from_slice(&data[..length]);
+            self.response.set(response);
+            self.state.set(State::Response { offset: 0, length });
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        }
+    }
+
+    /// Fail the current command with the given sense data.
+    fn fail(&self, sense_key: u8, sense_code: u8) {
+        self.set_sense(sense_key, sense_code);
+        self.skip_data(CommandStatus::Failed);
+    }
+
+    /// Complete the current command with `status`, without transferring any
+    /// data. If the host expects data, we pad or drop it.
+    fn skip_data(&self, status: CommandStatus) {
+        self.status.set(status);
+        if self.transferred.get() >= self.host_length.get() {
+            self.send_status();
+        } else if self.host_in.get() {
+            self.state.set(State::Pad);
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        } else {
+            self.state.set(State::Discard);
+        }
+    }
+
+    fn send_status(&self) {
+        self.state.set(State::Status);
+        self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+    }
+
+    /// Account for a data packet of `length` bytes, of which `data` bytes
+    /// are not padding.
+    fn count_transferred(&self, length: usize, data: usize) {
+        self.transferred.set(self.transferred.get() + length as u32);
+        self.data_length.set(self.data_length.get() + data as u32);
+    }
+
+    /// Continue sending data to the host after a packet was sent.
+    fn continue_data_in(&self) {
+        let more = self.transferred.get() < self.host_length.get();
+        match self.state.get() {
+            State::Response { offset, length } => {
+                if offset < length && more {
+                    self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+                } else {
+                    self.skip_data(self.status.get());
+                }
+            }
+            State::ReadData { offset } => {
+                if offset < BLOCK_SIZE {
+                    self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+                } else {
+                    self.lba.set(self.lba.get() + 1);
+                    self.blocks.set(self.blocks.get() - 1);
+                    if self.blocks.get() > 0 {
+                        self.read_block();
+                    } else {
+                        self.skip_data(self.status.get());
+                    }
+                }
+            }
+            State::Pad => {
+                if more {
+                    self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+                } else {
+                    self.send_status();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_block(&self) {
+        self.state.set(State::Reading);
+        let result = self
+            .block_buffer
+            .take()
+            .map_or(Err(ErrorCode::NOMEM), |buffer| {
+                self.storage.read(buffer, self.address(), BLOCK_SIZE)
+            });
+        if result.is_err() {
+            self.fail(SENSE_MEDIUM_ERROR, ASC_READ_ERROR);
+        }
+    }
+
+    fn write_block(&self) {
+        self.state.set(State::Writing);
+        let result = self
+            .block_buffer
+            .take()
+            .map_or(Err(ErrorCode::NOMEM), |buffer| {
+                self.storage.write(buffer, self.address(), BLOCK_SIZE)
+            });
+        if result.is_err() {
+            self.fail(SENSE_MEDIUM_ERROR, ASC_WRITE_ERROR);
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> MediumClient for MassStorage<'a, U> {
+    fn medium_changed(&self, block_count: u32) {
+        self.set_block_count(block_count);
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> NonvolatileStorageClient<'a> for MassStorage<'a, U> {
+    fn read_done(&self, buffer: &'a mut [u8], length: usize) {
+        self.block_buffer.replace(buffer);
+        if self.state.get() != State::Reading {
+            // The command was abandoned by a reset.
+            return;
+        }
+        if length < BLOCK_SIZE {
+            self.fail(SENSE_MEDIUM_ERROR, ASC_READ_ERROR);
+        } else {
+            self.state.set(State::ReadData { offset: 0 });
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        }
+    }
+
+    fn write_done(&self, buffer: &'a mut [u8], length: usize) {
+        self.block_buffer.replace(buffer);
+        if self.state.get() != State::Writing {
+            // The command was abandoned by a reset.
+            return;
+        }
+        if length < BLOCK_SIZE {
+            self.fail(SENSE_MEDIUM_ERROR, ASC_WRITE_ERROR);
+        } else {
+            self.lba.set(self.lba.get() + 1);
+            self.blocks.set(self.blocks.get() - 1);
+            if self.blocks.get() > 0 {
+                self.state.set(State::WriteData { offset: 0 });
+            } else {
+                self.skip_data(self.status.get());
+            }
+        }
+        // We delayed the OUT endpoint while the block was written.
+        self.controller().endpoint_resume_out(ENDPOINT_OUT_NUM);
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for MassStorage<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Setup buffers for IN and OUT data transfer.
+        self.controller()
+            .endpoint_set_in_buffer(ENDPOINT_IN_NUM, self.buffer(ENDPOINT_IN_NUM));
+        self.controller()
+            .endpoint_in_enable(TransferType::Bulk, ENDPOINT_IN_NUM);
+
+        self.controller()
+            .endpoint_set_out_buffer(ENDPOINT_OUT_NUM, self.buffer(ENDPOINT_OUT_NUM));
+        self.controller()
+            .endpoint_out_enable(TransferType::Bulk, ENDPOINT_OUT_NUM);
+
+        self.state.set(State::Command);
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.reset();
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// The mass storage class defines two requests: one for the number of
+    /// logical units, and one to reset the Bulk-Only Transport.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf).map(|setup_data| {
+            if let RequestType::Class = setup_data.request_type.request_type() {
+                match setup_data.request_code {
+                    REQUEST_GET_MAX_LUN => self.ctrl_state.set(CtrlState::GetMaxLun),
+                    REQUEST_MASS_STORAGE_RESET => self.reset(),
+                    _ => {}
+                }
+            }
+        });
+
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        match self.ctrl_state.get() {
+            CtrlState::GetMaxLun => {
+                // Only LUN 0 exists.
+                self.client_ctrl.ctrl_buffer.buf[0].set(0);
+                hil::usb::CtrlInResult::Packet(1, true)
+            }
+            CtrlState::Idle => self.client_ctrl.ctrl_in(endpoint),
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.ctrl_state.set(CtrlState::Idle);
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction.
+    ///
+    /// This is called when we resume the IN endpoint because we have data or
+    /// a status to send.
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        if !matches!(transfer_type, TransferType::Bulk) {
+            return hil::usb::InResult::Delay;
+        }
+
+        let packet = self.buffer(endpoint);
+        let remaining = (self.host_length.get() - self.transferred.get()) as usize;
+        match self.state.get() {
+            State::Response { offset, length } if offset < length && remaining > 0 => {
+                let to_send = cmp::min(cmp::min(packet.len(), length - offset), remaining);
+                let response = self.response.get();
+                for i in 0..to_send {
+                    packet[i].set(response[offset + i]);
+                }
+                self.state.set(State::Response {
+                    offset: offset + to_send,
+                    length,
+                });
+                self.count_transferred(to_send, to_send);
+                hil::usb::InResult::Packet(to_send)
+            }
+            State::ReadData { offset } if offset < BLOCK_SIZE && remaining > 0 => self
+                .block_buffer
+                .map_or(hil::usb::InResult::Delay, |buffer| {
+                    let to_send = cmp::min(cmp::min(packet.len(), BLOCK_SIZE - offset), remaining);
+                    for i in 0..to_send {
+                        packet[i].set(buffer[offset + i]);
+                    }
+                    self.state.set(State::ReadData {
+                        offset: offset + to_send,
+                    });
+                    self.count_transferred(to_send, to_send);
+                    hil::usb::InResult::Packet(to_send)
+                }),
+            State::Pad if remaining > 0 => {
+                let to_send = cmp::min(packet.len(), remaining);
+                for i in 0..to_send {
+                    packet[i].set(0);
+                }
+                self.count_transferred(to_send, 0);
+                hil::usb::InResult::Packet(to_send)
+            }
+            State::Status => {
+                let residue = self.host_length.get() - self.data_length.get();
+                let fields = [CSW_SIGNATURE, self.tag.get(), residue];
+                for (i, field) in fields.iter().enumerate() {
+                    for (j, byte) in field.to_le_bytes().iter().enumerate() {
+                        packet[i * 4 + j].set(*byte);
+                    }
+                }
+                packet[12].set(self.status.get() as u8);
+
+                // The host can send the next command once it has the status.
+                self.state.set(State::Command);
+                hil::usb::InResult::Packet(CSW_LENGTH)
+            }
+            _ => hil::usb::InResult::Delay,
+        }
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        if !matches!(transfer_type, TransferType::Bulk) {
+            return hil::usb::OutResult::Ok;
+        }
+
+        let packet_bytes = packet_bytes as usize;
+        match self.state.get() {
+            State::Command => self.command_block(packet_bytes),
+            State::WriteData { offset } => {
+                let packet = self.buffer(endpoint);
+                let received = cmp::min(packet_bytes, BLOCK_SIZE - offset);
+                self.block_buffer.map(|buffer| {
+                    for i in 0..received {
+                        buffer[offset + i] = packet[i].get();
+                    }
+                });
+                self.count_transferred(packet_bytes, received);
+                if offset + received < BLOCK_SIZE {
+                    self.state.set(State::WriteData {
+                        offset: offset + received,
+                    });
+                } else {
+                    self.write_block();
+                }
+            }
+            State::Discard => {
+                self.count_transferred(packet_bytes, 0);
+                if self.transferred.get() >= self.host_length.get() {
+                    self.send_status();
+                }
+            }
+            _ => {
+                // The host should not send data now, so drop it.
+            }
+        }
+
+        if self.state.get() == State::Writing {
+            // Receive the next block once this one is written.
+            hil::usb::OutResult::Delay
+        } else {
+            hil::usb::OutResult::Ok
+        }
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {
+        self.continue_data_in();
+    }
+}
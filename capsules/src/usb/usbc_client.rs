@@ -91,8 +91,9 @@ impl<'a, C: hil::usb::UsbController<'a>> Client<'a, C> {
                 descriptors::ConfigurationDescriptor::default(),
                 interfaces,
                 endpoints,
-                None, // No HID descriptor
+                &[],  // No HID descriptor
                 None, // No CDC descriptor array
+                None, // No DFU descriptor
             );
 
         Client {
@@ -100,8 +101,8 @@ impl<'a, C: hil::usb::UsbController<'a>> Client<'a, C> {
                 controller,
                 device_descriptor_buffer,
                 other_descriptor_buffer,
-                None, // No HID descriptor
-                None, // No report descriptor
+                &[], // No HID descriptor
+                &[], // No report descriptor
                 LANGUAGES,
                 STRINGS,
             ),
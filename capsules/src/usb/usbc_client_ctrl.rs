@@ -64,14 +64,14 @@ pub struct ClientCtrl<'a, 'b, U: 'a> {
     /// descriptor and all other descriptors for this device.
     other_descriptor_buffer: DescriptorBuffer,
 
-    /// An optional HID descriptor for the configuration. This can be requested
-    /// separately. It must also be included in `other_descriptor_buffer` if it exists.
-    hid_descriptor: Option<&'b HIDDescriptor<'b>>,
+    /// HID descriptors of the configuration, indexed by interface number.
+    /// These can be requested separately. They must also be included in
+    /// `other_descriptor_buffer`.
+    hid_descriptors: &'b [&'b HIDDescriptor<'b>],
 
-    /// An optional report descriptor for the configuration. This can be
-    /// requested separately. It must also be included in
-    /// `other_descriptor_buffer` if it exists.
-    report_descriptor: Option<&'b ReportDescriptor<'b>>,
+    /// Report descriptors of the configuration, indexed by interface number.
+    /// These can be requested separately.
+    report_descriptors: &'b [&'b ReportDescriptor<'b>],
 
     /// Supported language (only one for now).
     language: &'b [u16; 1],
@@ -106,8 +106,8 @@ impl<'a, 'b, U: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, U> {
         controller: &'a U,
         device_descriptor_buffer: DeviceBuffer,
         other_descriptor_buffer: DescriptorBuffer,
-        hid_descriptor: Option<&'b HIDDescriptor<'b>>,
-        report_descriptor: Option<&'b ReportDescriptor<'b>>,
+        hid_descriptors: &'b [&'b HIDDescriptor<'b>],
+        report_descriptors: &'b [&'b ReportDescriptor<'b>],
         language: &'b [u16; 1],
         strings: &'b [&'b str],
     ) -> Self {
@@ -155,8 +155,8 @@ impl<'a, 'b, U: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, U> {
             ctrl_buffer: Buffer64::default(),
             device_descriptor_buffer,
             other_descriptor_buffer,
-            hid_descriptor,
-            report_descriptor,
+            hid_descriptors,
+            report_descriptors,
             language,
             strings,
         }
@@ -333,12 +333,12 @@ impl<'a, 'b, U: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, U> {
                 descriptor_type,
                 // TODO: use the descriptor index
                 descriptor_index: _,
-                // TODO: use the language ID?
-                lang_id: _,
+                // For class descriptors this holds the interface number.
+                lang_id: interface,
                 requested_length,
             } => match descriptor_type {
                 DescriptorType::HID => {
-                    if let Some(desc) = self.hid_descriptor {
+                    if let Some(desc) = self.hid_descriptors.get(interface as usize) {
                         let buf = self.descriptor_buf();
                         let len = desc.write_to(buf);
                         let end = min(len, requested_length as usize);
@@ -349,7 +349,7 @@ impl<'a, 'b, U: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, U> {
                     }
                 }
                 DescriptorType::Report => {
-                    if let Some(desc) = self.report_descriptor {
+                    if let Some(desc) = self.report_descriptors.get(interface as usize) {
                         let buf = self.descriptor_buf();
                         let len = desc.write_to(buf);
                         let end = min(len, requested_length as usize);
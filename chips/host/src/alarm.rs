@@ -0,0 +1,96 @@
+//! Alarm driven by simulated time.
+//!
+//! Time on the host does not pass on its own. It only advances when the chip
+//! sleeps, and then jumps straight to the expiration of the alarm, so that
+//! tests using alarms run instantly and deterministically.
+
+use core::cell::Cell;
+
+use kernel::hil::time::{self, Alarm, Ticks, Ticks32, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+pub struct HostAlarm<'a> {
+    now: Cell<Ticks32>,
+    reference: Cell<Ticks32>,
+    dt: Cell<Ticks32>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<'a> HostAlarm<'a> {
+    pub fn new() -> HostAlarm<'a> {
+        HostAlarm {
+            now: Cell::new(Ticks32::from(0)),
+            reference: Cell::new(Ticks32::from(0)),
+            dt: Cell::new(Ticks32::from(0)),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Whether the alarm is armed and has expired.
+    pub fn is_pending(&self) -> bool {
+        self.armed.get() && self.now().wrapping_sub(self.reference.get()) >= self.dt.get()
+    }
+
+    /// Fires the alarm if it has expired.
+    pub fn handle_interrupt(&self) {
+        if self.is_pending() {
+            self.armed.set(false);
+            self.client.map(|client| client.alarm());
+        }
+    }
+
+    /// Advances time to the expiration of the alarm. Returns `false` if the
+    /// alarm is not armed, in which case nothing would ever wake the chip.
+    pub fn advance_to_alarm(&self) -> bool {
+        if self.armed.get() && !self.is_pending() {
+            self.now.set(self.get_alarm());
+        }
+        self.armed.get()
+    }
+
+    /// Advances time by `ticks`.
+    pub fn advance(&self, ticks: u32) {
+        self.now.set(self.now().wrapping_add(Ticks32::from(ticks)));
+    }
+}
+
+impl Time for HostAlarm<'_> {
+    type Frequency = time::Freq1KHz;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        self.now.get()
+    }
+}
+
+impl<'a> Alarm<'a> for HostAlarm<'a> {
+    fn set_alarm_client(&self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.armed.set(false);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        Ticks32::from(1)
+    }
+}
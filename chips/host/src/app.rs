@@ -0,0 +1,373 @@
+//! Simulated applications.
+//!
+//! An application on the host is a Rust closure that is given an
+//! [`AppContext`](struct.AppContext.html). The closure runs on its own thread
+//! and issues system calls through the context, which blocks the thread until
+//! the kernel resumes the process. Returning from the closure terminates the
+//! process with completion code 0, and a panic in the closure is a fault of
+//! the process.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let app = App::new("blink", |ctx| {
+//!     ctx.command(LED_DRIVER_NUM, 1, 0, 0);
+//!     ctx.exit(0);
+//! });
+//! ```
+
+use core::ptr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use kernel::process::FunctionCall;
+use kernel::syscall::{Syscall, SyscallReturn};
+use kernel::ErrorCode;
+
+/// The default amount of RAM requested by an application.
+pub const DEFAULT_RAM_SIZE: usize = 4096;
+
+/// Number of distinct upcall ids an application can subscribe with.
+pub const NUM_UPCALL_IDS: usize = 256;
+
+/// Memop operand for moving the process memory break by an increment.
+const MEMOP_SBRK: usize = 1;
+
+/// An application to flash on the host board.
+#[derive(Clone)]
+pub struct App {
+    pub(crate) name: String,
+    pub(crate) ram_size: usize,
+    main: Arc<dyn Fn(&mut AppContext) + Send + Sync>,
+}
+
+impl App {
+    pub fn new<F: Fn(&mut AppContext) + Send + Sync + 'static>(name: &str, main: F) -> App {
+        App {
+            name: String::from(name),
+            ram_size: DEFAULT_RAM_SIZE,
+            main: Arc::new(main),
+        }
+    }
+
+    /// Sets the minimum amount of RAM the process requests in its TBF header.
+    pub fn ram_size(mut self, ram_size: usize) -> App {
+        self.ram_size = ram_size;
+        self
+    }
+
+    /// Starts a thread running this application. The thread waits until the
+    /// process is first switched to.
+    pub(crate) fn spawn(&self) -> AppThread {
+        let (resume_tx, resume_rx) = mpsc::channel();
+        let (trap_tx, trap_rx) = mpsc::channel();
+        let main = self.main.clone();
+        thread::Builder::new()
+            .name(self.name.clone())
+            .spawn(move || {
+                let mut context = AppContext {
+                    resume: resume_rx,
+                    trap: trap_tx,
+                    flash_start: 0,
+                    memory_start: ptr::null(),
+                    app_brk: ptr::null(),
+                };
+                if let Resume::Start(init) = context.wait() {
+                    context.flash_start = init.argument0;
+                    main(&mut context);
+                    context.exit(0);
+                }
+            })
+            .expect("unable to spawn thread for process");
+        AppThread {
+            resume: resume_tx,
+            trap: trap_rx,
+        }
+    }
+}
+
+/// How the kernel resumes a process.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Resume {
+    /// Call the init function of the process.
+    Start(FunctionCall),
+    /// Return from the last system call.
+    Return(SyscallReturn),
+    /// Call an upcall of the process.
+    Upcall(FunctionCall),
+    /// Continue after a system call without a return value.
+    Continue,
+}
+
+/// A resumption of a process, with the bounds of its accessible memory.
+pub(crate) struct Resumed {
+    pub(crate) resume: Resume,
+    pub(crate) memory_start: *const u8,
+    pub(crate) app_brk: *const u8,
+}
+
+/// Wrapper for moving values that contain pointers into process memory
+/// between the kernel and the process threads.
+pub(crate) struct Message<T>(pub(crate) T);
+
+// Only one of the kernel and the processes runs at any time, so process
+// memory is never accessed from two threads at once.
+unsafe impl<T> Send for Message<T> {}
+
+/// The kernel end of the channels to a process thread.
+pub(crate) struct AppThread {
+    pub(crate) resume: Sender<Message<Resumed>>,
+    pub(crate) trap: Receiver<Message<Syscall>>,
+}
+
+/// An upcall called by the kernel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Upcall {
+    /// The upcall id the process passed to `subscribe`.
+    pub id: usize,
+    pub args: (usize, usize, usize),
+    pub appdata: usize,
+}
+
+/// What a system call returned to the process.
+#[derive(Copy, Clone, Debug)]
+pub enum Event {
+    /// The kernel returned a value from the system call.
+    Return(SyscallReturn),
+    /// The kernel called an upcall while the process was yielded.
+    Upcall(Upcall),
+    /// The kernel resumed the process without a return value.
+    Continue,
+}
+
+/// The interface of a simulated application to the kernel.
+pub struct AppContext {
+    resume: Receiver<Message<Resumed>>,
+    trap: Sender<Message<Syscall>>,
+    flash_start: usize,
+    memory_start: *const u8,
+    app_brk: *const u8,
+}
+
+impl AppContext {
+    /// Blocks until the kernel resumes the process. If the process never runs
+    /// again, because it exited or was restarted, the thread parks forever.
+    fn wait(&mut self) -> Resume {
+        match self.resume.recv() {
+            Ok(Message(resumed)) => {
+                self.memory_start = resumed.memory_start;
+                self.app_brk = resumed.app_brk;
+                resumed.resume
+            }
+            Err(_) => loop {
+                thread::park();
+            },
+        }
+    }
+
+    /// Issues `syscall` and blocks until the kernel resumes the process.
+    pub fn syscall(&mut self, syscall: Syscall) -> Event {
+        if self.trap.send(Message(syscall)).is_err() {
+            loop {
+                thread::park();
+            }
+        }
+        match self.wait() {
+            Resume::Return(return_value) => Event::Return(return_value),
+            Resume::Upcall(upcall) => Event::Upcall(Upcall {
+                id: upcall.pc.wrapping_sub(self.flash_start),
+                args: (upcall.argument0, upcall.argument1, upcall.argument2),
+                appdata: upcall.argument3,
+            }),
+            Resume::Continue => Event::Continue,
+            Resume::Start(_) => panic!("process started twice"),
+        }
+    }
+
+    /// Issues a system call that always has a return value.
+    fn syscall_return(&mut self, syscall: Syscall) -> SyscallReturn {
+        match self.syscall(syscall) {
+            Event::Return(return_value) => return_value,
+            event => panic!("no return value for {:?}: {:?}", syscall, event),
+        }
+    }
+
+    /// Blocks until the kernel calls an upcall of the process.
+    pub fn yield_wait(&mut self) -> Upcall {
+        match self.syscall(Syscall::Yield {
+            which: 1,
+            address: ptr::null_mut(),
+        }) {
+            Event::Upcall(upcall) => upcall,
+            event => panic!("yield-wait resumed without an upcall: {:?}", event),
+        }
+    }
+
+    /// Returns the upcall the kernel called, if there was one pending.
+    pub fn yield_no_wait(&mut self) -> Option<Upcall> {
+        match self.syscall(Syscall::Yield {
+            which: 0,
+            address: ptr::null_mut(),
+        }) {
+            Event::Upcall(upcall) => Some(upcall),
+            _ => None,
+        }
+    }
+
+    /// Subscribes to an upcall, which is identified by `upcall_id` when it is
+    /// called. The kernel only accepts upcalls that point into the process, so
+    /// the id is used as an offset into the binary of the process and must be
+    /// less than `NUM_UPCALL_IDS`.
+    pub fn subscribe(
+        &mut self,
+        driver_number: usize,
+        subdriver_number: usize,
+        upcall_id: usize,
+        appdata: usize,
+    ) -> SyscallReturn {
+        assert!(
+            upcall_id < NUM_UPCALL_IDS,
+            "invalid upcall id {}",
+            upcall_id
+        );
+        self.syscall_return(Syscall::Subscribe {
+            driver_number,
+            subdriver_number,
+            upcall_ptr: (self.flash_start + upcall_id) as *mut (),
+            appdata,
+        })
+    }
+
+    /// Unsubscribes from an upcall.
+    pub fn unsubscribe(&mut self, driver_number: usize, subdriver_number: usize) -> SyscallReturn {
+        self.syscall_return(Syscall::Subscribe {
+            driver_number,
+            subdriver_number,
+            upcall_ptr: ptr::null_mut(),
+            appdata: 0,
+        })
+    }
+
+    pub fn command(
+        &mut self,
+        driver_number: usize,
+        subdriver_number: usize,
+        arg0: usize,
+        arg1: usize,
+    ) -> SyscallReturn {
+        self.syscall_return(Syscall::Command {
+            driver_number,
+            subdriver_number,
+            arg0,
+            arg1,
+        })
+    }
+
+    pub fn allow_readwrite(
+        &mut self,
+        driver_number: usize,
+        subdriver_number: usize,
+        allow_address: *mut u8,
+        allow_size: usize,
+    ) -> SyscallReturn {
+        self.syscall_return(Syscall::ReadWriteAllow {
+            driver_number,
+            subdriver_number,
+            allow_address,
+            allow_size,
+        })
+    }
+
+    pub fn allow_readonly(
+        &mut self,
+        driver_number: usize,
+        subdriver_number: usize,
+        allow_address: *const u8,
+        allow_size: usize,
+    ) -> SyscallReturn {
+        self.syscall_return(Syscall::ReadOnlyAllow {
+            driver_number,
+            subdriver_number,
+            allow_address,
+            allow_size,
+        })
+    }
+
+    pub fn memop(&mut self, operand: usize, arg0: usize) -> SyscallReturn {
+        self.syscall_return(Syscall::Memop { operand, arg0 })
+    }
+
+    /// Grows the accessible memory of the process by `increment` bytes and
+    /// returns the start of the new memory.
+    ///
+    /// Memops return addresses as 32 bit values, which cannot hold the
+    /// addresses of the host, so the start is taken from the previous break
+    /// instead.
+    pub fn sbrk(&mut self, increment: usize) -> Result<*mut u8, ErrorCode> {
+        let start = self.app_brk as *mut u8;
+        match self.memop(MEMOP_SBRK, increment) {
+            SyscallReturn::Failure(err) => Err(err),
+            _ => Ok(start),
+        }
+    }
+
+    /// Start of the binary of the process in flash.
+    pub fn flash_start(&self) -> usize {
+        self.flash_start
+    }
+
+    /// Checks that `address..address+len` is accessible memory of the process.
+    /// Like an access outside its memory on hardware, a failed check is a
+    /// fault of the process.
+    fn check_access(&self, address: *const u8, len: usize) {
+        let start = address as usize;
+        if start < self.memory_start as usize || start.saturating_add(len) > self.app_brk as usize {
+            panic!(
+                "process accessed {:#x}-{:#x} outside its memory {:?}-{:?}",
+                start,
+                start.saturating_add(len),
+                self.memory_start,
+                self.app_brk
+            );
+        }
+    }
+
+    /// Writes `data` to process memory at `address`.
+    // The access is checked to be within the memory of the process.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn write(&mut self, address: *mut u8, data: &[u8]) {
+        self.check_access(address, data.len());
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), address, data.len());
+        }
+    }
+
+    /// Reads process memory at `address` into `buf`.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn read(&self, address: *const u8, buf: &mut [u8]) {
+        self.check_access(address, buf.len());
+        unsafe {
+            ptr::copy_nonoverlapping(address, buf.as_mut_ptr(), buf.len());
+        }
+    }
+
+    /// Terminates the process with `completion_code`.
+    pub fn exit(&mut self, completion_code: usize) -> ! {
+        self.syscall(Syscall::Exit {
+            which: 0,
+            completion_code,
+        });
+        panic!("process resumed after exit");
+    }
+
+    /// Restarts the process with `completion_code`.
+    pub fn restart(&mut self, completion_code: usize) -> ! {
+        self.syscall(Syscall::Exit {
+            which: 1,
+            completion_code,
+        });
+        panic!("process resumed after restart");
+    }
+}
@@ -0,0 +1,240 @@
+//! Board for running the kernel with simulated applications on the host.
+//!
+//! The board plays the role of `main.rs` on hardware: it creates the kernel,
+//! maps driver numbers to capsules, flashes and loads the applications and
+//! runs the kernel loop. All of its state is leaked, as the kernel requires
+//! `'static` references, so each test can create its own board.
+//!
+//! Capsules that use grants must be created before the applications are
+//! loaded, since loading processes finalizes the number of grants.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let board = HostBoard::new();
+//! let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+//! let driver = Box::leak(Box::new(MyDriver::new(
+//!     board.kernel().create_grant(DRIVER_NUM, &grant_cap),
+//! )));
+//! board.add_driver(DRIVER_NUM, driver);
+//! board
+//!     .load_apps(&[App::new("app", |ctx| {
+//!         ctx.command(DRIVER_NUM, 1, 0, 0);
+//!     })])
+//!     .unwrap();
+//! board.run_until_idle(board.round_robin());
+//! ```
+
+use core::cell::{Cell, RefCell};
+use core::slice;
+use std::boxed::Box;
+use std::vec::Vec;
+
+use kernel::capabilities;
+use kernel::platform::chip::Chip;
+use kernel::platform::{KernelResources, SyscallDriverLookup};
+use kernel::process::{self, Process, ProcessFaultPolicy, ProcessLoadError};
+use kernel::scheduler::round_robin::{RoundRobinProcessNode, RoundRobinSched};
+use kernel::syscall::SyscallDriver;
+use kernel::{create_capability, Kernel, Scheduler};
+
+use crate::app::App;
+use crate::chip::Host;
+use crate::tbf;
+
+/// Number of concurrent processes the board supports.
+pub const NUM_PROCS: usize = 4;
+
+/// Amount of memory shared by all processes.
+pub const APP_MEMORY_SIZE: usize = 128 * 1024;
+
+pub struct HostBoard {
+    kernel: &'static Kernel,
+    chip: &'static Host,
+    processes: *mut [Option<&'static dyn Process>; NUM_PROCS],
+    drivers: RefCell<Vec<(usize, &'static dyn SyscallDriver)>>,
+    fault_policy: Cell<&'static dyn ProcessFaultPolicy>,
+}
+
+impl HostBoard {
+    pub fn new() -> HostBoard {
+        let processes: &'static mut [Option<&'static dyn Process>; NUM_PROCS] =
+            Box::leak(Box::new([None; NUM_PROCS]));
+        let processes = processes as *mut [Option<&'static dyn Process>; NUM_PROCS];
+        let kernel = Box::leak(Box::new(Kernel::new(unsafe { &*processes })));
+        HostBoard {
+            kernel,
+            chip: Box::leak(Box::new(Host::new())),
+            processes,
+            drivers: RefCell::new(Vec::new()),
+            fault_policy: Cell::new(&process::StopFaultPolicy {}),
+        }
+    }
+
+    pub fn kernel(&self) -> &'static Kernel {
+        self.kernel
+    }
+
+    pub fn chip(&self) -> &'static Host {
+        self.chip
+    }
+
+    pub fn processes(&self) -> &'static [Option<&'static dyn Process>; NUM_PROCS] {
+        unsafe { &*self.processes }
+    }
+
+    /// Returns the process with the package name `name`.
+    pub fn process(&self, name: &str) -> Option<&'static dyn Process> {
+        self.processes()
+            .iter()
+            .flatten()
+            .find(|process| process.get_process_name() == name)
+            .copied()
+    }
+
+    /// Makes `driver` handle the system calls for `driver_num`.
+    pub fn add_driver(&self, driver_num: usize, driver: &'static dyn SyscallDriver) {
+        self.drivers.borrow_mut().push((driver_num, driver));
+    }
+
+    /// Sets the fault policy of the processes loaded afterwards. Processes
+    /// are stopped when they fault by default.
+    pub fn set_fault_policy(&self, fault_policy: &'static dyn ProcessFaultPolicy) {
+        self.fault_policy.set(fault_policy);
+    }
+
+    /// Flashes `apps` and loads them as processes. This must only be called
+    /// once per board.
+    pub fn load_apps(&self, apps: &[App]) -> Result<(), ProcessLoadError> {
+        let mut flash = Vec::new();
+        let init_fns: Vec<usize> = apps
+            .iter()
+            .map(|app| tbf::append_app(&mut flash, app))
+            .collect();
+        let flash: &'static [u8] = Box::leak(flash.into_boxed_slice());
+        for (app, init_fn) in apps.iter().zip(init_fns) {
+            self.chip
+                .userspace_kernel_boundary()
+                .add_app(flash.as_ptr() as usize + init_fn, app.clone());
+        }
+
+        // Allocate process memory as words, so that the kernel structures
+        // stored in it are aligned.
+        let memory: &'static mut [u64] =
+            Box::leak(vec![0u64; APP_MEMORY_SIZE / 8].into_boxed_slice());
+        let memory =
+            unsafe { slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, APP_MEMORY_SIZE) };
+
+        let process_management_capability =
+            create_capability!(capabilities::ProcessManagementCapability);
+        process::load_processes(
+            self.kernel,
+            self.chip,
+            flash,
+            memory,
+            unsafe { &mut *self.processes },
+            self.fault_policy.get(),
+            &process_management_capability,
+        )
+    }
+
+    /// Creates a round robin scheduler for the processes of the board.
+    pub fn round_robin(&self) -> &'static RoundRobinSched<'static> {
+        let scheduler = Box::leak(Box::new(RoundRobinSched::new()));
+        for process in self.processes().iter() {
+            scheduler
+                .processes
+                .push_tail(Box::leak(Box::new(RoundRobinProcessNode::new(process))));
+        }
+        scheduler
+    }
+
+    /// Runs the kernel loop until no process is ready and nothing is left
+    /// that could make one ready, i.e. the chip would sleep forever.
+    pub fn run_until_idle<S: Scheduler<Host>>(&self, scheduler: &S) {
+        let main_loop_capability = create_capability!(capabilities::MainLoopCapability);
+        let resources = HostResources {
+            board: self,
+            scheduler,
+        };
+        self.chip.take_idle();
+        while !self.chip.take_idle() {
+            self.kernel.kernel_loop_operation::<_, _, NUM_PROCS>(
+                &resources,
+                self.chip,
+                None,
+                false,
+                &main_loop_capability,
+            );
+        }
+    }
+
+    /// Runs the kernel loop forever.
+    pub fn kernel_loop<S: Scheduler<Host>>(&self, scheduler: &S) -> ! {
+        let main_loop_capability = create_capability!(capabilities::MainLoopCapability);
+        let resources = HostResources {
+            board: self,
+            scheduler,
+        };
+        self.kernel.kernel_loop::<_, _, NUM_PROCS>(
+            &resources,
+            self.chip,
+            None,
+            &main_loop_capability,
+        )
+    }
+}
+
+impl SyscallDriverLookup for HostBoard {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn SyscallDriver>) -> R,
+    {
+        let driver = self
+            .drivers
+            .borrow()
+            .iter()
+            .find(|(num, _)| *num == driver_num)
+            .map(|(_, driver)| *driver);
+        f(driver)
+    }
+}
+
+/// The resources of the board together with the scheduler it is run with.
+struct HostResources<'a, S: Scheduler<Host>> {
+    board: &'a HostBoard,
+    scheduler: &'a S,
+}
+
+impl<'a, S: Scheduler<Host>> KernelResources<Host> for HostResources<'a, S> {
+    type SyscallDriverLookup = HostBoard;
+    type SyscallFilter = ();
+    type ProcessFault = ();
+    type Scheduler = S;
+    type SchedulerTimer = ();
+    type WatchDog = ();
+    type ContextSwitchCallback = ();
+
+    fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
+        self.board
+    }
+    fn syscall_filter(&self) -> &Self::SyscallFilter {
+        &()
+    }
+    fn process_fault(&self) -> &Self::ProcessFault {
+        &()
+    }
+    fn scheduler(&self) -> &Self::Scheduler {
+        self.scheduler
+    }
+    fn scheduler_timer(&self) -> &Self::SchedulerTimer {
+        &()
+    }
+    fn watchdog(&self) -> &Self::WatchDog {
+        &()
+    }
+    fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
+        &()
+    }
+}
@@ -0,0 +1,82 @@
+//! Chip trait setup for the host.
+
+use core::cell::Cell;
+use core::fmt::Write;
+
+use kernel::hil::time::{Alarm, Ticks, Time};
+use kernel::platform::chip::Chip;
+
+use crate::alarm::HostAlarm;
+use crate::syscall::SysCall;
+
+pub struct Host {
+    userspace_kernel_boundary: SysCall,
+    alarm: HostAlarm<'static>,
+    /// Set when the chip went to sleep with nothing that could wake it.
+    idle: Cell<bool>,
+}
+
+impl Host {
+    pub fn new() -> Host {
+        Host {
+            userspace_kernel_boundary: SysCall::new(),
+            alarm: HostAlarm::new(),
+            idle: Cell::new(false),
+        }
+    }
+
+    pub fn alarm(&self) -> &HostAlarm<'static> {
+        &self.alarm
+    }
+
+    /// Returns whether the chip went to sleep with no interrupt source left
+    /// to wake it since the last call, and clears the flag.
+    pub fn take_idle(&self) -> bool {
+        self.idle.replace(false)
+    }
+}
+
+impl Chip for Host {
+    type MPU = ();
+    type UserspaceKernelBoundary = SysCall;
+
+    fn mpu(&self) -> &Self::MPU {
+        &()
+    }
+
+    fn userspace_kernel_boundary(&self) -> &SysCall {
+        &self.userspace_kernel_boundary
+    }
+
+    fn service_pending_interrupts(&self) {
+        self.alarm.handle_interrupt();
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        self.alarm.is_pending()
+    }
+
+    fn sleep(&self) {
+        // Simulated time jumps to the next interrupt instead of waiting for
+        // it.
+        if !self.alarm.advance_to_alarm() {
+            self.idle.set(true);
+        }
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // Interrupts on the host are only serviced from the kernel loop.
+        f()
+    }
+
+    unsafe fn print_state(&self, writer: &mut dyn Write) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n---| Host |---\r\n Time: {} ticks, alarm armed: {}\r\n",
+            self.alarm.now().into_u32(),
+            self.alarm.is_armed(),
+        ));
+    }
+}
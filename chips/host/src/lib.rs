@@ -0,0 +1,19 @@
+//! Simulated chip for running the Tock kernel on the host.
+//!
+//! This crate lets the kernel, capsules and schedulers run as a normal Linux
+//! program, so that they can be tested with `cargo test` instead of on an MCU
+//! or in QEMU. The chip has no MPU and no scheduler timer, and its only
+//! peripheral is an alarm driven by simulated time. Applications are Rust
+//! closures that issue `Syscall` values, see the `app` module.
+
+pub mod alarm;
+pub mod app;
+pub mod board;
+pub mod chip;
+pub mod syscall;
+
+mod tbf;
+
+pub use crate::app::{App, AppContext, Event, Upcall};
+pub use crate::board::HostBoard;
+pub use crate::chip::Host;
@@ -0,0 +1,172 @@
+//! Kernel-userland system call interface for simulated processes.
+//!
+//! Processes on the host are Rust closures rather than machine code. Every
+//! process runs on its own thread, but only one of the kernel and the
+//! processes executes at any time: `switch_to_process()` resumes the thread of
+//! the process and blocks until the process issues its next system call, which
+//! the kernel then handles as if the process had trapped.
+
+use core::fmt::Write;
+use std::cell::RefCell;
+use std::vec::Vec;
+
+use kernel::errorcode::ErrorCode;
+use kernel::process::{self, FunctionCallSource};
+use kernel::syscall::{ContextSwitchReason, SyscallReturn, UserspaceKernelBoundary};
+
+use crate::app::{App, AppThread, Message, Resume, Resumed};
+
+/// This holds all of the state that the kernel must keep for the process when
+/// the process is not executing.
+#[derive(Default)]
+pub struct HostStoredState {
+    /// Index of the thread running the process in `SysCall::threads`, once the
+    /// process has been started.
+    thread: Option<usize>,
+    /// How the process continues the next time it is switched to.
+    resume: Option<Resume>,
+}
+
+/// Implementation of the `UserspaceKernelBoundary` for simulated processes.
+pub struct SysCall {
+    /// The applications on the board, by the address of their init function.
+    apps: RefCell<Vec<(usize, App)>>,
+    /// The threads of all processes that have been started.
+    threads: RefCell<Vec<Option<AppThread>>>,
+}
+
+impl SysCall {
+    pub(crate) fn new() -> SysCall {
+        SysCall {
+            apps: RefCell::new(Vec::new()),
+            threads: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Registers `app` as the application whose init function is at
+    /// `init_fn`.
+    pub(crate) fn add_app(&self, init_fn: usize, app: App) {
+        self.apps.borrow_mut().push((init_fn, app));
+    }
+
+    /// Drops the channels to the thread of a process, which parks the thread
+    /// forever.
+    fn stop_thread(&self, state: &mut HostStoredState) {
+        if let Some(index) = state.thread.take() {
+            self.threads.borrow_mut()[index] = None;
+        }
+    }
+}
+
+impl UserspaceKernelBoundary for SysCall {
+    type StoredState = HostStoredState;
+
+    fn initial_process_app_brk_size(&self) -> usize {
+        // Simulated processes keep their stack on the host, so they do not
+        // need any accessible memory to start.
+        0
+    }
+
+    unsafe fn initialize_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+    ) -> Result<(), ()> {
+        // A restarted process gets a new thread when its init function is set.
+        self.stop_thread(state);
+        state.resume = None;
+        Ok(())
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+        return_value: SyscallReturn,
+    ) -> Result<(), ()> {
+        state.resume = Some(Resume::Return(return_value));
+        Ok(())
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+        callback: process::FunctionCall,
+    ) -> Result<(), ()> {
+        match callback.source {
+            FunctionCallSource::Kernel => {
+                let thread = self
+                    .apps
+                    .borrow()
+                    .iter()
+                    .find(|(init_fn, _)| *init_fn == callback.pc)
+                    .map(|(_, app)| app.spawn())
+                    .ok_or(())?;
+                self.stop_thread(state);
+                let mut threads = self.threads.borrow_mut();
+                threads.push(Some(thread));
+                state.thread = Some(threads.len() - 1);
+                state.resume = Some(Resume::Start(callback));
+            }
+            FunctionCallSource::Driver(_) => {
+                state.resume = Some(Resume::Upcall(callback));
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &mut Self::StoredState,
+    ) -> (ContextSwitchReason, Option<*const u8>) {
+        let threads = self.threads.borrow();
+        let thread = match state.thread.and_then(|index| threads[index].as_ref()) {
+            Some(thread) => thread,
+            None => return (ContextSwitchReason::Fault, None),
+        };
+
+        let resumed = Resumed {
+            resume: state.resume.take().unwrap_or(Resume::Continue),
+            memory_start: accessible_memory_start,
+            app_brk,
+        };
+        if thread.resume.send(Message(resumed)).is_err() {
+            return (ContextSwitchReason::Fault, None);
+        }
+
+        // The process runs until it issues its next system call. If the
+        // closure of the process panics, the channel is closed.
+        match thread.trap.recv() {
+            Ok(Message(syscall)) => (ContextSwitchReason::SyscallFired { syscall }, None),
+            Err(_) => (ContextSwitchReason::Fault, None),
+        }
+    }
+
+    unsafe fn print_context(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &HostStoredState,
+        writer: &mut dyn Write,
+    ) {
+        let _ = writer.write_fmt(format_args!(
+            "\
+             \r\n Simulated process on thread {:?}\
+             \r\n Accessible memory: {:?}-{:?}\
+             \r\n Resumes with: {:?}\
+             \r\n",
+            state.thread, accessible_memory_start, app_brk, state.resume,
+        ));
+    }
+
+    fn store_context(&self, _state: &HostStoredState, _out: &mut [u8]) -> Result<usize, ErrorCode> {
+        // The state of a simulated process lives on its thread.
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
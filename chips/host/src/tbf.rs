@@ -0,0 +1,80 @@
+//! Construction of TBF images for simulated applications.
+//!
+//! Simulated applications have no code, but the kernel only loads processes
+//! from flash, so each application gets a TBF header followed by a small
+//! placeholder binary. The placeholder gives every application a distinct
+//! init function address, by which the application is found again when the
+//! kernel starts the process, and room for the addresses of its upcalls.
+
+use core::mem;
+use std::vec::Vec;
+
+use crate::app::{App, NUM_UPCALL_IDS};
+
+/// Size of the placeholder binary of an application. Upcall ids are offsets
+/// into it, and the kernel checks that a whole pointer fits at the address of
+/// an upcall.
+const BINARY_SIZE: usize = NUM_UPCALL_IDS + mem::size_of::<usize>();
+
+/// TBF header flag marking the application as enabled.
+const FLAG_ENABLED: u32 = 1;
+
+const TLV_MAIN: u16 = 1;
+const TLV_PACKAGE_NAME: u16 = 3;
+const TLV_KERNEL_VERSION: u16 = 8;
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn push_tlv_header(image: &mut Vec<u8>, tipe: u16, length: usize) {
+    image.extend_from_slice(&tipe.to_le_bytes());
+    image.extend_from_slice(&(length as u16).to_le_bytes());
+}
+
+/// Appends the TBF image of `app` to `flash` and returns the offset of the
+/// init function of the application in `flash`.
+pub(crate) fn append_app(flash: &mut Vec<u8>, app: &App) -> usize {
+    let name = app.name.as_bytes();
+    let header_size = 16 + (4 + 12) + (4 + align4(name.len())) + (4 + 4);
+    let total_size = header_size + BINARY_SIZE;
+    // Processes are allocated back to back in memory, so keep their memory
+    // word aligned.
+    let ram_size = (app.ram_size + 7) & !7;
+
+    let mut image = Vec::with_capacity(total_size);
+
+    // Base header. The checksum is filled in once the header is complete.
+    image.extend_from_slice(&2u16.to_le_bytes());
+    image.extend_from_slice(&(header_size as u16).to_le_bytes());
+    image.extend_from_slice(&(total_size as u32).to_le_bytes());
+    image.extend_from_slice(&FLAG_ENABLED.to_le_bytes());
+    image.extend_from_slice(&0u32.to_le_bytes());
+
+    // The init function is the first byte of the binary, right after the
+    // header, and nothing is protected besides the header.
+    push_tlv_header(&mut image, TLV_MAIN, 12);
+    image.extend_from_slice(&0u32.to_le_bytes());
+    image.extend_from_slice(&0u32.to_le_bytes());
+    image.extend_from_slice(&(ram_size as u32).to_le_bytes());
+
+    push_tlv_header(&mut image, TLV_PACKAGE_NAME, name.len());
+    image.extend_from_slice(name);
+    image.resize(image.len() + align4(name.len()) - name.len(), 0);
+
+    push_tlv_header(&mut image, TLV_KERNEL_VERSION, 4);
+    image.extend_from_slice(&kernel::MAJOR.to_le_bytes());
+    image.extend_from_slice(&kernel::MINOR.to_le_bytes());
+
+    let checksum = image
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .fold(0, |checksum, word| checksum ^ word);
+    image[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+    image.resize(total_size, 0);
+
+    let init_fn = flash.len() + header_size;
+    flash.extend_from_slice(&image);
+    init_fn
+}
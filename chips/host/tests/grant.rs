@@ -0,0 +1,270 @@
+//! Frees custom grants and limits the grant memory of drivers with quotas.
+
+use host::{App, AppContext, HostBoard};
+use kernel::capabilities;
+use kernel::create_capability;
+use kernel::grant::{AllowRoCount, AllowRwCount, CustomGrant, Grant, GrantQuota, UpcallCount};
+use kernel::syscall::{CommandReturn, SyscallDriver, SyscallReturn};
+use kernel::{ErrorCode, ProcessId};
+
+const POOL_DRIVER_NUM: usize = 0x90002;
+const COUNTER_DRIVER_NUM: usize = 0x90003;
+const BLOCK_SIZE: usize = 512;
+
+/// Memop that returns the start of the grant region, the kernel memory
+/// break.
+const MEMOP_GRANT_START: usize = 6;
+
+#[derive(Default)]
+struct Blocks {
+    blocks: [Option<CustomGrant<[u8; BLOCK_SIZE]>>; 4],
+}
+
+/// Allocates blocks of process memory in custom grants, filled with a value
+/// given by the process, and frees them again. Command 4 frees a block while
+/// another one is entered.
+struct Pool {
+    apps: Grant<Blocks, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
+}
+
+impl SyscallDriver for Pool {
+    fn command(
+        &self,
+        command_num: usize,
+        slot: usize,
+        value: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        self.apps
+            .enter_with_allocator(processid, |blocks, _, allocator| {
+                if command_num == 4 {
+                    return enter_and_free(blocks, slot, value);
+                }
+                let block = match blocks.blocks.get_mut(slot) {
+                    Some(block) => block,
+                    None => return CommandReturn::failure(ErrorCode::INVAL),
+                };
+                match (command_num, block.as_mut()) {
+                    (1, None) => match allocator.alloc_with(|| [value as u8; BLOCK_SIZE]) {
+                        Ok(custom_grant) => {
+                            *block = Some(custom_grant);
+                            CommandReturn::success()
+                        }
+                        Err(err) => CommandReturn::failure(err.into()),
+                    },
+                    (2, Some(_)) => block.take().map_or(
+                        CommandReturn::failure(ErrorCode::FAIL),
+                        |custom_grant| match custom_grant.free() {
+                            Ok(()) => CommandReturn::success(),
+                            Err(err) => CommandReturn::failure(err.into()),
+                        },
+                    ),
+                    (3, Some(custom_grant)) => custom_grant
+                        .enter(|data| CommandReturn::success_u32(data[BLOCK_SIZE - 1] as u32))
+                        .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+                    _ => CommandReturn::failure(ErrorCode::INVAL),
+                }
+            })
+            .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+/// Enters the block in `slot` and, while it is entered, frees the block in
+/// `other`. Returns the value read from the entered block after the free.
+fn enter_and_free(blocks: &mut Blocks, slot: usize, other: usize) -> CommandReturn {
+    let freed = match blocks.blocks.get_mut(other).and_then(Option::take) {
+        Some(freed) => freed,
+        None => return CommandReturn::failure(ErrorCode::INVAL),
+    };
+    match blocks.blocks.get_mut(slot).and_then(Option::as_mut) {
+        Some(custom_grant) => custom_grant
+            .enter(|data| match freed.free() {
+                Ok(()) => CommandReturn::success_u32(data[BLOCK_SIZE - 1] as u32),
+                Err(err) => CommandReturn::failure(err.into()),
+            })
+            .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+        None => CommandReturn::failure(ErrorCode::INVAL),
+    }
+}
+
+/// Counts the commands of every process in its grant.
+struct Counter {
+    apps: Grant<usize, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
+}
+
+impl SyscallDriver for Counter {
+    fn command(&self, _: usize, _: usize, _: usize, processid: ProcessId) -> CommandReturn {
+        self.apps
+            .enter(processid, |count, _| {
+                **count += 1;
+                CommandReturn::success_u32(**count as u32)
+            })
+            .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+fn pool_board() -> HostBoard {
+    let board = HostBoard::new();
+    let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+    let pool = Box::leak(Box::new(Pool {
+        apps: board.kernel().create_grant(POOL_DRIVER_NUM, &grant_cap),
+    }));
+    let counter = Box::leak(Box::new(Counter {
+        apps: board.kernel().create_grant(COUNTER_DRIVER_NUM, &grant_cap),
+    }));
+    board.add_driver(POOL_DRIVER_NUM, pool);
+    board.add_driver(COUNTER_DRIVER_NUM, counter);
+    board
+}
+
+/// The value returned by a command, or the error.
+fn result(ret: SyscallReturn) -> Result<u32, ErrorCode> {
+    match ret {
+        SyscallReturn::Success => Ok(0),
+        SyscallReturn::SuccessU32(value) => Ok(value),
+        SyscallReturn::Failure(err) => Err(err),
+        _ => Err(ErrorCode::FAIL),
+    }
+}
+
+fn allocate(ctx: &mut AppContext, slot: usize, value: usize) -> Result<(), ErrorCode> {
+    result(ctx.command(POOL_DRIVER_NUM, 1, slot, value)).map(|_| ())
+}
+
+fn free(ctx: &mut AppContext, slot: usize) -> Result<(), ErrorCode> {
+    result(ctx.command(POOL_DRIVER_NUM, 2, slot, 0)).map(|_| ())
+}
+
+fn read(ctx: &mut AppContext, slot: usize) -> Result<u32, ErrorCode> {
+    result(ctx.command(POOL_DRIVER_NUM, 3, slot, 0))
+}
+
+fn enter_and_free_block(ctx: &mut AppContext, slot: usize, other: usize) -> Result<u32, ErrorCode> {
+    result(ctx.command(POOL_DRIVER_NUM, 4, slot, other))
+}
+
+fn count(ctx: &mut AppContext) -> Result<u32, ErrorCode> {
+    result(ctx.command(COUNTER_DRIVER_NUM, 1, 0, 0))
+}
+
+/// The kernel memory break, truncated to 32 bits like every memop result.
+fn grant_start(ctx: &mut AppContext) -> u32 {
+    match ctx.memop(MEMOP_GRANT_START, 0) {
+        SyscallReturn::SuccessU32(address) => address,
+        _ => panic!("no grant start"),
+    }
+}
+
+#[test]
+fn freed_custom_grant_is_reclaimed() {
+    let board = pool_board();
+    board
+        .load_apps(&[App::new("reclaim", |ctx| {
+            assert_eq!(allocate(ctx, 0, 1), Ok(()));
+            assert_eq!(allocate(ctx, 1, 2), Ok(()));
+            // The grant of the counter is allocated below both blocks.
+            assert_eq!(count(ctx), Ok(1));
+
+            // The process cannot grow its memory up to the blocks.
+            let before = grant_start(ctx);
+            let app_break = ctx.sbrk(0).unwrap() as u32;
+            let grow = before.wrapping_sub(app_break) as usize + 64;
+            assert_eq!(ctx.sbrk(grow), Err(ErrorCode::NOMEM));
+
+            // Freeing the first block moves the second block and the grant of
+            // the counter up and raises the kernel memory break.
+            assert_eq!(free(ctx, 0), Ok(()));
+            let after = grant_start(ctx);
+            assert!(after.wrapping_sub(before) as usize >= BLOCK_SIZE);
+            assert_eq!(read(ctx, 1), Ok(2));
+            assert_eq!(count(ctx), Ok(2));
+
+            // The reclaimed memory can hold a new block, and once that is
+            // freed as well the process can grow into it.
+            assert_eq!(allocate(ctx, 0, 3), Ok(()));
+            assert_eq!(read(ctx, 0), Ok(3));
+            assert_eq!(read(ctx, 1), Ok(2));
+            assert_eq!(free(ctx, 0), Ok(()));
+            assert_eq!(read(ctx, 0), Err(ErrorCode::INVAL));
+            assert_eq!(grant_start(ctx), after);
+            assert!(ctx.sbrk(grow).is_ok());
+        })
+        .ram_size(8192)])
+        .unwrap();
+    board.run_until_idle(board.round_robin());
+
+    let process = board.process("reclaim").unwrap();
+    assert_eq!(process.get_completion_code(), Some(Some(0)));
+}
+
+#[test]
+fn grant_quota_limits_driver() {
+    let board = pool_board();
+    let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+    // Room for the grant of the pool and two blocks, but not three.
+    board.kernel().set_grant_quotas(
+        &[GrantQuota {
+            driver_num: POOL_DRIVER_NUM,
+            max_size: 3 * BLOCK_SIZE,
+        }],
+        &grant_cap,
+    );
+    board
+        .load_apps(&[App::new("quota", |ctx| {
+            assert_eq!(allocate(ctx, 0, 1), Ok(()));
+            assert_eq!(allocate(ctx, 1, 2), Ok(()));
+            assert_eq!(allocate(ctx, 2, 3), Err(ErrorCode::NOMEM));
+
+            // The process itself is not out of memory.
+            assert_eq!(count(ctx), Ok(1));
+            assert!(ctx.sbrk(BLOCK_SIZE).is_ok());
+
+            // Freeing a block makes room within the quota again.
+            assert_eq!(free(ctx, 0), Ok(()));
+            assert_eq!(allocate(ctx, 2, 3), Ok(()));
+            assert_eq!(read(ctx, 2), Ok(3));
+        })
+        .ram_size(8192)])
+        .unwrap();
+    board.run_until_idle(board.round_robin());
+
+    let process = board.process("quota").unwrap();
+    assert_eq!(process.get_completion_code(), Some(Some(0)));
+}
+
+#[test]
+fn entered_custom_grant_is_not_moved() {
+    let board = pool_board();
+    board
+        .load_apps(&[App::new("entered", |ctx| {
+            assert_eq!(allocate(ctx, 0, 1), Ok(()));
+            assert_eq!(allocate(ctx, 1, 2), Ok(()));
+            let before = grant_start(ctx);
+
+            // Freeing the first block would move the second block up, but the
+            // second block is entered, so the grant region is not compacted.
+            assert_eq!(enter_and_free_block(ctx, 1, 0), Ok(2));
+            assert_eq!(grant_start(ctx), before);
+            assert_eq!(read(ctx, 0), Err(ErrorCode::INVAL));
+            assert_eq!(read(ctx, 1), Ok(2));
+
+            // The memory of the first block is reclaimed with the next
+            // compaction.
+            assert_eq!(free(ctx, 1), Ok(()));
+            assert!(grant_start(ctx).wrapping_sub(before) as usize >= 2 * BLOCK_SIZE);
+        })
+        .ram_size(8192)])
+        .unwrap();
+    board.run_until_idle(board.round_robin());
+
+    let process = board.process("entered").unwrap();
+    assert_eq!(process.get_completion_code(), Some(Some(0)));
+}
@@ -0,0 +1,204 @@
+//! Runs simulated applications against the kernel and some capsules.
+
+use capsules::alarm::AlarmDriver;
+use host::{App, HostBoard};
+use kernel::capabilities;
+use kernel::create_capability;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::process::State;
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver, SyscallReturn};
+use kernel::{ErrorCode, ProcessId};
+
+const COUNTER_DRIVER_NUM: usize = 0x90000;
+const ALARM_DRIVER_NUM: usize = capsules::alarm::DRIVER_NUM;
+
+#[derive(Default)]
+struct Count {
+    value: usize,
+}
+
+/// Counts the commands of every process and copies the count into the
+/// buffer the process allowed.
+struct Counter {
+    apps: Grant<Count, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<1>>,
+}
+
+impl SyscallDriver for Counter {
+    fn command(
+        &self,
+        command_num: usize,
+        _: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        self.apps
+            .enter(processid, |count, kernel_data| match command_num {
+                0 => CommandReturn::success(),
+                1 => {
+                    count.value += 1;
+                    CommandReturn::success_u32(count.value as u32)
+                }
+                2 => kernel_data
+                    .get_readwrite_processbuffer(0)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|buffer| {
+                            buffer.iter().for_each(|byte| byte.set(count.value as u8))
+                        })
+                    })
+                    .map_or_else(
+                        |err| CommandReturn::failure(err.into()),
+                        |()| CommandReturn::success(),
+                    ),
+                _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+fn counter_board() -> HostBoard {
+    let board = HostBoard::new();
+    let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+    let counter = Box::leak(Box::new(Counter {
+        apps: board.kernel().create_grant(COUNTER_DRIVER_NUM, &grant_cap),
+    }));
+    board.add_driver(COUNTER_DRIVER_NUM, counter);
+    board
+}
+
+fn count(n: usize) -> App {
+    App::new(&format!("count{}", n), move |ctx| {
+        let mut value = 0;
+        for _ in 0..n {
+            match ctx.command(COUNTER_DRIVER_NUM, 1, 0, 0) {
+                SyscallReturn::SuccessU32(count) => value = count,
+                _ => ctx.exit(0),
+            }
+        }
+        ctx.exit(value as usize);
+    })
+}
+
+#[test]
+fn processes_exit_with_completion_code() {
+    let board = counter_board();
+    board.load_apps(&[count(3), count(5)]).unwrap();
+    board.run_until_idle(board.round_robin());
+
+    for (name, code) in [("count3", 3), ("count5", 5)] {
+        let process = board.process(name).unwrap();
+        assert_eq!(process.get_state(), State::Terminated);
+        assert_eq!(process.get_completion_code(), Some(Some(code)));
+    }
+}
+
+#[test]
+fn missing_driver_returns_nodevice() {
+    let board = HostBoard::new();
+    board
+        .load_apps(&[App::new("nodevice", |ctx| {
+            if let SyscallReturn::Failure(ErrorCode::NODEVICE) = ctx.command(0x90001, 0, 0, 0) {
+                ctx.exit(1);
+            }
+        })])
+        .unwrap();
+    board.run_until_idle(board.round_robin());
+
+    let process = board.process("nodevice").unwrap();
+    assert_eq!(process.get_completion_code(), Some(Some(1)));
+}
+
+#[test]
+fn driver_writes_allowed_buffer() {
+    let board = counter_board();
+    board
+        .load_apps(&[App::new("allow", |ctx| {
+            let buffer = ctx.sbrk(8).unwrap();
+            ctx.command(COUNTER_DRIVER_NUM, 1, 0, 0);
+            ctx.command(COUNTER_DRIVER_NUM, 1, 0, 0);
+            match ctx.allow_readwrite(COUNTER_DRIVER_NUM, 0, buffer, 8) {
+                SyscallReturn::AllowReadWriteSuccess(_, _) => {}
+                _ => ctx.exit(1),
+            }
+            match ctx.command(COUNTER_DRIVER_NUM, 2, 0, 0) {
+                SyscallReturn::Success => {}
+                _ => ctx.exit(2),
+            }
+            let mut data = [0; 8];
+            ctx.read(buffer, &mut data);
+            assert_eq!(data, [2; 8]);
+        })])
+        .unwrap();
+    board.run_until_idle(board.round_robin());
+
+    let process = board.process("allow").unwrap();
+    assert_eq!(process.get_completion_code(), Some(Some(0)));
+}
+
+#[test]
+fn allow_outside_process_memory_fails() {
+    let board = counter_board();
+    board
+        .load_apps(&[App::new("badallow", |ctx| {
+            let buffer = [0u8; 8];
+            match ctx.allow_readwrite(COUNTER_DRIVER_NUM, 0, buffer.as_ptr() as *mut u8, 8) {
+                SyscallReturn::AllowReadWriteFailure(ErrorCode::INVAL, _, _) => ctx.exit(1),
+                _ => ctx.exit(2),
+            }
+        })])
+        .unwrap();
+    board.run_until_idle(board.round_robin());
+
+    let process = board.process("badallow").unwrap();
+    assert_eq!(process.get_completion_code(), Some(Some(1)));
+}
+
+#[test]
+fn panicking_process_faults() {
+    let board = HostBoard::new();
+    board
+        .load_apps(&[App::new("panic", |ctx| {
+            ctx.yield_no_wait();
+            panic!("simulated fault");
+        })])
+        .unwrap();
+    board.run_until_idle(board.round_robin());
+
+    assert_eq!(board.process("panic").unwrap().get_state(), State::Faulted);
+}
+
+#[test]
+fn alarm_upcall_wakes_process() {
+    let board = HostBoard::new();
+    let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+    let alarm = board.chip().alarm();
+    let alarm_driver = Box::leak(Box::new(AlarmDriver::new(
+        alarm,
+        board.kernel().create_grant(ALARM_DRIVER_NUM, &grant_cap),
+    )));
+    kernel::hil::time::Alarm::set_alarm_client(alarm, alarm_driver);
+    board.add_driver(ALARM_DRIVER_NUM, alarm_driver);
+
+    board
+        .load_apps(&[App::new("sleep", |ctx| {
+            ctx.subscribe(ALARM_DRIVER_NUM, 0, 42, 7);
+            ctx.command(ALARM_DRIVER_NUM, 5, 250, 0);
+            let upcall = ctx.yield_wait();
+            assert_eq!(upcall.id, 42);
+            assert_eq!(upcall.appdata, 7);
+            match ctx.command(ALARM_DRIVER_NUM, 2, 0, 0) {
+                SyscallReturn::SuccessU32(now) => ctx.exit(now as usize),
+                _ => ctx.exit(0),
+            }
+        })])
+        .unwrap();
+    board.run_until_idle(board.round_robin());
+
+    let process = board.process("sleep").unwrap();
+    assert_eq!(process.get_state(), State::Terminated);
+    assert_eq!(process.get_completion_code(), Some(Some(250)));
+}
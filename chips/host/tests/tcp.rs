@@ -0,0 +1,463 @@
+//! Drives the TCP state machine through `MuxTcp`, with a fake IPv6 layer
+//! standing in for the network and the host alarm for its timer.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use capsules::net::ipv6::ipv6_recv::IP6RecvClient;
+use capsules::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use capsules::net::ipv6::{IP6Header, TransportHeader};
+use capsules::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use capsules::net::tcp::tcp_mux::MuxTcp;
+use capsules::net::tcp::tcp_socket::{TCPClient, TCPSocket, TcpState};
+use capsules::net::tcp::{tcp_flags, TCPHeader, TCP_HDR_LEN};
+use host::alarm::HostAlarm;
+use kernel::capabilities;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+const LOCAL_PORT: u16 = 1000;
+const PEER_PORT: u16 = 2000;
+const PEER_ADDR: IPAddr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+/// Initial sequence number used by the peer.
+const PEER_ISS: u32 = 5000;
+/// Timer ticks until the first retransmission of a segment.
+const INITIAL_RTO: usize = 4;
+
+/// A segment sent by the socket.
+#[derive(Debug)]
+struct Segment {
+    dst: IPAddr,
+    header: TCPHeader,
+    data: Vec<u8>,
+}
+
+/// Records every segment passed to it. The segment is "on the wire" until
+/// `Harness::sent()` completes the transmission.
+struct FakeIp {
+    sent: RefCell<VecDeque<Segment>>,
+    client: OptionalCell<&'static dyn IP6SendClient>,
+}
+
+impl IP6Sender<'static> for FakeIp {
+    fn set_client(&self, client: &'static dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, _src_addr: IPAddr) {}
+
+    fn set_gateway(&self, _gateway: MacAddress) {}
+
+    fn set_header(&mut self, _ip6_header: IP6Header) {}
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        let header = match transport_header {
+            TransportHeader::TCP(header) => header,
+            _ => panic!("not a TCP segment"),
+        };
+        self.sent.borrow_mut().push_back(Segment {
+            dst,
+            header,
+            data: payload[..].to_vec(),
+        });
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Connected(Result<(), ErrorCode>),
+    DataAvailable(usize),
+    SendDone(usize),
+    RemoteClosed,
+    Closed(Result<(), ErrorCode>),
+}
+
+#[derive(Default)]
+struct Events(RefCell<Vec<Event>>);
+
+impl TCPClient for Events {
+    fn connected(&self, _socket_id: usize, result: Result<(), ErrorCode>) {
+        self.0.borrow_mut().push(Event::Connected(result));
+    }
+
+    fn data_available(&self, _socket_id: usize, len: usize) {
+        self.0.borrow_mut().push(Event::DataAvailable(len));
+    }
+
+    fn send_done(&self, _socket_id: usize, acked: usize) {
+        self.0.borrow_mut().push(Event::SendDone(acked));
+    }
+
+    fn remote_closed(&self, _socket_id: usize) {
+        self.0.borrow_mut().push(Event::RemoteClosed);
+    }
+
+    fn closed(&self, _socket_id: usize, result: Result<(), ErrorCode>) {
+        self.0.borrow_mut().push(Event::Closed(result));
+    }
+}
+
+struct Harness {
+    alarm: &'static HostAlarm<'static>,
+    ip: &'static FakeIp,
+    mux: &'static MuxTcp<'static, HostAlarm<'static>>,
+    socket: &'static TCPSocket<'static, HostAlarm<'static>>,
+    events: &'static Events,
+}
+
+fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+impl Harness {
+    fn new() -> Harness {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let net_cap = leak(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+        let alarm = leak(HostAlarm::new());
+        let ip = leak(FakeIp {
+            sent: RefCell::new(VecDeque::new()),
+            client: OptionalCell::empty(),
+        });
+        let mux = leak(MuxTcp::new(
+            ip,
+            alarm,
+            Box::leak(Box::new([0; 64])),
+            net_cap,
+        ));
+        alarm.set_alarm_client(mux);
+        ip.set_client(mux);
+        let socket = leak(TCPSocket::new(
+            0,
+            mux,
+            Box::leak(Box::new([0; 32])),
+            Box::leak(Box::new([0; 32])),
+        ));
+        mux.add_socket(socket);
+        let events = leak(Events::default());
+        socket.set_client(events);
+        Harness {
+            alarm,
+            ip,
+            mux,
+            socket,
+            events,
+        }
+    }
+
+    /// Completes the transmission of every segment the socket sends and
+    /// returns them.
+    fn sent(&self) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        loop {
+            let segment = self.ip.sent.borrow_mut().pop_front();
+            match segment {
+                Some(segment) => {
+                    segments.push(segment);
+                    self.ip.client.map(|client| client.send_done(Ok(())));
+                }
+                None => return segments,
+            }
+        }
+    }
+
+    /// Returns the single segment the socket sent.
+    fn sent_one(&self) -> Segment {
+        let mut segments = self.sent();
+        assert_eq!(segments.len(), 1, "{:?}", segments);
+        segments.pop().unwrap()
+    }
+
+    /// Delivers a segment from the peer to the socket.
+    fn deliver(&self, seq: u32, ack: u32, flags: u8, data: &[u8]) {
+        let mut header = TCPHeader::new();
+        header.set_src_port(PEER_PORT);
+        header.set_dst_port(LOCAL_PORT);
+        header.set_seq_num(seq);
+        header.set_ack_num(ack);
+        header.set_flags(flags);
+        header.set_window(1024);
+        let mut payload = vec![0; TCP_HDR_LEN];
+        header.encode(&mut payload, 0).done().unwrap();
+        payload.extend_from_slice(data);
+
+        let mut ip_header = IP6Header::new();
+        ip_header.src_addr = PEER_ADDR;
+        ip_header.set_next_header(ip6_nh::TCP);
+        self.mux.receive(ip_header, &payload);
+    }
+
+    /// Fires the TCP timer once. Returns false if it was not running.
+    fn tick(&self) -> bool {
+        if !self.alarm.advance_to_alarm() {
+            return false;
+        }
+        self.alarm.handle_interrupt();
+        true
+    }
+
+    /// Fires the TCP timer until the retransmission timeout of a segment
+    /// sent for the first time expires.
+    fn expire_rto(&self) {
+        for _ in 0..INITIAL_RTO {
+            assert!(self.tick());
+        }
+    }
+
+    fn take_events(&self) -> Vec<Event> {
+        self.events.0.replace(Vec::new())
+    }
+
+    /// Opens a connection actively and returns the socket's initial
+    /// sequence number.
+    fn establish(&self) -> u32 {
+        self.socket
+            .connect(LOCAL_PORT, PEER_ADDR, PEER_PORT)
+            .unwrap();
+        let iss = self.sent_one().header.get_seq_num();
+        self.deliver(
+            PEER_ISS,
+            iss.wrapping_add(1),
+            tcp_flags::SYN | tcp_flags::ACK,
+            &[],
+        );
+        self.sent();
+        assert_eq!(self.take_events(), [Event::Connected(Ok(()))]);
+        iss
+    }
+}
+
+#[test]
+fn active_open() {
+    let h = Harness::new();
+    h.socket.connect(LOCAL_PORT, PEER_ADDR, PEER_PORT).unwrap();
+    assert_eq!(h.socket.get_state(), TcpState::SynSent);
+
+    let syn = h.sent_one();
+    assert_eq!(syn.dst, PEER_ADDR);
+    assert_eq!(syn.header.get_src_port(), LOCAL_PORT);
+    assert_eq!(syn.header.get_dst_port(), PEER_PORT);
+    assert_eq!(syn.header.get_flags(), tcp_flags::SYN);
+    let iss = syn.header.get_seq_num();
+
+    h.deliver(
+        PEER_ISS,
+        iss.wrapping_add(1),
+        tcp_flags::SYN | tcp_flags::ACK,
+        &[],
+    );
+    assert_eq!(h.socket.get_state(), TcpState::Established);
+    assert_eq!(h.take_events(), [Event::Connected(Ok(()))]);
+
+    let ack = h.sent_one();
+    assert_eq!(ack.header.get_flags(), tcp_flags::ACK);
+    assert_eq!(ack.header.get_seq_num(), iss.wrapping_add(1));
+    assert_eq!(ack.header.get_ack_num(), PEER_ISS + 1);
+}
+
+#[test]
+fn active_open_refused() {
+    let h = Harness::new();
+    h.socket.connect(LOCAL_PORT, PEER_ADDR, PEER_PORT).unwrap();
+    let iss = h.sent_one().header.get_seq_num();
+
+    h.deliver(0, iss.wrapping_add(1), tcp_flags::RST | tcp_flags::ACK, &[]);
+    assert_eq!(h.socket.get_state(), TcpState::Closed);
+    assert_eq!(h.take_events(), [Event::Connected(Err(ErrorCode::FAIL))]);
+}
+
+#[test]
+fn passive_open() {
+    let h = Harness::new();
+    h.socket.listen(LOCAL_PORT).unwrap();
+    assert!(h.sent().is_empty());
+
+    h.deliver(PEER_ISS, 0, tcp_flags::SYN, &[]);
+    assert_eq!(h.socket.get_state(), TcpState::SynReceived);
+    let syn_ack = h.sent_one();
+    assert_eq!(syn_ack.header.get_flags(), tcp_flags::SYN | tcp_flags::ACK);
+    assert_eq!(syn_ack.header.get_ack_num(), PEER_ISS + 1);
+    let iss = syn_ack.header.get_seq_num();
+
+    // The SYN-ACK is retransmitted if the peer does not answer.
+    h.expire_rto();
+    assert_eq!(h.sent_one().header.get_seq_num(), iss);
+
+    h.deliver(PEER_ISS + 1, iss.wrapping_add(1), tcp_flags::ACK, &[]);
+    assert_eq!(h.socket.get_state(), TcpState::Established);
+    assert_eq!(h.socket.get_remote(), (PEER_ADDR, PEER_PORT));
+    assert_eq!(h.take_events(), [Event::Connected(Ok(()))]);
+}
+
+#[test]
+fn receive_data() {
+    let h = Harness::new();
+    let iss = h.establish();
+
+    h.deliver(PEER_ISS + 1, iss.wrapping_add(1), tcp_flags::ACK, b"ping");
+    assert_eq!(h.take_events(), [Event::DataAvailable(4)]);
+    let ack = h.sent_one();
+    assert_eq!(ack.header.get_ack_num(), PEER_ISS + 5);
+
+    let mut received = Vec::new();
+    h.socket.receive(|data| {
+        received.extend_from_slice(data);
+        data.len()
+    });
+    assert_eq!(received, b"ping");
+}
+
+#[test]
+fn retransmit_until_acknowledged() {
+    let h = Harness::new();
+    let iss = h.establish();
+
+    assert_eq!(h.socket.send(b"hello"), Ok(5));
+    let segment = h.sent_one();
+    assert_eq!(segment.header.get_seq_num(), iss.wrapping_add(1));
+    assert_eq!(segment.data, b"hello");
+
+    h.expire_rto();
+    let retransmission = h.sent_one();
+    assert_eq!(retransmission.header.get_seq_num(), iss.wrapping_add(1));
+    assert_eq!(retransmission.data, b"hello");
+
+    // A partial acknowledgment only frees the acknowledged bytes.
+    h.deliver(PEER_ISS + 1, iss.wrapping_add(3), tcp_flags::ACK, &[]);
+    assert_eq!(h.take_events(), [Event::SendDone(2)]);
+    h.expire_rto();
+    assert_eq!(h.sent_one().data, b"llo");
+
+    h.deliver(PEER_ISS + 1, iss.wrapping_add(6), tcp_flags::ACK, &[]);
+    assert_eq!(h.take_events(), [Event::SendDone(3)]);
+    // Nothing is outstanding, so the timer only fires once more to stop.
+    h.tick();
+    assert!(!h.tick());
+    assert!(h.sent().is_empty());
+}
+
+#[test]
+fn acknowledgment_for_unsent_data_is_ignored() {
+    let h = Harness::new();
+    let iss = h.establish();
+
+    h.socket.send(b"hello").unwrap();
+    h.sent();
+    h.deliver(PEER_ISS + 1, iss.wrapping_add(100), tcp_flags::ACK, &[]);
+    assert!(h.take_events().is_empty());
+    // The peer is reminded which data it can acknowledge.
+    assert_eq!(h.sent_one().header.get_ack_num(), PEER_ISS + 1);
+}
+
+#[test]
+fn acknowledgment_after_timeout_is_accepted() {
+    let h = Harness::new();
+    let iss = h.establish();
+
+    h.socket.send(b"hello").unwrap();
+    // Keep the segment on the wire, so the timeout can't retransmit it.
+    assert_eq!(h.ip.sent.borrow().len(), 1);
+    h.expire_rto();
+
+    // The acknowledgment for the original transmission arrives after the
+    // retransmission timer rewound the send sequence number.
+    h.deliver(PEER_ISS + 1, iss.wrapping_add(6), tcp_flags::ACK, &[]);
+    assert_eq!(h.take_events(), [Event::SendDone(5)]);
+
+    // Only the original segment was sent; the acknowledged data is not
+    // retransmitted.
+    let segments = h.sent();
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].data, b"hello");
+}
+
+#[test]
+fn active_close_through_time_wait() {
+    let h = Harness::new();
+    let iss = h.establish();
+
+    h.socket.close().unwrap();
+    let fin = h.sent_one();
+    assert_eq!(fin.header.get_flags(), tcp_flags::FIN | tcp_flags::ACK);
+    assert_eq!(fin.header.get_seq_num(), iss.wrapping_add(1));
+    assert_eq!(h.socket.get_state(), TcpState::FinWait1);
+
+    h.deliver(PEER_ISS + 1, iss.wrapping_add(2), tcp_flags::ACK, &[]);
+    assert_eq!(h.socket.get_state(), TcpState::FinWait2);
+
+    h.deliver(
+        PEER_ISS + 1,
+        iss.wrapping_add(2),
+        tcp_flags::FIN | tcp_flags::ACK,
+        &[],
+    );
+    assert_eq!(h.socket.get_state(), TcpState::TimeWait);
+    let ack = h.sent_one();
+    assert_eq!(ack.header.get_seq_num(), iss.wrapping_add(2));
+    assert_eq!(ack.header.get_ack_num(), PEER_ISS + 2);
+    assert!(h.take_events().is_empty());
+
+    let mut ticks = 0;
+    while h.socket.get_state() == TcpState::TimeWait {
+        assert!(h.tick());
+        ticks += 1;
+        assert!(ticks < 100);
+    }
+    assert_eq!(h.socket.get_state(), TcpState::Closed);
+    assert_eq!(h.take_events(), [Event::Closed(Ok(()))]);
+}
+
+#[test]
+fn fin_acknowledged_after_timeout() {
+    let h = Harness::new();
+    let iss = h.establish();
+
+    h.socket.close().unwrap();
+    h.expire_rto();
+    h.deliver(PEER_ISS + 1, iss.wrapping_add(2), tcp_flags::ACK, &[]);
+    assert_eq!(h.socket.get_state(), TcpState::FinWait2);
+
+    let segments = h.sent();
+    assert_eq!(segments.len(), 1);
+    assert!(segments[0].header.has_flags(tcp_flags::FIN));
+}
+
+#[test]
+fn passive_close() {
+    let h = Harness::new();
+    let iss = h.establish();
+
+    h.deliver(
+        PEER_ISS + 1,
+        iss.wrapping_add(1),
+        tcp_flags::FIN | tcp_flags::ACK,
+        &[],
+    );
+    assert_eq!(h.socket.get_state(), TcpState::CloseWait);
+    assert_eq!(h.take_events(), [Event::RemoteClosed]);
+    assert_eq!(h.sent_one().header.get_ack_num(), PEER_ISS + 2);
+
+    h.socket.close().unwrap();
+    assert_eq!(h.socket.get_state(), TcpState::LastAck);
+    assert!(h.sent_one().header.has_flags(tcp_flags::FIN));
+
+    h.deliver(PEER_ISS + 2, iss.wrapping_add(2), tcp_flags::ACK, &[]);
+    assert_eq!(h.socket.get_state(), TcpState::Closed);
+    assert_eq!(h.take_events(), [Event::Closed(Ok(()))]);
+}
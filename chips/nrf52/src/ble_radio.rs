@@ -32,11 +32,19 @@
 //! * Payload - 2 to 255 bytes
 //!
 //! * CRC - 3 bytes
+//!
+//! ### Connections
+//!
+//! For connection events the radio responds to a received data channel PDU
+//! in hardware: the END, DISABLED and READY shortcuts turn the radio around
+//! from receive to transmit, and `TIFS` delays the response by T_IFS. The
+//! response is written into a separate buffer while the transmitter ramps up.
 
 use core::cell::Cell;
 use core::convert::TryFrom;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::ble_connection;
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
@@ -530,12 +538,35 @@ register_bitfields! [u32,
 static mut PAYLOAD: [u8; nrf5x::constants::RADIO_PAYLOAD_LENGTH] =
     [0x00; nrf5x::constants::RADIO_PAYLOAD_LENGTH];
 
+/// Response to a received data channel PDU.
+static mut RESPONSE_PAYLOAD: [u8; nrf5x::constants::RADIO_PAYLOAD_LENGTH] =
+    [0x00; nrf5x::constants::RADIO_PAYLOAD_LENGTH];
+
+/// The operation the radio is performing.
+#[derive(Copy, Clone, PartialEq)]
+enum Operation {
+    /// Advertising or scanning through `BleAdvertisementDriver`.
+    Advertisement,
+    /// Transmitting a connectable advertisement.
+    ConnectableAdvertisement,
+    /// Listening for a request after a connectable advertisement.
+    AdvertisingListen,
+    /// Listening on a data channel.
+    DataListen,
+    /// Transmitting the response to a data channel PDU.
+    DataResponse,
+}
+
 pub struct Radio<'a> {
     registers: StaticRef<RadioRegisters>,
     tx_power: Cell<TxPower>,
     rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
     tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
+    connection_client: OptionalCell<&'a dyn ble_connection::ConnectionClient>,
     buffer: TakeCell<'static, [u8]>,
+    operation: Cell<Operation>,
+    access_address: Cell<u32>,
+    crc_init: Cell<u32>,
 }
 
 impl<'a> Radio<'a> {
@@ -545,7 +576,11 @@ impl<'a> Radio<'a> {
             tx_power: Cell::new(TxPower::ZerodBm),
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
+            connection_client: OptionalCell::empty(),
             buffer: TakeCell::empty(),
+            operation: Cell::new(Operation::Advertisement),
+            access_address: Cell::new(ble_connection::ADVERTISING_ACCESS_ADDRESS),
+            crc_init: Cell::new(nrf5x::constants::RADIO_CRCINIT_BLE),
         }
     }
 
@@ -595,14 +630,26 @@ impl<'a> Radio<'a> {
         }
     }
 
+    fn set_response_dma_ptr(&self) {
+        unsafe {
+            self.registers
+                .packetptr
+                .set(RESPONSE_PAYLOAD.as_ptr() as u32);
+        }
+    }
+
     #[inline(never)]
     pub fn handle_interrupt(&self) {
         self.disable_all_interrupts();
 
         if self.registers.event_ready.is_set(Event::READY) {
             self.registers.event_ready.write(Event::READY::CLEAR);
-            self.registers.event_end.write(Event::READY::CLEAR);
-            self.registers.task_start.write(Task::ENABLE::SET);
+            // Connection operations start the radio with the READY_START
+            // shortcut.
+            if self.operation.get() == Operation::Advertisement {
+                self.registers.event_end.write(Event::READY::CLEAR);
+                self.registers.task_start.write(Task::ENABLE::SET);
+            }
         }
 
         if self.registers.event_address.is_set(Event::READY) {
@@ -622,6 +669,12 @@ impl<'a> Radio<'a> {
                 Err(ErrorCode::FAIL)
             };
 
+            if self.operation.get() != Operation::Advertisement {
+                self.connection_event_end(result);
+                self.enable_interrupts();
+                return;
+            }
+
             match self.registers.state.get() {
                 nrf5x::constants::RADIO_STATE_TXRU
                 | nrf5x::constants::RADIO_STATE_TXIDLE
@@ -652,6 +705,52 @@ impl<'a> Radio<'a> {
         self.enable_interrupts();
     }
 
+    fn connection_event_end(&self, result: Result<(), ErrorCode>) {
+        // Length is: S0 (1 Byte) + Length (1 Byte) + Payload
+        let len = unsafe { PAYLOAD[1] as usize + 2 };
+        match self.operation.get() {
+            Operation::ConnectableAdvertisement => {
+                // The advertisement was sent and the shortcuts started the
+                // receiver, stop after the next packet.
+                self.registers
+                    .shorts
+                    .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+                self.operation.set(Operation::AdvertisingListen);
+            }
+            Operation::AdvertisingListen => {
+                self.radio_off();
+                self.operation.set(Operation::Advertisement);
+                self.connection_client
+                    .map(|client| unsafe { client.advertising_request(&PAYLOAD[..len], result) });
+            }
+            Operation::DataListen => {
+                // The shortcuts are already ramping up the transmitter, only
+                // the response is left to be written.
+                self.set_response_dma_ptr();
+                self.registers
+                    .shorts
+                    .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+                self.operation.set(Operation::DataResponse);
+                unsafe {
+                    let written = self.connection_client.map_or(0, |client| {
+                        client.data_received(&PAYLOAD[..len], result, &mut RESPONSE_PAYLOAD)
+                    });
+                    if written < 2 {
+                        // Respond with an empty PDU.
+                        RESPONSE_PAYLOAD[0] = 0x01;
+                        RESPONSE_PAYLOAD[1] = 0;
+                    }
+                }
+            }
+            Operation::DataResponse => {
+                self.radio_off();
+                self.operation.set(Operation::Advertisement);
+                self.connection_client.map(|client| client.response_sent());
+            }
+            Operation::Advertisement => (),
+        }
+    }
+
     pub fn enable_interrupts(&self) {
         self.registers.intenset.write(
             Interrupt::READY::SET
@@ -725,6 +824,34 @@ impl<'a> Radio<'a> {
         self.registers.base0.set(0x89bed600);
     }
 
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1.2 Access Address
+    // The most significant byte of the access address is the prefix
+    fn ble_set_connection_access_address(&self) {
+        let access_address = self.access_address.get();
+        self.registers.prefix0.set(access_address >> 24);
+        self.registers.base0.set(access_address << 8);
+        self.registers.crcinit.set(self.crc_init.get());
+    }
+
+    // Configure the shortcuts that turn the radio around to the opposite
+    // direction T_IFS after the end of a packet
+    fn ble_set_turnaround(&self, to_rx: bool) {
+        self.registers.tifs.set(ble_connection::T_IFS_US);
+        if to_rx {
+            self.registers.shorts.write(
+                Shortcut::READY_START::SET
+                    + Shortcut::END_DISABLE::SET
+                    + Shortcut::DISABLED_RXEN::SET,
+            );
+        } else {
+            self.registers.shorts.write(
+                Shortcut::READY_START::SET
+                    + Shortcut::END_DISABLE::SET
+                    + Shortcut::DISABLED_TXEN::SET,
+            );
+        }
+    }
+
     // Packet configuration
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1 Packet Format
     //
@@ -789,6 +916,7 @@ impl<'a> Radio<'a> {
 
 impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
     fn transmit_advertisement(&self, buf: &'static mut [u8], _len: usize, channel: RadioChannel) {
+        self.operation.set(Operation::Advertisement);
         let res = self.replace_radio_buffer(buf);
         self.buffer.replace(res);
         self.ble_initialize(channel);
@@ -797,6 +925,7 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
     }
 
     fn receive_advertisement(&self, channel: RadioChannel) {
+        self.operation.set(Operation::Advertisement);
         self.ble_initialize(channel);
         self.rx();
         self.enable_interrupts();
@@ -811,6 +940,46 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
     }
 }
 
+impl<'a> ble_connection::BleConnectionDriver<'a> for Radio<'a> {
+    fn set_connection_client(&self, client: &'a dyn ble_connection::ConnectionClient) {
+        self.connection_client.set(client);
+    }
+
+    fn advertise_and_listen(&self, pdu: &[u8], channel: RadioChannel) {
+        unsafe {
+            for (i, c) in pdu.iter().take(PAYLOAD.len()).enumerate() {
+                PAYLOAD[i] = *c;
+            }
+        }
+        self.ble_initialize(channel);
+        self.ble_set_turnaround(true);
+        self.operation.set(Operation::ConnectableAdvertisement);
+        self.tx();
+        self.enable_interrupts();
+    }
+
+    fn set_access_address(&self, access_address: u32, crc_init: u32) {
+        self.access_address.set(access_address);
+        self.crc_init.set(crc_init);
+    }
+
+    fn listen_and_respond(&self, channel: RadioChannel) {
+        self.ble_initialize(channel);
+        self.ble_set_connection_access_address();
+        self.ble_set_turnaround(false);
+        self.operation.set(Operation::DataListen);
+        self.rx();
+        self.enable_interrupts();
+    }
+
+    fn stop(&self) {
+        self.disable_all_interrupts();
+        self.registers.shorts.set(0);
+        self.radio_off();
+        self.operation.set(Operation::Advertisement);
+    }
+}
+
 impl ble_advertising::BleConfig for Radio<'_> {
     // The BLE Advertising Driver validates that the `tx_power` is between -20 to 10 dBm but then
     // underlying chip must validate if the current `tx_power` is supported as well
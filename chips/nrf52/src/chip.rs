@@ -4,6 +4,7 @@ use cortexm4::{self, nvic};
 use kernel::deferred_call;
 use kernel::hil::time::Alarm;
 use kernel::platform::chip::InterruptService;
+use kernel::platform::power::SleepState;
 
 pub struct NRF52<'a, I: InterruptService<DeferredCallTask> + 'a> {
     mpu: cortexm4::mpu::MPU,
@@ -194,6 +195,15 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> kernel::platform::chip::Chi
         }
     }
 
+    /// The nRF52 stops its clocks on its own in System ON, so `Sleep` and
+    /// `DeepSleep` both wait for an interrupt, and `Off` enters System OFF.
+    fn sleep_in(&self, state: SleepState) {
+        match state {
+            SleepState::Sleep | SleepState::DeepSleep => self.sleep(),
+            SleepState::Off => crate::power::system_off(),
+        }
+    }
+
     unsafe fn atomic<F, R>(&self, f: F) -> R
     where
         F: FnOnce() -> R,
@@ -231,6 +231,19 @@ register_bitfields! [u32,
     ]
 ];
 
+/// Enters System OFF. The chip only wakes up from a reset, for example from a
+/// GPIO pin with SENSE configured, and then boots again.
+pub fn system_off() -> ! {
+    POWER_BASE.systemoff.write(Task::ENABLE::SET);
+    // With a debugger attached the chip emulates System OFF and keeps running
+    // code, so wait here instead.
+    loop {
+        unsafe {
+            cortexm4::support::wfi();
+        }
+    }
+}
+
 /// The USB state machine needs to be notified of power events (USB detected, USB
 /// removed, USB power ready) in order to be initialized and shut down properly.
 /// These events come from the power management registers of this module; that's
@@ -1755,9 +1755,8 @@ impl<'a> Usbd<'a> {
         self.client.map(|client| {
             match client.ctrl_in(endpoint) {
                 hil::usb::CtrlInResult::Packet(size, last) => {
-                    if size == 0 {
-                        internal_err!("Empty ctrl packet?");
-                    }
+                    // A size of zero sends a zero length packet, which ends a
+                    // transfer that is a multiple of the packet size.
                     self.start_dma_in(endpoint, size);
                     if last {
                         self.descriptors[endpoint]
@@ -9,6 +9,7 @@ use enum_primitive::cast::FromPrimitive;
 use enum_primitive::enum_from_primitive;
 use kernel::debug;
 use kernel::hil;
+use kernel::platform::power::{SleepState, WakeSource};
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, ReadWrite};
@@ -514,6 +515,39 @@ impl<'a> hil::gpio::Interrupt<'a> for GPIOPin<'a> {
     }
 }
 
+/// A pin with its interrupt enabled wakes the chip from System OFF with the
+/// SENSE mechanism of the GPIO, since GPIOTE is stopped in System OFF.
+impl WakeSource for GPIOPin<'_> {
+    fn is_armed(&self) -> bool {
+        self.find_channel(self.pin).map_or(false, |channel| {
+            !self.gpiote_registers.config[channel].matches_all(Config::MODE::Disabled)
+        })
+    }
+
+    fn deepest_wake_state(&self) -> SleepState {
+        SleepState::Off
+    }
+
+    fn prepare_wake(&self, state: SleepState) {
+        if state != SleepState::Off {
+            return;
+        }
+        if let Ok(channel) = self.find_channel(self.pin) {
+            let high = match self.gpiote_registers.config[channel].read_as_enum(Config::POLARITY) {
+                Some(Config::POLARITY::Value::LoToHi) => true,
+                Some(Config::POLARITY::Value::HiToLo) => false,
+                // Wake on the next change of the level.
+                _ => !hil::gpio::Input::read(self),
+            };
+            self.gpio_registers.pin_cnf[self.pin as usize].modify(if high {
+                PinConfig::SENSE::High
+            } else {
+                PinConfig::SENSE::Low
+            });
+        }
+    }
+}
+
 impl GPIOPin<'_> {
     /// Allocate a GPIOTE channel
     /// If the channel couldn't be allocated return error instead
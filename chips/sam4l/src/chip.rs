@@ -7,6 +7,7 @@ use core::fmt::Write;
 use cortexm4;
 use kernel::deferred_call;
 use kernel::platform::chip::{Chip, InterruptService};
+use kernel::platform::power::SleepState;
 
 pub struct Sam4l<I: InterruptService<Task> + 'static> {
     mpu: cortexm4::mpu::MPU,
@@ -274,7 +275,13 @@ impl<I: InterruptService<Task> + 'static> Chip for Sam4l<I> {
     }
 
     fn sleep(&self) {
-        if pm::deep_sleep_ready() {
+        self.sleep_in(SleepState::DeepSleep);
+    }
+
+    /// The SAM4L enters deep sleep for `DeepSleep` and `Off` when no
+    /// peripheral that needs its clocks is enabled, and sleep otherwise.
+    fn sleep_in(&self, state: SleepState) {
+        if state >= SleepState::DeepSleep && pm::deep_sleep_ready() {
             unsafe {
                 cortexm4::scb::set_sleepdeep();
             }
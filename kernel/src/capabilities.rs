@@ -90,3 +90,12 @@ pub unsafe trait CreatePortTableCapability {}
 /// of the networking stack. A capsule would never hold this capability although
 /// it may hold capabilities created via this capability.
 pub unsafe trait NetworkCapabilityCreationCapability {}
+
+/// The `KernelStorageCapability` allows the holder to create
+/// `StoragePermissions` that are not tied to a process. This lets code in the
+/// kernel, rather than an application, read and write persistent storage.
+pub unsafe trait KernelStorageCapability {}
+
+/// The `ChipResetCapability` allows the holder to reset the whole chip, for
+/// example by giving a capsule a function that resets the chip.
+pub unsafe trait ChipResetCapability {}
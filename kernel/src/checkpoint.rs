@@ -0,0 +1,445 @@
+//! Checkpoints of processes that survive a reboot.
+//!
+//! A checkpoint is a snapshot of a running process: its stored registers and
+//! the memory it can access. A process that is loaded with
+//! `process::load_processes_and_restore()` resumes from its checkpoint
+//! instead of starting from its init function, so the state of the process
+//! survives a reboot or a brown-out.
+//!
+//! The `checkpoint` capsule writes checkpoints to flash, when a process asks
+//! for one or when the kernel requests one. Each process has a slot in a
+//! `CheckpointRegion`, selected by its index in the processes array. A
+//! checkpoint is only restored into the same process running on the same
+//! kernel: the hash of its TBF object, its location in flash and RAM and the
+//! identifier of the kernel build given to the `CheckpointRegion` must all be
+//! unchanged. If any of them differ, or the checkpoint is corrupted, the
+//! process starts from its init function. Boards should derive the
+//! identifier of the kernel build from the kernel image, for example with
+//! `crc32()` over the text section of the kernel.
+//!
+//! The grant region is not part of a checkpoint, as the data capsules keep
+//! in it is only meaningful to the kernel that stored it. A restored process
+//! starts with no grants, so its subscriptions and allowed buffers are gone
+//! and upcalls that were pending are dropped. To let the process find out,
+//! every checkpoint names a resume function in the application. The kernel
+//! queues a call to it when it restores the process, which runs the next
+//! time the process yields, before any other upcall.
+//!
+//! Checkpoint Format
+//! -----------------
+//!
+//! All values are little endian. A checkpoint starts with a 60 byte header of
+//! 32-bit words. Offsets of addresses are relative to the start of the
+//! memory of the process.
+//!
+//! | Offset | Field                                                      |
+//! |--------|------------------------------------------------------------|
+//! | 0      | Magic, the ASCII string `TKCP`                             |
+//! | 4      | Format version, currently 3                                |
+//! | 8      | Length of the checkpoint, including the header             |
+//! | 12     | CRC-32 of the checkpoint after the header                  |
+//! | 16     | FNV-1a hash of the TBF header and the application binary   |
+//! | 20     | Start address of the process in flash                      |
+//! | 24     | Start address of the memory of the process                 |
+//! | 28     | Length of the memory of the process                        |
+//! | 32     | Offset of the app break                                    |
+//! | 36     | Offset of the allow high water mark                        |
+//! | 40     | Length of the stored registers                             |
+//! | 44     | State, 0 if the process was running and 1 if it yielded    |
+//! | 48     | Identifier of the kernel build                             |
+//! | 52     | Address of the resume function                             |
+//! | 56     | Argument passed to the resume function                     |
+//!
+//! The header is followed by these sections, each padded with zeros to a
+//! multiple of four bytes:
+//!
+//! 1. The stored registers as returned by `Process::get_stored_state()`.
+//! 2. The memory of the process from its start up to the app break.
+
+use crate::process::ProcessId;
+
+/// Magic bytes at the start of every checkpoint.
+pub const MAGIC: [u8; 4] = *b"TKCP";
+
+/// Version of the checkpoint format.
+pub const VERSION: u32 = 3;
+
+/// Length of the header of a checkpoint.
+pub const HEADER_LENGTH: usize = 60;
+
+/// Longest stored registers a checkpoint can hold.
+pub(crate) const CONTEXT_MAX_LENGTH: usize = 256;
+
+/// Flash region, mapped into memory, that holds one checkpoint slot per
+/// process.
+#[derive(Clone, Copy)]
+pub struct CheckpointRegion {
+    flash: &'static [u8],
+    slot_length: usize,
+    kernel_id: u32,
+}
+
+impl CheckpointRegion {
+    /// Creates a region of slots of `slot_length` bytes in `flash`. The slot
+    /// length must be a multiple of the flash page size. `kernel_id`
+    /// identifies the kernel build; only checkpoints stored with the same
+    /// identifier are restored.
+    pub const fn new(flash: &'static [u8], slot_length: usize, kernel_id: u32) -> CheckpointRegion {
+        CheckpointRegion {
+            flash,
+            slot_length,
+            kernel_id,
+        }
+    }
+
+    /// Length of each slot in bytes.
+    pub fn slot_length(&self) -> usize {
+        self.slot_length
+    }
+
+    /// Identifier of the kernel build stored in every checkpoint.
+    pub fn kernel_id(&self) -> u32 {
+        self.kernel_id
+    }
+
+    /// The contents of the slot of the process, or `None` if the region has
+    /// no slot for it.
+    pub fn slot_contents(&self, processid: ProcessId) -> Option<&'static [u8]> {
+        self.slot(processid.index)
+    }
+
+    /// Offset in the region of the slot of the process, or `None` if the
+    /// region has no slot for it.
+    pub fn slot_offset(&self, processid: ProcessId) -> Option<usize> {
+        self.slot_range(processid.index).map(|(start, _)| start)
+    }
+
+    /// The slot of the process with the index `index` in the processes array.
+    pub(crate) fn slot(&self, index: usize) -> Option<&'static [u8]> {
+        self.slot_range(index)
+            .and_then(|(start, end)| self.flash.get(start..end))
+    }
+
+    fn slot_range(&self, index: usize) -> Option<(usize, usize)> {
+        let start = index.checked_mul(self.slot_length)?;
+        let end = start.checked_add(self.slot_length)?;
+        if self.slot_length == 0 || end > self.flash.len() {
+            None
+        } else {
+            Some((start, end))
+        }
+    }
+}
+
+/// The fields of the header of a checkpoint, other than its magic, version
+/// and CRC.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CheckpointHeader {
+    pub(crate) length: usize,
+    pub(crate) tbf_hash: u32,
+    pub(crate) flash_start: usize,
+    pub(crate) memory_start: usize,
+    pub(crate) memory_length: usize,
+    pub(crate) app_break: usize,
+    pub(crate) allow_high_water_mark: usize,
+    pub(crate) context_length: usize,
+    pub(crate) yielded: bool,
+    pub(crate) kernel_id: u32,
+    pub(crate) resume_function: usize,
+    pub(crate) resume_argument: usize,
+}
+
+impl CheckpointHeader {
+    /// Length of a checkpoint with sections of the given lengths.
+    pub(crate) fn checkpoint_length(sections: &[usize]) -> usize {
+        sections
+            .iter()
+            .fold(HEADER_LENGTH, |length, section| length + padded(*section))
+    }
+
+    /// The header with a zero CRC, which `seal()` fills in together with the
+    /// identifier of the kernel build and the resume function.
+    pub(crate) fn to_bytes(&self) -> [u8; HEADER_LENGTH] {
+        let mut buf = [0; HEADER_LENGTH];
+        buf[0..4].copy_from_slice(&MAGIC);
+        let words = [
+            VERSION,
+            self.length as u32,
+            0,
+            self.tbf_hash,
+            self.flash_start as u32,
+            self.memory_start as u32,
+            self.memory_length as u32,
+            self.app_break as u32,
+            self.allow_high_water_mark as u32,
+            self.context_length as u32,
+            self.yielded as u32,
+            self.kernel_id,
+            self.resume_function as u32,
+            self.resume_argument as u32,
+        ];
+        for (index, word) in words.iter().enumerate() {
+            write_u32(&mut buf, index + 1, *word);
+        }
+        buf
+    }
+
+    /// Parses the header of the checkpoint in `buf`, if it is valid.
+    pub(crate) fn parse(buf: &[u8]) -> Option<CheckpointHeader> {
+        let length = validate(buf)?;
+        let header = CheckpointHeader {
+            length,
+            tbf_hash: read_u32(buf, 4),
+            flash_start: read_u32(buf, 5) as usize,
+            memory_start: read_u32(buf, 6) as usize,
+            memory_length: read_u32(buf, 7) as usize,
+            app_break: read_u32(buf, 8) as usize,
+            allow_high_water_mark: read_u32(buf, 9) as usize,
+            context_length: read_u32(buf, 10) as usize,
+            yielded: read_u32(buf, 11) != 0,
+            kernel_id: read_u32(buf, 12),
+            resume_function: read_u32(buf, 13) as usize,
+            resume_argument: read_u32(buf, 14) as usize,
+        };
+        Some(header)
+    }
+}
+
+/// A section of a checkpoint.
+pub(crate) enum Section<'a> {
+    Bytes(&'a [u8]),
+    /// Memory of the process, which is copied without creating a reference
+    /// to it.
+    Memory {
+        start: *const u8,
+        length: usize,
+    },
+}
+
+impl Section<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Section::Bytes(bytes) => bytes.len(),
+            Section::Memory { length, .. } => *length,
+        }
+    }
+}
+
+/// Copies the part of the checkpoint made of `sections` that starts at
+/// `offset` into `out`, and returns the length of the whole checkpoint.
+/// Bytes of `out` past the end of the checkpoint are not changed.
+///
+/// # Safety
+///
+/// The memory of every `Section::Memory` must be readable.
+pub(crate) unsafe fn read_sections(sections: &[Section], offset: usize, out: &mut [u8]) -> usize {
+    let mut section_start = 0;
+    for section in sections {
+        let length = section.len();
+        let section_end = section_start + padded(length);
+        let out_start = offset;
+        let out_end = offset + out.len();
+        if out_start < section_end && out_end > section_start {
+            // The overlap of the section, with its padding, and `out`.
+            let start = core::cmp::max(out_start, section_start);
+            let end = core::cmp::min(out_end, section_end);
+            for byte in &mut out[start - offset..end - offset] {
+                *byte = 0;
+            }
+            let data_end = core::cmp::min(end, section_start + length);
+            if data_end > start {
+                let count = data_end - start;
+                let dest = &mut out[start - offset..data_end - offset];
+                match section {
+                    Section::Bytes(bytes) => {
+                        dest.copy_from_slice(
+                            &bytes[start - section_start..data_end - section_start],
+                        );
+                    }
+                    Section::Memory { start: memory, .. } => {
+                        core::ptr::copy_nonoverlapping(
+                            memory.add(start - section_start),
+                            dest.as_mut_ptr(),
+                            count,
+                        );
+                    }
+                }
+            }
+        }
+        section_start = section_end;
+    }
+    section_start
+}
+
+/// Fills in the CRC, the identifier of the kernel build and the resume
+/// function of a checkpoint whose header is at the start of `buf`. `crc` is
+/// the `crc32()` of the checkpoint after the header. The process is resumed
+/// by calling the function at address `resume_function` with
+/// `resume_argument` as its last argument.
+pub fn seal(
+    buf: &mut [u8],
+    crc: u32,
+    kernel_id: u32,
+    resume_function: usize,
+    resume_argument: usize,
+) {
+    if buf.len() >= HEADER_LENGTH {
+        write_u32(buf, 3, crc);
+        write_u32(buf, 12, kernel_id);
+        write_u32(buf, 13, resume_function as u32);
+        write_u32(buf, 14, resume_argument as u32);
+    }
+}
+
+/// Checks that `buf` starts with a complete and valid checkpoint and returns
+/// the length of the checkpoint.
+pub fn validate(buf: &[u8]) -> Option<usize> {
+    if buf.len() < HEADER_LENGTH || buf[0..4] != MAGIC {
+        return None;
+    }
+    if read_u32(buf, 1) != VERSION {
+        return None;
+    }
+    let length = read_u32(buf, 2) as usize;
+    if length < HEADER_LENGTH || length > buf.len() || length % 4 != 0 {
+        return None;
+    }
+    if crc32(0, &buf[HEADER_LENGTH..length]) != read_u32(buf, 3) {
+        return None;
+    }
+    Some(length)
+}
+
+/// CRC-32 (IEEE 802.3) of `data`, continuing from the CRC `crc` of the data
+/// before it. The CRC of data that is split in parts is computed by passing
+/// 0 for the first part and the result for each following part.
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// 32-bit FNV-1a hash of `data`, which identifies the TBF object a
+/// checkpoint belongs to.
+pub(crate) fn hash(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// `length` rounded up to a multiple of four.
+pub(crate) fn padded(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+fn read_u32(buf: &[u8], index: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[index * 4],
+        buf[index * 4 + 1],
+        buf[index * 4 + 2],
+        buf[index * 4 + 3],
+    ])
+}
+
+fn write_u32(buf: &mut [u8], index: usize, value: u32) {
+    buf[index * 4..index * 4 + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_header(length: usize) -> CheckpointHeader {
+        CheckpointHeader {
+            length,
+            tbf_hash: hash(b"tbf"),
+            flash_start: 0x40000,
+            memory_start: 0x20004000,
+            memory_length: 0x2000,
+            app_break: 0x800,
+            allow_high_water_mark: 0x400,
+            context_length: 6,
+            yielded: true,
+            kernel_id: 0x4b45_524e,
+            resume_function: 0x40101,
+            resume_argument: 7,
+        }
+    }
+
+    #[test]
+    fn checkpoint_read_in_parts_is_valid() {
+        let memory = [0x5Au8; 10];
+        let length = CheckpointHeader::checkpoint_length(&[6, 10]);
+        let header = test_header(length).to_bytes();
+        let sections = [
+            Section::Bytes(&header),
+            Section::Bytes(b"regs!!"),
+            Section::Memory {
+                start: memory.as_ptr(),
+                length: memory.len(),
+            },
+        ];
+
+        // Read the checkpoint in parts of 12 bytes, as if writing pages.
+        let mut buf = [0xFF; 96];
+        for offset in (0..buf.len()).step_by(12) {
+            let read = unsafe { read_sections(&sections, offset, &mut buf[offset..offset + 12]) };
+            assert_eq!(read, length);
+        }
+        let crc = crc32(0, &buf[HEADER_LENGTH..length]);
+        seal(&mut buf, crc, 0x4b45_524e, 0x40101, 7);
+
+        // Six bytes padded to eight and ten bytes padded to twelve.
+        assert_eq!(length, HEADER_LENGTH + 8 + 12);
+        assert_eq!(&buf[HEADER_LENGTH..HEADER_LENGTH + 8], b"regs!!\0\0");
+        assert_eq!(&buf[HEADER_LENGTH + 8..HEADER_LENGTH + 18], &memory);
+        assert_eq!(&buf[HEADER_LENGTH + 18..length], &[0, 0]);
+        assert_eq!(&buf[length..], &[0xFF; 96 - HEADER_LENGTH - 20]);
+        assert_eq!(validate(&buf), Some(length));
+        assert!(CheckpointHeader::parse(&buf) == Some(test_header(length)));
+    }
+
+    #[test]
+    fn corrupted_checkpoint_is_rejected() {
+        let length = CheckpointHeader::checkpoint_length(&[8]);
+        let header = test_header(length).to_bytes();
+        let sections = [Section::Bytes(&header), Section::Bytes(b"contexts")];
+        let mut buf = [0; HEADER_LENGTH + 8];
+        unsafe { read_sections(&sections, 0, &mut buf) };
+        let crc = crc32(0, &buf[HEADER_LENGTH..]);
+        seal(&mut buf, crc, 0, 0, 0);
+        assert_eq!(validate(&buf), Some(HEADER_LENGTH + 8));
+
+        buf[HEADER_LENGTH + 6] ^= 0x01;
+        assert_eq!(validate(&buf), None);
+        assert_eq!(validate(&[0xFF; HEADER_LENGTH + 8]), None);
+
+        // Swapped words do not change an XOR, but they do change the CRC.
+        buf[HEADER_LENGTH + 6] ^= 0x01;
+        let (first, second) = buf[HEADER_LENGTH..].split_at_mut(4);
+        first.swap_with_slice(&mut second[..4]);
+        assert_eq!(validate(&buf), None);
+    }
+
+    #[test]
+    fn crc32_in_parts() {
+        assert_eq!(crc32(0, b""), 0);
+        assert_eq!(crc32(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn kernel_id_and_resume_function_are_sealed() {
+        let length = CheckpointHeader::checkpoint_length(&[]);
+        let mut buf = test_header(length).to_bytes();
+        seal(&mut buf, 0, 0x1234_5678, 0x40201, 3);
+        let header = CheckpointHeader::parse(&buf).unwrap();
+        assert_eq!(header.kernel_id, 0x1234_5678);
+        assert_eq!(
+            (header.resume_function, header.resume_argument),
+            (0x40201, 3)
+        );
+    }
+}
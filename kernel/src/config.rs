@@ -61,6 +61,14 @@ pub(crate) struct Config {
     // is identified, using configuration constants is the most effective
     // option.
     pub(crate) debug_panics: bool,
+
+    /// Whether custom grants can be freed and grant quotas are enforced.
+    ///
+    /// If enabled, the kernel stores a header above every allocation in the grant region of a
+    /// process, which it uses to account the memory of each driver against its `GrantQuota`, and
+    /// to compact the grant region after a custom grant is freed. This costs four words of process
+    /// memory per allocation. If disabled, `CustomGrant::free()` fails and quotas are ignored.
+    pub(crate) grant_compaction: bool,
 }
 
 /// A unique instance of `Config` where compile-time configuration options are defined. These
@@ -71,4 +79,5 @@ pub(crate) const CONFIG: Config = Config {
     trace_syscalls: cfg!(feature = "trace_syscalls"),
     debug_load_processes: cfg!(feature = "debug_load_processes"),
     debug_panics: !cfg!(feature = "no_debug_panics"),
+    grant_compaction: cfg!(feature = "grant_compaction"),
 };
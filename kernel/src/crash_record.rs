@@ -0,0 +1,406 @@
+//! Crash records that survive a reboot.
+//!
+//! When a process faults or the kernel panics, the process printer and
+//! `debug::panic()` stream the state of the system to the console, which is
+//! lost on devices that nobody is watching. This module serializes the same
+//! state into a compact binary crash record. A `CrashRecorder`, for example
+//! the `crash_record` capsule, stores the record in flash so that it can be
+//! retrieved after the next boot and decoded on a host with
+//! `tools/decode_crash_record.py`.
+//!
+//! Record Format
+//! -------------
+//!
+//! All values are little endian. A record starts with a 16 byte header:
+//!
+//! | Offset | Size | Field                                                  |
+//! |--------|------|--------------------------------------------------------|
+//! | 0      | 4    | Magic, the ASCII string `TKCR`                         |
+//! | 4      | 2    | Format version, currently 1                            |
+//! | 6      | 1    | Cause of the crash, see `CrashKind`                    |
+//! | 7      | 1    | Flags, bit 0 is set if entries did not fit the record  |
+//! | 8      | 4    | Length of the record, including the header             |
+//! | 12     | 4    | XOR of all 32-bit words of the record after the header |
+//!
+//! The header is followed by entries. Each entry has a 2 byte type, a 2 byte
+//! length of its payload and the payload, padded with zeros to a multiple of
+//! four bytes. The entry types are listed in `EntryType`.
+//!
+//! A `Process` entry starts the state of a process. The `Registers`,
+//! `MemoryMap` and `Stack` entries that follow it, up to the next `Process`
+//! entry, describe that process. A record of a process fault
+//! contains one process, a record of a kernel panic contains all processes.
+
+use core::fmt;
+use core::panic::PanicInfo;
+use core::slice;
+
+use crate::process::{self, Process};
+
+/// Magic bytes at the start of every crash record.
+pub const MAGIC: [u8; 4] = *b"TKCR";
+
+/// Version of the record format.
+pub const VERSION: u16 = 1;
+
+/// Length of the header of a crash record.
+pub const HEADER_LENGTH: usize = 16;
+
+/// How many bytes of the stack of a process are stored, at most.
+pub const STACK_SNAPSHOT_LENGTH: usize = 256;
+
+/// Flag that is set if some entries did not fit in the record.
+const FLAG_TRUNCATED: u8 = 0x01;
+
+/// What caused the crash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CrashKind {
+    /// The kernel panicked.
+    KernelPanic = 1,
+    /// A process faulted.
+    ProcessFault = 2,
+}
+
+/// Types of the entries of a crash record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum EntryType {
+    /// The message of the kernel panic, as UTF-8 text.
+    PanicMessage = 1,
+    /// The identifier (`u32`), restart count (`u32`) and state (`u8`) of a
+    /// process, followed by 3 bytes of padding and the name of the process.
+    Process = 2,
+    /// The stored registers of the process as returned by
+    /// `Process::get_stored_state()`. The layout is architecture specific and
+    /// identified by the tag in the blob.
+    Registers = 3,
+    /// The addresses of `ProcessAddresses`, in the order flash start, flash
+    /// non-protected start, flash end, RAM start, app break, grant start, RAM
+    /// end, heap start, stack top and stack bottom, each as a `u32`. Unknown
+    /// addresses are zero.
+    MemoryMap = 4,
+    /// The address (`u32`) of the first byte of the snapshot of the stack,
+    /// followed by the snapshot. The snapshot starts at the lowest stack
+    /// pointer the kernel has seen.
+    Stack = 5,
+}
+
+/// Stores crash records so they can be retrieved after a reboot.
+pub trait CrashRecorder {
+    /// Records that `process` faulted.
+    ///
+    /// This is called before the kernel acts on the fault, so the state of
+    /// the process is still intact. The record may be written to storage
+    /// after this returns.
+    fn record_process_fault(&self, process: &dyn Process);
+
+    /// Records a kernel panic together with the state of all `processes`.
+    ///
+    /// This does not return until the record is stored. Interrupts are not
+    /// handled during a panic, so the recorder calls `service` to make
+    /// progress on the operations of its storage.
+    fn record_panic(
+        &self,
+        panic_info: &PanicInfo,
+        processes: &[Option<&'static dyn Process>],
+        service: &dyn Fn(),
+    );
+}
+
+/// Serializes a crash record into a buffer.
+///
+/// Entries that do not fit in the buffer are left out and the record is
+/// marked as truncated.
+pub struct CrashRecordWriter<'a> {
+    buf: &'a mut [u8],
+    length: usize,
+    truncated: bool,
+}
+
+impl<'a> CrashRecordWriter<'a> {
+    /// Starts a new record of a crash caused by `kind` in `buf`. Returns
+    /// `None` if `buf` is too short to hold the header.
+    pub fn new(buf: &'a mut [u8], kind: CrashKind) -> Option<CrashRecordWriter<'a>> {
+        if buf.len() < HEADER_LENGTH {
+            return None;
+        }
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        buf[6] = kind as u8;
+        Some(CrashRecordWriter {
+            buf,
+            length: HEADER_LENGTH,
+            truncated: false,
+        })
+    }
+
+    /// Adds an entry of type `entry_type`. `write` is passed the space that is
+    /// left for the payload and returns the length of the payload, or `None`
+    /// if it does not fit.
+    fn add_entry<F: FnOnce(&mut [u8]) -> Option<usize>>(
+        &mut self,
+        entry_type: EntryType,
+        write: F,
+    ) {
+        let payload_start = self.length + 4;
+        if payload_start > self.buf.len() {
+            self.truncated = true;
+            return;
+        }
+        let payload_end = core::cmp::min(self.buf.len(), payload_start + (u16::MAX as usize & !3));
+        match write(&mut self.buf[payload_start..payload_end]) {
+            Some(payload_length) => {
+                let padded_length = (payload_length + 3) & !3;
+                if payload_start + padded_length > self.buf.len() {
+                    self.truncated = true;
+                    return;
+                }
+                let entry = &mut self.buf[self.length..];
+                entry[0..2].copy_from_slice(&(entry_type as u16).to_le_bytes());
+                entry[2..4].copy_from_slice(&(payload_length as u16).to_le_bytes());
+                for byte in &mut entry[4 + payload_length..4 + padded_length] {
+                    *byte = 0;
+                }
+                self.length = payload_start + padded_length;
+            }
+            None => self.truncated = true,
+        }
+    }
+
+    /// Adds the message of a kernel panic. Messages that do not fit are cut
+    /// short.
+    pub fn add_panic_message(&mut self, message: fmt::Arguments) {
+        self.add_entry(EntryType::PanicMessage, |payload| {
+            // Leave room for the padding of the entry.
+            let usable = payload.len() & !3;
+            let mut writer = SliceWriter {
+                buf: &mut payload[..usable],
+                length: 0,
+            };
+            let _ = fmt::write(&mut writer, message);
+            Some(writer.length)
+        });
+    }
+
+    /// Adds all entries that describe `process`.
+    pub fn add_process(&mut self, process: &dyn Process) {
+        self.add_entry(EntryType::Process, |payload| {
+            let name = process.get_process_name().as_bytes();
+            let length = 12 + name.len();
+            if length > payload.len() {
+                return None;
+            }
+            write_u32(payload, 0, process.processid().id() as u32);
+            write_u32(payload, 1, process.get_restart_count() as u32);
+            payload[8] = state_code(process.get_state());
+            payload[9..12].copy_from_slice(&[0; 3]);
+            payload[12..length].copy_from_slice(name);
+            Some(length)
+        });
+
+        self.add_entry(EntryType::Registers, |payload| {
+            process.get_stored_state(payload).ok()
+        });
+
+        let addresses = process.get_addresses();
+        self.add_entry(EntryType::MemoryMap, |payload| {
+            let words = [
+                addresses.flash_start,
+                addresses.flash_non_protected_start,
+                addresses.flash_end,
+                addresses.sram_start,
+                addresses.sram_app_brk,
+                addresses.sram_grant_start,
+                addresses.sram_end,
+                addresses.sram_heap_start.unwrap_or(0),
+                addresses.sram_stack_top.unwrap_or(0),
+                addresses.sram_stack_bottom.unwrap_or(0),
+            ];
+            write_words(payload, &words)
+        });
+
+        if let Some(stack_bottom) = addresses.sram_stack_bottom {
+            // Only read memory the process has access to.
+            let stack_end = addresses
+                .sram_stack_top
+                .map_or(addresses.sram_app_brk, |top| {
+                    core::cmp::min(top, addresses.sram_app_brk)
+                });
+            if stack_bottom >= addresses.sram_start && stack_bottom < stack_end {
+                self.add_entry(EntryType::Stack, |payload| {
+                    if payload.len() < 4 {
+                        return None;
+                    }
+                    let length = core::cmp::min(
+                        core::cmp::min(stack_end - stack_bottom, STACK_SNAPSHOT_LENGTH),
+                        payload.len() - 4,
+                    );
+                    // Safety: the snapshot lies within the memory the process
+                    // has access to, which is valid memory that the process
+                    // cannot change while the kernel is running.
+                    let stack = unsafe { slice::from_raw_parts(stack_bottom as *const u8, length) };
+                    write_u32(payload, 0, stack_bottom as u32);
+                    payload[4..4 + length].copy_from_slice(stack);
+                    Some(4 + length)
+                });
+            }
+        }
+    }
+
+    /// Completes the header of the record and returns its length.
+    pub fn finish(self) -> usize {
+        let length = self.length;
+        self.buf[7] = if self.truncated { FLAG_TRUNCATED } else { 0 };
+        self.buf[8..12].copy_from_slice(&(length as u32).to_le_bytes());
+        let checksum = checksum(&self.buf[HEADER_LENGTH..length]);
+        self.buf[12..16].copy_from_slice(&checksum.to_le_bytes());
+        length
+    }
+}
+
+/// Checks that `buf` starts with a complete crash record and returns the
+/// length of the record.
+pub fn validate(buf: &[u8]) -> Option<usize> {
+    if buf.len() < HEADER_LENGTH || buf[0..4] != MAGIC {
+        return None;
+    }
+    if u16::from_le_bytes([buf[4], buf[5]]) != VERSION {
+        return None;
+    }
+    let length = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]) as usize;
+    if length < HEADER_LENGTH || length > buf.len() || length % 4 != 0 {
+        return None;
+    }
+    let stored_checksum = u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]);
+    if checksum(&buf[HEADER_LENGTH..length]) != stored_checksum {
+        return None;
+    }
+    Some(length)
+}
+
+/// Returns the cause of the crash recorded at the start of `buf`, if it holds
+/// a complete crash record.
+pub fn kind(buf: &[u8]) -> Option<CrashKind> {
+    validate(buf)?;
+    match buf[6] {
+        1 => Some(CrashKind::KernelPanic),
+        2 => Some(CrashKind::ProcessFault),
+        _ => None,
+    }
+}
+
+/// XOR of all 32-bit words in `buf`, whose length must be a multiple of four.
+fn checksum(buf: &[u8]) -> u32 {
+    buf.chunks_exact(4).fold(0, |checksum, word| {
+        checksum ^ u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+    })
+}
+
+fn write_u32(buf: &mut [u8], index: usize, value: u32) {
+    buf[index * 4..index * 4 + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_words(buf: &mut [u8], words: &[usize]) -> Option<usize> {
+    if words.len() * 4 > buf.len() {
+        return None;
+    }
+    for (index, word) in words.iter().enumerate() {
+        write_u32(buf, index, *word as u32);
+    }
+    Some(words.len() * 4)
+}
+
+fn state_code(state: process::State) -> u8 {
+    match state {
+        process::State::Running => 0,
+        process::State::Yielded => 1,
+        process::State::StoppedRunning => 2,
+        process::State::StoppedYielded => 3,
+        process::State::Faulted => 4,
+        process::State::Terminated => 5,
+        process::State::Unstarted => 6,
+        process::State::CredentialsUnchecked => 7,
+        process::State::CredentialsFailed => 8,
+    }
+}
+
+/// `fmt::Write` into a slice that drops what does not fit.
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    length: usize,
+}
+
+impl fmt::Write for SliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let available = self.buf.len() - self.length;
+        let count = core::cmp::min(available, s.len());
+        self.buf[self.length..self.length + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.length += count;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_with_panic_message_is_valid() {
+        let mut buf = [0xFF; 64];
+        let mut writer = CrashRecordWriter::new(&mut buf, CrashKind::KernelPanic).unwrap();
+        writer.add_panic_message(format_args!("panicked at {}", 42));
+        let length = writer.finish();
+
+        // The message is 14 bytes, padded to 16.
+        assert_eq!(length, HEADER_LENGTH + 4 + 16);
+        assert_eq!(validate(&buf), Some(length));
+        assert_eq!(buf[6], CrashKind::KernelPanic as u8);
+        assert_eq!(buf[7], 0);
+        assert_eq!(
+            &buf[HEADER_LENGTH + 4..HEADER_LENGTH + 18],
+            b"panicked at 42"
+        );
+    }
+
+    #[test]
+    fn long_panic_message_is_cut_short() {
+        let mut buf = [0; 32];
+        let mut writer = CrashRecordWriter::new(&mut buf, CrashKind::KernelPanic).unwrap();
+        writer.add_panic_message(format_args!("a message that does not fit"));
+        let length = writer.finish();
+
+        assert_eq!(length, 32);
+        assert_eq!(validate(&buf), Some(32));
+        assert_eq!(&buf[HEADER_LENGTH + 4..], b"a message th");
+    }
+
+    #[test]
+    fn corrupted_record_is_rejected() {
+        let mut buf = [0; 64];
+        let mut writer = CrashRecordWriter::new(&mut buf, CrashKind::KernelPanic).unwrap();
+        writer.add_panic_message(format_args!("panicked"));
+        let length = writer.finish();
+
+        buf[length - 1] ^= 0x01;
+        assert_eq!(validate(&buf), None);
+        assert_eq!(validate(&[0xFF; 64]), None);
+    }
+
+    #[test]
+    fn kind_of_record() {
+        let mut buf = [0; 64];
+        let length = CrashRecordWriter::new(&mut buf, CrashKind::ProcessFault)
+            .unwrap()
+            .finish();
+        assert_eq!(kind(&buf[..length]), Some(CrashKind::ProcessFault));
+
+        let mut writer = CrashRecordWriter::new(&mut buf, CrashKind::KernelPanic).unwrap();
+        writer.add_panic_message(format_args!("panicked"));
+        let length = writer.finish();
+        assert_eq!(kind(&buf[..length]), Some(CrashKind::KernelPanic));
+
+        buf[length - 1] ^= 0x01;
+        assert_eq!(kind(&buf[..length]), None);
+    }
+}
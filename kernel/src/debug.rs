@@ -56,6 +56,7 @@ use core::str;
 
 use crate::collections::queue::Queue;
 use crate::collections::ring_buffer::RingBuffer;
+use crate::crash_record::CrashRecorder;
 use crate::hil;
 use crate::platform::chip::Chip;
 use crate::process::Process;
@@ -202,6 +203,25 @@ pub unsafe fn panic_process_info<PP: ProcessPrinter, W: Write>(
     });
 }
 
+/// Store a crash record of the panic and the state of all processes.
+///
+/// Boards that keep crash records call this in their panic handler before
+/// printing the panic, so that the record is stored even if printing hangs.
+/// While the record is written, the interrupts of the chip are serviced by
+/// polling.
+pub unsafe fn panic_crash_record<C: Chip>(
+    recorder: &'static Option<&'static dyn CrashRecorder>,
+    panic_info: &PanicInfo,
+    processes: &'static [Option<&'static dyn Process>],
+    chip: &'static Option<&'static C>,
+) {
+    recorder.map(|recorder| {
+        recorder.record_panic(panic_info, processes, &|| {
+            chip.map(|c| c.service_pending_interrupts());
+        });
+    });
+}
+
 /// Blinks a recognizable pattern forever.
 ///
 /// If a multi-color LED is used for the panic pattern, it is
@@ -0,0 +1,344 @@
+//! Loading new processes while the kernel is running.
+//!
+//! Processes are normally discovered once at boot by `load_processes()`,
+//! which walks the linked list of TBF objects in the app flash region. This
+//! module lets a capsule add a process afterwards, for example one that
+//! receives a TBF object over a UART and writes it to flash. Updating a single
+//! application then no longer requires reflashing the whole board.
+//!
+//! Loading a process is split in two steps:
+//!
+//! 1. `allocate_flash()` finds room for a TBF object after the last object in
+//!    the app flash region. The caller writes the object there, preceded by
+//!    the padding header of the region if it has one.
+//! 2. `load_process()` validates the TBF header that was written, creates a
+//!    `ProcessStandard` in a free slot of the processes array and starts it.
+//!
+//! Boards that check the credentials of processes at boot with
+//! `load_and_check_processes()` must pass the same `ProcessCheckerMachine` to
+//! the loader. A new process is then created in the `CredentialsUnchecked`
+//! state and only starts once the board's `AppCredentialsChecker` approved
+//! it, just like the processes found at boot. The client of the checker is
+//! told the outcome.
+//!
+//! Because the object is appended to the linked list of TBF objects, the
+//! process is also found by `load_processes()` after the next reboot.
+//!
+//! New processes get their memory from a region that the board reserves for
+//! this purpose, since all memory given to `load_processes()` may already be
+//! in use by the processes loaded at boot. Loading is one-way: the slot and
+//! memory of a process stay with it when it terminates, so that it can be
+//! restarted, and are never handed to another process. The size of this
+//! region and the number of free slots therefore bound how many processes can
+//! be loaded until the next reboot.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let (boot_memory, dynamic_memory) = APP_MEMORY.split_at_mut(APP_MEMORY.len() / 2);
+//!
+//! kernel::process::load_and_check_processes(
+//!     board_kernel,
+//!     chip,
+//!     app_flash,
+//!     boot_memory,
+//!     &mut PROCESSES,
+//!     &FAULT_RESPONSE,
+//!     checker,
+//!     &process_management_capability,
+//! )
+//! .unwrap_or_else(|err| {
+//!     debug!("Error loading processes!");
+//!     debug!("{:?}", err);
+//! });
+//!
+//! let loader = static_init!(
+//!     kernel::dynamic_process_loader::DynamicProcessLoader<Chip, ProcessMgmtCap>,
+//!     kernel::dynamic_process_loader::DynamicProcessLoader::new(
+//!         board_kernel,
+//!         chip,
+//!         app_flash,
+//!         dynamic_memory,
+//!         &FAULT_RESPONSE,
+//!         Some(checker),
+//!         create_capability!(capabilities::ProcessManagementCapability),
+//!     )
+//! );
+//! ```
+
+use core::convert::TryInto;
+use core::slice;
+
+use crate::capabilities::ProcessManagementCapability;
+use crate::config;
+use crate::debug;
+use crate::kernel::Kernel;
+use crate::platform::chip::Chip;
+use crate::process::ProcessId;
+use crate::process_checker::ProcessCheckerMachine;
+use crate::process_policies::ProcessFaultPolicy;
+use crate::process_standard::ProcessStandard;
+use crate::process_utilities::ProcessLoadError;
+use crate::utilities::cells::TakeCell;
+
+/// Length of the TBF header of a padding object, which is just the base
+/// header without any TLVs.
+pub const PADDING_HEADER_LENGTH: usize = 16;
+
+/// A region of the app flash region that has been allocated for a new TBF
+/// object. All addresses are absolute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlashRegion {
+    /// Address of the first byte after the last TBF object in flash. If it is
+    /// smaller than `start`, the gap must be filled with a padding object
+    /// whose header is returned by `padding_header()`.
+    pub padding_start: usize,
+    /// Address at which the new TBF object must be written.
+    pub start: usize,
+    /// Length of the new TBF object.
+    pub length: usize,
+}
+
+impl FlashRegion {
+    /// Returns the TBF header that must be written at `padding_start` to skip
+    /// over the gap in front of the new object, or `None` if there is no gap.
+    pub fn padding_header(&self) -> Option<[u8; PADDING_HEADER_LENGTH]> {
+        let padding_length = self.start - self.padding_start;
+        if padding_length == 0 {
+            return None;
+        }
+
+        let version = 2u32;
+        let header_size = PADDING_HEADER_LENGTH as u32;
+        let total_size = padding_length as u32;
+        // A padding object is never enabled.
+        let flags = 0u32;
+        let checksum = (version | header_size << 16) ^ total_size ^ flags;
+
+        let mut header = [0; PADDING_HEADER_LENGTH];
+        header[0..4].copy_from_slice(&(version | header_size << 16).to_le_bytes());
+        header[4..8].copy_from_slice(&total_size.to_le_bytes());
+        header[8..12].copy_from_slice(&flags.to_le_bytes());
+        header[12..16].copy_from_slice(&checksum.to_le_bytes());
+        Some(header)
+    }
+}
+
+/// Interface for capsules that install new applications at runtime.
+pub trait DynamicProcessLoad {
+    /// Finds room in the app flash region for a TBF object of `length` bytes.
+    ///
+    /// The object is placed at an address that is a multiple of its length
+    /// rounded up to a power of two, so that MPUs that require naturally
+    /// aligned regions can protect it.
+    fn allocate_flash(&self, length: usize) -> Result<FlashRegion, ProcessLoadError>;
+
+    /// Validates the TBF object that was written to `region` and starts it as
+    /// a new process.
+    ///
+    /// If credentials are checked, the process is only created here and
+    /// starts once its credentials are approved.
+    fn load_process(&self, region: &FlashRegion) -> Result<ProcessId, ProcessLoadError>;
+}
+
+/// Creates processes at runtime from TBF objects in the app flash region.
+pub struct DynamicProcessLoader<C: 'static + Chip, P: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: TakeCell<'static, [u8]>,
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    checker: Option<&'static ProcessCheckerMachine>,
+    capability: P,
+}
+
+impl<C: 'static + Chip, P: ProcessManagementCapability> DynamicProcessLoader<C, P> {
+    /// Creates a loader that adds processes to the free slots of the
+    /// processes array of `kernel`.
+    ///
+    /// `app_memory` is the memory new processes are allocated from. It must
+    /// not overlap the memory given to `load_processes()`.
+    ///
+    /// If `checker` is given, new processes only start once it approved their
+    /// credentials. It must be `None` only on boards that do not check
+    /// credentials at boot either.
+    pub fn new(
+        kernel: &'static Kernel,
+        chip: &'static C,
+        app_flash: &'static [u8],
+        app_memory: &'static mut [u8],
+        fault_policy: &'static dyn ProcessFaultPolicy,
+        checker: Option<&'static ProcessCheckerMachine>,
+        capability: P,
+    ) -> DynamicProcessLoader<C, P> {
+        DynamicProcessLoader {
+            kernel,
+            chip,
+            app_flash,
+            app_memory: TakeCell::new(app_memory),
+            fault_policy,
+            checker,
+            capability,
+        }
+    }
+
+    /// Returns the offset in the app flash region of the first byte after the
+    /// linked list of TBF objects.
+    fn end_of_apps(&self) -> usize {
+        let mut offset = 0;
+        loop {
+            let header = match self.app_flash.get(offset..offset + 8) {
+                Some(header) => header,
+                None => return offset,
+            };
+            let entry_length = match tock_tbf::parse::parse_tbf_header_lengths(
+                header.try_into().unwrap_or(&[0; 8]),
+            ) {
+                Ok((_, _, entry_length)) => entry_length,
+                Err(tock_tbf::types::InitialTbfParseError::InvalidHeader(entry_length)) => {
+                    entry_length
+                }
+                Err(tock_tbf::types::InitialTbfParseError::UnableToParse) => return offset,
+            };
+            if entry_length == 0 {
+                return offset;
+            }
+            offset += entry_length as usize;
+        }
+    }
+}
+
+impl<C: 'static + Chip, P: ProcessManagementCapability> DynamicProcessLoad
+    for DynamicProcessLoader<C, P>
+{
+    fn allocate_flash(&self, length: usize) -> Result<FlashRegion, ProcessLoadError> {
+        if length < PADDING_HEADER_LENGTH {
+            return Err(ProcessLoadError::NotEnoughFlash);
+        }
+
+        let flash_start = self.app_flash.as_ptr() as usize;
+        let flash_end = flash_start + self.app_flash.len();
+        let padding_start = flash_start + self.end_of_apps();
+
+        let alignment = length.next_power_of_two();
+        let mut start = (padding_start + alignment - 1) & !(alignment - 1);
+        if start > padding_start && start - padding_start < PADDING_HEADER_LENGTH {
+            // The gap is too small for a padding header.
+            start += alignment;
+        }
+
+        if start + length > flash_end {
+            return Err(ProcessLoadError::NotEnoughFlash);
+        }
+        Ok(FlashRegion {
+            padding_start,
+            start,
+            length,
+        })
+    }
+
+    fn load_process(&self, region: &FlashRegion) -> Result<ProcessId, ProcessLoadError> {
+        let offset = region
+            .start
+            .checked_sub(self.app_flash.as_ptr() as usize)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+        let entry_flash = self
+            .app_flash
+            .get(offset..offset + region.length)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+
+        // Check that the object is a complete and enabled application before
+        // handing out memory and a slot to it.
+        let test_header_slice: &'static [u8; 8] = entry_flash
+            .get(0..8)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?
+            .try_into()
+            .or(Err(ProcessLoadError::InternalError))?;
+        let (version, header_length, entry_length) =
+            match tock_tbf::parse::parse_tbf_header_lengths(test_header_slice) {
+                Ok(lengths) => lengths,
+                Err(tock_tbf::types::InitialTbfParseError::InvalidHeader(_)) => {
+                    return Err(tock_tbf::types::TbfParseError::NotEnoughFlash.into());
+                }
+                Err(tock_tbf::types::InitialTbfParseError::UnableToParse) => {
+                    let version = u16::from_le_bytes([test_header_slice[0], test_header_slice[1]]);
+                    return Err(tock_tbf::types::TbfParseError::UnsupportedVersion(version).into());
+                }
+            };
+        if entry_length as usize > entry_flash.len() {
+            return Err(ProcessLoadError::NotEnoughFlash);
+        }
+        let entry_flash = &entry_flash[0..entry_length as usize];
+        let header = tock_tbf::parse::parse_tbf_header(
+            entry_flash
+                .get(0..header_length as usize)
+                .ok_or(ProcessLoadError::NotEnoughFlash)?,
+            version,
+        )?;
+        if !header.is_app() || !header.enabled() {
+            return Err(ProcessLoadError::NotEnabled);
+        }
+
+        let index = self
+            .kernel
+            .free_process_slot(&self.capability)
+            .ok_or(ProcessLoadError::NoProcessSlot)?;
+        let memory = self
+            .app_memory
+            .take()
+            .ok_or(ProcessLoadError::NotEnoughMemory)?;
+        let (memory_start, memory_length) = (memory.as_mut_ptr(), memory.len());
+
+        let result = unsafe {
+            ProcessStandard::create(
+                self.kernel,
+                self.chip,
+                entry_flash,
+                header_length as usize,
+                version,
+                memory,
+                self.fault_policy,
+                true,
+                self.checker.is_some(),
+                index,
+            )
+        };
+        let process = match result {
+            Ok((Some(process), unused_memory)) => {
+                self.app_memory.replace(unused_memory);
+                process
+            }
+            Ok((None, unused_memory)) => {
+                self.app_memory.replace(unused_memory);
+                return Err(ProcessLoadError::NotEnabled);
+            }
+            Err(err) => {
+                // No process was created, so none of the memory is in use and
+                // it can be handed out again.
+                self.app_memory.replace(unsafe {
+                    slice::from_raw_parts_mut(memory_start, memory_length)
+                });
+                return Err(err);
+            }
+        };
+        if config::CONFIG.debug_load_processes {
+            debug!(
+                "Loaded process[{}] at runtime from flash={:#010X}-{:#010X} = {:?}",
+                index,
+                entry_flash.as_ptr() as usize,
+                entry_flash.as_ptr() as usize + entry_flash.len() - 1,
+                process.get_process_name()
+            );
+        }
+        self.kernel
+            .add_process(index, process, &self.capability)
+            .or(Err(ProcessLoadError::InternalError))?;
+        let process_id = process.processid();
+
+        if let Some(checker) = self.checker {
+            checker.start();
+        }
+        Ok(process_id)
+    }
+}
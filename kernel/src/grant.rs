@@ -35,7 +35,16 @@
 //! `SizeOf<T>` plus the size for the structure to hold upcalls and allowed
 //! buffer references. If capsules need additional process-specific memory for
 //! their operation, they can use an `Allocator` to request additional memory
-//! from the process's grant region.
+//! from the process's grant region. If the kernel is built with the
+//! `grant_compaction` feature, this memory can be freed again with
+//! `CustomGrant::free()`, after which the grant region is compacted so that
+//! long running processes do not run out of kernel memory.
+//!
+//! With the same feature, boards can limit how much memory each driver may use
+//! in the grant region of a process, its grant and custom grants together, by
+//! passing a list of `GrantQuota`s to `Kernel::set_grant_quotas()`.
+//! Allocations that would exceed the quota fail with `Error::OutOfMemory`, as
+//! if the process were out of memory.
 //!
 //! ```text,ignore
 //!                            ┌──────────────────┐
@@ -124,6 +133,7 @@
 //! the closure.               ▼
 //! ```
 
+use core::cell::Cell;
 use core::cmp;
 use core::marker::PhantomData;
 use core::mem::{align_of, size_of};
@@ -1377,7 +1387,8 @@ impl<T> CustomGrant<T> {
             .process_map_or(Err(Error::NoSuchApp), self.processid, |process| {
                 // App is valid.
 
-                // Now try to access the custom grant memory.
+                // Now try to access the custom grant memory. This also keeps
+                // the memory from being moved while it is entered.
                 let grant_ptr = process.enter_custom_grant(self.identifier)?;
 
                 // # Safety
@@ -1391,7 +1402,28 @@ impl<T> CustomGrant<T> {
                 // once (because of the `&mut self` requirement).
                 let custom_grant = unsafe { &mut *(grant_ptr as *mut T) };
                 let borrowed = GrantData::new(custom_grant);
-                Ok(fun(borrowed))
+                let result = fun(borrowed);
+
+                process.leave_custom_grant(self.identifier);
+                Ok(result)
+            })
+    }
+
+    /// Frees the memory of this custom grant in the process's grant region.
+    ///
+    /// The value stored in the custom grant is not dropped. The grant region
+    /// of the process is compacted to make the memory available for future
+    /// grant allocations.
+    ///
+    /// Returns `Err(Error::NoSuchApp)` if the process no longer exists, in
+    /// which case its memory was already reclaimed, and
+    /// `Err(Error::KernelError)` if the kernel was built without the
+    /// `grant_compaction` feature.
+    pub fn free(self) -> Result<(), Error> {
+        self.processid
+            .kernel
+            .process_map_or(Err(Error::NoSuchApp), self.processid, |process| {
+                process.free_custom_grant(self.identifier)
             })
     }
 }
@@ -1518,6 +1550,18 @@ impl GrantRegionAllocator {
     }
 }
 
+/// Limit on how much memory the grant of a driver, including the custom grants
+/// allocated through it, may use in the grant region of each process.
+#[derive(Clone, Copy)]
+pub struct GrantQuota {
+    /// The syscall driver number of the capsule owning the grant.
+    pub driver_num: usize,
+
+    /// The maximum number of bytes, including the kernel's bookkeeping of
+    /// each allocation.
+    pub max_size: usize,
+}
+
 /// Type for storing an object of type T in process memory that is only
 /// accessible by the kernel.
 ///
@@ -1651,8 +1695,8 @@ pub struct Iter<
 
     /// Iterator over valid processes.
     subiter: core::iter::FilterMap<
-        core::slice::Iter<'a, Option<&'static dyn Process>>,
-        fn(&Option<&'static dyn Process>) -> Option<&'static dyn Process>,
+        core::slice::Iter<'a, Cell<Option<&'static dyn Process>>>,
+        fn(&Cell<Option<&'static dyn Process>>) -> Option<&'static dyn Process>,
     >,
 }
 
@@ -113,7 +113,54 @@ pub enum RadioChannel {
     AdvertisingChannel39 = 80,
 }
 
+/// The data channels, indexed by channel index.
+static DATA_CHANNELS: [RadioChannel; 37] = [
+    RadioChannel::DataChannel0,
+    RadioChannel::DataChannel1,
+    RadioChannel::DataChannel2,
+    RadioChannel::DataChannel3,
+    RadioChannel::DataChannel4,
+    RadioChannel::DataChannel5,
+    RadioChannel::DataChannel6,
+    RadioChannel::DataChannel7,
+    RadioChannel::DataChannel8,
+    RadioChannel::DataChannel9,
+    RadioChannel::DataChannel10,
+    RadioChannel::DataChannel11,
+    RadioChannel::DataChannel12,
+    RadioChannel::DataChannel13,
+    RadioChannel::DataChannel14,
+    RadioChannel::DataChannel15,
+    RadioChannel::DataChannel16,
+    RadioChannel::DataChannel17,
+    RadioChannel::DataChannel18,
+    RadioChannel::DataChannel19,
+    RadioChannel::DataChannel20,
+    RadioChannel::DataChannel21,
+    RadioChannel::DataChannel22,
+    RadioChannel::DataChannel23,
+    RadioChannel::DataChannel24,
+    RadioChannel::DataChannel25,
+    RadioChannel::DataChannel26,
+    RadioChannel::DataChannel27,
+    RadioChannel::DataChannel28,
+    RadioChannel::DataChannel29,
+    RadioChannel::DataChannel30,
+    RadioChannel::DataChannel31,
+    RadioChannel::DataChannel32,
+    RadioChannel::DataChannel33,
+    RadioChannel::DataChannel34,
+    RadioChannel::DataChannel35,
+    RadioChannel::DataChannel36,
+];
+
 impl RadioChannel {
+    /// Returns the data channel with channel index `index`, if it is one of
+    /// the data channels 0 to 36.
+    pub fn data_channel(index: u8) -> Option<RadioChannel> {
+        DATA_CHANNELS.get(index as usize).copied()
+    }
+
     pub fn get_channel_index(&self) -> u32 {
         match *self {
             RadioChannel::DataChannel0 => 0,
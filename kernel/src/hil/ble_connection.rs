@@ -0,0 +1,77 @@
+//! Bluetooth Low Energy connection HIL
+//!
+//! Radio operations needed by a link layer in the peripheral (slave) role:
+//! connectable advertising, and data channel connection events.
+//!
+//! In a connection event the central transmits first and the peripheral has
+//! to respond exactly T_IFS (150 µs) after the end of the received packet.
+//! This is too tight for software, so the radio turns around from receive to
+//! transmit in hardware and asks the client for the response while it ramps
+//! up its transmitter. The client must therefore answer
+//! `ConnectionClient::data_received()` quickly, and do any further processing
+//! after it has written the response.
+//!
+//! ```text
+//!            central                      peripheral
+//!               |         data PDU            |
+//!               | --------------------------> |  data_received()
+//!               |                             |
+//!               |     T_IFS     response      |
+//!               | <-------------------------- |  response_sent()
+//! ```
+
+use crate::hil::ble_advertising::RadioChannel;
+use crate::ErrorCode;
+
+/// The access address used on the advertising channels.
+///
+/// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1.2
+pub const ADVERTISING_ACCESS_ADDRESS: u32 = 0x8E89BED6;
+
+/// T_IFS, the inter frame space between a received packet and its response.
+pub const T_IFS_US: u32 = 150;
+
+pub trait BleConnectionDriver<'a> {
+    fn set_connection_client(&self, client: &'a dyn ConnectionClient);
+
+    /// Transmit the advertising channel PDU `pdu` on `channel`, then listen
+    /// on the same channel for a request (SCAN_REQ or CONNECT_IND) until
+    /// `stop()` is called or a PDU is received. A received PDU is passed to
+    /// `ConnectionClient::advertising_request()`.
+    fn advertise_and_listen(&self, pdu: &[u8], channel: RadioChannel);
+
+    /// Set the access address and CRC initialization value of the
+    /// connection, used by following calls to `listen_and_respond()`.
+    fn set_access_address(&self, access_address: u32, crc_init: u32);
+
+    /// Listen on the data channel `channel` until `stop()` is called or a PDU
+    /// is received. When a PDU is received, its response is transmitted T_IFS
+    /// after it.
+    fn listen_and_respond(&self, channel: RadioChannel);
+
+    /// Abort the current operation and turn off the radio. No callback is
+    /// issued for the aborted operation.
+    fn stop(&self);
+}
+
+pub trait ConnectionClient {
+    /// A PDU was received on the advertising channel after an advertisement.
+    /// `pdu` includes the 2 byte header.
+    fn advertising_request(&self, pdu: &[u8], result: Result<(), ErrorCode>);
+
+    /// A data channel PDU was received, `result` is an error if its CRC is
+    /// wrong. The client writes the response PDU, including its 2 byte
+    /// header, into `response` and returns its length.
+    ///
+    /// This is called while the radio ramps up to transmit the response, so
+    /// it must return quickly.
+    fn data_received(
+        &self,
+        pdu: &[u8],
+        result: Result<(), ErrorCode>,
+        response: &mut [u8],
+    ) -> usize;
+
+    /// The response to the last received data channel PDU was transmitted.
+    fn response_sent(&self);
+}
@@ -0,0 +1,132 @@
+//! Interface for Key-Value (KV) Stores with permissions
+//!
+//! This is the level 3 "KV Store" HIL described in `hil::kv_system`. Users of
+//! this HIL operate on unhashed keys and do not need to know how the values
+//! are laid out in storage.
+//!
+//! Every value is stored together with a header that records the `write_id`
+//! of whoever stored it. Each operation is given the `StoragePermissions` of
+//! the caller, and implementations of this HIL must check them against the
+//! header of the stored value:
+//!
+//! - `get()` only returns values the caller is allowed to read.
+//! - `set()` only replaces existing values the caller is allowed to modify,
+//!   and requires the caller to have a `write_id`.
+//! - `delete()` only removes values the caller is allowed to modify.
+//!
+//! The header is placed in the value buffer in front of the data, so buffers
+//! passed to `get()` and `set()` must leave `header_size()` bytes of room in
+//! addition to the data itself.
+
+use crate::storage_permissions::StoragePermissions;
+use crate::utilities::leasable_buffer::LeasableBuffer;
+use crate::ErrorCode;
+
+/// Implement this trait and use `set_client()` in order to receive callbacks.
+pub trait StoreClient {
+    /// This callback is called when the get operation completes.
+    ///
+    /// `result`: The length of the value on success, 'ErrorCode' on error.
+    ///           On success the value is stored at the start of `value`.
+    /// `key`: The key buffer
+    /// `value`: The value buffer
+    fn get_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    );
+
+    /// This callback is called when the set operation completes.
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error
+    /// `key`: The key buffer
+    /// `value`: The value buffer
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    );
+
+    /// This callback is called when the delete operation completes.
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error
+    /// `key`: The key buffer
+    fn delete_complete(&self, result: Result<(), ErrorCode>, key: &'static mut [u8]);
+}
+
+pub trait KVStore<'a> {
+    /// Set the client
+    fn set_client(&self, client: &'a dyn StoreClient);
+
+    /// The number of bytes the store needs in front of the data in the value
+    /// buffers passed to `get()` and `set()`.
+    fn header_size(&self) -> usize;
+
+    /// Retrieve the value stored for `key`.
+    ///
+    /// `key`: The unhashed key. Only the active portion is used.
+    /// `value`: A buffer to store the value to. This must be large enough
+    ///          to hold the header and the value.
+    /// `permissions`: The permissions of the caller.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key, value and an `ErrorCode` will be returned.
+    ///
+    /// The possible `ErrorCode`s, here and in `get_complete()`, are:
+    ///    `BUSY`: An operation is already in progress
+    ///    `NOSUPPORT`: The caller is not allowed to read the value
+    ///    `SIZE`: The value does not fit in the `value` buffer
+    ///    `FAIL`: The key could not be found
+    fn get(
+        &self,
+        key: LeasableBuffer<'static, u8>,
+        value: &'static mut [u8],
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], &'static mut [u8], ErrorCode)>;
+
+    /// Store a value for `key`, replacing any existing value.
+    ///
+    /// `key`: The unhashed key. Only the active portion is used.
+    /// `value`: A buffer holding the data to store at offset
+    ///          `header_size()`. The bytes in front of the data are
+    ///          overwritten with the header.
+    /// `length`: The length of the data.
+    /// `permissions`: The permissions of the caller.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key, value and an `ErrorCode` will be returned.
+    ///
+    /// The possible `ErrorCode`s, here and in `set_complete()`, are:
+    ///    `BUSY`: An operation is already in progress
+    ///    `NOSUPPORT`: The caller is not allowed to write, or not allowed
+    ///                 to modify the existing value
+    ///    `SIZE`: The `value` buffer is too small for the header and data
+    ///    `FAIL`: The value could not be stored
+    fn set(
+        &self,
+        key: LeasableBuffer<'static, u8>,
+        value: &'static mut [u8],
+        length: usize,
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], &'static mut [u8], ErrorCode)>;
+
+    /// Remove the value stored for `key`.
+    ///
+    /// `key`: The unhashed key. Only the active portion is used.
+    /// `permissions`: The permissions of the caller.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key and an `ErrorCode` will be returned.
+    ///
+    /// The possible `ErrorCode`s, here and in `delete_complete()`, are:
+    ///    `BUSY`: An operation is already in progress
+    ///    `NOSUPPORT`: The caller is not allowed to modify the value
+    ///    `FAIL`: The key could not be found
+    fn delete(
+        &self,
+        key: LeasableBuffer<'static, u8>,
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], ErrorCode)>;
+}
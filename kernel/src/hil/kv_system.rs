@@ -30,7 +30,7 @@
 //! This level is also in charge of generating the key hash by calling into
 //! level 2.
 //!
-//! There is currently no implementation of this HIL in Tock.
+//! This level is described by `hil::kv_store`.
 //!
 //! The expected setup inside Tock will look like this:
 //! +-----------------------+
@@ -39,7 +39,7 @@
 //! |                       |
 //! +-----------------------+
 //!
-//!    hil::kv_store (not written yet)
+//!    hil::kv_store
 //!
 //! +-----------------------+
 //! |                       |
@@ -47,7 +47,7 @@
 //! |                       |
 //! +-----------------------+
 //!
-//!    hil::kv_system (this PR)
+//!    hil::kv_system (this file)
 //!
 //! +-----------------------+
 //! |                       |
@@ -91,6 +91,22 @@ pub trait Client<K: KeyType> {
         value: &'static [u8],
     );
 
+    /// This callback is called when the append_key_mut operation completes
+    ///
+    /// Clients that call `append_key_mut()` must implement this to get their
+    /// value buffer back.
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error
+    /// `key`: The key buffer
+    /// `value`: The value buffer
+    fn append_key_mut_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _key: &'static mut K,
+        _value: &'static mut [u8],
+    ) {
+    }
+
     /// This callback is called when the get_value operation completes
     ///
     /// `result`: Nothing on success, 'ErrorCode' on error
@@ -163,6 +179,38 @@ pub trait KVSystem<'a> {
         value: &'static [u8],
     ) -> Result<(), (&'static mut Self::K, &'static [u8], Result<(), ErrorCode>)>;
 
+    /// Appends the key/value pair from a mutable buffer, like `append_key()`,
+    /// but hands the buffer back to `Client::append_key_mut_complete()` so
+    /// that the caller can reuse it.
+    ///
+    /// `key`: A hashed key. This key will be used in future to retrieve
+    ///        or remove the `value`.
+    /// `value`: A buffer containing the data to be stored to flash.
+    /// `length`: The number of bytes at the start of `value` to store.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key, value and a `Result<(), ErrorCode>` will be returned.
+    ///
+    /// The possible `Result<(), ErrorCode>`s are the ones of `append_key()`,
+    /// and `NOSUPPORT` if the implementation does not support this operation,
+    /// which is the default.
+    fn append_key_mut(
+        &self,
+        key: &'static mut Self::K,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            &'static mut [u8],
+            Result<(), ErrorCode>,
+        ),
+    > {
+        let _ = length;
+        Err((key, value, Err(ErrorCode::NOSUPPORT)))
+    }
+
     /// Retrieves the value from a specified key.
     ///
     /// `key`: A hashed key. This key will be used to retrieve the `value`.
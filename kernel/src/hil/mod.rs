@@ -3,6 +3,7 @@
 pub mod adc;
 pub mod analog_comparator;
 pub mod ble_advertising;
+pub mod ble_connection;
 pub mod bus8080;
 pub mod crc;
 pub mod dac;
@@ -14,6 +15,7 @@ pub mod gpio;
 pub mod gpio_async;
 pub mod hasher;
 pub mod i2c;
+pub mod kv_store;
 pub mod kv_system;
 pub mod led;
 pub mod log;
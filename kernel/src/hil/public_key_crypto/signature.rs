@@ -0,0 +1,53 @@
+//! Interface for verifying signatures.
+
+use crate::ErrorCode;
+
+/// Implement this trait and use `set_verify_client()` in order to receive
+/// callbacks.
+///
+/// `HL` is the length of the hash, `SL` is the length of the signature.
+pub trait ClientVerify<const HL: usize, const SL: usize> {
+    /// This callback is called when the verification is complete.
+    ///
+    /// On success the result indicates whether the signature is a valid
+    /// signature over the hash with the key held by the implementation.
+    /// On failure the result will indicate an `ErrorCode`.
+    ///
+    /// In both cases `hash` and `signature` contain references to the original
+    /// buffers passed to `verify()`.
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static mut [u8; HL],
+        signature: &'static mut [u8; SL],
+    );
+}
+
+/// Verify a signature over a hash.
+///
+/// This trait deliberately does not hash the signed data itself. Callers
+/// compute the hash with a `hil::digest` implementation and pass the result
+/// to `verify()`. The public key the signature is checked against is managed
+/// by the implementation, for example through `hil::public_key_crypto::keys`.
+///
+/// `HL` is the length of the hash, `SL` is the length of the signature.
+pub trait SignatureVerify<'a, const HL: usize, const SL: usize> {
+    /// Set the client instance which will receive the `verification_done()`
+    /// callback.
+    fn set_verify_client(&'a self, client: &'a dyn ClientVerify<HL, SL>);
+
+    /// Verify that `signature` is a valid signature over `hash`.
+    ///
+    /// If this returns `Ok(())`, then the `verification_done()` callback will
+    /// be called. If this returns `Err()`, no callback will be called.
+    ///
+    /// The possible ErrorCodes are:
+    ///     - `BUSY`: A verification is already in progress.
+    ///     - `NODEVICE`: No public key is available to verify with.
+    ///     - `FAIL`: The verification could not be started.
+    fn verify(
+        &'a self,
+        hash: &'static mut [u8; HL],
+        signature: &'static mut [u8; SL],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; HL], &'static mut [u8; SL])>;
+}
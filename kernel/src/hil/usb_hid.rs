@@ -9,6 +9,7 @@ impl UsbHidType for [u8; 64] {}
 impl UsbHidType for [u8; 32] {}
 impl UsbHidType for [u8; 16] {}
 impl UsbHidType for [u8; 8] {}
+impl UsbHidType for [u8; 4] {}
 
 /// Implement this trait and use `set_client()` in order to receive callbacks.
 pub trait Client<'a, T: UsbHidType> {
@@ -123,6 +123,36 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_timeslice_expiration_count())
     }
 
+    /// Returns how long the app has run on the CPU since it started, in
+    /// microseconds.
+    pub fn app_execution_time_us(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> u64 {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_execution_time_us())
+    }
+
+    /// Returns a tuple of the (highest number of bytes of heap, highest number
+    /// of bytes of grant region) the app has used since it started. If the
+    /// app has not told the kernel where its heap starts, the heap is counted
+    /// from the start of its memory.
+    pub fn app_memory_high_water_marks(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> (usize, usize) {
+        self.kernel.process_map_or((0, 0), app, |process| {
+            let addresses = process.get_addresses();
+            let heap_start = addresses.sram_heap_start.unwrap_or(addresses.sram_start);
+            (
+                addresses.sram_app_brk_max.saturating_sub(heap_start),
+                addresses.sram_end - addresses.sram_grant_start_min,
+            )
+        })
+    }
+
     /// Returns a tuple of the (the number of grants in the grant region this
     /// app has allocated, total number of grants that exist in the system).
     pub fn number_app_grant_uses(
@@ -152,4 +182,24 @@ impl KernelInfo {
         });
         count.get()
     }
+
+    /// Returns how long all processes have run on the CPU in total, in
+    /// microseconds.
+    pub fn execution_time_us(&self, _capability: &dyn ProcessManagementCapability) -> u64 {
+        let total: Cell<u64> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            total.set(total.get() + proc.debug_execution_time_us());
+        });
+        total.get()
+    }
+
+    /// Returns the total number of deadlines all periodic processes have
+    /// missed.
+    pub fn deadline_misses(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_deadline_miss_count());
+        });
+        count.get()
+    }
 }
@@ -10,14 +10,15 @@ use crate::config;
 use crate::debug;
 use crate::dynamic_deferred_call::DynamicDeferredCall;
 use crate::errorcode::ErrorCode;
-use crate::grant::{AllowRoSize, AllowRwSize, Grant, UpcallSize};
+use crate::grant::{AllowRoSize, AllowRwSize, Grant, GrantQuota, UpcallSize};
 use crate::ipc;
 use crate::memop;
 use crate::platform::chip::Chip;
 use crate::platform::mpu::MPU;
 use crate::platform::platform::ContextSwitchCallback;
 use crate::platform::platform::KernelResources;
-use crate::platform::platform::{ProcessFault, SyscallDriverLookup, SyscallFilter};
+use crate::platform::platform::{ProcessFault, SyscallDriverLookup, SyscallFilter, SyscallTracer};
+use crate::platform::power::SleepPolicy;
 use crate::platform::scheduler_timer::SchedulerTimer;
 use crate::platform::watchdog::WatchDog;
 use crate::process::ProcessId;
@@ -39,7 +40,10 @@ pub struct Kernel {
     work: Cell<usize>,
 
     /// 这包含一个指向静态进程指针数组的指针。
-    processes: &'static [Option<&'static dyn process::Process>],
+    ///
+    /// The slots are cells so that `add_process()` can fill an empty slot
+    /// while the kernel is running.
+    processes: &'static [Cell<Option<&'static dyn process::Process>>],
 
     /// 跟踪创建了多少进程标识符的计数器。 这用于为进程创建新的唯一标识符。
     process_identifier_max: Cell<usize>,
@@ -51,6 +55,15 @@ pub struct Kernel {
     /// 用于标记Grant已完成的标志。
     /// 这意味着内核不能支持创建新的Grant，因为已经创建了进程并且已经建立了Grant的数据结构
     grants_finalized: Cell<bool>,
+
+    /// 每个驱动程序在每个进程的Grant区域中最多可以使用的内存量。
+    grant_quotas: Cell<&'static [GrantQuota]>,
+
+    /// 内核空闲时决定芯片可以进入的最深睡眠状态的策略。默认为 `()`，即深度睡眠。
+    sleep_policy: Cell<&'static dyn SleepPolicy>,
+
+    /// 在每个系统调用处理完成后被通知的系统调用跟踪器。默认为 `()`，即不跟踪。
+    syscall_tracer: Cell<&'static dyn SyscallTracer>,
 }
 
 /// 枚举用于通知调度程序为什么进程停止执行（也就是为什么 `do_process()` 返回）
@@ -105,12 +118,22 @@ fn try_allocate_grant<KR: KernelResources<C>, C: Chip>(
 
 impl Kernel {
     pub fn new(processes: &'static [Option<&'static dyn process::Process>]) -> Kernel {
+        // `Cell<T>` has the same layout as `T`. The processes array is a
+        // `static mut` that `load_processes()` also writes to, so it is
+        // writable memory.
+        let processes = unsafe {
+            &*(processes as *const [Option<&'static dyn process::Process>]
+                as *const [Cell<Option<&'static dyn process::Process>>])
+        };
         Kernel {
             work: Cell::new(0),
             processes,
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            grant_quotas: Cell::new(&[]),
+            sleep_policy: Cell::new(&()),
+            syscall_tracer: Cell::new(&()),
         }
     }
 
@@ -161,11 +184,11 @@ impl Kernel {
         // 我们在 `appid` 中使用索引，所以我们可以直接查找。
         // 但是，我们不能保证应用程序仍然存在于进程数组中的该索引处。
         // 为了避免额外的开销，我们在这里进行查找和检查，而不是调用`.index()`。
-        match self.processes.get(processid.index) {
-            Some(Some(process)) => {
+        match self.processes.get(processid.index).and_then(Cell::get) {
+            Some(process) => {
                 // 检查此处存储的进程是否与 `appid` 中的标识符匹配。
                 if process.processid() == processid {
-                    Some(*process)
+                    Some(process)
                 } else {
                     None
                 }
@@ -227,9 +250,9 @@ impl Kernel {
         F: FnMut(&dyn process::Process),
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    closure(*p);
+                    closure(p);
                 }
                 None => {}
             }
@@ -240,13 +263,13 @@ impl Kernel {
     pub(crate) fn get_process_iter(
         &self,
     ) -> core::iter::FilterMap<
-        core::slice::Iter<Option<&dyn process::Process>>,
-        fn(&Option<&'static dyn process::Process>) -> Option<&'static dyn process::Process>,
+        core::slice::Iter<Cell<Option<&'static dyn process::Process>>>,
+        fn(&Cell<Option<&'static dyn process::Process>>) -> Option<&'static dyn process::Process>,
     > {
         fn keep_some(
-            &x: &Option<&'static dyn process::Process>,
+            x: &Cell<Option<&'static dyn process::Process>>,
         ) -> Option<&'static dyn process::Process> {
-            x
+            x.get()
         }
         self.processes.iter().filter_map(keep_some)
     }
@@ -263,9 +286,9 @@ impl Kernel {
         F: FnMut(&dyn process::Process),
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    closure(*p);
+                    closure(p);
                 }
                 None => {}
             }
@@ -279,9 +302,9 @@ impl Kernel {
         F: Fn(&dyn process::Process) -> Option<T>,
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    let ret = closure(*p);
+                    let ret = closure(p);
                     if ret.is_some() {
                         return ret;
                     }
@@ -298,10 +321,38 @@ impl Kernel {
     /// `ProcessId` 本身需要执行 `.index()` 命令来验证引用的应用程序是否仍在正确的索引处。
     pub(crate) fn processid_is_valid(&self, appid: &ProcessId) -> bool {
         self.processes.get(appid.index).map_or(false, |p| {
-            p.map_or(false, |process| process.processid().id() == appid.id())
+            p.get()
+                .map_or(false, |process| process.processid().id() == appid.id())
         })
     }
 
+    /// Returns the index of the first empty slot in the processes array.
+    pub fn free_process_slot(
+        &self,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) -> Option<usize> {
+        self.processes.iter().position(|slot| slot.get().is_none())
+    }
+
+    /// Puts a process created while the kernel is running into the empty slot
+    /// at `index`, which must be the index its `ProcessId` was created with.
+    ///
+    /// Returns `INVAL` if there is no such slot and `BUSY` if the slot is
+    /// already in use.
+    pub fn add_process(
+        &self,
+        index: usize,
+        process: &'static dyn process::Process,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) -> Result<(), ErrorCode> {
+        let slot = self.processes.get(index).ok_or(ErrorCode::INVAL)?;
+        if slot.get().is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        slot.set(Some(process));
+        Ok(())
+    }
+
     /// 创建一个新的Grant。 这用于板级初始化以设置Capsules用于与Process交互的Grant。
     ///
     /// Grant**必须**仅在_before_进程被初始化时创建。进程使用已分配的Grant数量来正确初始化进程的内存，
@@ -328,6 +379,48 @@ impl Kernel {
         Grant::new(self, driver_num, grant_index)
     }
 
+    /// 设置Grant配额，即每个驱动程序在每个进程的Grant区域中最多可以使用的字节数，
+    /// 包括其Grant和通过它分配的自定义Grant。没有配额的驱动程序不受限制。
+    ///
+    /// 这样，一个Capsules就不能耗尽一个进程的所有内核内存。
+    /// 只有在内核启用 `grant_compaction` 功能构建时才会强制执行配额。
+    pub fn set_grant_quotas(
+        &self,
+        quotas: &'static [GrantQuota],
+        _capability: &dyn capabilities::MemoryAllocationCapability,
+    ) {
+        self.grant_quotas.set(quotas);
+    }
+
+    /// 设置内核空闲时使用的睡眠策略，例如 `PowerManager`。
+    /// 没有设置睡眠策略的板子在空闲时进入深度睡眠。
+    pub fn set_sleep_policy(
+        &self,
+        policy: &'static dyn SleepPolicy,
+        _capability: &dyn capabilities::MainLoopCapability,
+    ) {
+        self.sleep_policy.set(policy);
+    }
+
+    /// 设置系统调用跟踪器，例如 `SyscallTrace`。
+    /// 没有设置跟踪器的板子不跟踪系统调用。
+    pub fn set_syscall_tracer(
+        &self,
+        tracer: &'static dyn SyscallTracer,
+        _capability: &dyn capabilities::MainLoopCapability,
+    ) {
+        self.syscall_tracer.set(tracer);
+    }
+
+    /// 返回驱动程序在每个进程的Grant区域中最多可以使用的字节数，如果不受限制，则返回 `None`。
+    pub(crate) fn get_grant_quota(&self, driver_num: usize) -> Option<usize> {
+        self.grant_quotas
+            .get()
+            .iter()
+            .find(|quota| quota.driver_num == driver_num)
+            .map(|quota| quota.max_size)
+    }
+
     /// 返回系统中已设置的Grant数量，并将Grant标记为“已完成”。
     /// 这意味着不能再创建Grant，因为在调用此函数时已根据Grant数量设置了数据结构。
     /// 实际上，这是在创建进程时调用的，并且进程内存是根据当前Grant的数量设置的。
@@ -364,7 +457,7 @@ impl Kernel {
     /// 这限制了一般Capsules能够调用此函数，因为Capsules不应该能够任意重启所有应用程序。
     pub fn hardfault_all_apps<C: capabilities::ProcessManagementCapability>(&self, _c: &C) {
         for p in self.processes.iter() {
-            p.map(|process| {
+            p.get().map(|process| {
                 process.set_fault_state();
             });
         }
@@ -410,6 +503,9 @@ impl Kernel {
                             self.process_map_or((), appid, |process| {
                                 let (reason, time_executed) =
                                     self.do_process(resources, chip, process, ipc, timeslice_us);
+                                // 累计进程的执行时间，以便统计每个进程的CPU使用情况。
+                                time_executed
+                                    .map(|time_us| process.debug_add_execution_time(time_us));
                                 scheduler.result(reason, time_executed);
                             });
                         }
@@ -424,8 +520,12 @@ impl Kernel {
                                         && !DynamicDeferredCall::global_instance_calls_pending()
                                             .unwrap_or(false)
                                     {
+                                        // 向板子的睡眠策略询问芯片可以进入的最深睡眠状态。
+                                        let sleep_policy = self.sleep_policy.get();
+                                        let state = sleep_policy.sleep_state();
                                         resources.watchdog().suspend();
-                                        chip.sleep();
+                                        sleep_policy.prepare_sleep(state);
+                                        chip.sleep_in(state);
                                         resources.watchdog().resume();
                                     }
                                 });
@@ -561,8 +661,14 @@ impl Kernel {
                         }
                         Some(ContextSwitchReason::SyscallFired { syscall }) => {
                             self.handle_syscall(resources, process, syscall);
+                            resources
+                                .context_switch_callback()
+                                .context_switch_return_hook(process);
                         }
                         Some(ContextSwitchReason::Interrupted) => {
+                            resources
+                                .context_switch_callback()
+                                .context_switch_return_hook(process);
                             if scheduler_timer.get_remaining_us().is_none() {
                                 // 此中断是时间片到期。
                                 process.debug_timeslice_expired();
@@ -626,6 +732,10 @@ impl Kernel {
                     // 我们永远不应该安排一个错误的进程。
                     panic!("Attempted to schedule a faulty process");
                 }
+                process::State::CredentialsUnchecked | process::State::CredentialsFailed => {
+                    // 凭证未被批准的进程永远不应该被调度。
+                    panic!("Attempted to schedule a process without approved credentials");
+                }
                 process::State::StoppedRunning => {
                     return_reason = StoppedExecutingReason::Stopped;
                     break;
@@ -658,6 +768,19 @@ impl Kernel {
         (return_reason, time_executed_us)
     }
 
+    /// 将系统调用的返回值设置到进程中，并报告给内核的系统调用跟踪器。
+    fn set_syscall_return(
+        &self,
+        process: &dyn process::Process,
+        syscall: &Syscall,
+        rval: SyscallReturn,
+    ) {
+        self.syscall_tracer
+            .get()
+            .syscall_handled(process, syscall, Some(&rval));
+        process.set_syscall_return_value(rval);
+    }
+
     /// 在特定Process上调用系统调用的方法。 应用内核系统调用过滤策略（如果有）。
     /// 处理 `Yield` 和 `Exit`，将 `Memop` 分派到 `memop::memop`，
     /// 并通过平台 `with_driver` 方法将外围驱动系统调用分派到外围驱动封装。
@@ -699,7 +822,7 @@ impl Kernel {
                 // Check all other syscalls for filtering.
                 if let Err(response) = resources.syscall_filter().filter_syscall(process, &syscall)
                 {
-                    process.set_syscall_return_value(SyscallReturn::Failure(response));
+                    self.set_syscall_return(process, &syscall, SyscallReturn::Failure(response));
 
                     if config::CONFIG.trace_syscalls {
                         debug!(
@@ -728,9 +851,12 @@ impl Kernel {
                         rval
                     );
                 }
-                process.set_syscall_return_value(rval);
+                self.set_syscall_return(process, &syscall, rval);
             }
             Syscall::Yield { which, address } => {
+                self.syscall_tracer
+                    .get()
+                    .syscall_handled(process, &syscall, None);
                 if config::CONFIG.trace_syscalls {
                     debug!("[{:?}] yield. which: {}", process.processid(), which);
                 }
@@ -896,7 +1022,7 @@ impl Kernel {
                     );
                 }
 
-                process.set_syscall_return_value(rval);
+                self.set_syscall_return(process, &syscall, rval);
             }
             Syscall::Command {
                 driver_number,
@@ -924,7 +1050,7 @@ impl Kernel {
                         res,
                     );
                 }
-                process.set_syscall_return_value(res);
+                self.set_syscall_return(process, &syscall, res);
             }
             Syscall::ReadWriteAllow {
                 driver_number,
@@ -1017,7 +1143,7 @@ impl Kernel {
                         res
                     );
                 }
-                process.set_syscall_return_value(res);
+                self.set_syscall_return(process, &syscall, res);
             }
             Syscall::UserspaceReadableAllow {
                 driver_number,
@@ -1095,7 +1221,7 @@ impl Kernel {
                         res
                     );
                 }
-                process.set_syscall_return_value(res);
+                self.set_syscall_return(process, &syscall, res);
             }
             Syscall::ReadOnlyAllow {
                 driver_number,
@@ -1189,19 +1315,33 @@ impl Kernel {
                     );
                 }
 
-                process.set_syscall_return_value(res);
+                self.set_syscall_return(process, &syscall, res);
             }
             Syscall::Exit {
                 which,
                 completion_code,
             } => match which {
                 // The process called the `exit-terminate` system call.
-                0 => process.terminate(Some(completion_code as u32)),
+                0 => {
+                    self.syscall_tracer
+                        .get()
+                        .syscall_handled(process, &syscall, None);
+                    process.terminate(Some(completion_code as u32))
+                }
                 // The process called the `exit-restart` system call.
-                1 => process.try_restart(Some(completion_code as u32)),
+                1 => {
+                    self.syscall_tracer
+                        .get()
+                        .syscall_handled(process, &syscall, None);
+                    process.try_restart(Some(completion_code as u32))
+                }
                 // The process called an invalid variant of the Exit
                 // system call class.
-                _ => process.set_syscall_return_value(SyscallReturn::Failure(ErrorCode::NOSUPPORT)),
+                _ => self.set_syscall_return(
+                    process,
+                    &syscall,
+                    SyscallReturn::Failure(ErrorCode::NOSUPPORT),
+                ),
             },
         }
     }
@@ -94,11 +94,14 @@ pub const MAJOR: u16 = 2;
 pub const MINOR: u16 = 0;
 
 pub mod capabilities;
+pub mod checkpoint;
 pub mod collections;
 pub mod component;
+pub mod crash_record;
 pub mod debug;
 pub mod deferred_call;
 pub mod dynamic_deferred_call;
+pub mod dynamic_process_loader;
 pub mod errorcode;
 pub mod grant;
 pub mod hil;
@@ -106,8 +109,10 @@ pub mod introspection;
 pub mod ipc;
 pub mod platform;
 pub mod process;
+pub mod process_checker;
 pub mod processbuffer;
 pub mod scheduler;
+pub mod storage_permissions;
 pub mod syscall;
 pub mod upcall;
 pub mod utilities;
@@ -1,6 +1,7 @@
 //! Interfaces for implementing microcontrollers in Tock.
 
 use crate::platform::mpu;
+use crate::platform::power::SleepState;
 use crate::syscall;
 use core::fmt::Write;
 
@@ -46,6 +47,18 @@ pub trait Chip {
     /// chip and resumes the scheduler.
     fn sleep(&self);
 
+    /// Called instead of `sleep()` when the kernel is idle, with the deepest
+    /// `SleepState` the `SleepPolicy` of the kernel allows. The chip enters its
+    /// deepest mode that is not deeper than `state` and that it can currently
+    /// wake from. Entering `SleepState::Off` may not return, since waking from
+    /// it resets the chip.
+    ///
+    /// The default implementation ignores `state` and calls `sleep()`.
+    fn sleep_in(&self, state: SleepState) {
+        let _ = state;
+        self.sleep();
+    }
+
     /// Run a function in an atomic state, which means that interrupts are
     /// disabled so that an interrupt will not fire during the passed in
     /// function's execution.
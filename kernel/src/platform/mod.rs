@@ -4,6 +4,7 @@
 
 pub mod chip;
 pub mod mpu;
+pub mod power;
 pub mod scheduler_timer;
 pub mod watchdog;
 
@@ -14,4 +15,5 @@ pub use self::platform::KernelResources;
 pub use self::platform::ProcessFault;
 pub use self::platform::SyscallDriverLookup;
 pub use self::platform::SyscallFilter;
+pub use self::platform::SyscallTracer;
 pub use self::platform::TbfHeaderFilterDefaultAllow;
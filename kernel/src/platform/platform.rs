@@ -135,6 +135,24 @@ pub trait SyscallFilter {
 /// Implement default allow all SyscallFilter trait for unit.
 impl SyscallFilter for () {}
 
+/// Trait for observing the system calls of processes, for example to record a
+/// trace of them.
+pub trait SyscallTracer {
+    /// Called after the kernel handled a system call of `process`. `result`
+    /// is the value returned to the process, or `None` for Yield and Exit,
+    /// which have no return value and are traced when they are called.
+    fn syscall_handled(
+        &self,
+        _process: &dyn process::Process,
+        _syscall: &syscall::Syscall,
+        _result: Option<&syscall::SyscallReturn>,
+    ) {
+    }
+}
+
+/// Implement default SyscallTracer trait for unit, which traces nothing.
+impl SyscallTracer for () {}
+
 /// An allow list system call filter based on the TBF header, with a default
 /// allow all fallback.
 ///
@@ -275,6 +293,14 @@ pub trait ContextSwitchCallback {
     ///
     /// `process` is the app that is about to run
     fn context_switch_hook(&self, process: &dyn process::Process);
+
+    /// This function is called after a process returned to the kernel because
+    /// of an interrupt, or because of a system call once the kernel handled
+    /// it. The callback may stop the process, for example when it is being
+    /// debugged.
+    ///
+    /// `process` is the app that just ran
+    fn context_switch_return_hook(&self, _process: &dyn process::Process) {}
 }
 
 /// Implement default ContextSwitchCallback trait for unit.
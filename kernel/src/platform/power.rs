@@ -0,0 +1,377 @@
+//! Interfaces for managing how deeply the chip sleeps.
+//!
+//! When the kernel has no work to do it asks the `SleepPolicy` set with
+//! `Kernel::set_sleep_policy()` for the deepest `SleepState` the chip may
+//! enter, and then calls `Chip::sleep_in()`, which maps the state to a real
+//! mode of the chip. Boards that do not set a policy sleep in
+//! `SleepState::DeepSleep`.
+//!
+//! `PowerManager` is a `SleepPolicy` where peripherals and capsules vote on
+//! the deepest allowed state with a `SleepVote`, for example a UART that is
+//! receiving needs its high frequency clock and limits sleep to
+//! `SleepState::Sleep`. It also makes sure the chip only enters a state from
+//! which all armed `WakeSource`s can wake it.
+//!
+//! Without votes the chip sleeps in `SleepState::DeepSleep`. Drivers that do
+//! not hold a vote cannot keep the chip from turning off, so `PowerManager`
+//! only enters `SleepState::Off` after the board or a capsule asked for it
+//! with `request_off()`, for example once the user switched the device off.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let alarm_wake = static_init!(
+//!     AlarmWakeSource<'static, nrf52840::rtc::Rtc>,
+//!     AlarmWakeSource::new(&base_peripherals.rtc, SleepState::DeepSleep)
+//! );
+//! let wake_sources = static_init!(
+//!     [&'static dyn WakeSource; 2],
+//!     [alarm_wake, &nrf52840_peripherals.gpio_port[BUTTON_PIN]]
+//! );
+//! let power_manager = static_init!(
+//!     PowerManager<'static>,
+//!     PowerManager::new(SleepState::Off, wake_sources)
+//! );
+//!
+//! // When the device should turn off until the button is pressed:
+//! power_manager.request_off();
+//!
+//! // Before starting the kernel loop:
+//! board_kernel.set_sleep_policy(power_manager, &main_loop_capability);
+//! ```
+
+use core::cell::Cell;
+
+use crate::hil::gpio;
+use crate::hil::time::Alarm;
+use crate::utilities::cells::OptionalCell;
+
+/// Sleep states, from the lightest to the deepest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SleepState {
+    /// The core is stopped, but clocks and peripherals keep running.
+    Sleep = 0,
+    /// The high frequency clocks are stopped, only low power peripherals,
+    /// such as timers on a low frequency clock and GPIO interrupts, keep
+    /// running.
+    DeepSleep = 1,
+    /// The chip is off and only keeps the state needed to wake up. Waking
+    /// from this state resets the chip.
+    Off = 2,
+}
+
+/// Decides how deeply the chip sleeps when the kernel is idle.
+pub trait SleepPolicy {
+    /// Returns the deepest state the chip may enter now.
+    fn sleep_state(&self) -> SleepState {
+        SleepState::DeepSleep
+    }
+
+    /// Called with interrupts disabled right before the chip enters `state`.
+    fn prepare_sleep(&self, _state: SleepState) {}
+}
+
+/// Implement default SleepPolicy trait for unit, which lets the chip sleep as
+/// deep as it can on its own.
+impl SleepPolicy for () {}
+
+/// Source of events that can wake the chip from sleep.
+pub trait WakeSource {
+    /// Whether the source is enabled, so the chip must be able to wake from
+    /// it.
+    fn is_armed(&self) -> bool;
+
+    /// The deepest state the source can wake the chip from.
+    fn deepest_wake_state(&self) -> SleepState;
+
+    /// Configures the source to wake the chip from `state`, for sources that
+    /// need more than their interrupt for this. Called right before the chip
+    /// enters `state`.
+    fn prepare_wake(&self, _state: SleepState) {}
+}
+
+/// A `SleepPolicy` where peripherals vote on the deepest allowed state.
+pub struct PowerManager<'a> {
+    /// The deepest state the board allows.
+    deepest: SleepState,
+    /// Number of votes limiting sleep to `Sleep` and to `DeepSleep`.
+    votes: [Cell<usize>; 2],
+    /// Whether the chip may turn off.
+    off_requested: Cell<bool>,
+    wake_sources: &'a [&'a dyn WakeSource],
+}
+
+impl<'a> PowerManager<'a> {
+    /// `deepest` is the deepest state the board allows. The chip only enters
+    /// `Off` once `request_off()` is called.
+    pub fn new(deepest: SleepState, wake_sources: &'a [&'a dyn WakeSource]) -> PowerManager<'a> {
+        PowerManager {
+            deepest,
+            votes: [Cell::new(0), Cell::new(0)],
+            off_requested: Cell::new(false),
+            wake_sources,
+        }
+    }
+
+    /// Lets the chip turn off the next time the kernel is idle, if nothing
+    /// votes against it and an armed wake source can wake it from `Off`.
+    pub fn request_off(&self) {
+        self.off_requested.set(true);
+    }
+
+    /// Withdraws `request_off()`, the chip sleeps in `DeepSleep` at most.
+    pub fn cancel_off(&self) {
+        self.off_requested.set(false);
+    }
+
+    fn add_vote(&self, state: SleepState) {
+        if let Some(votes) = self.votes.get(state as usize) {
+            votes.set(votes.get() + 1);
+        }
+    }
+
+    fn remove_vote(&self, state: SleepState) {
+        if let Some(votes) = self.votes.get(state as usize) {
+            votes.set(votes.get().saturating_sub(1));
+        }
+    }
+}
+
+impl SleepPolicy for PowerManager<'_> {
+    fn sleep_state(&self) -> SleepState {
+        let voted = if self.votes[SleepState::Sleep as usize].get() > 0 {
+            SleepState::Sleep
+        } else if self.votes[SleepState::DeepSleep as usize].get() > 0 || !self.off_requested.get()
+        {
+            SleepState::DeepSleep
+        } else {
+            SleepState::Off
+        };
+
+        let mut armed = false;
+        let state = self
+            .wake_sources
+            .iter()
+            .filter(|source| source.is_armed())
+            .fold(self.deepest.min(voted), |state, source| {
+                armed = true;
+                state.min(source.deepest_wake_state())
+            });
+
+        // Only reset can wake the chip from `Off` without a wake source.
+        if state == SleepState::Off && !armed {
+            SleepState::DeepSleep
+        } else {
+            state
+        }
+    }
+
+    fn prepare_sleep(&self, state: SleepState) {
+        for source in self.wake_sources.iter() {
+            if source.is_armed() {
+                source.prepare_wake(state);
+            }
+        }
+    }
+}
+
+/// A vote on the deepest state the chip may sleep in.
+///
+/// A peripheral holds one vote and limits sleep while it needs clocks that
+/// deeper states stop, for example during a transfer.
+pub struct SleepVote<'a> {
+    manager: &'a PowerManager<'a>,
+    limit: OptionalCell<SleepState>,
+}
+
+impl<'a> SleepVote<'a> {
+    pub const fn new(manager: &'a PowerManager<'a>) -> SleepVote<'a> {
+        SleepVote {
+            manager,
+            limit: OptionalCell::empty(),
+        }
+    }
+
+    /// Limits sleep to `state` or lighter states, replacing the previous
+    /// limit of this vote.
+    pub fn limit(&self, state: SleepState) {
+        self.release();
+        if state != SleepState::Off {
+            self.manager.add_vote(state);
+            self.limit.set(state);
+        }
+    }
+
+    /// Removes the limit of this vote.
+    pub fn release(&self) {
+        if let Some(state) = self.limit.take() {
+            self.manager.remove_vote(state);
+        }
+    }
+
+    /// The current limit of this vote, if any.
+    pub fn current(&self) -> Option<SleepState> {
+        self.limit.extract()
+    }
+}
+
+/// Wakes the chip when an alarm is armed.
+///
+/// `deepest` is the deepest state the alarm keeps running in on this chip,
+/// typically `DeepSleep` for a timer on the low frequency clock.
+pub struct AlarmWakeSource<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    deepest: SleepState,
+}
+
+impl<'a, A: Alarm<'a>> AlarmWakeSource<'a, A> {
+    pub fn new(alarm: &'a A, deepest: SleepState) -> AlarmWakeSource<'a, A> {
+        AlarmWakeSource { alarm, deepest }
+    }
+}
+
+impl<'a, A: Alarm<'a>> WakeSource for AlarmWakeSource<'a, A> {
+    fn is_armed(&self) -> bool {
+        self.alarm.is_armed()
+    }
+
+    fn deepest_wake_state(&self) -> SleepState {
+        self.deepest
+    }
+}
+
+/// Wakes the chip with an interrupt on a GPIO pin.
+///
+/// The pin is used as a wake source between `enable()` and `disable()`.
+/// `deepest` is the deepest state the GPIO interrupts of the chip work in.
+pub struct GpioWakeSource<'a, P: gpio::Interrupt<'a>> {
+    pin: &'a P,
+    deepest: SleepState,
+    edge: OptionalCell<gpio::InterruptEdge>,
+}
+
+impl<'a, P: gpio::Interrupt<'a>> GpioWakeSource<'a, P> {
+    pub fn new(pin: &'a P, deepest: SleepState) -> GpioWakeSource<'a, P> {
+        GpioWakeSource {
+            pin,
+            deepest,
+            edge: OptionalCell::empty(),
+        }
+    }
+
+    /// Enables the interrupt of the pin and wakes the chip on `edge`.
+    pub fn enable(&self, edge: gpio::InterruptEdge) {
+        self.pin.enable_interrupts(edge);
+        self.edge.set(edge);
+    }
+
+    /// Disables the interrupt of the pin.
+    pub fn disable(&self) {
+        self.pin.disable_interrupts();
+        self.edge.clear();
+    }
+}
+
+impl<'a, P: gpio::Interrupt<'a>> WakeSource for GpioWakeSource<'a, P> {
+    fn is_armed(&self) -> bool {
+        self.edge.is_some()
+    }
+
+    fn deepest_wake_state(&self) -> SleepState {
+        self.deepest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestSource {
+        armed: Cell<bool>,
+        deepest: SleepState,
+    }
+
+    impl WakeSource for TestSource {
+        fn is_armed(&self) -> bool {
+            self.armed.get()
+        }
+
+        fn deepest_wake_state(&self) -> SleepState {
+            self.deepest
+        }
+    }
+
+    #[test]
+    fn votes_limit_sleep_state() {
+        let manager = PowerManager::new(SleepState::DeepSleep, &[]);
+        let uart = SleepVote::new(&manager);
+        let spi = SleepVote::new(&manager);
+        assert_eq!(manager.sleep_state(), SleepState::DeepSleep);
+
+        uart.limit(SleepState::Sleep);
+        spi.limit(SleepState::Sleep);
+        assert_eq!(manager.sleep_state(), SleepState::Sleep);
+        uart.release();
+        assert_eq!(manager.sleep_state(), SleepState::Sleep);
+        spi.limit(SleepState::DeepSleep);
+        assert_eq!(manager.sleep_state(), SleepState::DeepSleep);
+        spi.release();
+        spi.release();
+        assert_eq!(manager.sleep_state(), SleepState::DeepSleep);
+        assert_eq!(spi.current(), None);
+    }
+
+    #[test]
+    fn off_needs_wake_source() {
+        let alarm = TestSource {
+            armed: Cell::new(false),
+            deepest: SleepState::DeepSleep,
+        };
+        let button = TestSource {
+            armed: Cell::new(false),
+            deepest: SleepState::Off,
+        };
+        let sources: [&dyn WakeSource; 2] = [&alarm, &button];
+        let manager = PowerManager::new(SleepState::Off, &sources);
+        manager.request_off();
+        assert_eq!(manager.sleep_state(), SleepState::DeepSleep);
+
+        button.armed.set(true);
+        assert_eq!(manager.sleep_state(), SleepState::Off);
+        alarm.armed.set(true);
+        assert_eq!(manager.sleep_state(), SleepState::DeepSleep);
+
+        let vote = SleepVote::new(&manager);
+        vote.limit(SleepState::Sleep);
+        assert_eq!(manager.sleep_state(), SleepState::Sleep);
+        vote.limit(SleepState::Off);
+        assert_eq!(manager.sleep_state(), SleepState::DeepSleep);
+    }
+
+    #[test]
+    fn off_only_when_requested() {
+        let button = TestSource {
+            armed: Cell::new(true),
+            deepest: SleepState::Off,
+        };
+        let sources: [&dyn WakeSource; 1] = [&button];
+        let manager = PowerManager::new(SleepState::Off, &sources);
+        assert_eq!(manager.sleep_state(), SleepState::DeepSleep);
+
+        manager.request_off();
+        assert_eq!(manager.sleep_state(), SleepState::Off);
+        let vote = SleepVote::new(&manager);
+        vote.limit(SleepState::DeepSleep);
+        assert_eq!(manager.sleep_state(), SleepState::DeepSleep);
+        vote.release();
+        assert_eq!(manager.sleep_state(), SleepState::Off);
+
+        manager.cancel_off();
+        assert_eq!(manager.sleep_state(), SleepState::DeepSleep);
+
+        let manager = PowerManager::new(SleepState::DeepSleep, &sources);
+        manager.request_off();
+        assert_eq!(manager.sleep_state(), SleepState::DeepSleep);
+    }
+}
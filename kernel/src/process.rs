@@ -12,18 +12,23 @@ use crate::ipc;
 use crate::kernel::Kernel;
 use crate::platform::mpu::{self};
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
+use crate::storage_permissions::StoragePermissions;
 use crate::syscall::{self, Syscall, SyscallReturn};
 use crate::upcall::UpcallId;
-use tock_tbf::types::CommandPermissions;
+use tock_tbf::types::{CommandPermissions, TbfFooterV2Credentials};
 
 // Export all process related types via `kernel::process::`.
 pub use crate::process_policies::{
-    PanicFaultPolicy, ProcessFaultPolicy, RestartFaultPolicy, StopFaultPolicy,
-    StopWithDebugFaultPolicy, ThresholdRestartFaultPolicy, ThresholdRestartThenPanicFaultPolicy,
+    CrashRecordFaultPolicy, PanicFaultPolicy, ProcessFaultPolicy, RestartFaultPolicy,
+    StopFaultPolicy, StopWithDebugFaultPolicy, ThresholdRestartFaultPolicy,
+    ThresholdRestartThenPanicFaultPolicy,
 };
 pub use crate::process_printer::{ProcessPrinter, ProcessPrinterContext, ProcessPrinterText};
 pub use crate::process_standard::ProcessStandard;
-pub use crate::process_utilities::{load_processes, load_processes_advanced, ProcessLoadError};
+pub use crate::process_utilities::{
+    load_and_check_processes, load_processes, load_processes_advanced, load_processes_and_restore,
+    ProcessLoadError,
+};
 
 /// Userspace process identifier.
 ///
@@ -167,6 +172,14 @@ impl ProcessId {
             (addresses.flash_non_protected_start, addresses.flash_end)
         })
     }
+
+    /// Get the persistent storage permissions of the app this `ProcessId`
+    /// refers to. Returns `None` if the app does not exist or is not allowed
+    /// to use persistent storage.
+    pub fn get_storage_permissions(&self) -> Option<StoragePermissions> {
+        self.kernel
+            .process_map_or(None, *self, |process| process.get_storage_permissions())
+    }
 }
 
 /// This trait represents a generic process that the Tock scheduler can
@@ -239,6 +252,42 @@ pub trait Process {
     /// Returns how many times this process has been restarted.
     fn get_restart_count(&self) -> usize;
 
+    /// Get the region of the TBF object in flash that credentials cover. This
+    /// spans from the start of the TBF header to the end of the application
+    /// binary.
+    fn get_integrity_region(&self) -> &'static [u8];
+
+    /// Get the region of the TBF object in flash after the application binary
+    /// that holds the footers. This may be empty.
+    fn get_footers(&self) -> &'static [u8];
+
+    /// Mark that the credentials of this process were approved. This moves the
+    /// process from the `CredentialsUnchecked` state to the `Unstarted` state
+    /// and schedules its init function so it starts running. `credentials` is
+    /// the credential that was accepted, or `None` if the process was approved
+    /// without one because the checker does not require credentials.
+    ///
+    /// This does nothing if the process is not in the `CredentialsUnchecked`
+    /// state.
+    fn mark_credentials_pass(&self, credentials: Option<TbfFooterV2Credentials>);
+
+    /// Mark that no credential of this process was approved. This moves the
+    /// process from the `CredentialsUnchecked` state to the
+    /// `CredentialsFailed` state, and it will never be scheduled.
+    ///
+    /// This does nothing if the process is not in the `CredentialsUnchecked`
+    /// state.
+    fn mark_credentials_fail(&self);
+
+    /// Get the credential of this process that the `ProcessCheckerMachine`
+    /// accepted. This is `None` if the credentials of the process were not
+    /// checked, or it was approved without an accepted credential.
+    ///
+    /// Unlike the name of the process, which the author of the application
+    /// chooses, the kernel verified that this credential covers the TBF
+    /// object, so it can be used to identify the application.
+    fn get_credentials(&self) -> Option<TbfFooterV2Credentials>;
+
     /// Get the name of the process. Used for IPC.
     fn get_process_name(&self) -> &'static str;
 
@@ -400,6 +449,14 @@ pub trait Process {
     /// The offset indicates the multiple of 64 command numbers to get permissions for.
     fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions;
 
+    /// Return the persistent storage permissions of this process, or `None`
+    /// if the process is not allowed to use persistent storage.
+    fn get_storage_permissions(&self) -> Option<StoragePermissions>;
+
+    /// Return the timing parameters of this process if it is a periodic
+    /// real-time task, or `None` if its TBF header does not include them.
+    fn get_realtime_parameters(&self) -> Option<RealtimeParameters>;
+
     // mpu
 
     /// Configure the MPU to use the process's allocated regions.
@@ -443,7 +500,8 @@ pub trait Process {
     /// - The process is inactive, or
     /// - There is not enough available memory to do the allocation, or
     /// - The grant_num is invalid, or
-    /// - The grant_num already has an allocated grant.
+    /// - The grant_num already has an allocated grant, or
+    /// - The allocation would exceed the grant quota of `driver_num`.
     fn allocate_grant(
         &self,
         grant_num: usize,
@@ -462,6 +520,8 @@ pub trait Process {
     /// aligned to `align` bytes. This is used for creating custom grants which
     /// are not recorded in the grant pointer array, but are useful for capsules
     /// which need additional process-specific dynamically allocated memory.
+    /// The memory counts towards the quota of the driver whose grant was
+    /// entered last, which is the grant the custom grant is allocated through.
     ///
     /// If successful, return a Some() with an identifier that can be used with
     /// `enter_custom_grant()` to get access to the memory and the pointer to
@@ -472,6 +532,18 @@ pub trait Process {
         align: usize,
     ) -> Option<(ProcessCustomGrantIdentifer, NonNull<u8>)>;
 
+    /// Free a custom grant so that its memory can be reused.
+    ///
+    /// The grant region is compacted after the custom grant is freed, which
+    /// moves the grants and custom grants allocated after it. Grants that
+    /// are entered are not moved, and nothing is moved while a custom grant
+    /// is entered.
+    ///
+    /// This returns an error if the custom grant was already freed, if the
+    /// process is inactive, or if the kernel was built without the
+    /// `grant_compaction` feature.
+    fn free_custom_grant(&self, identifier: ProcessCustomGrantIdentifer) -> Result<(), Error>;
+
     /// Enter the grant based on `grant_num` for this process.
     ///
     /// Entering a grant means getting access to the actual memory for the
@@ -493,6 +565,10 @@ pub trait Process {
     fn enter_custom_grant(&self, identifier: ProcessCustomGrantIdentifer)
         -> Result<*mut u8, Error>;
 
+    /// Opposite of `enter_custom_grant()`. Used to signal that the custom
+    /// grant is no longer entered.
+    fn leave_custom_grant(&self, identifier: ProcessCustomGrantIdentifer);
+
     /// Opposite of `enter_grant()`. Used to signal that the grant is no longer
     /// entered.
     ///
@@ -571,6 +647,56 @@ pub trait Process {
     /// representation. Returns `ErrorCode::FAIL` on an internal error.
     fn get_stored_state(&self, out: &mut [u8]) -> Result<usize, ErrorCode>;
 
+    /// Write the part of a checkpoint of this process that starts at
+    /// `offset` into `out`, and return the length of the whole checkpoint.
+    /// See `checkpoint` for the format. Bytes of `out` past the end of the
+    /// checkpoint are not changed.
+    ///
+    /// The checkpoint reflects the process when each part is read, so the
+    /// process should be stopped until all of it has been read.
+    ///
+    /// Returns `ErrorCode::INVAL` if the process is not running, yielded or
+    /// stopped, and `ErrorCode::NOSUPPORT` if its stored state cannot be
+    /// saved.
+    fn read_checkpoint(&self, offset: usize, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Resume this process from a checkpoint instead of its init function.
+    /// `kernel_id` identifies the running kernel build, and the checkpoint
+    /// must have been stored with the same identifier.
+    ///
+    /// Only the stored registers and the memory up to the app break are
+    /// restored. The process has no grants, and a call to the resume
+    /// function of the checkpoint is its only pending upcall.
+    ///
+    /// Returns `ErrorCode::INVAL` if the process has already started or
+    /// `checkpoint` does not hold a valid checkpoint of this process, in
+    /// which case the process is unchanged.
+    fn restore_checkpoint(&self, checkpoint: &[u8], kernel_id: u32) -> Result<(), ErrorCode>;
+
+    /// Replace the stored state of this process with `state`, in the format
+    /// written by `get_stored_state()`. Debuggers use this to change the
+    /// registers of a process.
+    ///
+    /// Returns `ErrorCode::INVAL` if the process is not stopped or `state` is
+    /// not stored state of this architecture.
+    fn set_stored_state(&self, state: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Copy the memory of this process at `address` into `out`, for
+    /// debuggers. Only the flash of the process and the memory below its app
+    /// break can be read.
+    ///
+    /// Returns `ErrorCode::INVAL` if any byte of the range is outside of
+    /// those regions.
+    fn debug_read_memory(&self, address: usize, out: &mut [u8]) -> Result<(), ErrorCode>;
+
+    /// Write `data` to the memory of this process at `address`, for
+    /// debuggers. Only the memory below the app break of the process can be
+    /// written, its flash cannot.
+    ///
+    /// Returns `ErrorCode::INVAL` if any byte of the range is outside of that
+    /// region.
+    fn debug_write_memory(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode>;
+
     /// Print out the full state of the process: its memory map, its
     /// context, and the state of the memory protection unit (MPU).
     fn print_full_process(&self, writer: &mut dyn Write);
@@ -589,6 +715,21 @@ pub trait Process {
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
+    /// Returns how many times this process has missed the deadline of one of
+    /// its periodic jobs.
+    fn debug_deadline_miss_count(&self) -> usize;
+
+    /// Increment the number of times the process has missed a deadline.
+    fn debug_deadline_missed(&self);
+
+    /// Returns how long this process has run on the CPU since it started, in
+    /// microseconds. Only time measured by the scheduler timer is counted,
+    /// so processes run cooperatively do not accumulate execution time.
+    fn debug_execution_time_us(&self) -> u64;
+
+    /// Add the time the process just ran for to its execution time.
+    fn debug_add_execution_time(&self, execution_time_us: u32);
+
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
@@ -607,13 +748,14 @@ pub trait Process {
 ///
 /// We use this type rather than a direct pointer so that any attempt to access
 /// can ensure the process still exists and is valid, and that the custom grant
-/// has not been freed.
+/// has not been freed. It also allows the custom grant to be moved when the
+/// grant region is compacted.
 ///
 /// The fields of this struct are private so only Process can create this
 /// identifier.
 #[derive(Copy, Clone)]
 pub struct ProcessCustomGrantIdentifer {
-    pub(crate) offset: usize,
+    pub(crate) id: usize,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -703,6 +845,15 @@ pub enum State {
     /// processes yet. It can also happen if an process is terminated and all of
     /// its state is reset as if it has not been executed yet.
     Unstarted,
+
+    /// The process was loaded but the credentials in its TBF footers have not
+    /// been checked yet. The process will not be started until its credentials
+    /// are approved.
+    CredentialsUnchecked,
+
+    /// The credentials of the process were checked and none of them were
+    /// accepted. The process will never be started.
+    CredentialsFailed,
 }
 
 /// A wrapper around `Cell<State>` is used by `Process` to prevent bugs arising
@@ -852,6 +1003,27 @@ pub struct ProcessAddresses {
     /// have reached a lower address, this is only the lowest address seen when
     /// the process calls a syscall.
     pub sram_stack_bottom: Option<usize>,
+
+    /// The highest address the application break has reached since the
+    /// process started. This is the high water mark of the memory the process
+    /// has access to, including its heap.
+    pub sram_app_brk_max: usize,
+    /// The lowest address the start of the grant region has reached since the
+    /// process started. This is the high water mark of the memory the kernel
+    /// has used on behalf of the process.
+    pub sram_grant_start_min: usize,
+}
+
+/// Timing parameters of a process that runs as a periodic real-time task, as
+/// specified in its TBF header. All times are in microseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RealtimeParameters {
+    /// Time between the releases of two consecutive jobs of the process.
+    pub period_us: u32,
+    /// Time after the release of a job by which the job must be complete.
+    pub deadline_us: u32,
+    /// CPU time each job may use.
+    pub budget_us: u32,
 }
 
 /// Collection of process state related to the size in memory of various process
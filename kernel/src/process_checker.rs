@@ -0,0 +1,336 @@
+//! Checking the credentials of processes before they are started.
+//!
+//! Processes can carry credentials, such as hashes or signatures over the
+//! application binary, in footers that follow the binary in the TBF object.
+//! Boards that want to refuse processes that are unsigned or have been
+//! tampered with provide an `AppCredentialsChecker`, which decides whether
+//! an individual credential is acceptable. The `ProcessCheckerMachine` walks
+//! over the footers of every loaded process, asks the checker about each
+//! credential, and starts the process only once a credential is accepted.
+//!
+//! Checkers are asynchronous so they can be built on top of hardware digest
+//! and signature engines (`hil::digest` and `hil::public_key_crypto`).
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let checker = static_init!(
+//!     kernel::process_checker::ProcessCheckerMachine,
+//!     kernel::process_checker::ProcessCheckerMachine::new(board_kernel)
+//! );
+//! checker.set_policy(sha256_checker);
+//! sha256_checker.set_client(checker);
+//!
+//! kernel::process::load_and_check_processes(
+//!     board_kernel,
+//!     chip,
+//!     app_flash,
+//!     app_memory,
+//!     &mut PROCESSES,
+//!     &FAULT_RESPONSE,
+//!     checker,
+//!     &process_management_capability,
+//! )
+//! .unwrap_or_else(|err| {
+//!     debug!("Error loading processes!");
+//!     debug!("{:?}", err);
+//! });
+//! ```
+
+use core::cell::Cell;
+
+use crate::config;
+use crate::debug;
+use crate::errorcode::ErrorCode;
+use crate::kernel::Kernel;
+use crate::process::{Process, ProcessId, State};
+use crate::process_utilities::ProcessLoadError;
+use crate::utilities::cells::OptionalCell;
+
+// Export the credential types so checkers outside of the kernel crate can
+// inspect credentials.
+pub use tock_tbf::types::{TbfFooterV2Credentials, TbfFooterV2CredentialsType};
+
+/// The decision of an `AppCredentialsChecker` about a single credential.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckResult {
+    /// The credential is valid and sufficient for the process to run. No
+    /// further credentials of the process are checked.
+    Accept,
+
+    /// The checker has no opinion about this credential, for example because
+    /// it does not support its format. The next credential is checked.
+    Pass,
+
+    /// The credential is invalid and the process must not run. No further
+    /// credentials of the process are checked.
+    Reject,
+}
+
+/// Receives the result of checking a credential.
+pub trait Client<'a> {
+    /// Called when the check of `credentials` started with
+    /// `check_credentials()` completes.
+    ///
+    /// `binary` is the integrity region passed to `check_credentials()`. An
+    /// `Err` result means the check could not be completed and is treated the
+    /// same as `CheckResult::Pass`.
+    fn check_done(
+        &self,
+        result: Result<CheckResult, ErrorCode>,
+        credentials: TbfFooterV2Credentials,
+        binary: &'a [u8],
+    );
+}
+
+/// A board-supplied policy that decides which credentials are acceptable.
+pub trait AppCredentialsChecker<'a> {
+    /// Set the client that receives `check_done()` callbacks.
+    fn set_client(&self, client: &'a dyn Client<'a>);
+
+    /// Whether processes must have an accepted credential to run.
+    ///
+    /// If this returns `false`, processes for which no credential is accepted
+    /// or rejected (for example because they have no footers at all) are
+    /// still started.
+    fn require_credentials(&self) -> bool;
+
+    /// Check `credentials` against `binary`, the integrity region of the
+    /// process.
+    ///
+    /// If this returns `Ok(())`, `check_done()` will be called with the
+    /// result. If this returns `Err()`, no callback will be called and the
+    /// credential is treated as if the checker passed on it.
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        binary: &'a [u8],
+    ) -> Result<(), (ErrorCode, TbfFooterV2Credentials, &'a [u8])>;
+}
+
+/// Receives the outcome of checking all credentials of a process.
+pub trait ProcessCheckerMachineClient {
+    /// Called once per process after its credentials were checked. `result`
+    /// is `Ok(())` if the process was started, or the reason it was not.
+    fn process_checked(&self, process_id: ProcessId, result: Result<(), ProcessLoadError>);
+}
+
+/// Walks over all processes in the `CredentialsUnchecked` state and checks
+/// their credentials one at a time with the board's `AppCredentialsChecker`.
+pub struct ProcessCheckerMachine {
+    kernel: &'static Kernel,
+    policy: OptionalCell<&'static dyn AppCredentialsChecker<'static>>,
+    client: OptionalCell<&'static dyn ProcessCheckerMachineClient>,
+    /// Position of the next process to check in the list of loaded processes.
+    process: Cell<usize>,
+    /// The process whose credential is being checked. Processes loaded at
+    /// runtime can shift the positions of the others, so the result of a
+    /// check is only applied to this process.
+    checking: OptionalCell<ProcessId>,
+    /// Offset of the next footer to check in the footers of the process.
+    footer: Cell<usize>,
+    /// Whether a walk over the processes is in progress.
+    running: Cell<bool>,
+    /// Whether processes were added during the walk, which may already be
+    /// past their slots, so that it has to start over.
+    restart: Cell<bool>,
+}
+
+impl ProcessCheckerMachine {
+    pub fn new(kernel: &'static Kernel) -> ProcessCheckerMachine {
+        ProcessCheckerMachine {
+            kernel,
+            policy: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+            process: Cell::new(0),
+            checking: OptionalCell::empty(),
+            footer: Cell::new(0),
+            running: Cell::new(false),
+            restart: Cell::new(false),
+        }
+    }
+
+    /// Set the checker that decides which credentials are acceptable.
+    pub fn set_policy(&self, policy: &'static dyn AppCredentialsChecker<'static>) {
+        self.policy.set(policy);
+    }
+
+    /// Set the client that is told the outcome for each process.
+    pub fn set_client(&self, client: &'static dyn ProcessCheckerMachineClient) {
+        self.client.set(client);
+    }
+
+    /// Start checking all processes that are waiting for their credentials to
+    /// be checked. This is called by `load_and_check_processes()`, and by the
+    /// `DynamicProcessLoader` for every process it loads.
+    pub(crate) fn start(&self) {
+        if self.running.get() {
+            self.restart.set(true);
+            return;
+        }
+        self.running.set(true);
+        self.process.set(0);
+        self.footer.set(0);
+        self.next();
+    }
+
+    fn current_process(&self) -> Option<&'static dyn Process> {
+        self.kernel.get_process_iter().nth(self.process.get())
+    }
+
+    /// Check the next credential. Processes are finished in order until a
+    /// credential check is started or all processes have been checked.
+    fn next(&self) {
+        loop {
+            while let Some(process) = self.current_process() {
+                if process.get_state() != State::CredentialsUnchecked {
+                    self.advance_process();
+                    continue;
+                }
+
+                match self.check_next_footer(process) {
+                    // A check was started, wait for `check_done()`.
+                    None => {
+                        self.checking.set(process.processid());
+                        return;
+                    }
+                    Some(result) => self.finish_process(process, result, None),
+                }
+            }
+
+            if !self.restart.replace(false) {
+                break;
+            }
+            self.process.set(0);
+            self.footer.set(0);
+        }
+        self.running.set(false);
+    }
+
+    /// Start checking the next credential of `process`. Returns `None` if a
+    /// check is in progress, or the final result for the process if it has no
+    /// more credentials to check.
+    fn check_next_footer(&self, process: &dyn Process) -> Option<Result<(), ProcessLoadError>> {
+        let policy = match self.policy.extract() {
+            Some(policy) => policy,
+            // Without a policy no credential can ever be accepted.
+            None => return Some(Err(ProcessLoadError::CredentialsNoAccept)),
+        };
+
+        let footers = process.get_footers();
+        let binary = process.get_integrity_region();
+        if binary.is_empty() {
+            // The header does not describe a valid integrity region, so there
+            // is nothing a credential could vouch for.
+            return Some(Err(ProcessLoadError::CredentialsNoAccept));
+        }
+
+        loop {
+            let remaining = match footers.get(self.footer.get()..) {
+                Some(remaining) if remaining.len() > 0 => remaining,
+                _ => break,
+            };
+
+            // Anything that does not parse as a credentials footer (e.g.
+            // padding after the last footer) ends the list of credentials.
+            let (credentials, footer_len) = match tock_tbf::parse::parse_tbf_footer(remaining) {
+                Ok(footer) => footer,
+                Err(_) => break,
+            };
+            self.footer.set(self.footer.get() + footer_len as usize);
+
+            match policy.check_credentials(credentials, binary) {
+                Ok(()) => return None,
+                // The checker cannot check this credential, try the next one.
+                Err(_) => {}
+            }
+        }
+
+        // No credential was accepted or rejected.
+        if policy.require_credentials() {
+            Some(Err(ProcessLoadError::CredentialsNoAccept))
+        } else {
+            Some(Ok(()))
+        }
+    }
+
+    /// Starts or refuses `process`. `credentials` is the credential that was
+    /// accepted, if any.
+    fn finish_process(
+        &self,
+        process: &dyn Process,
+        result: Result<(), ProcessLoadError>,
+        credentials: Option<TbfFooterV2Credentials>,
+    ) {
+        match result {
+            Ok(()) => process.mark_credentials_pass(credentials),
+            Err(_) => process.mark_credentials_fail(),
+        }
+
+        if config::CONFIG.debug_load_processes {
+            match result {
+                Ok(()) => debug!(
+                    "Process {:?} credentials approved",
+                    process.get_process_name()
+                ),
+                Err(ref err) => debug!(
+                    "Process {:?} not started: {:?}",
+                    process.get_process_name(),
+                    err
+                ),
+            }
+        }
+
+        let process_id = process.processid();
+        self.client
+            .map(move |client| client.process_checked(process_id, result));
+
+        self.advance_process();
+    }
+
+    fn advance_process(&self) {
+        self.process.set(self.process.get() + 1);
+        self.footer.set(0);
+    }
+}
+
+impl Client<'static> for ProcessCheckerMachine {
+    fn check_done(
+        &self,
+        result: Result<CheckResult, ErrorCode>,
+        credentials: TbfFooterV2Credentials,
+        _binary: &'static [u8],
+    ) {
+        let process = self
+            .checking
+            .take()
+            .and_then(|processid| self.kernel.get_process(processid))
+            .filter(|process| process.get_state() == State::CredentialsUnchecked);
+        if let Some(process) = process {
+            match result {
+                Ok(CheckResult::Accept) => self.finish_process(process, Ok(()), Some(credentials)),
+                Ok(CheckResult::Reject) => {
+                    self.finish_process(process, Err(ProcessLoadError::CredentialsNoAccept), None)
+                }
+                Ok(CheckResult::Pass) | Err(_) => {
+                    // Keep checking the remaining credentials of this
+                    // process.
+                    match self.check_next_footer(process) {
+                        None => {
+                            self.checking.set(process.processid());
+                            return;
+                        }
+                        Some(result) => self.finish_process(process, result, None),
+                    }
+                }
+            }
+        } else {
+            // The process is gone, so the result is dropped and whatever
+            // process is now at this position is checked from its first
+            // credential.
+            self.footer.set(0);
+        }
+        self.next();
+    }
+}
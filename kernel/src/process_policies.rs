@@ -4,8 +4,10 @@
 //! kernel can use when managing processes. For example, these policies control
 //! decisions such as whether a specific process should be restarted.
 
+use crate::crash_record::CrashRecorder;
 use crate::process;
 use crate::process::Process;
+use crate::utilities::cells::OptionalCell;
 
 /// Generic trait for implementing a policy on what to do when a process faults.
 ///
@@ -105,3 +107,43 @@ impl ProcessFaultPolicy for ThresholdRestartThenPanicFaultPolicy {
         }
     }
 }
+
+/// Implementation of `ProcessFaultPolicy` that stores a crash record of every
+/// process fault with a `CrashRecorder` and then takes the action of the
+/// wrapped policy.
+///
+/// Faults that make the board panic are not recorded here, as the panic
+/// handler records the state of all processes.
+pub struct CrashRecordFaultPolicy<P: ProcessFaultPolicy> {
+    policy: P,
+    recorder: OptionalCell<&'static dyn CrashRecorder>,
+}
+
+impl<P: ProcessFaultPolicy> CrashRecordFaultPolicy<P> {
+    pub fn new(policy: P) -> CrashRecordFaultPolicy<P> {
+        CrashRecordFaultPolicy {
+            policy,
+            recorder: OptionalCell::empty(),
+        }
+    }
+
+    /// Set the recorder that stores the crash records. Faults are not
+    /// recorded until this is called.
+    pub fn set_recorder(&self, recorder: &'static dyn CrashRecorder) {
+        self.recorder.set(recorder);
+    }
+}
+
+impl<P: ProcessFaultPolicy> ProcessFaultPolicy for CrashRecordFaultPolicy<P> {
+    fn action(&self, process: &dyn Process) -> process::FaultAction {
+        let action = self.policy.action(process);
+        match action {
+            process::FaultAction::Panic => {}
+            _ => {
+                self.recorder
+                    .map(|recorder| recorder.record_process_fault(process));
+            }
+        }
+        action
+    }
+}
@@ -9,6 +9,7 @@ use core::fmt::Write;
 use core::ptr::NonNull;
 use core::{mem, ptr, slice, str};
 
+use crate::checkpoint::{self, CheckpointHeader, Section};
 use crate::collections::queue::Queue;
 use crate::collections::ring_buffer::RingBuffer;
 use crate::config;
@@ -19,14 +20,15 @@ use crate::platform::chip::Chip;
 use crate::platform::mpu::{self, MPU};
 use crate::process::{Error, FunctionCall, FunctionCallSource, Process, State, Task};
 use crate::process::{FaultAction, ProcessCustomGrantIdentifer, ProcessId, ProcessStateCell};
-use crate::process::{ProcessAddresses, ProcessSizes};
+use crate::process::{ProcessAddresses, ProcessSizes, RealtimeParameters};
 use crate::process_policies::ProcessFaultPolicy;
 use crate::process_utilities::ProcessLoadError;
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
+use crate::storage_permissions::StoragePermissions;
 use crate::syscall::{self, Syscall, SyscallReturn, UserspaceKernelBoundary};
 use crate::upcall::UpcallId;
 use crate::utilities::cells::{MapCell, NumericCellExt, OptionalCell};
-use tock_tbf::types::CommandPermissions;
+use tock_tbf::types::{CommandPermissions, TbfFooterV2Credentials};
 
 /// State for helping with debugging apps.
 ///
@@ -67,6 +69,18 @@ struct ProcessStandardDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How many deadlines of periodic jobs this process has missed.
+    deadline_miss_count: usize,
+
+    /// How long this process has run on the CPU, in microseconds.
+    execution_time_us: u64,
+
+    /// The highest the application break has been.
+    app_break_max_pointer: *const u8,
+
+    /// The lowest the kernel memory break has been.
+    kernel_memory_break_min_pointer: *const u8,
 }
 
 /// Entry that is stored in the grant pointer table at the top of process
@@ -88,6 +102,161 @@ struct GrantPointerEntry {
     /// The start of the memory location where the grant has been allocated, or
     /// null if the grant has not been allocated.
     grant_ptr: *mut u8,
+
+    /// While the grant is entered, the grant that was entered last before it
+    /// and has not been left yet, or `GrantPointerEntry::NONE`.
+    entered_before: usize,
+}
+
+impl GrantPointerEntry {
+    const NONE: usize = usize::MAX;
+}
+
+/// Header stored right above every allocation in the grant region if the
+/// kernel is built with the `grant_compaction` feature.
+///
+/// The headers let the kernel walk the grant region from its top down to the
+/// kernel memory break, to account the memory used by each grant and to move
+/// allocations when the region is compacted. Without the feature the grant
+/// region holds only the allocations, which never move.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GrantRegionHeader {
+    /// Size of the allocation in bytes, without this header and padding.
+    size: usize,
+
+    /// Alignment of the allocation.
+    align: usize,
+
+    /// The grant the allocation is accounted to.
+    grant_num: usize,
+
+    /// What the allocation holds: `GRANT` for the grant itself, `FREED` for a
+    /// custom grant that was freed, or the identifier of a custom grant.
+    kind: usize,
+}
+
+impl GrantRegionHeader {
+    const GRANT: usize = 0;
+    const FREED: usize = usize::MAX;
+
+    /// Address of an allocation of `size` bytes aligned to `align` whose
+    /// header ends at `top`. This is also the bottom of the allocation.
+    fn data_address(top: usize, size: usize, align: usize) -> usize {
+        top.wrapping_sub(mem::size_of::<Self>()).wrapping_sub(size) & !(align - 1)
+    }
+}
+
+/// An allocation in the grant region.
+#[derive(Clone, Copy)]
+struct GrantRegionBlock {
+    /// The end of the allocation, where its header ends.
+    top: usize,
+    header: GrantRegionHeader,
+}
+
+impl GrantRegionBlock {
+    fn data(&self) -> usize {
+        GrantRegionHeader::data_address(self.top, self.header.size, self.header.align)
+    }
+
+    fn len(&self) -> usize {
+        self.top - self.data()
+    }
+}
+
+/// Iterator over the allocations in the grant region of a process, from the
+/// top of the region down to the kernel memory break.
+#[derive(Clone)]
+struct GrantRegionIter {
+    top: usize,
+    kernel_memory_break: usize,
+}
+
+impl GrantRegionIter {
+    /// Move the allocations up over the memory of freed custom grants, and
+    /// return the new kernel memory break.
+    ///
+    /// Allocations for which `in_use` returns `true` are not moved, and
+    /// neither are the allocations above them. `moved` is called with the
+    /// new address of every allocation that moved.
+    ///
+    /// # Safety
+    ///
+    /// No references may exist to allocations that are not in use.
+    unsafe fn compact(
+        mut self,
+        in_use: impl Fn(&GrantRegionHeader) -> bool,
+        mut moved: impl FnMut(&GrantRegionHeader, usize),
+    ) -> usize {
+        // Only allocations below the lowest one in use can move.
+        let mut start = self.top;
+        for block in self.clone() {
+            if in_use(&block.header) {
+                start = block.data();
+            }
+        }
+        self.top = start;
+
+        // Move each allocation as far up as its alignment allows. Allocations
+        // only move up, so moving one never overwrites the header of the
+        // next one, which the iterator reads afterwards.
+        let mut top = start;
+        for block in self {
+            if block.header.kind == GrantRegionHeader::FREED {
+                continue;
+            }
+            let data = GrantRegionHeader::data_address(top, block.header.size, block.header.align);
+            if top != block.top {
+                // `ptr::copy()` allows the old and new location to overlap.
+                ptr::copy(
+                    block.data() as *const u8,
+                    data as *mut u8,
+                    block.header.size,
+                );
+                #[allow(clippy::cast_ptr_alignment)]
+                ptr::write(
+                    (top - mem::size_of::<GrantRegionHeader>()) as *mut GrantRegionHeader,
+                    block.header,
+                );
+                moved(&block.header, data);
+            }
+            top = data;
+        }
+        top
+    }
+}
+
+impl Iterator for GrantRegionIter {
+    type Item = GrantRegionBlock;
+
+    fn next(&mut self) -> Option<GrantRegionBlock> {
+        if self.top <= self.kernel_memory_break {
+            return None;
+        }
+        // # Safety
+        //
+        // Every allocation between the top of the grant region and the kernel
+        // memory break is stored below its header, so `top` is the end of a
+        // header written by `allocate_in_grant_region_internal()` or
+        // `compact()`. Headers are word aligned.
+        #[allow(clippy::cast_ptr_alignment)]
+        let header = unsafe {
+            ptr::read((self.top - mem::size_of::<GrantRegionHeader>()) as *const GrantRegionHeader)
+        };
+        let block = GrantRegionBlock {
+            top: self.top,
+            header,
+        };
+        // Stop rather than walk outside of the grant region if a header is
+        // corrupted.
+        if block.data() >= self.top || block.data() < self.kernel_memory_break {
+            self.top = self.kernel_memory_break;
+            return None;
+        }
+        self.top = block.data();
+        Some(block)
+    }
 }
 
 /// A type for userspace processes in Tock.
@@ -154,6 +323,20 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// Pointer to the end of the allocated (and MPU protected) grant region.
     kernel_memory_break: Cell<*const u8>,
 
+    /// Identifier given to the next custom grant. Identifiers are not reused
+    /// when the process restarts, so that custom grants allocated before the
+    /// restart cannot be entered.
+    next_custom_grant_id: Cell<usize>,
+
+    /// How many custom grants are currently entered. Allocations are not moved
+    /// while a custom grant is entered.
+    custom_grants_entered: Cell<usize>,
+
+    /// The grant that was entered last and has not been left yet, or
+    /// `GrantPointerEntry::NONE`. Custom grants are allocated through this
+    /// grant.
+    entered_grant: Cell<usize>,
+
     /// Pointer to the end of process RAM that has been sbrk'd to the process.
     app_break: Cell<*const u8>,
 
@@ -167,6 +350,9 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// Collection of pointers to the TBF header in flash.
     header: tock_tbf::types::TbfHeader,
 
+    /// The credential the `ProcessCheckerMachine` accepted for this process.
+    credentials: OptionalCell<TbfFooterV2Credentials>,
+
     /// State saved on behalf of the process each time the app switches to the
     /// kernel.
     stored_state:
@@ -347,6 +533,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn try_restart(&self, completion_code: Option<u32>) {
+        // A process that has not been approved to run must never be started
+        // by a restart.
+        match self.state.get() {
+            State::CredentialsUnchecked | State::CredentialsFailed => return,
+            _ => {}
+        }
+
         // Terminate the process, freeing its state and removing any
         // pending tasks from the scheduler's queue.
         self.terminate(completion_code);
@@ -388,6 +581,58 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.restart_count.get()
     }
 
+    fn get_integrity_region(&self) -> &'static [u8] {
+        // `create()` rejects processes whose binary end is outside of their
+        // flash, but an empty region can never pass a credentials check
+        // should this ever be reached anyway.
+        let binary_end = self.header.get_binary_end() as usize;
+        self.flash.get(..binary_end).unwrap_or(&[])
+    }
+
+    fn get_footers(&self) -> &'static [u8] {
+        let binary_end = self.header.get_binary_end() as usize;
+        self.flash.get(binary_end..).unwrap_or(&[])
+    }
+
+    fn mark_credentials_pass(&self, credentials: Option<TbfFooterV2Credentials>) {
+        if self.state.get() != State::CredentialsUnchecked {
+            return;
+        }
+        self.credentials.insert(credentials);
+
+        // The process is now allowed to run, so we schedule its init function
+        // exactly as `create()` does for processes that are not checked.
+        let flash_protected_size = self.header.get_protected_size() as usize;
+        let flash_app_start = self.flash_start() as usize + flash_protected_size;
+        let init_fn = self.flash_start() as usize + self.header.get_init_function_offset() as usize;
+
+        self.state.update(State::Unstarted);
+
+        self.tasks.map(|tasks| {
+            tasks.enqueue(Task::FunctionCall(FunctionCall {
+                source: FunctionCallSource::Kernel,
+                pc: init_fn,
+                argument0: flash_app_start,
+                argument1: self.mem_start() as usize,
+                argument2: self.memory_len,
+                argument3: self.app_break.get() as usize,
+            }));
+        });
+
+        // Mark that the process is ready to run.
+        self.kernel.increment_work();
+    }
+
+    fn mark_credentials_fail(&self) {
+        if self.state.get() == State::CredentialsUnchecked {
+            self.state.update(State::CredentialsFailed);
+        }
+    }
+
+    fn get_credentials(&self) -> Option<TbfFooterV2Credentials> {
+        self.credentials.extract()
+    }
+
     fn has_tasks(&self) -> bool {
         self.tasks.map_or(false, |tasks| tasks.has_elements())
     }
@@ -409,6 +654,20 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.header.get_command_permissions(driver_num, offset)
     }
 
+    fn get_storage_permissions(&self) -> Option<StoragePermissions> {
+        StoragePermissions::from_header(&self.header)
+    }
+
+    fn get_realtime_parameters(&self) -> Option<RealtimeParameters> {
+        self.header
+            .get_realtime_parameters()
+            .map(|(period_us, deadline_us, budget_us)| RealtimeParameters {
+                period_us,
+                deadline_us,
+                budget_us,
+            })
+    }
+
     fn number_writeable_flash_regions(&self) -> usize {
         self.header.number_writeable_flash_regions()
     }
@@ -528,6 +787,11 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                 } else {
                     let old_break = self.app_break.get();
                     self.app_break.set(new_break);
+                    self.debug.map(|debug| {
+                        if new_break > debug.app_break_max_pointer {
+                            debug.app_break_max_pointer = new_break;
+                        }
+                    });
                     self.chip.mpu().configure_mpu(&config, &self.processid());
                     Ok(old_break)
                 }
@@ -742,7 +1006,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
 
         // Use the shared grant allocator function to actually allocate memory.
         // Returns `None` if the allocation cannot be created.
-        if let Some(grant_ptr) = self.allocate_in_grant_region_internal(size, align) {
+        if let Some(grant_ptr) = self.allocate_in_grant_region(
+            grant_num,
+            driver_num,
+            GrantRegionHeader::GRANT,
+            size,
+            align,
+        ) {
             // Update the grant pointer to the address of the new allocation.
             self.grant_pointers.map_or(None, |grant_pointers| {
                 // Implement `grant_pointers[grant_num] = grant_ptr` without a
@@ -774,20 +1044,83 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             return None;
         }
 
+        // The custom grant is accounted to the grant it is allocated through,
+        // which is the grant entered last.
+        let grant_num = self.entered_grant.get();
+
+        // That grant must be allocated, and its driver's quota applies.
+        let driver_num = self.grant_pointers.map_or(None, |grant_pointers| {
+            grant_pointers
+                .get(grant_num)
+                .filter(|grant_entry| !grant_entry.grant_ptr.is_null())
+                .map(|grant_entry| grant_entry.driver_num)
+        })?;
+
+        if !config::CONFIG.grant_compaction {
+            // Custom grants never move, so the identifier is the offset of the
+            // custom grant from the end of process memory.
+            return self
+                .allocate_in_grant_region(grant_num, driver_num, 0, size, align)
+                .map(|ptr| {
+                    let id = self.mem_end() as usize - ptr.as_ptr() as usize;
+                    (ProcessCustomGrantIdentifer { id }, ptr)
+                });
+        }
+
+        // Create the identifier that the caller will use to get access to
+        // this custom grant in the future.
+        let id = self.next_custom_grant_id.get();
+        self.next_custom_grant_id
+            .set(if id + 1 == GrantRegionHeader::FREED {
+                1
+            } else {
+                id + 1
+            });
+
         // Use the shared grant allocator function to actually allocate memory.
         // Returns `None` if the allocation cannot be created.
-        if let Some(ptr) = self.allocate_in_grant_region_internal(size, align) {
-            // Create the identifier that the caller will use to get access to
-            // this custom grant in the future.
-            let identifier = self.create_custom_grant_identifier(ptr);
-
-            Some((identifier, ptr))
+        if let Some(ptr) = self.allocate_in_grant_region(grant_num, driver_num, id, size, align) {
+            Some((ProcessCustomGrantIdentifer { id }, ptr))
         } else {
             // Could not allocate memory for the custom grant.
             None
         }
     }
 
+    fn free_custom_grant(&self, identifier: ProcessCustomGrantIdentifer) -> Result<(), Error> {
+        // Do not modify an inactive process.
+        if !self.is_active() {
+            return Err(Error::InactiveApp);
+        }
+
+        // Without headers in the grant region the memory cannot be reused.
+        if !config::CONFIG.grant_compaction {
+            return Err(Error::KernelError);
+        }
+
+        let block = self
+            .find_custom_grant(identifier)
+            .ok_or(Error::AddressOutOfBounds)?;
+
+        // # Safety
+        //
+        // The header of the custom grant is in the grant region, which only
+        // the kernel accesses, and no reference to it exists.
+        #[allow(clippy::cast_ptr_alignment)]
+        unsafe {
+            ptr::write(
+                (block.top - mem::size_of::<GrantRegionHeader>()) as *mut GrantRegionHeader,
+                GrantRegionHeader {
+                    kind: GrantRegionHeader::FREED,
+                    ..block.header
+                },
+            );
+        }
+
+        self.compact_grant_region();
+        Ok(())
+    }
+
     fn enter_grant(&self, grant_num: usize) -> Result<*mut u8, Error> {
         // Do not try to access the grant region of inactive process.
         if !self.is_active() {
@@ -818,6 +1151,11 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                             // grant pointer.
                             grant_entry.grant_ptr = (grant_ptr as usize | 0x1) as *mut u8;
 
+                            // Grants are left in the reverse order they were
+                            // entered, so remember which grant to go back to.
+                            grant_entry.entered_before = self.entered_grant.get();
+                            self.entered_grant.set(grant_num);
+
                             // And we return the grant pointer to the entered
                             // grant.
                             Ok(grant_ptr)
@@ -837,14 +1175,28 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             return Err(Error::InactiveApp);
         }
 
-        // Get the address of the custom grant based on the identifier.
-        let custom_grant_address = self.get_custom_grant_address(identifier);
+        // Get the address of the custom grant based on the identifier. This
+        // fails if the custom grant was freed.
+        let custom_grant_address = if config::CONFIG.grant_compaction {
+            self.find_custom_grant(identifier)
+                .ok_or(Error::AddressOutOfBounds)?
+                .data()
+        } else {
+            self.mem_end() as usize - identifier.id
+        };
+
+        // Keep the custom grant from moving until it is left.
+        self.custom_grants_entered.increment();
 
-        // We never deallocate custom grants and only we can change the
-        // `identifier` so we know this is a valid address for the custom grant.
         Ok(custom_grant_address as *mut u8)
     }
 
+    fn leave_custom_grant(&self, _identifier: ProcessCustomGrantIdentifer) {
+        if self.custom_grants_entered.get() > 0 {
+            self.custom_grants_entered.decrement();
+        }
+    }
+
     fn leave_grant(&self, grant_num: usize) {
         // Do not modify an inactive process.
         if !self.is_active() {
@@ -863,6 +1215,10 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                     // lowest bit back to zero and save this as the grant
                     // pointer.
                     grant_entry.grant_ptr = (grant_ptr as usize & !0x1) as *mut u8;
+
+                    if self.entered_grant.get() == grant_num {
+                        self.entered_grant.set(grant_entry.entered_before);
+                    }
                 }
                 None => {}
             }
@@ -1058,6 +1414,23 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             .map(|debug| debug.timeslice_expiration_count += 1);
     }
 
+    fn debug_deadline_miss_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.deadline_miss_count)
+    }
+
+    fn debug_deadline_missed(&self) {
+        self.debug.map(|debug| debug.deadline_miss_count += 1);
+    }
+
+    fn debug_execution_time_us(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.execution_time_us)
+    }
+
+    fn debug_add_execution_time(&self, execution_time_us: u32) {
+        self.debug
+            .map(|debug| debug.execution_time_us += execution_time_us as u64);
+    }
+
     fn debug_syscall_called(&self, last_syscall: Syscall) {
         self.debug.map(|debug| {
             debug.syscall_count += 1;
@@ -1087,6 +1460,16 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             sram_stack_bottom: self.debug.map_or(None, |debug| {
                 debug.app_stack_min_pointer.map(|p| p as usize)
             }),
+            sram_app_brk_max: self
+                .debug
+                .map_or(self.app_memory_break() as usize, |debug| {
+                    debug.app_break_max_pointer as usize
+                }),
+            sram_grant_start_min: self
+                .debug
+                .map_or(self.kernel_memory_break() as usize, |debug| {
+                    debug.kernel_memory_break_min_pointer as usize
+                }),
         }
     }
 
@@ -1200,6 +1583,210 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             })
             .unwrap_or(Err(ErrorCode::FAIL))
     }
+
+    fn set_stored_state(&self, state: &[u8]) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::StoppedRunning | State::StoppedYielded => {}
+            _ => return Err(ErrorCode::INVAL),
+        }
+        self.stored_state
+            .map(|stored_state| {
+                self.chip
+                    .userspace_kernel_boundary()
+                    .load_context(stored_state, state)
+                    .or(Err(ErrorCode::INVAL))
+            })
+            .unwrap_or(Err(ErrorCode::FAIL))
+    }
+
+    fn debug_read_memory(&self, address: usize, out: &mut [u8]) -> Result<(), ErrorCode> {
+        let end = address.checked_add(out.len()).ok_or(ErrorCode::INVAL)?;
+        let flash_start = self.flash_start() as usize;
+        if address >= flash_start && end <= self.flash_end() as usize {
+            let offset = address - flash_start;
+            out.copy_from_slice(&self.flash[offset..offset + out.len()]);
+            Ok(())
+        } else if address >= self.mem_start() as usize && end <= self.app_break.get() as usize {
+            // # Safety
+            //
+            // The range is within the memory the process can access. The
+            // process is not running, and the kernel only references this
+            // memory while it handles a system call of the process.
+            unsafe {
+                ptr::copy_nonoverlapping(address as *const u8, out.as_mut_ptr(), out.len());
+            }
+            Ok(())
+        } else {
+            Err(ErrorCode::INVAL)
+        }
+    }
+
+    fn debug_write_memory(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        let end = address.checked_add(data.len()).ok_or(ErrorCode::INVAL)?;
+        if address >= self.mem_start() as usize && end <= self.app_break.get() as usize {
+            // # Safety
+            //
+            // Same as for `debug_read_memory()`.
+            unsafe {
+                ptr::copy_nonoverlapping(data.as_ptr(), address as *mut u8, data.len());
+            }
+            Ok(())
+        } else {
+            Err(ErrorCode::INVAL)
+        }
+    }
+
+    fn read_checkpoint(&self, offset: usize, out: &mut [u8]) -> Result<usize, ErrorCode> {
+        let yielded = match self.state.get() {
+            State::Running | State::StoppedRunning => false,
+            State::Yielded | State::StoppedYielded => true,
+            _ => return Err(ErrorCode::INVAL),
+        };
+
+        let mut context = [0; checkpoint::CONTEXT_MAX_LENGTH];
+        let context_length = self.get_stored_state(&mut context)?;
+
+        let mem_start = self.mem_start() as usize;
+        let app_break = self.app_break.get() as usize;
+
+        // Hashing the TBF object is only worth it if the header is read.
+        let tbf_hash = if offset < checkpoint::HEADER_LENGTH {
+            checkpoint::hash(self.get_integrity_region())
+        } else {
+            0
+        };
+        let header = CheckpointHeader {
+            length: CheckpointHeader::checkpoint_length(&[context_length, app_break - mem_start]),
+            tbf_hash,
+            flash_start: self.flash_start() as usize,
+            memory_start: mem_start,
+            memory_length: self.memory_len,
+            app_break: app_break - mem_start,
+            allow_high_water_mark: self.allow_high_water_mark.get() as usize - mem_start,
+            context_length,
+            yielded,
+            // Filled in by `checkpoint::seal()` together with the CRC.
+            kernel_id: 0,
+            resume_function: 0,
+            resume_argument: 0,
+        }
+        .to_bytes();
+
+        let sections = [
+            Section::Bytes(&header),
+            Section::Bytes(&context[..context_length]),
+            Section::Memory {
+                start: self.mem_start(),
+                length: app_break - mem_start,
+            },
+        ];
+        // # Safety
+        //
+        // The memory up to the app break is memory of this process.
+        Ok(unsafe { checkpoint::read_sections(&sections, offset, out) })
+    }
+
+    fn restore_checkpoint(&self, checkpoint: &[u8], kernel_id: u32) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Unstarted {
+            return Err(ErrorCode::INVAL);
+        }
+        let header = CheckpointHeader::parse(checkpoint).ok_or(ErrorCode::INVAL)?;
+
+        // The checkpoint must be of this process, at the same place in flash
+        // and RAM, and stored by this kernel build. Its memory must end below
+        // the memory the kernel uses in this boot, and it must resume in the
+        // application.
+        let mem_start = self.mem_start() as usize;
+        let kernel_memory_break = self.kernel_memory_break.get() as usize;
+        let resume_function = header.resume_function & !0x1;
+        if header.kernel_id != kernel_id
+            || header.tbf_hash != checkpoint::hash(self.get_integrity_region())
+            || header.flash_start != self.flash_start() as usize
+            || header.memory_start != mem_start
+            || header.memory_length != self.memory_len
+            || header.allow_high_water_mark > header.app_break
+            || header.app_break > kernel_memory_break - mem_start
+            || resume_function < self.flash_non_protected_start() as usize
+            || resume_function >= self.flash_end() as usize
+            || header.length
+                != CheckpointHeader::checkpoint_length(&[header.context_length, header.app_break])
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let context_start = checkpoint::HEADER_LENGTH;
+        let app_memory_start = context_start + checkpoint::padded(header.context_length);
+        let context = &checkpoint[context_start..context_start + header.context_length];
+        let app_memory = &checkpoint[app_memory_start..app_memory_start + header.app_break];
+
+        let mut stored_state = Default::default();
+        self.chip
+            .userspace_kernel_boundary()
+            .load_context(&mut stored_state, context)
+            .or(Err(ErrorCode::INVAL))?;
+
+        let app_break = self.mem_start().wrapping_add(header.app_break);
+        self.mpu_config.map_or(Err(ErrorCode::FAIL), |config| {
+            self.chip
+                .mpu()
+                .update_app_memory_region(
+                    app_break,
+                    self.kernel_memory_break.get(),
+                    mpu::Permissions::ReadWriteOnly,
+                    config,
+                )
+                .or(Err(ErrorCode::NOMEM))
+        })?;
+
+        // # Safety
+        //
+        // The memory was checked to be below the kernel memory break of this
+        // process, which has not run yet, so nothing references it.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                app_memory.as_ptr(),
+                self.mem_start() as *mut u8,
+                app_memory.len(),
+            );
+        }
+
+        self.app_break.set(app_break);
+        self.allow_high_water_mark
+            .set(self.mem_start().wrapping_add(header.allow_high_water_mark));
+        self.debug.map(|debug| {
+            debug.app_break_max_pointer = app_break;
+        });
+
+        self.stored_state.replace(stored_state);
+
+        // The process resumes where it was, so its init function must not be
+        // called. Its grants, and with them its subscriptions, are gone, so
+        // it is told with a call to its resume function instead.
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        for _ in 0..tasks_len {
+            self.kernel.decrement_work();
+        }
+        self.tasks.map(|tasks| {
+            tasks.empty();
+            tasks.enqueue(Task::FunctionCall(FunctionCall {
+                source: FunctionCallSource::Kernel,
+                pc: header.resume_function,
+                argument0: 0,
+                argument1: 0,
+                argument2: 0,
+                argument3: header.resume_argument,
+            }));
+        });
+        self.kernel.increment_work();
+
+        self.state.update(if header.yielded {
+            State::Yielded
+        } else {
+            State::Running
+        });
+
+        Ok(())
+    }
 }
 
 impl<C: 'static + Chip> ProcessStandard<'_, C> {
@@ -1219,6 +1806,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         remaining_memory: &'a mut [u8],
         fault_policy: &'static dyn ProcessFaultPolicy,
         require_kernel_version: bool,
+        check_credentials: bool,
         index: usize,
     ) -> Result<(Option<&'static dyn Process>, &'a mut [u8]), ProcessLoadError> {
         // Get a slice for just the app header.
@@ -1305,6 +1893,22 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             }
         }
 
+        // The footers start at the end of the binary, and everything before
+        // it is covered by the credentials. An end outside of the TBF object
+        // or inside its header would make that region meaningless.
+        let binary_end = tbf_header.get_binary_end() as usize;
+        if binary_end > app_flash.len() || binary_end < header_length {
+            if config::CONFIG.debug_load_processes {
+                debug!(
+                    "WARN process {:?} not loaded as its binary end {:#x} is outside of its {:#x} bytes",
+                    process_name.unwrap_or("(no name)"),
+                    binary_end,
+                    app_flash.len()
+                );
+            }
+            return Err(ProcessLoadError::InvalidBinaryEnd);
+        }
+
         // Otherwise, actually load the app.
         let process_ram_requested_size = tbf_header.get_minimum_app_ram_size() as usize;
         let init_fn = app_flash
@@ -1514,6 +2118,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         for grant_entry in grant_pointers.iter_mut() {
             grant_entry.driver_num = 0;
             grant_entry.grant_ptr = ptr::null_mut();
+            grant_entry.entered_before = GrantPointerEntry::NONE;
         }
 
         // Now that we know we have the space we can setup the memory for the
@@ -1560,7 +2165,11 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.memory_start = app_memory.as_ptr();
         process.memory_len = app_memory.len();
         process.header = tbf_header;
+        process.credentials = OptionalCell::empty();
         process.kernel_memory_break = Cell::new(kernel_memory_break);
+        process.next_custom_grant_id = Cell::new(1);
+        process.custom_grants_entered = Cell::new(0);
+        process.entered_grant = Cell::new(GrantPointerEntry::NONE);
         process.app_break = Cell::new(initial_app_brk);
         process.grant_pointers = MapCell::new(grant_pointers);
 
@@ -1595,21 +2204,32 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             last_syscall: None,
             dropped_upcall_count: 0,
             timeslice_expiration_count: 0,
+            deadline_miss_count: 0,
+            execution_time_us: 0,
+            app_break_max_pointer: initial_app_brk,
+            kernel_memory_break_min_pointer: kernel_memory_break,
         });
 
         let flash_protected_size = process.header.get_protected_size() as usize;
         let flash_app_start_addr = app_flash.as_ptr() as usize + flash_protected_size;
 
-        process.tasks.map(|tasks| {
-            tasks.enqueue(Task::FunctionCall(FunctionCall {
-                source: FunctionCallSource::Kernel,
-                pc: init_fn,
-                argument0: flash_app_start_addr,
-                argument1: process.memory_start as usize,
-                argument2: process.memory_len,
-                argument3: process.app_break.get() as usize,
-            }));
-        });
+        // If the credentials of the process must be checked, the process may
+        // not start until they are approved. In that case the init function is
+        // scheduled by `mark_credentials_pass()` instead.
+        if check_credentials {
+            process.state.update(State::CredentialsUnchecked);
+        } else {
+            process.tasks.map(|tasks| {
+                tasks.enqueue(Task::FunctionCall(FunctionCall {
+                    source: FunctionCallSource::Kernel,
+                    pc: init_fn,
+                    argument0: flash_app_start_addr,
+                    argument1: process.memory_start as usize,
+                    argument2: process.memory_len,
+                    argument3: process.app_break.get() as usize,
+                }));
+            });
+        }
 
         // Handle any architecture-specific requirements for a new process.
         //
@@ -1640,7 +2260,9 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             }
         };
 
-        kernel.increment_work();
+        if !check_credentials {
+            kernel.increment_work();
+        }
 
         // Return the process object and a remaining memory for processes slice.
         Ok((Some(process), unused_memory))
@@ -1667,6 +2289,8 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             debug.last_syscall = None;
             debug.dropped_upcall_count = 0;
             debug.timeslice_expiration_count = 0;
+            debug.deadline_miss_count = 0;
+            debug.execution_time_us = 0;
         });
 
         // FLASH
@@ -1752,6 +2376,10 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             .wrapping_add(app_mpu_mem_len)
             .wrapping_sub(initial_kernel_memory_size);
         self.kernel_memory_break.set(kernel_brk);
+        self.debug.map(|debug| {
+            debug.app_break_max_pointer = app_brk;
+            debug.kernel_memory_break_min_pointer = kernel_brk;
+        });
         // High water mark for `allow`ed memory is reset to the start of the
         // process's memory region.
         self.allow_high_water_mark.set(app_mpu_mem_start);
@@ -1839,37 +2467,87 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             for grant_entry in grant_pointers.iter_mut() {
                 grant_entry.driver_num = 0;
                 grant_entry.grant_ptr = ptr::null_mut();
+                grant_entry.entered_before = GrantPointerEntry::NONE;
             }
         });
+        self.entered_grant.set(GrantPointerEntry::NONE);
+    }
+
+    /// Allocate memory in a process's grant region for the grant `grant_num`
+    /// of the driver `driver_num`.
+    ///
+    /// If there is not enough memory the grant region is compacted, which may
+    /// free enough memory for the allocation. Quotas and compaction require
+    /// the `grant_compaction` feature.
+    fn allocate_in_grant_region(
+        &self,
+        grant_num: usize,
+        driver_num: usize,
+        kind: usize,
+        size: usize,
+        align: usize,
+    ) -> Option<NonNull<u8>> {
+        if !config::CONFIG.grant_compaction {
+            return self.allocate_in_grant_region_internal(grant_num, None, kind, size, align);
+        }
+
+        let quota = self.kernel.get_grant_quota(driver_num);
+        self.allocate_in_grant_region_internal(grant_num, quota, kind, size, align)
+            .or_else(|| {
+                if self.compact_grant_region() {
+                    self.allocate_in_grant_region_internal(grant_num, quota, kind, size, align)
+                } else {
+                    None
+                }
+            })
     }
 
     /// Allocate memory in a process's grant region.
     ///
     /// Ensures that the allocation is of `size` bytes and aligned to `align`
-    /// bytes.
+    /// bytes. With the `grant_compaction` feature, this also stores a header
+    /// recording `grant_num` and `kind` above the allocation.
     ///
-    /// If there is not enough memory, or the MPU cannot isolate the process
-    /// accessible region from the new kernel memory break after doing the
-    /// allocation, then this will return `None`.
-    fn allocate_in_grant_region_internal(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
+    /// If there is not enough memory, the memory used by `grant_num` would
+    /// exceed `quota`, or the MPU cannot isolate the process accessible region
+    /// from the new kernel memory break after doing the allocation, then this
+    /// will return `None`.
+    fn allocate_in_grant_region_internal(
+        &self,
+        grant_num: usize,
+        quota: Option<usize>,
+        kind: usize,
+        size: usize,
+        align: usize,
+    ) -> Option<NonNull<u8>> {
         self.mpu_config.and_then(|mut config| {
-            // First, compute the candidate new pointer. Note that at this point
-            // we have not yet checked whether there is space for this
-            // allocation or that it meets alignment requirements.
-            let new_break_unaligned = self.kernel_memory_break.get().wrapping_sub(size);
-
             // Our minimum alignment requirement is two bytes, so that the
             // lowest bit of the address will always be zero and we can use it
-            // as a flag. It doesn't hurt to increase the alignment (except for
-            // potentially a wasted byte) so we make sure `align` is at least
-            // two.
-            let align = cmp::max(align, 2);
+            // as a flag. The header stored above every allocation must be word
+            // aligned, so with headers we make sure `align` is at least the
+            // alignment of the header.
+            let align = if config::CONFIG.grant_compaction {
+                cmp::max(align, mem::align_of::<GrantRegionHeader>())
+            } else {
+                cmp::max(align, 2)
+            };
+
+            // Compute the candidate new pointer, below the header of the
+            // allocation if there is one. The alignment must be a power of
+            // two. Note that at this point we have not yet checked whether
+            // there is space for this allocation.
+            let kernel_memory_break = self.kernel_memory_break.get() as usize;
+            let new_break = if config::CONFIG.grant_compaction {
+                GrantRegionHeader::data_address(kernel_memory_break, size, align)
+            } else {
+                kernel_memory_break.wrapping_sub(size) & !(align - 1)
+            } as *const u8;
 
-            // The alignment must be a power of two, 2^a. The expression
-            // `!(align - 1)` then returns a mask with leading ones, followed by
-            // `a` trailing zeros.
-            let alignment_mask = !(align - 1);
-            let new_break = (new_break_unaligned as usize & alignment_mask) as *const u8;
+            // Verify the driver stays within its quota.
+            let over_quota = quota.map_or(false, |quota| {
+                let len = kernel_memory_break.wrapping_sub(new_break as usize);
+                self.grant_region_usage(grant_num).saturating_add(len) > quota
+            });
 
             // Verify there is space for this allocation
             if new_break < self.app_break.get() {
@@ -1877,6 +2555,8 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             // Verify it didn't wrap around
             } else if new_break > self.kernel_memory_break.get() {
                 None
+            } else if over_quota {
+                None
             // Verify this is compatible with the MPU.
             } else if let Err(_) = self.chip.mpu().update_app_memory_region(
                 self.app_break.get(),
@@ -1891,6 +2571,35 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
                 // We always allocate down, so we must lower the
                 // kernel_memory_break.
                 self.kernel_memory_break.set(new_break);
+                self.debug.map(|debug| {
+                    if new_break < debug.kernel_memory_break_min_pointer {
+                        debug.kernel_memory_break_min_pointer = new_break;
+                    }
+                });
+
+                // Record the allocation in its header.
+                //
+                // # Safety
+                //
+                // The header is between the new and the old kernel memory
+                // break, so it is in the grant region which only the kernel
+                // accesses. The old break is word aligned, as every
+                // allocation with a header is.
+                if config::CONFIG.grant_compaction {
+                    #[allow(clippy::cast_ptr_alignment)]
+                    unsafe {
+                        ptr::write(
+                            (kernel_memory_break - mem::size_of::<GrantRegionHeader>())
+                                as *mut GrantRegionHeader,
+                            GrantRegionHeader {
+                                size,
+                                align,
+                                grant_num,
+                                kind,
+                            },
+                        );
+                    }
+                }
 
                 // We need `grant_ptr` as a mutable pointer.
                 let grant_ptr = new_break as *mut u8;
@@ -1905,30 +2614,86 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         })
     }
 
-    /// Create the identifier for a custom grant that grant.rs uses to access
-    /// the custom grant.
-    ///
-    /// We create this identifier by calculating the number of bytes between
-    /// where the custom grant starts and the end of the process memory.
-    fn create_custom_grant_identifier(&self, ptr: NonNull<u8>) -> ProcessCustomGrantIdentifer {
-        let custom_grant_address = ptr.as_ptr() as usize;
-        let process_memory_end = self.mem_end() as usize;
+    /// The allocations in the grant region, from the oldest one right below
+    /// the kernel's data structures at the end of process memory down to the
+    /// kernel memory break.
+    fn grant_region_blocks(&self) -> GrantRegionIter {
+        // The grant region starts below the grant pointers, the upcall queue
+        // and the process struct, see `create()`.
+        let grant_ptrs_offset =
+            mem::size_of::<GrantPointerEntry>() * self.kernel.get_grant_count_and_finalize();
+        let initial_kernel_memory_size =
+            grant_ptrs_offset + Self::CALLBACKS_OFFSET + Self::PROCESS_STRUCT_OFFSET;
 
-        ProcessCustomGrantIdentifer {
-            offset: process_memory_end - custom_grant_address,
+        GrantRegionIter {
+            top: self.mem_end() as usize - initial_kernel_memory_size,
+            kernel_memory_break: self.kernel_memory_break.get() as usize,
         }
     }
 
-    /// Use a ProcessCustomGrantIdentifer to find the address of the custom
-    /// grant.
+    /// Find the custom grant with the identifier, if it was not freed.
+    fn find_custom_grant(
+        &self,
+        identifier: ProcessCustomGrantIdentifer,
+    ) -> Option<GrantRegionBlock> {
+        self.grant_region_blocks()
+            .find(|block| block.header.kind == identifier.id)
+    }
+
+    /// Number of bytes in the grant region used by the grant `grant_num` and
+    /// the custom grants accounted to it.
+    fn grant_region_usage(&self, grant_num: usize) -> usize {
+        self.grant_region_blocks()
+            .filter(|block| {
+                block.header.kind != GrantRegionHeader::FREED && block.header.grant_num == grant_num
+            })
+            .map(|block| block.len())
+            .sum()
+    }
+
+    /// Compact the grant region by moving allocations up over the memory of
+    /// freed custom grants, and raise the kernel memory break accordingly.
     ///
-    /// This reverses `create_custom_grant_identifier()`.
-    fn get_custom_grant_address(&self, identifier: ProcessCustomGrantIdentifer) -> usize {
-        let process_memory_end = self.mem_end() as usize;
+    /// Allocations that may be referenced, entered grants and all custom
+    /// grants while one of them is entered, are not moved. Returns `true` if
+    /// memory was reclaimed.
+    fn compact_grant_region(&self) -> bool {
+        let custom_grant_entered = self.custom_grants_entered.get() > 0;
+        let in_use = |header: &GrantRegionHeader| match header.kind {
+            GrantRegionHeader::FREED => false,
+            GrantRegionHeader::GRANT => {
+                custom_grant_entered
+                    || self.grant_pointers.map_or(true, |grant_pointers| {
+                        grant_pointers
+                            .get(header.grant_num)
+                            .map_or(true, |grant_entry| {
+                                grant_entry.grant_ptr as usize & 0x1 == 0x1
+                            })
+                    })
+            }
+            _ => custom_grant_entered,
+        };
+        let moved = |header: &GrantRegionHeader, data: usize| {
+            if header.kind == GrantRegionHeader::GRANT {
+                self.grant_pointers.map(|grant_pointers| {
+                    if let Some(grant_entry) = grant_pointers.get_mut(header.grant_num) {
+                        grant_entry.grant_ptr = data as *mut u8;
+                    }
+                });
+            }
+        };
 
-        // Subtract the offset in the identifier from the end of the process
-        // memory to get the address of the custom grant.
-        process_memory_end - identifier.offset
+        // # Safety
+        //
+        // The grant region only holds allocations made by
+        // `allocate_in_grant_region_internal()`, and allocations that may be
+        // referenced are in use.
+        let kernel_memory_break = unsafe { self.grant_region_blocks().compact(in_use, moved) };
+
+        let reclaimed = kernel_memory_break != self.kernel_memory_break.get() as usize;
+        self.kernel_memory_break
+            .set(kernel_memory_break as *const u8);
+        reclaimed
     }
 
     /// Check if the process is active.
@@ -1942,8 +2707,13 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
     /// if the process faults and is in an invalid state, or if the process
     /// explicitly exits.
     fn is_active(&self) -> bool {
-        let current_state = self.state.get();
-        current_state != State::Terminated && current_state != State::Faulted
+        match self.state.get() {
+            State::Terminated
+            | State::Faulted
+            | State::CredentialsUnchecked
+            | State::CredentialsFailed => false,
+            _ => true,
+        }
     }
 
     /// The start address of allocated RAM for this process.
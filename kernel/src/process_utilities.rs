@@ -4,11 +4,13 @@ use core::convert::TryInto;
 use core::fmt;
 
 use crate::capabilities::ProcessManagementCapability;
+use crate::checkpoint::CheckpointRegion;
 use crate::config;
 use crate::debug;
 use crate::kernel::Kernel;
 use crate::platform::chip::Chip;
 use crate::process::Process;
+use crate::process_checker::ProcessCheckerMachine;
 use crate::process_policies::ProcessFaultPolicy;
 use crate::process_standard::ProcessStandard;
 
@@ -59,6 +61,26 @@ pub enum ProcessLoadError {
     /// KernelVersion TBF header.
     IncompatibleKernelVersion { version: Option<(u16, u16)> },
 
+    /// The credentials of the process were checked by the board's
+    /// `AppCredentialsChecker` and none of them were accepted. Either the
+    /// process has no credentials footer and the board requires one, or the
+    /// process (or its credentials) were modified after signing.
+    CredentialsNoAccept,
+
+    /// The TBF header places the end of the application binary, where the
+    /// footers start, outside of the TBF object or inside the header. The
+    /// integrity region of the process is then undefined, so the process is not
+    /// loaded.
+    InvalidBinaryEnd,
+
+    /// The TBF object is padding or a disabled application, so no process was
+    /// created from it.
+    NotEnabled,
+
+    /// All entries of the processes array are in use, so there is no room for
+    /// another process.
+    NoProcessSlot,
+
     /// Process loading error due (likely) to a bug in the kernel. If you get
     /// this error please open a bug report.
     InternalError,
@@ -124,6 +146,22 @@ impl fmt::Debug for ProcessLoadError {
                 None => write!(f, "Process did not provide a TBF kernel version header"),
             },
 
+            ProcessLoadError::CredentialsNoAccept => {
+                write!(f, "No credentials of the process were accepted")
+            }
+
+            ProcessLoadError::InvalidBinaryEnd => {
+                write!(f, "TBF binary end is outside of the application binary")
+            }
+
+            ProcessLoadError::NotEnabled => {
+                write!(f, "TBF object is not an enabled application")
+            }
+
+            ProcessLoadError::NoProcessSlot => {
+                write!(f, "No free slot in the processes array")
+            }
+
             ProcessLoadError::InternalError => write!(f, "Error in kernel. Likely a bug."),
         }
     }
@@ -161,6 +199,106 @@ pub fn load_processes_advanced<C: Chip>(
     fault_policy: &'static dyn ProcessFaultPolicy,
     require_kernel_version: bool,
     _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_from_flash(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_policy,
+        require_kernel_version,
+        false,
+        None,
+    )
+}
+
+/// Load processes from flash like `load_processes_advanced()`, but only start
+/// the processes whose credentials are approved by the board's
+/// `AppCredentialsChecker`.
+///
+/// Processes are created in the `CredentialsUnchecked` state and are not
+/// runnable. Once all processes have been created, the `checker` is started
+/// and asynchronously checks the credentials in the TBF footers of each
+/// process. Processes with an accepted credential are started, all other
+/// processes move to the `CredentialsFailed` state and the checker's client is
+/// told with `ProcessLoadError::CredentialsNoAccept`.
+///
+/// Like `load_processes()`, this requires processes to include the
+/// `KernelVersion` TBF header.
+#[inline(always)]
+pub fn load_and_check_processes<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
+    procs: &'static mut [Option<&'static dyn Process>],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    checker: &'static ProcessCheckerMachine,
+    _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_from_flash(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_policy,
+        true,
+        true,
+        None,
+    )?;
+    checker.start();
+    Ok(())
+}
+
+/// Load processes from flash like `load_processes()`, but resume each process
+/// from its checkpoint in `checkpoints` if there is a valid one.
+///
+/// The checkpoint of a process is in the slot of `checkpoints` with the index
+/// of the process in `procs`. A process without a checkpoint, or whose
+/// checkpoint does not match it, for example because the application or
+/// the kernel was updated, starts from its init function. See `checkpoint` for details.
+#[inline(always)]
+pub fn load_processes_and_restore<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
+    procs: &'static mut [Option<&'static dyn Process>],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    checkpoints: &CheckpointRegion,
+    _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_from_flash(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_policy,
+        true,
+        false,
+        Some(checkpoints),
+    )
+}
+
+/// Shared implementation of discovering and creating processes from flash.
+///
+/// If `check_credentials` is `true` the created processes are not started and
+/// must be approved through the `ProcessCheckerMachine` first. If
+/// `checkpoints` is given, processes are resumed from their checkpoints.
+#[inline(always)]
+fn load_processes_from_flash<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8],
+    procs: &'static mut [Option<&'static dyn Process>],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    require_kernel_version: bool,
+    check_credentials: bool,
+    checkpoints: Option<&CheckpointRegion>,
 ) -> Result<(), ProcessLoadError> {
     if config::CONFIG.debug_load_processes {
         debug!(
@@ -248,6 +386,7 @@ pub fn load_processes_advanced<C: Chip>(
                     remaining_memory,
                     fault_policy,
                     require_kernel_version,
+                    check_credentials,
                     index,
                 )?
             };
@@ -265,6 +404,21 @@ pub fn load_processes_advanced<C: Chip>(
                     );
                 }
 
+                if let Some(checkpoints) = checkpoints {
+                    let restored = checkpoints.slot(index).map_or(false, |slot| {
+                        process
+                            .restore_checkpoint(slot, checkpoints.kernel_id())
+                            .is_ok()
+                    });
+                    if config::CONFIG.debug_load_processes && restored {
+                        debug!(
+                            "Restored process[{}] from checkpoint = {:?}",
+                            index,
+                            process.get_process_name()
+                        );
+                    }
+                }
+
                 // Save the reference to this process in the processes array.
                 procs[index] = Some(process);
                 // Can now increment index to use the next spot in the processes
@@ -288,6 +442,9 @@ pub fn load_processes_advanced<C: Chip>(
 ///
 /// Default arguments are:
 ///  - `require_kernel_version`: prevent loading processes that do not provide a `KernelVersion`
+///
+/// Boards that keep checkpoints of processes use
+/// `load_processes_and_restore()` instead.
 #[inline(always)]
 pub fn load_processes<C: Chip>(
     kernel: &'static Kernel,
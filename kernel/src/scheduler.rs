@@ -3,6 +3,7 @@
 pub mod cooperative;
 pub mod mlfq;
 pub mod priority;
+pub mod realtime;
 pub mod round_robin;
 
 use crate::dynamic_deferred_call::DynamicDeferredCall;
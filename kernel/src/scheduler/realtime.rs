@@ -0,0 +1,359 @@
+//! Real-time scheduler for periodic processes.
+//!
+//! Processes whose TBF header includes the `Realtime` element are periodic
+//! tasks. Every `period` a new job of the process is released, which must be
+//! complete `deadline` after its release and may use at most `budget` of CPU
+//! time. Among the processes that are ready and have budget left, the
+//! scheduler runs the one with the highest priority according to its policy:
+//!
+//! - Earliest deadline first (EDF): the job with the earliest absolute
+//!   deadline runs first.
+//! - Rate monotonic (RM): the process with the shortest period runs first.
+//!
+//! Budgets are enforced with the `SchedulerTimer`. A process that used up the
+//! budget of its current job is not run again until its next job is released.
+//! Processes without the `Realtime` element run in round-robin order whenever
+//! no real-time process can run.
+//!
+//! A deadline is missed if a process has work to do both before and at the
+//! deadline of its job. Missed deadlines are counted per process and can be
+//! inspected with `Process::debug_deadline_miss_count()`, for example with the
+//! `list` command of the process console.
+//!
+//! The scheduler keeps time with a dedicated alarm, which it also uses to wake
+//! the chip when the next job of a throttled process is released.
+
+use core::cell::Cell;
+
+use crate::collections::list::{List, ListLink, ListNode};
+use crate::dynamic_deferred_call::DynamicDeferredCall;
+use crate::hil::time::{self, ConvertTicks, Frequency, Ticks};
+use crate::kernel::{Kernel, StoppedExecutingReason};
+use crate::platform::chip::Chip;
+use crate::process::{Process, ProcessId, RealtimeParameters};
+use crate::scheduler::{Scheduler, SchedulingDecision};
+use crate::utilities::cells::OptionalCell;
+
+/// How the scheduler orders real-time processes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RealtimePolicy {
+    /// Run the job with the earliest absolute deadline.
+    EarliestDeadlineFirst,
+    /// Run the process with the shortest period.
+    RateMonotonic,
+}
+
+/// State of the current job of a periodic process.
+struct Job {
+    /// The process the job belongs to. The job state is reset when the
+    /// process in the slot changes or restarts.
+    processid: OptionalCell<ProcessId>,
+    /// Release time of the job.
+    release_us: Cell<u64>,
+    /// Absolute deadline of the job.
+    deadline_us: Cell<u64>,
+    /// CPU time the job may still use.
+    budget_us: Cell<u32>,
+    /// Whether the process had work to do since the job was released.
+    pending: Cell<bool>,
+    /// Whether the deadline of the job has been checked for a miss.
+    checked: Cell<bool>,
+}
+
+impl Job {
+    fn new() -> Job {
+        Job {
+            processid: OptionalCell::empty(),
+            release_us: Cell::new(0),
+            deadline_us: Cell::new(0),
+            budget_us: Cell::new(0),
+            pending: Cell::new(false),
+            checked: Cell::new(false),
+        }
+    }
+}
+
+/// Nodes store per-process state
+pub struct RealtimeProcessNode<'a> {
+    proc: &'static Option<&'static dyn Process>,
+    job: Job,
+    next: ListLink<'a, RealtimeProcessNode<'a>>,
+}
+
+impl<'a> RealtimeProcessNode<'a> {
+    pub fn new(proc: &'static Option<&'static dyn Process>) -> RealtimeProcessNode<'a> {
+        RealtimeProcessNode {
+            proc,
+            job: Job::new(),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Returns the timing parameters of the process in this slot, with
+    /// defaults filled in, or `None` if it is not a real-time process.
+    fn parameters(&self) -> Option<RealtimeParameters> {
+        self.proc
+            .and_then(|proc| proc.get_realtime_parameters())
+            .filter(|params| params.period_us > 0)
+            .map(|params| {
+                // A deadline of zero or beyond the period means the job must
+                // be complete by the next release.
+                let deadline_us =
+                    if params.deadline_us == 0 || params.deadline_us > params.period_us {
+                        params.period_us
+                    } else {
+                        params.deadline_us
+                    };
+                // A budget of zero means the job may run until its deadline.
+                let budget_us = if params.budget_us == 0 {
+                    deadline_us
+                } else {
+                    params.budget_us
+                };
+                RealtimeParameters {
+                    period_us: params.period_us,
+                    deadline_us,
+                    budget_us,
+                }
+            })
+    }
+}
+
+impl<'a> ListNode<'a, RealtimeProcessNode<'a>> for RealtimeProcessNode<'a> {
+    fn next(&'a self) -> &'static ListLink<'a, RealtimeProcessNode<'a>> {
+        &self.next
+    }
+}
+
+pub struct RealtimeSched<'a, A: 'static + time::Alarm<'static>> {
+    alarm: &'static A,
+    policy: RealtimePolicy,
+    pub processes: List<'a, RealtimeProcessNode<'a>>,
+    /// Alarm time at the last update of `ticks`.
+    last_now: Cell<A::Ticks>,
+    /// Ticks elapsed since the scheduler started, which unlike the alarm time
+    /// does not wrap around.
+    ticks: Cell<u64>,
+    /// The node of the process that was last chosen to run.
+    running: OptionalCell<&'a RealtimeProcessNode<'a>>,
+    /// Position in `processes` of the last process without real-time
+    /// parameters that ran.
+    last_background: Cell<usize>,
+}
+
+impl<'a, A: 'static + time::Alarm<'static>> RealtimeSched<'a, A> {
+    /// How long processes without real-time parameters run before being
+    /// preempted.
+    pub const BACKGROUND_TIMESLICE_US: u32 = 10000;
+
+    pub fn new(alarm: &'static A, policy: RealtimePolicy) -> Self {
+        Self {
+            alarm,
+            policy,
+            processes: List::new(),
+            last_now: Cell::new(A::Ticks::from(0)),
+            ticks: Cell::new(0),
+            running: OptionalCell::empty(),
+            last_background: Cell::new(0),
+        }
+    }
+
+    /// Returns the time since the scheduler started in microseconds.
+    fn now_us(&self) -> u64 {
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.last_now.get()).into_u32();
+        self.last_now.set(now);
+        let ticks = self.ticks.get() + elapsed as u64;
+        self.ticks.set(ticks);
+
+        let frequency = A::Frequency::frequency() as u64;
+        (ticks / frequency) * 1_000_000 + (ticks % frequency) * 1_000_000 / frequency
+    }
+
+    /// Releases new jobs and accounts for missed deadlines up to `now`.
+    fn update_job(&self, node: &RealtimeProcessNode<'a>, now: u64) {
+        let (proc, params) = match (*node.proc, node.parameters()) {
+            (Some(proc), Some(params)) => (proc, params),
+            _ => return,
+        };
+        let job = &node.job;
+
+        if job.processid.map_or(true, |id| *id != proc.processid()) {
+            // The process started since the last update, so it gets its first
+            // job now.
+            job.processid.set(proc.processid());
+            self.release(node, params, now);
+        }
+
+        if !job.checked.get() && now >= job.deadline_us.get() {
+            job.checked.set(true);
+            if job.pending.get() && proc.ready() {
+                proc.debug_deadline_missed();
+            }
+        }
+
+        let period = params.period_us as u64;
+        if now >= job.release_us.get() + period {
+            // Skip the periods in which the scheduler did not run.
+            let periods = (now - job.release_us.get()) / period;
+            self.release(node, params, job.release_us.get() + periods * period);
+        }
+
+        if proc.ready() {
+            if !job.checked.get() {
+                job.pending.set(true);
+            }
+        } else {
+            job.pending.set(false);
+        }
+    }
+
+    fn release(&self, node: &RealtimeProcessNode<'a>, params: RealtimeParameters, release: u64) {
+        let job = &node.job;
+        job.release_us.set(release);
+        job.deadline_us.set(release + params.deadline_us as u64);
+        job.budget_us.set(params.budget_us);
+        job.pending.set(false);
+        job.checked.set(false);
+    }
+
+    /// Returns the priority of the real-time process of `node`, where lower
+    /// values are more urgent.
+    fn priority(&self, node: &RealtimeProcessNode<'a>, params: RealtimeParameters) -> u64 {
+        match self.policy {
+            RealtimePolicy::EarliestDeadlineFirst => node.job.deadline_us.get(),
+            RealtimePolicy::RateMonotonic => params.period_us as u64,
+        }
+    }
+
+    /// Returns the real-time process that should run now, if any.
+    fn next_realtime(&self) -> Option<&'a RealtimeProcessNode<'a>> {
+        self.processes
+            .iter()
+            .filter_map(|node| {
+                let params = node.parameters()?;
+                let ready = node.proc.map_or(false, |proc| proc.ready());
+                if ready && node.job.budget_us.get() > 0 {
+                    Some((self.priority(node, params), node))
+                } else {
+                    None
+                }
+            })
+            .fold(
+                None,
+                |best: Option<(u64, &'a RealtimeProcessNode<'a>)>, next| match best {
+                    Some(best) if best.0 <= next.0 => Some(best),
+                    _ => Some(next),
+                },
+            )
+            .map(|(_, node)| node)
+    }
+
+    /// Returns the next process without real-time parameters in round-robin
+    /// order that is ready to run.
+    fn next_background(&self) -> Option<&'a RealtimeProcessNode<'a>> {
+        let count = self.processes.iter().count();
+        (1..=count)
+            .map(|offset| (self.last_background.get() + offset) % count)
+            .find_map(|position| {
+                self.processes
+                    .iter()
+                    .nth(position)
+                    .filter(|node| {
+                        node.parameters().is_none() && node.proc.map_or(false, |proc| proc.ready())
+                    })
+                    .map(|node| {
+                        self.last_background.set(position);
+                        node
+                    })
+            })
+    }
+
+    /// Returns the time until the next job of any real-time process is
+    /// released.
+    fn until_next_release(&self, now: u64) -> Option<u64> {
+        self.processes
+            .iter()
+            .filter_map(|node| {
+                node.parameters().map(|params| {
+                    (node.job.release_us.get() + params.period_us as u64).saturating_sub(now)
+                })
+            })
+            .min()
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for RealtimeSched<'a, A> {
+    fn next(&self, kernel: &Kernel) -> SchedulingDecision {
+        let now = self.now_us();
+        for node in self.processes.iter() {
+            self.update_job(node, now);
+        }
+
+        if kernel.processes_blocked() {
+            // No processes ready
+            self.running.clear();
+            return SchedulingDecision::TrySleep;
+        }
+
+        // Preempt the running process in time to release the next job.
+        let until_release = self
+            .until_next_release(now)
+            .map_or(u32::MAX, |us| us.min(u32::MAX as u64) as u32);
+
+        let (node, timeslice) = match self.next_realtime() {
+            Some(node) => (node, node.job.budget_us.get()),
+            None => match self.next_background() {
+                Some(node) => (node, Self::BACKGROUND_TIMESLICE_US),
+                None => {
+                    // Only throttled processes are ready, so wake up when the
+                    // next job is released.
+                    self.running.clear();
+                    if until_release != u32::MAX {
+                        self.alarm
+                            .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(until_release));
+                    }
+                    return SchedulingDecision::TrySleep;
+                }
+            },
+        };
+
+        self.running.set(node);
+        let next = node.proc.unwrap().processid(); // Nodes are only chosen if they hold a ready process.
+        SchedulingDecision::RunProcess((next, Some(timeslice.min(until_release))))
+    }
+
+    fn result(&self, result: StoppedExecutingReason, execution_time_us: Option<u32>) {
+        let execution_time_us = execution_time_us.unwrap_or(0);
+        self.running.take().map(|node| {
+            if node.parameters().is_some() {
+                let job = &node.job;
+                job.budget_us
+                    .set(job.budget_us.get().saturating_sub(execution_time_us));
+                if result == StoppedExecutingReason::NoWorkLeft && !job.checked.get() {
+                    job.pending.set(false);
+                }
+            }
+        });
+    }
+
+    unsafe fn continue_process(&self, _: ProcessId, chip: &C) -> bool {
+        // Besides kernel work, a more urgent real-time process that became
+        // ready, for example through IPC, preempts the running process.
+        !(chip.has_pending_interrupts()
+            || DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
+            || self.running.map_or(false, |running| {
+                self.next_realtime().map_or(false, |next| {
+                    match (next.parameters(), running.parameters()) {
+                        (Some(next_params), Some(running_params)) => {
+                            self.priority(next, next_params)
+                                < self.priority(running, running_params)
+                        }
+                        // Any real-time process preempts a background process.
+                        (Some(_), None) => true,
+                        _ => false,
+                    }
+                })
+            }))
+    }
+}
@@ -0,0 +1,90 @@
+//! Permissions for accessing persistent storage.
+//!
+//! Items in persistent storage (for example in a key-value store) are tagged
+//! with the ID of whoever wrote them. The permissions of a process are
+//! specified by the `PersistentAcl` TLV in its TBF header, which lists the ID
+//! the process writes with, the IDs it may read, and the IDs it may modify or
+//! delete. Storage layers check these permissions before giving out or
+//! changing stored items.
+
+use crate::capabilities;
+use tock_tbf::types::TbfHeader;
+
+/// The maximum number of read or access IDs a set of permissions can hold.
+pub const MAX_IDS: usize = 8;
+
+/// The persistent storage permissions of a process (or the kernel).
+#[derive(Clone, Copy, Debug)]
+pub struct StoragePermissions {
+    /// The ID stored items are tagged with. `None` means items cannot be
+    /// written.
+    write_id: Option<u32>,
+    read_count: usize,
+    read_ids: [u32; MAX_IDS],
+    access_count: usize,
+    access_ids: [u32; MAX_IDS],
+}
+
+impl StoragePermissions {
+    /// Create the permissions of a process from its TBF header. Returns `None`
+    /// if the process has no persistent ACL header, in which case it cannot
+    /// use persistent storage at all.
+    pub(crate) fn from_header(header: &TbfHeader) -> Option<StoragePermissions> {
+        let write_id = header.get_storage_write_id()?;
+        let (read_count, read_ids) = header.get_storage_read_ids()?;
+        let (access_count, access_ids) = header.get_storage_access_ids()?;
+
+        Some(StoragePermissions {
+            // A write ID of 0 means the process cannot write.
+            write_id: if write_id == 0 { None } else { Some(write_id) },
+            read_count: read_count.min(MAX_IDS),
+            read_ids,
+            access_count: access_count.min(MAX_IDS),
+            access_ids,
+        })
+    }
+
+    /// Create permissions for use by the kernel itself.
+    ///
+    /// At most `MAX_IDS` entries of `read_ids` and `access_ids` are used.
+    pub fn new_kernel(
+        write_id: Option<u32>,
+        read_ids: &[u32],
+        access_ids: &[u32],
+        _capability: &dyn capabilities::KernelStorageCapability,
+    ) -> StoragePermissions {
+        let mut permissions = StoragePermissions {
+            write_id,
+            read_count: read_ids.len().min(MAX_IDS),
+            read_ids: [0; MAX_IDS],
+            access_count: access_ids.len().min(MAX_IDS),
+            access_ids: [0; MAX_IDS],
+        };
+        permissions.read_ids[..permissions.read_count]
+            .copy_from_slice(&read_ids[..permissions.read_count]);
+        permissions.access_ids[..permissions.access_count]
+            .copy_from_slice(&access_ids[..permissions.access_count]);
+        permissions
+    }
+
+    /// The ID items written with these permissions are tagged with, or `None`
+    /// if writing is not permitted.
+    pub fn get_write_id(&self) -> Option<u32> {
+        self.write_id
+    }
+
+    /// Whether an item written with `stored_id` can be read.
+    ///
+    /// Items can always be read by whoever wrote them.
+    pub fn check_read_permission(&self, stored_id: u32) -> bool {
+        self.write_id == Some(stored_id) || self.read_ids[..self.read_count].contains(&stored_id)
+    }
+
+    /// Whether an item written with `stored_id` can be modified or deleted.
+    ///
+    /// Items can always be modified by whoever wrote them.
+    pub fn check_modify_permission(&self, stored_id: u32) -> bool {
+        self.write_id == Some(stored_id)
+            || self.access_ids[..self.access_count].contains(&stored_id)
+    }
+}
@@ -652,4 +652,14 @@ pub trait UserspaceKernelBoundary {
     /// Store architecture specific (e.g. CPU registers or status flags) data
     /// for a process. On success returns the number of elements written to out.
     fn store_context(&self, state: &Self::StoredState, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Load architecture specific data for a process that was stored with
+    /// `store_context()`, for example before a reboot, into `state`.
+    ///
+    /// Returns an error and leaves `state` unchanged if `input` is not stored
+    /// state of this architecture. Architectures that cannot load stored
+    /// state return `ErrorCode::NOSUPPORT`, which is the default.
+    fn load_context(&self, _state: &mut Self::StoredState, _input: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
@@ -143,6 +143,11 @@ pub struct AsyncTicKV<'a, C: FlashController<S>, const S: usize> {
     pub tickv: TicKV<'a, C, S>,
     key: Cell<Option<u64>>,
     value: Cell<Option<&'static [u8]>>,
+    /// The buffer passed to `append_key_mut()`.
+    mut_value: Cell<Option<&'static mut [u8]>>,
+    /// The number of bytes of `mut_value` to store, if the pending append
+    /// was started by `append_key_mut()`.
+    mut_value_length: Cell<Option<usize>>,
     buf: Cell<Option<&'static mut [u8]>>,
 }
 
@@ -158,6 +163,8 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             tickv: TicKV::<C, S>::new(controller, read_buffer, flash_size),
             key: Cell::new(None),
             value: Cell::new(None),
+            mut_value: Cell::new(None),
+            mut_value_length: Cell::new(None),
             buf: Cell::new(None),
         }
     }
@@ -191,11 +198,57 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             Err(e) => {
                 self.key.replace(Some(hash));
                 self.value.replace(Some(value));
+                self.mut_value_length.set(None);
                 Err(e)
             }
         }
     }
 
+    /// Appends the key/value pair to flash storage, like `append_key()`, but
+    /// from a mutable buffer so that the caller can reuse it once the
+    /// operation has completed.
+    ///
+    /// `hash`: A hashed key. This key will be used in future to retrieve
+    ///         or remove the `value`.
+    /// `value`: A buffer containing the data to be stored to flash.
+    /// `length`: The number of bytes at the start of `value` to store.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned, together with `value` unless
+    /// the error is an async error.
+    ///
+    /// If `value` is not returned, it can be retrieved with
+    /// `get_stored_mut_value_buffer()` once the operation has completed.
+    pub fn append_key_mut(
+        &self,
+        hash: u64,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<SuccessCode, (Option<&'static mut [u8]>, ErrorCode)> {
+        let ret = match value.get(0..length) {
+            Some(data) => self.tickv.append_key(hash, data),
+            None => return Err((Some(value), ErrorCode::BufferTooSmall(length))),
+        };
+
+        match ret {
+            Ok(code) => {
+                self.mut_value.replace(Some(value));
+                Ok(code)
+            }
+            Err(e) => match e {
+                ErrorCode::ReadNotReady(_)
+                | ErrorCode::EraseNotReady(_)
+                | ErrorCode::WriteNotReady(_) => {
+                    self.key.replace(Some(hash));
+                    self.mut_value.replace(Some(value));
+                    self.mut_value_length.set(Some(length));
+                    Err((None, e))
+                }
+                _ => Err((Some(value), e)),
+            },
+        }
+    }
+
     /// Retrieves the value from flash storage.
     ///
     /// `hash`: A hashed key.
@@ -270,6 +323,12 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         self.value.take()
     }
 
+    /// Get the `value` buffer that was passed in by a previous
+    /// `append_key_mut()`.
+    pub fn get_stored_mut_value_buffer(&self) -> Option<&'static mut [u8]> {
+        self.mut_value.take()
+    }
+
     /// Get the `buf` buffer that was passed in by previous
     /// commands.
     pub fn get_stored_buffer(&self) -> Option<&'static mut [u8]> {
@@ -294,9 +353,19 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
     pub fn continue_operation(&self) -> ContinueReturn {
         let ret = match self.tickv.state.get() {
             State::Init(_) => self.tickv.initalise(self.key.get().unwrap()),
-            State::AppendKey(_) => self
-                .tickv
-                .append_key(self.key.get().unwrap(), self.value.get().unwrap()),
+            State::AppendKey(_) => match self.mut_value_length.get() {
+                Some(length) => {
+                    let value = self.mut_value.take().unwrap();
+                    let ret = self
+                        .tickv
+                        .append_key(self.key.get().unwrap(), &value[0..length]);
+                    self.mut_value.replace(Some(value));
+                    ret
+                }
+                None => self
+                    .tickv
+                    .append_key(self.key.get().unwrap(), self.value.get().unwrap()),
+            },
             State::GetKey(_) => {
                 let buf = self.buf.take().unwrap();
                 let ret = self.tickv.get_key(self.key.get().unwrap(), buf);
@@ -567,6 +636,55 @@ mod store_flast_ctrl {
         }
     }
 
+    #[test]
+    fn test_append_mut() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+
+        let tickv = AsyncTicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+
+        let mut ret = tickv.initalise(hash_function.finish());
+        while ret.is_err() {
+            // There is no actual delay in the test, just continue now
+            let (r, _buf) = tickv.continue_operation();
+            ret = r;
+        }
+
+        static mut VALUE: [u8; 32] = [0x23; 32];
+        static mut BUF: [u8; 32] = [0; 32];
+
+        println!("Add key ONE");
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key_mut(get_hashed_key(b"ONE"), &mut VALUE, 32) };
+        match ret {
+            Err((None, ErrorCode::ReadNotReady(reg))) => {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                tickv.continue_operation().0.unwrap();
+            }
+            Ok(_) => {}
+            _ => unreachable!(),
+        }
+
+        // The value buffer can be changed and used again.
+        let value = tickv.get_stored_mut_value_buffer().unwrap();
+        value[0] = 0x42;
+
+        println!("Add a value that is longer than its buffer");
+        match tickv.append_key_mut(get_hashed_key(b"TWO"), value, 33) {
+            Err((Some(value), ErrorCode::BufferTooSmall(33))) => assert_eq!(value[0], 0x42),
+            _ => unreachable!(),
+        }
+
+        println!("Get key ONE");
+        #[allow(unsafe_code)]
+        unsafe {
+            tickv.get_key(get_hashed_key(b"ONE"), &mut BUF).unwrap();
+            assert_eq!(BUF, [0x23; 32]);
+        }
+    }
+
     #[test]
     fn test_double_append() {
         let mut read_buf: [u8; 1024] = [0; 1024];
@@ -126,6 +126,7 @@ pub fn parse_tbf_header(
                 // Places to save fields that we parse out of the header
                 // options.
                 let mut main_pointer: Option<types::TbfHeaderV2Main> = None;
+                let mut program_pointer: Option<types::TbfHeaderV2Program> = None;
                 let mut wfr_pointer: [Option<types::TbfHeaderV2WriteableFlashRegion>; 4] =
                     Default::default();
                 let mut app_name_str = "";
@@ -133,6 +134,7 @@ pub fn parse_tbf_header(
                 let mut permissions_pointer: Option<types::TbfHeaderV2Permissions<8>> = None;
                 let mut persistent_acls_pointer: Option<types::TbfHeaderV2PersistentAcl<8>> = None;
                 let mut kernel_version: Option<types::TbfHeaderV2KernelVersion> = None;
+                let mut realtime_pointer: Option<types::TbfHeaderV2Realtime> = None;
 
                 // Iterate the remainder of the header looking for TLV entries.
                 while remaining.len() > 0 {
@@ -160,6 +162,20 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderProgram => {
+                            let entry_len = mem::size_of::<types::TbfHeaderV2Program>();
+
+                            // Like the Main TLV, the Program TLV has a fixed
+                            // size.
+                            if tlv_header.length as usize == entry_len {
+                                program_pointer = Some(remaining.try_into()?);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
                         types::TbfHeaderTypes::TbfHeaderWriteableFlashRegions => {
                             // Length must be a multiple of the size of a region definition.
                             if tlv_header.length as usize
@@ -241,6 +257,17 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderRealtime => {
+                            let entry_len = mem::size_of::<types::TbfHeaderV2Realtime>();
+                            if tlv_header.length as usize == entry_len {
+                                realtime_pointer = Some(remaining.try_into()?);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
                         _ => {}
                     }
 
@@ -255,12 +282,14 @@ pub fn parse_tbf_header(
                 let tbf_header = types::TbfHeaderV2 {
                     base: tbf_header_base,
                     main: main_pointer,
+                    program: program_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: Some(wfr_pointer),
                     fixed_addresses: fixed_address_pointer,
                     permissions: permissions_pointer,
                     persistent_acls: persistent_acls_pointer,
                     kernel_version: kernel_version,
+                    realtime: realtime_pointer,
                 };
 
                 Ok(types::TbfHeader::TbfHeaderV2(tbf_header))
@@ -269,3 +298,108 @@ pub fn parse_tbf_header(
         _ => Err(types::TbfParseError::UnsupportedVersion(version)),
     }
 }
+
+/// Parse one credentials footer stored in flash.
+///
+/// The `footers` slice must start at a footer TLV, i.e. at the binary end
+/// offset of the app (see `TbfHeader::get_binary_end()`) or directly after a
+/// previously parsed footer. Footers are always in TBF version 2 format.
+///
+/// ## Return
+///
+/// On success, returns the credentials and the total length of the footer TLV
+/// in bytes (including the TLV header), so that the caller can advance to the
+/// next footer.
+pub fn parse_tbf_footer(
+    footers: &'static [u8],
+) -> Result<(types::TbfFooterV2Credentials, u32), types::TbfParseError> {
+    let tlv_header: types::TbfHeaderTlv = footers.try_into()?;
+    match tlv_header.tipe {
+        types::TbfHeaderTypes::TbfFooterCredentials => {
+            let credentials_buf = footers
+                .get(4..4 + tlv_header.length as usize)
+                .ok_or(types::TbfParseError::NotEnoughFlash)?;
+            let credentials: types::TbfFooterV2Credentials = credentials_buf.try_into()?;
+
+            // Footers are padded to 4 bytes just like header TLVs.
+            let footer_len = 4 + align4!(tlv_header.length as usize);
+            Ok((credentials, footer_len as u32))
+        }
+        _ => Err(types::TbfParseError::BadTlvEntry(tlv_header.tipe as usize)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_tbf_footer, parse_tbf_header};
+    use crate::types::{TbfFooterV2CredentialsType, TbfParseError};
+
+    #[test]
+    fn sha256_footer() {
+        static FOOTER: [u8; 40] = [
+            0x80, 0x00, 0x24, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06,
+            0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14,
+            0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20,
+        ];
+        let (credentials, length) = parse_tbf_footer(&FOOTER).ok().unwrap();
+        assert_eq!(credentials.format(), TbfFooterV2CredentialsType::SHA256);
+        assert_eq!(credentials.data(), &FOOTER[8..]);
+        assert_eq!(length, 40);
+    }
+
+    #[test]
+    fn reserved_footer_is_padded() {
+        static FOOTER: [u8; 12] = [
+            0x80, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00,
+        ];
+        let (credentials, length) = parse_tbf_footer(&FOOTER).ok().unwrap();
+        assert_eq!(credentials.format(), TbfFooterV2CredentialsType::Reserved);
+        assert_eq!(credentials.data().len(), 2);
+        assert_eq!(length, 12);
+    }
+
+    #[test]
+    fn truncated_hash_is_rejected() {
+        static FOOTER: [u8; 12] = [
+            0x80, 0x00, 0x08, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04,
+        ];
+        assert!(matches!(
+            parse_tbf_footer(&FOOTER),
+            Err(TbfParseError::BadTlvEntry(128))
+        ));
+    }
+
+    #[test]
+    fn header_tlv_is_not_a_footer() {
+        static FOOTER: [u8; 8] = [0x08, 0x00, 0x04, 0x00, 0x02, 0x00, 0x00, 0x00];
+        assert!(matches!(
+            parse_tbf_footer(&FOOTER),
+            Err(TbfParseError::BadTlvEntry(8))
+        ));
+    }
+
+    #[test]
+    fn realtime_header() {
+        static HEADER: [u8; 48] = [
+            0x02, 0x00, 0x30, 0x00, 0x30, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xb8, 0x3f,
+            0x30, 0x00, 0x01, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x0c, 0x00, 0x10, 0x27, 0x00, 0x00, 0x40, 0x1f,
+            0x00, 0x00, 0xd0, 0x07, 0x00, 0x00,
+        ];
+        let header = parse_tbf_header(&HEADER, 2).ok().unwrap();
+        assert_eq!(header.get_realtime_parameters(), Some((10000, 8000, 2000)));
+    }
+
+    #[test]
+    fn truncated_realtime_header_is_rejected() {
+        static HEADER: [u8; 40] = [
+            0x02, 0x00, 0x28, 0x00, 0x28, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x30, 0x27,
+            0x20, 0x00, 0x01, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x04, 0x00, 0x10, 0x27, 0x00, 0x00,
+        ];
+        assert!(matches!(
+            parse_tbf_header(&HEADER, 2),
+            Err(TbfParseError::BadTlvEntry(10))
+        ));
+    }
+}
@@ -122,6 +122,9 @@ pub enum TbfHeaderTypes {
     TbfHeaderPermissions = 6,
     TbfHeaderPersistentAcl = 7,
     TbfHeaderKernelVersion = 8,
+    TbfHeaderProgram = 9,
+    TbfHeaderRealtime = 10,
+    TbfFooterCredentials = 128,
 
     /// Some field in the header that we do not understand. Since the TLV format
     /// specifies the length of each section, if we get a field we do not
@@ -147,6 +150,21 @@ pub struct TbfHeaderV2Main {
     minimum_ram_size: u32,
 }
 
+/// The v2 program section for apps.
+///
+/// The program section replaces the main section for apps that place a footer
+/// after the application binary. In addition to the fields of the main
+/// section, it records where the binary ends (and the footers begin) and a
+/// version number for the application.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2Program {
+    init_fn_offset: u32,
+    protected_trailer_size: u32,
+    minimum_ram_size: u32,
+    binary_end_offset: u32,
+    version: u32,
+}
+
 /// Writeable flash regions only need an offset and size.
 ///
 /// There can be multiple (or zero) flash regions defined, so this is its own
@@ -211,6 +229,72 @@ pub struct TbfHeaderV2KernelVersion {
     minor: u16,
 }
 
+/// Timing parameters for apps that run as periodic real-time tasks.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2Realtime {
+    period_us: u32,
+    deadline_us: u32,
+    budget_us: u32,
+}
+
+/// The format of the data in a credentials footer.
+///
+/// Hash formats store only the hash of the integrity region. Signature formats
+/// store only the signature over the hash of the integrity region; the kernel
+/// is expected to already hold the public key the signature is checked
+/// against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TbfFooterV2CredentialsType {
+    Reserved = 0,
+    Rsa3072Key = 1,
+    Rsa4096Key = 2,
+    SHA256 = 3,
+    SHA384 = 4,
+    SHA512 = 5,
+    EcdsaNistP256 = 6,
+}
+
+impl TbfFooterV2CredentialsType {
+    /// The number of bytes of data a credential of this format carries.
+    ///
+    /// `Reserved` credentials have no fixed length and are used to reserve
+    /// space in the footer region for credentials added later.
+    pub fn data_length(&self) -> Option<usize> {
+        match self {
+            TbfFooterV2CredentialsType::Reserved => None,
+            TbfFooterV2CredentialsType::Rsa3072Key => Some(384),
+            TbfFooterV2CredentialsType::Rsa4096Key => Some(512),
+            TbfFooterV2CredentialsType::SHA256 => Some(32),
+            TbfFooterV2CredentialsType::SHA384 => Some(48),
+            TbfFooterV2CredentialsType::SHA512 => Some(64),
+            TbfFooterV2CredentialsType::EcdsaNistP256 => Some(64),
+        }
+    }
+}
+
+/// A credential stored in a TBF footer.
+///
+/// Credentials cover the integrity region of the TBF object, which spans from
+/// the start of the TBF header to the end of the application binary (as given
+/// by `TbfHeader::get_binary_end()`).
+#[derive(Clone, Copy, Debug)]
+pub struct TbfFooterV2Credentials {
+    format: TbfFooterV2CredentialsType,
+    data: &'static [u8],
+}
+
+impl TbfFooterV2Credentials {
+    /// Get the format of this credential.
+    pub fn format(&self) -> TbfFooterV2CredentialsType {
+        self.format
+    }
+
+    /// Get the hash or signature stored in this credential.
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+}
+
 // Conversion functions from slices to the various TBF fields.
 
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
@@ -259,6 +343,9 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             6 => Ok(TbfHeaderTypes::TbfHeaderPermissions),
             7 => Ok(TbfHeaderTypes::TbfHeaderPersistentAcl),
             8 => Ok(TbfHeaderTypes::TbfHeaderKernelVersion),
+            9 => Ok(TbfHeaderTypes::TbfHeaderProgram),
+            10 => Ok(TbfHeaderTypes::TbfHeaderRealtime),
+            128 => Ok(TbfHeaderTypes::TbfFooterCredentials),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
     }
@@ -308,6 +395,40 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Main {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Program {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Program, Self::Error> {
+        Ok(TbfHeaderV2Program {
+            init_fn_offset: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            protected_trailer_size: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            minimum_ram_size: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            binary_end_offset: u32::from_le_bytes(
+                b.get(12..16)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            version: u32::from_le_bytes(
+                b.get(16..20)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2WriteableFlashRegion {
     type Error = TbfParseError;
 
@@ -487,6 +608,49 @@ impl<const L: usize> core::convert::TryFrom<&[u8]> for TbfHeaderV2PersistentAcl<
     }
 }
 
+impl core::convert::TryFrom<u32> for TbfFooterV2CredentialsType {
+    type Error = TbfParseError;
+
+    fn try_from(format: u32) -> Result<TbfFooterV2CredentialsType, Self::Error> {
+        match format {
+            0 => Ok(TbfFooterV2CredentialsType::Reserved),
+            1 => Ok(TbfFooterV2CredentialsType::Rsa3072Key),
+            2 => Ok(TbfFooterV2CredentialsType::Rsa4096Key),
+            3 => Ok(TbfFooterV2CredentialsType::SHA256),
+            4 => Ok(TbfFooterV2CredentialsType::SHA384),
+            5 => Ok(TbfFooterV2CredentialsType::SHA512),
+            6 => Ok(TbfFooterV2CredentialsType::EcdsaNistP256),
+            _ => Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfFooterCredentials as usize,
+            )),
+        }
+    }
+}
+
+impl core::convert::TryFrom<&'static [u8]> for TbfFooterV2Credentials {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'static [u8]) -> Result<TbfFooterV2Credentials, Self::Error> {
+        let format: TbfFooterV2CredentialsType = u32::from_le_bytes(
+            b.get(0..4)
+                .ok_or(TbfParseError::NotEnoughFlash)?
+                .try_into()?,
+        )
+        .try_into()?;
+
+        let data = b.get(4..).ok_or(TbfParseError::NotEnoughFlash)?;
+
+        // Fixed-length formats must carry exactly the expected amount of data,
+        // otherwise the credential cannot be interpreted.
+        match format.data_length() {
+            Some(length) if length != data.len() => Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfFooterCredentials as usize,
+            )),
+            _ => Ok(TbfFooterV2Credentials { format, data }),
+        }
+    }
+}
+
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2KernelVersion {
     type Error = TbfParseError;
 
@@ -506,6 +670,30 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2KernelVersion {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Realtime {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Realtime, Self::Error> {
+        Ok(TbfHeaderV2Realtime {
+            period_us: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            deadline_us: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            budget_us: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
 /// The command permissions specified by the TBF header.
 ///
 /// Use the `get_command_permissions()` function to retrieve these.
@@ -529,12 +717,14 @@ pub enum CommandPermissions {
 pub struct TbfHeaderV2 {
     pub(crate) base: TbfHeaderV2Base,
     pub(crate) main: Option<TbfHeaderV2Main>,
+    pub(crate) program: Option<TbfHeaderV2Program>,
     pub(crate) package_name: Option<&'static str>,
     pub(crate) writeable_regions: Option<[Option<TbfHeaderV2WriteableFlashRegion>; 4]>,
     pub(crate) fixed_addresses: Option<TbfHeaderV2FixedAddresses>,
     pub(crate) permissions: Option<TbfHeaderV2Permissions<8>>,
     pub(crate) persistent_acls: Option<TbfHeaderV2PersistentAcl<8>>,
     pub(crate) kernel_version: Option<TbfHeaderV2KernelVersion>,
+    pub(crate) realtime: Option<TbfHeaderV2Realtime>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -575,7 +765,10 @@ impl TbfHeader {
     /// needed for this app.
     pub fn get_minimum_app_ram_size(&self) -> u32 {
         match *self {
-            TbfHeader::TbfHeaderV2(hd) => hd.main.map_or(0, |m| m.minimum_ram_size),
+            TbfHeader::TbfHeaderV2(hd) => match hd.program {
+                Some(p) => p.minimum_ram_size,
+                None => hd.main.map_or(0, |m| m.minimum_ram_size),
+            },
             _ => 0,
         }
     }
@@ -585,7 +778,11 @@ impl TbfHeader {
     pub fn get_protected_size(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.protected_size) + (hd.base.header_size as u32)
+                let protected_size = match hd.program {
+                    Some(p) => p.protected_trailer_size,
+                    None => hd.main.map_or(0, |m| m.protected_size),
+                };
+                protected_size + (hd.base.header_size as u32)
             }
             _ => 0,
         }
@@ -596,12 +793,39 @@ impl TbfHeader {
     pub fn get_init_function_offset(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.init_fn_offset) + (hd.base.header_size as u32)
+                let init_fn_offset = match hd.program {
+                    Some(p) => p.init_fn_offset,
+                    None => hd.main.map_or(0, |m| m.init_fn_offset),
+                };
+                init_fn_offset + (hd.base.header_size as u32)
             }
             _ => 0,
         }
     }
 
+    /// Get the offset from the beginning of the app's flash region where the
+    /// application binary ends and the footers start.
+    ///
+    /// Only apps with a program header have footers. For all other apps the
+    /// binary extends to the end of the TBF object.
+    pub fn get_binary_end(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .program
+                .map_or(hd.base.total_size, |p| p.binary_end_offset),
+            TbfHeader::Padding(base) => base.total_size,
+        }
+    }
+
+    /// Get the version of the app as specified in the program header. Apps
+    /// without a program header have version 0.
+    pub fn get_app_version(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.program.map_or(0, |p| p.version),
+            _ => 0,
+        }
+    }
+
     /// Get the name of the app.
     pub fn get_package_name(&self) -> Option<&'static str> {
         match *self {
@@ -702,6 +926,50 @@ impl TbfHeader {
         }
     }
 
+    /// Get the ID this process uses when writing to persistent storage.
+    /// Returns `None` if the persistent ACL header is not included.
+    pub fn get_storage_write_id(&self) -> Option<u32> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => match hd.persistent_acls {
+                Some(persistent_acls) => Some(persistent_acls.write_id),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Get the IDs of the persistent storage items this process can read, as
+    /// the number of valid IDs and the IDs themselves.
+    /// Returns `None` if the persistent ACL header is not included.
+    pub fn get_storage_read_ids(&self) -> Option<(usize, [u32; 8])> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => match hd.persistent_acls {
+                Some(persistent_acls) => Some((
+                    persistent_acls.read_length as usize,
+                    persistent_acls.read_ids,
+                )),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Get the IDs of the persistent storage items this process can modify
+    /// and delete, as the number of valid IDs and the IDs themselves.
+    /// Returns `None` if the persistent ACL header is not included.
+    pub fn get_storage_access_ids(&self) -> Option<(usize, [u32; 8])> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => match hd.persistent_acls {
+                Some(persistent_acls) => Some((
+                    persistent_acls.access_length as usize,
+                    persistent_acls.access_ids,
+                )),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
     /// Get the minimum compatible kernel version this process requires.
     /// Returns `None` if the kernel compatibility header is not included.
     pub fn get_kernel_version(&self) -> Option<(u16, u16)> {
@@ -713,4 +981,16 @@ impl TbfHeader {
             _ => None,
         }
     }
+
+    /// Get the real-time parameters of the process as `(period, deadline,
+    /// budget)` in microseconds. Returns `None` if the process does not
+    /// include the real-time header and is not a periodic task.
+    pub fn get_realtime_parameters(&self) -> Option<(u32, u32, u32)> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .realtime
+                .map(|realtime| (realtime.period_us, realtime.deadline_us, realtime.budget_us)),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,211 @@
+#!/usr/bin/env python3
+
+# Decodes crash records stored by the crash_record capsule.
+#
+# Usage: decode_crash_record.py RECORD
+
+"""
+Script to print a crash record stored by the crash_record capsule.
+
+The record is read from a binary file, for example one saved by an
+application through the crash record driver or a dump of the flash region
+that holds the record.
+
+Usage: decode_crash_record.py RECORD
+"""
+
+import struct
+import sys
+
+# Must match `kernel::crash_record`.
+MAGIC = b"TKCR"
+VERSION = 1
+HEADER_LENGTH = 16
+FLAG_TRUNCATED = 0x01
+
+CRASH_KINDS = {1: "Kernel panic", 2: "Process fault"}
+
+ENTRY_PANIC_MESSAGE = 1
+ENTRY_PROCESS = 2
+ENTRY_REGISTERS = 3
+ENTRY_MEMORY_MAP = 4
+ENTRY_STACK = 5
+
+STATES = [
+    "Running",
+    "Yielded",
+    "StoppedRunning",
+    "StoppedYielded",
+    "Faulted",
+    "Terminated",
+    "Unstarted",
+    "CredentialsUnchecked",
+    "CredentialsFailed",
+]
+
+MEMORY_MAP_FIELDS = [
+    "Flash start",
+    "Flash non-protected start",
+    "Flash end",
+    "RAM start",
+    "App break",
+    "Grant start",
+    "RAM end",
+    "Heap start",
+    "Stack top",
+    "Stack bottom",
+]
+
+
+def words(data):
+    return struct.unpack("<%dI" % (len(data) // 4), data[: len(data) // 4 * 4])
+
+
+def parse_record(data):
+    """Checks the header and returns the kind, flags and list of entries."""
+    if len(data) < HEADER_LENGTH or data[0:4] != MAGIC:
+        raise ValueError("Not a crash record")
+    version, kind, flags, length, checksum = struct.unpack("<HBBII", data[4:16])
+    if version != VERSION:
+        raise ValueError("Unsupported record version %d" % version)
+    if length > len(data) or length < HEADER_LENGTH:
+        raise ValueError("Record is incomplete")
+    computed = 0
+    for word in words(data[HEADER_LENGTH:length]):
+        computed ^= word
+    if computed != checksum:
+        raise ValueError("Checksum mismatch")
+
+    entries = []
+    offset = HEADER_LENGTH
+    while offset + 4 <= length:
+        entry_type, entry_length = struct.unpack("<HH", data[offset : offset + 4])
+        payload = data[offset + 4 : offset + 4 + entry_length]
+        entries.append((entry_type, payload))
+        offset += 4 + ((entry_length + 3) & ~3)
+    return kind, flags, entries
+
+
+def print_registers(payload, stack):
+    """Prints the architecture specific stored state of a process."""
+    values = words(payload)
+    if len(values) < 3:
+        print("  Registers: (invalid)")
+        return
+    tag = struct.pack("<I", values[2])
+    regs = values[3:]
+    if tag == b"ctxm" and len(regs) >= 11:
+        yield_pc, psr, psp = regs[0:3]
+        r = regs[3:11]
+        print("  Registers (Cortex-M):")
+        for index in range(8):
+            print("    R%-2d: 0x%08X" % (index + 4, r[index]))
+        print("    SP : 0x%08X (Process Stack Pointer)" % psp)
+        print("    YPC: 0x%08X" % yield_pc)
+        print("    PSR: 0x%08X" % psr)
+        # The hardware stacked the remaining registers at the stack pointer.
+        frame = stack_words(stack, psp, 8)
+        if frame is not None:
+            names = ["R0 ", "R1 ", "R2 ", "R3 ", "R12", "LR ", "PC ", "xPSR"]
+            print("  Exception frame at 0x%08X:" % psp)
+            for name, value in zip(names, frame):
+                print("    %s: 0x%08X" % (name, value))
+    elif tag == b"rv5i" and len(regs) >= 34:
+        pc, mcause, mtval = regs[0:3]
+        print("  Registers (RISC-V):")
+        print("    pc    : 0x%08X" % pc)
+        print("    mcause: 0x%08X" % mcause)
+        print("    mtval : 0x%08X" % mtval)
+        for index, value in enumerate(regs[3:34]):
+            print("    x%-5d: 0x%08X" % (index + 1, value))
+    else:
+        print("  Registers (unknown architecture %r):" % tag)
+        for value in regs:
+            print("    0x%08X" % value)
+
+
+def stack_words(stack, address, count):
+    """Returns `count` words at `address` if they are in the stack snapshot."""
+    if stack is None:
+        return None
+    start, snapshot = stack
+    offset = address - start
+    if offset < 0 or offset + count * 4 > len(snapshot):
+        return None
+    return words(snapshot[offset : offset + count * 4])
+
+
+def print_process(entries):
+    """Prints the entries that describe one process."""
+    stack = None
+    for entry_type, payload in entries:
+        if entry_type == ENTRY_STACK and len(payload) >= 4:
+            stack = (words(payload[0:4])[0], payload[4:])
+
+    for entry_type, payload in entries:
+        if entry_type == ENTRY_PROCESS:
+            identifier, restarts = struct.unpack("<II", payload[0:8])
+            state = payload[8]
+            name = payload[12:].decode("utf-8", "replace")
+            print("Process %s" % name)
+            print("  Identifier: %d" % identifier)
+            print("  Restarts  : %d" % restarts)
+            print(
+                "  State     : %s"
+                % (STATES[state] if state < len(STATES) else "Unknown (%d)" % state)
+            )
+        elif entry_type == ENTRY_REGISTERS:
+            print_registers(payload, stack)
+        elif entry_type == ENTRY_MEMORY_MAP:
+            print("  Memory map:")
+            for name, value in zip(MEMORY_MAP_FIELDS, words(payload)):
+                if value == 0 and name in ("Heap start", "Stack top", "Stack bottom"):
+                    print("    %-26s: unknown" % name)
+                else:
+                    print("    %-26s: 0x%08X" % (name, value))
+        elif entry_type == ENTRY_STACK and stack is not None:
+            start, snapshot = stack
+            print("  Stack from 0x%08X:" % start)
+            for offset in range(0, len(snapshot) - 3, 16):
+                line = words(snapshot[offset : offset + 16])
+                print(
+                    "    0x%08X: %s"
+                    % (start + offset, " ".join("%08X" % value for value in line))
+                )
+
+
+def main():
+    if len(sys.argv) != 2:
+        print(__doc__)
+        sys.exit(1)
+
+    with open(sys.argv[1], "rb") as record_file:
+        data = record_file.read()
+
+    try:
+        kind, flags, entries = parse_record(data)
+    except ValueError as err:
+        print(err)
+        sys.exit(1)
+
+    print("Crash record: %s" % CRASH_KINDS.get(kind, "Unknown (%d)" % kind))
+    if flags & FLAG_TRUNCATED:
+        print("Some entries did not fit in the record.")
+
+    process_entries = None
+    for entry_type, payload in entries:
+        if entry_type == ENTRY_PANIC_MESSAGE:
+            print(payload.decode("utf-8", "replace").strip())
+        elif entry_type == ENTRY_PROCESS:
+            if process_entries is not None:
+                print_process(process_entries)
+            print()
+            process_entries = [(entry_type, payload)]
+        elif process_entries is not None:
+            process_entries.append((entry_type, payload))
+    if process_entries is not None:
+        print_process(process_entries)
+
+
+if __name__ == "__main__":
+    main()
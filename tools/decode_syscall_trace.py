@@ -0,0 +1,222 @@
+#!/usr/bin/env python3
+
+# Decodes the system call trace streamed by the syscall_trace capsule.
+#
+# Usage: decode_syscall_trace.py TRACE
+
+"""
+Script to print the system call trace streamed by the syscall_trace capsule.
+
+The trace is read from a binary file, for example one captured from the UART
+the trace is streamed to, or `-` to read it from standard input while it is
+being captured:
+
+    stty -F /dev/ttyACM1 raw 115200
+    cat /dev/ttyACM1 | decode_syscall_trace.py -
+
+Usage: decode_syscall_trace.py TRACE
+"""
+
+import struct
+import sys
+
+# Must match `capsules::syscall_trace`.
+MAGIC = b"TKST"
+VERSION = 1
+HEADER_LENGTH = 12
+RECORD_LENGTH = 44
+SYNC = 0xA5
+FLAG_RETURNED = 0x01
+
+CLASSES = {
+    0: "yield",
+    1: "subscribe",
+    2: "command",
+    3: "rw-allow",
+    4: "ro-allow",
+    5: "memop",
+    6: "exit",
+    7: "ur-allow",
+}
+
+VARIANTS = {
+    0: "Failure",
+    1: "FailureU32",
+    2: "FailureU32U32",
+    3: "FailureU64",
+    128: "Success",
+    129: "SuccessU32",
+    130: "SuccessU32U32",
+    131: "SuccessU64",
+    132: "SuccessU32U32U32",
+    133: "SuccessU64U32",
+}
+
+ERROR_CODES = [
+    "SUCCESS",
+    "FAIL",
+    "BUSY",
+    "ALREADY",
+    "OFF",
+    "RESERVE",
+    "INVAL",
+    "SIZE",
+    "CANCEL",
+    "NOMEM",
+    "NOSUPPORT",
+    "NODEVICE",
+    "UNINSTALLED",
+    "NOACK",
+]
+
+
+def error_name(code):
+    return ERROR_CODES[code] if code < len(ERROR_CODES) else "ERROR(%d)" % code
+
+
+def format_call(syscall_class, args):
+    """Formats a system call with its arguments."""
+    if syscall_class == 0:
+        if args[0] == 1:
+            return "yield-wait()"
+        elif args[0] == 0:
+            return "yield-no-wait(@0x%x)" % args[1]
+        return "yield(%d)" % args[0]
+    elif syscall_class == 1:
+        return "subscribe(0x%x, %d, @0x%x, 0x%x)" % tuple(args)
+    elif syscall_class == 2:
+        return "command(0x%x, %d, 0x%x, 0x%x)" % tuple(args)
+    elif syscall_class in (3, 4, 7):
+        return "%s(0x%x, %d, @0x%x, %d)" % ((CLASSES[syscall_class],) + tuple(args))
+    elif syscall_class == 5:
+        return "memop(%d, 0x%x)" % (args[0], args[1])
+    elif syscall_class == 6:
+        which = {0: "exit-terminate", 1: "exit-restart"}.get(args[0], "exit")
+        return "%s(%d)" % (which, args[1])
+    return "unknown-class-%d(0x%x, 0x%x, 0x%x, 0x%x)" % ((syscall_class,) + tuple(args))
+
+
+def format_return(registers):
+    """Formats a return value encoded in registers as specified in TRD104."""
+    variant, a1, a2, a3 = registers
+    name = VARIANTS.get(variant, "Unknown(%d)" % variant)
+    if variant == 0:
+        values = [error_name(a1)]
+    elif variant == 1:
+        values = [error_name(a1), "0x%x" % a2]
+    elif variant == 2:
+        values = [error_name(a1), "0x%x" % a2, "0x%x" % a3]
+    elif variant == 3:
+        values = [error_name(a1), "0x%x" % (a2 | a3 << 32)]
+    elif variant == 129:
+        values = ["0x%x" % a1]
+    elif variant == 130:
+        values = ["0x%x" % a1, "0x%x" % a2]
+    elif variant == 131:
+        values = ["0x%x" % (a1 | a2 << 32)]
+    elif variant == 132:
+        values = ["0x%x" % a1, "0x%x" % a2, "0x%x" % a3]
+    elif variant == 133:
+        values = ["0x%x" % (a1 | a2 << 32), "0x%x" % a3]
+    else:
+        values = []
+    return "%s(%s)" % (name, ", ".join(values)) if values else name
+
+
+class Decoder:
+    """Decodes the stream, resynchronizing on headers and sync bytes."""
+
+    def __init__(self):
+        self.buffer = b""
+        self.frequency = None
+        self.width = 32
+        self.last_ticks = None
+        self.time = 0
+
+    def feed(self, data):
+        self.buffer += data
+        while True:
+            if self.frequency is None or self.buffer[0:4] == MAGIC[: len(self.buffer)]:
+                if not self.read_header():
+                    return
+            elif len(self.buffer) < RECORD_LENGTH:
+                return
+            elif self.buffer[0] != SYNC:
+                # Skip to the next record or header.
+                self.buffer = self.buffer[1:]
+            else:
+                self.print_record(self.buffer[:RECORD_LENGTH])
+                self.buffer = self.buffer[RECORD_LENGTH:]
+
+    def read_header(self):
+        index = self.buffer.find(MAGIC)
+        if index < 0:
+            # Keep a possible partial magic at the end.
+            self.buffer = self.buffer[-(len(MAGIC) - 1) :]
+            return False
+        if len(self.buffer) < index + HEADER_LENGTH:
+            self.buffer = self.buffer[index:]
+            return False
+        version, record_length, width, _, frequency = struct.unpack(
+            "<BBBBI", self.buffer[index + 4 : index + HEADER_LENGTH]
+        )
+        self.buffer = self.buffer[index + HEADER_LENGTH :]
+        if version != VERSION or record_length != RECORD_LENGTH:
+            print("Unsupported trace version %d" % version)
+            return True
+        if self.frequency is not None:
+            print("--- board restarted ---")
+        self.frequency = frequency
+        self.width = width if 0 < width <= 32 else 32
+        self.last_ticks = None
+        self.time = 0
+        return True
+
+    def print_record(self, record):
+        (_, syscall_class, processid, ticks) = struct.unpack("<BBHI", record[0:8])
+        args = struct.unpack("<4I", record[8:24])
+        flags, _, dropped = struct.unpack("<BBH", record[24:28])
+        registers = struct.unpack("<4I", record[28:44])
+
+        # Timestamps wrap around, and records of different processes are
+        # interleaved, so they may go back slightly.
+        if self.last_ticks is not None:
+            delta = (ticks - self.last_ticks) & ((1 << self.width) - 1)
+            if delta >= 1 << (self.width - 1):
+                delta -= 1 << self.width
+            self.time += delta
+        self.last_ticks = ticks
+
+        if dropped:
+            print("(process %d: %d records lost)" % (processid, dropped))
+        line = "[%12.6f] %3d %s" % (
+            self.time / self.frequency if self.frequency else 0,
+            processid,
+            format_call(syscall_class, args),
+        )
+        if flags & FLAG_RETURNED:
+            line += " = " + format_return(registers)
+        print(line)
+
+
+def main():
+    if len(sys.argv) != 2:
+        print(__doc__)
+        sys.exit(1)
+
+    decoder = Decoder()
+    if sys.argv[1] == "-":
+        trace_file = sys.stdin.buffer
+    else:
+        trace_file = open(sys.argv[1], "rb")
+    with trace_file:
+        while True:
+            data = trace_file.read1(RECORD_LENGTH)
+            if not data:
+                break
+            decoder.feed(data)
+            sys.stdout.flush()
+
+
+if __name__ == "__main__":
+    main()
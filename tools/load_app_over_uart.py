@@ -0,0 +1,96 @@
+#!/usr/bin/env python3
+
+# Installs a TBF on a running board through the AppLoader capsule.
+#
+# Usage: load_app_over_uart.py PORT TBF
+
+"""
+Script to send a TBF to the AppLoader capsule of a running Tock kernel.
+
+Usage: load_app_over_uart.py [-b baud] PORT TBF
+Options:
+  -b, --baud=n        Baud rate of the UART. Default: 115200
+"""
+
+import sys
+import getopt
+import serial  # pyserial
+
+# Must match `capsules::app_loader::CHUNK_SIZE`.
+CHUNK_SIZE = 512
+TBF_LENGTHS_SIZE = 8
+
+# Names of the `ErrorCode`s the capsule responds with.
+ERROR_CODES = {
+    1: "FAIL",
+    2: "BUSY",
+    3: "ALREADY",
+    4: "OFF",
+    5: "RESERVE",
+    6: "INVAL",
+    7: "SIZE",
+    8: "CANCEL",
+    9: "NOMEM",
+    10: "NOSUPPORT",
+    11: "NODEVICE",
+    12: "UNINSTALLED",
+    13: "NOACK",
+}
+
+
+def send(port, data):
+    """Sends data and waits for the status byte of the capsule."""
+    port.write(data)
+    status = port.read(1)
+    if len(status) == 0:
+        raise RuntimeError("No response from the board")
+    if status[0] != 0:
+        raise RuntimeError(
+            "Board responded with " + ERROR_CODES.get(status[0], str(status[0]))
+        )
+
+
+def load_app(port, tbf):
+    """Sends the TBF in the chunks the capsule expects."""
+    send(port, tbf[:TBF_LENGTHS_SIZE])
+    for offset in range(TBF_LENGTHS_SIZE, len(tbf), CHUNK_SIZE):
+        send(port, tbf[offset : offset + CHUNK_SIZE])
+
+
+def main():
+    try:
+        opts, args = getopt.getopt(sys.argv[1:], "b:", ["baud="])
+    except getopt.GetoptError as err:
+        print(err)
+        print(__doc__)
+        sys.exit(1)
+
+    baud = 115200
+    for opt, val in opts:
+        if opt in ("-b", "--baud"):
+            baud = int(val)
+
+    if len(args) != 2:
+        print(__doc__)
+        sys.exit(1)
+
+    with open(args[1], "rb") as tbf_file:
+        tbf = tbf_file.read()
+
+    total_size = int.from_bytes(tbf[4:8], "little")
+    if len(tbf) < total_size:
+        print("TBF is shorter than the size in its header")
+        sys.exit(1)
+    tbf = tbf[:total_size]
+
+    with serial.Serial(args[0], baud, timeout=10) as port:
+        try:
+            load_app(port, tbf)
+        except RuntimeError as err:
+            print(err)
+            sys.exit(1)
+    print("Loaded " + args[1])
+
+
+if __name__ == "__main__":
+    main()